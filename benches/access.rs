@@ -0,0 +1,71 @@
+//! Compares `ATT_WRITE_CMD` access checks, which use precomputed
+//! characteristic information, against a characteristic scan on a
+//! 300-attribute database. Run with `cargo bench -p burble`.
+
+#![allow(unused_crate_dependencies)]
+
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use burble::att::{Access, Handle, Opcode, Request};
+use burble::gap::Uuid;
+use burble::gatt::{Builder, Cache, Characteristic, Db, Io, Prop, Server};
+use burble::MemoryStore;
+
+fn access(c: &mut Criterion) {
+    let srv = Server::new(large_db(), Arc::new(MemoryStore::<Cache>::new()));
+    let db = srv.db();
+    let hdls: Vec<Handle> = db.iter().map(|(hdl, ..)| hdl).collect();
+    assert_eq!(hdls.len(), 300);
+    let req = Request::test(Opcode::WriteCmd);
+    let mut g = c.benchmark_group("access");
+    g.bench_function("check", |b| {
+        b.iter(|| {
+            for &hdl in &hdls {
+                let _ = black_box(db.try_access(req, black_box(hdl)));
+            }
+        });
+    });
+    g.bench_function("scan", |b| {
+        b.iter(|| {
+            for &hdl in &hdls {
+                black_box(scan_char(db, black_box(hdl)));
+            }
+        });
+    });
+    g.finish();
+}
+
+/// Returns the properties of the characteristic containing `hdl` by scanning
+/// all services and characteristics.
+fn scan_char(db: &Db, hdl: Handle) -> Option<Prop> {
+    let svc = (db.primary_services(Handle::MIN, None)).find(|s| s.handle_range().contains(&hdl))?;
+    let ch = (db.characteristics(svc.handle_range())).find(|c| c.handle_range().contains(&hdl))?;
+    Some(ch.properties())
+}
+
+/// Returns a database with 3 services, each containing 33 characteristics
+/// with a CCCD, for a total of 300 attributes.
+fn large_db() -> Builder<Db> {
+    let mut db = Db::build();
+    for i in 0..3_u16 {
+        let uuid = Uuid::new(0x1234_5678_0000_1000_8000_0080_5F9B_34FB + u128::from(i));
+        db.primary_service(uuid.unwrap(), [], |db| {
+            for _ in 0..33 {
+                db.characteristic(
+                    Characteristic::Report,
+                    Prop::READ | Prop::WRITE_CMD | Prop::NOTIFY,
+                    Access::READ_WRITE,
+                    Io::NONE,
+                    |db| db.cccd(Access::READ_WRITE),
+                );
+            }
+        });
+    }
+    db
+}
+
+criterion_group!(benches, access);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+//! Compares AES-CMAC performance with and without reusing the key schedule.
+//! Run with `cargo bench -p burble-crypto`.
+
+#![allow(unused_crate_dependencies)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use burble_crypto::AesCmac;
+
+/// Message length used by the f4 confirm value function.
+const MSG: [u8; 65] = [0xA5; 65];
+
+fn cmac(c: &mut Criterion) {
+    let mut g = c.benchmark_group("cmac");
+    g.bench_function("new", |b| {
+        b.iter(|| {
+            let mut m = AesCmac::db_hash();
+            m.update(black_box(MSG));
+            m.finalize()
+        });
+    });
+    let mut m = AesCmac::db_hash();
+    g.bench_function("reset", |b| {
+        b.iter(|| m.update(black_box(MSG)).finalize_reset());
+    });
+    g.finish();
+}
+
+criterion_group!(benches, cmac);
+criterion_main!(benches);
@@ -0,0 +1,263 @@
+use aes::cipher::{BlockEncrypt, KeyInit};
+use subtle::ConstantTimeEq;
+
+use crate::{debug_secret, LTK};
+
+/// AES-CCM link-layer encryption with a 4-byte MIC ([Vol 6] Part E). The
+/// AES key schedule is zeroized on drop.
+pub struct Ccm {
+    aes: aes::Aes128,
+    iv: u64,
+}
+
+debug_secret!(Ccm);
+
+impl Ccm {
+    /// Creates the CCM state from the session key `sk` and the initialization
+    /// vector `iv` ([Vol 6] Part E, Section 2.1).
+    #[inline]
+    #[must_use]
+    pub fn new(sk: u128, iv: u64) -> Self {
+        Self {
+            aes: aes::Aes128::new(&sk.to_be_bytes().into()),
+            iv,
+        }
+    }
+
+    /// Derives the session key `SK = e(LTK, SKD)` from the long term key and
+    /// the session key diversifier `SKD = SKDs || SKDm`, and creates the CCM
+    /// state for the connection with `IV = IVs || IVm` ([Vol 6] Part B,
+    /// Section 5.1.3.1).
+    #[inline]
+    #[must_use]
+    pub fn for_session(ltk: &LTK, skd: u128, iv: u64) -> Self {
+        Self::new(session_key(ltk, skd), iv)
+    }
+
+    /// Returns the nonce for the specified 39-bit packet counter and
+    /// direction bit ([Vol 6] Part E, Section 2.1). The direction bit is set
+    /// for packets sent by the Central.
+    #[inline]
+    #[must_use]
+    pub const fn nonce(&self, ctr: u64, central: bool) -> CcmNonce {
+        CcmNonce::new(ctr, central, self.iv)
+    }
+
+    /// Encrypts `buf` in place and returns the MIC. `aad` is the first octet
+    /// of the PDU header with the NESN, SN, and MD bits cleared (or the
+    /// corresponding bits of an isochronous PDU header).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is longer than [`u16::MAX`].
+    pub fn encrypt(&self, n: &CcmNonce, aad: u8, buf: &mut [u8]) -> [u8; 4] {
+        let t = self.cbc_mac(n, aad, buf);
+        self.ctr(n, buf);
+        self.mic(n, t)
+    }
+
+    /// Decrypts `buf` in place and verifies the MIC. Returns `false` and
+    /// zeroes `buf` if authentication fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf` is longer than [`u16::MAX`].
+    #[must_use]
+    pub fn decrypt(&self, n: &CcmNonce, aad: u8, buf: &mut [u8], mic: [u8; 4]) -> bool {
+        self.ctr(n, buf);
+        let t = self.mic(n, self.cbc_mac(n, aad, buf));
+        if bool::from(t.ct_eq(&mic)) {
+            return true;
+        }
+        buf.fill(0);
+        false
+    }
+
+    /// Computes the unencrypted CBC-MAC tag over the payload and the
+    /// single-octet additional authenticated data (RFC 3610, Section 2.2).
+    fn cbc_mac(&self, n: &CcmNonce, aad: u8, buf: &[u8]) -> [u8; 4] {
+        let len = u16::try_from(buf.len()).expect("CCM payload too long");
+        // Flags: Adata = 1, M' = (4 - 2) / 2, L' = 2 - 1
+        let mut x = aes::Block::from(n.block(0x49, len));
+        self.aes.encrypt_block(&mut x);
+        let mut b1 = [0; 16];
+        b1[..3].copy_from_slice(&[0x00, 0x01, aad]);
+        xor(&mut x, &b1);
+        self.aes.encrypt_block(&mut x);
+        for b in buf.chunks(16) {
+            xor(&mut x, b);
+            self.aes.encrypt_block(&mut x);
+        }
+        let mut t = [0; 4];
+        t.copy_from_slice(&x[..4]);
+        t
+    }
+
+    /// Applies the CTR mode keystream starting with counter 1 to `buf`.
+    fn ctr(&self, n: &CcmNonce, buf: &mut [u8]) {
+        for (i, b) in (1..=u16::MAX).zip(buf.chunks_mut(16)) {
+            let mut s = aes::Block::from(n.block(0x01, i));
+            self.aes.encrypt_block(&mut s);
+            xor(b, &s);
+        }
+    }
+
+    /// Encrypts the CBC-MAC tag with counter 0.
+    fn mic(&self, n: &CcmNonce, mut t: [u8; 4]) -> [u8; 4] {
+        let mut s = aes::Block::from(n.block(0x01, 0));
+        self.aes.encrypt_block(&mut s);
+        xor(&mut t, &s);
+        t
+    }
+}
+
+/// 13-byte CCM nonce ([Vol 6] Part E, Section 2.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[must_use]
+#[repr(transparent)]
+pub struct CcmNonce([u8; 13]);
+
+impl CcmNonce {
+    /// Creates a nonce from the 39-bit packet counter, direction bit, and
+    /// 64-bit IV. Counter bits above bit 38 are ignored.
+    #[inline]
+    pub const fn new(ctr: u64, dir: bool, iv: u64) -> Self {
+        let dir = if dir { 1 << 39 } else { 0 };
+        let c = (ctr & ((1 << 39) - 1) | dir).to_le_bytes();
+        let iv = iv.to_le_bytes();
+        Self([
+            c[0], c[1], c[2], c[3], c[4], iv[0], iv[1], iv[2], iv[3], iv[4], iv[5], iv[6], iv[7],
+        ])
+    }
+
+    /// Returns a CCM block with the specified flags, nonce, and trailing
+    /// 16-bit value.
+    #[inline]
+    fn block(&self, flags: u8, v: u16) -> [u8; 16] {
+        let mut b = [0; 16];
+        b[0] = flags;
+        b[1..14].copy_from_slice(&self.0);
+        b[14..].copy_from_slice(&v.to_be_bytes());
+        b
+    }
+}
+
+/// Computes the session key `SK = e(LTK, SKD)`.
+fn session_key(ltk: &LTK, skd: u128) -> u128 {
+    let aes = aes::Aes128::new(&u128::from(ltk).to_be_bytes().into());
+    let mut sk = aes::Block::from(skd.to_be_bytes());
+    aes.encrypt_block(&mut sk);
+    u128::from_be_bytes(sk.into())
+}
+
+/// Performs `dst ^= src` for the length of `dst`.
+#[inline]
+fn xor(dst: &mut [u8], src: &[u8]) {
+    dst.iter_mut().zip(src).for_each(|(d, s)| *d ^= s);
+}
+
+#[allow(clippy::unreadable_literal)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: u128 = 0x4C68384139F574D836BCF34E9DFB01BF;
+    const SKD: u128 = 0x0213243546576879ACBDCEDFE0F10213;
+    const IV: u64 = 0xDEAFBABEBADCAB24;
+
+    /// Sample data ([Vol 6] Part C, Section 1).
+    fn session() -> Ccm {
+        Ccm::for_session(&LTK::new(KEY), SKD, IV)
+    }
+
+    #[test]
+    fn session_key() {
+        let (skdm, skds) = (0xACBDCEDFE0F10213_u128, 0x0213243546576879_u128);
+        let (ivm, ivs) = (0xBADCAB24_u64, 0xDEAFBABE_u64);
+        assert_eq!(skds << 64 | skdm, SKD);
+        assert_eq!(ivs << 32 | ivm, IV);
+        assert_eq!(
+            super::session_key(&LTK::new(KEY), SKD),
+            0x99AD1B5226A37E3E058E3B8E27C2C666
+        );
+    }
+
+    #[test]
+    fn nonce() {
+        let n = session().nonce(0, true);
+        assert_eq!(
+            n.0,
+            [0, 0, 0, 0, 0x80, 0x24, 0xAB, 0xDC, 0xBA, 0xBE, 0xBA, 0xAF, 0xDE]
+        );
+        let n = CcmNonce::new(u64::MAX, false, 0);
+        assert_eq!(n.0[..5], [0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    /// LL_START_ENC_RSP packets ([Vol 6] Part C, Section 1).
+    #[test]
+    fn start_enc_rsp() {
+        let s = session();
+        eq(
+            &s,
+            s.nonce(0, true),
+            0x0F,
+            &[0x06],
+            &[0x9F],
+            [0xCD, 0xA7, 0xF4, 0x48],
+        );
+        eq(
+            &s,
+            s.nonce(0, false),
+            0x07,
+            &[0x06],
+            &[0xA3],
+            [0x4C, 0x13, 0xA4, 0x15],
+        );
+    }
+
+    /// Multi-block payloads with NESN, SN, and MD bits masked out of the AAD.
+    #[test]
+    fn multi_block() {
+        let s = session();
+        let p: Vec<u8> = (0..27).collect();
+        let c = [
+            0x6D, 0x71, 0xB7, 0x03, 0x74, 0x41, 0x0C, 0x9D, 0x0A, 0x74, 0xE2, 0xFD, 0x67, 0x67,
+            0x64, 0x26, 0x0A, 0xF6, 0x76, 0x6F, 0x59, 0x4B, 0x7B, 0xBC, 0xC6, 0xDF, 0x18,
+        ];
+        eq(&s, s.nonce(1, true), 0x1E, &p, &c, [0x61, 0xC2, 0x72, 0x45]);
+        let c = [
+            0x85, 0x0D, 0xA1, 0x63, 0x26, 0x8E, 0x5A, 0x84, 0x53, 0x1F, 0xE7, 0x41, 0xA3, 0x6C,
+            0x0D, 0xEC,
+        ];
+        eq(
+            &s,
+            s.nonce((1 << 39) - 1, false),
+            0x02,
+            &p[..16],
+            &c,
+            [0x7F, 0x68, 0xF2, 0x38],
+        );
+    }
+
+    #[test]
+    fn auth_failure() {
+        let s = session();
+        let n = s.nonce(0, true);
+        let mut b = [0x9F];
+        assert!(!s.decrypt(&n, 0x02, &mut b, [0xCD, 0xA7, 0xF4, 0x48]));
+        assert_eq!(b, [0]);
+        let mut b = [0x9F];
+        assert!(!s.decrypt(&n, 0x03, &mut b, [0xCD, 0xA7, 0xF4, 0x49]));
+        let mut b = [0x9E];
+        assert!(!s.decrypt(&n, 0x03, &mut b, [0xCD, 0xA7, 0xF4, 0x48]));
+    }
+
+    fn eq(s: &Ccm, n: CcmNonce, hdr: u8, p: &[u8], c: &[u8], mic: [u8; 4]) {
+        let aad = hdr & 0xE3;
+        let mut b = p.to_vec();
+        assert_eq!(s.encrypt(&n, aad, &mut b), mic);
+        assert_eq!(b, c);
+        assert!(s.decrypt(&n, aad, &mut b, mic));
+        assert_eq!(b, p);
+    }
+}
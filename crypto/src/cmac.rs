@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use core::fmt::Debug;
 
 use cmac::digest;
 use zeroize::{Zeroize, ZeroizeOnDrop};
@@ -6,9 +6,9 @@ use zeroize::{Zeroize, ZeroizeOnDrop};
 /// Provides a [`Debug`] implementation for a type containing sensitive data.
 macro_rules! debug_secret {
     ($T:ty) => {
-        impl ::std::fmt::Debug for $T {
+        impl ::core::fmt::Debug for $T {
             #[inline]
-            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
                 f.debug_tuple(stringify!($T)).field(&"<secret>").finish()
             }
         }
@@ -16,11 +16,19 @@ macro_rules! debug_secret {
 }
 pub(super) use debug_secret;
 
-/// RFC-4493 AES-CMAC ([Vol 3] Part H, Section 2.2.5).
-#[derive(Debug)]
+/// RFC-4493 AES-CMAC ([Vol 3] Part H, Section 2.2.5). The internal state,
+/// including the AES key schedule, is zeroized on drop.
+///
+/// The same instance can be used to compute multiple MACs with the same key
+/// by calling [`Self::reset`] or [`Self::finalize_reset`], which avoids
+/// repeating the key schedule and subkey generation.
+#[derive(Clone, Debug)]
 #[repr(transparent)]
 pub struct AesCmac(cmac::Cmac<aes::Aes128>);
 
+// Cmac zeroizes its state on drop when the `zeroize` feature is enabled
+impl ZeroizeOnDrop for AesCmac {}
+
 impl AesCmac {
     /// Creates new AES-CMAC state using key `k`.
     #[inline(always)]
@@ -44,6 +52,13 @@ impl AesCmac {
         self
     }
 
+    /// Discards any data processed since the last reset, keeping the key.
+    #[inline(always)]
+    pub fn reset(&mut self) -> &mut Self {
+        digest::Reset::reset(&mut self.0);
+        self
+    }
+
     /// Computes the final MAC value.
     #[inline(always)]
     #[must_use]
@@ -51,6 +66,15 @@ impl AesCmac {
         u128::from_be_bytes(*digest::FixedOutput::finalize_fixed(self.0).as_ref())
     }
 
+    /// Computes the final MAC value and resets the state for computing
+    /// another MAC with the same key.
+    #[inline(always)]
+    #[must_use]
+    pub fn finalize_reset(&mut self) -> u128 {
+        let v = digest::FixedOutputReset::finalize_fixed_reset(&mut self.0);
+        u128::from_be_bytes(*v.as_ref())
+    }
+
     /// Computes the final MAC value for use as a future key and resets the
     /// state.
     #[inline(always)]
@@ -77,6 +101,19 @@ impl Key {
     pub fn new(k: u128) -> Self {
         Self(k.to_be_bytes().into())
     }
+
+    /// Computes the AES-CMAC of the concatenation of `msgs` using this key.
+    /// This is intended for keys that are used only once, such as nonces.
+    /// Use [`AesCmac`] to compute multiple MACs with the same key.
+    #[inline]
+    #[must_use]
+    pub fn cmac(&self, msgs: &[&[u8]]) -> u128 {
+        let mut m = AesCmac::new(self);
+        for &b in msgs {
+            m.update(b);
+        }
+        m.finalize()
+    }
 }
 
 impl From<&Key> for u128 {
@@ -118,4 +155,27 @@ mod tests {
         m.update(b(0xf69f2445_df4f9b17_ad2b417b_e66c3710));
         assert_eq!(m.finalize(), 0x51f0bebf_7e3b9d92_fc497417_79363cfe);
     }
+
+    /// Reused and one-shot AES-CMAC must match the streaming results.
+    #[test]
+    fn aes_cmac_reuse() {
+        let k = Key::new(0x2b7e1516_28aed2a6_abf71588_09cf4f3c);
+        let m1 = 0x6bc1bee2_2e409f96_e93d7e11_7393172a_u128.to_be_bytes();
+        let m2 = 0xae2d8a57_1e03ac9c_9eb76fac_45af8e51_u128.to_be_bytes();
+        let m3 = 0x30c81c46_a35ce411_u64.to_be_bytes();
+        let want = 0xdfa66747_de9ae630_30ca3261_1497c827;
+
+        let mut m = AesCmac::new(&k);
+        assert_eq!(m.finalize_reset(), 0xbb1d6929_e9593728_7fa37d12_9b756746);
+        m.update(m1).update(m2).update(m3);
+        assert_eq!(m.finalize_reset(), want);
+        m.update(m1).update(m2).update(m3);
+        assert_eq!(m.clone().finalize(), want);
+        m.reset().update(m1);
+        assert_eq!(m.finalize_reset(), 0x070a16b4_6b4d4144_f79bdd9d_d04a287c);
+
+        assert_eq!(k.cmac(&[]), 0xbb1d6929_e9593728_7fa37d12_9b756746);
+        assert_eq!(k.cmac(&[&m1, &m2, &m3]), want);
+        assert_eq!(k.cmac(&[&[m1, m2].concat(), &m3]), want);
+    }
 }
@@ -1,15 +1,20 @@
 #![doc = include_str!("../README.md")]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![forbid(unsafe_code)]
 #![warn(unused_crate_dependencies)]
+#![cfg_attr(test, allow(unused_crate_dependencies))]
 
-use std::fmt::{Debug, Display, Formatter};
-use std::mem;
+use core::fmt::{Debug, Display, Formatter};
+use core::mem;
+
+use rand_core::CryptoRngCore;
 
 use structbuf::{Packer, Unpacker};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-pub use crate::{cmac::*, p256::*};
+pub use crate::{ccm::*, cmac::*, p256::*};
 
+mod ccm;
 mod cmac;
 mod p256;
 
@@ -112,11 +117,21 @@ impl Nonce {
     ///
     /// Panics if the OS CSPRNG is broken.
     #[allow(clippy::new_without_default)]
+    #[cfg(feature = "std")]
     #[inline]
     pub fn new() -> Self {
-        use rand_core::{OsRng, RngCore};
+        Self::new_with(&mut rand_core::OsRng)
+    }
+
+    /// Generates a new non-zero random nonce value from the specified CSPRNG.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the CSPRNG returns all zeros.
+    #[inline]
+    pub fn new_with(rng: &mut impl CryptoRngCore) -> Self {
         let mut b = [0; mem::size_of::<u128>()];
-        OsRng.fill_bytes(b.as_mut_slice());
+        rng.fill_bytes(b.as_mut_slice());
         let n = u128::from_ne_bytes(b);
         assert_ne!(n, 0);
         Self(n)
@@ -126,27 +141,23 @@ impl Nonce {
     /// ([Vol 3] Part H, Section 2.2.6).
     #[inline]
     pub fn f4(&self, u: &PublicKeyX, v: &PublicKeyX, z: u8) -> Confirm {
-        let mut m = AesCmac::new(&Key::new(self.0));
-        m.update(u.as_be_bytes())
-            .update(v.as_be_bytes())
-            .update([z]);
-        Confirm(m.finalize())
+        let k = Key::new(self.0);
+        Confirm(k.cmac(&[u.as_be_bytes(), v.as_be_bytes(), &[z]]))
     }
 
     /// Generates LE Secure Connections numeric comparison value
     /// ([Vol 3] Part H, Section 2.2.9).
     #[inline]
     pub fn g2(&self, pkax: &PublicKeyX, pkbx: &PublicKeyX, nb: &Self) -> NumCompare {
-        let mut m = AesCmac::new(&Key::new(self.0));
-        m.update(pkax.as_be_bytes())
-            .update(pkbx.as_be_bytes())
-            .update(nb.0.to_be_bytes());
+        let k = Key::new(self.0);
+        let v = k.cmac(&[pkax.as_be_bytes(), pkbx.as_be_bytes(), &nb.0.to_be_bytes()]);
         #[allow(clippy::cast_possible_truncation)]
-        NumCompare(m.finalize() as u32 % 1_000_000)
+        NumCompare(v as u32 % 1_000_000)
     }
 }
 
-/// LE Secure Connections confirm value generated by [`Nonce::f4`].
+/// Confirm value generated by [`Nonce::f4`] for LE Secure Connections or by
+/// [`TK::c1`] for LE legacy pairing.
 #[derive(Clone, Copy, Debug, Eq)]
 #[must_use]
 #[repr(transparent)]
@@ -155,6 +166,43 @@ pub struct Confirm(u128);
 u128_codec!(Confirm);
 ct_newtype!(Confirm);
 
+/// LE Secure Connections out-of-band data ([Vol 3] Part H, Section 2.3.5.6.4).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[must_use]
+pub struct OobData {
+    /// Random value sent to the peer over the OOB channel.
+    pub r: Nonce,
+    /// Confirmation value computed over the local public key.
+    pub confirm: Confirm,
+}
+
+impl OobData {
+    /// Generates OOB data for the public key associated with secret key `sk`
+    /// using a new random value.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn new(sk: &SecretKey) -> Self {
+        Self::new_with(sk, &mut rand_core::OsRng)
+    }
+
+    /// Generates OOB data for the public key associated with secret key `sk`
+    /// using a new random value from the specified CSPRNG.
+    #[inline]
+    pub fn new_with(sk: &SecretKey, rng: &mut impl CryptoRngCore) -> Self {
+        Self::with_nonce(sk.public_key().x(), Nonce::new_with(rng))
+    }
+
+    /// Generates OOB data for public key X coordinate `pkx` and random value
+    /// `r`.
+    #[inline]
+    fn with_nonce(pkx: &PublicKeyX, r: Nonce) -> Self {
+        Self {
+            r,
+            confirm: r.f4(pkx, pkx, 0),
+        }
+    }
+}
+
 /// 6-digit LE Secure Connections numeric comparison value generated by
 /// [`Nonce::g2`].
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -164,7 +212,7 @@ pub struct NumCompare(u32);
 
 impl Debug for NumCompare {
     #[inline]
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("NumCompare")
             .field(&format_args!("{:06}", self.0))
             .finish()
@@ -173,16 +221,18 @@ impl Debug for NumCompare {
 
 impl Display for NumCompare {
     #[inline]
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:06}", self.0)
     }
 }
 
 /// 128-bit key used to compute LE Secure Connections check value
-/// ([Vol 3] Part H, Section 2.2.8).
+/// ([Vol 3] Part H, Section 2.2.8). The AES-CMAC key schedule is computed once
+/// and reused for each check value.
+#[derive(ZeroizeOnDrop)]
 #[must_use]
 #[repr(transparent)]
-pub struct MacKey(Key);
+pub struct MacKey(AesCmac);
 
 debug_secret!(MacKey);
 
@@ -191,7 +241,7 @@ impl MacKey {
     /// ([Vol 3] Part H, Section 2.2.8).
     #[inline]
     pub fn f6(&self, n1: Nonce, n2: Nonce, r: u128, io_cap: IoCap, a1: Addr, a2: Addr) -> Check {
-        let mut m = AesCmac::new(&self.0);
+        let mut m = self.0.clone();
         m.update(n1.0.to_be_bytes())
             .update(n2.0.to_be_bytes())
             .update(r.to_be_bytes())
@@ -202,6 +252,41 @@ impl MacKey {
     }
 }
 
+/// Implements common methods for a 128-bit secret key newtype struct.
+macro_rules! key128 {
+    ($T:ident) => {
+        debug_secret!($T);
+
+        impl $T {
+            /// Creates a key from a `u128` value.
+            #[inline(always)]
+            pub const fn new(k: u128) -> Self {
+                Self(k)
+            }
+
+            /// Creates a key from a little-endian byte array.
+            #[inline(always)]
+            pub const fn from_le_bytes(b: [u8; 16]) -> Self {
+                Self(u128::from_le_bytes(b))
+            }
+
+            /// Returns the key as a little-endian byte array.
+            #[inline(always)]
+            #[must_use]
+            pub const fn to_le_bytes(&self) -> [u8; 16] {
+                self.0.to_le_bytes()
+            }
+        }
+
+        impl From<&$T> for u128 {
+            #[inline(always)]
+            fn from(k: &$T) -> Self {
+                k.0
+            }
+        }
+    };
+}
+
 /// LE Secure Connections Long Term Key.
 #[derive(Eq, PartialEq, Zeroize, ZeroizeOnDrop, serde::Deserialize, serde::Serialize)]
 #[must_use]
@@ -209,23 +294,159 @@ impl MacKey {
 #[serde(transparent)]
 pub struct LTK(#[serde(with = "u128ser")] u128);
 
-debug_secret!(LTK);
+key128!(LTK);
 
-impl LTK {
-    /// Creates a Long Term Key from a `u128` value.
-    #[inline(always)]
-    pub const fn new(k: u128) -> Self {
-        Self(k)
+/// LE legacy pairing Temporary Key ([Vol 3] Part H, Section 2.3.5).
+#[derive(Zeroize, ZeroizeOnDrop)]
+#[must_use]
+#[repr(transparent)]
+pub struct TK(u128);
+
+key128!(TK);
+
+impl TK {
+    /// Generates LE legacy pairing confirm value for random value `r`
+    /// ([Vol 3] Part H, Section 2.2.3). `preq` and `pres` are the Pairing
+    /// Request and Pairing Response commands, including the command code, in
+    /// the over-the-air (little-endian) byte order. `ia` and `ra` are the
+    /// initiating and responding device addresses.
+    pub fn c1(&self, r: &Nonce, preq: &[u8; 7], pres: &[u8; 7], ia: Addr, ra: Addr) -> Confirm {
+        // p1 = pres || preq || rat' || iat'
+        let mut p1 = [0; 16];
+        p1[..7].copy_from_slice(preq);
+        p1[7..14].copy_from_slice(pres);
+        p1[..14].reverse();
+        p1[14] = ra.0[0];
+        p1[15] = ia.0[0];
+        // p2 = padding || ia || ra
+        let mut p2 = [0; 16];
+        p2[4..10].copy_from_slice(&ia.0[1..]);
+        p2[10..].copy_from_slice(&ra.0[1..]);
+        let v = e(self.0, r.0 ^ u128::from_be_bytes(p1));
+        Confirm(e(self.0, v ^ u128::from_be_bytes(p2)))
+    }
+
+    /// Generates LE legacy pairing Short Term Key (STK) from the responding
+    /// device random value `r1` and the initiating device random value `r2`
+    /// ([Vol 3] Part H, Section 2.2.4). The STK is used in place of the LTK to
+    /// encrypt the connection.
+    #[inline]
+    pub fn s1(&self, r1: &Nonce, r2: &Nonce) -> LTK {
+        LTK(e(self.0, (r1.0 << 64) | (r2.0 & u128::from(u64::MAX))))
+    }
+}
+
+/// Identity Resolving Key used to generate and resolve resolvable private
+/// addresses ([Vol 3] Part H, Section 2.4.2.1).
+#[derive(Eq, PartialEq, Zeroize, ZeroizeOnDrop, serde::Deserialize, serde::Serialize)]
+#[must_use]
+#[repr(transparent)]
+#[serde(transparent)]
+pub struct IRK(#[serde(with = "u128ser")] u128);
+
+key128!(IRK);
+u128_codec!(IRK);
+
+impl IRK {
+    /// Computes the random address hash function `ah(k, r)` for the 24-bit
+    /// value `r`, which is used to generate and resolve resolvable private
+    /// addresses ([Vol 3] Part H, Section 2.2.2). The upper 8 bits of `r` are
+    /// ignored.
+    #[must_use]
+    pub fn ah(&self, r: u32) -> u32 {
+        #[allow(clippy::cast_possible_truncation)]
+        let h = e(self.0, u128::from(r & 0xFF_FFFF)) as u32;
+        h & 0xFF_FFFF
+    }
+}
+
+/// Connection Signature Resolving Key used to sign and verify data
+/// ([Vol 3] Part H, Section 2.4.2.2).
+#[derive(Eq, PartialEq, Zeroize, ZeroizeOnDrop, serde::Deserialize, serde::Serialize)]
+#[must_use]
+#[repr(transparent)]
+#[serde(transparent)]
+pub struct CSRK(#[serde(with = "u128ser")] u128);
+
+key128!(CSRK);
+u128_codec!(CSRK);
+
+impl CSRK {
+    /// Returns the signature of message `m` using the specified sign counter
+    /// ([Vol 3] Part H, Section 2.4.5). The message is in the over-the-air
+    /// (little-endian) byte order.
+    #[inline]
+    pub fn sign(&self, m: &[u8], sign_counter: u32) -> Signature {
+        Signature {
+            sign_counter,
+            mac: self.mac(m, sign_counter),
+        }
+    }
+
+    /// Verifies the signature of message `m` in constant time.
+    #[inline]
+    #[must_use]
+    pub fn verify(&self, m: &[u8], sig: &Signature) -> bool {
+        use subtle::ConstantTimeEq;
+        self.mac(m, sig.sign_counter).ct_eq(&sig.mac).into()
+    }
+
+    /// Computes the MAC of message `m` using the specified sign counter.
+    fn mac(&self, m: &[u8], sign_counter: u32) -> u64 {
+        // M = m || SignCounter is processed as a big-endian value
+        let mut h = AesCmac::new(&Key::new(self.0));
+        h.update(sign_counter.to_be_bytes());
+        for &b in m.iter().rev() {
+            h.update([b]);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let mac = (h.finalize() >> 64) as u64;
+        mac
     }
 }
 
-impl From<&LTK> for u128 {
+/// Authentication signature appended to signed data, such as the ATT
+/// Authentication Signature ([Vol 3] Part H, Section 2.4.5 and
+/// [Vol 3] Part F, Section 3.3.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[must_use]
+pub struct Signature {
+    /// Sign counter used to generate the MAC.
+    pub sign_counter: u32,
+    /// Message Authentication Code.
+    pub mac: u64,
+}
+
+impl Signature {
+    /// Signature length in bytes.
+    pub const LEN: usize = 12;
+}
+
+impl Codec for Signature {
     #[inline(always)]
-    fn from(k: &LTK) -> Self {
-        k.0
+    fn pack(&self, p: &mut Packer) {
+        p.u32(self.sign_counter).u64(self.mac);
+    }
+
+    #[inline(always)]
+    fn unpack(p: &mut Unpacker) -> Option<Self> {
+        Some(Self {
+            sign_counter: p.u32(),
+            mac: p.u64(),
+        })
     }
 }
 
+/// Security function `e` that encrypts `v` using AES-128 with key `k`
+/// ([Vol 3] Part H, Section 2.2.1).
+fn e(k: u128, v: u128) -> u128 {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    let aes = aes::Aes128::new(&k.to_be_bytes().into());
+    let mut b = aes::Block::from(v.to_be_bytes());
+    aes.encrypt_block(&mut b);
+    u128::from_be_bytes(b.into())
+}
+
 /// LE Secure Connections check value generated by [`MacKey::f6`].
 #[derive(Clone, Copy, Debug, Eq)]
 #[must_use]
@@ -238,7 +459,7 @@ ct_newtype!(Check);
 /// Serializer/deserializer for `u128` and `NonZeroU128`.
 #[doc(hidden)]
 pub mod u128ser {
-    use std::fmt;
+    use core::fmt;
 
     use serde::{de, ser};
 
@@ -248,10 +469,14 @@ pub mod u128ser {
         S: ser::Serializer,
     {
         // TODO: Handle non-human-readable formats?
-        use std::io::Write;
-        let mut b = std::io::Cursor::new([0_u8; 32]);
-        write!(b, "{:032X}", (*v).into()).expect("buffer overflow");
-        s.serialize_str(std::str::from_utf8(b.get_ref()).expect("invalid string"))
+        let v: u128 = (*v).into();
+        let mut b = [0_u8; 32];
+        for (i, c) in b.iter_mut().rev().enumerate() {
+            #[allow(clippy::cast_possible_truncation)]
+            let d = (v >> (4 * i)) as usize & 0xF;
+            *c = b"0123456789ABCDEF"[d];
+        }
+        s.serialize_str(core::str::from_utf8(&b).expect("invalid string"))
     }
 
     pub fn deserialize<'de, T, D>(d: D) -> Result<T, D::Error>
@@ -280,12 +505,60 @@ pub mod u128ser {
 mod tests {
     use super::*;
 
+    #[test]
+    fn zeroize_on_drop() {
+        const fn check<T: ZeroizeOnDrop>() {}
+        check::<Key>();
+        check::<MacKey>();
+        check::<LTK>();
+        check::<TK>();
+        check::<IRK>();
+        check::<CSRK>();
+        check::<SecretKey>();
+        check::<DHKey>();
+    }
+
     #[test]
     fn nonce() {
         // No fair dice rolls for us!
         assert_ne!(Nonce::new(), Nonce::new());
     }
 
+    #[test]
+    fn new_with_rng() {
+        /// Deterministic RNG for testing external RNG support.
+        struct Counter(u8);
+        impl rand_core::RngCore for Counter {
+            fn next_u32(&mut self) -> u32 {
+                rand_core::impls::next_u32_via_fill(self)
+            }
+            fn next_u64(&mut self) -> u64 {
+                rand_core::impls::next_u64_via_fill(self)
+            }
+            fn fill_bytes(&mut self, b: &mut [u8]) {
+                for v in b {
+                    self.0 = self.0.wrapping_add(1);
+                    *v = self.0;
+                }
+            }
+            fn try_fill_bytes(&mut self, b: &mut [u8]) -> Result<(), rand_core::Error> {
+                self.fill_bytes(b);
+                Ok(())
+            }
+        }
+        impl rand_core::CryptoRng for Counter {}
+
+        let n = Nonce::new_with(&mut Counter(0));
+        assert_eq!(
+            n.0.to_ne_bytes(),
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]
+        );
+        let pk = SecretKey::new_with(&mut Counter(0)).public_key();
+        let sk = SecretKey::new_with(&mut Counter(0));
+        assert_eq!(sk.public_key(), pk);
+        assert_eq!(OobData::new_with(&sk, &mut Counter(0)).r, n);
+    }
+
     /// Confirm value generation function ([Vol 3] Part H, Section D.2).
     #[test]
     fn nonce_f4() {
@@ -301,6 +574,39 @@ mod tests {
         assert_eq!(x.f4(&u, &v, 0).0, 0xf2c916f1_07a9bd1c_f1eda1be_a974872d);
     }
 
+    #[test]
+    fn key_bytes() {
+        let b = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        let k = IRK::from_le_bytes(b);
+        assert_eq!(u128::from(&k), 0x0f0e0d0c_0b0a0908_07060504_03020100);
+        assert_eq!(k.to_le_bytes(), b);
+        assert_eq!(LTK::from_le_bytes(b).to_le_bytes(), b);
+        assert_eq!(CSRK::from_le_bytes(b).to_le_bytes(), b);
+        assert_eq!(format!("{k:?}"), r#"IRK("<secret>")"#);
+    }
+
+    /// Confirm value generation function for OOB data
+    /// ([Vol 3] Part H, Section 2.3.5.6.4).
+    #[test]
+    fn oob_data() {
+        let pkx = PublicKeyX::from_be_bytes(u256(
+            0x20b003d2_f297be2c_5e2c83a7_e9f9a5b9,
+            0xeff49111_acf4fddb_cc030148_0e359de6,
+        ));
+        let r = Nonce(0xd5cb8454_d177733e_ffffb2ec_712baeab);
+        let oob = OobData::with_nonce(&pkx, r);
+        assert_eq!(oob.r, r);
+        assert_eq!(oob.confirm, r.f4(&pkx, &pkx, 0));
+        assert_eq!(oob.confirm.0, 0xe8312978_1359e98f_018c4135_29988817);
+
+        let sk = SecretKey::new();
+        let oob = OobData::new(&sk);
+        assert_eq!(
+            oob.confirm,
+            oob.r.f4(sk.public_key().x(), sk.public_key().x(), 0)
+        );
+    }
+
     /// Numeric comparison generation function ([Vol 3] Part H, Section D.5).
     #[allow(clippy::unreadable_literal)]
     #[test]
@@ -321,7 +627,8 @@ mod tests {
     /// Check value generation function ([Vol 3] Part H, Section D.4).
     #[test]
     fn mac_key_f6() {
-        let k = MacKey(Key::new(0x2965f176_a1084a02_fd3f6a20_ce636e20));
+        let k = Key::new(0x2965f176_a1084a02_fd3f6a20_ce636e20);
+        let k = MacKey(AesCmac::new(&k));
         let n1 = Nonce(0xd5cb8454_d177733e_ffffb2ec_712baeab);
         let n2 = Nonce(0xa6e8e7cc_25a75f6e_216583f7_ff3dc4cf);
         let r = 0x12a3343b_b453bb54_08da42d2_0c2d0fc8;
@@ -330,5 +637,80 @@ mod tests {
         let a2 = Addr([0x00, 0xa7, 0x13, 0x70, 0x2d, 0xcf, 0xc1]);
         let c = k.f6(n1, n2, r, io_cap, a1, a2);
         assert_eq!(c.0, 0xe3c47398_9cd0e8c5_d26c0b09_da958f61);
+        assert_eq!(k.f6(n1, n2, r, io_cap, a1, a2), c);
+    }
+
+    /// Data signing function ([Vol 3] Part H, Section 2.4.5).
+    #[test]
+    fn csrk_sign() {
+        let k = CSRK(0x2b7e1516_28aed2a6_abf71588_09cf4f3c);
+        let m = [0xd2, 0x03, 0x00, 0x01, 0x02];
+        let sig = k.sign(&m, 1);
+        assert_eq!(sig.mac, 0x37b1e52d_0738d14c);
+        assert!(k.verify(&m, &sig));
+        let sig2 = Signature {
+            sign_counter: 2,
+            ..sig
+        };
+        assert!(!k.verify(&m, &sig2));
+        assert!(!k.verify(&m[..4], &sig));
+    }
+
+    /// Signing and verifying an `ATT_SIGNED_WRITE_CMD` PDU
+    /// ([Vol 3] Part F, Section 3.4.5.4).
+    #[test]
+    fn csrk_signed_write() {
+        use structbuf::{Pack, StructBuf};
+        let k = CSRK(0x2b7e1516_28aed2a6_abf71588_09cf4f3c);
+        let mut pdu = StructBuf::with_capacity(3 + 2 + Signature::LEN);
+        pdu.append().put([0xd2, 0x03, 0x00, 0x01, 0x02]);
+        let sig = k.sign(&pdu, 7);
+        sig.pack(&mut pdu.append());
+        assert_eq!(pdu[5..9], [7, 0, 0, 0]);
+        assert_eq!(pdu[9..], sig.mac.to_le_bytes());
+
+        let (m, s) = pdu.split_at(5);
+        let v = Signature::unpack(&mut Unpacker::new(s)).unwrap();
+        assert_eq!(v, sig);
+        assert!(k.verify(m, &v));
+        assert!(!k.verify(&m[..4], &v));
+        let replay = Signature {
+            sign_counter: 8,
+            ..v
+        };
+        assert!(!k.verify(m, &replay));
+    }
+
+    /// Legacy pairing confirm value generation function
+    /// ([Vol 3] Part H, Section 2.2.3).
+    #[test]
+    fn tk_c1() {
+        let k = TK(0);
+        let r = Nonce(0x5783d521_56ad6f0e_6388274e_c6702ee0);
+        let preq = [0x01, 0x01, 0x00, 0x00, 0x10, 0x07, 0x07];
+        let pres = [0x02, 0x03, 0x00, 0x00, 0x08, 0x00, 0x05];
+        let ia = Addr::from_le_bytes(true, [0xa6, 0xa5, 0xa4, 0xa3, 0xa2, 0xa1]);
+        let ra = Addr::from_le_bytes(false, [0xb6, 0xb5, 0xb4, 0xb3, 0xb2, 0xb1]);
+        let c = k.c1(&r, &preq, &pres, ia, ra);
+        assert_eq!(c.0, 0x1e1e3fef_878988ea_d2a74dc5_bef13b86);
+        assert_ne!(c, k.c1(&r, &preq, &pres, ra, ia));
+    }
+
+    /// Legacy pairing key generation function
+    /// ([Vol 3] Part H, Section 2.2.4).
+    #[test]
+    fn tk_s1() {
+        let k = TK(0);
+        let r1 = Nonce(0x000f0e0d_0c0b0a09_11223344_55667788);
+        let r2 = Nonce(0x01020304_05060708_99aabbcc_ddeeff00);
+        assert_eq!(k.s1(&r1, &r2).0, 0x9a1fe1f0_e8b0f49b_5b4216ae_796da062);
+    }
+
+    /// Random address hash function ([Vol 3] Part H, Section D.7).
+    #[test]
+    fn irk_ah() {
+        let k = IRK(0xec0234a3_57c8ad05_341010a6_0a397d9b);
+        assert_eq!(k.ah(0x708194), 0x0dfbaa);
+        assert_eq!(k.ah(0xFF708194), 0x0dfbaa);
     }
 }
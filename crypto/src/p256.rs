@@ -1,5 +1,5 @@
-use std::fmt::Debug;
-use std::mem;
+use core::fmt::Debug;
+use core::mem;
 
 use p256::ecdh;
 use structbuf::{Packer, Unpacker};
@@ -16,11 +16,29 @@ pub struct SecretKey(p256::NonZeroScalar);
 debug_secret!(SecretKey);
 
 impl SecretKey {
-    /// Generates a new random secret key.
+    /// Generates a new random secret key using the OS CSPRNG.
     #[allow(clippy::new_without_default)]
+    #[cfg(feature = "std")]
     #[inline(always)]
     pub fn new() -> Self {
-        Self(p256::NonZeroScalar::random(&mut rand_core::OsRng))
+        Self::new_with(&mut rand_core::OsRng)
+    }
+
+    /// Generates a new random secret key using the specified CSPRNG.
+    #[inline(always)]
+    pub fn new_with(rng: &mut impl rand_core::CryptoRngCore) -> Self {
+        Self(p256::NonZeroScalar::random(rng))
+    }
+
+    /// Creates a secret key from a big-endian encoded scalar. Returns [`None`]
+    /// if the scalar is zero or not less than the curve order.
+    ///
+    /// This is only intended for reproducing test vectors and interoperability
+    /// testing. Pairing with a known secret key provides no security.
+    #[inline]
+    #[must_use]
+    pub fn dangerous_from_be_bytes(b: [u8; 32]) -> Option<Self> {
+        Option::from(p256::NonZeroScalar::from_repr(b.into())).map(Self)
     }
 
     /// Computes the associated public key.
@@ -41,16 +59,11 @@ impl SecretKey {
     /// from the same secret key ([Vol 3] Part H, Section 2.3.5.6.1).
     #[must_use]
     pub fn dh_key(&self, pk: PublicKey) -> Option<DHKey> {
-        use p256::elliptic_curve::sec1::FromEncodedPoint;
         if pk.is_debug() {
             return None; // TODO: Compile-time option for debug-only mode
         }
-        let (x, y) = (&pk.x.0 .0.into(), &pk.y.0.into());
-        let rep = p256::EncodedPoint::from_affine_coordinates(x, y, false);
+        let rpk = pk.to_point()?;
         let lpk = p256::PublicKey::from_secret_scalar(&self.0);
-        // Constant-time ops not required:
-        // https://github.com/RustCrypto/traits/issues/1227
-        let rpk = Option::from(p256::PublicKey::from_encoded_point(&rep)).unwrap_or(lpk);
         (rpk != lpk).then(|| DHKey(ecdh::diffie_hellman(&self.0, rpk.as_affine())))
     }
 }
@@ -64,12 +77,44 @@ pub struct PublicKey {
 }
 
 impl PublicKey {
+    /// Creates a public key from big-endian encoded affine coordinates.
+    /// Returns [`None`] if the key is not a valid P-256 point.
+    #[inline]
+    #[must_use]
+    pub fn from_be_coords(x: [u8; 32], y: [u8; 32]) -> Option<Self> {
+        let pk = Self {
+            x: PublicKeyX(Coord(x)),
+            y: Coord(y),
+        };
+        pk.validate().then_some(pk)
+    }
+
     /// Returns the public key X coordinate.
     #[inline(always)]
     pub const fn x(&self) -> &PublicKeyX {
         &self.x
     }
 
+    /// Returns whether `self` is a valid P-256 point. Both coordinates must be
+    /// less than the field modulus `p` and the point must be on the curve and
+    /// not the point at infinity ([Vol 3] Part H, Section 2.3.5.6.1).
+    #[inline]
+    #[must_use]
+    pub fn validate(&self) -> bool {
+        self.to_point().is_some()
+    }
+
+    /// Converts the key into a curve point. Returns [`None`] if the key is not
+    /// a valid point.
+    fn to_point(&self) -> Option<p256::PublicKey> {
+        use p256::elliptic_curve::sec1::FromEncodedPoint;
+        let (x, y) = (&self.x.0 .0.into(), &self.y.0.into());
+        let rep = p256::EncodedPoint::from_affine_coordinates(x, y, false);
+        // Constant-time ops not required:
+        // https://github.com/RustCrypto/traits/issues/1227
+        Option::from(p256::PublicKey::from_encoded_point(&rep))
+    }
+
     /// Returns whether `self` is the debug public key
     /// ([Vol 3] Part H, Section 2.3.5.6.1).
     #[allow(clippy::unreadable_literal)]
@@ -114,9 +159,8 @@ pub struct PublicKeyX(Coord);
 
 impl PublicKeyX {
     /// Creates the coordinate from a big-endian encoded byte array.
-    #[cfg(test)]
     #[inline]
-    pub(super) const fn from_be_bytes(x: [u8; mem::size_of::<Self>()]) -> Self {
+    pub const fn from_be_bytes(x: [u8; mem::size_of::<Self>()]) -> Self {
         Self(Coord(x))
     }
 
@@ -136,6 +180,14 @@ pub struct DHKey(ecdh::SharedSecret);
 debug_secret!(DHKey);
 
 impl DHKey {
+    /// Creates a shared secret from the big-endian encoded X coordinate of the
+    /// shared point. This is only intended for reproducing test vectors and
+    /// interoperability testing.
+    #[inline]
+    pub fn from_be_bytes(b: [u8; 32]) -> Self {
+        Self(ecdh::SharedSecret::from(p256::FieldBytes::from(b)))
+    }
+
     /// Generates LE Secure Connections `MacKey` and `LTK`
     /// ([Vol 3] Part H, Section 2.2.7).
     #[inline]
@@ -155,7 +207,8 @@ impl DHKey {
         let mut m = AesCmac::new(&Key::new(0x6C88_8391_AAF5_A538_6037_0BDB_5A60_83BE));
         m.update(self.0.raw_secret_bytes());
         let mut m = AesCmac::new(&m.finalize_key());
-        (MacKey(half(&mut m, 0)), LTK(u128::from(&half(&mut m, 1))))
+        let mac_key = MacKey(AesCmac::new(&half(&mut m, 0)));
+        (mac_key, LTK(u128::from(&half(&mut m, 1))))
     }
 }
 
@@ -308,6 +361,50 @@ mod tests {
         );
     }
 
+    /// Invalid curve points and reflected keys
+    /// ([Vol 3] Part H, Section 2.3.5.6.1).
+    #[test]
+    fn validate() {
+        let sk = secret_key(
+            0x55188b3d_32f6bb9a_900afcfb_eed4e72a,
+            0x59cb9ac2_f19d7cfb_6b4fdd49_f47fc5fd,
+        );
+        let pk = sk.public_key();
+        assert!(pk.validate());
+
+        // Point not on the curve
+        let mut bad = pk;
+        bad.y.0[31] ^= 1;
+        assert!(!bad.validate());
+        assert!(SecretKey::new().dh_key(bad).is_none());
+
+        // Point at infinity cannot be represented by affine coordinates
+        let zero = PublicKey {
+            x: PublicKeyX(Coord([0; 32])),
+            y: Coord([0; 32]),
+        };
+        assert!(!zero.validate());
+
+        // Coordinates not less than p
+        let p = u256::<[u8; 32]>(
+            0xffffffff_00000001_00000000_00000000,
+            0x00000000_ffffffff_ffffffff_ffffffff,
+        );
+        let big = PublicKey {
+            x: PublicKeyX(Coord(p)),
+            y: pk.y,
+        };
+        assert!(!big.validate());
+        let big = PublicKey {
+            x: pk.x,
+            y: Coord([0xff; 32]),
+        };
+        assert!(!big.validate());
+
+        // Peer reflects our own key
+        assert!(sk.dh_key(pk).is_none());
+    }
+
     /// Key generation function ([Vol 3] Part H, Section D.3).
     #[test]
     fn dh_key_f5() {
@@ -326,11 +423,11 @@ mod tests {
 
     #[inline]
     fn secret_key(hi: u128, lo: u128) -> SecretKey {
-        SecretKey(p256::NonZeroScalar::from_repr(u256(hi, lo)).unwrap())
+        SecretKey::dangerous_from_be_bytes(u256(hi, lo)).unwrap()
     }
 
     #[inline]
     fn shared_secret(hi: u128, lo: u128) -> DHKey {
-        DHKey(ecdh::SharedSecret::from(u256::<p256::FieldBytes>(hi, lo)))
+        DHKey::from_be_bytes(u256(hi, lo))
     }
 }
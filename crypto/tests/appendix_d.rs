@@ -0,0 +1,112 @@
+//! LE Secure Connections pairing computation using only the public API and
+//! the sample data from [Vol 3] Part H, Appendix D and [Vol 2] Part G,
+//! Section 7.1.2.1. AES-CMAC (Section D.1) is covered by the unit tests.
+
+#![allow(clippy::unreadable_literal)]
+#![allow(clippy::unusual_byte_groupings)]
+#![allow(unused_crate_dependencies)]
+
+use structbuf::Unpacker;
+
+use burble_crypto::*;
+
+#[test]
+fn pairing() {
+    // P-256 data set 1 ([Vol 2] Part G, Section 7.1.2.1)
+    let ska = SecretKey::dangerous_from_be_bytes(u256(
+        0x3f49f6d4_a3c55f38_74c9b3e3_d2103f50,
+        0x4aff607b_eb40b799_5899b8a6_cd3c1abd,
+    ))
+    .unwrap();
+    let pka = PublicKey::from_be_coords(
+        u256(
+            0x20b003d2_f297be2c_5e2c83a7_e9f9a5b9,
+            0xeff49111_acf4fddb_cc030148_0e359de6,
+        ),
+        u256(
+            0xdc809c49_652aeb6d_63329abf_5a52155c,
+            0x766345c2_8fed3024_741c8ed0_1589d28b,
+        ),
+    )
+    .unwrap();
+    let pkb = PublicKey::from_be_coords(
+        u256(
+            0x1ea1f0f0_1faf1d96_09592284_f19e4c00,
+            0x47b58afd_8615a69f_559077b2_2faaa190,
+        ),
+        u256(
+            0x4c55f33e_429dad37_7356703a_9ab85160,
+            0x472d1130_e28e3676_5f89aff9_15b1214a,
+        ),
+    )
+    .unwrap();
+    assert_eq!(ska.public_key(), pka);
+    let w = ska.dh_key(pkb).unwrap();
+
+    // Confirm value (Section D.2). The sample V value is not the X coordinate
+    // of a public key, so it is used as given.
+    let u = pka.x();
+    let v = PublicKeyX::from_be_bytes(u256(
+        0x55188b3d_32f6bb9a_900afcfb_eed4e72a,
+        0x59cb9ac2_f19d7cfb_6b4fdd49_f47fc5fd,
+    ));
+    let na: Nonce = val(0xd5cb8454_d177733e_ffffb2ec_712baeab);
+    let nb: Nonce = val(0xa6e8e7cc_25a75f6e_216583f7_ff3dc4cf);
+    assert_eq!(
+        na.f4(u, &v, 0),
+        val::<Confirm>(0xf2c916f1_07a9bd1c_f1eda1be_a974872d)
+    );
+
+    // Key generation (Section D.3)
+    let a1 = Addr::from_le_bytes(false, [0xce, 0xbf, 0x37, 0x37, 0x12, 0x56]);
+    let a2 = Addr::from_le_bytes(false, [0xc1, 0xcf, 0x2d, 0x70, 0x13, 0xa7]);
+    let (mac_key, ltk) = w.f5(na, nb, a1, a2);
+    assert_eq!(u128::from(&ltk), 0x69867911_69d7cd23_980522b5_94750a38);
+    let dh_key = DHKey::from_be_bytes(u256(
+        0xec0234a3_57c8ad05_341010a6_0a397d9b,
+        0x99796b13_b4f866f1_868d34f3_73bfa698,
+    ));
+    assert_eq!(dh_key.f5(na, nb, a1, a2).1, ltk);
+
+    // Check value (Section D.4)
+    let r = 0x12a3343b_b453bb54_08da42d2_0c2d0fc8;
+    let io_cap = IoCap::new(0x01, true, 0x02);
+    assert_eq!(
+        mac_key.f6(na, nb, r, io_cap, a1, a2),
+        val::<Check>(0xe3c47398_9cd0e8c5_d26c0b09_da958f61)
+    );
+
+    // Numeric comparison value (Section D.5)
+    assert_eq!(na.g2(u, &v, &nb).to_string(), "938554");
+}
+
+#[test]
+fn invalid() {
+    assert!(SecretKey::dangerous_from_be_bytes([0; 32]).is_none());
+    assert!(SecretKey::dangerous_from_be_bytes([0xff; 32]).is_none());
+    assert!(PublicKey::from_be_coords([0; 32], [0; 32]).is_none());
+    let x = u256(
+        0x20b003d2_f297be2c_5e2c83a7_e9f9a5b9,
+        0xeff49111_acf4fddb_cc030148_0e359de6,
+    );
+    let mut y = u256(
+        0xdc809c49_652aeb6d_63329abf_5a52155c,
+        0x766345c2_8fed3024_741c8ed0_1589d28b,
+    );
+    assert!(PublicKey::from_be_coords(x, y).is_some());
+    y[31] ^= 1;
+    assert!(PublicKey::from_be_coords(x, y).is_none());
+}
+
+/// Combines `hi` and `lo` values into a big-endian byte array.
+fn u256(hi: u128, lo: u128) -> [u8; 32] {
+    let mut b = [0; 32];
+    b[..16].copy_from_slice(&hi.to_be_bytes());
+    b[16..].copy_from_slice(&lo.to_be_bytes());
+    b
+}
+
+/// Decodes a 128-bit value from its SMP PDU encoding.
+fn val<T: Codec>(v: u128) -> T {
+    T::unpack(&mut Unpacker::new(&v.to_le_bytes())).unwrap()
+}
@@ -0,0 +1,27 @@
+//! Verifies that the crate builds for a bare-metal target without `std`.
+
+#![allow(unused_crate_dependencies)]
+
+use std::path::Path;
+use std::process::Command;
+
+const TARGET: &str = "thumbv7em-none-eabihf";
+
+#[test]
+#[ignore = "requires the thumbv7em-none-eabihf target (rustup target add thumbv7em-none-eabihf)"]
+fn thumbv7em() {
+    let status = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args([
+            "build",
+            "--lib",
+            "--no-default-features",
+            "--target",
+            TARGET,
+        ])
+        .arg("--target-dir")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"))
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "no_std build for {TARGET} failed");
+}
@@ -59,8 +59,9 @@ async fn main() -> Result<()> {
     host.init(&hci::EventMask::default()).await?;
     host.le_set_default_phy(Some(hci::PhyMask::LE_2M), Some(hci::PhyMask::LE_2M))
         .await?;
-    let r = serve(args, host).await;
-    event_loop.stop().await?;
+    let r = serve(args, host.clone()).await;
+    host.shutdown(Duration::from_secs(2)).await;
+    event_loop.await?;
     r
 }
 
@@ -116,9 +117,11 @@ async fn serve(args: Args, host: hci::Host) -> Result<()> {
         .with_manufacturer_name("Blackrock Neurotech")
         // [HOGP] Section 3.3.2
         .with_pnp_id(dis::PnpId::new(dis::VendorId::USB(0x1209), 0x0001, (1, 0, 0)).unwrap())
-        .define(&mut db, SEC);
+        .with_perms(SEC)
+        .define(&mut db);
     bas::BatteryService::new().define(&mut db, SEC);
-    let hid = HidService::new(KeyboardMouse::new(Keyboard::us(1), Mouse::new(2, 400)));
+    let hid =
+        HidService::new(KeyboardMouse::new(Keyboard::us(1), Mouse::new(2, 400))).with_security(SEC);
     //#[cfg(debug_assertions)]
     //db.morph_next();
     hid.define(&mut db);
@@ -191,7 +194,7 @@ async fn advertise(args: Args, host: hci::Host) -> hci::Result<AdvEvent> {
             ..hci::AdvParams::default()
         }
     };
-    let (h, power) = adv.create(params).await?;
+    let (set, power) = adv.create(params).await?;
     let mut data = gap::ResponseDataMut::new();
     data.flags(gap::AdvFlag::LE_GENERAL | gap::AdvFlag::NO_BREDR)
         // [HOGP] Section 3.1.3
@@ -201,9 +204,9 @@ async fn advertise(args: Args, host: hci::Host) -> hci::Result<AdvEvent> {
         // [HOGP] Section 3.1.5
         .appearance(Appearance::GenericHumanInterfaceDevice)
         .tx_power(power);
-    adv.set_data(h, data.get()).await?;
+    adv.set_data(&set, data.get()).await?;
     let enable_params = hci::AdvEnableParams {
-        handle: h,
+        handle: set.handle(),
         duration: Duration::from_secs(20),
         max_events: 0,
     };
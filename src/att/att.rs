@@ -1,11 +1,13 @@
 //! Attribute Protocol ([Vol 3] Part F).
 
 use std::fmt::{Debug, Display, Formatter};
-use std::time::Duration;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 
 use structbuf::{Pack, Packer, Unpacker};
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, trace, warn};
 
+pub(crate) use pdu::ValuePdu;
 pub use {consts::*, handle::*, perm::*};
 
 use crate::gap::Uuid;
@@ -29,6 +31,10 @@ pub enum Error {
     Timeout(Opcode),
     #[error("notification/indication session closed")]
     NotifyClosed,
+    #[error("characteristic {1} not found in service {0}")]
+    CharacteristicNotFound(Uuid, Uuid),
+    #[error("multiple instances of characteristic {1} in service {0}; use a CharRef")]
+    AmbiguousCharacteristic(Uuid, Uuid),
 }
 
 /// Common ATT result type.
@@ -67,6 +73,13 @@ impl ErrorRsp {
     pub(crate) const fn new(req: u8, hdl: Option<Handle>, err: ErrorCode) -> Self {
         Self { req, hdl, err }
     }
+
+    /// Returns the error code.
+    #[inline(always)]
+    #[must_use]
+    pub const fn code(&self) -> ErrorCode {
+        self.err
+    }
 }
 
 impl Display for ErrorRsp {
@@ -86,8 +99,11 @@ impl Display for ErrorRsp {
 
 /// ATT bearer ([Vol 3] Part F, Section 3.2.11).
 #[derive(Clone, Debug)]
-#[repr(transparent)]
-pub struct Bearer(Chan);
+pub struct Bearer {
+    ch: Chan,
+    max_mtu: Option<u16>,
+    mtu_exchanged: bool,
+}
 
 impl Bearer {
     /// Creates an ATT bearer by associating an L2CAP channel with an ATT
@@ -95,27 +111,69 @@ impl Bearer {
     #[inline]
     #[must_use]
     pub(crate) const fn new(ch: Chan) -> Self {
-        Self(ch)
+        Self {
+            ch,
+            max_mtu: None,
+            mtu_exchanged: false,
+        }
+    }
+
+    /// Sets the MTU that is offered during MTU exchange, which is limited to
+    /// [`MAX_MTU`]. By default, the MTU is limited such that each PDU fits in
+    /// a single ACL data packet. A larger MTU requires PDUs to be fragmented,
+    /// but allows long attribute values to be read and written with fewer
+    /// requests. This must be called before the MTU is exchanged.
+    #[inline]
+    pub fn set_max_mtu(&mut self, mtu: u16) {
+        assert!(mtu >= LE_DEFAULT_MTU, "MTU too small");
+        self.max_mtu = Some(mtu.min(MAX_MTU));
     }
 
     /// Returns the channel ID.
     #[inline(always)]
     #[must_use]
     pub fn cid(&self) -> LeCid {
-        self.0.cid()
+        self.ch.cid()
+    }
+
+    /// Sets the transmit priority of the bearer relative to other channels on
+    /// the same logical link. ATT bearers use [`l2cap::Priority::Normal`] by
+    /// default.
+    #[inline]
+    pub fn set_priority(&self, p: l2cap::Priority) {
+        self.ch.set_priority(p);
+    }
+
+    /// Limits the number of received commands that may be queued while the
+    /// bearer is busy. Commands are identified by the Command Flag
+    /// ([Vol 3] Part F, Section 3.3.1). Requests are never dropped because
+    /// the client must wait for a response before sending another one.
+    #[inline]
+    pub(crate) fn limit_cmds(
+        &self,
+        max: usize,
+        policy: l2cap::CmdOverflow,
+        dropped: Arc<AtomicU64>,
+    ) {
+        self.ch.set_cmd_limit(l2cap::CmdLimit {
+            max,
+            policy,
+            is_cmd: |sdu| sdu.first().map_or(false, |&op| op & 0x40 != 0),
+            dropped,
+        });
     }
 
     /// Returns the connection watch channel.
     #[inline(always)]
     pub(crate) fn conn(&self) -> &hci::ConnWatch {
-        self.0.conn()
+        self.ch.conn()
     }
 
     /// Returns the current MTU.
     #[inline(always)]
     #[must_use]
     pub const fn mtu(&self) -> u16 {
-        self.0.mtu()
+        self.ch.mtu()
     }
 
     /// Executes a request and returns the response PDU.
@@ -126,10 +184,10 @@ impl Bearer {
         self.recv_rsp(rsp).await.map(Pdu)
     }
 
-    /// Returns the next command, request, notification, or indication PDU. This
-    /// method is cancel safe.
+    /// Returns the next command, request, notification, indication, or
+    /// confirmation PDU. This method is cancel safe.
     pub async fn recv(&mut self) -> Result<Pdu> {
-        let pdu = self.0.recv().await?;
+        let pdu = self.ch.recv().await?;
         // [Vol 3] Part F, Section 3.3
         let Some(&op) = pdu.as_ref().first() else {
             warn!("Empty PDU");
@@ -139,7 +197,7 @@ impl Bearer {
             warn!("Unknown opcode: {op}");
             return Err(ErrorRsp::new(op, None, ErrorCode::RequestNotSupported).into());
         };
-        if matches!(op.typ(), PduType::Rsp | PduType::Cfm) {
+        if matches!(op.typ(), PduType::Rsp) {
             // TODO: This should probably be ignored.
             warn!("Unexpected PDU: {op}");
             return Err(ErrorRsp::new(op as _, None, ErrorCode::UnlikelyError).into());
@@ -157,7 +215,7 @@ impl Bearer {
     /// Panics if the `pdu` is not a read/write request.
     #[inline]
     pub(crate) fn access_req(&self, pdu: &Pdu) -> Request {
-        let sec = self.0.conn().borrow().sec;
+        let sec = self.ch.conn().borrow().sec;
         pdu.opcode().request(sec)
     }
 
@@ -182,10 +240,10 @@ impl Bearer {
 
     /// Performs MTU exchange ([Vol 3] Part F, Section 3.2.8 and 3.4.2.1).
     pub(crate) async fn exchange_mtu(&mut self) -> Result<()> {
-        if self.0.cid().chan != Cid::ATT {
+        if self.ch.cid().chan != Cid::ATT {
             return Ok(());
         }
-        let local = self.0.preferred_mtu();
+        let local = self.local_mtu();
         let req = self.pack(Opcode::ExchangeMtuReq, |p| {
             p.u16(local);
         });
@@ -210,7 +268,7 @@ impl Bearer {
                 Opcode::ExchangeMtuRsp => {
                     let remote = pdu.unpack(Opcode::ExchangeMtuRsp, |p| Ok(p.u16()))?;
                     debug!("{} remote preferred MTU: {}", self.cid(), remote);
-                    self.0.set_mtu(local.min(remote));
+                    self.set_exchanged_mtu(local, remote);
                     return Ok(());
                 }
                 _ => unreachable!(),
@@ -218,12 +276,18 @@ impl Bearer {
         }
     }
 
-    /// Handles `ATT_EXCHANGE_MTU_REQ` ([Vol 3] Part F, Section 3.4.2.1).
+    /// Handles `ATT_EXCHANGE_MTU_REQ` ([Vol 3] Part F, Section 3.4.2.1). The
+    /// server always responds with its own MTU, but the request is only used to
+    /// change the MTU once per bearer ([Vol 3] Part G, Section 4.3.1).
     pub(crate) async fn handle_exchange_mtu_req(&mut self, pdu: &Pdu) -> Result<()> {
         let r = (pdu.unpack(Opcode::ExchangeMtuReq, |p| Ok(p.u16()))).and_then(|remote| {
             debug!("{} remote preferred MTU: {}", self.cid(), remote);
-            let local = self.0.preferred_mtu();
-            self.0.set_mtu(local.min(remote));
+            let local = self.local_mtu();
+            if self.mtu_exchanged {
+                warn!("{} MTU already exchanged", self.cid());
+            } else {
+                self.set_exchanged_mtu(local, remote);
+            }
             self.rsp(Opcode::ExchangeMtuRsp, |p| {
                 p.u16(local);
                 Ok(())
@@ -232,16 +296,28 @@ impl Bearer {
         self.send_rsp(r).await
     }
 
+    /// Returns the MTU offered during MTU exchange.
+    #[inline]
+    fn local_mtu(&self) -> u16 {
+        (self.max_mtu).unwrap_or_else(|| self.ch.preferred_mtu().min(MAX_MTU))
+    }
+
+    /// Sets the MTU after a completed exchange.
+    #[inline]
+    fn set_exchanged_mtu(&mut self, local: u16, remote: u16) {
+        self.mtu_exchanged = true;
+        self.ch.set_mtu(exchange_mtu(local, remote));
+    }
+
     /// Receives a response or confirmation PDU ([Vol 3] Part F, Section 3.4.9).
     /// If `rsp` is `ExchangeMtuRsp`, then this will also return any received
     /// `ExchangeMtuReq` to avoid a deadlock.
     async fn recv_rsp(&mut self, rsp: Opcode) -> Result<Payload> {
         let want = u8::from(rsp);
         let err = matches!(rsp.typ(), PduType::Rsp).then_some(Opcode::ErrorRsp as u8);
-        // Transaction timeout ([Vol 3] Part F, Section 3.3.3)
         let r = tokio::time::timeout(
-            Duration::from_secs(30),
-            self.0.recv_filter(|mut pdu| {
+            TRANSACTION_TIMEOUT,
+            self.ch.recv_filter(|mut pdu| {
                 let have = pdu.u8();
                 have == want
                     || (Some(have) == err && pdu.u8() == want)
@@ -252,10 +328,7 @@ impl Bearer {
         let pdu = match r.await {
             Ok(Ok(pdu)) => pdu,
             Ok(Err(e)) => return Err(e.into()),
-            Err(_) => {
-                self.0.set_error();
-                return Err(Error::Timeout(rsp));
-            }
+            Err(_) => return Err(self.timeout(rsp)),
         };
         let mut p = Unpacker::new(pdu.as_ref());
         if Some(p.u8()) == err {
@@ -269,11 +342,20 @@ impl Bearer {
         }
     }
 
+    /// Marks the bearer as unusable after a transaction timeout while waiting
+    /// for `op` and returns the corresponding error
+    /// ([Vol 3] Part F, Section 3.3.3).
+    #[inline]
+    pub(crate) fn timeout(&self, op: Opcode) -> Error {
+        self.ch.set_error();
+        Error::Timeout(op)
+    }
+
     /// Returns a response PDU.
     #[allow(clippy::unnecessary_wraps)]
     #[inline(always)]
     fn rsp(&self, op: Opcode, f: impl FnOnce(&mut Packer) -> RspResult<()>) -> RspResult<Rsp> {
-        let mut pdu = self.0.alloc();
+        let mut pdu = self.ch.alloc();
         f(pdu.append().u8(op))?;
         trace!("{op}: {:02X?}", &pdu.as_ref()[1..]);
         Ok(Rsp(pdu))
@@ -283,7 +365,7 @@ impl Bearer {
     /// opcode.
     #[inline(always)]
     fn pack(&self, op: Opcode, f: impl FnOnce(&mut Packer)) -> Payload {
-        let mut pdu = self.0.alloc();
+        let mut pdu = self.ch.alloc();
         f(pdu.append().u8(op));
         trace!("{op}: {:02X?}", &pdu.as_ref()[1..]);
         pdu
@@ -292,7 +374,20 @@ impl Bearer {
     /// Sends a PDU over the channel.
     #[inline(always)]
     async fn send(&mut self, pdu: Payload) -> Result<()> {
-        Ok(self.0.send(pdu).await?)
+        Ok(self.ch.send(pdu).await?)
+    }
+}
+
+/// Returns the MTU resulting from an exchange of `local` and `remote` values.
+/// If either value is less than the default MTU, the default MTU is used
+/// ([Vol 3] Part F, Section 3.4.2.2).
+#[inline]
+#[must_use]
+fn exchange_mtu(local: u16, remote: u16) -> u16 {
+    if local < LE_DEFAULT_MTU || remote < LE_DEFAULT_MTU {
+        LE_DEFAULT_MTU
+    } else {
+        local.min(remote)
     }
 }
 
@@ -345,3 +440,19 @@ impl<'a, H: Copy, F: FnMut() -> Option<(H, &'a [u8])>> ValueIter<H> for ValueFn<
         self.v.unwrap().1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_mtu() {
+        use super::exchange_mtu;
+        assert_eq!(exchange_mtu(23, 23), 23);
+        assert_eq!(exchange_mtu(247, 23), 23);
+        assert_eq!(exchange_mtu(247, 517), 247);
+        assert_eq!(exchange_mtu(517, 247), 247);
+        assert_eq!(exchange_mtu(247, 22), LE_DEFAULT_MTU);
+        assert_eq!(exchange_mtu(247, 0), LE_DEFAULT_MTU);
+    }
+}
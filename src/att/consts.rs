@@ -1,12 +1,27 @@
 use std::fmt::Debug;
+use std::time::Duration;
 
 use crate::hci;
 
 use super::*;
 
+/// Default `ATT_MTU` for LE ([Vol 3] Part F, Section 3.2.8).
+pub(crate) const LE_DEFAULT_MTU: u16 = 23;
+
+/// Maximum `ATT_MTU` that can be negotiated. This is the smallest MTU that
+/// allows a maximum-length attribute value to be sent in a single
+/// `ATT_PREPARE_WRITE_REQ` PDU ([Vol 3] Part F, Section 3.2.9 and 3.4.6.1).
+pub const MAX_MTU: u16 = 517;
+
+/// Transaction timeout ([Vol 3] Part F, Section 3.3.3).
+pub(crate) const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Maximum attribute value length ([Vol 3] Part F, Section 3.2.9).
 pub(crate) const MAX_VAL_LEN: usize = 512;
 
+/// Authentication Signature length ([Vol 3] Part F, Section 3.3.1).
+pub(crate) const SIGNATURE_LEN: usize = 12;
+
 /// Attribute opcode ([Vol 3] Part F, Section 3.3.1 and
 /// [Vol 3] Part F, Section 3.4.8).
 #[derive(
@@ -253,6 +268,21 @@ pub enum ErrorCode {
     DatabaseOutOfSync = 0x12,
     /// The attribute parameter value was not allowed.
     ValueNotAllowed = 0x13,
+    /// Application error indicating that the requested control point operation
+    /// is not supported ([HRS] Section 3.3.2.1, [BMS] Section 3.3.2.1).
+    ///
+    /// [HRS]: https://www.bluetooth.com/specifications/specs/heart-rate-service-1-0/
+    /// [BMS]: https://www.bluetooth.com/specifications/specs/bond-management-service-1-0-1/
+    ControlPointNotSupported = 0x80,
+    /// Application error indicating that the server was unable to complete the
+    /// requested control point operation ([BMS] Section 3.3.2.1), that the
+    /// requested trigger condition is not supported ([ESS] Section 1.6), or
+    /// that no object is selected ([OTS] Section 3.2).
+    ///
+    /// [BMS]: https://www.bluetooth.com/specifications/specs/bond-management-service-1-0-1/
+    /// [ESS]: https://www.bluetooth.com/specifications/specs/environmental-sensing-service-1-0/
+    /// [OTS]: https://www.bluetooth.com/specifications/specs/object-transfer-service-1-0/
+    OperationFailed = 0x81,
     /// Write operation cannot be fulfilled for reasons other than permissions.
     WriteRequestRejected = 0xFC,
     /// Client Characteristic Configuration descriptor is not configured
@@ -265,4 +295,46 @@ pub enum ErrorCode {
     OutOfRange = 0xFF,
 }
 
+impl ErrorCode {
+    /// Returns the error that a request requiring security should fail with
+    /// after the controller failed to enable encryption with status `st`
+    /// ([Vol 3] Part C, Section 10.3.1.1). A missing LTK or failed
+    /// authentication means that the client must pair again, so it maps to
+    /// [`Self::InsufficientAuthentication`]. All other failures leave the
+    /// existing keys usable and map to [`Self::InsufficientEncryption`].
+    #[must_use]
+    pub const fn for_encryption_status(st: hci::Status) -> Self {
+        use hci::Status::*;
+        match st {
+            PinOrKeyMissing | AuthenticationFailure | PairingNotAllowed => {
+                Self::InsufficientAuthentication
+            }
+            _ => Self::InsufficientEncryption,
+        }
+    }
+}
+
 crate::impl_display_via_debug! { Opcode, ErrorCode }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encryption_status() {
+        use hci::Status::*;
+        let f = ErrorCode::for_encryption_status;
+        assert_eq!(f(PinOrKeyMissing), ErrorCode::InsufficientAuthentication);
+        assert_eq!(
+            f(AuthenticationFailure),
+            ErrorCode::InsufficientAuthentication
+        );
+        assert_eq!(f(InsufficientSecurity), ErrorCode::InsufficientEncryption);
+        assert_eq!(
+            f(EncryptionModeNotAcceptable),
+            ErrorCode::InsufficientEncryption
+        );
+        assert_eq!(f(LmpLlResponseTimeout), ErrorCode::InsufficientEncryption);
+        assert_eq!(f(UnspecifiedError), ErrorCode::InsufficientEncryption);
+    }
+}
@@ -5,6 +5,7 @@ use structbuf::Unpack;
 use tracing::trace;
 
 use burble_const::UuidPacker;
+use burble_crypto::{Codec, Signature, CSRK};
 use {ErrorCode::*, Opcode::*};
 
 use crate::gap::Uuid16;
@@ -176,6 +177,14 @@ impl Pdu {
         self.unpack(ReadReq, |p| self.handle(p))
     }
 
+    /// Returns `ATT_READ_RSP` or `ATT_READ_BLOB_RSP` PDU parameters
+    /// ([Vol 3] Part F, Section 3.4.4.4 and 3.4.4.6).
+    pub fn read_rsp(&self) -> RspResult<&[u8]> {
+        let op = self.opcode();
+        debug_assert!(matches!(op, ReadRsp | ReadBlobRsp));
+        self.unpack(op, |p| Ok(take(p)))
+    }
+
     /// Returns `ATT_READ_BLOB_REQ` PDU parameters
     /// ([Vol 3] Part F, Section 3.4.4.5).
     pub fn read_blob_req(&self) -> RspResult<(Handle, u16)> {
@@ -194,6 +203,12 @@ impl Pdu {
         self.read_by_type_op(ReadByGroupTypeReq)
     }
 
+    /// Returns `ATT_READ_BY_GROUP_TYPE_RSP` PDU parameters
+    /// ([Vol 3] Part F, Section 3.4.4.10).
+    pub fn read_by_group_type_rsp(&self) -> RspResult<MultiValueRsp<HandleRange>> {
+        MultiValueRsp::new(self)
+    }
+
     /// Returns `ATT_READ_MULTIPLE_VARIABLE_REQ` PDU parameters
     /// ([Vol 3] Part F, Section 3.4.4.11).
     pub fn read_multiple_variable_req(&self) -> RspResult<Vec<Handle>> {
@@ -246,11 +261,25 @@ impl Bearer {
         })
     }
 
+    /// Returns an `ATT_READ_REQ` PDU ([Vol 3] Part F, Section 3.4.4.3).
+    pub fn read_req(&self, hdl: Handle) -> Req {
+        Req(self.pack(ReadReq, |p| {
+            p.u16(hdl);
+        }))
+    }
+
     /// Returns an `ATT_READ_RSP` PDU ([Vol 3] Part F, Section 3.4.4.4).
     pub fn read_rsp(&self, v: &[u8]) -> RspResult<Rsp> {
         self.read_op(ReadRsp, v)
     }
 
+    /// Returns an `ATT_READ_BLOB_REQ` PDU ([Vol 3] Part F, Section 3.4.4.5).
+    pub fn read_blob_req(&self, hdl: Handle, off: u16) -> Req {
+        Req(self.pack(ReadBlobReq, |p| {
+            p.u16(hdl).u16(off);
+        }))
+    }
+
     /// Returns an `ATT_READ_BLOB_RSP` PDU ([Vol 3] Part F, Section 3.4.4.6).
     pub fn read_blob_rsp(&self, v: &[u8]) -> RspResult<Rsp> {
         self.read_op(ReadBlobRsp, v)
@@ -268,6 +297,14 @@ impl Bearer {
         })
     }
 
+    /// Returns an `ATT_READ_BY_GROUP_TYPE_REQ` PDU
+    /// ([Vol 3] Part F, Section 3.4.4.9).
+    pub fn read_by_group_type_req(&self, hdls: HandleRange, uuid: impl Into<Uuid>) -> Req {
+        Req(self.pack(ReadByGroupTypeReq, |p| {
+            p.u16(hdls.start()).u16(hdls.end()).uuid(uuid);
+        }))
+    }
+
     /// Returns an `ATT_READ_BY_GROUP_TYPE_RSP` PDU
     /// ([Vol 3] Part F, Section 3.4.4.10).
     #[allow(single_use_lifetimes)]
@@ -347,22 +384,79 @@ impl Bearer {
 
 /// Writing attributes parameter decoders ([Vol 3] Part F, Section 3.4.5).
 impl Pdu {
-    /// Returns `ATT_WRITE_REQ` or `ATT_WRITE_CMD` PDU parameters
-    /// ([Vol 3] Part F, Section 3.4.5.1 and 3.4.5.3).
+    /// Returns `ATT_WRITE_REQ`, `ATT_WRITE_CMD`, or `ATT_SIGNED_WRITE_CMD` PDU
+    /// parameters without the Authentication Signature
+    /// ([Vol 3] Part F, Section 3.4.5.1, 3.4.5.3, and 3.4.5.4).
     pub fn write_req(&self) -> RspResult<(Handle, &[u8])> {
         let op = self.opcode();
-        debug_assert!(matches!(op, WriteReq | WriteCmd));
-        // TODO: Strip signature for ATT_SIGNED_WRITE_CMD?
-        self.unpack(op, |p| Ok((self.handle(p)?, take(p))))
+        debug_assert!(matches!(op, WriteReq | WriteCmd | SignedWriteCmd));
+        self.unpack(op, |p| {
+            let hdl = self.handle(p)?;
+            if !op.is_signed() {
+                return Ok((hdl, take(p)));
+            }
+            // The signature is verified using Self::signed_write_cmd()
+            let v = p.skip(p.len().saturating_sub(SIGNATURE_LEN));
+            p.skip(SIGNATURE_LEN); // Invalidates `p` if the signature is truncated
+            Ok((hdl, v.map_or(&[][..], Unpacker::into_inner)))
+        })
+    }
+
+    /// Returns the signed data and Authentication Signature of an
+    /// `ATT_SIGNED_WRITE_CMD` PDU ([Vol 3] Part F, Section 3.4.5.4). The
+    /// signed data includes the opcode.
+    pub fn signed_write_cmd(&self) -> RspResult<(&[u8], Signature)> {
+        let b = self.0.as_ref();
+        debug_assert_eq!(self.opcode(), SignedWriteCmd);
+        let Some(n) = b.len().checked_sub(SIGNATURE_LEN).filter(|&n| n >= 3) else {
+            return self.err(InvalidPdu);
+        };
+        let (m, sig) = b.split_at(n);
+        match Signature::unpack(&mut Unpacker::new(sig)) {
+            Some(sig) => Ok((m, sig)),
+            None => self.err(InvalidPdu),
+        }
     }
 }
 
 /// Writing attributes encoders ([Vol 3] Part F, Section 3.4.5).
 impl Bearer {
+    /// Returns an `ATT_WRITE_REQ` PDU ([Vol 3] Part F, Section 3.4.5.1).
+    pub fn write_req(&self, hdl: Handle, v: &[u8]) -> Req {
+        Req(self.pack(WriteReq, |p| {
+            p.u16(hdl).put(v);
+        }))
+    }
+
     /// Returns an `ATT_WRITE_RSP` PDU ([Vol 3] Part F, Section 3.4.5.2).
     pub fn write_rsp(&self) -> RspResult<Rsp> {
         self.rsp(WriteRsp, |_| Ok(()))
     }
+
+    /// Sends an `ATT_WRITE_CMD` PDU ([Vol 3] Part F, Section 3.4.5.3).
+    pub async fn write_cmd(&mut self, hdl: Handle, v: &[u8]) -> Result<()> {
+        let cmd = self.pack(WriteCmd, |p| {
+            p.u16(hdl).put(v);
+        });
+        self.send(cmd).await
+    }
+
+    /// Sends an `ATT_SIGNED_WRITE_CMD` PDU signed with `csrk` using sign
+    /// counter `ctr` ([Vol 3] Part F, Section 3.4.5.4).
+    pub async fn signed_write_cmd(
+        &mut self,
+        hdl: Handle,
+        v: &[u8],
+        csrk: &CSRK,
+        ctr: u32,
+    ) -> Result<()> {
+        let mut cmd = self.pack(SignedWriteCmd, |p| {
+            p.u16(hdl).put(v);
+        });
+        let sig = csrk.sign(cmd.as_ref(), ctr);
+        sig.pack(&mut cmd.append());
+        self.send(cmd).await
+    }
 }
 
 //
@@ -377,6 +471,12 @@ impl Pdu {
         self.unpack(PrepareWriteReq, |p| Ok((self.handle(p)?, p.u16(), take(p))))
     }
 
+    /// Returns `ATT_PREPARE_WRITE_RSP` PDU parameters
+    /// ([Vol 3] Part F, Section 3.4.6.2).
+    pub fn prepare_write_rsp(&self) -> RspResult<(Handle, u16, &[u8])> {
+        self.unpack(PrepareWriteRsp, |p| Ok((self.handle(p)?, p.u16(), take(p))))
+    }
+
     /// Returns `ATT_EXECUTE_WRITE_REQ` PDU parameters
     /// ([Vol 3] Part F, Section 3.4.6.3).
     pub fn execute_write_req(&self) -> RspResult<bool> {
@@ -386,6 +486,14 @@ impl Pdu {
 
 /// Queued writes encoders ([Vol 3] Part F, Section 3.4.6).
 impl Bearer {
+    /// Returns an `ATT_PREPARE_WRITE_REQ` PDU
+    /// ([Vol 3] Part F, Section 3.4.6.1).
+    pub fn prepare_write_req(&self, hdl: Handle, off: u16, v: &[u8]) -> Req {
+        Req(self.pack(PrepareWriteReq, |p| {
+            p.u16(hdl).u16(off).put(v);
+        }))
+    }
+
     /// Returns an `ATT_PREPARE_WRITE_RSP` PDU
     /// ([Vol 3] Part F, Section 3.4.6.2).
     pub fn prepare_write_rsp(&self, hdl: Handle, off: u16, v: &[u8]) -> RspResult<Rsp> {
@@ -395,6 +503,14 @@ impl Bearer {
         })
     }
 
+    /// Returns an `ATT_EXECUTE_WRITE_REQ` PDU
+    /// ([Vol 3] Part F, Section 3.4.6.3).
+    pub fn execute_write_req(&self, commit: bool) -> Req {
+        Req(self.pack(ExecuteWriteReq, |p| {
+            p.u8(u8::from(commit));
+        }))
+    }
+
     /// Returns an `ATT_EXECUTE_WRITE_RSP` PDU
     /// ([Vol 3] Part F, Section 3.4.6.4).
     pub fn execute_write_rsp(&self) -> RspResult<Rsp> {
@@ -406,6 +522,17 @@ impl Bearer {
 // Server initiated ([Vol 3] Part F, Section 3.4.7)
 //
 
+/// Server initiated decoders ([Vol 3] Part F, Section 3.4.7).
+impl Pdu {
+    /// Returns `ATT_HANDLE_VALUE_NTF` or `ATT_HANDLE_VALUE_IND` PDU parameters
+    /// ([Vol 3] Part F, Section 3.4.7.1 and 3.4.7.2).
+    pub fn handle_value(&self) -> RspResult<(Handle, &[u8])> {
+        let op = self.opcode();
+        debug_assert!(matches!(op, HandleValueNtf | HandleValueInd));
+        self.unpack(op, |p| Ok((self.handle(p)?, take(p))))
+    }
+}
+
 /// Server initiated encoders ([Vol 3] Part F, Section 3.4.7).
 impl Bearer {
     /// Sends an `ATT_HANDLE_VALUE_NTF` PDU ([Vol 3] Part F, Section 3.4.7.1).
@@ -417,12 +544,44 @@ impl Bearer {
     /// Sends an `ATT_HANDLE_VALUE_IND` PDU and waits for the confirmation
     /// ([Vol 3] Part F, Section 3.4.7.2).
     pub async fn handle_value_ind(&mut self, hdl: Handle, v: &[u8]) -> Result<()> {
-        let ind = self.pack(HandleValueInd, |p| put_truncate(p.u16(hdl), v));
-        self.send(ind).await?;
+        self.send_handle_value_ind(hdl, v).await?;
         drop(self.recv_rsp(HandleValueCfm).await?);
         Ok(())
     }
 
+    /// Sends an `ATT_HANDLE_VALUE_IND` PDU without waiting for the
+    /// confirmation, which is returned by [`Self::recv`]. The caller is
+    /// responsible for the transaction timeout.
+    pub(crate) async fn send_handle_value_ind(&mut self, hdl: Handle, v: &[u8]) -> Result<()> {
+        let ind = self.pack(HandleValueInd, |p| put_truncate(p.u16(hdl), v));
+        self.send(ind).await
+    }
+
+    /// Sends an `ATT_HANDLE_VALUE_NTF` or `ATT_HANDLE_VALUE_IND` PDU that was
+    /// encoded before the bearer was available. The caller is responsible for
+    /// receiving the confirmation of an indication, as with
+    /// [`Self::send_handle_value_ind`].
+    pub(crate) async fn send_handle_value(&mut self, v: ValuePdu, ind: bool) -> Result<()> {
+        if v.pdu.as_ref().len() > usize::from(self.mtu()) {
+            // The value was encoded for a larger MTU
+            return if ind {
+                self.send_handle_value_ind(v.hdl, v.value()).await
+            } else {
+                self.handle_value_ntf(v.hdl, v.value()).await
+            };
+        }
+        let (op, mut pdu) = (if ind { HandleValueInd } else { HandleValueNtf }, v.pdu);
+        pdu.at(0).u8(op);
+        trace!("{op}: {:02X?}", &pdu.as_ref()[1..]);
+        self.send(pdu).await
+    }
+
+    /// Sends an `ATT_HANDLE_VALUE_CFM` PDU ([Vol 3] Part F, Section 3.4.7.3).
+    pub async fn handle_value_cfm(&mut self) -> Result<()> {
+        let cfm = self.pack(HandleValueCfm, |_| {});
+        self.send(cfm).await
+    }
+
     /// Sends an `ATT_MULTIPLE_HANDLE_VALUE_NTF` PDU
     /// ([Vol 3] Part F, Section 3.4.7.4).
     pub async fn multiple_handle_value_ntf(
@@ -440,6 +599,33 @@ impl Bearer {
     }
 }
 
+/// `ATT_HANDLE_VALUE_NTF` or `ATT_HANDLE_VALUE_IND` PDU that is encoded
+/// without a bearer. The value is written directly into the PDU, so it is not
+/// copied again until it is fragmented into ACL data packets.
+#[derive(Debug)]
+pub(crate) struct ValuePdu {
+    pdu: Payload,
+    hdl: Handle,
+}
+
+impl ValuePdu {
+    /// Creates a PDU for the value of attribute `hdl`, calling `f` to encode
+    /// at most `mtu - 3` bytes of the value.
+    pub fn new(mtu: u16, hdl: Handle, f: impl FnOnce(&mut Packer)) -> Self {
+        let mut pdu = Payload::detached(mtu);
+        f(pdu.append().u8(HandleValueNtf).u16(hdl));
+        Self { pdu, hdl }
+    }
+
+    /// Returns the attribute value.
+    #[inline]
+    #[must_use]
+    pub fn value(&self) -> &[u8] {
+        // SAFETY: The PDU starts with an opcode and a handle
+        unsafe { self.pdu.as_ref().get_unchecked(3..) }
+    }
+}
+
 /// Iterator over a multi-value response.
 #[derive(Clone, Debug)]
 pub struct MultiValueRsp<'a, T> {
@@ -448,11 +634,13 @@ pub struct MultiValueRsp<'a, T> {
     _format: PhantomData<T>,
 }
 
-impl<'a> MultiValueRsp<'a, Handle> {
+impl<'a, T: Debug> MultiValueRsp<'a, T> {
     #[inline]
     fn new(pdu: &'a Pdu) -> RspResult<Self> {
+        #[allow(clippy::cast_possible_truncation)]
+        let hdr = mem::size_of::<T>() as u8;
         pdu.unpack(pdu.opcode(), |p| {
-            p.u8().checked_sub(2).map_or_else(
+            p.u8().checked_sub(hdr).map_or_else(
                 || pdu.err(InvalidPdu),
                 |n| {
                     Ok(Self {
@@ -479,6 +667,18 @@ impl<'a> Iterator for MultiValueRsp<'a, Handle> {
     // TODO: size_hint
 }
 
+impl<'a> Iterator for MultiValueRsp<'a, HandleRange> {
+    type Item = (HandleRange, &'a [u8]);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Handle::new(self.p.u16())?;
+        let end = Handle::new(self.p.u16()).filter(|&end| start <= end)?;
+        let n = self.p.len().min(usize::from(self.n));
+        (self.p.skip(n)).map(|v| (HandleRange::new(start, end), v.into_inner()))
+    }
+}
+
 /// Consumes any remaining bytes in `p`.
 #[inline]
 fn take<'a>(p: &mut Unpacker<'a>) -> &'a [u8] {
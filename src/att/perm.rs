@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use std::ops::BitOr;
 
 use super::*;
@@ -54,6 +55,27 @@ impl Access {
         self.key_len(Perm::KEY_MAX)
     }
 
+    /// Requires encryption with a key size of at least `n` bytes. Requests
+    /// from connections encrypted with a shorter key are denied with
+    /// [`ErrorCode::EncryptionKeySizeTooShort`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not between 7 and 16.
+    #[inline]
+    pub const fn key_size(self, n: u8) -> Self {
+        assert!(7 <= n && n <= 16, "invalid encryption key size");
+        self.key_len(n * 8)
+    }
+
+    /// Returns the minimum encryption key size in bytes or 0 if encryption is
+    /// not required.
+    #[inline]
+    #[must_use]
+    pub const fn min_key_size(self) -> u8 {
+        self.0.key_len() / 8
+    }
+
     /// Sets encryption key length between 56 and 128 bits in 8 bit increments.
     /// A key length of 0 clears encryption requirement/status.
     #[inline]
@@ -74,6 +96,24 @@ impl Access {
         Self(self.0.access_type())
     }
 
+    /// Returns read-only access with the same security requirements.
+    #[inline]
+    pub const fn read(self) -> Self {
+        Self(self.0.difference(Perm::WRITE).union(Perm::READ))
+    }
+
+    /// Returns write-only access with the same security requirements.
+    #[inline]
+    pub const fn write(self) -> Self {
+        Self(self.0.difference(Perm::READ).union(Perm::WRITE))
+    }
+
+    /// Returns read/write access with the same security requirements.
+    #[inline]
+    pub const fn read_write(self) -> Self {
+        Self(self.0.union(Perm::READ_WRITE))
+    }
+
     /// Returns the permission array index.
     #[inline]
     #[must_use]
@@ -82,6 +122,27 @@ impl Access {
     }
 }
 
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self.0.access_type() {
+            Perm::READ => "R",
+            Perm::WRITE => "W",
+            Perm::READ_WRITE => "RW",
+            _ => "-",
+        })?;
+        if self.0.contains(Perm::AUTHN) {
+            f.write_str(" AUTHN")?;
+        }
+        if self.0.contains(Perm::AUTHZ) {
+            f.write_str(" AUTHZ")?;
+        }
+        match self.min_key_size() {
+            0 => Ok(()),
+            n => write!(f, " KEY>={n}"),
+        }
+    }
+}
+
 impl BitOr for Access {
     type Output = Perms;
 
@@ -99,6 +160,20 @@ pub struct Request {
     pub(crate) ac: Access,
 }
 
+impl Request {
+    /// Returns a request over an unauthenticated and unencrypted link for
+    /// benchmarks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the opcode is not a read/write request.
+    #[doc(hidden)]
+    #[inline]
+    pub fn test(op: Opcode) -> Self {
+        op.request(hci::ConnSec::empty())
+    }
+}
+
 /// A set of attribute permissions. Contains separate permissions for read-only,
 /// write-only, and read/write access.
 #[derive(Clone, Copy, Debug, Default)]
@@ -162,6 +237,15 @@ impl Perms {
     }
 }
 
+impl Display for Perms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut it = (self.0.iter()).filter(|p| p.is_set()).map(|&p| Access(p));
+        let Some(first) = it.next() else { return f.write_str("-") };
+        Display::fmt(&first, f)?;
+        it.try_for_each(|p| write!(f, " | {p}"))
+    }
+}
+
 impl From<Access> for Perms {
     #[inline]
     fn from(v: Access) -> Self {
@@ -314,6 +398,17 @@ mod tests {
             rw.authn().authz().key_len(56),
             Err(EncryptionKeySizeTooShort),
         );
+
+        test(wo.key_size(16), wo.key_size(16), Ok(()));
+        test(
+            wo.key_size(16),
+            wo.key_size(15),
+            Err(EncryptionKeySizeTooShort),
+        );
+        test(wo.key_size(7), wo, Err(InsufficientEncryption));
+        assert_eq!(ro.key_size(10).min_key_size(), 10);
+        assert_eq!(ro.encrypt().min_key_size(), 16);
+        assert_eq!(ro.min_key_size(), 0);
     }
 
     #[test]
@@ -341,4 +436,12 @@ mod tests {
         test(ps, rw.encrypt(), Err(InsufficientAuthorization));
         test(ps, rw.authz().encrypt(), Ok(()));
     }
+
+    #[test]
+    fn display() {
+        let ps = Access::READ | Access::WRITE.authn().authz().key_size(16);
+        assert_eq!(ps.to_string(), "R | W AUTHN AUTHZ KEY>=16");
+        assert_eq!(Perms::default().to_string(), "-");
+        assert_eq!(Access::READ_WRITE.key_size(7).to_string(), "RW KEY>=7");
+    }
 }
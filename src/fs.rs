@@ -1,12 +1,444 @@
 //! Bluetooth LE file system storage backend.
 
+use std::fmt::{Debug, Formatter};
 use std::io::{Cursor, Write};
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{fs, io};
 
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde::de::{Deserialize, DeserializeOwned, Deserializer};
+use serde::ser::{Serialize, Serializer};
 use tracing::{debug, error, warn};
+use zeroize::Zeroizing;
 
-use crate::{gatt, le::Addr, smp, PeerStore};
+use crate::le::{Addr, RawAddr};
+use crate::{gatt, is_valid_kind, smp, KindStore, PeerStore};
+
+/// Generic peer data store that saves the data for each peer in a separate
+/// file in a file system directory. Files are named after the peer address and
+/// contain a checksum to detect corruption. Corrupt files are treated as
+/// missing.
+pub struct FileStore<T>(Dir, PhantomData<fn() -> T>);
+
+impl<T> FileStore<T> {
+    /// Creates or opens a store in the specified directory. The directory is
+    /// created when the first value is saved.
+    #[inline(always)]
+    #[must_use]
+    pub fn open(dir: impl AsRef<Path>) -> Self {
+        Self(Dir(dir.as_ref().to_path_buf()), PhantomData)
+    }
+
+    /// Creates or opens a store named `name` in the current user's local data
+    /// directory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it cannot determine the user directory.
+    #[inline(always)]
+    #[must_use]
+    pub fn per_user(app: impl AsRef<Path>, name: impl AsRef<Path>) -> Self {
+        Self(Dir::per_user(app, name), PhantomData)
+    }
+}
+
+impl<T> Clone for FileStore<T> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData)
+    }
+}
+
+impl<T> Debug for FileStore<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FileStore").field(&self.0 .0).finish()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> PeerStore for FileStore<T> {
+    type Value = T;
+
+    #[inline(always)]
+    fn save(&self, peer: Addr, v: &Self::Value) -> bool {
+        self.0.save(peer, v)
+    }
+
+    #[inline(always)]
+    fn load(&self, peer: Addr) -> Option<Self::Value> {
+        self.0.load(peer)
+    }
+
+    #[inline(always)]
+    fn remove(&self, peer: Addr) {
+        self.0.remove(peer);
+    }
+
+    #[inline(always)]
+    fn clear(&self) {
+        self.0.clear();
+    }
+
+    #[inline(always)]
+    fn peers(&self) -> Vec<Addr> {
+        self.0.peers()
+    }
+}
+
+/// Peer data store that saves multiple kinds of data for each peer in a file
+/// system directory. Each peer has a subdirectory named after its address with
+/// one file per kind. Files contain a checksum to detect corruption, and
+/// corrupt files are treated as missing.
+#[derive(Clone)]
+pub struct FileKindStore(Dir);
+
+impl FileKindStore {
+    /// Creates or opens a store in the specified directory. The directory is
+    /// created when the first value is saved.
+    #[inline(always)]
+    #[must_use]
+    pub fn open(dir: impl AsRef<Path>) -> Self {
+        Self(Dir(dir.as_ref().to_path_buf()))
+    }
+
+    /// Creates or opens a store named `name` in the current user's local data
+    /// directory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it cannot determine the user directory.
+    #[inline(always)]
+    #[must_use]
+    pub fn per_user(app: impl AsRef<Path>, name: impl AsRef<Path>) -> Self {
+        Self(Dir::per_user(app, name))
+    }
+
+    /// Returns the file path for the specified peer and kind.
+    fn path(&self, peer: Addr, kind: &str) -> PathBuf {
+        assert!(is_valid_kind(kind), "invalid kind name: {kind:?}");
+        self.0.path(peer).join(kind)
+    }
+}
+
+impl Debug for FileKindStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FileKindStore").field(&self.0 .0).finish()
+    }
+}
+
+impl KindStore for FileKindStore {
+    fn save_kind(&self, peer: Addr, kind: &str, v: &[u8]) -> bool {
+        let path = self.path(peer, kind);
+        let sum = blake3::hash(v);
+        let mut b = format!("{}{}\n", Dir::CHECKSUM, sum.to_hex()).into_bytes();
+        b.extend_from_slice(v);
+        Dir(self.0.path(peer)).write(&path, &b)
+    }
+
+    fn load_kind(&self, peer: Addr, kind: &str) -> Option<Vec<u8>> {
+        let path = self.path(peer, kind);
+        let b = match fs::read(&path) {
+            Ok(b) => b,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound) => return None,
+            Err(e) => {
+                error!("Failed to read: {} ({e})", path.display());
+                return None;
+            }
+        };
+        let Some(v) = Dir::verify_bytes(&b) else {
+            error!("Invalid file checksum: {}", path.display());
+            return None;
+        };
+        Some(v.to_vec())
+    }
+
+    fn remove_kind(&self, peer: Addr, kind: &str) {
+        Dir::remove_file(&self.path(peer, kind));
+        // Fails if other kinds remain
+        let _ = fs::remove_dir(self.0.path(peer));
+    }
+
+    /// Removes all kinds of peer data by renaming the peer directory before
+    /// deleting it, so a crash never leaves some kinds behind.
+    fn remove(&self, peer: Addr) {
+        let dir = self.0.path(peer);
+        let del = dir.with_extension("del");
+        Dir(del.clone()).clear();
+        match fs::rename(&dir, &del) {
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound) => return,
+            Err(e) => {
+                error!("Failed to remove: {} ({e})", dir.display());
+                return;
+            }
+        }
+        if let Err(e) = self.0.sync_dir() {
+            error!("Failed to remove: {} ({e})", dir.display());
+        }
+        Dir(del).clear();
+    }
+
+    #[inline(always)]
+    fn clear(&self) {
+        self.0.clear();
+    }
+
+    #[inline(always)]
+    fn peers(&self) -> Vec<Addr> {
+        self.0.peers()
+    }
+}
+
+/// [`PeerStore`] adapter for one kind of data in a [`KindStore`], allowing
+/// independent layers to share one store. Values are serialized as JSON.
+///
+/// Removing a peer or clearing this store only removes data of this kind. Use
+/// [`KindStore::remove`] to remove all data for a peer.
+pub struct StoreFor<S, T> {
+    store: S,
+    kind: &'static str,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<S, T> StoreFor<S, T> {
+    /// Creates an adapter for the data `kind` in `store`. Use a clone of a
+    /// shared store, such as [`crate::MemoryKindStore`] or [`FileKindStore`],
+    /// to create adapters for multiple kinds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `kind` is not a valid kind name.
+    #[must_use]
+    pub fn new(store: S, kind: &'static str) -> Self {
+        assert!(is_valid_kind(kind), "invalid kind name: {kind:?}");
+        Self {
+            store,
+            kind,
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<S: Clone, T> Clone for StoreFor<S, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(self.store.clone(), self.kind)
+    }
+}
+
+impl<S: Debug, T> Debug for StoreFor<S, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        (f.debug_struct("StoreFor"))
+            .field("store", &self.store)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl<S, T> PeerStore for StoreFor<S, T>
+where
+    S: KindStore,
+    T: Serialize + DeserializeOwned,
+{
+    type Value = T;
+
+    fn save(&self, peer: Addr, v: &Self::Value) -> bool {
+        let v = serde_json::to_vec(v).expect("failed to serialize peer data");
+        self.store.save_kind(peer, self.kind, &v)
+    }
+
+    fn load(&self, peer: Addr) -> Option<Self::Value> {
+        let v = self.store.load_kind(peer, self.kind)?;
+        serde_json::from_slice(&v)
+            .map_err(|e| error!("Invalid {} data for {peer} ({e})", self.kind))
+            .ok()
+    }
+
+    #[inline(always)]
+    fn remove(&self, peer: Addr) {
+        self.store.remove_kind(peer, self.kind);
+    }
+
+    fn clear(&self) {
+        for peer in self.store.peers() {
+            self.store.remove_kind(peer, self.kind);
+        }
+    }
+
+    fn peers(&self) -> Vec<Addr> {
+        let mut v = self.store.peers();
+        v.retain(|&peer| self.store.load_kind(peer, self.kind).is_some());
+        v
+    }
+}
+
+/// Peer data store that encrypts values before saving them in another store,
+/// such as a [`FileStore<Sealed>`]. This protects keys and other sensitive data
+/// at rest.
+///
+/// Values are encrypted with AES-256-GCM using a random 96-bit nonce for each
+/// entry. The peer address type and address are authenticated as associated
+/// data, so an entry copied from one peer to another is rejected. The cipher
+/// key is derived from the caller's key with BLAKE3 and is zeroized on drop,
+/// as are plaintext buffers.
+///
+/// Values that fail authentication or decoding are treated as missing. The
+/// number of such failures is available via [`Self::failures`].
+pub struct EncryptedStore<S, T> {
+    inner: S,
+    aead: Aes256Gcm,
+    failures: AtomicU64,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<S, T> EncryptedStore<S, T> {
+    const NONCE_LEN: usize = 12;
+    const TAG_LEN: usize = 16;
+
+    /// Creates a store that encrypts values saved in `inner` using a 128 or
+    /// 256-bit `key` (e.g. from a TPM or OS keystore).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not 16 or 32 bytes long.
+    #[must_use]
+    pub fn new(inner: S, key: &[u8]) -> Self {
+        assert!(matches!(key.len(), 16 | 32), "invalid key length");
+        let k = blake3::derive_key("burble EncryptedStore AES-256-GCM key", key);
+        let k = Zeroizing::new(k);
+        Self {
+            inner,
+            aead: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&*k)),
+            failures: AtomicU64::new(0),
+            _t: PhantomData,
+        }
+    }
+
+    /// Returns the number of stored values that could not be decrypted.
+    #[inline]
+    #[must_use]
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Encrypts plaintext `v` for the specified peer.
+    fn seal(&self, peer: Addr, v: &[u8]) -> Sealed {
+        let n = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = Self::aad(peer);
+        let v = Payload { msg: v, aad: &aad };
+        let ct = self.aead.encrypt(&n, v).expect("peer data too long");
+        let mut b = Vec::with_capacity(Self::NONCE_LEN + ct.len());
+        b.extend_from_slice(&n);
+        b.extend_from_slice(&ct);
+        Sealed(b)
+    }
+
+    /// Authenticates and decrypts a value sealed for the specified peer.
+    fn open(&self, peer: Addr, s: &Sealed) -> Option<Zeroizing<Vec<u8>>> {
+        if s.0.len() < Self::NONCE_LEN + Self::TAG_LEN {
+            return None;
+        }
+        let (n, ct) = s.0.split_at(Self::NONCE_LEN);
+        let aad = Self::aad(peer);
+        let ct = Payload { msg: ct, aad: &aad };
+        (self.aead.decrypt(Nonce::from_slice(n), ct).ok()).map(Zeroizing::new)
+    }
+
+    /// Returns the associated data for the specified peer, consisting of the
+    /// address type and address.
+    fn aad(peer: Addr) -> [u8; 7] {
+        let mut b = [0; 7];
+        b[0] = u8::from(peer.is_random());
+        b[1..].copy_from_slice(&peer.raw().as_le_bytes());
+        b
+    }
+
+    /// Records a decryption failure.
+    fn fail(&self, peer: Addr, why: &str) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        error!("Failed to decrypt data for {peer}: {why}");
+    }
+}
+
+impl<S: Debug, T> Debug for EncryptedStore<S, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        (f.debug_struct("EncryptedStore"))
+            .field("inner", &self.inner)
+            .field("failures", &self.failures())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, T> PeerStore for EncryptedStore<S, T>
+where
+    S: PeerStore<Value = Sealed>,
+    T: Serialize + DeserializeOwned,
+{
+    type Value = T;
+
+    fn save(&self, peer: Addr, v: &Self::Value) -> bool {
+        let v = Zeroizing::new(serde_json::to_vec(v).expect("failed to serialize peer data"));
+        self.inner.save(peer, &self.seal(peer, &v))
+    }
+
+    fn load(&self, peer: Addr) -> Option<Self::Value> {
+        let s = self.inner.load(peer)?;
+        let Some(v) = self.open(peer, &s) else {
+            self.fail(peer, "authentication failed");
+            return None;
+        };
+        serde_json::from_slice(&v)
+            .map_err(|e| self.fail(peer, &e.to_string()))
+            .ok()
+    }
+
+    #[inline(always)]
+    fn remove(&self, peer: Addr) {
+        self.inner.remove(peer);
+    }
+
+    #[inline(always)]
+    fn clear(&self) {
+        self.inner.clear();
+    }
+
+    #[inline(always)]
+    fn peers(&self) -> Vec<Addr> {
+        self.inner.peers()
+    }
+}
+
+/// Encrypted value produced by [`EncryptedStore`], consisting of the nonce,
+/// ciphertext, and authentication tag. It is serialized as a hex string.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sealed(Vec<u8>);
+
+impl Serialize for Sealed {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use std::fmt::Write as _;
+        let mut h = String::with_capacity(self.0.len() * 2);
+        for b in &self.0 {
+            let _ = write!(h, "{b:02x}");
+        }
+        s.serialize_str(&h)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sealed {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let h = String::deserialize(d)?;
+        let b = (h.len() % 2 == 0).then(|| {
+            (h.as_bytes().chunks(2))
+                .map(|c| u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok())
+                .collect::<Option<Vec<_>>>()
+        });
+        b.flatten()
+            .map(Self)
+            .ok_or_else(|| serde::de::Error::custom("invalid hex string"))
+    }
+}
 
 /// Security database stored in a file system directory.
 #[derive(Clone, Debug)]
@@ -58,6 +490,11 @@ impl PeerStore for KeyStore {
     fn clear(&self) {
         self.0.clear();
     }
+
+    #[inline(always)]
+    fn peers(&self) -> Vec<Addr> {
+        self.0.peers()
+    }
 }
 
 /// GATT server database stored in a file system directory.
@@ -110,6 +547,11 @@ impl PeerStore for GattServerStore {
     fn clear(&self) {
         self.0.clear();
     }
+
+    #[inline(always)]
+    fn peers(&self) -> Vec<Addr> {
+        self.0.peers()
+    }
 }
 
 /// Database in a file system directory.
@@ -119,6 +561,7 @@ struct Dir(PathBuf);
 
 impl Dir {
     const FILE_NAME_FMT: &'static str = "P-001122334455";
+    const CHECKSUM: &'static str = "blake3:";
 
     /// Creates or opens a database store in the specified root directory.
     #[inline(always)]
@@ -142,20 +585,25 @@ impl Dir {
         Self(dir)
     }
 
-    /// Saves peer data to the file system.
-    fn save(&self, peer: Addr, v: &impl serde::ser::Serialize) -> bool {
+    /// Saves peer data to the file system. The data is written to a temporary
+    /// file, which then replaces any existing file, so a crash never leaves a
+    /// partially written file in place of the old one.
+    fn save(&self, peer: Addr, v: &impl Serialize) -> bool {
         let s = serde_json::to_string_pretty(v).expect("failed to serialize peer data");
+        let sum = blake3::hash(s.as_bytes());
+        let s = format!("{}{}\n{s}", Self::CHECKSUM, sum.to_hex());
+        self.write(&self.path(peer), s.as_bytes())
+    }
+
+    /// Creates the directory and atomically writes `b` to `path`.
+    fn write(&self, path: &Path, b: &[u8]) -> bool {
         if let Err(e) = fs::create_dir_all(&self.0) {
             warn!(
                 "Failed to create database directory: {} ({e})",
                 self.0.display()
             );
         }
-        let path = self.path(peer);
-        // TODO: Make atomic?
-        match fs::File::create(&path)
-            .and_then(|mut f| f.write_all(s.as_bytes()).and_then(|_| f.sync_data()))
-        {
+        match self.write_atomic(path, b) {
             Ok(_) => {
                 debug!("Wrote: {}", path.display());
                 true
@@ -167,8 +615,32 @@ impl Dir {
         }
     }
 
+    /// Writes `b` to a temporary file and renames it to `path`.
+    fn write_atomic(&self, path: &Path, b: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(b)?;
+        f.sync_all()?;
+        drop(f);
+        fs::rename(&tmp, path)?;
+        self.sync_dir()
+    }
+
+    /// Flushes directory metadata to persist the rename.
+    #[cfg(unix)]
+    fn sync_dir(&self) -> io::Result<()> {
+        fs::File::open(&self.0)?.sync_all()
+    }
+
+    /// Flushes directory metadata to persist the rename.
+    #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    #[cfg(not(unix))]
+    fn sync_dir(&self) -> io::Result<()> {
+        Ok(()) // Directories can't be opened as files
+    }
+
     /// Loads peer data from the file system.
-    fn load<T: serde::de::DeserializeOwned>(&self, peer: Addr) -> Option<T> {
+    fn load<T: DeserializeOwned>(&self, peer: Addr) -> Option<T> {
         let path = self.path(peer);
         let s = match fs::read_to_string(&path) {
             Ok(s) => s,
@@ -178,7 +650,11 @@ impl Dir {
                 return None;
             }
         };
-        serde_json::from_str(&s)
+        let Some(s) = Self::verify(&s) else {
+            error!("Invalid file checksum: {}", path.display());
+            return None;
+        };
+        serde_json::from_str(s)
             .map_err(|e| {
                 error!("Invalid file contents: {} ({e})", path.display());
                 Err::<T, ()>(())
@@ -186,10 +662,32 @@ impl Dir {
             .ok()
     }
 
+    /// Verifies the checksum and returns the serialized data. Files without a
+    /// checksum that were written by earlier versions are returned as is.
+    fn verify(s: &str) -> Option<&str> {
+        let Some(s) = s.strip_prefix(Self::CHECKSUM) else {
+            return Some(s);
+        };
+        let (sum, v) = s.split_once('\n')?;
+        (blake3::hash(v.as_bytes()).to_hex().as_str() == sum).then_some(v)
+    }
+
+    /// Verifies the checksum of binary data.
+    fn verify_bytes(b: &[u8]) -> Option<&[u8]> {
+        let b = b.strip_prefix(Self::CHECKSUM.as_bytes())?;
+        let i = b.iter().position(|&c| c == b'\n')?;
+        let (sum, v) = (&b[..i], &b[i + 1..]);
+        (blake3::hash(v).to_hex().as_bytes() == sum).then_some(v)
+    }
+
     /// Removes peer data from the file system.
     fn remove(&self, peer: Addr) {
-        let path = self.path(peer);
-        match fs::remove_file(&path) {
+        Self::remove_file(&self.path(peer));
+    }
+
+    /// Removes the specified file.
+    fn remove_file(path: &Path) {
+        match fs::remove_file(path) {
             Ok(_) => {}
             Err(e) if matches!(e.kind(), io::ErrorKind::NotFound) => {}
             Err(e) => error!("Failed to remove: {} ({e})", path.display()),
@@ -205,6 +703,40 @@ impl Dir {
         }
     }
 
+    /// Returns the addresses of all peers with stored data.
+    fn peers(&self) -> Vec<Addr> {
+        let dir = match fs::read_dir(&self.0) {
+            Ok(dir) => dir,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound) => return Vec::new(),
+            Err(e) => {
+                error!("Failed to read: {} ({e})", self.0.display());
+                return Vec::new();
+            }
+        };
+        let mut v: Vec<Addr> =
+            (dir.filter_map(|e| Self::peer(e.ok()?.file_name().to_str()?))).collect();
+        v.sort_unstable();
+        v
+    }
+
+    /// Returns the peer address for the specified file name.
+    fn peer(name: &str) -> Option<Addr> {
+        let (typ, hex) = name.split_once('-')?;
+        if hex.len() != 12 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut raw = [0; 6];
+        for (i, b) in raw.iter_mut().rev().enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        let raw = RawAddr::from_le_bytes(raw);
+        match typ {
+            "P" => Some(Addr::Public(raw)),
+            "R" => Some(Addr::Random(raw)),
+            _ => None,
+        }
+    }
+
     /// Returns the key file path for the specified peer address.
     fn path(&self, peer: Addr) -> PathBuf {
         let (raw, typ) = match peer {
@@ -232,15 +764,203 @@ mod tests {
 
     use super::*;
 
+    const PEER: Addr = Addr::Public(RawAddr::from_le_bytes([0x55, 0x44, 0x33, 0x22, 0x11, 0x00]));
+
     #[test]
     fn save_load() {
-        const PEER: Addr =
-            Addr::Public(RawAddr::from_le_bytes([0x55, 0x44, 0x33, 0x22, 0x11, 0x00]));
-        let tmp = (Builder::new().prefix(concat!("burble-test-")).tempdir()).unwrap();
+        let tmp = tempdir();
         let db = KeyStore(Dir(tmp.path().to_path_buf()));
         let keys = smp::Keys::test();
         assert!(db.save(PEER, &keys));
         assert!(tmp.path().join(Dir::FILE_NAME_FMT).exists());
         assert_eq!(db.load(PEER).unwrap(), keys);
     }
+
+    #[test]
+    fn file_store() {
+        let tmp = tempdir();
+        let db = FileStore::<Vec<u32>>::open(tmp.path().join("test"));
+        assert_eq!(db.load(PEER), None);
+        assert!(db.save(PEER, &vec![1, 2, 3]));
+        assert_eq!(db.load(PEER), Some(vec![1, 2, 3]));
+        let other = Addr::Random(RawAddr::from_le_bytes([0x55, 0x44, 0x33, 0x22, 0x11, 0xC0]));
+        assert_eq!(db.load(other), None);
+        assert_eq!(db.peers(), [PEER]);
+        assert!(db.save(other, &vec![4]));
+        fs::write(tmp.path().join("test").join("P-0011223344"), "").unwrap();
+        fs::write(tmp.path().join("test").join("X-001122334455"), "").unwrap();
+        assert_eq!(db.peers(), [PEER, other]);
+        db.remove(PEER);
+        assert_eq!(db.load(PEER), None);
+        assert_eq!(db.peers(), [other]);
+        db.clear();
+        assert!(db.peers().is_empty());
+    }
+
+    #[test]
+    fn file_kind_store() {
+        let tmp = tempdir();
+        let db = FileKindStore::open(tmp.path());
+        assert!(db.save_kind(PEER, "keys", &[1, 2]));
+        assert!(db.save_kind(PEER, "ccc", b"3"));
+        let dir = tmp.path().join(Dir::FILE_NAME_FMT);
+        assert!(dir.join("keys").exists());
+        assert_eq!(db.load_kind(PEER, "keys"), Some(vec![1, 2]));
+        assert_eq!(db.load_kind(PEER, "ccc"), Some(b"3".to_vec()));
+        assert_eq!(db.load_kind(PEER, "cache"), None);
+        assert_eq!(db.peers(), [PEER]);
+
+        // Corrupt file
+        let mut v = fs::read(dir.join("ccc")).unwrap();
+        *v.last_mut().unwrap() = b'4';
+        fs::write(dir.join("ccc"), v).unwrap();
+        assert_eq!(db.load_kind(PEER, "ccc"), None);
+
+        db.remove_kind(PEER, "ccc");
+        assert!(dir.exists());
+        db.remove_kind(PEER, "keys");
+        assert!(!dir.exists());
+        assert!(db.peers().is_empty());
+
+        // Crash after renaming the peer directory
+        assert!(db.save_kind(PEER, "keys", &[1]));
+        assert!(db.save_kind(PEER, "ccc", &[2]));
+        fs::create_dir(dir.with_extension("del")).unwrap();
+        assert_eq!(db.peers(), [PEER]);
+        db.remove(PEER);
+        assert_eq!(db.load_kind(PEER, "keys"), None);
+        assert_eq!(db.load_kind(PEER, "ccc"), None);
+        assert!(!dir.with_extension("del").exists());
+        assert!(db.peers().is_empty());
+    }
+
+    #[test]
+    fn store_for() {
+        let tmp = tempdir();
+        let db = FileKindStore::open(tmp.path());
+        let keys = StoreFor::<_, smp::Keys>::new(db.clone(), "keys");
+        let ctr = StoreFor::<_, u32>::new(db.clone(), "sign-counter");
+        let other = Addr::Random(RawAddr::from_le_bytes([0x55, 0x44, 0x33, 0x22, 0x11, 0xC0]));
+        assert!(keys.save(PEER, &smp::Keys::test()));
+        assert!(ctr.save(PEER, &1));
+        assert!(ctr.save(other, &2));
+        assert_eq!(keys.load(PEER).unwrap(), smp::Keys::test());
+        assert_eq!(ctr.load(PEER), Some(1));
+        assert_eq!(keys.peers(), [PEER]);
+        assert_eq!(ctr.peers(), [PEER, other]);
+        ctr.clear();
+        assert_eq!(ctr.load(PEER), None);
+        assert_eq!(keys.peers(), [PEER]);
+        assert_eq!(db.peers(), [PEER]);
+        assert!(ctr.save(PEER, &3));
+        db.remove(PEER);
+        assert_eq!(keys.load(PEER), None);
+        assert_eq!(ctr.load(PEER), None);
+
+        let mem = crate::MemoryKindStore::new();
+        let ctr = StoreFor::<_, u32>::new(mem.clone(), "sign-counter");
+        assert!(ctr.save(PEER, &4));
+        assert_eq!(mem.load_kind(PEER, "sign-counter"), Some(b"4".to_vec()));
+        assert!(mem.save_kind(PEER, "sign-counter", b"x"));
+        assert_eq!(ctr.load(PEER), None);
+    }
+
+    /// Crash after writing the temporary file, but before renaming it.
+    #[test]
+    fn crash_before_rename() {
+        let tmp = tempdir();
+        let db = FileStore::<u32>::open(tmp.path());
+        assert!(db.save(PEER, &1));
+        let path = tmp.path().join(Dir::FILE_NAME_FMT);
+        let partial = b"blake3:0123";
+        fs::write(path.with_extension("tmp"), partial).unwrap();
+        assert_eq!(db.load(PEER), Some(1));
+        assert!(db.save(PEER, &2));
+        assert_eq!(db.load(PEER), Some(2));
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn corrupt() {
+        let tmp = tempdir();
+        let db = FileStore::<u32>::open(tmp.path());
+        let path = tmp.path().join(Dir::FILE_NAME_FMT);
+        assert!(db.save(PEER, &12345));
+        let mut v = fs::read(&path).unwrap();
+        *v.last_mut().unwrap() = b'6';
+        fs::write(&path, &v).unwrap();
+        assert_eq!(db.load(PEER), None);
+
+        // Truncated file
+        v.truncate(v.len() - 2);
+        fs::write(&path, &v).unwrap();
+        assert_eq!(db.load(PEER), None);
+
+        // File without a checksum from an earlier version
+        fs::write(&path, "123").unwrap();
+        assert_eq!(db.load(PEER), Some(123));
+        fs::write(&path, "12x").unwrap();
+        assert_eq!(db.load(PEER), None);
+    }
+
+    #[test]
+    fn encrypted_store() {
+        let other = Addr::Random(RawAddr::from_le_bytes([0x55, 0x44, 0x33, 0x22, 0x11, 0xC0]));
+        let mem = crate::MemoryStore::new();
+        let db = EncryptedStore::new(mem.clone(), &[1; 16]);
+        let keys = smp::Keys::test();
+        assert!(db.save(PEER, &keys));
+        assert_eq!(db.load(PEER).unwrap(), keys);
+        assert_eq!(db.load(other), None);
+
+        // Random nonces
+        let a = mem.load(PEER).unwrap();
+        assert!(db.save(PEER, &keys));
+        assert_ne!(mem.load(PEER).unwrap(), a);
+        assert_eq!(db.failures(), 0);
+
+        // Entry moved to another peer
+        assert!(mem.save(other, &a));
+        assert_eq!(db.load(other), None);
+        assert_eq!(db.failures(), 1);
+
+        // Modified ciphertext
+        let mut b = a.clone();
+        b.0[EncryptedStore::<(), ()>::NONCE_LEN] ^= 1;
+        assert!(mem.save(PEER, &b));
+        assert_eq!(db.load(PEER), None);
+        assert_eq!(db.failures(), 2);
+
+        // Truncated value
+        assert!(mem.save(PEER, &Sealed(a.0[..40].to_vec())));
+        assert_eq!(db.load(PEER), None);
+        assert_eq!(db.failures(), 3);
+
+        // Wrong key
+        assert!(mem.save(PEER, &a));
+        assert_eq!(db.load(PEER).unwrap(), keys);
+        let db = EncryptedStore::<_, smp::Keys>::new(mem, &[1; 32]);
+        assert_eq!(db.load(PEER), None);
+        assert_eq!(db.failures(), 1);
+    }
+
+    #[test]
+    fn encrypted_file_store() {
+        let tmp = tempdir();
+        let db = EncryptedStore::new(FileStore::<Sealed>::open(tmp.path()), &[2; 32]);
+        assert!(db.save(PEER, &"secret".to_owned()));
+        let s = fs::read_to_string(tmp.path().join(Dir::FILE_NAME_FMT)).unwrap();
+        assert!(!s.contains("secret"));
+        assert_eq!(db.load(PEER).as_deref(), Some("secret"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid key length")]
+    fn encrypted_store_key_len() {
+        let _ = EncryptedStore::<_, u32>::new(crate::MemoryStore::<Sealed>::new(), &[0; 24]);
+    }
+
+    fn tempdir() -> tempfile::TempDir {
+        (Builder::new().prefix(concat!("burble-test-")).tempdir()).unwrap()
+    }
 }
@@ -0,0 +1,408 @@
+use std::collections::BTreeSet;
+use std::mem;
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::le::{Addr, AddrKind};
+use crate::{hci, smp, AsyncMutex, PeerStore, SyncMutex};
+
+/// Policy that keeps the controller's Filter Accept List in sync with the
+/// bonded peers in a key store, so that only bonded centrals can connect
+/// while the device is not in pairing mode ([Vol 3] Part C, Section 9.3.9).
+///
+/// The list is loaded when the policy is created and updated whenever a bond
+/// is saved or removed through [`Self::store`]. Only peers with an identity
+/// address (public or static random) can be added. If the bonded peers don't
+/// fit in the list, or while pairing mode is enabled, advertising falls back
+/// to accepting connections from all devices.
+///
+/// The controller rejects list changes while advertising uses the list, so
+/// changes made during advertising are deferred until the next call to
+/// [`Self::adv_filter_policy`] or [`Self::sync`].
+#[derive(Debug)]
+pub struct AcceptListPolicy {
+    host: hci::Host,
+    store: Arc<BondStore>,
+    state: SyncMutex<State>,
+    sync: AsyncMutex<()>,
+}
+
+impl AcceptListPolicy {
+    /// Creates a policy for bonds in `store` and loads the Filter Accept
+    /// List.
+    pub async fn new(host: &hci::Host, store: Arc<smp::KeyStore>) -> hci::Result<Self> {
+        let cap = host.le_read_filter_accept_list_size().await?;
+        host.le_clear_filter_accept_list().await?;
+        let this = Self {
+            host: host.clone(),
+            store: Arc::new(BondStore {
+                inner: store,
+                changed: Notify::new(),
+            }),
+            state: SyncMutex::new(State::new(cap)),
+            sync: AsyncMutex::new(()),
+        };
+        this.sync().await?;
+        Ok(this)
+    }
+
+    /// Returns the key store that must be used for pairing and bond
+    /// management to keep the list up to date.
+    #[inline]
+    #[must_use]
+    pub fn store(&self) -> Arc<smp::KeyStore> {
+        Arc::clone(&self.store) as Arc<smp::KeyStore>
+    }
+
+    /// Enables or disables pairing mode, which lifts the filter to allow new
+    /// centrals to connect. The caller must reconfigure advertising with the
+    /// new [`Self::adv_filter_policy`].
+    #[inline]
+    pub fn set_pairing_mode(&self, enable: bool) {
+        self.state.lock().pairing = enable;
+    }
+
+    /// Returns whether pairing mode is enabled.
+    #[inline]
+    #[must_use]
+    pub fn is_pairing_mode(&self) -> bool {
+        self.state.lock().pairing
+    }
+
+    /// Updates the list if needed and returns the filter policy to use for
+    /// connectable advertising. This must be called while advertising is
+    /// disabled.
+    pub async fn adv_filter_policy(&self) -> hci::Result<hci::AdvFilterPolicy> {
+        if self.state.lock().dirty {
+            self.sync().await?;
+        }
+        Ok(self.state.lock().filter_policy())
+    }
+
+    /// Updates the list whenever a bond is saved or removed until an error is
+    /// encountered.
+    pub async fn event_loop(&self) -> hci::Result<()> {
+        loop {
+            self.store.changed.notified().await;
+            self.state.lock().dirty = true;
+            match self.sync().await {
+                Err(e) if e.status() == Some(hci::Status::CommandDisallowed) => {
+                    debug!("Filter Accept List update deferred while in use");
+                }
+                r => r?,
+            }
+        }
+    }
+
+    /// Updates the Filter Accept List to contain all bonded peers.
+    pub async fn sync(&self) -> hci::Result<()> {
+        let _guard = self.sync.lock().await;
+        let want = (self.store.inner.peers().into_iter())
+            .filter(|&peer| is_identity(peer))
+            .filter(|&peer| self.store.inner.load(peer).map_or(false, |k| k.is_bond()))
+            .collect();
+        let (rm, add) = self.state.lock().diff(want);
+        for peer in rm {
+            debug!("Removing {peer} from the Filter Accept List");
+            (self.host.le_remove_device_from_filter_accept_list(peer)).await?;
+            self.state.lock().list.remove(&peer);
+        }
+        for peer in add {
+            debug!("Adding {peer} to the Filter Accept List");
+            match self.host.le_add_device_to_filter_accept_list(peer).await {
+                Ok(()) => {}
+                Err(e) if e.status() == Some(hci::Status::MemoryCapacityExceeded) => {
+                    self.state.lock().overflow();
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+            self.state.lock().list.insert(peer);
+        }
+        self.state.lock().dirty = false;
+        Ok(())
+    }
+}
+
+/// Returns whether `peer` is an identity address that can be added to the
+/// Filter Accept List without address resolution.
+#[inline]
+fn is_identity(peer: Addr) -> bool {
+    matches!(peer.kind(), AddrKind::Public | AddrKind::Static)
+}
+
+/// Filter Accept List state.
+#[derive(Debug)]
+struct State {
+    list: BTreeSet<Addr>,
+    cap: usize,
+    full: bool,
+    dirty: bool,
+    pairing: bool,
+}
+
+impl State {
+    /// Creates the state of an empty list with capacity `cap`.
+    #[inline]
+    const fn new(cap: usize) -> Self {
+        Self {
+            list: BTreeSet::new(),
+            cap,
+            full: false,
+            dirty: true,
+            pairing: false,
+        }
+    }
+
+    /// Returns the peers that must be removed from and added to the list to
+    /// make it contain `want`. If `want` exceeds the list capacity, the
+    /// existing entries are kept and filtering is disabled.
+    fn diff(&mut self, want: BTreeSet<Addr>) -> (Vec<Addr>, Vec<Addr>) {
+        if want.len() > self.cap {
+            self.overflow();
+            return (Vec::new(), Vec::new());
+        }
+        self.full = false;
+        let rm = self.list.difference(&want).copied().collect();
+        let add = want.difference(&self.list).copied().collect();
+        (rm, add)
+    }
+
+    /// Disables filtering because not all bonded peers fit in the list.
+    fn overflow(&mut self) {
+        if !self.full {
+            warn!(
+                "Filter Accept List capacity ({}) exceeded, advertising without a filter",
+                self.cap
+            );
+        }
+        self.full = true;
+        self.dirty = false;
+    }
+
+    /// Returns the advertising filter policy.
+    #[inline]
+    fn filter_policy(&self) -> hci::AdvFilterPolicy {
+        if self.pairing || self.full || self.list.is_empty() {
+            hci::AdvFilterPolicy::None
+        } else {
+            hci::AdvFilterPolicy::FilterConnect
+        }
+    }
+}
+
+/// Mirror of the controller's Filter Accept List for applications that manage
+/// the list directly ([Vol 6] Part B, Section 4.3.1). Additions that would
+/// exceed the capacity reported by the controller are refused without sending
+/// a command, and [`Self::restore`] re-applies all entries after a controller
+/// reset. This must not be combined with [`AcceptListPolicy`] for the same
+/// host.
+#[derive(Debug)]
+pub struct FilterAcceptList {
+    host: hci::Host,
+    entries: AsyncMutex<Entries>,
+}
+
+impl FilterAcceptList {
+    /// Reads the list capacity and clears the list.
+    pub async fn new(host: &hci::Host) -> hci::Result<Self> {
+        let cap = host.le_read_filter_accept_list_size().await?;
+        host.le_clear_filter_accept_list().await?;
+        Ok(Self {
+            host: host.clone(),
+            entries: AsyncMutex::new(Entries {
+                set: BTreeSet::new(),
+                cap,
+            }),
+        })
+    }
+
+    /// Returns the maximum number of entries.
+    pub async fn capacity(&self) -> usize {
+        self.entries.lock().await.cap
+    }
+
+    /// Returns the current entries.
+    pub async fn entries(&self) -> Vec<hci::AcceptListEntry> {
+        self.entries.lock().await.set.iter().copied().collect()
+    }
+
+    /// Adds an entry to the list. Returns
+    /// [`hci::Status::MemoryCapacityExceeded`] if the list is full.
+    pub async fn add(&self, e: impl Into<hci::AcceptListEntry> + Send) -> hci::Result<()> {
+        let e = e.into();
+        let mut entries = self.entries.lock().await;
+        if entries.want_add(e)? {
+            self.host.le_add_device_to_filter_accept_list(e).await?;
+            entries.set.insert(e);
+        }
+        Ok(())
+    }
+
+    /// Removes an entry from the list.
+    pub async fn remove(&self, e: impl Into<hci::AcceptListEntry> + Send) -> hci::Result<()> {
+        let e = e.into();
+        let mut entries = self.entries.lock().await;
+        if entries.set.contains(&e) {
+            (self.host.le_remove_device_from_filter_accept_list(e)).await?;
+            entries.set.remove(&e);
+        }
+        Ok(())
+    }
+
+    /// Removes all entries from the list.
+    pub async fn clear(&self) -> hci::Result<()> {
+        let mut entries = self.entries.lock().await;
+        self.host.le_clear_filter_accept_list().await?;
+        entries.set.clear();
+        Ok(())
+    }
+
+    /// Re-applies all entries to the controller. This must be called after
+    /// [`hci::Host::reset`], which clears the controller's list. Entries that
+    /// no longer fit are dropped if the capacity changed.
+    pub async fn restore(&self) -> hci::Result<()> {
+        let mut entries = self.entries.lock().await;
+        entries.cap = self.host.le_read_filter_accept_list_size().await?;
+        self.host.le_clear_filter_accept_list().await?;
+        let all = mem::take(&mut entries.set);
+        for e in all {
+            if entries.want_add(e).is_err() {
+                warn!("Dropping {e:?} from the Filter Accept List (capacity exceeded)");
+                continue;
+            }
+            self.host.le_add_device_to_filter_accept_list(e).await?;
+            entries.set.insert(e);
+        }
+        Ok(())
+    }
+}
+
+/// Entries of a [`FilterAcceptList`].
+#[derive(Debug)]
+struct Entries {
+    set: BTreeSet<hci::AcceptListEntry>,
+    cap: usize,
+}
+
+impl Entries {
+    /// Returns whether `e` needs to be added or an error if the list is full.
+    fn want_add(&self, e: hci::AcceptListEntry) -> hci::Result<bool> {
+        if self.set.contains(&e) {
+            return Ok(false);
+        }
+        if self.set.len() >= self.cap {
+            return Err(hci::Status::MemoryCapacityExceeded.into());
+        }
+        Ok(true)
+    }
+}
+
+/// Key store wrapper that signals bond changes.
+#[derive(Debug)]
+struct BondStore {
+    inner: Arc<smp::KeyStore>,
+    changed: Notify,
+}
+
+impl PeerStore for BondStore {
+    type Value = smp::Keys;
+
+    fn save(&self, peer: Addr, v: &Self::Value) -> bool {
+        let ok = self.inner.save(peer, v);
+        self.changed.notify_one();
+        ok
+    }
+
+    #[inline]
+    fn load(&self, peer: Addr) -> Option<Self::Value> {
+        self.inner.load(peer)
+    }
+
+    fn remove(&self, peer: Addr) {
+        self.inner.remove(peer);
+        self.changed.notify_one();
+    }
+
+    fn clear(&self) {
+        self.inner.clear();
+        self.changed.notify_one();
+    }
+
+    #[inline]
+    fn peers(&self) -> Vec<Addr> {
+        self.inner.peers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+
+    use crate::le::RawAddr;
+
+    use super::*;
+
+    fn addr(i: u8) -> Addr {
+        Addr::Public(RawAddr::from_le_bytes([i, 0, 0, 0, 0, 0]))
+    }
+
+    fn peers(ids: impl IntoIterator<Item = u8>) -> BTreeSet<Addr> {
+        ids.into_iter().map(addr).collect()
+    }
+
+    #[test]
+    fn diff() {
+        let mut s = State::new(2);
+        assert_eq!(s.filter_policy(), hci::AdvFilterPolicy::None);
+        let (rm, add) = s.diff(peers([1, 2]));
+        assert!(rm.is_empty());
+        s.list.extend(add);
+        assert_eq!(s.filter_policy(), hci::AdvFilterPolicy::FilterConnect);
+
+        // Pairing mode lifts the filter
+        s.pairing = true;
+        assert_eq!(s.filter_policy(), hci::AdvFilterPolicy::None);
+        s.pairing = false;
+
+        // Overflow keeps the list, but disables filtering
+        let (rm, add) = s.diff(peers([1, 2, 3]));
+        assert!(rm.is_empty() && add.is_empty());
+        assert_eq!(s.filter_policy(), hci::AdvFilterPolicy::None);
+
+        assert_eq!(s.diff(peers([2, 3])), (vec![addr(1)], vec![addr(3)]));
+        s.list = peers([2, 3]);
+        assert_eq!(s.filter_policy(), hci::AdvFilterPolicy::FilterConnect);
+    }
+
+    #[test]
+    fn entries() {
+        let mut e = Entries {
+            set: BTreeSet::new(),
+            cap: 2,
+        };
+        let anon = hci::AcceptListEntry::Anonymous;
+        assert!(e.want_add(anon).unwrap());
+        e.set.insert(anon);
+        assert!(!e.want_add(anon).unwrap());
+        e.set.insert(addr(1).into());
+        assert_matches!(
+            e.want_add(addr(2).into()),
+            Err(hci::Error::Hci {
+                status: hci::Status::MemoryCapacityExceeded
+            })
+        );
+        assert!(!e.want_add(addr(1).into()).unwrap());
+    }
+
+    #[test]
+    fn identity() {
+        let raw = |b| RawAddr::from_le_bytes([1, 2, 3, 4, 5, b]);
+        assert!(is_identity(Addr::Public(raw(0x40))));
+        assert!(is_identity(Addr::Random(raw(0xC0))));
+        assert!(!is_identity(Addr::Random(raw(0x40))));
+        assert!(!is_identity(Addr::Random(raw(0x00))));
+    }
+}
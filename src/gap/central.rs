@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use burble_crypto::IRK;
+use tracing::{debug, info, warn};
+
+use crate::le::{Addr, RawAddr};
+use crate::{gatt, hci, l2cap, name_of, smp};
+
+use super::*;
+
+/// Central role that discovers, connects to, and pairs with peripherals
+/// ([Vol 3] Part C, Section 2.2.2). Only one connection may be established
+/// at a time.
+///
+/// The central owns the L2CAP channel manager and the security database, so it
+/// must not be combined with another [`l2cap::ChanManager`] or
+/// [`smp::SecDb`] for the same host.
+///
+/// Peers using resolvable private addresses can be reconnected after their
+/// address changes only if the central is created with
+/// [`Central::with_resolving_list`] and address resolution is enabled in the
+/// controller ([Vol 3] Part C, Section 10.7). Otherwise, such peers can be
+/// reconnected only while the address is unchanged.
+#[derive(Debug)]
+pub struct Central {
+    host: hci::Host,
+    cm: l2cap::ChanManager,
+    store: Arc<smp::KeyStore>,
+    secdb: tokio::task::JoinHandle<hci::Result<()>>,
+    own_addr_type: hci::OwnAddrType,
+    local_addr: Addr,
+}
+
+impl Central {
+    /// Maximum time to wait for a connection to be established.
+    pub const CONN_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Creates a new central that uses the local identity address and persists
+    /// keys in `store`. The identity address is the static random address set
+    /// by [`hci::Host::set_static_address`], if any, or the public device
+    /// address.
+    #[inline]
+    pub async fn new(host: &hci::Host, store: Arc<smp::KeyStore>) -> Result<Self> {
+        Self::with_secdb(host, store, |db| db).await
+    }
+
+    /// Creates a new central, as in [`Self::new`], that loads all bonded peers
+    /// that distributed an IRK into the controller's resolving list. `local` is
+    /// the local IRK, which should also be distributed to peers via
+    /// [`smp::Device::with_identity`]. Address resolution must be enabled
+    /// separately with [`hci::HostConfig::with_addr_resolution`]. Peers bonded
+    /// by this central are added to the list when the central is next created.
+    #[inline]
+    pub async fn with_resolving_list(
+        host: &hci::Host,
+        store: Arc<smp::KeyStore>,
+        local: IRK,
+    ) -> Result<Self> {
+        Self::with_secdb(host, store, |db| db.with_resolving_list(local)).await
+    }
+
+    /// Creates a new central with the security database configured by `f`.
+    async fn with_secdb(
+        host: &hci::Host,
+        store: Arc<smp::KeyStore>,
+        f: impl FnOnce(smp::SecDb) -> smp::SecDb + Send,
+    ) -> Result<Self> {
+        let mut secdb = f(smp::SecDb::new(host.clone(), Arc::clone(&store)));
+        let own_addr_type = if host.static_address().is_some() {
+            hci::OwnAddrType::Random
+        } else {
+            hci::OwnAddrType::Public
+        };
+        Ok(Self {
+            host: host.clone(),
+            cm: l2cap::ChanManager::new(host).await?,
+            store,
+            secdb: tokio::task::spawn(async move { secdb.event_loop().await }),
+            own_addr_type,
+            local_addr: host.identity_addr(),
+        })
+    }
+
+    /// Sets the random device address used for scanning and initiating
+    /// connections, such as a static random address from
+    /// [`RawAddr::load_or_gen_static_random`].
+    pub async fn set_random_address(&mut self, a: RawAddr) -> Result<()> {
+        self.host.le_set_random_address(a).await?;
+        self.own_addr_type = hci::OwnAddrType::Random;
+        self.local_addr = Addr::Random(a);
+        Ok(())
+    }
+
+    /// Starts scanning for advertising devices. Only the reports for which
+    /// `filter` returns `true` are returned by [`Scan::next`].
+    pub async fn scan(
+        &mut self,
+        p: hci::ScanParams,
+        filter: impl FnMut(&hci::AdvReport) -> bool + Send + 'static,
+    ) -> Result<Scan> {
+        let ctl = self.host.events();
+        let p = hci::ScanParams {
+            addr_type: self.own_addr_type,
+            ..p
+        };
+        let host = &self.host;
+        host.le_set_extended_scan_parameters(p).await?;
+        (host.le_set_extended_scan_enable(true, true, Duration::ZERO)).await?;
+        Ok(Scan {
+            host: Some(self.host.clone()),
+            ctl,
+            filter: Box::new(filter),
+            reports: VecDeque::new(),
+        })
+    }
+
+    /// Connects to `peer`, performs the MTU exchange, and returns the new
+    /// connection. Scanning must be stopped before calling this method. This
+    /// method is not cancel safe.
+    pub async fn connect(&mut self, peer: Addr, p: hci::ConnParams) -> Result<Connection> {
+        let mut ctl = self.host.events();
+        let cp = hci::CreateConnParams {
+            addr_type: self.own_addr_type,
+            conn: p,
+            ..hci::CreateConnParams::new(peer)
+        };
+        self.host.le_extended_create_connection(cp).await?;
+        let evt =
+            match tokio::time::timeout(Self::CONN_TIMEOUT, Self::conn_complete(&mut ctl)).await {
+                Ok(r) => r?,
+                Err(_) => {
+                    // [Vol 4] Part E, Section 7.8.13
+                    warn!("Connection to {peer} timed out");
+                    if let Err(e) = self.host.le_create_connection_cancel().await {
+                        // The connection was created before the cancel
+                        if e.status() != Some(hci::Status::CommandDisallowed) {
+                            return Err(e.into());
+                        }
+                    }
+                    Self::conn_complete(&mut ctl).await?
+                }
+            };
+        if !evt.status.is_ok() {
+            return Err(match evt.status {
+                hci::Status::UnknownConnectionIdentifier => Error::ConnTimeout,
+                st => hci::Error::from(st).into(),
+            });
+        }
+        drop(ctl);
+        info!("Connected to {peer} ({})", evt.handle);
+        let local_addr = self.local_addr;
+        self.host
+            .update_conn(evt.handle, |cn| cn.local_addr = local_addr);
+        let mut cn = loop {
+            let cn = self.cm.next().await?;
+            if hci::ConnHandle::from(cn.link()) == evt.handle {
+                break cn;
+            }
+            warn!("Ignoring unexpected {}", cn.link());
+        };
+        let mut br = cn.att_bearer().expect("ATT bearer already consumed");
+        br.exchange_mtu().await?;
+        debug!("ATT MTU for {peer}: {}", br.mtu());
+        Ok(Connection {
+            host: self.host.clone(),
+            hdl: evt.handle,
+            peer: evt.peer_addr,
+            gatt: gatt::Client::new(br),
+            smp: cn.smp_central().expect("SMP channel already consumed"),
+            store: Arc::clone(&self.store),
+        })
+    }
+
+    /// Returns the next central role connection complete event.
+    async fn conn_complete(ctl: &mut hci::EventStream) -> Result<hci::LeConnectionComplete> {
+        use hci::EventCode::*;
+        loop {
+            let evt = ctl.next().await?;
+            if matches!(
+                evt.code(),
+                LeConnectionComplete | LeEnhancedConnectionComplete
+            ) {
+                let c: hci::LeConnectionComplete = evt.get();
+                if !c.status.is_ok() || c.role == hci::Role::Central {
+                    return Ok(c);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Central {
+    #[inline]
+    fn drop(&mut self) {
+        self.secdb.abort();
+    }
+}
+
+/// Active scan started by [`Central::scan`]. Scanning is disabled when the
+/// scan is stopped or dropped.
+pub struct Scan {
+    host: Option<hci::Host>,
+    ctl: hci::EventStream,
+    filter: Box<dyn FnMut(&hci::AdvReport) -> bool + Send>,
+    reports: VecDeque<hci::AdvReport>,
+}
+
+impl Scan {
+    /// Returns the next advertising report accepted by the filter. This
+    /// method is cancel safe.
+    pub async fn next(&mut self) -> Result<hci::AdvReport> {
+        loop {
+            if let Some(r) = self.reports.pop_front() {
+                return Ok(r);
+            }
+            let evt = self.ctl.next().await?;
+            if !matches!(evt.code(), hci::EventCode::LeExtendedAdvertisingReport) {
+                continue;
+            }
+            let all: hci::LeExtendedAdvertisingReport = evt.get();
+            let filter = &mut self.filter;
+            (self.reports).extend(all.into_iter().filter(|r| filter(r)));
+        }
+    }
+
+    /// Stops scanning.
+    pub async fn stop(mut self) -> Result<()> {
+        let host = self.host.take().expect("scan already stopped");
+        (host.le_set_extended_scan_enable(false, false, Duration::ZERO)).await?;
+        Ok(())
+    }
+}
+
+impl Debug for Scan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(name_of!(Scan))
+            .field("reports", &self.reports.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Scan {
+    fn drop(&mut self) {
+        let Some(host) = self.host.take() else { return };
+        tokio::task::spawn(async move {
+            if let Err(e) = (host.le_set_extended_scan_enable(false, false, Duration::ZERO)).await {
+                warn!("Failed to disable scanning: {e}");
+            }
+        });
+    }
+}
+
+/// Connection established by [`Central::connect`].
+#[derive(Debug)]
+pub struct Connection {
+    host: hci::Host,
+    hdl: hci::ConnHandle,
+    peer: Addr,
+    gatt: gatt::Client,
+    smp: smp::Central,
+    store: Arc<smp::KeyStore>,
+}
+
+impl Connection {
+    /// Returns the connection handle.
+    #[inline(always)]
+    #[must_use]
+    pub const fn handle(&self) -> hci::ConnHandle {
+        self.hdl
+    }
+
+    /// Returns the peer address, which is the peer's identity address after
+    /// pairing if it was distributed.
+    #[inline(always)]
+    #[must_use]
+    pub const fn peer_addr(&self) -> Addr {
+        self.peer
+    }
+
+    /// Returns the current transmitter and receiver PHY.
+    #[must_use]
+    pub fn phy(&self) -> (hci::Phy, hci::Phy) {
+        (self.host.conn(self.hdl)).map_or_else(Default::default, |cn| cn.borrow().phy)
+    }
+
+    /// Returns the GATT client.
+    #[inline(always)]
+    pub fn gatt(&mut self) -> &mut gatt::Client {
+        &mut self.gatt
+    }
+
+    /// Encrypts the connection using saved keys. Returns `false` if there are
+    /// no keys for the peer.
+    pub async fn encrypt(&mut self) -> Result<bool> {
+        let Some(keys) = self.store.load(self.peer) else {
+            return Ok(false);
+        };
+        if !keys.is_supported() {
+            return Ok(false);
+        }
+        self.start_encryption(&keys).await?;
+        Ok(true)
+    }
+
+    /// Pairs with the peer using LE Secure Connections, encrypts the
+    /// connection, and saves the keys. A bond is created if `bond` is `true`.
+    /// If the peer distributes its identity address, the keys are saved under
+    /// that address, which becomes the peer address of the connection.
+    pub async fn pair(&mut self, dev: &mut smp::Device, bond: bool) -> Result<()> {
+        let keys = self.smp.initiate(dev, self.store.as_ref(), bond).await?;
+        self.start_encryption(&keys).await?;
+        let store = self.store.as_ref();
+        self.peer = self.smp.distribute_keys(dev, store, keys).await?;
+        Ok(())
+    }
+
+    /// Terminates the connection and waits for the disconnection to complete.
+    pub async fn disconnect(self) -> Result<()> {
+        // [Vol 3] Part C, Section 9.3.10
+        let r = hci::Status::RemoteUserTerminatedConnection;
+        let done = self.host.disconnected(self.hdl);
+        self.host.disconnect(self.hdl, r).await?;
+        debug!("Disconnected from {}: {}", self.peer, done.await);
+        Ok(())
+    }
+
+    /// Starts encryption with the Long Term Key and waits for the result
+    /// ([Vol 3] Part C, Section 10.6).
+    async fn start_encryption(&mut self, keys: &smp::Keys) -> Result<()> {
+        use hci::EventCode::*;
+        let mut ctl = self.host.conn_events(self.hdl);
+        // [Vol 3] Part H, Section 2.4.4
+        (self.host.le_enable_encryption(self.hdl, 0, 0, keys.ltk())).await?;
+        loop {
+            let evt = ctl.next().await?;
+            match evt.code() {
+                EncryptionChange | EncryptionChangeV2 => {
+                    let e: hci::EncryptionChange = evt.get();
+                    if e.handle != self.hdl {
+                        continue;
+                    }
+                    if !e.status.is_ok() {
+                        return Err(Error::Encryption(e.status));
+                    }
+                    info!("Encryption enabled for {}", self.peer);
+                    return Ok(());
+                }
+                DisconnectionComplete => {
+                    let e: hci::DisconnectionComplete = evt.get();
+                    if e.handle == self.hdl {
+                        return Err(Error::Disconnected(e.reason.into()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
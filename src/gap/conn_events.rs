@@ -0,0 +1,306 @@
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, Instrument};
+
+use crate::hci;
+use crate::le::Addr;
+
+use super::*;
+
+/// Connection lifecycle event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ConnEvent {
+    /// Connection was established. This is always the first event.
+    Connected {
+        role: hci::Role,
+        peer: Addr,
+        params: ActiveConnParams,
+    },
+    /// Connection parameters were updated.
+    ParamsUpdated(ActiveConnParams),
+    /// Transmitter or receiver PHY was changed.
+    PhyChanged { tx: hci::Phy, rx: hci::Phy },
+    /// Maximum LL Data PDU payload sizes or transmission times were changed.
+    DataLenChanged(hci::DataLen),
+    /// Encryption was enabled or disabled.
+    EncryptionChanged { enabled: bool },
+    /// Controller failed to enable encryption. Pending requests that require
+    /// security should fail with [`att::ErrorCode::for_encryption_status`].
+    EncryptionFailed(hci::Status),
+    /// ATT MTU was changed.
+    MtuChanged(u16),
+    /// Connection was closed. This is always the last event.
+    Disconnected { reason: DisconnectReason },
+}
+
+/// Reason for the termination of a connection, derived from the HCI status
+/// reported by the controller ([Vol 1] Part F, Section 1.3).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    /// Connection was terminated by the local host.
+    LocalHost,
+    /// Remote user or host terminated the connection, possibly because of low
+    /// resources or imminent power off.
+    Remote(hci::Status),
+    /// Link supervision timeout or LL response timeout expired.
+    Timeout(hci::Status),
+    /// Authentication failed or the peer did not have the required key.
+    Authentication(hci::Status),
+    /// Message integrity check failed for a received packet.
+    MicFailure,
+    /// Connection failed to be established or the connection parameters were
+    /// not acceptable.
+    Failed(hci::Status),
+    /// Connection was closed for another reason, or the reason is unknown.
+    Other(hci::Status),
+}
+
+impl DisconnectReason {
+    /// Returns the HCI status code of the disconnect reason.
+    #[must_use]
+    pub const fn status(self) -> hci::Status {
+        use DisconnectReason::*;
+        match self {
+            LocalHost => hci::Status::ConnectionTerminatedByLocalHost,
+            MicFailure => hci::Status::ConnectionTerminatedDueToMicFailure,
+            Remote(st) | Timeout(st) | Authentication(st) | Failed(st) | Other(st) => st,
+        }
+    }
+
+    /// Returns whether the connection was lost unexpectedly, rather than
+    /// closed by either host.
+    #[inline]
+    #[must_use]
+    pub const fn is_link_loss(self) -> bool {
+        !matches!(self, Self::LocalHost | Self::Remote(_))
+    }
+}
+
+impl From<hci::Status> for DisconnectReason {
+    fn from(st: hci::Status) -> Self {
+        use hci::Status::*;
+        match st {
+            ConnectionTerminatedByLocalHost => Self::LocalHost,
+            RemoteUserTerminatedConnection
+            | RemoteDeviceTerminatedConnectionDueToLowResources
+            | RemoteDeviceTerminatedConnectionDueToPowerOff => Self::Remote(st),
+            ConnectionTimeout | LmpLlResponseTimeout => Self::Timeout(st),
+            AuthenticationFailure | PinOrKeyMissing | InsufficientSecurity => {
+                Self::Authentication(st)
+            }
+            ConnectionTerminatedDueToMicFailure => Self::MicFailure,
+            ConnectionFailedToBeEstablished | UnacceptableConnectionParameters => Self::Failed(st),
+            _ => Self::Other(st),
+        }
+    }
+}
+
+impl std::fmt::Display for DisconnectReason {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.status(), f)
+    }
+}
+
+/// Stream of lifecycle events for one connection, returned by
+/// [`l2cap::Conn::events`]. Events are buffered, so the stream does not delay
+/// event processing for other connections if it is not polled.
+#[derive(Debug)]
+pub struct ConnEvents {
+    rx: mpsc::UnboundedReceiver<ConnEvent>,
+    done: bool,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ConnEvents {
+    /// Creates an event stream for connection `hdl`, which must be monitored
+    /// for ATT MTU changes via `mtu`.
+    pub(crate) fn new(host: &hci::Host, hdl: hci::ConnHandle, mtu: watch::Receiver<u16>) -> Self {
+        // The stream must be created before the connection state is checked
+        // to guarantee that DisconnectionComplete event is received.
+        let ctl = host.conn_events(hdl);
+        let cn = host.conn(hdl);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let t = Task { hdl, tx, mtu };
+        let f = t.run(ctl, cn);
+        Self {
+            rx,
+            done: false,
+            task: tokio::task::spawn(f.instrument(host.conn_span(hdl))),
+        }
+    }
+
+    /// Returns the next event or [`None`] after [`ConnEvent::Disconnected`].
+    /// This method is cancel safe.
+    pub async fn next(&mut self) -> Option<ConnEvent> {
+        if self.done {
+            return None;
+        }
+        let e = (self.rx.recv().await).unwrap_or(ConnEvent::Disconnected {
+            reason: hci::Status::UnspecifiedError.into(),
+        });
+        self.done = matches!(e, ConnEvent::Disconnected { .. });
+        Some(e)
+    }
+}
+
+impl Drop for ConnEvents {
+    #[inline]
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Connection event forwarding task.
+#[derive(Debug)]
+struct Task {
+    hdl: hci::ConnHandle,
+    tx: mpsc::UnboundedSender<ConnEvent>,
+    mtu: watch::Receiver<u16>,
+}
+
+impl Task {
+    /// Forwards connection events until the connection is closed.
+    async fn run(mut self, mut ctl: hci::EventStream, cn: Option<hci::ConnWatch>) {
+        let Some(cn) = cn else {
+            // The connection was closed before the stream was created
+            self.send(ConnEvent::Disconnected {
+                reason: hci::Status::UnknownConnectionIdentifier.into(),
+            });
+            return;
+        };
+        let e = {
+            let c = cn.borrow();
+            ConnEvent::Connected {
+                role: c.role,
+                peer: c.peer_addr,
+                params: c.params,
+            }
+        };
+        self.send(e);
+        let mut mtu_open = true;
+        let reason = loop {
+            let evt = tokio::select! {
+                r = self.mtu.changed(), if mtu_open => {
+                    if r.is_ok() {
+                        let mtu = *self.mtu.borrow_and_update();
+                        self.send(ConnEvent::MtuChanged(mtu));
+                    } else {
+                        mtu_open = false;
+                    }
+                    continue;
+                }
+                r = ctl.next() => match r {
+                    Ok(evt) => evt,
+                    Err(e) => {
+                        debug!("Connection event stream error: {e}");
+                        let r = cn.borrow().disconnect_reason;
+                        break r.unwrap_or(hci::Status::UnspecifiedError).into();
+                    }
+                },
+            };
+            if evt.conn_handle() != Some(self.hdl) {
+                continue;
+            }
+            if let Some(e) = Self::convert(&evt) {
+                if let ConnEvent::Disconnected { reason } = e {
+                    break reason;
+                }
+                self.send(e);
+            }
+        };
+        self.send(ConnEvent::Disconnected { reason });
+    }
+
+    /// Converts an HCI event into a connection event.
+    fn convert(evt: &hci::Event) -> Option<ConnEvent> {
+        use hci::EventCode::*;
+        if !evt.status().is_ok() {
+            return matches!(evt.code(), EncryptionChange | EncryptionChangeV2)
+                .then(|| ConnEvent::EncryptionFailed(evt.status()));
+        }
+        Some(match evt.code() {
+            DisconnectionComplete => {
+                let e: hci::DisconnectionComplete = evt.get();
+                ConnEvent::Disconnected {
+                    reason: e.reason.into(),
+                }
+            }
+            LeConnectionUpdateComplete => {
+                let e: hci::LeConnectionUpdateComplete = evt.get();
+                ConnEvent::ParamsUpdated(e.into())
+            }
+            LePhyUpdateComplete => {
+                let e: hci::LePhyUpdateComplete = evt.get();
+                ConnEvent::PhyChanged {
+                    tx: e.tx_phy,
+                    rx: e.rx_phy,
+                }
+            }
+            LeDataLengthChange => {
+                let e: hci::LeDataLengthChange = evt.get();
+                ConnEvent::DataLenChanged(e.max)
+            }
+            EncryptionChange | EncryptionChangeV2 => {
+                let e: hci::EncryptionChange = evt.get();
+                ConnEvent::EncryptionChanged { enabled: e.enabled }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Sends an event to the stream. Events are discarded if the stream was
+    /// dropped.
+    #[inline]
+    fn send(&self, e: ConnEvent) {
+        let _ = self.tx.send(e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnect_reason() {
+        use hci::Status::*;
+        let r = DisconnectReason::from;
+        assert_eq!(
+            r(ConnectionTerminatedByLocalHost),
+            DisconnectReason::LocalHost
+        );
+        assert_eq!(
+            r(RemoteDeviceTerminatedConnectionDueToPowerOff),
+            DisconnectReason::Remote(RemoteDeviceTerminatedConnectionDueToPowerOff)
+        );
+        assert_eq!(
+            r(ConnectionTimeout),
+            DisconnectReason::Timeout(ConnectionTimeout)
+        );
+        assert_eq!(
+            r(PinOrKeyMissing),
+            DisconnectReason::Authentication(PinOrKeyMissing)
+        );
+        assert_eq!(
+            r(ConnectionTerminatedDueToMicFailure),
+            DisconnectReason::MicFailure
+        );
+        assert_eq!(
+            r(UnspecifiedError),
+            DisconnectReason::Other(UnspecifiedError)
+        );
+        for st in [
+            ConnectionTerminatedByLocalHost,
+            RemoteUserTerminatedConnection,
+            LmpLlResponseTimeout,
+            ConnectionTerminatedDueToMicFailure,
+            ConnectionFailedToBeEstablished,
+            UnspecifiedError,
+        ] {
+            assert_eq!(r(st).status(), st);
+        }
+        assert!(!r(RemoteUserTerminatedConnection).is_link_loss());
+        assert!(r(ConnectionTimeout).is_link_loss());
+    }
+}
@@ -0,0 +1,423 @@
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::{debug, info, warn, Instrument};
+
+use crate::{hci, l2cap};
+
+use super::*;
+
+/// Connection parameter profile applied by [`ConnParamsManager`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum ConnProfile {
+    /// Minimum latency for interactive use and bulk transfers (15 ms).
+    Fast,
+    /// Moderate latency and power consumption (30-60 ms).
+    #[default]
+    Balanced,
+    /// Minimum power consumption for idle connections (195-225 ms).
+    LowPower,
+}
+
+impl ConnProfile {
+    /// Returns the connection parameters requested for the profile. All
+    /// profiles satisfy [`is_ios_compatible`].
+    #[must_use]
+    pub const fn params(self) -> hci::ConnParams {
+        let (min, max, latency, timeout) = match self {
+            Self::Fast => (15, 15, 0, 2000),
+            Self::Balanced => (30, 60, 0, 4000),
+            Self::LowPower => (195, 225, 4, 6000),
+        };
+        hci::ConnParams {
+            interval: (Duration::from_millis(min), Duration::from_millis(max)),
+            latency,
+            timeout: Duration::from_millis(timeout),
+        }
+    }
+}
+
+/// Returns whether the parameters satisfy the requirements of Apple devices,
+/// which reject any other requests (Accessory Design Guidelines for Apple
+/// Devices, "Connection Parameters").
+#[must_use]
+pub fn is_ios_compatible(p: &hci::ConnParams) -> bool {
+    let (min, max) = p.interval;
+    let max_period = max * (u32::from(p.latency) + 1);
+    (min >= IOS_INTERVAL_STEP && min.as_micros() % IOS_INTERVAL_STEP.as_micros() == 0)
+        && (min + IOS_INTERVAL_STEP <= max || (min == IOS_INTERVAL_STEP && max == min))
+        && p.latency <= 30
+        && max_period <= Duration::from_secs(2)
+        && (Duration::from_secs(2) <= p.timeout && p.timeout <= Duration::from_secs(6))
+        && max_period * 3 < p.timeout
+}
+
+/// Connection interval granularity required by Apple devices.
+const IOS_INTERVAL_STEP: Duration = Duration::from_millis(15);
+
+/// Current parameters of a connection.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ActiveConnParams {
+    pub interval: Duration,
+    pub latency: u16,
+    pub timeout: Duration,
+}
+
+impl ActiveConnParams {
+    /// Returns whether the parameters are within the requested ranges.
+    #[inline]
+    #[must_use]
+    fn satisfies(&self, p: &hci::ConnParams) -> bool {
+        (p.interval.0 <= self.interval && self.interval <= p.interval.1)
+            && self.latency <= p.latency
+    }
+}
+
+impl From<hci::LeConnectionUpdateComplete> for ActiveConnParams {
+    #[inline]
+    fn from(e: hci::LeConnectionUpdateComplete) -> Self {
+        Self {
+            interval: e.conn_interval,
+            latency: e.peripheral_latency,
+            timeout: e.supervision_timeout,
+        }
+    }
+}
+
+/// Per-connection manager that applies the connection parameters of the
+/// selected [`ConnProfile`] ([Vol 3] Part C, Section 9.3.9). As the Central,
+/// the parameters are changed using the Link Layer connection update procedure.
+/// As the Peripheral, they are requested using the Link Layer connection
+/// parameters request procedure, or the L2CAP connection parameter update
+/// procedure if either controller does not support it. Rejected requests are
+/// retried with relaxed intervals, and rapid profile changes are debounced.
+///
+/// The manager stops when it is dropped or the connection is closed.
+#[derive(Debug)]
+pub struct ConnParamsManager {
+    profile: watch::Sender<ConnProfile>,
+    active: watch::Receiver<Option<ActiveConnParams>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ConnParamsManager {
+    /// Minimum time between profile changes before the profile is applied.
+    pub const DEBOUNCE: Duration = Duration::from_secs(1);
+
+    /// Creates a manager for connection `cn` that applies `profile` after
+    /// the debounce period.
+    #[must_use]
+    pub fn new(host: &hci::Host, cn: &l2cap::Conn, profile: ConnProfile) -> Self {
+        let hdl = hci::ConnHandle::from(cn.link());
+        let (profile, rx) = watch::channel(profile);
+        let (tx, active) = watch::channel(None);
+        let t = Task::new(host, cn, tx);
+        Self {
+            profile,
+            active,
+            task: tokio::task::spawn(t.run(rx).instrument(host.conn_span(hdl))),
+        }
+    }
+
+    /// Returns the selected profile.
+    #[inline]
+    #[must_use]
+    pub fn profile(&self) -> ConnProfile {
+        *self.profile.borrow()
+    }
+
+    /// Selects a new profile, which is applied once the selection is stable
+    /// for [`Self::DEBOUNCE`].
+    #[inline]
+    pub fn set_profile(&self, p: ConnProfile) {
+        self.profile.send_if_modified(|cur| {
+            let changed = *cur != p;
+            *cur = p;
+            changed
+        });
+    }
+
+    /// Returns the connection parameters after the last completed update.
+    #[inline]
+    #[must_use]
+    pub fn active(&self) -> Option<ActiveConnParams> {
+        *self.active.borrow()
+    }
+}
+
+impl Drop for ConnParamsManager {
+    #[inline]
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Connection parameter manager task.
+#[derive(Debug)]
+struct Task {
+    host: hci::Host,
+    hdl: hci::ConnHandle,
+    role: hci::Role,
+    sig: l2cap::SigHandle,
+    active: watch::Sender<Option<ActiveConnParams>>,
+    /// Whether the Link Layer procedure may be used in the Peripheral role.
+    ll_proc: AtomicBool,
+}
+
+impl Task {
+    /// Maximum number of retries with relaxed parameters.
+    const MAX_RETRIES: usize = 2;
+
+    /// Link Layer procedure response timeout ([Vol 6] Part B, Section 5.2).
+    const UPDATE_TIMEOUT: Duration = Duration::from_secs(40);
+
+    /// Creates a task for connection `cn`.
+    fn new(
+        host: &hci::Host,
+        cn: &l2cap::Conn,
+        active: watch::Sender<Option<ActiveConnParams>>,
+    ) -> Self {
+        let hdl = hci::ConnHandle::from(cn.link());
+        Self {
+            host: host.clone(),
+            hdl,
+            role: (host.conn(hdl)).map_or(hci::Role::Peripheral, |cn| cn.borrow().role),
+            sig: cn.sig(),
+            active,
+            ll_proc: AtomicBool::new(true),
+        }
+    }
+
+    /// Applies profile changes until the connection is closed.
+    async fn run(self, mut profile: watch::Receiver<ConnProfile>) {
+        loop {
+            // Debounce profile changes
+            loop {
+                match tokio::time::timeout(ConnParamsManager::DEBOUNCE, profile.changed()).await {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(_)) => return,
+                    Err(_) => break,
+                }
+            }
+            let p = *profile.borrow_and_update();
+            if let Err(e) = self.apply(p).await {
+                debug!("Connection parameter manager for {} stopped: {e}", self.hdl);
+                return;
+            }
+            if profile.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Applies the specified profile, relaxing the parameters if the request
+    /// is rejected.
+    async fn apply(&self, profile: ConnProfile) -> Result<()> {
+        let mut p = profile.params();
+        for retry in 0..=Self::MAX_RETRIES {
+            if retry > 0 {
+                p = relax(p);
+            }
+            debug!("Requesting {profile:?} parameters for {}: {p:?}", self.hdl);
+            if let Some(active) = self.update(p).await? {
+                info!("{profile:?} parameters for {}: {active:?}", self.hdl);
+                return Ok(());
+            }
+        }
+        warn!("Failed to apply {profile:?} parameters for {}", self.hdl);
+        Ok(())
+    }
+
+    /// Requests new connection parameters and waits for the Link Layer
+    /// procedure to complete. Returns [`None`] if the request was rejected or
+    /// the new parameters are outside of the requested ranges.
+    async fn update(&self, p: hci::ConnParams) -> Result<Option<ActiveConnParams>> {
+        loop {
+            let ll = self.role == hci::Role::Central || self.ll_proc.load(Relaxed);
+            match self.try_update(p, ll).await? {
+                Ok(active) => return Ok(active.satisfies(&p).then_some(active)),
+                Err(st) if ll && self.role == hci::Role::Peripheral && is_ll_unsupported(st) => {
+                    debug!("Connection parameters request procedure not supported ({st})");
+                    self.ll_proc.store(false, Relaxed);
+                }
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    /// Requests new connection parameters using the Link Layer procedure if
+    /// `ll` is `true` or the L2CAP procedure otherwise, and waits for the
+    /// update to complete. Returns the status for rejected requests.
+    async fn try_update(
+        &self,
+        p: hci::ConnParams,
+        ll: bool,
+    ) -> Result<std::result::Result<ActiveConnParams, hci::Status>> {
+        use hci::EventCode::*;
+        let mut ctl = self.host.conn_events(self.hdl);
+        let req = async {
+            if ll {
+                // [Vol 4] Part E, Section 7.8.18
+                match self.host.le_connection_update(self.hdl, p).await {
+                    Ok(()) => Ok(Ok(())),
+                    Err(hci::Error::CommandFailed { status, .. })
+                        if is_rejection(status) || is_ll_unsupported(status) =>
+                    {
+                        Ok(Err(status))
+                    }
+                    Err(e) => Err(Error::from(e)),
+                }
+            } else {
+                match self.sig.conn_param_update(p).await {
+                    Ok(true) => Ok(Ok(())),
+                    Ok(false) => Ok(Err(hci::Status::UnacceptableConnectionParameters)),
+                    Err(l2cap::Error::Rejected(..)) => {
+                        Ok(Err(hci::Status::UnsupportedRemoteFeature))
+                    }
+                    Err(e) => Err(Error::from(e)),
+                }
+            }
+        };
+        tokio::pin!(req);
+        let timeout = tokio::time::sleep(Self::UPDATE_TIMEOUT);
+        tokio::pin!(timeout);
+        let mut requested = false;
+        loop {
+            tokio::select! {
+                r = &mut req, if !requested => {
+                    if let Err(st) = r? {
+                        debug!("Connection parameter update rejected for {}: {st}", self.hdl);
+                        return Ok(Err(st));
+                    }
+                    requested = true;
+                }
+                evt = ctl.next() => {
+                    let evt = evt?;
+                    match evt.code() {
+                        LeConnectionUpdateComplete => {
+                            let e: hci::LeConnectionUpdateComplete = evt.get();
+                            if e.handle != self.hdl {
+                                continue;
+                            }
+                            if !e.status.is_ok() {
+                                debug!("Connection update failed for {}: {}", self.hdl, e.status);
+                                return Ok(Err(e.status));
+                            }
+                            let active = ActiveConnParams::from(e);
+                            self.active.send_replace(Some(active));
+                            return Ok(Ok(active));
+                        }
+                        DisconnectionComplete => {
+                            let e: hci::DisconnectionComplete = evt.get();
+                            if e.handle == self.hdl {
+                                return Err(Error::Disconnected(e.reason.into()));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ = &mut timeout => {
+                    debug!("Connection update timeout for {}", self.hdl);
+                    return Ok(Err(hci::Status::LmpLlResponseTimeout));
+                }
+            }
+        }
+    }
+}
+
+/// Requests new connection parameters for connection `cn` and waits for the
+/// update to complete. See [`l2cap::Conn::request_conn_params`].
+pub(crate) async fn request_conn_params(
+    host: &hci::Host,
+    cn: &l2cap::Conn,
+    p: hci::ConnParams,
+) -> Result<Option<ActiveConnParams>> {
+    let (tx, _) = watch::channel(None);
+    Task::new(host, cn, tx).update(p).await
+}
+
+/// Returns whether the status indicates that the Link Layer connection
+/// parameters request procedure is not supported by the local or remote
+/// controller.
+#[inline]
+const fn is_ll_unsupported(st: hci::Status) -> bool {
+    use hci::Status::*;
+    matches!(st, UnknownCommand | UnsupportedRemoteFeature)
+}
+
+/// Returns whether the status indicates that the peer or the controller
+/// rejected the connection parameters.
+#[inline]
+const fn is_rejection(st: hci::Status) -> bool {
+    use hci::Status::*;
+    matches!(
+        st,
+        UnsupportedRemoteFeature
+            | UnsupportedFeatureOrParameterValue
+            | InvalidCommandParameters
+            | InvalidLmpLlParameters
+            | UnsupportedLmpLlParameterValue
+            | UnacceptableConnectionParameters
+    )
+}
+
+/// Returns parameters with a wider connection interval range that remain
+/// valid and compatible with Apple devices.
+#[allow(clippy::cast_possible_truncation)]
+#[must_use]
+fn relax(p: hci::ConnParams) -> hci::ConnParams {
+    let step = IOS_INTERVAL_STEP;
+    let n = u32::from(p.latency) + 1;
+    // Largest interval that satisfies both period and timeout requirements
+    let limit =
+        Duration::from_secs(2).min((p.timeout / 3).saturating_sub(Duration::from_micros(1250))) / n;
+    let min = p.interval.0.saturating_sub(step).max(step);
+    let max = (p.interval.1 * 2).min(limit);
+    let max = step * (max.as_micros() / step.as_micros()) as u32;
+    hci::ConnParams {
+        interval: (min, max.max(min + step)),
+        ..p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        for p in [
+            ConnProfile::Fast,
+            ConnProfile::Balanced,
+            ConnProfile::LowPower,
+        ] {
+            let mut v = p.params();
+            assert!(v.is_valid(), "{p:?}");
+            assert!(is_ios_compatible(&v), "{p:?}");
+            for _ in 0..Task::MAX_RETRIES {
+                v = relax(v);
+                assert!(v.is_valid(), "{p:?} {v:?}");
+                assert!(is_ios_compatible(&v), "{p:?} {v:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn ios_compatible() {
+        let ms = Duration::from_millis;
+        let p = |min, max, latency, timeout| hci::ConnParams {
+            interval: (ms(min), ms(max)),
+            latency,
+            timeout: ms(timeout),
+        };
+        assert!(is_ios_compatible(&p(15, 15, 0, 2000)));
+        assert!(is_ios_compatible(&p(30, 45, 0, 2000)));
+        assert!(!is_ios_compatible(&p(30, 30, 0, 2000))); // Max too small
+        assert!(!is_ios_compatible(&p(20, 40, 0, 2000))); // Not a multiple of 15
+        assert!(!is_ios_compatible(&p(15, 30, 31, 6000))); // Latency
+        assert!(!is_ios_compatible(&p(15, 30, 0, 1000))); // Timeout too short
+        assert!(!is_ios_compatible(&p(600, 900, 2, 6000))); // Period too long
+    }
+}
@@ -74,6 +74,33 @@ bitflags::bitflags! {
     }
 }
 
+/// Supported LE roles and the preferred role for connection establishment
+/// ([CSS] Part A, Section 1.17).
+#[allow(clippy::exhaustive_enums)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Eq,
+    PartialEq,
+    enum_iterator::Sequence,
+    num_enum::IntoPrimitive,
+    num_enum::TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum LeRole {
+    /// Only Peripheral role supported.
+    OnlyPeripheral = 0x00,
+    /// Only Central role supported.
+    OnlyCentral = 0x01,
+    /// Peripheral and Central roles supported. Peripheral role preferred for
+    /// connection establishment.
+    PeripheralPreferred = 0x02,
+    /// Peripheral and Central roles supported. Central role preferred for
+    /// connection establishment.
+    CentralPreferred = 0x03,
+}
+
 /// Device appearance ([Assigned Numbers] Section 2.6.3).
 #[derive(
     Clone,
@@ -84,6 +111,7 @@ bitflags::bitflags! {
     Ord,
     PartialEq,
     PartialOrd,
+    enum_iterator::Sequence,
     num_enum::FromPrimitive,
     num_enum::IntoPrimitive,
 )]
@@ -400,4 +428,50 @@ impl Appearance {
     pub fn category(self) -> Self {
         Self::from(u16::from(self) & (u16::MAX << 6))
     }
+
+    /// Returns whether the appearance is a generic category value, which has
+    /// a sub-category of zero ([Assigned Numbers] Section 2.6.2).
+    #[inline]
+    #[must_use]
+    pub fn is_generic(self) -> bool {
+        u16::from(self) & !(u16::MAX << 6) == 0
+    }
+
+    /// Returns an iterator over all known appearance values in category
+    /// `cat`, including the generic category value itself. The iterator is
+    /// empty if `cat` is not a generic category value.
+    #[inline]
+    pub fn iter_category(cat: Self) -> impl Iterator<Item = Self> {
+        enum_iterator::all::<Self>().filter(move |v| v.category() == cat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appearance_category() {
+        use Appearance::*;
+        assert!(GenericWatch.is_generic());
+        assert!(GenericUnknown.is_generic());
+        assert!(!SportsWatch.is_generic());
+        assert_eq!(SportsWatch.category(), GenericWatch);
+
+        let v: Vec<_> = Appearance::iter_category(GenericInsulinPump).collect();
+        assert_eq!(
+            v,
+            [
+                GenericInsulinPump,
+                DurableInsulinPump,
+                PatchInsulinPump,
+                InsulinPen
+            ]
+        );
+        assert_eq!(Appearance::iter_category(SportsWatch).count(), 0);
+        for cat in enum_iterator::all::<Appearance>().filter(|v| v.is_generic()) {
+            assert!(Appearance::iter_category(cat).all(|v| v.category() == cat));
+            assert_eq!(Appearance::iter_category(cat).next(), Some(cat));
+        }
+    }
 }
@@ -1,7 +1,50 @@
 //! Generic Access Profile ([Vol 3] Part C).
 
 pub use burble_const::{Uuid, Uuid16, UuidType, UuidVec};
-pub use {consts::*, response_data::*};
+pub use {accept_list::*, central::*, conn_events::*, conn_params::*, consts::*, response_data::*};
 
+use crate::{att, hci, l2cap, smp};
+
+mod accept_list;
+mod central;
+mod conn_events;
+mod conn_params;
 mod consts;
 mod response_data;
+
+/// Error type returned by the GAP layer.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Hci(#[from] hci::Error),
+    #[error(transparent)]
+    L2cap(#[from] l2cap::Error),
+    #[error(transparent)]
+    Att(#[from] att::Error),
+    #[error(transparent)]
+    Smp(#[from] smp::Error),
+    #[error("connection timeout")]
+    ConnTimeout,
+    #[error("encryption failed: {0}")]
+    Encryption(hci::Status),
+    #[error("disconnected: {0}")]
+    Disconnected(DisconnectReason),
+}
+
+impl Error {
+    /// Returns the ATT error code that a GATT operation failed with. Failures
+    /// to enable encryption are translated into the ATT security error that
+    /// indicates whether the peer must pair again.
+    #[must_use]
+    pub fn att_error(&self) -> Option<att::ErrorCode> {
+        match *self {
+            Self::Att(att::Error::Att(ref e)) => Some(e.code()),
+            Self::Encryption(st) => Some(att::ErrorCode::for_encryption_status(st)),
+            _ => None,
+        }
+    }
+}
+
+/// Common GAP result type.
+pub type Result<T> = std::result::Result<T, Error>;
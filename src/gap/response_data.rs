@@ -14,11 +14,12 @@ use enum_iterator::Sequence;
 use structbuf::{Pack, Packer, StructBuf};
 
 use burble_const::{Service, Uuid};
+use burble_crypto::{Codec, OobData};
 
 use crate::gap::consts::ResponseDataType;
-use crate::gap::{AdvFlag, Appearance};
+use crate::gap::{AdvFlag, Appearance, LeRole};
 use crate::hci::{ticks_1250us, ticks_625us};
-use crate::le::TxPower;
+use crate::le::{Addr, TxPower};
 
 /// Response data builder.
 #[derive(Clone, Debug)]
@@ -32,6 +33,16 @@ impl ResponseDataMut {
         Self(StructBuf::new(254)) // [Vol 6] Part B, Section 2.3.4
     }
 
+    /// Creates a new response data buffer for legacy advertising PDUs, which
+    /// are limited to 31 bytes ([Vol 6] Part B, Section 2.3.1). Appending a
+    /// field that exceeds the limit panics. Use [`Self::try_append`] to append
+    /// fields that may not fit.
+    #[inline]
+    #[must_use]
+    pub const fn legacy() -> Self {
+        Self(StructBuf::new(31))
+    }
+
     /// Returns the final response data buffer.
     #[allow(clippy::missing_const_for_fn)]
     #[inline]
@@ -39,6 +50,29 @@ impl ResponseDataMut {
         self.0
     }
 
+    /// Returns an iterator over the length-type-data structures in the buffer,
+    /// including their length octets.
+    #[inline]
+    pub fn structs(&self) -> impl Iterator<Item = &[u8]> {
+        ad_structs(self.0.as_ref())
+    }
+
+    /// Calls `f` to append one or more fields. Returns an error and leaves the
+    /// buffer unchanged if the fields exceed the buffer limit.
+    pub fn try_append(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> &mut Self,
+    ) -> Result<&mut Self, ResponseDataOverflow> {
+        // Response data buffers never exceed the 254 bytes of Self::new()
+        let mut tmp = Self::new();
+        f(&mut tmp);
+        if tmp.0.len() > self.0.remaining() {
+            return Err(ResponseDataOverflow);
+        }
+        self.0.append().put(tmp.0.as_ref());
+        Ok(self)
+    }
+
     /// Appends service UUIDs (\[CSS\] Part A, Section 1.1). Each UUID is
     /// encoded in the optimal format.
     pub fn service<T: Into<Uuid> + Copy>(
@@ -105,14 +139,19 @@ impl ResponseDataMut {
     }
 
     /// Appends manufacturer-specific data (\[CSS\] Part A, Section 1.4).
-    pub fn manufacturer_data<T: AsRef<str>>(&mut self, company_id: u16, v: &[u8]) -> &mut Self {
+    pub fn manufacturer_data(&mut self, company_id: u16, v: &[u8]) -> &mut Self {
         self.put(ResponseDataType::ManufacturerData, |b| {
             b.u16(company_id).put(v);
         })
     }
 
     /// Appends TX power level (\[CSS\] Part A, Section 1.5).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the power level is unspecified or not managed.
     pub fn tx_power(&mut self, v: TxPower) -> &mut Self {
+        let v = v.dbm().expect("unspecified TX power level");
         self.put(ResponseDataType::TxPower, |b| {
             b.i8(v);
         })
@@ -162,6 +201,29 @@ impl ResponseDataMut {
         }
     }
 
+    /// Appends LE Bluetooth device address (\[CSS\] Part A, Section 1.16).
+    pub fn le_device_addr(&mut self, v: Addr) -> &mut Self {
+        self.put(ResponseDataType::LeDeviceAddr, |b| {
+            b.put(v.raw()).u8(u8::from(v.is_random()));
+        })
+    }
+
+    /// Appends supported LE roles (\[CSS\] Part A, Section 1.17).
+    pub fn le_role(&mut self, v: LeRole) -> &mut Self {
+        self.put(ResponseDataType::LeRole, |b| {
+            b.u8(v);
+        })
+    }
+
+    /// Appends an LE Secure Connections OOB data block containing the local
+    /// device address, role, confirmation value, and random value
+    /// ([Vol 3] Part H, Section 2.3.5.6.4 and \[CSS\] Part A, Section 1.6).
+    pub fn le_sc_oob(&mut self, addr: Addr, role: LeRole, oob: &OobData) -> &mut Self {
+        self.le_device_addr(addr).le_role(role);
+        self.put(ResponseDataType::LeScConfirmValue, |b| oob.confirm.pack(b));
+        self.put(ResponseDataType::LeScRandValue, |b| oob.r.pack(b))
+    }
+
     /// Appends a length-type-data field to the buffer, calling `f` to provide
     /// the data.
     #[inline]
@@ -196,8 +258,106 @@ impl Default for ResponseDataMut {
     }
 }
 
+/// Error returned when appended fields exceed the response data buffer limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
+#[error("response data overflow")]
+pub struct ResponseDataOverflow;
+
+/// Returns an iterator over the length-type-data structures in `v`, including
+/// their length octets. A truncated structure at the end is returned as is.
+pub(crate) fn ad_structs(mut v: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        let n = usize::from(*v.first()?) + 1;
+        let (s, tail) = v.split_at(n.min(v.len()));
+        v = tail;
+        Some(s)
+    })
+}
+
+/// Response data parser. The iterator returns the type and data of each
+/// length-type-data field. Iteration stops at the first zero-length field
+/// ([Vol 3] Part C, Section 11) or at a truncated field.
+#[derive(Clone, Copy, Debug, Default)]
+#[must_use]
+pub struct ResponseDataIter<'a>(&'a [u8]);
+
+impl<'a> ResponseDataIter<'a> {
+    /// Creates a parser for the response data in `v`.
+    #[inline(always)]
+    pub const fn new(v: &'a [u8]) -> Self {
+        Self(v)
+    }
+
+    /// Returns device appearance (\[CSS\] Part A, Section 1.12).
+    #[must_use]
+    pub fn appearance(self) -> Option<Appearance> {
+        match *self.find_type(ResponseDataType::Appearance)? {
+            [a, b] => Some(Appearance::from(u16::from_le_bytes([a, b]))),
+            _ => None,
+        }
+    }
+
+    /// Returns supported LE roles (\[CSS\] Part A, Section 1.17).
+    #[must_use]
+    pub fn le_role(self) -> Option<LeRole> {
+        match *self.find_type(ResponseDataType::LeRole)? {
+            [v] => LeRole::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the complete or shortened local name
+    /// (\[CSS\] Part A, Section 1.2).
+    #[must_use]
+    pub fn local_name(mut self) -> Option<&'a str> {
+        use ResponseDataType::*;
+        let v = self.find_map(|(t, v)| match ResponseDataType::try_from(t) {
+            Ok(CompleteLocalName | ShortLocalName) => Some(v),
+            _ => None,
+        })?;
+        std::str::from_utf8(v).ok()
+    }
+
+    /// Returns an iterator over all 16- and 128-bit service UUIDs
+    /// (\[CSS\] Part A, Section 1.1).
+    pub fn services(self) -> impl Iterator<Item = Uuid> + 'a {
+        use ResponseDataType::*;
+        self.filter_map(|(t, v)| match ResponseDataType::try_from(t) {
+            Ok(IncompleteServiceClass16 | CompleteServiceClass16) => Some(v.chunks_exact(2)),
+            Ok(IncompleteServiceClass128 | CompleteServiceClass128) => Some(v.chunks_exact(16)),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(Uuid::from_le_bytes)
+    }
+
+    /// Returns the data of the first field of the specified type.
+    #[inline]
+    fn find_type(mut self, typ: ResponseDataType) -> Option<&'a [u8]> {
+        let typ = u8::from(typ);
+        self.find_map(|(t, v)| (t == typ).then_some(v))
+    }
+}
+
+impl<'a> Iterator for ResponseDataIter<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&n, tail) = self.0.split_first()?;
+        let n = usize::from(n);
+        if n == 0 || tail.len() < n {
+            self.0 = &[];
+            return None;
+        }
+        let (v, tail) = tail.split_at(n);
+        self.0 = tail;
+        Some((v[0], &v[1..]))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::le::RawAddr;
     use crate::sdp::ServiceClass;
 
     use super::*;
@@ -254,4 +414,305 @@ mod tests {
         ];
         assert_eq!(ad.get().as_ref(), want);
     }
+
+    #[test]
+    fn le_sc_oob() {
+        let sk = burble_crypto::SecretKey::new();
+        let oob = OobData::new(&sk);
+        let addr = Addr::Random(crate::le::RawAddr::from_le_bytes([1, 2, 3, 4, 5, 0xC6]));
+        let mut ad = ResponseDataMut::new();
+        ad.le_sc_oob(addr, LeRole::OnlyPeripheral, &oob);
+        let ad = ad.get();
+        let mut want = StructBuf::new(64);
+        want.append()
+            .put([0x08, 0x1B, 1, 2, 3, 4, 5, 0xC6, 0x01, 0x02, 0x1C, 0x00]);
+        oob.confirm.pack(want.append().put([0x11, 0x22]));
+        oob.r.pack(want.append().put([0x11, 0x23]));
+        assert_eq!(ad.as_ref(), want.as_ref());
+    }
+
+    #[test]
+    fn appearance_filter() {
+        let watches: Vec<_> = Appearance::iter_category(Appearance::GenericWatch).collect();
+        assert_eq!(
+            watches,
+            [
+                Appearance::GenericWatch,
+                Appearance::SportsWatch,
+                Appearance::Smartwatch
+            ]
+        );
+        let is_watch = |ad: &[u8]| {
+            (ResponseDataIter::new(ad).appearance()).map_or(false, |a| watches.contains(&a))
+        };
+        let mut ad = ResponseDataMut::new();
+        ad.flags(AdvFlag::LE_GENERAL)
+            .appearance(Appearance::SportsWatch);
+        assert!(is_watch(ad.get().as_ref()));
+        let mut ad = ResponseDataMut::new();
+        ad.appearance(Appearance::Laptop);
+        assert!(!is_watch(ad.get().as_ref()));
+        assert!(!is_watch(&[0x02, 0x19, 0xC1]));
+    }
+
+    #[test]
+    fn le_role() {
+        for role in enum_iterator::all::<LeRole>() {
+            let mut ad = ResponseDataMut::new();
+            ad.flags(AdvFlag::LE_GENERAL).le_role(role);
+            let ad = ad.get();
+            assert_eq!(&ad.as_ref()[3..], &[0x02, 0x1C, u8::from(role)]);
+            assert_eq!(ResponseDataIter::new(ad.as_ref()).le_role(), Some(role));
+        }
+        assert_eq!(ResponseDataIter::new(&[0x02, 0x1C, 0x04]).le_role(), None);
+        assert_eq!(ResponseDataIter::new(&[0x03, 0x1C, 0x00]).le_role(), None);
+    }
+
+    #[test]
+    fn local_name_services() {
+        let uuid128 = Uuid::new(0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0).unwrap();
+        let mut ad = ResponseDataMut::new();
+        (ad.flags(AdvFlag::LE_GENERAL))
+            .service(true, [Service::Battery.into(), uuid128])
+            .local_name(false, "Burb");
+        let ad = ad.get();
+        let it = ResponseDataIter::new(ad.as_ref());
+        assert_eq!(it.local_name(), Some("Burb"));
+        let have: Vec<Uuid> = it.services().collect();
+        assert_eq!(have, [Service::Battery.into(), uuid128]);
+        assert_eq!(
+            ResponseDataIter::new(&[0x02, 0x09, 0xFF]).local_name(),
+            None
+        );
+    }
+
+    /// Encodes randomly generated field sequences and verifies that they are
+    /// decoded identically, including after truncating the final field.
+    #[test]
+    fn round_trip() {
+        let mut r = Rng(0x853C_49E6_748F_EA9B);
+        for _ in 0..1000 {
+            let fields: Vec<_> = (0..=r.below(8)).map(|_| Field::random(&mut r)).collect();
+            let mut ad = ResponseDataMut::new();
+            let mut want = Vec::new();
+            for f in &fields {
+                f.put(&mut ad);
+                want.extend(f.want());
+            }
+            let ad = ad.get();
+            assert_eq!(decode(ad.as_ref()), want, "{fields:?}");
+
+            // Zero padding terminates iteration
+            let mut v = ad.to_vec();
+            v.resize(254, 0);
+            assert_eq!(decode(&v), want, "{fields:?}");
+
+            // Truncated final field is skipped
+            if let Some(&(_, ref last)) = want.last() {
+                v.truncate(ad.len() - 1 - r.below(last.len() + 1));
+                let mut it = ResponseDataIter::new(&v);
+                let have: Vec<_> = (it.by_ref()).map(|(t, v)| (t, v.to_vec())).collect();
+                assert_eq!(have, want[..want.len() - 1], "{fields:?}");
+                assert_eq!(it.next(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn legacy() {
+        let mut ad = ResponseDataMut::legacy();
+        ad.flags(AdvFlag::LE_GENERAL)
+            .local_name(true, "01234567890123456789012345");
+        assert_eq!(ad.get().len(), 31);
+    }
+
+    #[test]
+    fn legacy_overflow() {
+        let mut ad = ResponseDataMut::legacy();
+        ad.flags(AdvFlag::LE_GENERAL)
+            .local_name(true, "012345678901234567890123");
+        let r = ad.try_append(|ad| ad.tx_power(TxPower::new(0)));
+        assert_eq!(r.err(), Some(ResponseDataOverflow));
+        assert_eq!(ad.structs().count(), 2);
+        ad.try_append(|ad| ad.local_name(false, "")).unwrap();
+        assert_eq!(ad.get().len(), 31);
+    }
+
+    /// Response data field generated by [`Field::random`].
+    #[derive(Debug)]
+    enum Field {
+        Service(bool, Vec<Uuid>),
+        LocalName(bool, String),
+        Flags(AdvFlag),
+        ManufacturerData(u16, Vec<u8>),
+        TxPower(TxPower),
+        ConnInterval(Option<u16>, Option<u16>),
+        Appearance(Appearance),
+        AdvInterval(u32),
+        LeDeviceAddr(Addr),
+        LeRole(LeRole),
+    }
+
+    impl Field {
+        /// Returns a random field.
+        fn random(r: &mut Rng) -> Self {
+            let uuids = [
+                Uuid::from(Service::GenericAccess),
+                Uuid::from(Service::Battery),
+                Uuid::from(Service::HeartRate),
+                Uuid::new(0x1234_5678_0000_1000_8000_0080_5F9B_34FB).unwrap(),
+                Uuid::new(0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0).unwrap(),
+            ];
+            match r.below(10) {
+                0 => {
+                    let complete = r.bool();
+                    let v = (0..r.below(4)).map(|_| uuids[r.below(uuids.len())]);
+                    Self::Service(complete, v.collect())
+                }
+                1 => {
+                    let complete = r.bool();
+                    let v = (0..r.below(20)).map(|_| char::from(b'a' + r.u8() % 26));
+                    Self::LocalName(complete, v.collect())
+                }
+                2 => Self::Flags(AdvFlag::from_bits_truncate(r.u8())),
+                3 => {
+                    let v = (0..r.below(20)).map(|_| r.u8()).collect();
+                    Self::ManufacturerData(u16::from_le_bytes([r.u8(), r.u8()]), v)
+                }
+                4 => Self::TxPower(TxPower::new(i8::from_le_bytes([r.u8()]))),
+                5 => {
+                    let mut t = || r.bool().then(|| u16::try_from(6 + r.below(3195)).unwrap());
+                    Self::ConnInterval(t(), t())
+                }
+                6 => {
+                    let n = enum_iterator::cardinality::<Appearance>();
+                    Self::Appearance(enum_iterator::all().nth(r.below(n)).unwrap())
+                }
+                7 => {
+                    let t = [0x20, 0x1_0000, 0x100_0000][r.below(3)] + r.below(0x1_0000);
+                    Self::AdvInterval(u32::try_from(t).unwrap())
+                }
+                8 => {
+                    let a = RawAddr::from_le_bytes([(); 6].map(|_| r.u8()));
+                    let a = if r.bool() {
+                        Addr::Random(a)
+                    } else {
+                        Addr::Public(a)
+                    };
+                    Self::LeDeviceAddr(a)
+                }
+                _ => {
+                    let n = enum_iterator::cardinality::<LeRole>();
+                    Self::LeRole(enum_iterator::all().nth(r.below(n)).unwrap())
+                }
+            }
+        }
+
+        /// Appends the field to `ad`.
+        fn put(&self, ad: &mut ResponseDataMut) {
+            let d = |t: u16| Duration::from_micros(u64::from(t) * 1250);
+            match *self {
+                Self::Service(complete, ref v) => ad.service(complete, v),
+                Self::LocalName(complete, ref v) => ad.local_name(complete, v),
+                Self::Flags(v) => ad.flags(v),
+                Self::ManufacturerData(id, ref v) => ad.manufacturer_data(id, v),
+                Self::TxPower(v) => ad.tx_power(v),
+                Self::ConnInterval(min, max) => {
+                    ad.peripheral_connection_interval(min.map(d), max.map(d))
+                }
+                Self::Appearance(v) => ad.appearance(v),
+                Self::AdvInterval(t) => ad.adv_interval(Duration::from_micros(u64::from(t) * 625)),
+                Self::LeDeviceAddr(v) => ad.le_device_addr(v),
+                Self::LeRole(v) => ad.le_role(v),
+            };
+        }
+
+        /// Returns the expected type and data of each encoded field.
+        fn want(&self) -> Vec<(u8, Vec<u8>)> {
+            use ResponseDataType as T;
+            let one = |t: T, v: &[u8]| vec![(u8::from(t), v.to_vec())];
+            match *self {
+                Self::Service(complete, ref v) => {
+                    let (mut u16s, mut u32s, mut u128s) = (Vec::new(), Vec::new(), Vec::new());
+                    for &u in v {
+                        if let Some(u) = u.as_u16() {
+                            if !matches!(u, 0x1800 | 0x1801) {
+                                u16s.extend(u.to_le_bytes());
+                            }
+                        } else if let Some(u) = u.as_u32() {
+                            u32s.extend(u.to_le_bytes());
+                        } else if let Some(u) = u.as_u128() {
+                            u128s.extend(u.to_le_bytes());
+                        }
+                    }
+                    let typ = u8::from(if complete {
+                        T::CompleteServiceClass16
+                    } else {
+                        T::IncompleteServiceClass16
+                    });
+                    let mut want = Vec::new();
+                    if complete || !u16s.is_empty() {
+                        want.push((typ, u16s));
+                    }
+                    for (typ, v) in [(typ + 2, u32s), (typ + 4, u128s)] {
+                        if !v.is_empty() {
+                            want.push((typ, v));
+                        }
+                    }
+                    want
+                }
+                Self::LocalName(true, ref v) => one(T::CompleteLocalName, v.as_bytes()),
+                Self::LocalName(false, ref v) => one(T::ShortLocalName, v.as_bytes()),
+                Self::Flags(v) => one(T::Flags, &[v.bits()]),
+                Self::ManufacturerData(id, ref v) => {
+                    one(T::ManufacturerData, &[&id.to_le_bytes()[..], v].concat())
+                }
+                Self::TxPower(v) => one(T::TxPower, &i8::from(v).to_le_bytes()),
+                Self::ConnInterval(min, max) => {
+                    let (min, max) = (min.unwrap_or(u16::MAX), max.unwrap_or(u16::MAX));
+                    let v = [min.to_le_bytes(), max.to_le_bytes()].concat();
+                    one(T::PeripheralConnectionIntervalRange, &v)
+                }
+                Self::Appearance(v) => one(T::Appearance, &u16::from(v).to_le_bytes()),
+                Self::AdvInterval(t) => match t.to_le_bytes() {
+                    [ref v @ .., 0, 0] => one(T::AdvInterval, v),
+                    [ref v @ .., 0] => one(T::AdvIntervalLong, v),
+                    ref v => one(T::AdvIntervalLong, v),
+                },
+                Self::LeDeviceAddr(v) => {
+                    let typ = u8::from(v.is_random());
+                    one(T::LeDeviceAddr, &[v.raw().as_ref(), &[typ]].concat())
+                }
+                Self::LeRole(v) => one(T::LeRole, &[u8::from(v)]),
+            }
+        }
+    }
+
+    /// Xorshift PRNG for reproducible test cases.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            usize::try_from(self.next() % n as u64).unwrap()
+        }
+
+        fn u8(&mut self) -> u8 {
+            self.next().to_le_bytes()[0]
+        }
+
+        fn bool(&mut self) -> bool {
+            self.next() & 1 != 0
+        }
+    }
+
+    fn decode(v: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        (ResponseDataIter::new(v).map(|(t, v)| (t, v.to_vec()))).collect()
+    }
 }
@@ -0,0 +1,327 @@
+use tracing::{debug, Instrument};
+
+use burble_crypto::CSRK;
+
+use crate::gap::Uuid;
+
+use super::*;
+
+/// GATT client ([Vol 3] Part G, Section 4).
+#[derive(Debug)]
+pub struct Client {
+    br: Bearer,
+}
+
+impl Client {
+    /// Creates a GATT client that uses the specified ATT bearer.
+    #[inline(always)]
+    #[must_use]
+    pub const fn new(br: Bearer) -> Self {
+        Self { br }
+    }
+
+    /// Returns the ATT bearer.
+    #[inline(always)]
+    pub fn bearer(&mut self) -> &mut Bearer {
+        &mut self.br
+    }
+
+    /// Discovers all primary services of the server
+    /// ([Vol 3] Part G, Section 4.4.1).
+    ///
+    /// Servers with many services require multiple `ATT_READ_BY_GROUP_TYPE_REQ`
+    /// transactions. Each subsequent request starts after the end group handle
+    /// of the last service in the previous response. Discovery completes when
+    /// the end group handle is `0xFFFF` or when the server responds with
+    /// `ATT_ERROR_RSP` containing [`ErrorCode::AttributeNotFound`]. The latter
+    /// is the normal termination condition and is not returned as an error.
+    pub async fn discover_primary_services(&mut self) -> Result<Vec<ServiceInfo>> {
+        let span = self.span("discover_primary_services");
+        let br = &mut self.br;
+        async move {
+            let mut d = ServiceDiscovery::new();
+            while let Some(start) = d.next {
+                let hdls = HandleRange::new(start, Handle::MAX);
+                let req = br.read_by_group_type_req(hdls, Declaration::PrimaryService);
+                match br.exec(req).await {
+                    Ok(rsp) => d.add(start, rsp.read_by_group_type_rsp()?)?,
+                    Err(Error::Att(e)) if e.code() == ErrorCode::AttributeNotFound => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            debug!("Discovered {} primary service(s)", d.services.len());
+            Ok(d.services)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Reads a characteristic value or descriptor of any length using the
+    /// "Read Long Characteristic Values" and "Read Long Characteristic
+    /// Descriptors" sub-procedures ([Vol 3] Part G, Section 4.8.3 and
+    /// 4.12.2). The first part is read with `ATT_READ_REQ` and the rest with
+    /// `ATT_READ_BLOB_REQ` until a response is shorter than `ATT_MTU - 1`
+    /// bytes.
+    pub async fn read_long(&mut self, hdl: Handle) -> Result<Vec<u8>> {
+        let span = self.span("read_long");
+        let br = &mut self.br;
+        async move {
+            let max = usize::from(br.mtu()) - 1;
+            let req = br.read_req(hdl);
+            let rsp = br.exec(req).await?;
+            let mut v = rsp.read_rsp()?.to_vec();
+            let mut n = v.len();
+            while n == max && v.len() < MAX_VAL_LEN {
+                let off = u16::try_from(v.len()).unwrap();
+                let req = br.read_blob_req(hdl, off);
+                let rsp = match br.exec(req).await {
+                    Ok(rsp) => rsp,
+                    Err(Error::Att(e)) if e.code() == ErrorCode::AttributeNotLong => break,
+                    Err(e) => return Err(e),
+                };
+                let blob = rsp.read_rsp()?;
+                n = blob.len();
+                v.extend_from_slice(blob);
+            }
+            debug!("Read {} byte(s) from {hdl:?}", v.len());
+            Ok(v)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Writes a characteristic value or descriptor of any length using the
+    /// "Write Long Characteristic Values" and "Write Long Characteristic
+    /// Descriptors" sub-procedures ([Vol 3] Part G, Section 4.9.4 and
+    /// 4.12.4). The value is written in `ATT_MTU - 5` byte parts. The write is
+    /// cancelled if the server does not echo a part correctly.
+    pub async fn write_long(&mut self, hdl: Handle, v: &[u8]) -> Result<()> {
+        if v.len() > MAX_VAL_LEN {
+            let err = ErrorCode::InvalidAttributeValueLength;
+            return Err(ErrorRsp::new(Opcode::PrepareWriteReq.into(), Some(hdl), err).into());
+        }
+        let span = self.span("write_long");
+        let br = &mut self.br;
+        async move {
+            let max = usize::from(br.mtu()) - 5;
+            for (i, part) in v.chunks(max).enumerate() {
+                let off = u16::try_from(i * max).unwrap();
+                let req = br.prepare_write_req(hdl, off, part);
+                let rsp = br.exec(req).await?;
+                if rsp.prepare_write_rsp()? != (hdl, off, part) {
+                    let req = br.execute_write_req(false);
+                    br.exec(req).await?;
+                    let err = Opcode::PrepareWriteRsp.hdl_err(ErrorCode::InvalidPdu, hdl);
+                    return err.map_err(Error::from);
+                }
+            }
+            let req = br.execute_write_req(true);
+            br.exec(req).await?;
+            debug!("Wrote {} byte(s) to {hdl:?}", v.len());
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Writes a characteristic value using the "Signed Write Without Response"
+    /// sub-procedure ([Vol 3] Part G, Section 4.9.2). The value is signed with
+    /// the local CSRK and sign counter `ctr`, which is incremented after the
+    /// command is sent ([Vol 3] Part H, Section 2.4.5).
+    ///
+    /// This sub-procedure must only be used on an unencrypted connection. An
+    /// encrypted connection should use an unsigned write instead
+    /// ([Vol 3] Part C, Section 10.4.1).
+    pub async fn signed_write_command(
+        &mut self,
+        hdl: Handle,
+        v: &[u8],
+        csrk: &CSRK,
+        ctr: &mut u32,
+    ) -> Result<()> {
+        // Opcode, handle, and signature
+        let max = usize::from(self.br.mtu()) - 3 - SIGNATURE_LEN;
+        if v.len() > max {
+            let err = ErrorCode::InvalidAttributeValueLength;
+            return Err(ErrorRsp::new(Opcode::SignedWriteCmd.into(), Some(hdl), err).into());
+        }
+        let span = self.span("signed_write_command");
+        (self.br.signed_write_cmd(hdl, v, csrk, *ctr))
+            .instrument(span)
+            .await?;
+        *ctr = ctr.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Returns a new GATT procedure span within the connection span.
+    fn span(&self, procedure: &'static str) -> tracing::Span {
+        tracing::debug_span!(parent: &self.br.conn().borrow().span, "gatt", procedure)
+    }
+}
+
+/// Service discovered on the server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ServiceInfo {
+    /// Service declaration and end group handles.
+    pub hdls: HandleRange,
+    /// Service UUID.
+    pub uuid: Uuid,
+}
+
+/// Service discovery state.
+#[derive(Debug)]
+struct ServiceDiscovery {
+    next: Option<Handle>,
+    services: Vec<ServiceInfo>,
+}
+
+impl ServiceDiscovery {
+    /// Creates a new discovery procedure starting at the first handle.
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            next: Some(Handle::MIN),
+            services: Vec::new(),
+        }
+    }
+
+    /// Adds services from a response to a request that started at handle
+    /// `start` and updates the start handle for the next request. Returns an
+    /// error if the response is empty, contains an invalid UUID, or does not
+    /// contain services in increasing handle order.
+    #[allow(single_use_lifetimes)]
+    fn add<'a>(
+        &mut self,
+        start: Handle,
+        groups: impl Iterator<Item = (HandleRange, &'a [u8])>,
+    ) -> RspResult<()> {
+        let n = self.services.len();
+        let mut next = Some(start);
+        for (hdls, uuid) in groups {
+            let (Some(uuid), Some(min)) = (Uuid::from_le_bytes(uuid), next) else {
+                return Opcode::ReadByGroupTypeRsp.err(ErrorCode::InvalidPdu);
+            };
+            if hdls.start() < min {
+                return Opcode::ReadByGroupTypeRsp.err(ErrorCode::InvalidPdu);
+            }
+            self.services.push(ServiceInfo { hdls, uuid });
+            next = hdls.end().next();
+        }
+        if self.services.len() == n {
+            return Opcode::ReadByGroupTypeRsp.err(ErrorCode::InvalidPdu);
+        }
+        self.next = next;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mock server that returns at most as many services as would fit in an
+    /// `ATT_READ_BY_GROUP_TYPE_RSP` PDU with the specified MTU.
+    struct MockServer {
+        mtu: usize,
+        services: Vec<(HandleRange, Vec<u8>)>,
+    }
+
+    impl MockServer {
+        fn new(mtu: usize, services: &[(u16, u16, Uuid)]) -> Self {
+            let services = (services.iter())
+                .map(|&(start, end, uuid)| {
+                    let hdls = HandleRange::new(hdl(start), hdl(end));
+                    let v = uuid.as_uuid16().map_or_else(
+                        || u128::from(uuid).to_le_bytes().to_vec(),
+                        |u| u16::from(u).to_le_bytes().to_vec(),
+                    );
+                    (hdls, v)
+                })
+                .collect();
+            Self { mtu, services }
+        }
+
+        /// Returns the response to a request starting at `start` or [`None`]
+        /// for `ATT_ERROR_RSP(AttributeNotFound)`.
+        fn rsp(&self, start: Handle) -> Option<Vec<(HandleRange, &[u8])>> {
+            let mut it = (self.services.iter()).filter(|&&(hdls, _)| start <= hdls.start());
+            let &(hdls, ref v) = it.next()?;
+            let max = (self.mtu - 2) / (4 + v.len());
+            let same_len = it.take_while(|&&(_, ref u)| u.len() == v.len());
+            let rsp = Some((hdls, v.as_slice())).into_iter();
+            let rsp = rsp.chain(same_len.map(|&(h, ref u)| (h, u.as_slice())));
+            Some(rsp.take(max).collect())
+        }
+
+        /// Performs discovery and returns discovered services and the number
+        /// of requests.
+        fn discover(&self) -> (Vec<ServiceInfo>, usize) {
+            let mut d = ServiceDiscovery::new();
+            let mut reqs = 0;
+            while let Some(start) = d.next {
+                reqs += 1;
+                let Some(rsp) = self.rsp(start) else { break };
+                d.add(start, rsp.into_iter()).unwrap();
+            }
+            (d.services, reqs)
+        }
+    }
+
+    #[test]
+    fn discover_primary_services() {
+        use burble_const::Service::*;
+        let uuid128 = Uuid::new(0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0).unwrap();
+        let want = [
+            (0x0001, 0x0005, GenericAccess.into()),
+            (0x0006, 0x000D, GenericAttribute.into()),
+            (0x000E, 0x0013, Glucose.into()),
+            (0x0014, 0x0016, Battery.into()),
+            (0x0017, 0x0020, DeviceInformation.into()),
+            (0x0021, 0x0030, uuid128),
+            (0x0031, 0x0031, HeartRate.into()),
+            (0x0040, 0x0045, CurrentTime.into()),
+            (0x0050, 0x0060, uuid128),
+            (0x0061, 0x0070, HumanInterfaceDevice.into()),
+        ];
+        let srv = MockServer::new(23, &want);
+        let (have, reqs) = srv.discover();
+        assert_eq!(have.len(), 10);
+        for (s, &(start, end, uuid)) in have.iter().zip(want.iter()) {
+            assert_eq!(s.hdls, HandleRange::new(hdl(start), hdl(end)));
+            assert_eq!(s.uuid, uuid);
+        }
+        // 3 + 2 + 1 + 2 + 1 + 1 services, then AttributeNotFound
+        assert_eq!(reqs, 7);
+
+        // Discovery stops without another request after handle 0xFFFF
+        let srv = MockServer::new(
+            23,
+            &[
+                (0x0001, 0x0005, GenericAccess.into()),
+                (0x0006, 0xFFFF, Battery.into()),
+            ],
+        );
+        let (have, reqs) = srv.discover();
+        assert_eq!(have.len(), 2);
+        assert_eq!(reqs, 1);
+    }
+
+    #[test]
+    fn invalid_rsp() {
+        let mut d = ServiceDiscovery::new();
+        assert!(d.add(Handle::MIN, [].into_iter()).is_err());
+
+        let v: &[u8] = &[0x0F, 0x18];
+        let g = (HandleRange::new(hdl(0x10), hdl(0x20)), v);
+        d.add(Handle::MIN, [g].into_iter()).unwrap();
+        assert_eq!(d.next, Some(hdl(0x21)));
+        assert!(d.add(hdl(0x21), [g].into_iter()).is_err());
+        let g = (HandleRange::new(hdl(0x21), hdl(0x22)), &[0x0F_u8][..]);
+        assert!(d.add(hdl(0x21), [g].into_iter()).is_err());
+    }
+
+    fn hdl(h: u16) -> Handle {
+        Handle::new(h).unwrap()
+    }
+}
@@ -84,9 +84,13 @@ impl Builder<Db> {
                 break; // Only one instance is allowed
             }
         }
+        let chars = self.index_chars();
+        let labels = (self.0.labels.iter()).map(|&(hdl, _, label)| (hdl, label));
         (
             Db {
                 attr: self.0.attr.into_boxed_slice(),
+                chars: chars.into_boxed_slice(),
+                labels: labels.collect(),
                 data: self.0.data.into_boxed_slice(),
             },
             IoMap(self.0.io),
@@ -129,6 +133,28 @@ impl Builder<Db> {
         self.0.flag.insert(Bld::MORPH);
     }
 
+    /// Labels the next service to identify it among multiple instances of the
+    /// same service, such as two Battery services for a device and its case.
+    /// The label selects the instance in [`Server::find_characteristic`] and
+    /// [`Server::notify`] via [`ServiceInstance::labeled`].
+    ///
+    /// Labeled instances of a service must be defined in ascending label
+    /// order. This keeps the client-facing order of the instances and their
+    /// handles tied to the labels, so instances can't swap handles between
+    /// restarts if the application defines them in a different order.
+    /// Instances should also be distinguishable by the client, e.g. via
+    /// Characteristic Presentation Format descriptors with different
+    /// descriptions.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the service is defined if the label is not greater than the
+    /// labels of all previous instances.
+    #[inline]
+    pub fn label_next(&mut self, label: &'static str) {
+        self.0.label = Some(label);
+    }
+
     /// Declares a primary or secondary service and any included services
     /// ([Vol 3] Part G, Section 3.2).
     fn service(&mut self, typ: Declaration, uuid: Uuid, include: &[Handle]) -> Handle {
@@ -139,7 +165,20 @@ impl Builder<Db> {
                 "only one instance of the {s} service is allowed"
             );
         }
+        let label = self.0.label.take();
+        if let Some(label) = label {
+            let prev = (self.labels.iter()).rfind(|&&(_, u, _)| u == uuid);
+            if let Some(&(_, _, prev)) = prev {
+                assert!(
+                    prev < label,
+                    "{uuid} service instance {label:?} must be defined before {prev:?}"
+                );
+            }
+        }
         let hdl = self.decl(typ, |v| v.uuid(uuid));
+        if let Some(label) = label {
+            self.labels.push((hdl, uuid, label));
+        }
         for &inc in include {
             let s = self.service_group(inc).expect("invalid service handle");
             #[allow(clippy::collection_is_never_read)] // TODO: False positive?
@@ -404,6 +443,8 @@ pub struct DbBuilder {
     io: BTreeMap<Handle, Io>,
     flag: Bld,
     fmt: SmallVec<[Handle; 4]>,
+    labels: Vec<(Handle, Uuid, &'static str)>,
+    label: Option<&'static str>,
 }
 
 impl DbBuilder {
@@ -418,6 +459,7 @@ impl DbBuilder {
                 typ: Some(typ),
                 val,
                 perms: Perms::new(Access::READ),
+                chr: None,
             });
             hdl
         }
@@ -450,6 +492,7 @@ impl DbBuilder {
             typ,
             val: (i, i),
             perms,
+            chr: None,
         });
         hdl
     }
@@ -471,6 +514,41 @@ impl DbBuilder {
         (start, end)
     }
 
+    /// Links every attribute within a characteristic definition to its
+    /// characteristic information, avoiding attribute scans during access
+    /// checks. Returns the information for all characteristics in handle order.
+    fn index_chars(&mut self) -> Vec<CharInfo> {
+        use private::Group;
+        let mut chars = Vec::<CharInfo>::new();
+        let mut chr = None;
+        for i in 0..self.attr.len() {
+            let at = self.attr[i];
+            if at.is_char() {
+                let v = self.value(&at);
+                let props = Prop::from_bits_retain(v.unpack().u8());
+                chars.push(CharInfo {
+                    props,
+                    ext_props: props.contains(Prop::EXT_PROPS).then(ExtProp::empty),
+                    vhdl: value_handle(v),
+                    uuid: Uuid::from_le_bytes(&v[CharacteristicDef::UUID_OFF..])
+                        .expect("invalid characteristic"),
+                });
+                chr = Some(chars.len() - 1);
+            } else if CharacteristicDef::is_next_group(at.typ) {
+                chr = None;
+            } else if let Some(ch) = chr.map(|i| &mut chars[i]) {
+                if at.is_ext_props() && at.hdl > ch.vhdl {
+                    let p = ExtProp::from_bits_truncate(self.value(&at).unpack().u16());
+                    ch.ext_props = ch.ext_props.map(|_| p);
+                }
+            }
+            self.attr[i].chr = chr.map(|i| {
+                Idx::try_from(i).expect("too many characteristics (see Idx type in gatt/db.rs)")
+            });
+        }
+        chars
+    }
+
     /// Returns a new builder.
     #[inline(always)]
     fn builder<T>(&mut self) -> &mut Builder<T> {
@@ -564,6 +642,34 @@ mod tests {
         assert!(it.next().is_none());
     }
 
+    #[test]
+    fn secondary_services() {
+        use Service::*;
+        let s = appendix_b();
+
+        let mut it = s.secondary_services(Handle::MIN, None);
+        group_eq(it.next(), 0x0014, 0x0016, Battery);
+        assert!(it.next().is_none());
+        drop(it);
+
+        let mut it = s.secondary_services(Handle::new(0x0014).unwrap(), Some(Battery.into()));
+        group_eq(it.next(), 0x0014, 0x0016, Battery);
+        assert!(it.next().is_none());
+        drop(it);
+
+        let mut it = s.secondary_services(Handle::MIN, Some(Glucose.into()));
+        assert!(it.next().is_none());
+        drop(it);
+        let mut it = s.secondary_services(Handle::new(0x0015).unwrap(), None);
+        assert!(it.next().is_none());
+        drop(it);
+
+        // Primary service group ends before the secondary service declaration
+        let mut it = s.primary_services(Handle::new(0x000E).unwrap(), None);
+        group_eq(it.next(), 0x000E, 0x0013, Glucose);
+        assert!(it.next().is_none());
+    }
+
     #[test]
     fn characteristics() {
         use Characteristic::*;
@@ -617,6 +723,119 @@ mod tests {
         assert_eq!(v.uuid(), uuid.into().as_uuid());
     }
 
+    /// `ATT_WRITE_CMD` requires the `WRITE_CMD` characteristic property
+    /// ([Vol 3] Part G, Section 4.9.1).
+    #[test]
+    fn write_cmd_access() {
+        let mut db = Db::build();
+        db.primary_service(Service::HumanInterfaceDevice, [], |db| {
+            db.characteristic(
+                Characteristic::HidControlPoint,
+                Prop::WRITE_CMD,
+                Access::WRITE,
+                Io::NONE,
+                |_| {},
+            );
+            db.characteristic(
+                Characteristic::ProtocolMode,
+                Prop::READ | Prop::WRITE,
+                Access::READ_WRITE,
+                Io::NONE,
+                |_| {},
+            );
+        });
+        let (db, _) = db.freeze();
+        let sec = crate::hci::ConnSec::default();
+        let (cmd, req) = (Opcode::WriteCmd.request(sec), Opcode::WriteReq.request(sec));
+        let (h1, h2) = (Handle::new(0x0003).unwrap(), Handle::new(0x0005).unwrap());
+        assert_eq!(db.try_access(cmd, h1).unwrap(), h1);
+        let e = db.try_access(req, h1).unwrap_err();
+        assert_eq!(e.code(), ErrorCode::WriteNotPermitted);
+        assert_eq!(db.try_access(req, h2).unwrap(), h2);
+        let e = db.try_access(cmd, h2).unwrap_err();
+        assert_eq!(e.code(), ErrorCode::WriteNotPermitted);
+    }
+
+    /// Precomputed characteristic information matches the result of scanning
+    /// the attributes around each handle.
+    #[test]
+    fn char_info() {
+        for db in [appendix_b(), large_db()] {
+            for at in db.attr.iter() {
+                let (have, want) = (db.characteristic_for_attr(at), scan_char(&db, at));
+                assert_eq!(have.is_some(), want.is_some(), "{}", at.hdl);
+                let (Some(have), Some(want)) = (have, want) else { continue };
+                assert_eq!(have.props, want.props);
+                assert_eq!(
+                    have.ext_props.map(|p| p.bits()),
+                    want.ext_props.map(|p| p.bits())
+                );
+                assert_eq!((have.vhdl, have.uuid), (want.vhdl, want.uuid));
+            }
+        }
+        let db = appendix_b();
+        let ch = db.get_characteristic(Handle::new(0x0012).unwrap()).unwrap();
+        assert_eq!(ch.vhdl, Handle::new(0x0011).unwrap());
+        assert_eq!(ch.ext_props.map(|p| p.bits()), Some(0));
+        let svc = Handle::new(0x000E).unwrap();
+        assert!(db.get_characteristic(svc).is_none());
+    }
+
+    /// Returns characteristic information for `at` by scanning the attributes
+    /// around it, which is how it was determined before being precomputed.
+    fn scan_char(db: &Db, at: &Attr) -> Option<CharInfo> {
+        use private::Group;
+        let i = db.index(at);
+        let decl = db.attr[..=i].iter().rposition(Attr::is_char)?;
+        let end = (db.attr[decl + 1..].iter())
+            .position(|at| CharacteristicDef::is_next_group(at.typ))
+            .map_or(db.attr.len(), |j| decl + 1 + j);
+        if end <= i {
+            return None;
+        }
+        let dval = db.value(&db.attr[decl]);
+        let vhdl = value_handle(dval);
+        let val = (db.attr[decl + 1..end].iter())
+            .position(|at| at.hdl == vhdl)
+            .map(|j| decl + 1 + j)
+            .expect("invalid characteristic");
+        let props = Prop::from_bits_retain(dval[0]);
+        let ext_props = props.contains(Prop::EXT_PROPS).then(|| {
+            let desc = &db.attr[val + 1..end];
+            (desc.iter().find(|&at| Attr::is_ext_props(at))).map_or(ExtProp::empty(), |at| {
+                ExtProp::from_bits_truncate(db.value(at).unpack().u16())
+            })
+        });
+        Some(CharInfo {
+            props,
+            ext_props,
+            vhdl,
+            uuid: db.typ(&db.attr[val]),
+        })
+    }
+
+    /// Returns a database with 3 services, each containing 33 characteristics
+    /// with a CCCD, for a total of 300 attributes.
+    fn large_db() -> Db {
+        let mut db = Db::build();
+        for i in 0..3_u16 {
+            let uuid = Uuid::new(0x1234_5678_0000_1000_8000_0080_5F9B_34FB + u128::from(i));
+            db.primary_service(uuid.unwrap(), [], |db| {
+                for _ in 0..33 {
+                    db.characteristic(
+                        Characteristic::Report,
+                        Prop::READ | Prop::WRITE_CMD | Prop::NOTIFY,
+                        Access::READ_WRITE,
+                        Io::NONE,
+                        |db| db.cccd(Access::READ_WRITE),
+                    );
+                }
+            });
+        }
+        let (db, _) = db.freeze();
+        db
+    }
+
     fn appendix_b() -> Db {
         let mut db = Db::build();
         db.primary_service(Service::GenericAccess, [], |db| {
@@ -24,6 +24,10 @@ type Idx = u16;
 pub struct Db {
     /// Attribute metadata sorted by handle.
     attr: Box<[Attr]>,
+    /// Characteristic information referenced by [`Attr::chr`].
+    chars: Box<[CharInfo]>,
+    /// Service instance labels sorted by service handle.
+    labels: Box<[(Handle, &'static str)]>,
     /// Concatenated GATT profile attribute values and 128-bit UUIDs, ending
     /// with a 128-bit hash in little-endian byte order.
     data: Box<[u8]>,
@@ -67,7 +71,7 @@ impl Db {
     /// any characteristic.
     #[inline]
     #[must_use]
-    pub(super) fn get_characteristic(&self, hdl: Handle) -> Option<CharInfo> {
+    pub(super) fn get_characteristic(&self, hdl: Handle) -> Option<&CharInfo> {
         (self.try_get(hdl).ok()).and_then(|at| self.characteristic_for_attr(at))
     }
 
@@ -79,12 +83,27 @@ impl Db {
         start: Handle,
         uuid: Option<Uuid>,
     ) -> impl Iterator<Item = DbEntry<ServiceDef>> {
-        let i = self.try_get(start).map_or_else(|i| i, |at| self.index(at));
-        let uuid = uuid.map_or_else(UuidVec::default, Uuid::to_vec);
-        // SAFETY: 0 <= i <= self.attr.len()
-        GroupIter::new(self, unsafe { self.attr.get_unchecked(i..) }, move |at| {
-            at.is_primary_service() && (uuid.is_empty() || self.value(at) == uuid.as_ref())
-        })
+        self.services(start, uuid, Attr::is_primary_service)
+    }
+
+    /// Returns an iterator over secondary services with optional UUID
+    /// matching. Secondary services are only discoverable via the include
+    /// declarations that reference them ([Vol 3] Part G, Section 3.1).
+    #[inline]
+    pub fn secondary_services(
+        &self,
+        start: Handle,
+        uuid: Option<Uuid>,
+    ) -> impl Iterator<Item = DbEntry<ServiceDef>> {
+        self.services(start, uuid, Attr::is_secondary_service)
+    }
+
+    /// Returns the label of the service instance declared at handle `hdl`
+    /// (see [`Builder::label_next`]).
+    #[inline]
+    #[must_use]
+    pub fn service_label(&self, hdl: Handle) -> Option<&'static str> {
+        (self.labels.binary_search_by_key(&hdl, |&(h, _)| h).ok()).map(|i| self.labels[i].1)
     }
 
     /// Returns an iterator over service includes
@@ -167,7 +186,8 @@ impl Db {
         Ok(it.map_while(RspResult::ok).collect())
     }
 
-    /// Logs database contents at `debug` level.
+    /// Logs database contents at `debug` level, including the permissions of
+    /// characteristic values and descriptors.
     ///
     /// # Panics
     ///
@@ -216,21 +236,41 @@ impl Db {
                         }
                         _ => unreachable!(),
                     },
-                    UuidType::Characteristic(_) => log!(at, "{cont}   |__ [Value <{uuid16:?}>]"),
-                    UuidType::Descriptor(_) => log!(at, "{cont}   |__ {uuid16} <{uuid16:?}>"),
+                    UuidType::Characteristic(_) => {
+                        log!(at, "{cont}   |__ [Value <{uuid16:?}>] ({})", at.perms);
+                    }
+                    UuidType::Descriptor(_) => {
+                        log!(at, "{cont}   |__ {uuid16} <{uuid16:?}> ({})", at.perms);
+                    }
                     typ => log!(at, "Unexpected {typ}"),
                 }
             } else {
                 let uuid = self.typ(at);
                 if at.hdl <= vhdl {
-                    log!(at, "{cont}   |__ [Value <{uuid:?}>]");
+                    log!(at, "{cont}   |__ [Value <{uuid:?}>] ({})", at.perms);
                 } else {
-                    log!(at, "{cont}   |__ Descriptor <{uuid:?}>");
+                    log!(at, "{cont}   |__ Descriptor <{uuid:?}> ({})", at.perms);
                 }
             }
         }
     }
 
+    /// Returns an iterator over services for which `is_type` returns `true`,
+    /// starting at handle `start`, with optional UUID matching.
+    fn services(
+        &self,
+        start: Handle,
+        uuid: Option<Uuid>,
+        is_type: fn(&Attr) -> bool,
+    ) -> impl Iterator<Item = DbEntry<ServiceDef>> {
+        let i = self.try_get(start).map_or_else(|i| i, |at| self.index(at));
+        let uuid = uuid.map_or_else(UuidVec::default, Uuid::to_vec);
+        // SAFETY: 0 <= i <= self.attr.len()
+        GroupIter::new(self, unsafe { self.attr.get_unchecked(i..) }, move |at| {
+            is_type(at) && (uuid.is_empty() || self.value(at) == uuid.as_ref())
+        })
+    }
+
     /// Returns a subset of attributes for one service. The service declaration
     /// is skipped.
     fn service_attrs(&self, hdls: HandleRange) -> &[Attr] {
@@ -311,44 +351,9 @@ impl Db {
     }
 
     /// Returns characteristic information for the specified attribute.
-    fn characteristic_for_attr(&self, at: &Attr) -> Option<CharInfo> {
-        use private::Group;
-        let i = self.index(at);
-        // SAFETY: 0 <= i < self.attr.len()
-        let decl = unsafe { self.attr.get_unchecked(..=i).iter() }.rposition(Attr::is_char)?;
-        // SAFETY: 0 <= decl <= i < self.attr.len()
-        let end = unsafe { self.attr.get_unchecked(decl + 1..).iter() }
-            .position(|at| CharacteristicDef::is_next_group(at.typ))
-            .map_or(self.attr.len(), |j| decl + 1 + j);
-        if end <= i {
-            return None; // hdl is not part of a characteristic definition
-        }
-        // SAFETY: 0 <= decl < self.attr.len()
-        let dval = self.value(unsafe { self.attr.get_unchecked(decl) });
-        let vhdl = value_handle(dval);
-        // SAFETY: 0 <= decl < end <= self.attr.len()
-        let val = unsafe { self.attr.get_unchecked(decl + 1..end).iter() }
-            .position(|at| at.hdl == vhdl)
-            .map(|j| decl + 1 + j)
-            .expect("invalid characteristic");
-        // SAFETY: 0 < val < end <= self.attr.len()
-        let desc = unsafe { self.attr.get_unchecked(val + 1..end) };
-        // SAFETY: A valid handle is at indices 1-2
-        let props = Prop::from_bits_retain(unsafe { *dval.get_unchecked(0) });
-        let ext_props = props.contains(Prop::EXT_PROPS).then(|| {
-            (desc.iter().find(|&at| Attr::is_ext_props(at))).map_or(ExtProp::empty(), |at| {
-                ExtProp::from_bits_truncate(self.value(at).unpack().u16())
-            })
-        });
-        // SAFETY: 0 < desc < val < end <= self.attr.len()
-        let at = unsafe { self.attr.get_unchecked(val) };
-        Some(CharInfo {
-            props,
-            ext_props,
-            vhdl: at.hdl,
-            uuid: self.typ(at),
-            _desc: desc,
-        })
+    #[inline]
+    fn characteristic_for_attr(&self, at: &Attr) -> Option<&CharInfo> {
+        at.chr.and_then(|i| self.chars.get(usize::from(i)))
     }
 
     /// Returns all attributes within the specified handle range or [`None`] if
@@ -552,19 +557,21 @@ impl<'a, T> AsRef<[u8]> for DbEntry<'a, T> {
     }
 }
 
-/// Information about a single characteristic.
+/// Information about a single characteristic, computed when the database is
+/// frozen.
 #[derive(Clone, Copy, Debug)]
-pub(super) struct CharInfo<'a> {
+pub(super) struct CharInfo {
     pub props: Prop,
     pub ext_props: Option<ExtProp>,
     pub vhdl: Handle,
     pub uuid: Uuid,
-    _desc: &'a [Attr], // TODO: Remove
 }
 
 /// Attribute entry. `val` contains start and end indices of the attribute value
 /// in the data array. If `typ` is [`None`], then the 128-bit UUID is stored at
-/// `val.0 - 16..val.0` in the data array.
+/// `val.0 - 16..val.0` in the data array. `chr` is the index of the
+/// characteristic information for any attribute within a characteristic
+/// definition.
 #[derive(Clone, Copy, Debug)]
 #[must_use]
 struct Attr {
@@ -572,6 +579,7 @@ struct Attr {
     typ: Option<Uuid16>,
     val: (Idx, Idx),
     perms: Perms,
+    chr: Option<Idx>,
 }
 
 impl Attr {
@@ -590,6 +598,12 @@ impl Attr {
         matches!(self.typ, Some(Declaration::PRIMARY_SERVICE))
     }
 
+    /// Returns whether the attribute is a secondary service declaration.
+    #[inline(always)]
+    const fn is_secondary_service(&self) -> bool {
+        matches!(self.typ, Some(Declaration::SECONDARY_SERVICE))
+    }
+
     /// Returns whether the attribute is an include declaration.
     #[inline(always)]
     const fn is_include(&self) -> bool {
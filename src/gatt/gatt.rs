@@ -6,12 +6,13 @@ use std::time::Duration;
 
 use tracing::{info, warn};
 
-pub use {consts::*, db::*, io::*, server::*};
+pub use {client::*, consts::*, db::*, io::*, server::*};
 
 use crate::att::*;
 use crate::le;
 use crate::smp::BondId;
 
+mod client;
 mod consts;
 #[path = "db/db.rs"]
 mod db;
@@ -21,10 +22,30 @@ mod server;
 /// Independent GATT service implementations.
 pub mod service {
     pub mod bas;
+    pub mod bcs;
+    pub mod bms;
+    mod cp;
+    pub mod cscs;
+    pub mod cts;
+    pub mod dfu;
     pub mod dis;
+    pub mod ess;
     pub mod gaps;
+    pub mod gls;
     #[cfg(feature = "hid")]
     pub mod hids;
+    pub mod hrs;
+    pub mod nus;
+    pub mod ots;
+    pub mod plx;
+    pub mod proximity;
+    pub mod racp;
+    pub mod rscs;
+    pub mod scps;
+    pub mod sfloat;
+    #[cfg(test)]
+    mod test;
+    pub mod wss;
 }
 
 /// Interface to persistent GATT cache storage.
@@ -66,7 +87,8 @@ impl Cache {
 #[serde(deny_unknown_fields)]
 struct ServiceChanged {
     /// Hash that reflects the current GATT service structure. It is similar to
-    /// db_hash, but includes all handles and a subset of GATT service values.
+    /// db_hash, but includes all handles, service instance labels, and a
+    /// subset of GATT service values.
     #[serde(with = "burble_crypto::u128ser")]
     hash: u128,
     /// Characteristic value handle.
@@ -91,6 +113,9 @@ impl ServiceChanged {
             }
             m.update(u16::from(hdl).to_le_bytes());
             m.update(u128::from(uuid).to_le_bytes());
+            if let Some(label) = db.service_label(hdl) {
+                m.update(label.as_bytes());
+            }
         }
         let vhdl = vhdl?;
         if u16::from(vhdl) != 3 {
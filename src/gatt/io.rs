@@ -6,6 +6,7 @@ use std::task::{ready, Context, Poll};
 
 use pin_project::{pin_project, pinned_drop};
 use structbuf::{Pack, Packer, StructBuf};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio_util::sync::WaitForCancellationFutureOwned;
 use tracing::debug;
 
@@ -178,6 +179,7 @@ impl ReadReq {
 /// Server characteristic or descriptor write request.
 #[derive(Debug)]
 pub struct WriteReq<'a> {
+    pub(super) peer: le::Addr,
     pub(super) op: Opcode,
     pub(super) hdl: Handle,
     pub(super) uuid: Uuid,
@@ -186,6 +188,13 @@ pub struct WriteReq<'a> {
 }
 
 impl<'a> WriteReq<'a> {
+    /// Returns the address of the peer that sent the request.
+    #[inline(always)]
+    #[must_use]
+    pub const fn peer(&self) -> le::Addr {
+        self.peer
+    }
+
     /// Returns the attribute handle.
     #[inline(always)]
     #[must_use]
@@ -274,6 +283,14 @@ impl NotifyReq {
         self.ind
     }
 
+    /// Returns the maximum value length that can be sent in a single
+    /// notification or indication.
+    #[inline(always)]
+    #[must_use]
+    pub const fn max_len(&self) -> usize {
+        self.mtu as usize - 3
+    }
+
     // TODO: Add a robust version of notifications that places a barrier in the
     // send queue and doesn't return until the PDU is acknowledged with
     // NumberOfCompletedPackets? We don't want to do this unconditionally
@@ -286,13 +303,17 @@ impl NotifyReq {
     /// buffer. For indications, the future resolves after confirmation is
     /// received.
     pub fn notify(&self, f: impl FnOnce(&mut Packer)) -> Notify {
-        let mut val = StructBuf::new(usize::from(self.mtu) - 3);
-        f(&mut val.append());
+        let val = ValuePdu::new(self.mtu, self.hdl, f);
         let (hdl, ind) = (self.hdl, self.ind);
         let ct = self.ct.clone().cancelled_owned();
         let (tx, rx) = tokio::sync::oneshot::channel();
         Notify {
-            val: Some(NotifyVal { hdl, val, ind, tx }),
+            val: Some(NotifyVal {
+                hdl,
+                val: Some(val),
+                ind,
+                tx,
+            }),
             // TODO: We don't want Notify to have a lifetime, but this allocates
             // a ReusableBoxFuture. That's ok for now since we also allocate
             // a oneshot channel.
@@ -302,6 +323,28 @@ impl NotifyReq {
         }
     }
 
+    /// Queues a notification or indication with the updated characteristic
+    /// value provided by `f` without waiting for space in the client's queue.
+    /// Returns `Ok(false)` if the queue is full and the value was dropped. The
+    /// result of the operation is not reported.
+    pub fn notify_dropping_if_full(&self, f: impl FnOnce(&mut Packer)) -> Result<bool> {
+        if self.ct.is_cancelled() {
+            return Err(Error::NotifyClosed);
+        }
+        let val = ValuePdu::new(self.mtu, self.hdl, f);
+        let (hdl, ind) = (self.hdl, self.ind);
+        let (tx, _) = tokio::sync::oneshot::channel();
+        let val = Some(val);
+        match self.tx.try_send(NotifyVal { hdl, val, ind, tx }) {
+            Ok(_) => Ok(true),
+            Err(TrySendError::Full(_)) => {
+                debug!("Notification queue full, dropping value for {hdl}");
+                Ok(false)
+            }
+            Err(TrySendError::Closed(_)) => Err(Error::NotifyClosed),
+        }
+    }
+
     /// Returns when the notification session is closed. This method is cancel
     /// safe.
     #[inline(always)]
@@ -378,26 +421,32 @@ impl PinnedDrop for Notify {
     }
 }
 
-/// Characteristic notification or indication value.
+/// Characteristic notification or indication value. The value is encoded
+/// directly into the outbound PDU to avoid copying it before it is sent.
 #[derive(Debug)]
 pub(super) struct NotifyVal {
     hdl: Handle,
-    val: StructBuf,
+    val: Option<ValuePdu>,
     ind: bool,
     tx: tokio::sync::oneshot::Sender<Result<()>>, // TODO: Avoid allocation
 }
 
 impl NotifyVal {
-    /// Executes notification or indication procedure.
+    /// Executes notification procedure or sends an indication. Returns the
+    /// indication if it was sent and is awaiting confirmation, which must be
+    /// reported via [`Self::result`].
     #[inline]
-    pub async fn exec(self, br: &mut Bearer) {
-        let val = self.val.as_ref();
-        let r = if self.ind {
-            br.handle_value_ind(self.hdl, val).await
-        } else {
-            br.handle_value_ntf(self.hdl, val).await
+    pub async fn exec(mut self, br: &mut Bearer) -> Option<Self> {
+        let Some(val) = self.val.take() else {
+            self.result(Ok(()));
+            return None;
         };
+        let r = br.send_handle_value(val, self.ind).await;
+        if self.ind && r.is_ok() {
+            return Some(self);
+        }
         self.result(r);
+        None
     }
 
     /// Reports the notification/indication result to the source.
@@ -408,8 +457,9 @@ impl NotifyVal {
 }
 
 impl AsRef<[u8]> for NotifyVal {
+    /// Returns the value if it was not sent.
     #[inline(always)]
     fn as_ref(&self) -> &[u8] {
-        self.val.as_ref()
+        self.val.as_ref().map_or(&[], ValuePdu::value)
     }
 }
@@ -1,15 +1,19 @@
 use std::collections::btree_map::Entry;
+use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
 use std::sync::{Arc, Weak};
 use std::vec;
 
-use structbuf::Unpack;
-use tracing::{debug, error, info, trace, warn};
+use structbuf::{Packer, Unpack};
+use tracing::{debug, error, info, trace, warn, Instrument};
 
+use burble_crypto::CSRK;
 use ErrorCode::*;
 
 use crate::gap::{Uuid, UuidType};
 use crate::gatt::service::gaps::GapService;
-use crate::{hci, le, SyncMutex, SyncMutexGuard};
+use crate::{hci, l2cap, le, PeerStore, SyncMutex, SyncMutexGuard};
 
 use super::*;
 
@@ -22,10 +26,25 @@ pub struct Server {
     sc: Option<ServiceChanged>,
     features: ServerFeature,
     store: Arc<CacheStore>,
+    signing_keys: Option<Arc<dyn PeerStore<Value = CSRK>>>,
+    sign_counters: Option<Arc<dyn PeerStore<Value = u32>>>,
+    notify_depth: usize,
+    cmd_depth: usize,
+    cmd_overflow: l2cap::CmdOverflow,
+    dropped_cmds: Arc<AtomicU64>,
     clients: SyncMutex<BTreeMap<le::Addr, Weak<SyncMutex<ClientCtx>>>>,
+    chars: SyncMutex<BTreeMap<(ServiceInstance, Uuid), CharRef>>,
 }
 
 impl Server {
+    /// Default number of notifications and indications that can be queued for
+    /// each client.
+    pub const NOTIFY_QUEUE_DEPTH: usize = 16;
+
+    /// Default number of received Write Commands that can be queued for each
+    /// bearer.
+    pub const WRITE_CMD_QUEUE_DEPTH: usize = 32;
+
     /// Defines the Generic Attribute service ([Vol 3] Part G, Section 7). This
     /// service should be first to maintain consistent handles for control-point
     /// characteristics. In particular, the Service Changed characteristic
@@ -63,29 +82,34 @@ impl Server {
         });
     }
 
-    /// Creates a new GATT server. The first service in the database should be
-    /// Generic Attribute, which can be defined with [`Self::define_service`].
+    /// Creates a new GATT server with default options. The first service in
+    /// the database should be Generic Attribute, which can be defined with
+    /// [`Self::define_service`].
     #[inline]
     #[must_use]
     pub fn new(db: Builder<Db>, store: Arc<CacheStore>) -> Arc<Self> {
-        let (db, io) = db.freeze();
-        let features = ServerFeature::empty();
-        let sc = ServiceChanged::new(&db, features);
-        // TODO: Move this logic to Builder
-        debug_assert!(
-            db.iter()
-                .filter(|&(_, uuid, _)| uuid == Characteristic::DatabaseHash)
-                .count()
-                <= 1
-        );
-        Arc::new(Self {
+        Self::build(db, store).finish()
+    }
+
+    /// Returns a builder for a GATT server with non-default options.
+    #[inline]
+    pub fn build(db: Builder<Db>, store: Arc<CacheStore>) -> ServerBuilder {
+        ServerBuilder {
             db,
-            io,
-            sc,
-            features,
             store,
-            clients: SyncMutex::new(BTreeMap::new()),
-        })
+            signing_keys: None,
+            sign_counters: None,
+            notify_depth: Self::NOTIFY_QUEUE_DEPTH,
+            cmd_depth: Self::WRITE_CMD_QUEUE_DEPTH,
+            cmd_overflow: l2cap::CmdOverflow::default(),
+        }
+    }
+
+    /// Returns the total number of Write Commands dropped due to a full queue.
+    #[inline]
+    #[must_use]
+    pub fn dropped_write_cmds(&self) -> u64 {
+        self.dropped_cmds.load(Relaxed)
     }
 
     /// Returns the server database.
@@ -95,12 +119,111 @@ impl Server {
         &self.db
     }
 
+    /// Returns the characteristic with UUID `chr` in the service selected by
+    /// `svc`, which is either a service UUID or a labeled
+    /// [`ServiceInstance`]. Returns [`None`] if the characteristic does not
+    /// exist or if there is more than one instance, in which case
+    /// [`Self::find_characteristics`] must be used to select one.
+    #[inline]
+    #[must_use]
+    pub fn find_characteristic(
+        &self,
+        svc: impl Into<ServiceInstance>,
+        chr: impl Into<Uuid>,
+    ) -> Option<CharRef> {
+        self.resolve(svc.into(), chr.into()).ok()
+    }
+
+    /// Returns all instances of characteristic `chr` in the services selected
+    /// by `svc` in handle order.
+    #[must_use]
+    pub fn find_characteristics(
+        &self,
+        svc: impl Into<ServiceInstance>,
+        chr: impl Into<Uuid>,
+    ) -> Vec<CharRef> {
+        let (svc, chr) = (svc.into(), chr.into());
+        let svcs = (self.db.primary_services(Handle::MIN, Some(svc.uuid)))
+            .chain(self.db.secondary_services(Handle::MIN, Some(svc.uuid)));
+        let mut v: Vec<CharRef> = svcs
+            .filter(|s| svc.label.is_none() || self.db.service_label(s.handle()) == svc.label)
+            .flat_map(|s| {
+                let svc_hdl = s.handle();
+                (self.db.characteristics(s.handle_range()))
+                    .filter(move |c| c.uuid() == chr)
+                    .map(move |c| CharRef::new(&self.db, svc_hdl, &c))
+            })
+            .collect();
+        v.sort_unstable_by_key(|c| c.vhdl);
+        v
+    }
+
+    /// Queues a notification or indication with the characteristic value
+    /// provided by `f` for all clients that enabled it via the Client
+    /// Characteristic Configuration descriptor. The characteristic is
+    /// specified either by a [`CharRef`] or by a `(service, characteristic)`
+    /// pair, where the service is a UUID or a labeled [`ServiceInstance`],
+    /// which is an error if it matches more than one characteristic. Values
+    /// are dropped for clients with a full queue. Returns the number of
+    /// clients that the value was queued for.
+    pub fn notify(&self, chr: impl Into<CharTarget>, f: impl Fn(&mut Packer)) -> Result<usize> {
+        let chr = match chr.into() {
+            CharTarget::Ref(c) => c,
+            CharTarget::Uuid(svc, chr) => self.resolve(svc, chr)?,
+        };
+        let Some(cccd) = chr.cccd else { return Ok(0) };
+        let clients: Vec<ArcClientCtx> = (self.clients.lock().values())
+            .filter_map(Weak::upgrade)
+            .collect();
+        let mut n = 0;
+        for cc in clients {
+            let req = {
+                let cc = cc.lock();
+                let (Some(ct), Some(&v)) = (cc.notify_cancel.get(&cccd), cc.cache.cccd.get(&cccd))
+                else {
+                    continue;
+                };
+                NotifyReq {
+                    hdl: chr.vhdl,
+                    uuid: chr.uuid,
+                    mtu: cc.notify_mtu,
+                    ind: v.contains(Cccd::INDICATE),
+                    tx: cc.tx.clone(),
+                    ct: ct.clone(),
+                }
+            };
+            if matches!(req.notify_dropping_if_full(&f), Ok(true)) {
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Resolves a unique characteristic by service instance and
+    /// characteristic UUID. Successful lookups are cached since the database
+    /// does not change.
+    fn resolve(&self, svc: ServiceInstance, chr: Uuid) -> Result<CharRef> {
+        if let Some(&c) = self.chars.lock().get(&(svc, chr)) {
+            return Ok(c);
+        }
+        match *self.find_characteristics(svc, chr) {
+            [] => Err(Error::CharacteristicNotFound(svc.uuid, chr)),
+            [c] => {
+                self.chars.lock().insert((svc, chr), c);
+                Ok(c)
+            }
+            _ => Err(Error::AmbiguousCharacteristic(svc.uuid, chr)),
+        }
+    }
+
     /// Creates a [`ServerCtx`] for the specified ATT bearer. The first bearer
     /// for a new connection, which should be the fixed ATT channel, becomes the
     /// sender of all notifications and indications.
     #[inline]
     pub fn attach(self: &Arc<Self>, br: &Bearer) -> ServerCtx {
         let peer = br.conn().borrow().peer_addr;
+        let dropped = Arc::clone(&self.dropped_cmds);
+        br.limit_cmds(self.cmd_depth, self.cmd_overflow, dropped);
         let cc = self.get_client_ctx(peer);
         let notify = cc.lock().notify_rx();
         ServerCtx {
@@ -108,6 +231,7 @@ impl Server {
             srv: Arc::clone(self),
             cc,
             notify,
+            access: AccessCache::default(),
             db_oos_sent: false,
         }
     }
@@ -118,7 +242,7 @@ impl Server {
         clients.retain(|_, cc| cc.strong_count() != 0);
         match clients.entry(peer) {
             Entry::Vacant(e) => {
-                let cc = ClientCtx::new();
+                let cc = ClientCtx::new(self.notify_depth);
                 e.insert(Arc::downgrade(&cc));
                 cc
             }
@@ -126,7 +250,7 @@ impl Server {
                 // `upgrade()` shouldn't fail because we just removed all Arcs
                 // without strong references, but there is a race with the last
                 // strong reference being dropped in a multithreaded runtime.
-                let cc = ClientCtx::new();
+                let cc = ClientCtx::new(self.notify_depth);
                 e.insert(Arc::downgrade(&cc));
                 cc
             }),
@@ -134,6 +258,221 @@ impl Server {
     }
 }
 
+/// GATT server builder returned by [`Server::build`].
+#[derive(Debug)]
+#[must_use]
+pub struct ServerBuilder {
+    db: Builder<Db>,
+    store: Arc<CacheStore>,
+    signing_keys: Option<Arc<dyn PeerStore<Value = CSRK>>>,
+    sign_counters: Option<Arc<dyn PeerStore<Value = u32>>>,
+    notify_depth: usize,
+    cmd_depth: usize,
+    cmd_overflow: l2cap::CmdOverflow,
+}
+
+impl ServerBuilder {
+    /// Sets the store that provides peer Connection Signature Resolving Keys
+    /// for verifying signed writes ([Vol 3] Part G, Section 4.9.2). Signed
+    /// writes from peers without a CSRK are discarded.
+    #[inline]
+    pub fn with_signing_key_store(mut self, store: Arc<dyn PeerStore<Value = CSRK>>) -> Self {
+        self.signing_keys = Some(store);
+        self
+    }
+
+    /// Sets the store used to persist the last sign counter received from
+    /// each bonded peer, which prevents signed writes from being replayed
+    /// across connections ([Vol 3] Part H, Section 2.4.5).
+    #[inline]
+    pub fn with_signing_counter_store(mut self, store: Arc<dyn PeerStore<Value = u32>>) -> Self {
+        self.sign_counters = Some(store);
+        self
+    }
+
+    /// Sets the number of notifications and indications that can be queued for
+    /// each client while waiting for controller buffer space
+    /// (default [`Server::NOTIFY_QUEUE_DEPTH`]). When the queue is full,
+    /// [`NotifyReq::notify`] waits and [`NotifyReq::notify_dropping_if_full`]
+    /// drops the value.
+    #[inline]
+    pub const fn with_notify_queue_depth(mut self, depth: NonZeroUsize) -> Self {
+        self.notify_depth = depth.get();
+        self
+    }
+
+    /// Sets the number of received Write Commands and Signed Write Commands
+    /// that can be queued for each bearer while the server is busy (default
+    /// [`Server::WRITE_CMD_QUEUE_DEPTH`]) and the action taken when a client
+    /// exceeds it (default [`l2cap::CmdOverflow::DropNewest`]). Commands are
+    /// discarded without notifying the client, which is allowed because they
+    /// don't require a response. Requests are never dropped because they are
+    /// flow controlled by the response. The channel limit of 64 queued PDUs
+    /// still applies to larger depths.
+    ///
+    /// Controller-to-host flow control is not enabled, so the controller
+    /// forwards data as fast as the client sends it and the link layer never
+    /// slows the client down. Even if enabled, it would stall all connections
+    /// sharing the controller's buffers rather than the flooding client, so
+    /// this limit is the only protection against unbounded queueing.
+    #[inline]
+    pub const fn with_write_cmd_queue(
+        mut self,
+        depth: NonZeroUsize,
+        policy: l2cap::CmdOverflow,
+    ) -> Self {
+        self.cmd_depth = depth.get();
+        self.cmd_overflow = policy;
+        self
+    }
+
+    /// Creates the GATT server.
+    #[must_use]
+    pub fn finish(self) -> Arc<Server> {
+        let (db, io) = self.db.freeze();
+        let features = ServerFeature::empty();
+        let sc = ServiceChanged::new(&db, features);
+        // TODO: Move this logic to Builder
+        debug_assert!(
+            db.iter()
+                .filter(|&(_, uuid, _)| uuid == Characteristic::DatabaseHash)
+                .count()
+                <= 1
+        );
+        Arc::new(Server {
+            db,
+            io,
+            sc,
+            features,
+            store: self.store,
+            signing_keys: self.signing_keys,
+            sign_counters: self.sign_counters,
+            notify_depth: self.notify_depth,
+            cmd_depth: self.cmd_depth,
+            cmd_overflow: self.cmd_overflow,
+            dropped_cmds: Arc::default(),
+            clients: SyncMutex::new(BTreeMap::new()),
+            chars: SyncMutex::new(BTreeMap::new()),
+        })
+    }
+}
+
+/// Reference to a characteristic instance in the server database.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CharRef {
+    svc: Handle,
+    vhdl: Handle,
+    cccd: Option<Handle>,
+    uuid: Uuid,
+}
+
+impl CharRef {
+    /// Creates a reference to characteristic `c` in service `svc`.
+    fn new(db: &Db, svc: Handle, c: &DbEntry<CharacteristicDef>) -> Self {
+        let (vhdl, end) = (c.value_handle(), c.handle_range().end());
+        let cccd = (vhdl.next().filter(|&start| start <= end)).and_then(|start| {
+            (db.descriptors(HandleRange::new(start, end)))
+                .find(|d| d.uuid() == Descriptor::ClientCharacteristicConfiguration)
+                .map(|d| d.handle())
+        });
+        Self {
+            svc,
+            vhdl,
+            cccd,
+            uuid: c.uuid(),
+        }
+    }
+
+    /// Returns the handle of the service declaration.
+    #[inline(always)]
+    #[must_use]
+    pub const fn service_handle(&self) -> Handle {
+        self.svc
+    }
+
+    /// Returns the handle of the characteristic value.
+    #[inline(always)]
+    #[must_use]
+    pub const fn value_handle(&self) -> Handle {
+        self.vhdl
+    }
+
+    /// Returns the characteristic UUID.
+    #[inline(always)]
+    #[must_use]
+    pub const fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+}
+
+/// Service selected by UUID and, if there are multiple instances of the
+/// service, by the label assigned with [`Builder::label_next`]. An unlabeled
+/// selector matches all instances.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct ServiceInstance {
+    uuid: Uuid,
+    label: Option<&'static str>,
+}
+
+impl ServiceInstance {
+    /// Selects the instance of service `uuid` with the specified label.
+    #[inline]
+    #[must_use]
+    pub fn labeled(uuid: impl Into<Uuid>, label: &'static str) -> Self {
+        Self {
+            uuid: uuid.into(),
+            label: Some(label),
+        }
+    }
+
+    /// Returns the service UUID.
+    #[inline(always)]
+    #[must_use]
+    pub const fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// Returns the instance label.
+    #[inline(always)]
+    #[must_use]
+    pub const fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+}
+
+impl<T: Into<Uuid>> From<T> for ServiceInstance {
+    #[inline(always)]
+    fn from(uuid: T) -> Self {
+        Self {
+            uuid: uuid.into(),
+            label: None,
+        }
+    }
+}
+
+/// Characteristic specified either by a [`CharRef`] or by a
+/// `(service, characteristic)` pair.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CharTarget {
+    Ref(CharRef),
+    Uuid(ServiceInstance, Uuid),
+}
+
+impl From<CharRef> for CharTarget {
+    #[inline(always)]
+    fn from(c: CharRef) -> Self {
+        Self::Ref(c)
+    }
+}
+
+impl<S: Into<ServiceInstance>, C: Into<Uuid>> From<(S, C)> for CharTarget {
+    #[inline(always)]
+    fn from((svc, chr): (S, C)) -> Self {
+        Self::Uuid(svc.into(), chr.into())
+    }
+}
+
 /// Server context used by an ATT bearer to handle client requests and service
 /// notifications/indications.
 #[derive(Debug)]
@@ -143,6 +482,7 @@ pub struct ServerCtx {
     srv: Arc<Server>,
     cc: ArcClientCtx,
     notify: Option<tokio::sync::mpsc::Receiver<NotifyVal>>,
+    access: AccessCache,
     db_oos_sent: bool,
 }
 
@@ -153,8 +493,15 @@ impl ServerCtx {
     // Alternatively, EATT support would allow using a dedicated channel for
     // client functionality.
 
+    /// Runs a server event loop for the specified bearer within the
+    /// connection span.
+    pub async fn serve(self, br: Bearer) -> Result<()> {
+        let span = br.conn().borrow().span.clone();
+        self.run(br).instrument(span).await
+    }
+
     /// Runs a server event loop for the specified bearer.
-    pub async fn serve(mut self, mut br: Bearer) -> Result<()> {
+    async fn run(mut self, mut br: Bearer) -> Result<()> {
         br.exchange_mtu().await?;
         info!("Serving: {:?}", GapService::read(&mut br).await?);
         if self.notify.is_none() {
@@ -173,15 +520,47 @@ impl ServerCtx {
         let mut conn = br.conn().clone();
         let sec = conn.borrow_and_update().sec;
         self.configure_notify(sec);
+        self.event_loop(&mut br, conn).await
+    }
+
+    /// Handles client requests, service notifications/indications, and
+    /// connection changes. The outstanding indication is tracked separately
+    /// from request processing, so the client may continue sending requests
+    /// while the server is waiting for a confirmation
+    /// ([Vol 3] Part F, Section 3.3.2).
+    async fn event_loop(&mut self, br: &mut Bearer, mut conn: hci::ConnWatch) -> Result<()> {
+        let mut ind: Option<NotifyVal> = None;
+        let cfm_timeout = tokio::time::sleep(TRANSACTION_TIMEOUT);
+        tokio::pin!(cfm_timeout);
         loop {
             let notify = self.notify.as_mut().expect("lost notification channel");
             tokio::select! {
-                pdu = br.recv() => self.handle(&mut br, &pdu?).await?,
-                // TODO: Allow processing requests while waiting for indication
-                // confirmation?
-                ntf = notify.recv() => {
+                pdu = br.recv() => {
+                    let pdu = pdu?;
+                    if pdu.opcode() != Opcode::HandleValueCfm {
+                        self.handle(br, &pdu).await?;
+                    } else if let Some(ind) = ind.take() {
+                        ind.result(Ok(()));
+                    } else {
+                        warn!("Ignoring unexpected {}", Opcode::HandleValueCfm);
+                    }
+                }
+                // Only one indication may be outstanding at a time
+                // ([Vol 3] Part F, Section 3.4.7.2).
+                ntf = notify.recv(), if ind.is_none() => {
                     // ClientCtx holds a Sender, so this should never panic
-                    ntf.expect("notification channel closed").exec(&mut br).await;
+                    ind = ntf.expect("notification channel closed").exec(br).await;
+                    if ind.is_some() {
+                        let t = tokio::time::Instant::now() + TRANSACTION_TIMEOUT;
+                        cfm_timeout.as_mut().reset(t);
+                    }
+                }
+                () = &mut cfm_timeout, if ind.is_some() => {
+                    let e = br.timeout(Opcode::HandleValueCfm);
+                    if let Some(ind) = ind.take() {
+                        ind.result(Err(e.clone()));
+                    }
+                    return Err(e);
                 }
                 _ = conn.changed(), if conn.has_changed().is_ok() => {
                     let (bond_id, sec) = {
@@ -189,6 +568,7 @@ impl ServerCtx {
                         let cn = conn.borrow();
                         (cn.bond_id, cn.sec)
                     };
+                    self.access.invalidate();
                     self.handle_bond_change(bond_id);
                     self.configure_notify(sec);
                 }
@@ -205,6 +585,9 @@ impl ServerCtx {
         // exchange_mtu(). It would be better to have a guarantee.
         let (peer, bond_id) = (self.peer, br.conn().borrow().bond_id);
         let mut cc = self.cc.lock();
+        if let (Some(s), Some(_)) = (self.srv.sign_counters.as_ref(), bond_id) {
+            cc.sign_counter = cc.sign_counter.max(s.load(peer));
+        }
         match self.srv.store.load(peer) {
             Some(mut cache) => {
                 cache.cccd.retain(|_, cccd| !cccd.is_empty());
@@ -598,6 +981,15 @@ impl ServerCtx {
         }
     }
 
+    /// Performs read/write access permission check for a single handle,
+    /// reusing a previous grant for the same handle and opcode if the
+    /// connection security state has not changed since then.
+    #[inline]
+    fn try_access(&mut self, br: &Bearer, pdu: &Pdu, hdl: Handle) -> RspResult<Handle> {
+        let sec = br.conn().borrow().sec;
+        self.access.try_access(&self.srv.db, pdu.opcode(), sec, hdl)
+    }
+
     /// Returns the UUID of the specified handle.
     #[inline]
     fn uuid(&self, hdl: Handle) -> Uuid {
@@ -624,15 +1016,18 @@ impl ServerCtx {
             ReadBlobReq => self.read_blob(br, pdu),
             ReadMultipleReq => self.read_multiple(br, pdu),
             ReadByGroupTypeReq => self.discover_primary_services(br, pdu),
-            WriteReq | WriteCmd => match self.write(br, pdu) {
-                Ok(Some(rsp)) => Ok(rsp),
-                Ok(None) => return Ok(()),
-                Err(e) => Err(e),
-            },
+            WriteReq => self.write(br, pdu),
+            WriteCmd => {
+                self.write_cmd(br, pdu);
+                return Ok(());
+            }
             PrepareWriteReq => self.prepare_write(br, pdu),
             ExecuteWriteReq => self.execute_write(br, pdu),
             ReadMultipleVariableReq => self.read_multiple_variable(br, pdu),
-            //SignedWriteCmd => unimplemented!(),
+            SignedWriteCmd => {
+                self.signed_write_cmd(br, pdu);
+                return Ok(());
+            }
             _ => {
                 if !matches!(pdu.opcode().typ(), PduType::Req) {
                     warn!("Ignoring unexpected {op}");
@@ -673,16 +1068,18 @@ impl ServerCtx {
 
     /// Handles "Discover All Primary Services" sub-procedure
     /// ([Vol 3] Part G, Section 4.4.1).
+    ///
+    /// All services in one response must have the same UUID size, so the
+    /// response ends before the first service with a different UUID size and
+    /// the client continues from there with the next request
+    /// ([Vol 3] Part F, Section 3.4.4.10).
     fn discover_primary_services(&self, br: &mut Bearer, pdu: &Pdu) -> RspResult<Rsp> {
         let (hdls, typ) = pdu.read_by_group_type_req()?;
         if typ != Declaration::PrimaryService {
             return pdu.err(UnsupportedGroupType);
         }
-        if hdls.end() != Handle::MAX {
-            return pdu.hdl_err(AttributeNotFound, hdls.start());
-        }
-        let mut it = (self.srv.db)
-            .primary_services(hdls.start(), None)
+        let mut it = (self.srv.db.primary_services(hdls.start(), None))
+            .take_while(|v| v.handle() <= hdls.end())
             .map(|v| (v.handle_range(), v.uuid(), v.value()));
         br.read_by_group_type_rsp(
             hdls.start(),
@@ -764,7 +1161,7 @@ impl ServerCtx {
     /// ([Vol 3] Part G, Section 4.8.1).
     fn read(&mut self, br: &mut Bearer, pdu: &Pdu) -> RspResult<Rsp> {
         self.require_db_sync(pdu.opcode())?;
-        let hdl = (self.srv.db).try_access(br.access_req(pdu), pdu.read_req()?)?;
+        let hdl = self.try_access(br, pdu, pdu.read_req()?)?;
         let mut r = ReadReq::new(pdu.opcode(), br.mtu());
         br.read_rsp(self.do_read(r.with(hdl, self.uuid(hdl), 0))?)
     }
@@ -781,7 +1178,7 @@ impl ServerCtx {
     fn read_blob(&mut self, br: &mut Bearer, pdu: &Pdu) -> RspResult<Rsp> {
         self.require_db_sync(pdu.opcode())?;
         let (hdl, off) = pdu.read_blob_req()?;
-        let hdl = self.srv.db.try_access(br.access_req(pdu), hdl)?;
+        let hdl = self.try_access(br, pdu, hdl)?;
         let mut r = ReadReq::new(pdu.opcode(), br.mtu());
         br.read_blob_rsp(self.do_read(r.with(hdl, self.uuid(hdl), off))?)
     }
@@ -805,41 +1202,100 @@ impl ServerCtx {
         br.read_multiple_variable_rsp(Reader::new(self, req, hdls))
     }
 
-    /// Handles "Write Without Response" and "Write Characteristic Value"
-    /// sub-procedures ([Vol 3] Part G, Section 4.9.1 and 4.9.3).
-    fn write(&mut self, br: &mut Bearer, pdu: &Pdu) -> RspResult<Option<Rsp>> {
+    /// Handles "Write Characteristic Value" sub-procedure
+    /// ([Vol 3] Part G, Section 4.9.3).
+    fn write(&mut self, br: &mut Bearer, pdu: &Pdu) -> RspResult<Rsp> {
+        self.write_val(br, pdu)?;
+        br.write_rsp()
+    }
+
+    /// Handles "Write Without Response" sub-procedure
+    /// ([Vol 3] Part G, Section 4.9.1). The server does not send a response
+    /// and silently discards the command if it fails for any reason
+    /// ([Vol 3] Part F, Section 3.4.5.3).
+    fn write_cmd(&mut self, br: &Bearer, pdu: &Pdu) {
+        if let Err(e) = self.write_val(br, pdu) {
+            debug!("Discarded {}: {e}", pdu.opcode());
+        }
+    }
+
+    /// Handles "Signed Write Without Response" sub-procedure
+    /// ([Vol 3] Part G, Section 4.9.2). The command is silently discarded if
+    /// the signature is invalid, the sign counter was already used, or the
+    /// write fails for any reason ([Vol 3] Part F, Section 3.4.5.4).
+    fn signed_write_cmd(&mut self, br: &Bearer, pdu: &Pdu) {
+        let Some(ctr) = self.verify_signature(pdu) else {
+            return;
+        };
+        if let Err(e) = self.write_val(br, pdu) {
+            debug!("Discarded {}: {e}", pdu.opcode());
+        }
+        let bonded = self.cc.lock().cache.bond_id.is_some();
+        if let (Some(s), true) = (self.srv.sign_counters.as_ref(), bonded) {
+            s.save(self.peer, &ctr);
+        }
+    }
+
+    /// Verifies the Authentication Signature of an `ATT_SIGNED_WRITE_CMD` PDU
+    /// and returns the accepted sign counter value
+    /// ([Vol 3] Part H, Section 2.4.5).
+    fn verify_signature(&self, pdu: &Pdu) -> Option<u32> {
+        let (op, peer) = (pdu.opcode(), self.peer);
+        let (m, sig) = pdu.signed_write_cmd().ok()?;
+        let Some(csrk) = (self.srv.signing_keys.as_ref()).and_then(|s| s.load(peer)) else {
+            debug!("Discarded {op}: no CSRK for {peer}");
+            return None;
+        };
+        if !csrk.verify(m, &sig) {
+            warn!("Discarded {op}: invalid signature from {peer}");
+            return None;
+        }
+        let ctr = sig.sign_counter;
+        if !self.cc.lock().accept_sign_counter(ctr) {
+            warn!("Discarded {op}: replayed sign counter {ctr} from {peer}");
+            return None;
+        }
+        Some(ctr)
+    }
+
+    /// Performs access checks and writes a characteristic value for
+    /// `ATT_WRITE_REQ`, `ATT_WRITE_CMD`, and `ATT_SIGNED_WRITE_CMD` PDUs.
+    fn write_val(&mut self, br: &Bearer, pdu: &Pdu) -> RspResult<()> {
         self.require_db_sync(pdu.opcode())?;
         let (hdl, val) = pdu.write_req()?;
-        let hdl = self.srv.db.try_access(br.access_req(pdu), hdl)?;
+        let hdl = self.try_access(br, pdu, hdl)?;
         if val.len() > MAX_VAL_LEN {
             return pdu.hdl_err(InvalidAttributeValueLength, hdl);
         }
         let w = WriteReq {
+            peer: self.peer,
             op: pdu.opcode(),
             hdl,
             uuid: self.uuid(hdl),
             off: 0,
             val,
         };
-        self.do_write(&w)?;
-        Ok(if Opcode::is_cmd(w.op as _) {
-            None
-        } else {
-            Some(br.write_rsp()?)
-        })
+        self.do_write(&w)
     }
 
     /// Handles the first phase of "Write Long Characteristic Values" and
     /// "Reliable Writes" sub-procedures
-    /// ([Vol 3] Part G, Section 4.9.4 and 4.9.5).
+    /// ([Vol 3] Part G, Section 4.9.4 and 4.9.5). The response echoes the
+    /// request parameters ([Vol 3] Part F, Section 3.4.6.2).
     fn prepare_write(&mut self, br: &mut Bearer, pdu: &Pdu) -> RspResult<Rsp> {
         self.require_db_sync(pdu.opcode())?;
         let (hdl, off, v) = pdu.prepare_write_req()?;
-        let hdl = self.srv.db.try_access(br.access_req(pdu), hdl)?;
+        let hdl = self.try_access(br, pdu, hdl)?;
         if off as usize + v.len() > MAX_VAL_LEN {
             return pdu.hdl_err(InvalidAttributeValueLength, hdl);
         }
-        if !self.cc.lock().write_queue.add(hdl, off, v) {
+        // Values provided by the application have an unknown length
+        let len = self.srv.db.get(hdl).map_or(0, |(_, v)| v.len());
+        let mut cc = self.cc.lock();
+        if !cc.write_queue.is_contiguous(hdl, off, len) {
+            return pdu.hdl_err(InvalidOffset, hdl);
+        }
+        if !cc.write_queue.add(hdl, off, v) {
             return pdu.hdl_err(PrepareQueueFull, hdl);
         }
         br.prepare_write_rsp(hdl, off, v)
@@ -857,6 +1313,7 @@ impl ServerCtx {
         }
         for (hdl, off, val) in cc.write_queue.iter() {
             self.do_write(&WriteReq {
+                peer: self.peer,
                 op: pdu.opcode(),
                 hdl,
                 uuid: self.uuid(hdl),
@@ -926,22 +1383,27 @@ struct ClientCtx {
     cache: Cache,
     db_hash_read: bool,
     write_queue: WriteQueue,
+    sign_counter: Option<u32>,
     notify_mtu: u16,
+    notify_depth: usize,
     notify_cancel: BTreeMap<Handle, tokio_util::sync::CancellationToken>,
     tx: tokio::sync::mpsc::Sender<NotifyVal>,
     rx: Option<tokio::sync::mpsc::Receiver<NotifyVal>>,
 }
 
 impl ClientCtx {
-    /// Creates a new client context.
+    /// Creates a new client context with a notification queue of the
+    /// specified depth.
     #[must_use]
-    fn new() -> ArcClientCtx {
-        let (tx, rx) = tokio::sync::mpsc::channel(1);
+    fn new(notify_depth: usize) -> ArcClientCtx {
+        let (tx, rx) = tokio::sync::mpsc::channel(notify_depth);
         Arc::new(SyncMutex::new(Self {
             cache: Cache::default(),
             db_hash_read: false,
             write_queue: WriteQueue::default(),
+            sign_counter: None,
             notify_mtu: 0,
+            notify_depth,
             notify_cancel: BTreeMap::new(),
             tx,
             rx: Some(rx),
@@ -961,12 +1423,70 @@ impl ClientCtx {
             // is even available, so services may try to send notifications
             // without any receiver.
             self.tx.is_closed().then(|| {
-                let (tx, rx) = tokio::sync::mpsc::channel(1);
+                let (tx, rx) = tokio::sync::mpsc::channel(self.notify_depth);
                 self.tx = tx;
                 rx
             })
         })
     }
+
+    /// Updates the last received sign counter and returns `true` if `ctr` is
+    /// greater than the previous value ([Vol 3] Part H, Section 2.4.5).
+    #[must_use]
+    fn accept_sign_counter(&mut self, ctr: u32) -> bool {
+        if self.sign_counter.map_or(false, |last| ctr <= last) {
+            return false;
+        }
+        self.sign_counter = Some(ctr);
+        true
+    }
+}
+
+/// Per-connection cache of granted single-handle access checks. Entries are
+/// keyed by handle and opcode, which determines the access type and the
+/// required characteristic property. All entries are discarded when the
+/// connection security state or the database changes.
+#[derive(Debug, Default)]
+#[must_use]
+struct AccessCache {
+    sec: hci::ConnSec,
+    db_hash: u128,
+    grants: BTreeSet<(Handle, u8)>,
+}
+
+impl AccessCache {
+    const LIMIT: usize = 64;
+
+    /// Performs an access check for `hdl`, returning a cached grant if one
+    /// exists for the current security state and database.
+    fn try_access(
+        &mut self,
+        db: &Db,
+        op: Opcode,
+        sec: hci::ConnSec,
+        hdl: Handle,
+    ) -> RspResult<Handle> {
+        if self.sec != sec || self.db_hash != db.hash() {
+            self.invalidate();
+            (self.sec, self.db_hash) = (sec, db.hash());
+        }
+        let k = (hdl, u8::from(op));
+        if self.grants.contains(&k) {
+            return Ok(hdl);
+        }
+        let hdl = db.try_access(op.request(sec), hdl)?;
+        if self.grants.len() >= Self::LIMIT {
+            self.grants.clear();
+        }
+        self.grants.insert(k);
+        Ok(hdl)
+    }
+
+    /// Removes all cached grants.
+    #[inline]
+    fn invalidate(&mut self) {
+        self.grants.clear();
+    }
 }
 
 /// Prepared write queue ([Vol 3] Part F, Section 3.4.6).
@@ -997,6 +1517,19 @@ impl WriteQueue {
         true
     }
 
+    /// Returns whether a prepared write at offset `off` would continue the
+    /// value of `hdl` without leaving a gap. `len` is the current length of the
+    /// value or 0 if unknown, which is extended by all previously queued writes
+    /// for the same handle.
+    #[must_use]
+    fn is_contiguous(&self, hdl: Handle, off: u16, len: usize) -> bool {
+        let end = (self.seq.iter())
+            .filter(|&&(h, ..)| !self.clear && h == hdl)
+            .map(|&(_, off, n)| usize::from(off) + usize::from(n))
+            .fold(len, usize::max);
+        usize::from(off) <= end
+    }
+
     /// Clears the queue.
     #[inline]
     fn clear(&mut self) {
@@ -1019,3 +1552,627 @@ impl WriteQueue {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_queue() {
+        let (h1, h2) = (Handle::new(1).unwrap(), Handle::new(2).unwrap());
+        let mut q = WriteQueue::default();
+        assert!(q.is_contiguous(h1, 0, 0));
+        assert!(!q.is_contiguous(h1, 4, 0));
+        assert!(q.is_contiguous(h1, 4, 4));
+        assert!(q.add(h1, 0, &[1, 2, 3]));
+        assert!(q.is_contiguous(h1, 3, 0));
+        assert!(q.is_contiguous(h1, 1, 0));
+        assert!(!q.is_contiguous(h1, 4, 0));
+        assert!(q.is_contiguous(h1, 4, 4));
+        assert!(!q.is_contiguous(h2, 4, 0));
+        assert!(q.is_contiguous(h2, 4, 4));
+        assert!(q.add(h2, 4, &[4]));
+        assert!(q.add(h1, 3, &[5, 6]));
+        assert!(!q.is_contiguous(h1, 6, 0));
+        assert!(q.is_contiguous(h2, 5, 0));
+        let v: Vec<_> = q.iter().map(|(h, off, v)| (h, off, v.to_vec())).collect();
+        let want = [
+            (h1, 0, vec![1, 2, 3]),
+            (h2, 4, vec![4]),
+            (h1, 3, vec![5, 6]),
+        ];
+        assert_eq!(v, want);
+        assert!(!q.is_contiguous(h1, 6, 0));
+        assert!(q.is_contiguous(h1, 0, 0));
+
+        // An overlapping write does not move the end of the value back
+        assert!(q.add(h1, 0, &[0; 10]));
+        assert!(q.add(h1, 10, &[0; 10]));
+        assert!(q.add(h1, 5, &[0; 3]));
+        assert!(q.is_contiguous(h1, 20, 0));
+        assert!(!q.is_contiguous(h1, 21, 0));
+    }
+
+    /// A security downgrade immediately invalidates cached grants.
+    #[test]
+    fn access_cache() {
+        let mut db = Db::build();
+        db.primary_service(Service::Battery, [], |db| {
+            db.characteristic(
+                Characteristic::BatteryLevel,
+                Prop::READ | Prop::WRITE,
+                Access::READ_WRITE.encrypt(),
+                Io::NONE,
+                |_| {},
+            );
+        });
+        let (db, _) = db.freeze();
+        let hdl = Handle::new(0x0003).unwrap();
+        let (enc, unenc) = (hci::ConnSec::key_len(128), hci::ConnSec::empty());
+        let mut c = AccessCache::default();
+
+        assert_eq!(c.try_access(&db, Opcode::ReadReq, enc, hdl).unwrap(), hdl);
+        assert_eq!(c.try_access(&db, Opcode::WriteReq, enc, hdl).unwrap(), hdl);
+        assert_eq!(c.grants.len(), 2);
+        assert_eq!(c.try_access(&db, Opcode::ReadReq, enc, hdl).unwrap(), hdl);
+        assert_eq!(c.grants.len(), 2);
+        let e = c.try_access(&db, Opcode::WriteCmd, enc, hdl).unwrap_err();
+        assert_eq!(e.code(), WriteNotPermitted);
+
+        let e = c.try_access(&db, Opcode::ReadReq, unenc, hdl).unwrap_err();
+        assert_eq!(e.code(), InsufficientEncryption);
+        assert!(c.grants.is_empty());
+        let e = c.try_access(&db, Opcode::WriteReq, unenc, hdl).unwrap_err();
+        assert_eq!(e.code(), InsufficientEncryption);
+
+        assert_eq!(c.try_access(&db, Opcode::ReadReq, enc, hdl).unwrap(), hdl);
+        c.invalidate();
+        assert!(c.grants.is_empty());
+
+        // A different database discards all grants
+        assert_eq!(c.try_access(&db, Opcode::ReadReq, enc, hdl).unwrap(), hdl);
+        let mut other = Db::build();
+        other.primary_service(Service::Battery, [], |_| {});
+        let (other, _) = other.freeze();
+        let e = c.try_access(&other, Opcode::ReadReq, enc, hdl).unwrap_err();
+        assert_eq!(e.code(), InvalidHandle);
+        assert!(c.grants.is_empty());
+    }
+
+    #[test]
+    fn sign_counter() {
+        let cc = ClientCtx::new(1);
+        let mut cc = cc.lock();
+        assert!(cc.accept_sign_counter(0));
+        assert!(!cc.accept_sign_counter(0));
+        assert!(cc.accept_sign_counter(2));
+        assert!(!cc.accept_sign_counter(1));
+        assert!(!cc.accept_sign_counter(2));
+
+        // Counter restored from a previous connection
+        cc.sign_counter = cc.sign_counter.max(Some(5));
+        assert!(!cc.accept_sign_counter(5));
+        assert!(cc.accept_sign_counter(6));
+        cc.sign_counter = cc.sign_counter.max(Some(3));
+        assert!(!cc.accept_sign_counter(6));
+    }
+
+    #[test]
+    fn notify_queue() {
+        let cc = ClientCtx::new(2);
+        let mut rx = cc.lock().notify_rx().unwrap();
+        let n = NotifyReq {
+            hdl: Handle::new(3).unwrap(),
+            uuid: Uuid::from(Characteristic::DeviceName),
+            mtu: 23,
+            ind: false,
+            tx: cc.lock().tx.clone(),
+            ct: tokio_util::sync::CancellationToken::new(),
+        };
+        let put = |v: u8| {
+            n.notify_dropping_if_full(|p| {
+                p.u8(v);
+            })
+        };
+        assert!(put(1).unwrap());
+        assert!(put(2).unwrap());
+        assert!(!put(3).unwrap());
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [1]);
+        assert!(put(4).unwrap());
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [2]);
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [4]);
+        drop(rx);
+        assert!(matches!(put(5), Err(Error::NotifyClosed)));
+        n.ct.cancel();
+        assert!(matches!(put(6), Err(Error::NotifyClosed)));
+    }
+
+    /// Client requests are handled while an indication is awaiting
+    /// confirmation.
+    #[tokio::test]
+    async fn request_during_indication() {
+        let mut db = Db::build();
+        let (_, (hdl, _)) = db.primary_service(Service::Battery, [], |db| {
+            db.characteristic(
+                Characteristic::BatteryLevel,
+                Prop::READ | Prop::INDICATE,
+                Access::READ,
+                Io::from(|req: IoReq| match req {
+                    IoReq::Read(r) => r.complete([42]),
+                    _ => Err(RequestNotSupported),
+                }),
+                |db| db.cccd(Access::READ_WRITE),
+            )
+        });
+        let srv = Server::new(db, Arc::new(NoStore));
+        let (p, c) = crate::l2cap::loopback::att();
+        let (mut sbr, mut cbr) = (Bearer::new(p), Bearer::new(c));
+        let mut ctx = srv.attach(&sbr);
+        let n = NotifyReq {
+            hdl,
+            uuid: Uuid::from(Characteristic::BatteryLevel),
+            mtu: 23,
+            ind: true,
+            tx: ctx.cc.lock().tx.clone(),
+            ct: tokio_util::sync::CancellationToken::new(),
+        };
+        let conn = sbr.conn().clone();
+        let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+        let ind = tokio::spawn(n.notify(|p| {
+            p.u8(1);
+        }));
+
+        let pdu = cbr.recv().await.unwrap();
+        assert_eq!(pdu.opcode(), Opcode::HandleValueInd);
+        assert_eq!(pdu.handle_value().unwrap(), (hdl, &[1][..]));
+
+        let req = cbr.read_by_type_req(HandleRange::ALL, Characteristic::BatteryLevel);
+        let rsp = cbr.exec(req).await.unwrap();
+        let mut it = rsp.read_by_type_rsp().unwrap();
+        assert_eq!(it.next(), Some((hdl, &[42][..])));
+        assert!(!ind.is_finished());
+
+        cbr.handle_value_cfm().await.unwrap();
+        ind.await.unwrap().unwrap();
+        srv_loop.abort();
+    }
+
+    /// Notification values are encoded into the outbound PDU and sent intact.
+    #[tokio::test]
+    async fn notify_value() {
+        let mut db = Db::build();
+        let (_, hdl) = battery(&mut db);
+        let srv = Server::new(db, Arc::new(NoStore));
+        let (p, c) = crate::l2cap::loopback::att();
+        let (mut sbr, mut cbr) = (Bearer::new(p), Bearer::new(c));
+        let mut ctx = srv.attach(&sbr);
+        let n = NotifyReq {
+            hdl,
+            uuid: Uuid::from(Characteristic::BatteryLevel),
+            mtu: 23,
+            ind: false,
+            tx: ctx.cc.lock().tx.clone(),
+            ct: tokio_util::sync::CancellationToken::new(),
+        };
+        let conn = sbr.conn().clone();
+        let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+
+        let v: Vec<u8> = (0..20).collect();
+        n.notify(|p| {
+            p.put(&v);
+        })
+        .await
+        .unwrap();
+        let pdu = cbr.recv().await.unwrap();
+        assert_eq!(pdu.opcode(), Opcode::HandleValueNtf);
+        assert_eq!(pdu.handle_value().unwrap(), (hdl, &v[..]));
+        srv_loop.abort();
+    }
+
+    /// Long reads, long writes, and maximum-size notifications are intact with
+    /// MTUs that require each PDU to be fragmented into many ACL data packets.
+    #[tokio::test]
+    async fn large_mtu() {
+        for mtu in [247, 512, MAX_MTU] {
+            let val = Arc::new(SyncMutex::new(vec![0; MAX_VAL_LEN]));
+            let v = Arc::clone(&val);
+            let mut db = Db::build();
+            let (_, (hdl, ())) = db.primary_service(Service::Battery, [], |db| {
+                db.characteristic(
+                    Characteristic::BatteryLevel,
+                    Prop::READ | Prop::WRITE | Prop::NOTIFY,
+                    Access::READ_WRITE,
+                    Io::from(move |req: IoReq| match req {
+                        IoReq::Read(r) => r.complete(&*v.lock()),
+                        IoReq::Write(w) => w.update(&mut *v.lock()),
+                        IoReq::Notify(_) => Err(RequestNotSupported),
+                    }),
+                    |_| {},
+                )
+            });
+            let srv = Server::new(db, Arc::new(NoStore));
+            let (p, c) = crate::l2cap::loopback::att();
+            let (mut sbr, mut cbr) = (Bearer::new(p), Bearer::new(c));
+            sbr.set_max_mtu(mtu);
+            cbr.set_max_mtu(mtu);
+            let (sr, cr) = tokio::join!(sbr.exchange_mtu(), cbr.exchange_mtu());
+            sr.unwrap();
+            cr.unwrap();
+            assert_eq!((sbr.mtu(), cbr.mtu()), (mtu, mtu));
+
+            let mut ctx = srv.attach(&sbr);
+            let n = NotifyReq {
+                hdl,
+                uuid: Uuid::from(Characteristic::BatteryLevel),
+                mtu,
+                ind: false,
+                tx: ctx.cc.lock().tx.clone(),
+                ct: tokio_util::sync::CancellationToken::new(),
+            };
+            let conn = sbr.conn().clone();
+            let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+            let mut cl = Client::new(cbr);
+
+            let want: Vec<u8> = (0..MAX_VAL_LEN)
+                .map(|i| u8::try_from(i % 251).unwrap())
+                .collect();
+            cl.write_long(hdl, &want).await.unwrap();
+            assert_eq!(*val.lock(), want);
+            assert_eq!(cl.read_long(hdl).await.unwrap(), want);
+
+            let ntf: Vec<u8> = want.iter().rev().take(n.max_len()).copied().collect();
+            assert_eq!(ntf.len(), usize::from(mtu - 3).min(MAX_VAL_LEN));
+            n.notify(|p| {
+                p.put(&ntf);
+            })
+            .await
+            .unwrap();
+            let pdu = cl.bearer().recv().await.unwrap();
+            assert_eq!(pdu.opcode(), Opcode::HandleValueNtf);
+            assert_eq!(pdu.handle_value().unwrap(), (hdl, &ntf[..]));
+            srv_loop.abort();
+        }
+    }
+
+    /// Prepare Write Responses echo the request parameters, including for
+    /// overlapping writes, and the queued value is written on execution.
+    #[tokio::test]
+    async fn prepare_write_echo() {
+        let val = Arc::new(SyncMutex::new(vec![0; 32]));
+        let v = Arc::clone(&val);
+        let mut db = Db::build();
+        let (_, (hdl, ())) = db.primary_service(Service::Battery, [], |db| {
+            db.characteristic(
+                Characteristic::BatteryLevel,
+                Prop::WRITE,
+                Access::WRITE,
+                Io::from(move |req: IoReq| match req {
+                    IoReq::Write(w) => w.update(&mut *v.lock()),
+                    _ => Err(RequestNotSupported),
+                }),
+                |_| {},
+            )
+        });
+        let srv = Server::new(db, Arc::new(NoStore));
+        let (p, c) = crate::l2cap::loopback::att();
+        let (mut sbr, mut cbr) = (Bearer::new(p), Bearer::new(c));
+        let mut ctx = srv.attach(&sbr);
+        let conn = sbr.conn().clone();
+        let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+
+        for (off, n) in [(0_u16, 10_u8), (10, 10), (5, 3), (20, 4)] {
+            let v: Vec<u8> = (0..n).map(|i| u8::try_from(off).unwrap() + i).collect();
+            let req = cbr.prepare_write_req(hdl, off, &v);
+            let rsp = cbr.exec(req).await.unwrap();
+            assert_eq!(rsp.opcode(), Opcode::PrepareWriteRsp);
+            assert_eq!(rsp.prepare_write_rsp().unwrap(), (hdl, off, &v[..]));
+        }
+        let rsp = cbr.exec(cbr.execute_write_req(true)).await.unwrap();
+        assert_eq!(rsp.opcode(), Opcode::ExecuteWriteRsp);
+        let want: Vec<u8> = (0..24).collect();
+        assert_eq!(val.lock()[..24], want[..]);
+        srv_loop.abort();
+    }
+
+    /// Write Commands beyond the queue depth are dropped without affecting
+    /// Write Requests.
+    #[tokio::test]
+    async fn write_cmd_flood() {
+        use l2cap::CmdOverflow::{DropNewest, DropOldest};
+        const N: u16 = 10_000;
+        const DEPTH: u16 = 8;
+        for policy in [DropNewest, DropOldest] {
+            let writes = Arc::new(SyncMutex::new(Vec::new()));
+            let mut db = Db::build();
+            let w = Arc::clone(&writes);
+            let (_, (hdl, ())) = db.primary_service(Service::Battery, [], |db| {
+                db.characteristic(
+                    Characteristic::BatteryLevel,
+                    Prop::WRITE | Prop::WRITE_CMD,
+                    Access::WRITE,
+                    Io::from(move |req: IoReq| match req {
+                        IoReq::Write(r) => {
+                            let v = r.value().try_into().unwrap();
+                            w.lock().push(u16::from_le_bytes(v));
+                            Ok(())
+                        }
+                        _ => Err(RequestNotSupported),
+                    }),
+                    |_| {},
+                )
+            });
+            let depth = NonZeroUsize::new(usize::from(DEPTH)).unwrap();
+            let srv = (Server::build(db, Arc::new(NoStore)))
+                .with_write_cmd_queue(depth, policy)
+                .finish();
+            let (p, c) = crate::l2cap::loopback::att();
+            let (mut sbr, mut cbr) = (Bearer::new(p), Bearer::new(c));
+            let mut ctx = srv.attach(&sbr);
+            for i in 0..N {
+                cbr.write_cmd(hdl, &i.to_le_bytes()).await.unwrap();
+            }
+            let conn = sbr.conn().clone();
+            let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+            let req = cbr.write_req(hdl, &N.to_le_bytes());
+            assert_eq!(cbr.exec(req).await.unwrap().opcode(), Opcode::WriteRsp);
+            srv_loop.abort();
+
+            assert_eq!(srv.dropped_write_cmds(), u64::from(N - DEPTH));
+            let want: Vec<u16> = match policy {
+                DropNewest => (0..DEPTH).chain([N]).collect(),
+                _ => (N - DEPTH..=N).collect(),
+            };
+            assert_eq!(*writes.lock(), want);
+        }
+    }
+
+    /// A signed write is accepted after bonding with a Central that
+    /// distributed its CSRK, and a write signed with another key is discarded.
+    #[tokio::test]
+    async fn bond_signed_write() {
+        use crate::smp::{self, test::Store};
+        let writes = Arc::new(SyncMutex::new(Vec::new()));
+        let mut db = Db::build();
+        let w = Arc::clone(&writes);
+        let (_, (hdl, ())) = db.primary_service(Service::Battery, [], |db| {
+            db.characteristic(
+                Characteristic::BatteryLevel,
+                Prop::WRITE | Prop::SIGNED_WRITE_CMD,
+                Access::WRITE,
+                Io::from(move |req: IoReq| match req {
+                    IoReq::Write(r) => {
+                        w.lock().push(r.value().to_vec());
+                        Ok(())
+                    }
+                    _ => Err(RequestNotSupported),
+                }),
+                |_| {},
+            )
+        });
+        let addr = |i| le::Addr::Random(le::RawAddr::from_le_bytes([i, 0, 0, 0, 0, 0x40]));
+        let (paddr, caddr) = (addr(1), addr(2));
+        let (p, c) = l2cap::loopback::connect(paddr, caddr);
+        let (ps, cs) = (Arc::new(Store::default()), Store::default());
+        let mut pdev = smp::Device::new();
+        let mut cdev = smp::Device::new().with_signing_key(CSRK::new(1));
+        let (mut psm, mut csm) = (smp::Peripheral::new(p.smp), smp::Central::new(c.smp));
+        let (pr, cr) = tokio::join!(psm.respond(&mut pdev, &*ps), async {
+            let keys = csm.initiate(&mut cdev, &cs, true).await?;
+            let sec = hci::ConnSec::key_len(keys.key_len());
+            (p.conn).send_modify(|cn| cn.sec = sec);
+            (c.conn).send_modify(|cn| cn.sec = sec);
+            csm.distribute_keys(&cdev, &cs, keys).await
+        });
+        pr.unwrap();
+        assert_eq!(cr.unwrap(), paddr);
+        assert_eq!(ps.load(caddr).unwrap().csrk(), Some(&CSRK::new(1)));
+
+        // Signed writes are used on an unencrypted link after reconnecting
+        (p.conn).send_modify(|cn| cn.sec = hci::ConnSec::empty());
+        (c.conn).send_modify(|cn| cn.sec = hci::ConnSec::empty());
+        let keys = smp::SigningKeyStore::new(Arc::clone(&ps) as _);
+        let srv = (Server::build(db, Arc::new(NoStore)))
+            .with_signing_key_store(Arc::new(keys))
+            .finish();
+        let mut sbr = Bearer::new(p.att);
+        let mut ctx = srv.attach(&sbr);
+        let conn = sbr.conn().clone();
+        let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+        let mut cl = Client::new(Bearer::new(c.att));
+        let mut ctr = 0;
+        (cl.signed_write_command(hdl, &[1], &CSRK::new(1), &mut ctr))
+            .await
+            .unwrap();
+        (cl.signed_write_command(hdl, &[2], &CSRK::new(2), &mut ctr))
+            .await
+            .unwrap();
+        let br = cl.bearer();
+        let req = br.write_req(hdl, &[3]);
+        assert_eq!(br.exec(req).await.unwrap().opcode(), Opcode::WriteRsp);
+        srv_loop.abort();
+        assert_eq!(*writes.lock(), [[1], [3]]);
+    }
+
+    /// Primary service discovery does not mix 16-bit and 128-bit UUIDs in one
+    /// response.
+    #[tokio::test]
+    async fn discover_mixed_uuids() {
+        use Service::*;
+        let custom = |i| Uuid::new(0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0 + i).unwrap();
+        let uuids = [
+            custom(1),
+            Battery.into(),
+            HeartRate.into(),
+            custom(2),
+            custom(3),
+            DeviceInformation.into(),
+        ];
+        let mut db = Db::build();
+        let want: Vec<_> = (uuids.iter())
+            .map(|&uuid| (db.primary_service(uuid, [], |_| {}).0, uuid))
+            .collect();
+        let srv = Server::new(db, Arc::new(NoStore));
+        let (p, c) = crate::l2cap::loopback::att();
+        let mut sbr = Bearer::new(p);
+        let mut ctx = srv.attach(&sbr);
+        let conn = sbr.conn().clone();
+        let srv_loop = tokio::spawn(async move { ctx.event_loop(&mut sbr, conn).await });
+        let mut cl = Client::new(Bearer::new(c));
+
+        let have = cl.discover_primary_services().await.unwrap();
+        let have: Vec<_> = have.iter().map(|s| (s.hdls.start(), s.uuid)).collect();
+        assert_eq!(have, want);
+
+        // Only one 128-bit UUID fits with the default MTU
+        let br = cl.bearer();
+        let req = br.read_by_group_type_req(HandleRange::ALL, Declaration::PrimaryService);
+        let rsp = br.exec(req).await.unwrap();
+        let v: Vec<_> = rsp.read_by_group_type_rsp().unwrap().collect();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].1, u128::from(custom(1)).to_le_bytes());
+
+        // The response ends at the end of the requested handle range
+        let hdls = HandleRange::new(want[1].0, want[2].0);
+        let req = br.read_by_group_type_req(hdls, Declaration::PrimaryService);
+        let rsp = br.exec(req).await.unwrap();
+        let v: Vec<_> = rsp.read_by_group_type_rsp().unwrap().collect();
+        assert_eq!(v.len(), 2);
+        assert_eq!(v[1].0.start(), want[2].0);
+        srv_loop.abort();
+    }
+
+    #[test]
+    fn find_characteristic() {
+        use {Characteristic::*, Service::*};
+        let mut db = Db::build();
+        Server::define_service(&mut db);
+        let (svc, vhdl) = battery(&mut db);
+        let srv = Server::new(db, Arc::new(NoStore));
+        let c = srv.find_characteristic(Battery, BatteryLevel).unwrap();
+        assert_eq!((c.service_handle(), c.value_handle()), (svc, vhdl));
+        assert_eq!(c.uuid(), BatteryLevel);
+        assert_eq!(c.cccd, vhdl.next());
+        assert_eq!(srv.find_characteristics(Battery, BatteryLevel), [c]);
+        assert!(srv.find_characteristic(Battery, DeviceName).is_none());
+        let c = srv.find_characteristic(GenericAttribute, BatteryLevel);
+        assert!(c.is_none());
+        let c = srv.find_characteristic(GenericAttribute, ClientSupportedFeatures);
+        assert_eq!(c.unwrap().cccd, None);
+
+        // Multiple service instances require a CharRef
+        let mut db = Db::build();
+        battery(&mut db);
+        battery(&mut db);
+        let srv = Server::new(db, Arc::new(NoStore));
+        assert!(srv.find_characteristic(Battery, BatteryLevel).is_none());
+        let all = srv.find_characteristics(Battery, BatteryLevel);
+        assert_eq!(all.len(), 2);
+        assert!(all[0].value_handle() < all[1].value_handle());
+        let e = srv.notify((Battery, BatteryLevel), |_| {}).unwrap_err();
+        assert!(matches!(e, Error::AmbiguousCharacteristic(..)));
+        assert_eq!(srv.notify(all[1], |_| {}).unwrap(), 0);
+    }
+
+    #[test]
+    fn service_instances() {
+        use {Characteristic::*, Service::*};
+        let mut db = Db::build();
+        db.label_next("case");
+        let (case, _) = battery(&mut db);
+        db.label_next("stylus");
+        let (stylus, vhdl) = battery(&mut db);
+        let srv = Server::new(db, Arc::new(NoStore));
+        assert_eq!(srv.db().service_label(case), Some("case"));
+        assert_eq!(srv.db().service_label(stylus), Some("stylus"));
+        assert_eq!(srv.db().service_label(vhdl), None);
+
+        let sel = ServiceInstance::labeled(Battery, "stylus");
+        let c = srv.find_characteristic(sel, BatteryLevel).unwrap();
+        assert_eq!((c.service_handle(), c.value_handle()), (stylus, vhdl));
+        assert_eq!(srv.find_characteristics(sel, BatteryLevel), [c]);
+        assert_eq!(srv.find_characteristics(Battery, BatteryLevel).len(), 2);
+        let c = srv.find_characteristic(ServiceInstance::labeled(Battery, "case"), BatteryLevel);
+        assert_eq!(c.unwrap().service_handle(), case);
+        let c = srv.find_characteristic(ServiceInstance::labeled(Battery, "pen"), BatteryLevel);
+        assert!(c.is_none());
+        assert_eq!(srv.notify((sel, BatteryLevel), |_| {}).unwrap(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be defined before")]
+    fn service_instance_order() {
+        let mut db = Db::build();
+        db.label_next("stylus");
+        battery(&mut db);
+        db.label_next("case");
+        battery(&mut db);
+    }
+
+    #[test]
+    fn notify_by_uuid() {
+        use {Characteristic::*, Service::*};
+        let mut db = Db::build();
+        battery(&mut db);
+        let srv = Server::new(db, Arc::new(NoStore));
+        let cc = srv.get_client_ctx(le::Addr::Public(le::RawAddr::default()));
+        let mut rx = cc.lock().notify_rx().unwrap();
+        let put = |v: u8| {
+            srv.notify((Battery, BatteryLevel), |p| {
+                p.u8(v);
+            })
+        };
+        assert_eq!(put(1).unwrap(), 0);
+        {
+            let c = srv.find_characteristic(Battery, BatteryLevel).unwrap();
+            let cccd = c.cccd.unwrap();
+            let mut cc = cc.lock();
+            cc.notify_mtu = 23;
+            cc.cache.cccd.insert(cccd, Cccd::NOTIFY);
+            (cc.notify_cancel).insert(cccd, tokio_util::sync::CancellationToken::new());
+        }
+        assert_eq!(put(2).unwrap(), 1);
+        let v = rx.try_recv().unwrap();
+        assert_eq!(v.as_ref(), [2]);
+        assert!(rx.try_recv().is_err());
+
+        let e = srv.notify((Battery, DeviceName), |_| {}).unwrap_err();
+        assert!(matches!(e, Error::CharacteristicNotFound(..)));
+        drop(cc);
+        assert_eq!(put(3).unwrap(), 0);
+    }
+
+    /// Defines a Battery service and returns the service and Battery Level
+    /// value handles.
+    fn battery(db: &mut Builder<Db>) -> (Handle, Handle) {
+        db.primary_service(Service::Battery, [], |db| {
+            let (vhdl, _) = db.characteristic(
+                Characteristic::BatteryLevel,
+                Prop::READ | Prop::NOTIFY,
+                Access::READ,
+                Io::NONE,
+                |db| db.cccd(Access::READ_WRITE),
+            );
+            vhdl
+        })
+    }
+
+    /// Store that does not persist any client caches.
+    #[derive(Debug)]
+    struct NoStore;
+
+    impl PeerStore for NoStore {
+        type Value = Cache;
+
+        fn save(&self, _: le::Addr, _: &Self::Value) -> bool {
+            false
+        }
+
+        fn load(&self, _: le::Addr) -> Option<Self::Value> {
+            None
+        }
+
+        fn remove(&self, _: le::Addr) {}
+
+        fn clear(&self) {}
+    }
+}
@@ -5,29 +5,252 @@
 //!
 //! [BAS]: https://www.bluetooth.com/specifications/specs/battery-service/
 
-use crate::att::{Handle, Perms};
-use crate::gatt::{Builder, Characteristic, Db, Service};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-/// Battery service state. This is currently a stub that always reports 100%.
+use tracing::debug;
+
+use burble_const::Unit;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{
+    Builder, Characteristic, Db, Description, Format, Io, IoReq, IoResult, NotifyReq, Prop, Service,
+};
+use crate::SyncMutex;
+
+/// Battery service instance. Clones refer to the same instance.
+///
+/// Battery Level notifications are sent to all subscribed clients when the
+/// level changes by at least the configured delta or when the configured
+/// interval has elapsed since the last notification. Client Characteristic
+/// Configuration descriptor values of bonded clients are persisted by the
+/// server, which re-enables notifications when such clients reconnect.
 #[derive(Clone, Debug, Default)]
-#[non_exhaustive]
-pub struct BatteryService;
+#[repr(transparent)]
+pub struct BatteryService(Arc<SyncMutex<State>>);
 
 impl BatteryService {
-    /// Creates a battery service.
-    #[inline(always)]
+    /// Creates a battery service with a level of 100%.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum level change that triggers a notification. The
+    /// default delta of 1 notifies clients of every change.
+    #[inline]
+    #[must_use]
+    pub fn with_delta(self, delta: u8) -> Self {
+        self.0.lock().delta = delta.max(1);
+        self
+    }
+
+    /// Sets the interval after which any level change triggers a
+    /// notification, even if it is smaller than the delta.
+    #[inline]
+    #[must_use]
+    pub fn with_interval(self, interval: Duration) -> Self {
+        self.0.lock().interval = Some(interval);
+        self
+    }
+
+    /// Adds a Characteristic Presentation Format descriptor to the Battery
+    /// Level characteristic. This is required to distinguish between multiple
+    /// service instances ([BAS] Section 3.1.2.1).
+    #[inline]
+    #[must_use]
+    pub fn with_description(self, desc: Description) -> Self {
+        self.0.lock().desc = Some(desc);
+        self
+    }
+
+    /// Returns the current battery level in percent.
+    #[inline]
     #[must_use]
-    pub const fn new() -> Self {
-        Self
+    pub fn level(&self) -> u8 {
+        self.0.lock().level
+    }
+
+    /// Sets the battery level, clamping it to 100%, and notifies subscribed
+    /// clients if the change satisfies the delta or interval requirement.
+    #[inline]
+    pub fn set_level(&self, level: u8) {
+        self.0.lock().set_level(level, Instant::now());
     }
 
-    /// Defines the service structure.
-    pub fn define(self, db: &mut Builder<Db>, perms: impl Into<Perms>) -> Handle {
-        let p = perms.into();
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for reading the battery level and configuring
+    /// notifications.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let desc = self.0.lock().desc;
         let (hdl, _) = db.primary_service(Service::Battery, [], |db| {
-            use Characteristic::*;
-            db.ro_characteristic(BatteryLevel, p, [100], |_| {});
+            // Battery Level ([BAS] Section 3.1)
+            db.characteristic(
+                Characteristic::BatteryLevel,
+                Prop::READ | Prop::NOTIFY,
+                ac,
+                Io::with(&self.0, |this, req| this.lock().level_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                    if let Some(desc) = desc {
+                        db.presentation_fmt(Format::U8, 0, Unit::Percent, desc);
+                    }
+                },
+            );
         });
         hdl
     }
 }
+
+/// Battery service state.
+#[derive(Debug)]
+struct State {
+    level: u8,
+    delta: u8,
+    interval: Option<Duration>,
+    desc: Option<Description>,
+    last: Option<(u8, Instant)>,
+    ntf: Vec<NotifyReq>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            level: 100,
+            delta: 1,
+            interval: None,
+            desc: None,
+            last: None,
+            ntf: Vec::new(),
+        }
+    }
+}
+
+impl State {
+    /// Handles Battery Level characteristic I/O.
+    fn level_io(&mut self, req: IoReq) -> IoResult {
+        match req {
+            IoReq::Read(r) => r.complete([self.level]),
+            IoReq::Write(_) => Err(ErrorCode::WriteNotPermitted),
+            IoReq::Notify(n) => {
+                if n.is_indicate() {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                self.ntf.retain(|n| !n.is_closed());
+                self.ntf.push(n);
+                Ok(())
+            }
+        }
+    }
+
+    /// Updates the battery level and sends notifications if required.
+    fn set_level(&mut self, level: u8, now: Instant) {
+        self.level = level.min(100);
+        if !self.should_notify(now) {
+            return;
+        }
+        self.last = Some((self.level, now));
+        let level = self.level;
+        self.ntf.retain(|n| {
+            n.notify_dropping_if_full(|p| {
+                p.u8(level);
+            })
+            .map_err(|e| debug!("Battery level notify error: {e}"))
+            .is_ok()
+        });
+    }
+
+    /// Returns whether the current level should be sent to the clients.
+    fn should_notify(&self, now: Instant) -> bool {
+        let Some((last, t)) = self.last else { return true };
+        if self.level == last {
+            return false;
+        }
+        self.level.abs_diff(last) >= self.delta
+            || (self.interval).map_or(false, |i| now.saturating_duration_since(t) >= i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use burble_const::Descriptor;
+
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn battery_level() {
+        let bas = BatteryService::new()
+            .with_delta(5)
+            .with_interval(Duration::from_secs(60));
+        let t = TestDb::new(|db| bas.define(db, Access::READ));
+        let c = t.characteristics().next().unwrap();
+        assert_eq!(c.uuid(), Characteristic::BatteryLevel);
+        assert_eq!(c.properties(), Prop::READ | Prop::NOTIFY);
+
+        let read = || t.read(c.value_handle()).unwrap();
+        assert_eq!(read(), [100]);
+        bas.set_level(150);
+        assert_eq!(bas.level(), 100);
+
+        // Enable notifications
+        let (tx, mut rx) = mpsc::channel(4);
+        let ct = CancellationToken::new();
+        let req = NotifyReq {
+            hdl: c.value_handle(),
+            uuid: c.uuid(),
+            mtu: 23,
+            ind: false,
+            tx,
+            ct: ct.clone(),
+        };
+        t.io.notify(req).unwrap();
+
+        // Delta and interval
+        let now = Instant::now();
+        let mut set = |v: u8, after: u64| {
+            (bas.0.lock()).set_level(v, now + Duration::from_secs(after));
+            rx.try_recv().ok().map(|v| v.as_ref().to_vec())
+        };
+        assert_eq!(set(99, 0), None);
+        assert_eq!(set(95, 1), Some(vec![95]));
+        assert_eq!(set(93, 2), None);
+        assert_eq!(set(93, 62), Some(vec![93]));
+        assert_eq!(set(93, 200), None);
+        assert_eq!(read(), [93]);
+
+        // Closed sessions are removed
+        ct.cancel();
+        assert_eq!(set(50, 200), None);
+        assert!(bas.0.lock().ntf.is_empty());
+    }
+
+    #[test]
+    fn multiple_instances() {
+        let t = TestDb::new(|db| {
+            BatteryService::new()
+                .with_description(Description::Main)
+                .define(db, Access::READ);
+            BatteryService::new()
+                .with_description(Description::Backup)
+                .define(db, Access::READ);
+        });
+        let desc: Vec<_> = (t.db.iter())
+            .filter(|&(_, uuid, _)| uuid == Descriptor::CharacteristicPresentationFormat)
+            .map(|(_, _, v)| v.to_vec())
+            .collect();
+        let fmt = |d: Description| {
+            let (u, d) = (
+                u16::from(Unit::Percent).to_le_bytes(),
+                d.raw().to_le_bytes(),
+            );
+            vec![Format::U8 as u8, 0, u[0], u[1], 0x01, d[0], d[1]]
+        };
+        assert_eq!(desc, [fmt(Description::Main), fmt(Description::Backup)]);
+    }
+}
@@ -0,0 +1,367 @@
+//! Body Composition Service ([BCS]).
+//!
+//! This service exposes body composition measurements, such as body fat
+//! percentage, from a body composition analyzer. Measurements that do not fit
+//! into a single indication are split into multiple packets.
+//!
+//! [BCS]: https://www.bluetooth.com/specifications/specs/body-composition-service-1-0/
+
+use std::sync::Arc;
+
+use structbuf::Packer;
+
+use crate::att::{Access, ErrorCode, Handle, Result};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+use super::cts::ExactTime256;
+use super::wss::{fixed, height, mass, pack_time_user, HeightResolution, MassResolution, Units};
+
+/// Body composition service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct BodyCompositionService(Arc<SyncMutex<State>>);
+
+impl BodyCompositionService {
+    /// Creates a body composition service.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the supported features.
+    #[inline]
+    #[must_use]
+    pub fn with_features(self, f: Features) -> Self {
+        self.0.lock().features = f;
+        self
+    }
+
+    /// Sets the mass and height measurement resolutions.
+    #[inline]
+    #[must_use]
+    pub fn with_resolution(self, mass: MassResolution, height: HeightResolution) -> Self {
+        self.0.lock().res = (mass, height);
+        self
+    }
+
+    /// Returns the Body Composition Feature characteristic value
+    /// ([BCS] Section 3.1.1).
+    #[must_use]
+    pub fn features(&self) -> u32 {
+        let s = self.0.lock();
+        let (mass, height) = s.res;
+        s.features.bits() | (mass as u32) << 11 | (height as u32) << 15
+    }
+
+    /// Indicates a body composition measurement to the client, using multiple
+    /// indications if the measurement does not fit into one. Returns
+    /// `Ok(false)` if the client has not enabled indications.
+    pub async fn indicate(&self, m: &BodyCompositionMeasurement) -> Result<bool> {
+        let ind: Vec<_> = {
+            let s = self.0.lock();
+            let Some(n) = s.ind.as_ref().filter(|n| !n.is_closed()) else { return Ok(false) };
+            (m.packets(n.max_len()).into_iter())
+                .map(|f| n.notify(|p| m.pack(p, f)))
+                .collect()
+        };
+        for ind in ind {
+            ind.await?;
+        }
+        Ok(true)
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let feat = self.features().to_le_bytes();
+        let (hdl, ()) = db.primary_service(Service::BodyComposition, [], |db| {
+            use Characteristic::*;
+            // Body Composition Feature ([BCS] Section 3.1)
+            db.ro_characteristic(BodyCompositionFeature, ac.read(), feat, |_| {});
+
+            // Body Composition Measurement ([BCS] Section 3.2)
+            db.characteristic(
+                BodyCompositionMeasurement,
+                Prop::INDICATE,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().measurement_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+        });
+        hdl
+    }
+}
+
+/// Body composition service state.
+#[derive(Debug, Default)]
+struct State {
+    features: Features,
+    res: (MassResolution, HeightResolution),
+    ind: Option<NotifyReq>,
+}
+
+impl State {
+    /// Handles Body Composition Measurement characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if !n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ind = Some(n);
+        Ok(())
+    }
+}
+
+bitflags::bitflags! {
+    /// Body Composition Feature characteristic flags ([BCS] Section 3.1.1).
+    /// The measurement resolutions are set separately.
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct Features: u32 {
+        const TIME_STAMP = 1 << 0;
+        const MULTIPLE_USERS = 1 << 1;
+        const BASAL_METABOLISM = 1 << 2;
+        const MUSCLE_PERCENTAGE = 1 << 3;
+        const MUSCLE_MASS = 1 << 4;
+        const FAT_FREE_MASS = 1 << 5;
+        const SOFT_LEAN_MASS = 1 << 6;
+        const BODY_WATER_MASS = 1 << 7;
+        const IMPEDANCE = 1 << 8;
+        const WEIGHT = 1 << 9;
+        const HEIGHT = 1 << 10;
+    }
+}
+
+/// Body Composition Measurement characteristic value ([BCS] Section 3.2.1).
+/// Masses are in kilograms or pounds and height is in meters or inches,
+/// depending on `units`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BodyCompositionMeasurement {
+    /// Measurement units.
+    pub units: Units,
+    /// Body fat percentage. NaN indicates an unsuccessful measurement.
+    pub body_fat: f32,
+    /// Measurement time. The `weekday` and `fractions256` fields are not
+    /// transferred.
+    pub time: Option<ExactTime256>,
+    /// User index. [`super::wss::UNKNOWN_USER`] indicates an unknown user.
+    pub user: Option<u8>,
+    /// Basal metabolism in kilojoules.
+    pub basal_metabolism: Option<u16>,
+    /// Muscle percentage.
+    pub muscle_percentage: Option<f32>,
+    /// Muscle mass.
+    pub muscle_mass: Option<f32>,
+    /// Fat free mass.
+    pub fat_free_mass: Option<f32>,
+    /// Soft lean mass.
+    pub soft_lean_mass: Option<f32>,
+    /// Body water mass.
+    pub body_water_mass: Option<f32>,
+    /// Impedance in ohms.
+    pub impedance: Option<f32>,
+    /// Weight.
+    pub weight: Option<f32>,
+    /// Height.
+    pub height: Option<f32>,
+}
+
+impl BodyCompositionMeasurement {
+    const IMPERIAL: u16 = 1 << 0;
+    const TIME_STAMP: u16 = 1 << 1;
+    const USER: u16 = 1 << 2;
+    const BASAL_METABOLISM: u16 = 1 << 3;
+    const MULTIPLE_PACKET: u16 = 1 << 12;
+
+    /// Returns the flags of each packet required to transfer the measurement
+    /// in values of at most `max_len` bytes ([BCS] Section 3.2.1). Each
+    /// packet contains the flags and body fat percentage, followed by as many
+    /// of the remaining fields as fit.
+    fn packets(&self, max_len: usize) -> Vec<u16> {
+        let all = self.flags();
+        let (units, fields) = (all & Self::IMPERIAL, all & !Self::IMPERIAL);
+        if Self::len(fields) <= max_len {
+            return vec![units | fields];
+        }
+        let mut pkts = Vec::with_capacity(2);
+        let mut f = 0;
+        for i in 1..Self::MULTIPLE_PACKET.trailing_zeros() {
+            let field = fields & 1 << i;
+            if field == 0 {
+                continue;
+            }
+            if f != 0 && Self::len(f | field) > max_len {
+                pkts.push(units | f | Self::MULTIPLE_PACKET);
+                f = 0;
+            }
+            f |= field;
+        }
+        pkts.push(units | f | Self::MULTIPLE_PACKET);
+        pkts
+    }
+
+    /// Returns the flags of all fields that are present.
+    fn flags(&self) -> u16 {
+        let flag = |present: bool, f: u16| if present { f } else { 0 };
+        let values = (self.values().iter().enumerate()).fold(0, |f, (i, v)| {
+            f | flag(v.is_some(), Self::BASAL_METABOLISM << i)
+        });
+        flag(self.units == Units::Imperial, Self::IMPERIAL)
+            | flag(self.time.is_some(), Self::TIME_STAMP)
+            | flag(self.user.is_some(), Self::USER)
+            | values
+    }
+
+    /// Returns the packed length of a packet containing the fields in `f`.
+    const fn len(f: u16) -> usize {
+        let time = if f & Self::TIME_STAMP != 0 { 7 } else { 0 };
+        let user = if f & Self::USER != 0 { 1 } else { 0 };
+        let values = (f & 0x0FF8).count_ones() as usize * 2;
+        2 + 2 + time + user + values
+    }
+
+    /// Returns the encoded values of the optional 16-bit fields in transfer
+    /// order, starting with basal metabolism.
+    fn values(&self) -> [Option<u16>; 9] {
+        let u = self.units;
+        let mass = |v: Option<f32>| v.map(|v| mass(u, v));
+        let pct = |v: Option<f32>| v.map(|v| fixed(v, 0.1));
+        [
+            self.basal_metabolism,
+            pct(self.muscle_percentage),
+            mass(self.muscle_mass),
+            mass(self.fat_free_mass),
+            mass(self.soft_lean_mass),
+            mass(self.body_water_mass),
+            pct(self.impedance),
+            mass(self.weight),
+            self.height.map(|v| height(u, v)),
+        ]
+    }
+
+    /// Packs a measurement packet containing the fields selected by flags
+    /// `f`.
+    fn pack(&self, p: &mut Packer, f: u16) {
+        let has = |flag: u16| f & flag != 0;
+        p.u16(f).u16(fixed(self.body_fat, 0.1));
+        let time = self.time.filter(|_| has(Self::TIME_STAMP));
+        pack_time_user(p, time, self.user.filter(|_| has(Self::USER)));
+        for (i, v) in self.values().into_iter().enumerate() {
+            if let Some(v) = v.filter(|_| has(Self::BASAL_METABOLISM << i)) {
+                p.u16(v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    fn full() -> BodyCompositionMeasurement {
+        BodyCompositionMeasurement {
+            units: Units::Si,
+            body_fat: 20.5,
+            time: Some(ExactTime256::from_unix(1_680_000_000, 0)),
+            user: Some(1),
+            basal_metabolism: Some(6000),
+            muscle_percentage: Some(40.0),
+            muscle_mass: Some(30.0),
+            fat_free_mass: Some(55.0),
+            soft_lean_mass: Some(50.0),
+            body_water_mass: Some(40.0),
+            impedance: Some(500.0),
+            weight: Some(70.0),
+            height: Some(1.75),
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let m = BodyCompositionMeasurement::default();
+        assert_eq!(m.packets(20), [0x0000]);
+        let m = BodyCompositionMeasurement {
+            units: Units::Imperial,
+            weight: Some(154.0),
+            ..m
+        };
+        assert_eq!(m.packets(20), [0x0401]);
+
+        let m = full();
+        assert_eq!(m.packets(30), [0x0FFE]);
+        assert_eq!(m.packets(20), [0x107E, 0x1F80]);
+        assert_eq!(m.packets(11), [0x1002, 0x103C, 0x11C0, 0x1E00]);
+        let m = BodyCompositionMeasurement {
+            units: Units::Imperial,
+            ..m
+        };
+        assert_eq!(m.packets(20), [0x107F, 0x1F81]);
+
+        let mut b = StructBuf::new(20);
+        full().pack(&mut b.append(), 0x1F80);
+        assert_eq!(
+            b.as_ref(),
+            [0x80, 0x1F, 0xCD, 0x00, 0x10, 0x27, 0x40, 0x1F, 0x88, 0x13, 0xB0, 0x36, 0xD6, 0x06]
+        );
+        let mut b = StructBuf::new(20);
+        full().pack(&mut b.append(), 0x1006);
+        assert_eq!(
+            b.as_ref(),
+            [0x06, 0x10, 0xCD, 0x00, 0xE7, 0x07, 0x03, 0x1C, 0x0A, 0x28, 0x00, 0x01]
+        );
+    }
+
+    #[tokio::test]
+    async fn service() {
+        let bcs = BodyCompositionService::new()
+            .with_features(Features::TIME_STAMP | Features::WEIGHT)
+            .with_resolution(MassResolution::R5g, HeightResolution::Unspecified);
+        assert_eq!(bcs.features(), 1 << 0 | 1 << 9 | 7 << 11);
+        let t = TestDb::new(|db| bcs.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (feat, meas) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(t.db.get(feat.value_handle()).unwrap().1, [0x01, 0x3A, 0, 0]);
+        assert_eq!(meas.properties(), Prop::INDICATE);
+
+        assert!(!bcs.indicate(&full()).await.unwrap());
+        let (tx, mut rx) = mpsc::channel(1);
+        let req = NotifyReq {
+            hdl: meas.value_handle(),
+            uuid: meas.uuid(),
+            mtu: 23,
+            ind: true,
+            tx,
+            ct: CancellationToken::new(),
+        };
+        t.io.notify(req).unwrap();
+        let confirm = async {
+            let mut flags = Vec::new();
+            for _ in 0..2 {
+                let v = timeout(Duration::from_secs(1), rx.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                flags.push(u16::from_le_bytes([v.as_ref()[0], v.as_ref()[1]]));
+                v.result(Ok(()));
+            }
+            flags
+        };
+        let m = full();
+        let (r, flags) = tokio::join!(bcs.indicate(&m), confirm);
+        assert!(r.unwrap());
+        assert_eq!(flags, [0x107E, 0x1F80]);
+    }
+}
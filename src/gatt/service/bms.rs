@@ -0,0 +1,254 @@
+//! Bond Management Service ([BMS]).
+//!
+//! This service allows a GATT client to delete bonding information stored by
+//! the server. Only the LE transport operations are supported.
+//!
+//! [BMS]: https://www.bluetooth.com/specifications/specs/bond-management-service-1-0-1/
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, Prop, Service, WriteReq};
+use crate::{smp, SyncMutex};
+
+/// Bond Management service instance. Clones refer to the same instance.
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct BondManagementService(Arc<SyncMutex<State>>);
+
+impl BondManagementService {
+    /// Creates a bond management service that deletes bonds from `store`. No
+    /// operations are enabled by default.
+    #[inline]
+    #[must_use]
+    pub fn new(store: Arc<smp::KeyStore>) -> Self {
+        Self(Arc::new(SyncMutex::new(State {
+            store,
+            ops: BTreeMap::new(),
+        })))
+    }
+
+    /// Enables the specified operation without authorization.
+    #[inline]
+    #[must_use]
+    pub fn with_op(self, op: BondOp) -> Self {
+        self.0.lock().ops.insert(op, None);
+        self
+    }
+
+    /// Enables the specified operation, which requires the client to provide
+    /// the authorization `code` ([BMS] Section 3.1.2.3).
+    #[inline]
+    #[must_use]
+    pub fn with_authorized_op(self, op: BondOp, code: impl Into<String>) -> Self {
+        self.0.lock().ops.insert(op, Some(code.into()));
+        self
+    }
+
+    /// Returns the Bond Management Feature characteristic value, which is
+    /// derived from the enabled operations ([BMS] Section 3.2).
+    #[must_use]
+    pub fn features(&self) -> u32 {
+        (self.0.lock().ops.iter()).fold(0, |f, (op, code)| f | op.feature(code.is_some()))
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let feat = self.features().to_le_bytes();
+        let (hdl, ()) = db.primary_service(Service::BondManagement, [], |db| {
+            use Characteristic::*;
+            // Bond Management Control Point ([BMS] Section 3.1)
+            db.characteristic(
+                BondManagementControlPoint,
+                Prop::WRITE,
+                ac.write(),
+                Io::with(&self.0, |this, req| this.lock().control_point_io(req)),
+                |_| {},
+            );
+
+            // Bond Management Feature ([BMS] Section 3.2)
+            db.ro_characteristic(BondManagementFeature, ac.read(), &feat[..3], |_| {});
+        });
+        hdl
+    }
+}
+
+/// Bond management service state.
+#[derive(Debug)]
+struct State {
+    store: Arc<smp::KeyStore>,
+    ops: BTreeMap<BondOp, Option<String>>,
+}
+
+impl State {
+    /// Handles Bond Management Control Point characteristic I/O
+    /// ([BMS] Section 3.1.2).
+    #[allow(clippy::needless_pass_by_value)]
+    fn control_point_io(&self, req: IoReq) -> IoResult {
+        let IoReq::Write(w) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if w.offset() != 0 {
+            return Err(ErrorCode::InvalidOffset);
+        }
+        let Some((&op, code)) = w.value().split_first() else {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        };
+        let Some((op, want)) =
+            (BondOp::try_from(op).ok()).and_then(|op| self.ops.get_key_value(&op))
+        else {
+            return Err(ErrorCode::ControlPointNotSupported);
+        };
+        if want.as_ref().map_or(false, |want| want.as_bytes() != code) {
+            warn!("Invalid authorization code for {op:?} from {}", w.peer());
+            return Err(ErrorCode::InsufficientAuthorization);
+        }
+        self.exec(*op, w)
+    }
+
+    /// Executes a bond management operation.
+    fn exec(&self, op: BondOp, w: &WriteReq) -> IoResult {
+        debug!("{op:?} requested by {}", w.peer());
+        let ok = match op {
+            BondOp::DeleteRequester => {
+                self.store.remove(w.peer());
+                self.store.load(w.peer()).is_none()
+            }
+            BondOp::DeleteAll => {
+                self.store.clear();
+                self.store.peers().is_empty()
+            }
+            BondOp::DeleteOthers => {
+                for peer in self.store.peers() {
+                    if peer != w.peer() {
+                        self.store.remove(peer);
+                    }
+                }
+                self.store.peers().iter().all(|&peer| peer == w.peer())
+            }
+        };
+        ok.then_some(()).ok_or(ErrorCode::OperationFailed)
+    }
+}
+
+/// Bond Management Control Point operation over the LE transport
+/// ([BMS] Section 3.1.2.1).
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, num_enum::TryFromPrimitive)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum BondOp {
+    /// Delete the bond of the requesting device.
+    DeleteRequester = 0x03,
+    /// Delete all bonds on the server.
+    DeleteAll = 0x06,
+    /// Delete all bonds on the server except that of the requesting device.
+    DeleteOthers = 0x09,
+}
+
+impl BondOp {
+    /// Returns the Bond Management Feature bit for the operation
+    /// ([BMS] Section 3.2.1).
+    #[inline]
+    #[must_use]
+    const fn feature(self, authz: bool) -> u32 {
+        let bit = match self {
+            Self::DeleteRequester => 4,
+            Self::DeleteAll => 10,
+            Self::DeleteOthers => 16,
+        };
+        1 << (bit + authz as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::att::Opcode;
+    use crate::gatt::service::test::TestDb;
+    use crate::le::{Addr, RawAddr};
+    use crate::PeerStore;
+
+    use super::*;
+
+    /// Store that only tracks which peers have keys.
+    #[derive(Debug, Default)]
+    struct Store(SyncMutex<BTreeSet<Addr>>);
+
+    impl PeerStore for Store {
+        type Value = smp::Keys;
+
+        fn save(&self, peer: Addr, _: &Self::Value) -> bool {
+            self.0.lock().insert(peer);
+            true
+        }
+
+        fn load(&self, peer: Addr) -> Option<Self::Value> {
+            self.0.lock().contains(&peer).then(smp::Keys::test)
+        }
+
+        fn remove(&self, peer: Addr) {
+            self.0.lock().remove(&peer);
+        }
+
+        fn clear(&self) {
+            self.0.lock().clear();
+        }
+
+        fn peers(&self) -> Vec<Addr> {
+            self.0.lock().iter().copied().collect()
+        }
+    }
+
+    #[test]
+    fn features() {
+        let bms = BondManagementService::new(Arc::new(Store::default()));
+        assert_eq!(bms.features(), 0);
+        let bms = bms
+            .with_op(BondOp::DeleteRequester)
+            .with_authorized_op(BondOp::DeleteAll, "1234");
+        assert_eq!(bms.features(), 1 << 4 | 1 << 11);
+        let bms = bms.with_op(BondOp::DeleteOthers);
+        assert_eq!(bms.features(), 1 << 4 | 1 << 11 | 1 << 16);
+    }
+
+    #[test]
+    fn service() {
+        let store = Arc::new(Store::default());
+        let peer = |i| Addr::Public(RawAddr::from_le_bytes([i; 6]));
+        for i in 0..4 {
+            assert!(store.save(peer(i), &smp::Keys::test()));
+        }
+        let bms = BondManagementService::new(Arc::clone(&store) as _)
+            .with_op(BondOp::DeleteRequester)
+            .with_authorized_op(BondOp::DeleteOthers, "secret");
+        let t = TestDb::new(|db| bms.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (cp, feat) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(cp.uuid(), Characteristic::BondManagementControlPoint);
+        assert_eq!(cp.properties(), Prop::WRITE);
+        assert_eq!(t.db.get(feat.value_handle()).unwrap().1, [0x10, 0x00, 0x02]);
+
+        let write = |i: u8, v: &[u8]| t.write_as(peer(i), Opcode::WriteReq, cp.value_handle(), v);
+        assert_eq!(write(0, &[]), Err(ErrorCode::InvalidAttributeValueLength));
+        assert_eq!(write(0, &[0x01]), Err(ErrorCode::ControlPointNotSupported));
+        assert_eq!(write(0, &[0x06]), Err(ErrorCode::ControlPointNotSupported));
+        assert_eq!(store.peers().len(), 4);
+
+        // Delete bond of requesting device
+        write(0, &[0x03]).unwrap();
+        assert_eq!(store.peers(), [peer(1), peer(2), peer(3)]);
+
+        // Delete all but the active bond
+        assert_eq!(write(1, &[0x09]), Err(ErrorCode::InsufficientAuthorization));
+        assert_eq!(
+            write(1, b"\x09wrong"),
+            Err(ErrorCode::InsufficientAuthorization)
+        );
+        assert_eq!(store.peers().len(), 3);
+        write(1, b"\x09secret").unwrap();
+        assert_eq!(store.peers(), [peer(1)]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Control point characteristic support.
+//!
+//! A control point is a characteristic that the client writes to request a
+//! procedure. The first byte of the request is the op code, and the result is
+//! indicated to the client as a response op code, the request op code, a
+//! response code, and an optional response parameter. Only one procedure may
+//! be in progress at a time; new requests are rejected until the client
+//! confirms the previous response. Services implement [`ControlPointState`] to
+//! dispatch op codes.
+//!
+//! This module also implements the SC Control Point procedures that are shared
+//! by the Cycling and Running Speed and Cadence services ([CSCS] Section 3.4,
+//! [RSCS] Section 3.4).
+//!
+//! [CSCS]: https://www.bluetooth.com/specifications/specs/cycling-speed-and-cadence-service-1-0/
+//! [RSCS]: https://www.bluetooth.com/specifications/specs/running-speed-and-cadence-service-1-0/
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use tracing::debug;
+
+use crate::att::ErrorCode;
+use crate::gatt::{IoReq, IoResult, NotifyReq};
+use crate::SyncMutex;
+
+use super::cscs::SensorLocation;
+
+/// Service state that executes control point procedures.
+pub(super) trait ControlPointState: Send + 'static {
+    /// Response code type.
+    type Code: Copy + Debug + Into<u8>;
+
+    /// Returns the control point state.
+    fn control_point(&mut self) -> &mut ControlPoint;
+
+    /// Executes procedure `op` with parameter `param`, returning the response
+    /// code and parameter.
+    fn exec(&mut self, op: u8, param: &[u8]) -> (Self::Code, Vec<u8>);
+}
+
+/// Control point state.
+#[derive(Debug)]
+pub(super) struct ControlPoint {
+    name: &'static str,
+    rsp_op: u8,
+    ind: Option<NotifyReq>,
+    busy: bool,
+}
+
+impl ControlPoint {
+    /// Creates a control point that responds with the `rsp_op` op code. `name`
+    /// is used for logging.
+    #[inline]
+    #[must_use]
+    pub(super) const fn new(name: &'static str, rsp_op: u8) -> Self {
+        Self {
+            name,
+            rsp_op,
+            ind: None,
+            busy: false,
+        }
+    }
+
+    /// Creates an SC Control Point.
+    #[inline]
+    #[must_use]
+    pub(super) const fn sc() -> Self {
+        Self::new("SC Control Point", ScOp::RESPONSE)
+    }
+
+    /// Handles control point characteristic I/O. The response is indicated to
+    /// the client, and any new requests are rejected until the indication is
+    /// confirmed.
+    #[inline]
+    pub(super) fn io<T: ControlPointState>(this: &Arc<SyncMutex<T>>, req: IoReq) -> IoResult {
+        Self::io_with(this, req, T::control_point, T::exec)
+    }
+
+    /// Handles control point characteristic I/O for services with more than
+    /// one control point. `cp` returns the control point state and `exec`
+    /// executes its procedures, as in [`ControlPointState`].
+    pub(super) fn io_with<T: Send + 'static, C: Copy + Debug + Into<u8>>(
+        this: &Arc<SyncMutex<T>>,
+        req: IoReq,
+        cp: fn(&mut T) -> &mut Self,
+        exec: fn(&mut T, u8, &[u8]) -> (C, Vec<u8>),
+    ) -> IoResult {
+        let mut s = this.lock();
+        let w = match req {
+            IoReq::Read(_) => return Err(ErrorCode::ReadNotPermitted),
+            IoReq::Write(w) => w,
+            IoReq::Notify(n) => {
+                if !n.is_indicate() {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                cp(&mut *s).ind = Some(n);
+                return Ok(());
+            }
+        };
+        if w.offset() != 0 {
+            return Err(ErrorCode::InvalidOffset);
+        }
+        let c = cp(&mut *s);
+        if !(c.ind.as_ref()).map_or(false, |n| !n.is_closed()) {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        if c.busy {
+            return Err(ErrorCode::ProcedureAlreadyInProgress);
+        }
+        let Some((&op, param)) = w.value().split_first() else {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        };
+        let (code, rsp) = exec(&mut *s, op, param);
+        let c = cp(&mut *s);
+        debug!("{} request {op:#04X}: {code:?}", c.name);
+        let Some(ind) = c.ind.as_ref().map(|n| {
+            n.notify(|p| {
+                p.u8(c.rsp_op).u8(op).u8(code.into()).put(rsp);
+            })
+        }) else {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        };
+        c.busy = true;
+        let name = c.name;
+        let this = Arc::clone(this);
+        tokio::spawn(async move {
+            if let Err(e) = ind.await {
+                debug!("{name} response error: {e}");
+            }
+            cp(&mut *this.lock()).busy = false;
+        });
+        Ok(())
+    }
+}
+
+impl Default for ControlPoint {
+    /// Creates an SC Control Point.
+    #[inline]
+    fn default() -> Self {
+        Self::sc()
+    }
+}
+
+/// SC Control Point op codes ([CSCS] Section 3.4.2).
+pub(super) struct ScOp;
+
+impl ScOp {
+    pub(super) const SET_CUMULATIVE_VALUE: u8 = 0x01;
+    pub(super) const START_SENSOR_CALIBRATION: u8 = 0x02;
+    pub(super) const UPDATE_SENSOR_LOCATION: u8 = 0x03;
+    pub(super) const REQUEST_SUPPORTED_SENSOR_LOCATIONS: u8 = 0x04;
+    pub(super) const RESPONSE: u8 = 0x10;
+}
+
+/// SC Control Point response value ([CSCS] Section 3.4.2.5).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub(super) enum ScResponse {
+    Success = 0x01,
+    OpCodeNotSupported = 0x02,
+    InvalidParameter = 0x03,
+    OperationFailed = 0x04,
+}
+
+impl From<ScResponse> for u8 {
+    #[inline(always)]
+    fn from(c: ScResponse) -> Self {
+        c as Self
+    }
+}
+
+/// Sensor location state shared by the speed and cadence services.
+#[derive(Debug, Default)]
+pub(super) struct ScLocation {
+    pub(super) loc: Option<SensorLocation>,
+    pub(super) supported: Vec<SensorLocation>,
+}
+
+impl ScLocation {
+    /// Sets the current location `loc`, which the client may change to any of
+    /// the `supported` locations.
+    pub(super) fn set(
+        &mut self,
+        loc: SensorLocation,
+        supported: impl IntoIterator<Item = SensorLocation>,
+    ) {
+        self.loc = Some(loc);
+        self.supported = supported.into_iter().collect();
+        if !self.supported.contains(&loc) {
+            self.supported.push(loc);
+        }
+        self.supported.sort_unstable();
+        self.supported.dedup();
+    }
+
+    /// Returns whether multiple sensor locations are supported.
+    #[inline(always)]
+    #[must_use]
+    pub(super) fn is_multiple(&self) -> bool {
+        !self.supported.is_empty()
+    }
+
+    /// Returns the Sensor Location characteristic value.
+    #[inline]
+    #[must_use]
+    pub(super) fn value(&self) -> u8 {
+        self.loc.map_or(0, |l| l as u8)
+    }
+
+    /// Executes the sensor location procedures. Returns [`None`] for all other
+    /// op codes.
+    pub(super) fn exec(&mut self, op: u8, param: &[u8]) -> Option<(ScResponse, Vec<u8>)> {
+        if !self.is_multiple() {
+            return None;
+        }
+        match op {
+            ScOp::UPDATE_SENSOR_LOCATION => {
+                let loc = match *param {
+                    [loc] => SensorLocation::try_from(loc).ok(),
+                    _ => None,
+                };
+                Some(match loc.filter(|loc| self.supported.contains(loc)) {
+                    Some(loc) => {
+                        self.loc = Some(loc);
+                        (ScResponse::Success, Vec::new())
+                    }
+                    None => (ScResponse::InvalidParameter, Vec::new()),
+                })
+            }
+            ScOp::REQUEST_SUPPORTED_SENSOR_LOCATIONS => Some(if param.is_empty() {
+                let locs = self.supported.iter().map(|&l| l as u8).collect();
+                (ScResponse::Success, locs)
+            } else {
+                (ScResponse::InvalidParameter, Vec::new())
+            }),
+            _ => None,
+        }
+    }
+}
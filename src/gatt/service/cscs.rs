@@ -0,0 +1,526 @@
+//! Cycling Speed and Cadence Service ([CSCS]).
+//!
+//! This service exposes wheel and crank revolution data from a cycling speed
+//! and/or cadence sensor. Cumulative revolution counts and event times wrap
+//! around, so collectors must use [`CscMeasurement::wheel_delta`] and
+//! [`CscMeasurement::crank_delta`] (or equivalent modular arithmetic) to
+//! calculate speed and cadence.
+//!
+//! [CSCS]: https://www.bluetooth.com/specifications/specs/cycling-speed-and-cadence-service-1-0/
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use structbuf::{Packer, Unpack};
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+use super::cp::{ControlPoint, ControlPointState, ScLocation, ScOp, ScResponse};
+
+/// Cycling speed and cadence service instance. Clones refer to the same
+/// instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct CyclingSpeedCadenceService(Arc<SyncMutex<State>>);
+
+impl CyclingSpeedCadenceService {
+    /// Creates a cycling speed and cadence service without any revolution
+    /// data.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables wheel revolution data (speed).
+    #[inline]
+    #[must_use]
+    pub fn with_wheel(self) -> Self {
+        self.0.lock().wheel = true;
+        self
+    }
+
+    /// Enables crank revolution data (cadence).
+    #[inline]
+    #[must_use]
+    pub fn with_crank(self) -> Self {
+        self.0.lock().crank = true;
+        self
+    }
+
+    /// Adds the Sensor Location characteristic with a fixed location.
+    #[inline]
+    #[must_use]
+    pub fn with_location(self, loc: SensorLocation) -> Self {
+        self.0.lock().loc.loc = Some(loc);
+        self
+    }
+
+    /// Adds the Sensor Location characteristic with the current location
+    /// `loc`, which the client may change to any of the `supported`
+    /// locations.
+    #[must_use]
+    pub fn with_locations(
+        self,
+        loc: SensorLocation,
+        supported: impl IntoIterator<Item = SensorLocation>,
+    ) -> Self {
+        self.0.lock().loc.set(loc, supported);
+        self
+    }
+
+    /// Returns the CSC Feature characteristic value ([CSCS] Section 3.2.1).
+    #[must_use]
+    pub fn features(&self) -> u16 {
+        self.0.lock().features()
+    }
+
+    /// Returns the current sensor location.
+    #[inline]
+    #[must_use]
+    pub fn location(&self) -> Option<SensorLocation> {
+        self.0.lock().loc.loc
+    }
+
+    /// Records `revs` wheel revolutions that completed at time `t`, which is
+    /// relative to an arbitrary fixed reference, such as the service start
+    /// time. The cumulative value wraps around at 2^32.
+    pub fn wheel_event(&self, revs: u32, t: Duration) {
+        let mut s = self.0.lock();
+        s.wheel_revs = s.wheel_revs.wrapping_add(revs);
+        s.wheel_time = event_time(t);
+    }
+
+    /// Records `revs` crank revolutions that completed at time `t`, which is
+    /// relative to an arbitrary fixed reference. The cumulative value wraps
+    /// around at 2^16.
+    pub fn crank_event(&self, revs: u16, t: Duration) {
+        let mut s = self.0.lock();
+        s.crank_revs = s.crank_revs.wrapping_add(revs);
+        s.crank_time = event_time(t);
+    }
+
+    /// Returns the current measurement.
+    #[must_use]
+    pub fn measurement(&self) -> CscMeasurement {
+        self.0.lock().measurement()
+    }
+
+    /// Notifies the client of the current measurement. This should be called
+    /// periodically, typically once per second.
+    pub fn notify_measurement(&self) {
+        let s = self.0.lock();
+        let Some(n) = s.ntf.as_ref() else { return };
+        let m = s.measurement();
+        if let Err(e) = n.notify_dropping_if_full(|p| m.pack(p)) {
+            debug!("CSC measurement notify error: {e}");
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (feat, loc, cp) = {
+            let s = self.0.lock();
+            (
+                s.features(),
+                s.loc.loc.is_some(),
+                s.wheel || s.loc.is_multiple(),
+            )
+        };
+        let (hdl, ()) = db.primary_service(Service::CyclingSpeedAndCadence, [], |db| {
+            use Characteristic::*;
+            // CSC Measurement ([CSCS] Section 3.1)
+            db.characteristic(
+                CscMeasurement,
+                Prop::NOTIFY,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().measurement_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+
+            // CSC Feature ([CSCS] Section 3.2)
+            db.ro_characteristic(CscFeature, ac.read(), feat.to_le_bytes(), |_| {});
+
+            // Sensor Location ([CSCS] Section 3.3)
+            if loc {
+                db.characteristic(
+                    SensorLocation,
+                    Prop::READ,
+                    ac.read(),
+                    Io::with(&self.0, |this, req| this.lock().location_io(req)),
+                    |_| {},
+                );
+            }
+
+            // SC Control Point ([CSCS] Section 3.4)
+            if cp {
+                db.characteristic(
+                    ScControlPoint,
+                    Prop::WRITE | Prop::INDICATE,
+                    ac.write(),
+                    {
+                        let this = Arc::clone(&self.0);
+                        move |req: IoReq| ControlPoint::io(&this, req)
+                    },
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+        });
+        hdl
+    }
+}
+
+/// Cycling speed and cadence service state.
+#[derive(Debug, Default)]
+struct State {
+    wheel: bool,
+    crank: bool,
+    loc: ScLocation,
+    wheel_revs: u32,
+    wheel_time: u16,
+    crank_revs: u16,
+    crank_time: u16,
+    ntf: Option<NotifyReq>,
+    cp: ControlPoint,
+}
+
+impl State {
+    const WHEEL: u16 = 1 << 0;
+    const CRANK: u16 = 1 << 1;
+    const MULTIPLE_LOCATIONS: u16 = 1 << 2;
+
+    /// Returns the CSC Feature characteristic value.
+    fn features(&self) -> u16 {
+        let flag = |present: bool, f: u16| if present { f } else { 0 };
+        flag(self.wheel, Self::WHEEL)
+            | flag(self.crank, Self::CRANK)
+            | flag(self.loc.is_multiple(), Self::MULTIPLE_LOCATIONS)
+    }
+
+    /// Returns the current measurement.
+    const fn measurement(&self) -> CscMeasurement {
+        CscMeasurement {
+            wheel: if self.wheel {
+                Some((self.wheel_revs, self.wheel_time))
+            } else {
+                None
+            },
+            crank: if self.crank {
+                Some((self.crank_revs, self.crank_time))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Handles CSC Measurement characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ntf = Some(n);
+        Ok(())
+    }
+
+    /// Handles Sensor Location characteristic I/O.
+    #[allow(clippy::needless_pass_by_value)]
+    fn location_io(&self, req: IoReq) -> IoResult {
+        let IoReq::Read(r) = req else { return Err(ErrorCode::RequestNotSupported) };
+        r.complete([self.loc.value()])
+    }
+}
+
+impl ControlPointState for State {
+    type Code = ScResponse;
+
+    #[inline(always)]
+    fn control_point(&mut self) -> &mut ControlPoint {
+        &mut self.cp
+    }
+
+    fn exec(&mut self, op: u8, param: &[u8]) -> (ScResponse, Vec<u8>) {
+        if let Some(r) = self.loc.exec(op, param) {
+            return r;
+        }
+        let code = match op {
+            ScOp::SET_CUMULATIVE_VALUE if self.wheel => match <[u8; 4]>::try_from(param) {
+                Ok(v) => {
+                    self.wheel_revs = u32::from_le_bytes(v);
+                    ScResponse::Success
+                }
+                Err(_) => ScResponse::InvalidParameter,
+            },
+            _ => ScResponse::OpCodeNotSupported,
+        };
+        (code, Vec::new())
+    }
+}
+
+/// Sensor location ([CSCS] Section 3.3.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, num_enum::TryFromPrimitive)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum SensorLocation {
+    Other = 0,
+    TopOfShoe = 1,
+    InShoe = 2,
+    Hip = 3,
+    FrontWheel = 4,
+    LeftCrank = 5,
+    RightCrank = 6,
+    LeftPedal = 7,
+    RightPedal = 8,
+    FrontHub = 9,
+    RearDropout = 10,
+    Chainstay = 11,
+    RearWheel = 12,
+    RearHub = 13,
+    Chest = 14,
+    Spider = 15,
+    ChainRing = 16,
+}
+
+/// CSC Measurement characteristic value ([CSCS] Section 3.1.1). Event times
+/// are in units of 1/1024 seconds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CscMeasurement {
+    /// Cumulative wheel revolutions and last wheel event time.
+    pub wheel: Option<(u32, u16)>,
+    /// Cumulative crank revolutions and last crank event time.
+    pub crank: Option<(u16, u16)>,
+}
+
+impl CscMeasurement {
+    const WHEEL: u8 = 1 << 0;
+    const CRANK: u8 = 1 << 1;
+
+    /// Packs the measurement.
+    pub fn pack(&self, p: &mut Packer) {
+        let flag = |present: bool, f: u8| if present { f } else { 0 };
+        p.u8(flag(self.wheel.is_some(), Self::WHEEL) | flag(self.crank.is_some(), Self::CRANK));
+        if let Some((revs, t)) = self.wheel {
+            p.u32(revs).u16(t);
+        }
+        if let Some((revs, t)) = self.crank {
+            p.u16(revs).u16(t);
+        }
+    }
+
+    /// Unpacks a measurement received from a sensor.
+    #[must_use]
+    pub fn unpack(v: &[u8]) -> Option<Self> {
+        let mut p = v.unpack();
+        let f = p.u8();
+        let wheel = (f & Self::WHEEL != 0).then(|| (p.u32(), p.u16()));
+        let crank = (f & Self::CRANK != 0).then(|| (p.u16(), p.u16()));
+        (p.is_ok() && p.is_empty()).then_some(Self { wheel, crank })
+    }
+
+    /// Returns the number of wheel revolutions and the elapsed time since the
+    /// `prev` measurement. Handles wrap-around of both values, which assumes
+    /// that measurements are no more than 64 seconds apart.
+    #[must_use]
+    pub fn wheel_delta(&self, prev: &Self) -> Option<(u32, Duration)> {
+        let ((r1, t1), (r0, t0)) = (self.wheel?, prev.wheel?);
+        Some((r1.wrapping_sub(r0), event_duration(t1.wrapping_sub(t0))))
+    }
+
+    /// Returns the number of crank revolutions and the elapsed time since the
+    /// `prev` measurement. Handles wrap-around of both values.
+    #[must_use]
+    pub fn crank_delta(&self, prev: &Self) -> Option<(u16, Duration)> {
+        let ((r1, t1), (r0, t0)) = (self.crank?, prev.crank?);
+        Some((r1.wrapping_sub(r0), event_duration(t1.wrapping_sub(t0))))
+    }
+}
+
+/// Converts time `t` to an event time in units of 1/1024 seconds, which wraps
+/// around every 64 seconds.
+#[allow(clippy::cast_possible_truncation)]
+#[inline]
+const fn event_time(t: Duration) -> u16 {
+    (t.as_micros() * 1024 / 1_000_000) as u16
+}
+
+/// Converts an event time difference to a duration.
+#[inline]
+fn event_duration(dt: u16) -> Duration {
+    Duration::from_micros(u64::from(dt) * 1_000_000 / 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+    use crate::gatt::NotifyVal;
+
+    use super::*;
+
+    #[test]
+    fn measurement() {
+        let m = CscMeasurement {
+            wheel: Some((0x0102_0304, 0x0506)),
+            crank: Some((0x0708, 0x090A)),
+        };
+        let mut b = StructBuf::new(11);
+        m.pack(&mut b.append());
+        assert_eq!(
+            b.as_ref(),
+            [0x03, 0x04, 0x03, 0x02, 0x01, 0x06, 0x05, 0x08, 0x07, 0x0A, 0x09]
+        );
+        assert_eq!(CscMeasurement::unpack(b.as_ref()), Some(m));
+        assert_eq!(
+            CscMeasurement::unpack(&[0x02, 0x08, 0x07, 0x0A, 0x09]),
+            Some(CscMeasurement {
+                wheel: None,
+                crank: Some((0x0708, 0x090A)),
+            })
+        );
+        assert_eq!(CscMeasurement::unpack(&[0x01, 0x00]), None);
+        assert_eq!(CscMeasurement::unpack(&[0x00, 0x00]), None);
+
+        // Wrap-around
+        let prev = CscMeasurement {
+            wheel: Some((u32::MAX - 1, u16::MAX - 511)),
+            crank: Some((u16::MAX, u16::MAX)),
+        };
+        let cur = CscMeasurement {
+            wheel: Some((2, 512)),
+            crank: Some((1, 1023)),
+        };
+        assert_eq!(cur.wheel_delta(&prev), Some((4, Duration::from_secs(1))));
+        assert_eq!(cur.crank_delta(&prev), Some((2, Duration::from_secs(1))));
+        assert_eq!(cur.wheel_delta(&CscMeasurement::default()), None);
+
+        assert_eq!(event_time(Duration::from_secs(1)), 1024);
+        assert_eq!(event_time(Duration::from_millis(64_500)), 512);
+    }
+
+    #[test]
+    fn events() {
+        let csc = CyclingSpeedCadenceService::new().with_wheel().with_crank();
+        assert_eq!(csc.features(), 0x03);
+        csc.wheel_event(u32::MAX, Duration::from_secs(1));
+        csc.wheel_event(3, Duration::from_secs(65));
+        csc.crank_event(u16::MAX, Duration::from_secs(2));
+        csc.crank_event(2, Duration::from_millis(2500));
+        assert_eq!(
+            csc.measurement(),
+            CscMeasurement {
+                wheel: Some((2, 1024)),
+                crank: Some((1, 2560)),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn control_point() {
+        use SensorLocation::*;
+        let csc = CyclingSpeedCadenceService::new()
+            .with_wheel()
+            .with_locations(FrontWheel, [RearHub, RearWheel]);
+        assert_eq!(csc.features(), 0x05);
+        let t = TestDb::new(|db| csc.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (meas, feat, loc, cp) = (
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+        );
+        assert_eq!(meas.properties(), Prop::NOTIFY);
+        assert_eq!(t.db.get(feat.value_handle()).unwrap().1, [0x05, 0x00]);
+        assert_eq!(cp.properties(), Prop::WRITE | Prop::INDICATE);
+
+        let read_loc = || t.read(loc.value_handle()).unwrap();
+        let write = |v: &[u8]| t.write(cp.value_handle(), v);
+        assert_eq!(read_loc(), [FrontWheel as u8]);
+        assert_eq!(
+            write(&[0x01, 0, 0, 0, 0]),
+            Err(ErrorCode::CccdImproperlyConfigured)
+        );
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let sub = |ind| {
+            t.io.notify(NotifyReq {
+                hdl: cp.value_handle(),
+                uuid: cp.uuid(),
+                mtu: 23,
+                ind,
+                tx: tx.clone(),
+                ct: CancellationToken::new(),
+            })
+        };
+        assert_eq!(sub(false), Err(ErrorCode::CccdImproperlyConfigured));
+        sub(true).unwrap();
+        assert_eq!(write(&[]), Err(ErrorCode::InvalidAttributeValueLength));
+
+        // Set cumulative value
+        write(&[0x01, 0x04, 0x03, 0x02, 0x01]).unwrap();
+        assert_eq!(write(&[0x04]), Err(ErrorCode::ProcedureAlreadyInProgress));
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x01, 0x01]);
+        assert_eq!(csc.measurement().wheel, Some((0x0102_0304, 0)));
+        write(&[0x01, 0x00]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x01, 0x03]);
+
+        // Request supported sensor locations
+        write(&[0x04]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x04, 0x01, 4, 12, 13]);
+
+        // Update sensor location
+        write(&[0x03, RearWheel as u8]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x03, 0x01]);
+        assert_eq!(csc.location(), Some(RearWheel));
+        assert_eq!(read_loc(), [RearWheel as u8]);
+        write(&[0x03, Chest as u8]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x03, 0x03]);
+        write(&[0x03, 0xFF]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x03, 0x03]);
+        assert_eq!(csc.location(), Some(RearWheel));
+
+        // Unsupported op codes
+        write(&[0x02]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x02, 0x02]);
+        write(&[0x10]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x10, 0x02]);
+    }
+
+    #[test]
+    fn optional_characteristics() {
+        let csc = CyclingSpeedCadenceService::new().with_crank();
+        let t = TestDb::new(|db| csc.define(db, Access::NONE));
+        let uuids: Vec<_> = t.characteristics().map(|c| c.uuid()).collect();
+        assert_eq!(
+            uuids,
+            [Characteristic::CscMeasurement, Characteristic::CscFeature]
+        );
+    }
+
+    /// Receives and confirms the next control point response, and waits for
+    /// the procedure to complete.
+    async fn confirm(rx: &mut mpsc::Receiver<NotifyVal>) -> Vec<u8> {
+        let v = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let r = v.as_ref().to_vec();
+        v.result(Ok(()));
+        tokio::task::yield_now().await;
+        r
+    }
+}
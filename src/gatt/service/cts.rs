@@ -0,0 +1,624 @@
+//! Current Time Service ([CTS]).
+//!
+//! This service exposes the current date and time and, optionally, the local
+//! time zone and daylight savings time offset.
+//!
+//! [CTS]: https://www.bluetooth.com/specifications/specs/current-time-service-1-1/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use structbuf::Unpack;
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+/// Callback for Current Time characteristic writes.
+type WriteFn = Box<dyn FnMut(CurrentTime) -> bool + Send>;
+
+/// Current Time service instance. Clones refer to the same instance.
+///
+/// The current time is obtained from the system clock and adjusted by the
+/// local time information, if any. Notifications are sent at most once per
+/// second, except for manual time changes ([CTS] Section 3.1.2.1).
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct CurrentTimeService(Arc<SyncMutex<State>>);
+
+impl CurrentTimeService {
+    /// Minimum interval between notifications that are not caused by a manual
+    /// time change.
+    const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Creates a current time service.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the Local Time Information characteristic. The time zone and DST
+    /// offsets are also applied to the Current Time characteristic value.
+    #[inline]
+    #[must_use]
+    pub fn with_local_time_info(self, info: LocalTimeInfo) -> Self {
+        self.0.lock().local = Some(info);
+        self
+    }
+
+    /// Enables Current Time characteristic writes. The time written by the
+    /// client is passed to `f`, which returns whether the time was accepted.
+    #[inline]
+    #[must_use]
+    pub fn with_write(self, f: impl FnMut(CurrentTime) -> bool + Send + 'static) -> Self {
+        self.0.lock().write = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the current local time.
+    #[inline]
+    #[must_use]
+    pub fn current_time(&self) -> CurrentTime {
+        self.0.lock().current_time()
+    }
+
+    /// Notifies subscribed clients that the time was adjusted for the
+    /// specified reason. Notifications that are not caused by a manual time
+    /// change are dropped if the previous one was sent less than a second ago.
+    #[inline]
+    pub fn adjusted(&self, reason: AdjustReason) {
+        let mut s = self.0.lock();
+        s.reason = reason;
+        let t = s.current_time();
+        s.notify(t, Instant::now());
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (local, write) = {
+            let s = self.0.lock();
+            (s.local, s.write.is_some())
+        };
+        let (hdl, ()) = db.primary_service(Service::CurrentTime, [], |db| {
+            use Characteristic::*;
+            // Current Time ([CTS] Section 3.1)
+            let (props, perms) = if write {
+                (Prop::READ | Prop::WRITE | Prop::NOTIFY, ac.read_write())
+            } else {
+                (Prop::READ | Prop::NOTIFY, ac.read())
+            };
+            db.characteristic(
+                CurrentTime,
+                props,
+                perms,
+                Io::with(&self.0, |this, req| this.lock().current_time_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+
+            // Local Time Information ([CTS] Section 3.2)
+            if let Some(info) = local {
+                db.ro_characteristic(LocalTimeInformation, ac.read(), info.to_bytes(), |_| {});
+            }
+        });
+        hdl
+    }
+}
+
+/// Current time service state.
+#[derive(Default)]
+struct State {
+    local: Option<LocalTimeInfo>,
+    reason: AdjustReason,
+    last: Option<Instant>,
+    write: Option<WriteFn>,
+    ntf: Vec<NotifyReq>,
+}
+
+impl State {
+    /// Returns the current local time.
+    fn current_time(&self) -> CurrentTime {
+        let off = self.local.map_or(0, LocalTimeInfo::offset);
+        let now = SystemTime::now();
+        let t = if off < 0 {
+            now - Duration::from_secs(u64::from(off.unsigned_abs()))
+        } else {
+            now + Duration::from_secs(off.unsigned_abs().into())
+        };
+        CurrentTime {
+            time: ExactTime256::from(t),
+            reason: self.reason,
+        }
+    }
+
+    /// Handles Current Time characteristic I/O.
+    fn current_time_io(&mut self, req: IoReq) -> IoResult {
+        match req {
+            IoReq::Read(r) => r.complete(self.current_time().to_bytes()),
+            IoReq::Write(w) => {
+                let Some(f) = self.write.as_mut() else {
+                    return Err(ErrorCode::WriteNotPermitted);
+                };
+                if w.offset() != 0 {
+                    return Err(ErrorCode::InvalidOffset);
+                }
+                if w.value().len() != 10 {
+                    return Err(ErrorCode::InvalidAttributeValueLength);
+                }
+                let mut t = CurrentTime::from_bytes(w.value()).ok_or(ErrorCode::ValueNotAllowed)?;
+                t.reason.insert(AdjustReason::MANUAL);
+                if !f(t) {
+                    return Err(ErrorCode::ValueNotAllowed);
+                }
+                self.reason = t.reason;
+                self.notify(t, Instant::now());
+                Ok(())
+            }
+            IoReq::Notify(n) => {
+                if n.is_indicate() {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                self.ntf.retain(|n| !n.is_closed());
+                self.ntf.push(n);
+                Ok(())
+            }
+        }
+    }
+
+    /// Notifies subscribed clients of time `t` subject to rate limiting.
+    fn notify(&mut self, t: CurrentTime, now: Instant) {
+        let manual = t.reason.contains(AdjustReason::MANUAL);
+        let recent = (self.last).map_or(false, |last| {
+            now.saturating_duration_since(last) < CurrentTimeService::NOTIFY_INTERVAL
+        });
+        if recent && !manual {
+            return;
+        }
+        self.last = Some(now);
+        let v = t.to_bytes();
+        self.ntf.retain(|n| {
+            n.notify_dropping_if_full(|p| {
+                p.put(v);
+            })
+            .map_err(|e| debug!("Current time notify error: {e}"))
+            .is_ok()
+        });
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("local", &self.local)
+            .field("reason", &self.reason)
+            .field("last", &self.last)
+            .field("ntf", &self.ntf)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Current Time characteristic value ([CTS] Section 3.1).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CurrentTime {
+    /// Date and time.
+    pub time: ExactTime256,
+    /// Reason for the last time adjustment.
+    pub reason: AdjustReason,
+}
+
+impl CurrentTime {
+    /// Returns the characteristic value.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; 10] {
+        let mut v = [0; 10];
+        v[..9].copy_from_slice(&self.time.to_bytes());
+        v[9] = self.reason.bits();
+        v
+    }
+
+    /// Parses the characteristic value.
+    #[must_use]
+    pub fn from_bytes(v: &[u8]) -> Option<Self> {
+        if v.len() != 10 {
+            return None;
+        }
+        Some(Self {
+            time: ExactTime256::from_bytes(&v[..9])?,
+            reason: AdjustReason::from_bits_truncate(v[9]),
+        })
+    }
+}
+
+/// Exact Time 256 value ([GSS] Section 3.90).
+///
+/// Zero values of `year`, `month`, `day`, and `weekday` mean that the field is
+/// not known.
+///
+/// [GSS]: https://www.bluetooth.com/specifications/gss/
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ExactTime256 {
+    /// Year (1582-9999).
+    pub year: u16,
+    /// Month of the year (1-12).
+    pub month: u8,
+    /// Day of the month (1-31).
+    pub day: u8,
+    /// Hours since midnight (0-23).
+    pub hour: u8,
+    /// Minutes since the start of the hour (0-59).
+    pub minute: u8,
+    /// Seconds since the start of the minute (0-59).
+    pub second: u8,
+    /// Day of the week, starting with Monday (1-7).
+    pub weekday: u8,
+    /// Fractions of a second in units of 1/256 seconds.
+    pub fractions256: u8,
+}
+
+impl ExactTime256 {
+    /// Unix epoch day of the week (Thursday).
+    const EPOCH_WEEKDAY: i64 = 4;
+
+    /// Converts Unix time to a UTC date and time.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    #[must_use]
+    pub fn from_unix(secs: i64, nanos: u32) -> Self {
+        let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year: year.clamp(0, 9999) as u16,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+            weekday: ((days + Self::EPOCH_WEEKDAY - 1).rem_euclid(7) + 1) as u8,
+            fractions256: (u64::from(nanos.min(999_999_999)) * 256 / 1_000_000_000) as u8,
+        }
+    }
+
+    /// Converts the date and time to Unix time, assuming UTC. Returns
+    /// [`None`] if the date is not known.
+    #[allow(clippy::cast_possible_truncation)]
+    #[must_use]
+    pub fn to_unix(&self) -> Option<(i64, u32)> {
+        if self.year == 0 || self.month == 0 || self.day == 0 {
+            return None;
+        }
+        let days = days_from_civil(i64::from(self.year), self.month, self.day);
+        let secs = ((days * 24 + i64::from(self.hour)) * 60 + i64::from(self.minute)) * 60
+            + i64::from(self.second);
+        let nanos = (u64::from(self.fractions256) * 1_000_000_000 / 256) as u32;
+        Some((secs, nanos))
+    }
+
+    /// Returns the characteristic value.
+    #[must_use]
+    pub const fn to_bytes(&self) -> [u8; 9] {
+        let y = self.year.to_le_bytes();
+        [
+            y[0],
+            y[1],
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.weekday,
+            self.fractions256,
+        ]
+    }
+
+    /// Parses the characteristic value.
+    #[must_use]
+    pub fn from_bytes(v: &[u8]) -> Option<Self> {
+        if v.len() != 9 {
+            return None;
+        }
+        let mut v = v.unpack();
+        let t = Self {
+            year: v.u16(),
+            month: v.u8(),
+            day: v.u8(),
+            hour: v.u8(),
+            minute: v.u8(),
+            second: v.u8(),
+            weekday: v.u8(),
+            fractions256: v.u8(),
+        };
+        let valid = (t.year == 0 || (1582..=9999).contains(&t.year))
+            && t.month <= 12
+            && t.day <= 31
+            && t.hour < 24
+            && t.minute < 60
+            && t.second < 60
+            && t.weekday <= 7;
+        valid.then_some(t)
+    }
+}
+
+impl From<SystemTime> for ExactTime256 {
+    #[allow(clippy::cast_possible_wrap)]
+    fn from(t: SystemTime) -> Self {
+        match t.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(d) => Self::from_unix(d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                let (mut secs, mut nanos) = (-(d.as_secs() as i64), d.subsec_nanos());
+                if nanos > 0 {
+                    secs -= 1;
+                    nanos = 1_000_000_000 - nanos;
+                }
+                Self::from_unix(secs, nanos)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for ExactTime256 {
+    /// Converts the date and time in its current offset.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from(t: time::OffsetDateTime) -> Self {
+        Self {
+            year: t.year().clamp(0, 9999) as u16,
+            month: t.month().into(),
+            day: t.day(),
+            hour: t.hour(),
+            minute: t.minute(),
+            second: t.second(),
+            weekday: t.weekday().number_from_monday(),
+            fractions256: (u64::from(t.nanosecond()) * 256 / 1_000_000_000) as u8,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Current time adjust reason ([CTS] Section 3.1.2.1).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct AdjustReason: u8 {
+        /// Manual time update.
+        const MANUAL = 1 << 0;
+        /// External reference time update.
+        const EXTERNAL_REFERENCE = 1 << 1;
+        /// Change of time zone.
+        const TIME_ZONE = 1 << 2;
+        /// Change of DST offset.
+        const DST = 1 << 3;
+    }
+}
+
+/// Local Time Information characteristic value ([CTS] Section 3.2).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LocalTimeInfo {
+    /// Time zone offset from UTC in 15-minute increments or [`None`] if not
+    /// known.
+    pub tz: Option<i8>,
+    /// Daylight savings time offset.
+    pub dst: DstOffset,
+}
+
+impl LocalTimeInfo {
+    /// Creates local time information from the time zone offset in 15-minute
+    /// increments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the offset is outside of the range -48..=56.
+    #[inline]
+    #[must_use]
+    pub const fn new(tz: i8, dst: DstOffset) -> Self {
+        assert!(-48 <= tz && tz <= 56, "invalid time zone offset");
+        Self { tz: Some(tz), dst }
+    }
+
+    /// Returns the total local time offset in seconds.
+    #[inline]
+    #[must_use]
+    pub fn offset(self) -> i32 {
+        let dst = match self.dst {
+            DstOffset::Unknown => 0,
+            dst => i32::from(dst as u8),
+        };
+        (i32::from(self.tz.unwrap_or(0)) + dst) * 15 * 60
+    }
+
+    /// Returns the characteristic value.
+    #[allow(clippy::cast_sign_loss)]
+    #[inline]
+    #[must_use]
+    pub fn to_bytes(self) -> [u8; 2] {
+        [self.tz.unwrap_or(i8::MIN) as u8, self.dst as u8]
+    }
+}
+
+/// Daylight savings time offset ([CTS] Section 3.2.1).
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum DstOffset {
+    /// Standard time.
+    #[default]
+    Standard = 0,
+    /// Half an hour daylight time (+0.5h).
+    HalfHour = 2,
+    /// Daylight time (+1h).
+    Daylight = 4,
+    /// Double daylight time (+2h).
+    DoubleDaylight = 8,
+    /// DST offset is not known.
+    Unknown = 255,
+}
+
+/// Returns the proleptic Gregorian calendar date for the specified number of
+/// days since the Unix epoch.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+const fn civil_from_days(z: i64) -> (i64, u8, u8) {
+    let z = z + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m as u8, d as u8)
+}
+
+/// Returns the number of days since the Unix epoch for the specified
+/// proleptic Gregorian calendar date.
+const fn days_from_civil(y: i64, m: u8, d: u8) -> i64 {
+    let (m, d) = (m as i64, d as i64);
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn exact_time() {
+        let t = |secs, nanos| ExactTime256::from_unix(secs, nanos);
+        let want = |year, month, day, hour, minute, second, weekday, fractions256| ExactTime256 {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            weekday,
+            fractions256,
+        };
+        assert_eq!(t(0, 0), want(1970, 1, 1, 0, 0, 0, 4, 0));
+        assert_eq!(t(-1, 0), want(1969, 12, 31, 23, 59, 59, 3, 0));
+        assert_eq!(t(951_782_400, 0), want(2000, 2, 29, 0, 0, 0, 2, 0));
+        assert_eq!(
+            t(1_678_883_696, 500_000_000),
+            want(2023, 3, 15, 12, 34, 56, 3, 128)
+        );
+        for secs in [-1, 0, 951_782_400, 1_678_883_696, 4_102_444_799] {
+            assert_eq!(t(secs, 0).to_unix(), Some((secs, 0)));
+        }
+
+        let before = SystemTime::UNIX_EPOCH - Duration::from_millis(500);
+        assert_eq!(
+            ExactTime256::from(before),
+            want(1969, 12, 31, 23, 59, 59, 3, 128)
+        );
+
+        let v = t(1_678_883_696, 0).to_bytes();
+        assert_eq!(v, [0xE7, 0x07, 3, 15, 12, 34, 56, 3, 0]);
+        assert_eq!(ExactTime256::from_bytes(&v), Some(t(1_678_883_696, 0)));
+        assert_eq!(
+            ExactTime256::from_bytes(&[0; 9]),
+            Some(ExactTime256::default())
+        );
+        assert_eq!(
+            ExactTime256::from_bytes(&[0xE7, 0x07, 13, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+        assert_eq!(ExactTime256::from_bytes(&v[..8]), None);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn offset_date_time() {
+        let t = time::OffsetDateTime::from_unix_timestamp(1_678_883_696).unwrap();
+        assert_eq!(
+            ExactTime256::from(t),
+            ExactTime256::from_unix(1_678_883_696, 0)
+        );
+    }
+
+    #[test]
+    fn local_time() {
+        let info = LocalTimeInfo::new(-20, DstOffset::Daylight);
+        assert_eq!(info.offset(), -4 * 3600);
+        assert_eq!(info.to_bytes(), [0xEC, 4]);
+        let unknown = LocalTimeInfo {
+            tz: None,
+            dst: DstOffset::Unknown,
+        };
+        assert_eq!(unknown.offset(), 0);
+        assert_eq!(unknown.to_bytes(), [0x80, 0xFF]);
+    }
+
+    #[test]
+    fn service() {
+        let (tx, mut written) = mpsc::unbounded_channel();
+        let cts = CurrentTimeService::new()
+            .with_local_time_info(LocalTimeInfo::new(4, DstOffset::Standard))
+            .with_write(move |t| tx.send(t).is_ok());
+        let t = TestDb::new(|db| cts.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (ct, lti) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(ct.properties(), Prop::READ | Prop::WRITE | Prop::NOTIFY);
+        assert_eq!(t.db.get(lti.value_handle()).unwrap().1, [4, 0]);
+
+        // Enable notifications
+        let (tx, mut rx) = mpsc::channel(4);
+        let req = NotifyReq {
+            hdl: ct.value_handle(),
+            uuid: ct.uuid(),
+            mtu: 23,
+            ind: false,
+            tx,
+            ct: CancellationToken::new(),
+        };
+        t.io.notify(req).unwrap();
+
+        // Rate limiting
+        let cur = CurrentTime {
+            time: ExactTime256::from_unix(0, 0),
+            reason: AdjustReason::EXTERNAL_REFERENCE,
+        };
+        let now = Instant::now();
+        let mut notify = |cur: CurrentTime, after: u64| {
+            (cts.0.lock()).notify(cur, now + Duration::from_millis(after));
+            rx.try_recv().ok().map(|v| v.as_ref().to_vec())
+        };
+        assert_eq!(notify(cur, 0), Some(cur.to_bytes().to_vec()));
+        assert_eq!(notify(cur, 500), None);
+        let manual = CurrentTime {
+            reason: AdjustReason::MANUAL,
+            ..cur
+        };
+        assert_eq!(notify(manual, 600), Some(manual.to_bytes().to_vec()));
+        assert_eq!(notify(cur, 1000), None);
+        assert_eq!(notify(cur, 1600), Some(cur.to_bytes().to_vec()));
+
+        // Write
+        let mut v = cur.to_bytes();
+        let write = |v: &[u8]| t.write(ct.value_handle(), v);
+        assert_eq!(write(&v[..9]), Err(ErrorCode::InvalidAttributeValueLength));
+        v[2] = 13;
+        assert_eq!(write(&v), Err(ErrorCode::ValueNotAllowed));
+        v[2] = 1;
+        write(&v).unwrap();
+        let want = CurrentTime {
+            reason: AdjustReason::MANUAL | AdjustReason::EXTERNAL_REFERENCE,
+            ..cur
+        };
+        assert_eq!(written.try_recv().unwrap(), want);
+        assert_eq!(cts.current_time().reason, want.reason);
+    }
+}
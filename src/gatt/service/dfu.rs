@@ -0,0 +1,526 @@
+//! Device Firmware Update (DFU) service.
+//!
+//! This is a simple vendor-specific service for transferring a firmware image
+//! to the device. It is not compatible with any other DFU protocol.
+//!
+//! The client subscribes to indications of the control point and notifications
+//! of the data characteristic, and performs the following steps:
+//!
+//! 1. Write the Start command (`0x01`) to the control point with the image
+//!    size, the CRC-32 of the image (as defined in ISO/IEC 3309), and the
+//!    requested flow control window in bytes, all `u32` LE. The response
+//!    parameter contains the offset at which to continue the transfer and the
+//!    accepted window size.
+//! 2. Write consecutive image chunks to the data characteristic using Write
+//!    Without Response. The server acknowledges received data with a data
+//!    characteristic notification containing the offset and CRC-32 of all data
+//!    received so far. The client must not have more than one window of
+//!    unacknowledged data outstanding.
+//! 3. Write the Validate command (`0x02`) once the entire image is transferred.
+//!    The image is passed to the application after the checksum is verified.
+//!
+//! The Abort command (`0x03`) discards the transfer. If the connection is lost
+//! or an acknowledgment is not received in time, the client repeats the Start
+//! command with the same parameters to resume the transfer from the last
+//! acknowledged offset.
+//!
+//! Control point responses consist of the response op code (`0x10`), request
+//! op code, and response code, followed by the response parameter, if any.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use structbuf::Unpack;
+use tracing::{debug, warn};
+
+use burble_const::Uuid;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Db, Io, IoReq, IoResult, NotifyReq, Prop};
+use crate::SyncMutex;
+
+use super::cp::ControlPoint;
+use super::ots::crc32;
+
+/// DFU service UUID.
+pub const SERVICE: Uuid = uuid(0xB0E5_0001_6275_7262_6C65_6466_7500_0000);
+
+/// Control point characteristic UUID.
+pub const CONTROL_POINT: Uuid = uuid(0xB0E5_0002_6275_7262_6C65_6466_7500_0000);
+
+/// Data characteristic UUID.
+pub const DATA: Uuid = uuid(0xB0E5_0003_6275_7262_6C65_6466_7500_0000);
+
+/// Application-provided firmware storage.
+pub trait Firmware: Send {
+    /// Prepares to receive an image of `size` bytes with checksum `crc`.
+    /// Returns `false` if the image cannot be accepted.
+    fn begin(&mut self, size: u32, crc: u32) -> bool;
+
+    /// Stores an image chunk at offset `off`. Chunks are written in order,
+    /// but a resumed transfer may rewrite data after the last acknowledged
+    /// offset. Returns `false` if the chunk could not be stored, which fails
+    /// the transfer.
+    fn write(&mut self, off: u32, chunk: &[u8]) -> bool;
+
+    /// Completes the update after the entire image is received and its
+    /// checksum is verified. Returns `false` if the image was not accepted.
+    fn finish(&mut self) -> bool;
+
+    /// Discards a partially transferred image.
+    fn abort(&mut self);
+}
+
+/// Firmware update status.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DfuStatus {
+    /// No transfer in progress.
+    #[default]
+    Idle,
+    /// Image transfer is in progress.
+    Receiving {
+        /// Number of bytes received.
+        received: u32,
+        /// Image size in bytes.
+        size: u32,
+    },
+    /// The transfer failed and must be restarted or aborted by the client.
+    Failed,
+    /// The image was validated and accepted by the application.
+    Complete,
+}
+
+/// Firmware update service instance. Clones refer to the same instance.
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct DfuService(Arc<SyncMutex<State>>);
+
+impl DfuService {
+    /// Minimum flow control window size.
+    const MIN_WINDOW: u32 = 512;
+    /// Maximum flow control window size.
+    const MAX_WINDOW: u32 = 16 * 1024;
+
+    /// Creates a firmware update service that stores images in `fw`.
+    #[inline]
+    #[must_use]
+    pub fn new(fw: impl Firmware + 'static) -> Self {
+        Self(Arc::new(SyncMutex::new(State {
+            fw: Box::new(fw),
+            xfer: None,
+            done: false,
+            ack: None,
+            cp: ControlPoint::new("DFU Control Point", Op::RESPONSE),
+        })))
+    }
+
+    /// Returns the update status.
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> DfuStatus {
+        self.0.lock().status()
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (hdl, ()) = db.primary_service(SERVICE, [], |db| {
+            db.characteristic(
+                CONTROL_POINT,
+                Prop::WRITE | Prop::INDICATE,
+                ac.write(),
+                {
+                    let this = Arc::clone(&self.0);
+                    move |req: IoReq| ControlPoint::io_with(&this, req, |s| &mut s.cp, State::exec)
+                },
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+            db.characteristic(
+                DATA,
+                Prop::WRITE_CMD | Prop::NOTIFY,
+                ac.write(),
+                Io::with(&self.0, |this, req| this.lock().data_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+        });
+        hdl
+    }
+}
+
+/// Firmware update service state.
+struct State {
+    fw: Box<dyn Firmware>,
+    xfer: Option<Transfer>,
+    done: bool,
+    ack: Option<NotifyReq>,
+    cp: ControlPoint,
+}
+
+impl State {
+    /// Returns the update status.
+    const fn status(&self) -> DfuStatus {
+        match self.xfer {
+            Some(Transfer { failed: true, .. }) => DfuStatus::Failed,
+            Some(Transfer { size, off, .. }) => DfuStatus::Receiving {
+                received: off,
+                size,
+            },
+            None if self.done => DfuStatus::Complete,
+            None => DfuStatus::Idle,
+        }
+    }
+
+    /// Handles data characteristic I/O.
+    fn data_io(&mut self, req: IoReq) -> IoResult {
+        let w = match req {
+            IoReq::Read(_) => return Err(ErrorCode::ReadNotPermitted),
+            IoReq::Write(w) => w,
+            IoReq::Notify(n) => {
+                if n.is_indicate() {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                self.ack = Some(n);
+                return Ok(());
+            }
+        };
+        let Some(x) = self.xfer.as_mut().filter(|x| !x.failed) else {
+            return Err(ErrorCode::WriteRequestRejected);
+        };
+        let Ok(n) = u32::try_from(w.value().len()) else {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        };
+        if x.size - x.off < n || !self.fw.write(x.off, w.value()) {
+            warn!("DFU write failed at offset {}", x.off);
+            x.failed = true;
+            return Err(ErrorCode::WriteRequestRejected);
+        }
+        x.off += n;
+        x.crc = crc32(x.crc, w.value());
+        if x.off - x.acked.0 >= x.window / 2 || x.off == x.size {
+            x.acked = (x.off, x.crc);
+            if let Some(n) = self.ack.as_ref() {
+                let (off, crc) = x.acked;
+                if let Err(e) = n.notify_dropping_if_full(|p| {
+                    p.u32(off).u32(crc);
+                }) {
+                    debug!("DFU acknowledgment error: {e}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a control point procedure.
+    fn exec(&mut self, op: u8, param: &[u8]) -> (ResponseCode, Vec<u8>) {
+        match op {
+            Op::START => self.start(param),
+            Op::VALIDATE if param.is_empty() => (self.validate(), Vec::new()),
+            Op::ABORT if param.is_empty() => {
+                if self.xfer.take().is_some() {
+                    self.fw.abort();
+                }
+                (ResponseCode::Success, Vec::new())
+            }
+            Op::VALIDATE | Op::ABORT => (ResponseCode::InvalidParameter, Vec::new()),
+            _ => (ResponseCode::OpCodeNotSupported, Vec::new()),
+        }
+    }
+
+    /// Starts or resumes a transfer.
+    fn start(&mut self, param: &[u8]) -> (ResponseCode, Vec<u8>) {
+        let mut p = param.unpack();
+        let (size, crc, window) = (p.u32(), p.u32(), p.u32());
+        if !p.is_ok() || !p.is_empty() || size == 0 || window == 0 {
+            return (ResponseCode::InvalidParameter, Vec::new());
+        }
+        let window = window.clamp(DfuService::MIN_WINDOW, DfuService::MAX_WINDOW);
+        match self.xfer.as_mut() {
+            Some(x) if !x.failed && x.size == size && x.image_crc == crc => {
+                debug!("Resuming DFU transfer at offset {}", x.acked.0);
+                (x.off, x.crc) = x.acked;
+                x.window = window;
+            }
+            _ => {
+                if self.xfer.take().is_some() {
+                    self.fw.abort();
+                }
+                self.done = false;
+                if !self.fw.begin(size, crc) {
+                    return (ResponseCode::OperationFailed, Vec::new());
+                }
+                self.xfer = Some(Transfer {
+                    size,
+                    image_crc: crc,
+                    window,
+                    off: 0,
+                    crc: 0,
+                    acked: (0, 0),
+                    failed: false,
+                });
+            }
+        }
+        let off = self.xfer.as_ref().map_or(0, |x| x.off);
+        let mut rsp = off.to_le_bytes().to_vec();
+        rsp.extend_from_slice(&window.to_le_bytes());
+        (ResponseCode::Success, rsp)
+    }
+
+    /// Validates the received image.
+    fn validate(&mut self) -> ResponseCode {
+        let Some(x) = self.xfer.as_mut() else { return ResponseCode::InvalidState };
+        if x.failed || x.off != x.size {
+            return ResponseCode::InvalidState;
+        }
+        if x.crc != x.image_crc {
+            x.failed = true;
+            return ResponseCode::ChecksumMismatch;
+        }
+        self.xfer = None;
+        if !self.fw.finish() {
+            return ResponseCode::OperationFailed;
+        }
+        self.done = true;
+        ResponseCode::Success
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("xfer", &self.xfer)
+            .field("done", &self.done)
+            .field("ack", &self.ack)
+            .field("cp", &self.cp)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Image transfer state.
+#[derive(Debug)]
+struct Transfer {
+    size: u32,
+    image_crc: u32,
+    window: u32,
+    off: u32,
+    crc: u32,
+    acked: (u32, u32),
+    failed: bool,
+}
+
+/// Control point op codes.
+struct Op;
+
+impl Op {
+    const START: u8 = 0x01;
+    const VALIDATE: u8 = 0x02;
+    const ABORT: u8 = 0x03;
+    const RESPONSE: u8 = 0x10;
+}
+
+/// Control point response codes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+enum ResponseCode {
+    Success = 0x01,
+    OpCodeNotSupported = 0x02,
+    InvalidParameter = 0x03,
+    OperationFailed = 0x04,
+    InvalidState = 0x05,
+    ChecksumMismatch = 0x06,
+}
+
+impl From<ResponseCode> for u8 {
+    #[inline(always)]
+    fn from(c: ResponseCode) -> Self {
+        c as Self
+    }
+}
+
+/// Creates a 128-bit UUID constant.
+const fn uuid(v: u128) -> Uuid {
+    match Uuid::new(v) {
+        Some(u) => u,
+        None => panic!("invalid UUID"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::att::Opcode;
+    use crate::gatt::service::test::TestDb;
+    use crate::gatt::{CharacteristicDef, DbEntry, NotifyVal};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Image(Arc<SyncMutex<(Vec<u8>, bool)>>);
+
+    impl Firmware for Image {
+        fn begin(&mut self, size: u32, _: u32) -> bool {
+            let mut s = self.0.lock();
+            *s = (Vec::with_capacity(size as _), false);
+            size <= 4096
+        }
+
+        fn write(&mut self, off: u32, chunk: &[u8]) -> bool {
+            let mut s = self.0.lock();
+            s.0.truncate(off as _);
+            s.0.extend_from_slice(chunk);
+            true
+        }
+
+        fn finish(&mut self) -> bool {
+            self.0.lock().1 = true;
+            true
+        }
+
+        fn abort(&mut self) {
+            self.0.lock().0.clear();
+        }
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let img: Vec<u8> = (0..2000_u32).map(|i| (i * 7) as u8).collect();
+        let crc = crc32(0, &img);
+        let fw = Image::default();
+        let out = Arc::clone(&fw.0);
+        let dfu = DfuService::new(fw);
+        let t = TestDb::new(|db| dfu.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (cp, data) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(data.properties(), Prop::WRITE_CMD | Prop::NOTIFY);
+
+        let write = |c: &DbEntry<CharacteristicDef>, op, v: &[u8]| {
+            t.write_as(TestDb::PEER, op, c.value_handle(), v)
+        };
+        let sub = |c: &DbEntry<CharacteristicDef>, ind, tx| {
+            t.io.notify(NotifyReq {
+                hdl: c.value_handle(),
+                uuid: c.uuid(),
+                mtu: 247,
+                ind,
+                tx,
+                ct: CancellationToken::new(),
+            })
+            .unwrap();
+        };
+        let start = |size: usize, crc: u32| {
+            let mut v = vec![Op::START];
+            v.extend_from_slice(&u32::try_from(size).unwrap().to_le_bytes());
+            v.extend_from_slice(&crc.to_le_bytes());
+            v.extend_from_slice(&1024_u32.to_le_bytes());
+            write(&cp, Opcode::WriteReq, &v)
+        };
+        let (ctx, mut crx) = mpsc::channel(1);
+        let (dtx, mut drx) = mpsc::channel(8);
+        sub(&cp, true, ctx.clone());
+        sub(&data, false, dtx.clone());
+
+        // Data before start is rejected
+        assert_eq!(
+            write(&data, Opcode::WriteCmd, &img[..10]),
+            Err(ErrorCode::WriteRequestRejected)
+        );
+        assert_eq!(dfu.status(), DfuStatus::Idle);
+
+        // Start and transfer part of the image
+        start(img.len(), crc).unwrap();
+        assert_eq!(
+            confirm(&mut crx).await,
+            [0x10, 0x01, 0x01, 0, 0, 0, 0, 0, 4, 0, 0]
+        );
+        for c in img[..700].chunks(100) {
+            write(&data, Opcode::WriteCmd, c).unwrap();
+        }
+        assert_eq!(ack(&mut drx), Some((600, crc32(0, &img[..600]))));
+        assert_eq!(ack(&mut drx), None);
+        assert_eq!(
+            dfu.status(),
+            DfuStatus::Receiving {
+                received: 700,
+                size: 2000
+            }
+        );
+
+        // Resume after reconnecting
+        sub(&cp, true, ctx.clone());
+        sub(&data, false, dtx.clone());
+        start(img.len(), crc).unwrap();
+        assert_eq!(confirm(&mut crx).await[3..7], 600_u32.to_le_bytes());
+        for c in img[600..].chunks(244) {
+            write(&data, Opcode::WriteCmd, c).unwrap();
+        }
+        let mut last = None;
+        while let Some(a) = ack(&mut drx) {
+            last = Some(a);
+        }
+        assert_eq!(last, Some((2000, crc)));
+        assert_eq!(
+            write(&data, Opcode::WriteCmd, &[0]),
+            Err(ErrorCode::WriteRequestRejected)
+        );
+        assert_eq!(dfu.status(), DfuStatus::Failed);
+
+        // Restart, validate, and finish
+        write(&cp, Opcode::WriteReq, &[Op::ABORT]).unwrap();
+        assert_eq!(confirm(&mut crx).await, [0x10, 0x03, 0x01]);
+        start(img.len(), crc).unwrap();
+        assert_eq!(confirm(&mut crx).await[3..7], [0; 4]);
+        write(&cp, Opcode::WriteReq, &[Op::VALIDATE]).unwrap();
+        assert_eq!(confirm(&mut crx).await, [0x10, 0x02, 0x05]);
+        for c in img.chunks(244) {
+            write(&data, Opcode::WriteCmd, c).unwrap();
+        }
+        write(&cp, Opcode::WriteReq, &[Op::VALIDATE]).unwrap();
+        assert_eq!(confirm(&mut crx).await, [0x10, 0x02, 0x01]);
+        assert_eq!(dfu.status(), DfuStatus::Complete);
+        assert_eq!(*out.lock(), (img.clone(), true));
+
+        // Checksum mismatch and oversized image
+        start(img.len(), !crc).unwrap();
+        assert_eq!(confirm(&mut crx).await[..3], [0x10, 0x01, 0x01]);
+        for c in img.chunks(244) {
+            write(&data, Opcode::WriteCmd, c).unwrap();
+        }
+        write(&cp, Opcode::WriteReq, &[Op::VALIDATE]).unwrap();
+        assert_eq!(confirm(&mut crx).await, [0x10, 0x02, 0x06]);
+        assert_eq!(dfu.status(), DfuStatus::Failed);
+        start(8192, crc).unwrap();
+        assert_eq!(confirm(&mut crx).await, [0x10, 0x01, 0x04]);
+        assert_eq!(dfu.status(), DfuStatus::Idle);
+        write(&cp, Opcode::WriteReq, &[0x04]).unwrap();
+        assert_eq!(confirm(&mut crx).await, [0x10, 0x04, 0x02]);
+    }
+
+    /// Returns the next acknowledgment notification, if any.
+    fn ack(rx: &mut mpsc::Receiver<NotifyVal>) -> Option<(u32, u32)> {
+        let v = rx.try_recv().ok()?;
+        let mut p = v.as_ref().unpack();
+        Some((p.u32(), p.u32()))
+    }
+
+    /// Receives and confirms the next control point response, and waits for
+    /// the procedure to complete.
+    async fn confirm(rx: &mut mpsc::Receiver<NotifyVal>) -> Vec<u8> {
+        let v = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let r = v.as_ref().to_vec();
+        v.result(Ok(()));
+        tokio::task::yield_now().await;
+        r
+    }
+}
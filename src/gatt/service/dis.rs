@@ -4,13 +4,17 @@
 //!
 //! [DIS]: https://www.bluetooth.com/specifications/specs/device-information-service-1-1/
 
-use crate::att::Perms;
+use structbuf::Unpack;
+
+use crate::att::{Access, Handle, Perms};
 use crate::gatt::{Builder, Characteristic, Db, Service, ServiceDef};
 
-/// Device Information Service configuration.
-#[derive(Clone, Debug, Default)]
+/// Device Information Service configuration. Only the characteristics that
+/// are set are included in the service.
+#[derive(Clone, Debug)]
 #[non_exhaustive]
 pub struct DeviceInfoService {
+    perms: Perms,
     manufacturer_name: Option<String>,
     model_num: Option<String>,
     serial_num: Option<String>,
@@ -22,6 +26,24 @@ pub struct DeviceInfoService {
     pnp_id: Option<PnpId>,
 }
 
+impl Default for DeviceInfoService {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            perms: Perms::new(Access::READ),
+            manufacturer_name: None,
+            model_num: None,
+            serial_num: None,
+            hardware_rev: None,
+            firmware_rev: None,
+            software_rev: None,
+            system_id: None,
+            regulatory_data: None,
+            pnp_id: None,
+        }
+    }
+}
+
 /// Implements `with_<x>` methods for [`String`] characteristics.
 macro_rules! with_str {
     ($($(#[$doc:meta])* $f:ident),*$(,)?) => {$(::paste::paste! {
@@ -36,13 +58,23 @@ macro_rules! with_str {
 }
 
 impl DeviceInfoService {
-    /// Creates an empty device information service.
+    /// Creates an empty device information service. All characteristics are
+    /// readable without any security requirements by default.
     #[inline(always)]
     #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Sets characteristic value permissions. The values are always
+    /// read-only, so any write permissions are ignored by the server.
+    #[inline(always)]
+    #[must_use]
+    pub fn with_perms(mut self, perms: impl Into<Perms>) -> Self {
+        self.perms = perms.into();
+        self
+    }
+
     with_str! {
         /// Sets device manufacturer name.
         manufacturer_name,
@@ -83,13 +115,13 @@ impl DeviceInfoService {
         self
     }
 
-    /// Defines the service structure.
-    pub fn define(self, db: &mut Builder<Db>, perms: impl Into<Perms>) {
+    /// Defines the service structure and returns the service handle.
+    pub fn define(self, db: &mut Builder<Db>) -> Handle {
         fn chr(db: &mut Builder<ServiceDef>, c: Characteristic, p: Perms, v: impl AsRef<[u8]>) {
             db.ro_characteristic(c, p, v, |_| {});
         }
-        let p = perms.into();
-        db.primary_service(Service::DeviceInformation, [], |db| {
+        let p = self.perms;
+        let (hdl, ()) = db.primary_service(Service::DeviceInformation, [], |db| {
             use Characteristic::*;
             if let Some(v) = self.manufacturer_name.as_ref() {
                 chr(db, ManufacturerNameString, p, v);
@@ -110,7 +142,7 @@ impl DeviceInfoService {
                 chr(db, SoftwareRevisionString, p, v);
             }
             if let Some(v) = self.system_id.as_ref() {
-                chr(db, SystemId, p, v.0.to_le_bytes());
+                chr(db, SystemId, p, v.to_bytes());
             }
             if let Some(v) = self.regulatory_data.as_ref() {
                 chr(db, IeeeRegulatoryCertificationDataList, p, &v.0);
@@ -119,6 +151,7 @@ impl DeviceInfoService {
                 chr(db, PnpId, p, v.to_bytes());
             }
         });
+        hdl
     }
 }
 
@@ -136,6 +169,48 @@ impl Eui64 {
     pub const fn new(v: u64) -> Self {
         Self(v)
     }
+
+    /// Creates a new EUI-64 from a 24-bit OUI and a 40-bit manufacturer-defined
+    /// identifier. Returns [`None`] if either value is out of range.
+    #[inline]
+    #[must_use]
+    pub const fn from_parts(oui: u32, id: u64) -> Option<Self> {
+        if oui >> 24 != 0 || id >> 40 != 0 {
+            return None;
+        }
+        Some(Self((oui as u64) << 40 | id))
+    }
+
+    /// Returns the 24-bit IEEE-assigned OUI.
+    #[inline(always)]
+    #[must_use]
+    pub const fn oui(self) -> u32 {
+        (self.0 >> 40) as u32
+    }
+
+    /// Returns the 40-bit manufacturer-defined identifier.
+    #[inline(always)]
+    #[must_use]
+    pub const fn id(self) -> u64 {
+        self.0 & ((1 << 40) - 1)
+    }
+
+    /// Converts the EUI-64 to the System ID characteristic value, which
+    /// contains the manufacturer-defined identifier followed by the OUI, both
+    /// in little-endian byte order ([DIS] Section 3.7.1.1).
+    #[inline(always)]
+    #[must_use]
+    pub const fn to_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    /// Converts the System ID characteristic value to an EUI-64. Returns
+    /// [`None`] if the value has an invalid length.
+    #[inline]
+    #[must_use]
+    pub fn from_bytes(v: &[u8]) -> Option<Self> {
+        Some(Self(u64::from_le_bytes(v.try_into().ok()?)))
+    }
 }
 
 /// IEEE 11073-20601 Regulatory Certification Data List.
@@ -144,9 +219,16 @@ impl Eui64 {
 /// White Paper for format information.
 ///
 /// [PHD]: https://www.bluetooth.com/wp-content/uploads/2019/03/PHD_Transcoding_WP_v16.pdf
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct RegulatoryData(Vec<u8>);
 
+impl AsRef<[u8]> for RegulatoryData {
+    #[inline(always)]
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl From<Vec<u8>> for RegulatoryData {
     #[inline(always)]
     fn from(v: Vec<u8>) -> Self {
@@ -175,6 +257,35 @@ impl PnpId {
         Some(Self { vid, pid, ver })
     }
 
+    /// Returns the `(major, minor, patch)` product version.
+    #[inline]
+    #[must_use]
+    pub const fn version(self) -> (u8, u8, u8) {
+        let [minor_patch, major] = self.ver.to_le_bytes();
+        (major, minor_patch >> 4, minor_patch & 0xf)
+    }
+
+    /// Converts PNP ID from byte representation. Returns [`None`] if the value
+    /// has an invalid length or vendor ID source.
+    #[must_use]
+    pub fn from_bytes(v: &[u8]) -> Option<Self> {
+        if v.len() != 7 {
+            return None;
+        }
+        let mut v = v.unpack();
+        // [DIS] Section 3.9.1.1
+        let vid = match (v.u8(), v.u16()) {
+            (0x01, vid) => VendorId::Bluetooth(vid),
+            (0x02, vid) => VendorId::USB(vid),
+            _ => return None,
+        };
+        Some(Self {
+            vid,
+            pid: v.u16(),
+            ver: v.u16(),
+        })
+    }
+
     /// Converts PNP ID to byte representation.
     #[must_use]
     pub fn to_bytes(self) -> [u8; 7] {
@@ -199,3 +310,89 @@ pub enum VendorId {
     Bluetooth(u16),
     USB(u16),
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::att::Access;
+    use crate::gap::Uuid;
+    use crate::gatt::service::test::TestDb;
+    use crate::gatt::{CharacteristicDef, DbEntry, Prop};
+
+    use super::*;
+
+    /// Service definition includes only the characteristics that were set and
+    /// all values are decoded as they were encoded.
+    #[test]
+    fn round_trip() {
+        let sys_id = Eui64::from_parts(0x00_1B_DC, 0x12_3456_789A).unwrap();
+        let reg = RegulatoryData::from(vec![0xFE, 0x00, 0x01, 0x02]);
+        let pnp = PnpId::new(VendorId::USB(0x1209), 0x0001, (1, 2, 3)).unwrap();
+        let dis = DeviceInfoService::new()
+            .with_manufacturer_name("Blackrock Neurotech")
+            .with_serial_num("0123")
+            .with_system_id(sys_id)
+            .with_regulatory_data(reg.clone())
+            .with_pnp_id(pnp);
+        let mut hdl = Handle::MAX;
+        let t = TestDb::new(|db| hdl = dis.define(db));
+
+        let srv = t.service();
+        assert_eq!(srv.handle(), hdl);
+        assert_eq!(srv.uuid(), Service::DeviceInformation);
+        let chars: Vec<_> = t.characteristics().collect();
+        let uuid = DbEntry::<CharacteristicDef>::uuid;
+        let have: Vec<_> = chars.iter().map(uuid).collect();
+        let want = {
+            use Characteristic::*;
+            [
+                ManufacturerNameString,
+                SerialNumberString,
+                SystemId,
+                IeeeRegulatoryCertificationDataList,
+                PnpId,
+            ]
+        };
+        assert_eq!(have, want.map(Uuid::from));
+        assert!(chars.iter().all(|c| c.properties() == Prop::READ));
+
+        let val = |i: usize| t.db.get(chars[i].value_handle()).unwrap().1;
+        assert_eq!(val(0), b"Blackrock Neurotech");
+        assert_eq!(val(1), b"0123");
+        assert_eq!(val(2), [0x9A, 0x78, 0x56, 0x34, 0x12, 0xDC, 0x1B, 0x00]);
+        let v = Eui64::from_bytes(val(2)).unwrap();
+        assert_eq!((v, v.oui(), v.id()), (sys_id, 0x00_1B_DC, 0x12_3456_789A));
+        assert_eq!(val(3), reg.as_ref());
+        assert_eq!(val(4), [0x02, 0x09, 0x12, 0x01, 0x00, 0x23, 0x01]);
+        let v = PnpId::from_bytes(val(4)).unwrap();
+        assert_eq!((v, v.version()), (pnp, (1, 2, 3)));
+    }
+
+    /// Values are readable without security by default, but permissions can be
+    /// overridden.
+    #[test]
+    fn perms() {
+        let sec = crate::hci::ConnSec::empty();
+        let req = crate::att::Opcode::ReadReq.request(sec);
+        for (svc, allow) in [
+            (DeviceInfoService::new(), true),
+            (
+                DeviceInfoService::new().with_perms(Access::READ.encrypt()),
+                false,
+            ),
+        ] {
+            let t = TestDb::new(|db| svc.with_model_num("B1").define(db));
+            let vhdl = Handle::new(0x0003).unwrap();
+            assert_eq!(t.db.try_access(req, vhdl).is_ok(), allow);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Eui64::from_parts(1 << 24, 0).is_none());
+        assert!(Eui64::from_parts(0, 1 << 40).is_none());
+        assert!(Eui64::from_bytes(&[0; 7]).is_none());
+        assert!(PnpId::new(VendorId::Bluetooth(1), 2, (1, 16, 0)).is_none());
+        assert!(PnpId::from_bytes(&[0x03, 0, 0, 0, 0, 0, 0]).is_none());
+        assert!(PnpId::from_bytes(&[0x01, 0, 0, 0, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,669 @@
+//! Environmental Sensing Service ([ESS]).
+//!
+//! This service exposes measurement data from environmental sensors. Each
+//! sensor is a characteristic that may be notified to the client based on a
+//! trigger condition configured by the client.
+//!
+//! [ESS]: https://www.bluetooth.com/specifications/specs/environmental-sensing-service-1-0/
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{
+    Builder, Characteristic, Db, Descriptor, Format, Io, IoReq, IoResult, NotifyReq, Prop, Service,
+};
+use crate::SyncMutex;
+
+/// Environmental Sensing service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct EnvSensingService(Arc<SyncMutex<State>>);
+
+impl EnvSensingService {
+    /// Creates an environmental sensing service without any sensors.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sensor characteristic. Sensors are identified by their index in
+    /// the order they were added.
+    #[inline]
+    #[must_use]
+    pub fn with_sensor(self, sensor: Sensor, opts: SensorOpts) -> Self {
+        (self.0.lock().ch).push(Channel {
+            sensor,
+            trigger: opts.trigger,
+            opts,
+            raw: 0,
+            last: None,
+            ntf: Vec::new(),
+        });
+        self
+    }
+
+    /// Returns the current value of sensor `i` in its base unit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is not a valid sensor index.
+    #[must_use]
+    pub fn value(&self, i: usize) -> f64 {
+        let s = self.0.lock();
+        s.ch[i].sensor.from_raw(s.ch[i].raw)
+    }
+
+    /// Sets the value of sensor `i` in its base unit and notifies subscribed
+    /// clients if the trigger condition is met. Time-based triggers are only
+    /// evaluated when the value is set, so the application should update the
+    /// value at least as often as the client's requested interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is not a valid sensor index.
+    pub fn set_value(&self, i: usize, v: f64) {
+        let mut s = self.0.lock();
+        let c = &mut s.ch[i];
+        c.raw = c.sensor.to_raw(v);
+        let Some(trig) = c.trigger else { return };
+        let now = Instant::now();
+        if !trig.eval(c.raw, c.last.map(|(prev, t)| (prev, now - t))) {
+            return;
+        }
+        c.last = Some((c.raw, now));
+        let val = c.sensor.encode(c.raw);
+        c.ntf.retain(|n| {
+            n.notify_dropping_if_full(|p| {
+                p.put(&val);
+            })
+            .map_err(|e| debug!("Environmental sensing notify error: {e}"))
+            .is_ok()
+        });
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let chs: Vec<_> = (self.0.lock().ch.iter())
+            .map(|c| (c.sensor, c.opts))
+            .collect();
+        let (hdl, ()) = db.primary_service(Service::EnvironmentalSensing, [], |db| {
+            for (i, (sensor, opts)) in chs.into_iter().enumerate() {
+                let notify = opts.trigger.is_some();
+                let props = if notify {
+                    Prop::READ | Prop::NOTIFY
+                } else {
+                    Prop::READ
+                };
+                db.characteristic(
+                    sensor.uuid(),
+                    props,
+                    ac.read(),
+                    Io::with(&self.0, move |this, req| this.lock().value_io(i, req)),
+                    |db| {
+                        if notify {
+                            db.cccd(ac.read_write());
+                        }
+                        // ES Measurement ([ESS] Section 3.1.2.1)
+                        if let Some(m) = opts.measurement {
+                            let v = m.to_bytes();
+                            db.ro_descriptor(
+                                Descriptor::EnvironmentalSensingMeasurement,
+                                ac.read(),
+                                v,
+                            );
+                        }
+                        // ES Trigger Setting ([ESS] Section 3.1.2.2)
+                        if notify {
+                            db.descriptor(
+                                Descriptor::EnvironmentalSensingTriggerSetting,
+                                ac.read_write(),
+                                Io::with(&self.0, move |this, req| this.lock().trigger_io(i, req)),
+                            );
+                        }
+                        // Valid Range ([ESS] Section 3.1.2.4)
+                        if let Some((lo, hi)) = opts.valid_range {
+                            let mut v = sensor.encode(sensor.to_raw(lo));
+                            v.extend(sensor.encode(sensor.to_raw(hi)));
+                            db.ro_descriptor(Descriptor::ValidRange, ac.read(), v);
+                        }
+                    },
+                );
+            }
+        });
+        hdl
+    }
+}
+
+/// Environmental sensing service state.
+#[derive(Debug, Default)]
+struct State {
+    ch: Vec<Channel>,
+}
+
+impl State {
+    /// Handles sensor characteristic I/O.
+    fn value_io(&mut self, i: usize, req: IoReq) -> IoResult {
+        let c = &mut self.ch[i];
+        match req {
+            IoReq::Read(r) => r.complete(c.sensor.encode(c.raw)),
+            IoReq::Write(_) => Err(ErrorCode::WriteNotPermitted),
+            IoReq::Notify(n) => {
+                if n.is_indicate() {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                c.ntf.retain(|n| !n.is_closed());
+                c.ntf.push(n);
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles ES Trigger Setting descriptor I/O.
+    fn trigger_io(&mut self, i: usize, req: IoReq) -> IoResult {
+        let c = &mut self.ch[i];
+        match req {
+            IoReq::Read(r) => r.complete(c.trigger.unwrap_or_default().to_bytes(c.sensor)),
+            IoReq::Write(w) => {
+                if w.offset() != 0 {
+                    return Err(ErrorCode::InvalidOffset);
+                }
+                let t = Trigger::from_bytes(c.sensor, w.value())?;
+                debug!("{:?} trigger: {t:?}", c.sensor);
+                (c.trigger, c.last) = (Some(t), None);
+                Ok(())
+            }
+            IoReq::Notify(_) => Err(ErrorCode::RequestNotSupported),
+        }
+    }
+}
+
+/// Sensor characteristic state.
+#[derive(Debug)]
+struct Channel {
+    sensor: Sensor,
+    opts: SensorOpts,
+    trigger: Option<Trigger>,
+    raw: i64,
+    last: Option<(i64, Instant)>,
+    ntf: Vec<NotifyReq>,
+}
+
+/// Optional sensor characteristic features.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SensorOpts {
+    /// ES Measurement descriptor value.
+    pub measurement: Option<EsMeasurement>,
+    /// Valid Range descriptor value as `(lower, upper)` inclusive bounds in
+    /// the sensor's base unit.
+    pub valid_range: Option<(f64, f64)>,
+    /// Initial trigger condition. Notifications and the ES Trigger Setting
+    /// descriptor are enabled if this is not [`None`].
+    pub trigger: Option<Trigger>,
+}
+
+/// Environmental sensor type. Values are represented in the base unit of each
+/// sensor and scaled by the characteristic's decimal exponent for transfer
+/// ([GSS] Section 3).
+///
+/// [GSS]: https://www.bluetooth.com/specifications/gss/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Sensor {
+    /// Apparent wind speed in meters per second.
+    ApparentWindSpeed,
+    /// Dew point in degrees Celsius.
+    DewPoint,
+    /// Elevation in meters.
+    Elevation,
+    /// Relative humidity in percent.
+    Humidity,
+    /// Irradiance in watts per square meter.
+    Irradiance,
+    /// Pressure in pascals.
+    Pressure,
+    /// Rainfall in meters.
+    Rainfall,
+    /// Temperature in degrees Celsius.
+    Temperature,
+    /// True wind speed in meters per second.
+    TrueWindSpeed,
+    /// UV index.
+    UvIndex,
+}
+
+impl Sensor {
+    /// Returns the characteristic UUID.
+    #[inline]
+    #[must_use]
+    pub const fn uuid(self) -> Characteristic {
+        match self {
+            Self::ApparentWindSpeed => Characteristic::ApparentWindSpeed,
+            Self::DewPoint => Characteristic::DewPoint,
+            Self::Elevation => Characteristic::Elevation,
+            Self::Humidity => Characteristic::Humidity,
+            Self::Irradiance => Characteristic::Irradiance,
+            Self::Pressure => Characteristic::Pressure,
+            Self::Rainfall => Characteristic::Rainfall,
+            Self::Temperature => Characteristic::Temperature,
+            Self::TrueWindSpeed => Characteristic::TrueWindSpeed,
+            Self::UvIndex => Characteristic::UvIndex,
+        }
+    }
+
+    /// Returns the characteristic value format.
+    #[inline]
+    #[must_use]
+    pub const fn format(self) -> Format {
+        match self {
+            Self::DewPoint => Format::I8,
+            Self::Temperature => Format::I16,
+            Self::Elevation => Format::I24,
+            Self::UvIndex => Format::U8,
+            Self::ApparentWindSpeed
+            | Self::Humidity
+            | Self::Irradiance
+            | Self::Rainfall
+            | Self::TrueWindSpeed => Format::U16,
+            Self::Pressure => Format::U32,
+        }
+    }
+
+    /// Returns the decimal exponent of the characteristic value.
+    #[inline]
+    #[must_use]
+    pub const fn exponent(self) -> i8 {
+        match self {
+            Self::DewPoint | Self::UvIndex => 0,
+            Self::Irradiance | Self::Pressure => -1,
+            Self::ApparentWindSpeed
+            | Self::Elevation
+            | Self::Humidity
+            | Self::Temperature
+            | Self::TrueWindSpeed => -2,
+            Self::Rainfall => -3,
+        }
+    }
+
+    /// Converts a value in the base unit to the characteristic's integer
+    /// representation, saturating at the format limits.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn to_raw(self, v: f64) -> i64 {
+        let (_, min, max) = self.bounds();
+        let v = (v * 10_f64.powi(-i32::from(self.exponent()))).round();
+        v.clamp(min as f64, max as f64) as i64
+    }
+
+    /// Converts the characteristic's integer representation to a value in
+    /// the base unit.
+    #[allow(clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn from_raw(self, raw: i64) -> f64 {
+        raw as f64 * 10_f64.powi(i32::from(self.exponent()))
+    }
+
+    /// Encodes an integer value in the characteristic format.
+    fn encode(self, raw: i64) -> Vec<u8> {
+        raw.to_le_bytes()[..self.bounds().0].to_vec()
+    }
+
+    /// Decodes an integer value in the characteristic format.
+    fn decode(self, v: &[u8]) -> Option<i64> {
+        let (n, min, _) = self.bounds();
+        if v.len() != n {
+            return None;
+        }
+        let mut b = [0; 8];
+        b[..n].copy_from_slice(v);
+        let shift = 64 - 8 * n;
+        let v = i64::from_le_bytes(b) << shift;
+        #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+        Some(if min < 0 {
+            v >> shift
+        } else {
+            ((v as u64) >> shift) as i64
+        })
+    }
+
+    /// Returns the size and limits of the characteristic format.
+    const fn bounds(self) -> (usize, i64, i64) {
+        match self.format() {
+            Format::U8 => (1, 0, 0xFF),
+            Format::U16 => (2, 0, 0xFFFF),
+            Format::U32 => (4, 0, 0xFFFF_FFFF),
+            Format::I8 => (1, -0x80, 0x7F),
+            Format::I16 => (2, -0x8000, 0x7FFF),
+            _ => (3, -0x80_0000, 0x7F_FFFF),
+        }
+    }
+}
+
+/// ES Measurement descriptor value ([ESS] Section 3.1.2.1).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EsMeasurement {
+    /// Sampling function applied to the measured values.
+    pub sampling: SamplingFunction,
+    /// Period over which the sampling function is applied, with a resolution
+    /// of one second. Zero means not in use.
+    pub period: Duration,
+    /// Interval between value updates, with a resolution of one second. Zero
+    /// means not in use.
+    pub update_interval: Duration,
+    /// Intended application of the sensor (Measurement Application assigned
+    /// number). Zero means unspecified.
+    pub application: u8,
+    /// Measurement uncertainty in units of 0.5%. `0xFF` means unknown.
+    pub uncertainty: u8,
+}
+
+impl EsMeasurement {
+    /// Returns the encoded descriptor value.
+    #[must_use]
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(11);
+        v.extend(0_u16.to_le_bytes()); // Flags (reserved)
+        v.push(self.sampling as u8);
+        v.extend(&secs_u24(self.period).to_le_bytes()[..3]);
+        v.extend(&secs_u24(self.update_interval).to_le_bytes()[..3]);
+        v.extend([self.application, self.uncertainty]);
+        v
+    }
+}
+
+/// Sampling function of the ES Measurement descriptor
+/// ([ESS] Section 3.1.2.1.2).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum SamplingFunction {
+    #[default]
+    Unspecified = 0x00,
+    Instantaneous = 0x01,
+    ArithmeticMean = 0x02,
+    Rms = 0x03,
+    Maximum = 0x04,
+    Minimum = 0x05,
+    Accumulated = 0x06,
+    Count = 0x07,
+}
+
+/// ES Trigger Setting condition ([ESS] Section 3.1.2.2). Value operands are
+/// in the characteristic's integer representation (see [`Sensor::to_raw`]).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Trigger {
+    /// No notifications.
+    Inactive,
+    /// Notify at a fixed interval.
+    FixedInterval(Duration),
+    /// Notify when the value changes, but no more often than the specified
+    /// interval.
+    MinInterval(Duration),
+    /// Notify when the value changes.
+    #[default]
+    OnChange,
+    /// Notify while the value is less than the operand.
+    LessThan(i64),
+    /// Notify while the value is less than or equal to the operand.
+    LessOrEqual(i64),
+    /// Notify while the value is greater than the operand.
+    GreaterThan(i64),
+    /// Notify while the value is greater than or equal to the operand.
+    GreaterOrEqual(i64),
+    /// Notify while the value is equal to the operand.
+    Equal(i64),
+    /// Notify while the value is not equal to the operand.
+    NotEqual(i64),
+}
+
+impl Trigger {
+    /// Returns whether value `v` should be notified. `last` is the last
+    /// notified value and the time elapsed since it was notified.
+    #[must_use]
+    pub fn eval(self, v: i64, last: Option<(i64, Duration)>) -> bool {
+        let changed = last.map_or(true, |(prev, _)| prev != v);
+        let elapsed = |d| last.map_or(true, |(_, t)| t >= d);
+        match self {
+            Self::Inactive => false,
+            Self::FixedInterval(d) => elapsed(d),
+            Self::MinInterval(d) => changed && elapsed(d),
+            Self::OnChange => changed,
+            Self::LessThan(x) => v < x,
+            Self::LessOrEqual(x) => v <= x,
+            Self::GreaterThan(x) => v > x,
+            Self::GreaterOrEqual(x) => v >= x,
+            Self::Equal(x) => v == x,
+            Self::NotEqual(x) => v != x,
+        }
+    }
+
+    /// Returns the encoded descriptor value for the specified sensor.
+    #[must_use]
+    pub fn to_bytes(self, s: Sensor) -> Vec<u8> {
+        let (cond, val) = match self {
+            Self::Inactive => return vec![0x00],
+            Self::FixedInterval(d) => {
+                return [&[0x01][..], &secs_u24(d).to_le_bytes()[..3]].concat()
+            }
+            Self::MinInterval(d) => return [&[0x02][..], &secs_u24(d).to_le_bytes()[..3]].concat(),
+            Self::OnChange => return vec![0x03],
+            Self::LessThan(x) => (0x04, x),
+            Self::LessOrEqual(x) => (0x05, x),
+            Self::GreaterThan(x) => (0x06, x),
+            Self::GreaterOrEqual(x) => (0x07, x),
+            Self::Equal(x) => (0x08, x),
+            Self::NotEqual(x) => (0x09, x),
+        };
+        let mut v = vec![cond];
+        v.extend(s.encode(val));
+        v
+    }
+
+    /// Decodes a descriptor value written for the specified sensor. Returns
+    /// [`ErrorCode::OperationFailed`] (Condition Not Supported) for reserved
+    /// conditions ([ESS] Section 1.6).
+    pub fn from_bytes(s: Sensor, v: &[u8]) -> Result<Self, ErrorCode> {
+        let Some((&cond, op)) = v.split_first() else {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        };
+        let secs = || match *op {
+            [b0, b1, b2] => {
+                let n = u32::from_le_bytes([b0, b1, b2, 0]);
+                Ok(Duration::from_secs(u64::from(n)))
+            }
+            _ => Err(ErrorCode::InvalidAttributeValueLength),
+        };
+        let val = || s.decode(op).ok_or(ErrorCode::InvalidAttributeValueLength);
+        let none = |t| {
+            if op.is_empty() {
+                Ok(t)
+            } else {
+                Err(ErrorCode::InvalidAttributeValueLength)
+            }
+        };
+        match cond {
+            0x00 => none(Self::Inactive),
+            0x01 => secs().map(Self::FixedInterval),
+            0x02 => secs().map(Self::MinInterval),
+            0x03 => none(Self::OnChange),
+            0x04 => val().map(Self::LessThan),
+            0x05 => val().map(Self::LessOrEqual),
+            0x06 => val().map(Self::GreaterThan),
+            0x07 => val().map(Self::GreaterOrEqual),
+            0x08 => val().map(Self::Equal),
+            0x09 => val().map(Self::NotEqual),
+            _ => Err(ErrorCode::OperationFailed),
+        }
+    }
+}
+
+/// Converts a duration to whole seconds, saturating at the `uint24` limit.
+#[allow(clippy::cast_possible_truncation)]
+fn secs_u24(d: Duration) -> u32 {
+    d.as_secs().min(0xFF_FFFF) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        let t = Sensor::Temperature;
+        assert_eq!(t.to_raw(21.456), 2146);
+        assert_eq!(t.to_raw(-40.0), -4000);
+        assert_eq!(t.to_raw(1000.0), 0x7FFF);
+        assert_eq!(t.encode(-4000), [0x60, 0xF0]);
+        assert_eq!(t.decode(&[0x60, 0xF0]), Some(-4000));
+        assert_eq!(t.decode(&[0x60]), None);
+        assert!((t.from_raw(2146) - 21.46).abs() < 1e-9);
+
+        let p = Sensor::Pressure;
+        assert_eq!(p.to_raw(101_325.0), 1_013_250);
+        assert_eq!(p.to_raw(-1.0), 0);
+        assert_eq!(p.decode(&[0xFF; 4]), Some(0xFFFF_FFFF));
+
+        let e = Sensor::Elevation;
+        assert_eq!(e.encode(-1), [0xFF; 3]);
+        assert_eq!(e.decode(&[0xFF; 3]), Some(-1));
+        assert_eq!(e.decode(&[0xFF, 0xFF, 0x7F]), Some(0x7F_FFFF));
+    }
+
+    #[test]
+    fn trigger_eval() {
+        let s = Duration::from_secs;
+        assert!(!Trigger::Inactive.eval(1, None));
+
+        let t = Trigger::OnChange;
+        assert!(t.eval(1, None));
+        assert!(!t.eval(1, Some((1, s(10)))));
+        assert!(t.eval(2, Some((1, s(0)))));
+
+        let t = Trigger::FixedInterval(s(5));
+        assert!(t.eval(1, None));
+        assert!(!t.eval(2, Some((1, s(4)))));
+        assert!(t.eval(1, Some((1, s(5)))));
+
+        let t = Trigger::MinInterval(s(5));
+        assert!(t.eval(1, None));
+        assert!(!t.eval(2, Some((1, s(4)))));
+        assert!(!t.eval(1, Some((1, s(6)))));
+        assert!(t.eval(2, Some((1, s(6)))));
+
+        let last = Some((0, s(0)));
+        assert!(Trigger::LessThan(10).eval(9, last));
+        assert!(!Trigger::LessThan(10).eval(10, last));
+        assert!(Trigger::LessOrEqual(10).eval(10, last));
+        assert!(!Trigger::GreaterThan(10).eval(10, last));
+        assert!(Trigger::GreaterOrEqual(10).eval(10, last));
+        assert!(Trigger::Equal(10).eval(10, last));
+        assert!(!Trigger::NotEqual(10).eval(10, last));
+        assert!(Trigger::NotEqual(10).eval(11, last));
+    }
+
+    #[test]
+    fn trigger_bytes() {
+        let t = Sensor::Temperature;
+        let cases = [
+            (Trigger::Inactive, &[0x00][..]),
+            (
+                Trigger::FixedInterval(Duration::from_secs(60)),
+                &[0x01, 60, 0, 0],
+            ),
+            (
+                Trigger::MinInterval(Duration::from_secs(0x01_0203)),
+                &[0x02, 3, 2, 1],
+            ),
+            (Trigger::OnChange, &[0x03]),
+            (Trigger::LessThan(-1), &[0x04, 0xFF, 0xFF]),
+            (Trigger::NotEqual(0x0102), &[0x09, 0x02, 0x01]),
+        ];
+        for (trig, v) in cases {
+            assert_eq!(trig.to_bytes(t), v);
+            assert_eq!(Trigger::from_bytes(t, v), Ok(trig));
+        }
+        let err = |v: &[u8]| Trigger::from_bytes(t, v).unwrap_err();
+        assert_eq!(err(&[]), ErrorCode::InvalidAttributeValueLength);
+        assert_eq!(err(&[0x00, 0x00]), ErrorCode::InvalidAttributeValueLength);
+        assert_eq!(err(&[0x01, 0x00]), ErrorCode::InvalidAttributeValueLength);
+        assert_eq!(err(&[0x04, 0x00]), ErrorCode::InvalidAttributeValueLength);
+        assert_eq!(err(&[0x0A]), ErrorCode::OperationFailed);
+    }
+
+    #[test]
+    fn service() {
+        let ess = EnvSensingService::new()
+            .with_sensor(
+                Sensor::Temperature,
+                SensorOpts {
+                    measurement: Some(EsMeasurement {
+                        sampling: SamplingFunction::ArithmeticMean,
+                        period: Duration::from_secs(60),
+                        update_interval: Duration::from_secs(10),
+                        application: 0x01,
+                        uncertainty: 0xFF,
+                    }),
+                    valid_range: Some((-40.0, 85.0)),
+                    trigger: Some(Trigger::OnChange),
+                },
+            )
+            .with_sensor(Sensor::Humidity, SensorOpts::default());
+        let t = TestDb::new(|db| ess.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (temp, hum) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(temp.properties(), Prop::READ | Prop::NOTIFY);
+        assert_eq!(hum.properties(), Prop::READ);
+        let desc = |u: Descriptor| t.db.iter().find(|&(_, uuid, _)| uuid == u).unwrap();
+        assert_eq!(
+            desc(Descriptor::EnvironmentalSensingMeasurement).2,
+            [0, 0, 0x02, 60, 0, 0, 10, 0, 0, 0x01, 0xFF]
+        );
+        assert_eq!(desc(Descriptor::ValidRange).2, [0x60, 0xF0, 0x34, 0x21]);
+        let trig = desc(Descriptor::EnvironmentalSensingTriggerSetting).0;
+
+        let read = |hdl| t.read(hdl).unwrap();
+        ess.set_value(1, 45.5);
+        assert_eq!(read(hum.value_handle()), [0xC6, 0x11]);
+        assert_eq!(read(trig), [0x03]);
+
+        // Notify on change
+        let (tx, mut rx) = mpsc::channel(4);
+        let req = NotifyReq {
+            hdl: temp.value_handle(),
+            uuid: temp.uuid(),
+            mtu: 23,
+            ind: false,
+            tx,
+            ct: CancellationToken::new(),
+        };
+        t.io.notify(req).unwrap();
+        ess.set_value(0, 20.0);
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [0xD0, 0x07]);
+        ess.set_value(0, 20.0);
+        rx.try_recv().unwrap_err();
+
+        // Client-configured trigger
+        let write = |v: &[u8]| t.write(trig, v);
+        assert_eq!(write(&[0xFF]), Err(ErrorCode::OperationFailed));
+        write(&[0x06, 0xD0, 0x07]).unwrap();
+        assert_eq!(read(trig), [0x06, 0xD0, 0x07]);
+        ess.set_value(0, 20.0);
+        rx.try_recv().unwrap_err();
+        ess.set_value(0, 20.5);
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [0x02, 0x08]);
+        ess.set_value(0, 20.5);
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [0x02, 0x08]);
+        assert!((ess.value(0) - 20.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,547 @@
+//! Glucose Service ([GLS]).
+//!
+//! This service exposes glucose measurements and their context from a glucose
+//! meter. Stored records are retrieved by the client via the Record Access
+//! Control Point.
+//!
+//! [GLS]: https://www.bluetooth.com/specifications/specs/glucose-service-1-0-1/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use structbuf::Packer;
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{
+    Builder, Characteristic, Db, Io, IoReq, IoResult, Notify, NotifyReq, Prop, Service,
+};
+use crate::SyncMutex;
+
+use super::cts::ExactTime256;
+use super::racp::{Racp, RacpState, RecordStore};
+use super::sfloat::Sfloat;
+
+/// Glucose record storage.
+pub type GlucoseStore = dyn RecordStore<Record = GlucoseRecord>;
+
+/// Glucose service instance. Clones refer to the same instance.
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct GlucoseService(Arc<SyncMutex<State>>);
+
+impl GlucoseService {
+    /// Creates a glucose service that provides stored records from `store`.
+    #[inline]
+    #[must_use]
+    pub fn new(store: Arc<GlucoseStore>) -> Self {
+        Self(Arc::new(SyncMutex::new(State {
+            store,
+            features: Features::empty(),
+            context: false,
+            meas: None,
+            ctx: None,
+            racp: Racp::default(),
+        })))
+    }
+
+    /// Sets the supported features.
+    #[inline]
+    #[must_use]
+    pub fn with_features(self, f: Features) -> Self {
+        self.0.lock().features = f;
+        self
+    }
+
+    /// Adds the Glucose Measurement Context characteristic.
+    #[inline]
+    #[must_use]
+    pub fn with_context(self) -> Self {
+        self.0.lock().context = true;
+        self
+    }
+
+    /// Notifies the client of a new record, which should also be added to the
+    /// record store.
+    pub fn push(&self, r: &GlucoseRecord) {
+        let s = self.0.lock();
+        let ctx = s.has_context(r);
+        for (i, ntf) in s.record_ntf(r).into_iter().enumerate() {
+            let Some(n) = ntf else { continue };
+            let v = n.notify_dropping_if_full(|p| {
+                if i == 0 {
+                    r.measurement.pack(p, ctx);
+                } else if let Some(c) = r.context.as_ref() {
+                    c.pack(p, r.measurement.seq);
+                }
+            });
+            if let Err(e) = v {
+                debug!("Glucose notify error: {e}");
+            }
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (features, context) = {
+            let s = self.0.lock();
+            (s.features, s.context)
+        };
+        let (hdl, ()) = db.primary_service(Service::Glucose, [], |db| {
+            use Characteristic::*;
+            // Glucose Measurement ([GLS] Section 3.1)
+            db.characteristic(
+                GlucoseMeasurement,
+                Prop::NOTIFY,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().measurement_io(req, false)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+
+            // Glucose Measurement Context ([GLS] Section 3.2)
+            if context {
+                db.characteristic(
+                    GlucoseMeasurementContext,
+                    Prop::NOTIFY,
+                    ac.read(),
+                    Io::with(&self.0, |this, req| this.lock().measurement_io(req, true)),
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+
+            // Glucose Feature ([GLS] Section 3.3)
+            db.ro_characteristic(
+                GlucoseFeature,
+                ac.read(),
+                features.bits().to_le_bytes(),
+                |_| {},
+            );
+
+            // Record Access Control Point ([GLS] Section 3.4)
+            db.characteristic(
+                RecordAccessControlPoint,
+                Prop::WRITE | Prop::INDICATE,
+                ac.write(),
+                {
+                    let this = Arc::clone(&self.0);
+                    move |req: IoReq| Racp::io(&this, req)
+                },
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+        });
+        hdl
+    }
+}
+
+/// Glucose service state.
+struct State {
+    store: Arc<GlucoseStore>,
+    features: Features,
+    context: bool,
+    meas: Option<NotifyReq>,
+    ctx: Option<NotifyReq>,
+    racp: Racp,
+}
+
+impl State {
+    /// Handles Glucose Measurement and Glucose Measurement Context
+    /// characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq, ctx: bool) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        *(if ctx { &mut self.ctx } else { &mut self.meas }) = Some(n);
+        Ok(())
+    }
+
+    /// Returns the notification requests for the measurement and context
+    /// characteristics of record `r`.
+    fn record_ntf(&self, r: &GlucoseRecord) -> [Option<&NotifyReq>; 2] {
+        fn open(n: Option<&NotifyReq>) -> Option<&NotifyReq> {
+            n.filter(|n| !n.is_closed())
+        }
+        let ctx = if self.has_context(r) {
+            open(self.ctx.as_ref())
+        } else {
+            None
+        };
+        [open(self.meas.as_ref()), ctx]
+    }
+
+    /// Returns whether the context of record `r` is reported.
+    #[inline]
+    const fn has_context(&self, r: &GlucoseRecord) -> bool {
+        self.context && r.context.is_some()
+    }
+}
+
+impl RacpState for State {
+    #[inline(always)]
+    fn racp(&mut self) -> &mut Racp {
+        &mut self.racp
+    }
+
+    #[inline(always)]
+    fn seqs(&self) -> Vec<u16> {
+        self.store.seqs()
+    }
+
+    fn report(&mut self, seq: u16) -> Option<Vec<Notify>> {
+        self.meas.as_ref().filter(|n| !n.is_closed())?;
+        let Some(r) = self.store.get(seq) else { return Some(Vec::new()) };
+        let [meas, ctx] = self.record_ntf(&r);
+        let mut v = Vec::with_capacity(2);
+        let has_ctx = self.has_context(&r);
+        v.extend(meas.map(|n| n.notify(|p| r.measurement.pack(p, has_ctx))));
+        if let (Some(n), Some(c)) = (ctx, r.context.as_ref()) {
+            v.push(n.notify(|p| c.pack(p, r.measurement.seq)));
+        }
+        Some(v)
+    }
+
+    #[inline(always)]
+    fn remove(&mut self, seq: u16) {
+        self.store.remove(seq);
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("features", &self.features)
+            .field("context", &self.context)
+            .field("racp", &self.racp)
+            .finish_non_exhaustive()
+    }
+}
+
+bitflags::bitflags! {
+    /// Glucose Feature characteristic value ([GLS] Section 3.3.1).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct Features: u16 {
+        const LOW_BATTERY_DETECTION = 1 << 0;
+        const SENSOR_MALFUNCTION_DETECTION = 1 << 1;
+        const SENSOR_SAMPLE_SIZE = 1 << 2;
+        const SENSOR_STRIP_INSERTION_ERROR_DETECTION = 1 << 3;
+        const SENSOR_STRIP_TYPE_ERROR_DETECTION = 1 << 4;
+        const SENSOR_RESULT_HIGH_LOW_DETECTION = 1 << 5;
+        const SENSOR_TEMPERATURE_HIGH_LOW_DETECTION = 1 << 6;
+        const SENSOR_READ_INTERRUPT_DETECTION = 1 << 7;
+        const GENERAL_DEVICE_FAULT = 1 << 8;
+        const TIME_FAULT = 1 << 9;
+        const MULTIPLE_BOND = 1 << 10;
+    }
+}
+
+/// Stored glucose record.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GlucoseRecord {
+    /// Glucose measurement.
+    pub measurement: Measurement,
+    /// Optional measurement context.
+    pub context: Option<Context>,
+}
+
+/// Glucose Measurement characteristic value ([GLS] Section 3.1.1).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Measurement {
+    /// Record sequence number.
+    pub seq: u16,
+    /// Base time of the measurement. The `weekday` and `fractions256` fields
+    /// are not transferred.
+    pub time: ExactTime256,
+    /// Offset from the base time in minutes.
+    pub time_offset: Option<i16>,
+    /// Glucose concentration.
+    pub concentration: Option<Concentration>,
+    /// Sensor Status Annunciation bits.
+    pub status: Option<u16>,
+}
+
+impl Measurement {
+    const TIME_OFFSET: u8 = 1 << 0;
+    const CONCENTRATION: u8 = 1 << 1;
+    const MOL_PER_L: u8 = 1 << 2;
+    const STATUS: u8 = 1 << 3;
+    const CONTEXT: u8 = 1 << 4;
+
+    /// Packs the measurement. `context` indicates whether the measurement is
+    /// followed by a context.
+    pub fn pack(&self, p: &mut Packer, context: bool) {
+        let mut f = 0;
+        if self.time_offset.is_some() {
+            f |= Self::TIME_OFFSET;
+        }
+        if let Some(c) = self.concentration {
+            f |= Self::CONCENTRATION;
+            if c.unit == ConcentrationUnit::MolPerL {
+                f |= Self::MOL_PER_L;
+            }
+        }
+        if self.status.is_some() {
+            f |= Self::STATUS;
+        }
+        if context {
+            f |= Self::CONTEXT;
+        }
+        p.u8(f).u16(self.seq).put(&self.time.to_bytes()[..7]);
+        if let Some(off) = self.time_offset {
+            p.i16(off);
+        }
+        if let Some(c) = self.concentration {
+            p.u16(Sfloat::new(c.value))
+                .u8(c.location << 4 | c.sample_type & 0xF);
+        }
+        if let Some(st) = self.status {
+            p.u16(st);
+        }
+    }
+}
+
+/// Glucose concentration, sample type, and sample location.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Concentration {
+    /// Concentration value in `unit`.
+    pub value: f32,
+    /// Concentration unit.
+    pub unit: ConcentrationUnit,
+    /// Sample type (1 = capillary whole blood, 2 = capillary plasma, ...).
+    pub sample_type: u8,
+    /// Sample location (1 = finger, 2 = alternate site test, ...).
+    pub location: u8,
+}
+
+/// Glucose concentration unit.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ConcentrationUnit {
+    /// Kilograms per liter.
+    #[default]
+    KgPerL,
+    /// Moles per liter.
+    MolPerL,
+}
+
+/// Glucose Measurement Context characteristic value ([GLS] Section 3.2.1).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Context {
+    /// Carbohydrate ID (1 = breakfast, 2 = lunch, ...) and amount in
+    /// kilograms.
+    pub carbohydrate: Option<(u8, f32)>,
+    /// Meal (1 = preprandial, 2 = postprandial, ...).
+    pub meal: Option<u8>,
+    /// Tester (1 = self, 2 = health care professional, ...) and health
+    /// (1 = minor health issues, 2 = major health issues, ...).
+    pub tester_health: Option<(u8, u8)>,
+    /// Exercise duration in seconds and intensity in percent.
+    pub exercise: Option<(u16, u8)>,
+    /// Medication ID (1 = rapid acting insulin, ...) and amount in kilograms.
+    pub medication: Option<(u8, f32)>,
+    /// Hemoglobin A1c in percent.
+    pub hba1c: Option<f32>,
+}
+
+impl Context {
+    const CARBOHYDRATE: u8 = 1 << 0;
+    const MEAL: u8 = 1 << 1;
+    const TESTER_HEALTH: u8 = 1 << 2;
+    const EXERCISE: u8 = 1 << 3;
+    const MEDICATION: u8 = 1 << 4;
+    const HBA1C: u8 = 1 << 6;
+
+    /// Packs the context of measurement `seq`.
+    pub fn pack(&self, p: &mut Packer, seq: u16) {
+        let flag = |present: bool, f: u8| if present { f } else { 0 };
+        let f = flag(self.carbohydrate.is_some(), Self::CARBOHYDRATE)
+            | flag(self.meal.is_some(), Self::MEAL)
+            | flag(self.tester_health.is_some(), Self::TESTER_HEALTH)
+            | flag(self.exercise.is_some(), Self::EXERCISE)
+            | flag(self.medication.is_some(), Self::MEDICATION)
+            | flag(self.hba1c.is_some(), Self::HBA1C);
+        p.u8(f).u16(seq);
+        if let Some((id, kg)) = self.carbohydrate {
+            p.u8(id).u16(Sfloat::new(kg));
+        }
+        if let Some(meal) = self.meal {
+            p.u8(meal);
+        }
+        if let Some((tester, health)) = self.tester_health {
+            p.u8(health << 4 | tester & 0xF);
+        }
+        if let Some((secs, pct)) = self.exercise {
+            p.u16(secs).u8(pct);
+        }
+        if let Some((id, kg)) = self.medication {
+            p.u8(id).u16(Sfloat::new(kg));
+        }
+        if let Some(pct) = self.hba1c {
+            p.u16(Sfloat::new(pct));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::{Records, TestDb};
+    use crate::gatt::{CharacteristicDef, DbEntry, NotifyVal};
+
+    use super::*;
+
+    fn record(seq: u16) -> GlucoseRecord {
+        GlucoseRecord {
+            measurement: Measurement {
+                seq,
+                time: ExactTime256::from_unix(1_680_000_000, 0),
+                ..Measurement::default()
+            },
+            context: (seq % 2 == 0).then_some(Context {
+                meal: Some(1),
+                ..Context::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn pack() {
+        let m = Measurement {
+            seq: 0x0102,
+            time: ExactTime256::from_unix(1_680_000_000, 0),
+            time_offset: Some(-60),
+            concentration: Some(Concentration {
+                value: 0.000_95,
+                unit: ConcentrationUnit::KgPerL,
+                sample_type: 1,
+                location: 1,
+            }),
+            status: Some(0x0010),
+        };
+        let mut b = StructBuf::new(32);
+        m.pack(&mut b.append(), true);
+        assert_eq!(
+            b.as_ref(),
+            [
+                0x1B, 0x02, 0x01, 0xE7, 0x07, 0x03, 0x1C, 0x0A, 0x28, 0x00, 0xC4, 0xFF, 0xB6, 0xA3,
+                0x11, 0x10, 0x00
+            ]
+        );
+
+        let c = Context {
+            carbohydrate: Some((1, 0.05)),
+            tester_health: Some((1, 2)),
+            exercise: Some((600, 50)),
+            hba1c: Some(6.5),
+            ..Context::default()
+        };
+        let mut b = StructBuf::new(32);
+        c.pack(&mut b.append(), 0x0102);
+        assert_eq!(
+            b.as_ref(),
+            [0x4D, 0x02, 0x01, 0x01, 0xF4, 0xC1, 0x21, 0x58, 0x02, 0x32, 0x8A, 0xE2]
+        );
+    }
+
+    #[tokio::test]
+    async fn racp() {
+        let store: Arc<Records<_>> = Arc::new((1..=3).map(|seq| (seq, record(seq))).collect());
+        let gls = GlucoseService::new(Arc::clone(&store) as _)
+            .with_features(Features::LOW_BATTERY_DETECTION)
+            .with_context();
+        let t = TestDb::new(|db| gls.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (meas, ctx, feat, racp) = (
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+        );
+        assert_eq!(t.db.get(feat.value_handle()).unwrap().1, [0x01, 0x00]);
+        assert_eq!(racp.properties(), Prop::WRITE | Prop::INDICATE);
+
+        let write = |v: &[u8]| t.write(racp.value_handle(), v);
+        assert_eq!(
+            write(&[0x01, 0x01]),
+            Err(ErrorCode::CccdImproperlyConfigured)
+        );
+
+        let sub = |c: &DbEntry<CharacteristicDef>, ind| {
+            let (tx, rx) = mpsc::channel(8);
+            let req = NotifyReq {
+                hdl: c.value_handle(),
+                uuid: c.uuid(),
+                mtu: 23,
+                ind,
+                tx,
+                ct: CancellationToken::new(),
+            };
+            t.io.notify(req).unwrap();
+            rx
+        };
+        let (mut meas, mut ctx, mut racp) = (sub(&meas, false), sub(&ctx, false), sub(&racp, true));
+
+        // Report number of stored records
+        write(&[0x04, 0x01]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x05, 0x00, 3, 0]);
+
+        // Report records greater than or equal to 2
+        write(&[0x01, 0x03, 0x01, 0x02, 0x00]).unwrap();
+        assert_eq!(
+            write(&[0x01, 0x01]),
+            Err(ErrorCode::ProcedureAlreadyInProgress)
+        );
+        assert_eq!(recv(&mut meas).await[..3], [0x10, 2, 0]);
+        assert_eq!(recv(&mut ctx).await, [0x02, 2, 0, 1]);
+        assert_eq!(recv(&mut meas).await[..3], [0x00, 3, 0]);
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x01, 0x01]);
+
+        // Invalid requests
+        write(&[0x01, 0x07]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x01, 0x04]);
+        write(&[0x09, 0x01]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x09, 0x02]);
+
+        // Abort
+        write(&[0x01, 0x01]).unwrap();
+        write(&[0x03, 0x00]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x03, 0x01]);
+        while let Ok(v) = meas.try_recv() {
+            v.result(Ok(()));
+        }
+
+        // Delete records
+        write(&[0x02, 0x05]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x02, 0x01]);
+        assert_eq!(store.seqs(), [2, 3]);
+        write(&[0x02, 0x01]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x02, 0x01]);
+        assert!(store.seqs().is_empty());
+        write(&[0x01, 0x01]).unwrap();
+        assert_eq!(recv(&mut racp).await, [0x06, 0x00, 0x01, 0x06]);
+    }
+
+    /// Receives the next notification or indication and completes it.
+    async fn recv(rx: &mut mpsc::Receiver<NotifyVal>) -> Vec<u8> {
+        let v = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let r = v.as_ref().to_vec();
+        v.result(Ok(()));
+        r
+    }
+}
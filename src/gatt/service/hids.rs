@@ -12,6 +12,7 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
+use tokio::sync::mpsc::error::TrySendError;
 use tracing::debug;
 
 use burble_const::{Characteristic, Descriptor, Service};
@@ -21,7 +22,7 @@ use burble_hid::mouse::Mouse;
 use burble_hid::usage::{GenericDesktop, Page};
 use burble_hid::{Device, Report, ReportType};
 
-use crate::att::{Access, ErrorCode};
+use crate::att::{Access, ErrorCode, Result};
 use crate::gatt::{Builder, Db, Io, IoReq, IoResult, Notify, NotifyReq, Prop};
 use crate::SyncMutex;
 
@@ -38,6 +39,26 @@ impl<T: Device + Send + 'static> HidService<T> {
         Self(Dev::new(dev))
     }
 
+    /// Sets the security requirements of all service characteristics. The
+    /// access type of `sec` is ignored. The default is to require encryption
+    /// ([HOGP] Section 6.1).
+    #[inline]
+    #[must_use]
+    pub fn with_security(self, sec: Access) -> Self {
+        self.0.lock().sec = sec;
+        self
+    }
+
+    /// Returns a channel receiver for output and feature reports written by
+    /// the host. Only the most recently created receiver gets the reports.
+    #[inline]
+    #[must_use]
+    pub fn output(&self) -> tokio::sync::mpsc::Receiver<Report> {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        self.0.lock().out = Some(tx);
+        rx
+    }
+
     /// Returns a watch receiver that reflects device state changes.
     #[inline]
     #[must_use]
@@ -65,6 +86,17 @@ impl<T: Device + Send + 'static> HidService<T> {
         }
     }
 
+    /// Sends an input report to the host, bypassing the device report queue.
+    /// Returns `Ok(false)` if the host has not enabled notifications for the
+    /// report in the current protocol mode.
+    pub async fn send(&self, r: Report) -> Result<bool> {
+        let ntf = self.0.lock().notify(r);
+        match ntf {
+            Some(ntf) => ntf.await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
     /// Calls `f` to control the device followed by a flush.
     #[inline(always)]
     pub async fn exec(&self, f: impl FnOnce(&mut T) + Send) {
@@ -78,17 +110,16 @@ impl<T: Device + Send + 'static> HidService<T> {
     ///
     /// Panics if the report descriptor is too long.
     pub fn define(&self, db: &mut Builder<Db>) {
-        const RO: Access = Access::READ.authn().encrypt();
-        const WO: Access = Access::WRITE.authn().encrypt();
-        const RW: Access = Access::READ_WRITE.authn().encrypt();
-        let ((ver, loc), rd, boot_support) = {
+        let ((ver, loc), rd, boot_support, sec) = {
             let d = self.0.lock();
             (
                 d.dev.hid_descriptor(),
                 d.dev.report_descriptor(),
                 d.dev.boot_mode().is_some(),
+                d.sec,
             )
         };
+        let (ro, wo, rw) = (sec.read(), sec.write(), sec.read_write());
         db.primary_service(Service::HumanInterfaceDevice, [], move |db| {
             use Characteristic::*;
             use GenericDesktop::{Keyboard, Mouse};
@@ -98,20 +129,20 @@ impl<T: Device + Send + 'static> HidService<T> {
             let flags = 0b10; // NormallyConnectable = 1, RemoteWake = 0
             db.ro_characteristic(
                 HidInformation,
-                RO,
+                ro,
                 [ver[0], ver[1], loc as _, flags],
                 |_| {},
             );
 
             // Report Map ([HIDS] Section 2.6)
             assert!(rd.as_ref().len() <= 512, "report descriptor too long");
-            db.ro_characteristic(ReportMap, RO, &rd, |_| {});
+            db.ro_characteristic(ReportMap, ro, &rd, |_| {});
 
             // HID Control Point ([HIDS] Section 2.11)
             db.characteristic(
                 HidControlPoint,
                 Prop::WRITE_CMD,
-                WO,
+                wo,
                 Io::with(&self.0, |this, req| this.lock().control_point_io(req)),
                 |_| {},
             );
@@ -131,9 +162,9 @@ impl<T: Device + Send + 'static> HidService<T> {
                     _ => continue,
                 };
                 let (props, perms) = match rref.typ {
-                    Input => (Prop::NOTIFY, RO), // TODO: Optional Write?
-                    Output => (Prop::WRITE.union(Prop::WRITE_CMD), RW),
-                    Feature => (Prop::WRITE, RW),
+                    Input => (Prop::NOTIFY, ro), // TODO: Optional Write?
+                    Output => (Prop::WRITE.union(Prop::WRITE_CMD), rw),
+                    Feature => (Prop::WRITE, rw),
                 };
                 db.characteristic(
                     uuid,
@@ -142,12 +173,12 @@ impl<T: Device + Send + 'static> HidService<T> {
                     Io::with(&self.0, move |this, req| this.lock().report_io(rref, req)),
                     |db| {
                         if rref.typ.is_input() {
-                            db.cccd(RW);
+                            db.cccd(rw);
                         }
                         if matches!(uuid, Report) {
                             db.ro_descriptor(
                                 Descriptor::ReportReference,
-                                RO,
+                                ro,
                                 [rref.id, rref.typ as _],
                             );
                         }
@@ -164,7 +195,7 @@ impl<T: Device + Send + 'static> HidService<T> {
                 db.characteristic(
                     ProtocolMode,
                     Prop::READ.union(Prop::WRITE_CMD),
-                    RW,
+                    rw,
                     Io::with(&self.0, |this, req| this.lock().protocol_mode_io(req)),
                     |_| {},
                 );
@@ -240,6 +271,8 @@ struct Dev<T> {
     boot: BTreeSet<(ReportType, u8)>,
     ntf: BTreeMap<ReportRef, NotifyReq>,
     w: tokio::sync::watch::Sender<HidState>,
+    sec: Access,
+    out: Option<tokio::sync::mpsc::Sender<Report>>,
 }
 
 impl<T: Device> Dev<T> {
@@ -252,7 +285,14 @@ impl<T: Device> Dev<T> {
         let mut s = HidState(Flag::empty());
         s.0.set(Flag::BOOT, Self::boot_mode(&dev));
         let (w, _) = tokio::sync::watch::channel(s);
-        Arc::new(SyncMutex::new(Self { dev, boot, ntf, w }))
+        Arc::new(SyncMutex::new(Self {
+            dev,
+            boot,
+            ntf,
+            w,
+            sec: Access::NONE.encrypt(),
+            out: None,
+        }))
     }
 
     /// Updates connection state when an I/O request comes in.
@@ -312,11 +352,17 @@ impl<T: Device> Dev<T> {
                 let mut v = (self.dev.get_report(rref.typ, rref.id))
                     .ok_or(ErrorCode::RequestNotSupported)?;
                 w.update(&mut v)?;
-                if self.dev.set_report(v) {
-                    Ok(())
-                } else {
-                    Err(ErrorCode::ValueNotAllowed)
+                if !self.dev.set_report(v) {
+                    return Err(ErrorCode::ValueNotAllowed);
+                }
+                if let Some(out) = self.out.as_ref() {
+                    match out.try_send(v) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => debug!("Output report dropped"),
+                        Err(TrySendError::Closed(_)) => self.out = None,
+                    }
                 }
+                Ok(())
             }
             IoReq::Notify(n) => {
                 if n.is_indicate() {
@@ -357,6 +403,22 @@ impl<T: Device> Dev<T> {
         }
     }
 
+    /// Returns a notification future for input report `r` or [`None`] if the
+    /// host has not enabled notifications for it.
+    fn notify(&self, r: Report) -> Option<Notify> {
+        let boot = Self::boot_mode(&self.dev) && self.boot.contains(&(r.typ(), r.id()));
+        let rref = ReportRef {
+            typ: r.typ(),
+            id: r.id(),
+            boot,
+        };
+        (self.ntf.get(&rref)).map(|n| {
+            n.notify(|p| {
+                p.put(r);
+            })
+        })
+    }
+
     /// Returns the device boot protocol mode state.
     #[inline(always)]
     #[must_use]
@@ -379,17 +441,9 @@ impl<T: Device> Iterator for Dev<T> {
                 debug!("Disconnected: {s:?}");
             });
         }
-        let boot = Self::boot_mode(&self.dev);
-        for r in self.dev.by_ref() {
-            let rref = ReportRef {
-                typ: r.typ(),
-                id: r.id(),
-                boot: boot && self.boot.contains(&(r.typ(), r.id())),
-            };
-            if let Some(n) = self.ntf.get(&rref) {
-                return Some(n.notify(|p| {
-                    p.put(r);
-                }));
+        while let Some(r) = self.dev.next() {
+            if let Some(ntf) = self.notify(r) {
+                return Some(ntf);
             }
         }
         None
@@ -593,7 +647,6 @@ mod tests {
     use std::pin::pin;
     use std::time::Duration;
 
-    use structbuf::StructBuf;
     use tokio::sync::{mpsc, watch};
     use tokio::time::timeout;
     use tokio_util::sync::CancellationToken;
@@ -601,9 +654,11 @@ mod tests {
     use burble_hid::mouse::Button;
     use burble_hid::usage::Key;
 
-    use crate::att::{Handle, Opcode};
+    use crate::att::Opcode;
     use crate::gatt::io::NotifyVal;
-    use crate::gatt::{CharacteristicDef, DbEntry, ReadReq, WriteReq};
+    use crate::gatt::service::test::TestDb;
+    use crate::gatt::{CharacteristicDef, DbEntry};
+    use crate::hci;
 
     use super::*;
 
@@ -620,13 +675,10 @@ mod tests {
         let rd = km.report_descriptor();
 
         // Define GATT service
-        let mut db = Db::build();
         let hid = HidService::new(km);
         let mut state = hid.state();
-        hid.define(&mut db);
-        let (db, io) = db.freeze();
-        let srv = db.primary_services(Handle::MIN, None).next().unwrap();
-        let mut chars = db.characteristics(srv.handle_range());
+        let t = TestDb::new(|db| hid.define(db));
+        let mut chars = t.characteristics();
 
         // Helper functions
         let mut next_char = |uuid: Characteristic, props: Prop| {
@@ -639,26 +691,9 @@ mod tests {
             assert!(r.has_changed().unwrap());
             assert_eq!(*r.borrow_and_update(), v);
         }
-        let read = |char: DbEntry<CharacteristicDef>| {
-            let mut req = ReadReq {
-                op: Opcode::ReadReq,
-                hdl: char.value_handle(),
-                uuid: Some(char.uuid()),
-                off: 0,
-                buf: StructBuf::new(255),
-            };
-            io.read(&mut req).unwrap();
-            req.buf
-        };
+        let read = |char: DbEntry<CharacteristicDef>| t.read(char.value_handle()).unwrap();
         let write = |char: DbEntry<CharacteristicDef>, val: &[u8]| {
-            let req = WriteReq {
-                op: Opcode::WriteCmd,
-                hdl: char.value_handle(),
-                uuid: char.uuid(),
-                off: 0,
-                val,
-            };
-            io.write(&req).unwrap();
+            (t.write_as(TestDb::PEER, Opcode::WriteCmd, char.value_handle(), val)).unwrap();
         };
         let enable_notify = |char: DbEntry<CharacteristicDef>| {
             let (tx, rx) = mpsc::channel(1);
@@ -671,20 +706,20 @@ mod tests {
                 tx,
                 ct: ct.clone(),
             };
-            io.notify(req).unwrap();
+            t.io.notify(req).unwrap();
             (rx, ct)
         };
 
         // HID Information
         let ch = next_char(Characteristic::HidInformation, Prop::READ);
         assert_eq!(
-            db.get(ch.value_handle()).unwrap().1,
+            t.db.get(ch.value_handle()).unwrap().1,
             [0x11, 0x01, Locale::Us as u8, 0b10].as_slice()
         );
 
         // Report Map
         let c = next_char(Characteristic::ReportMap, Prop::READ);
-        assert_eq!(db.get(c.value_handle()).unwrap().1, rd.as_ref());
+        assert_eq!(t.db.get(c.value_handle()).unwrap().1, rd.as_ref());
 
         // HID Control Point
         let c = next_char(Characteristic::HidControlPoint, Prop::WRITE_CMD);
@@ -696,7 +731,7 @@ mod tests {
 
         // Keyboard Input
         let c = next_char(Characteristic::Report, IN_PROPS);
-        assert_eq!(read(c).as_ref(), &[0; 7]);
+        assert_eq!(read(c), [0; 7]);
         let (mut krx, kct) = enable_notify(c);
         assert_changed(&mut state, HidState(Flag::CONN.union(Flag::ACTIVE)));
 
@@ -705,11 +740,11 @@ mod tests {
         assert_eq!(*led.borrow_and_update(), Led::empty());
         write(c, &[Led::NUM_LOCK.bits()]);
         assert_changed(&mut led, Led::NUM_LOCK);
-        assert_eq!(read(c).as_ref(), &[Led::NUM_LOCK.bits()]);
+        assert_eq!(read(c), [Led::NUM_LOCK.bits()]);
 
         // Mouse Input
         let c = next_char(Characteristic::Report, IN_PROPS);
-        assert_eq!(read(c).as_ref(), &[0; 3]);
+        assert_eq!(read(c), [0; 3]);
         let (mut mrx, mct) = enable_notify(c);
 
         // Boot Reports
@@ -722,9 +757,9 @@ mod tests {
             Characteristic::ProtocolMode,
             Prop::READ.union(Prop::WRITE_CMD),
         );
-        assert_eq!(read(c).as_ref(), &[1]);
+        assert_eq!(read(c), [1]);
         write(c, &[0]);
-        assert_eq!(read(c).as_ref(), &[0]);
+        assert_eq!(read(c), [0]);
         assert_changed(
             &mut state,
             HidState(Flag::CONN.union(Flag::ACTIVE).union(Flag::BOOT)),
@@ -772,4 +807,50 @@ mod tests {
         timeout(Duration::from_secs(1), hid.flush()).await.unwrap();
         assert_changed(&mut state, HidState(Flag::empty()));
     }
+
+    #[tokio::test]
+    async fn send_output() {
+        let hid = HidService::new(KeyboardMouse::new(Keyboard::us(1), Mouse::new(2, 0)));
+        let mut out = hid.output();
+        let t = TestDb::new(|db| hid.define(db));
+        let mut reports = (t.characteristics()).filter(|c| c.uuid() == Characteristic::Report);
+        let (kin, kout) = (reports.next().unwrap(), reports.next().unwrap());
+
+        // Encryption is required, but not authentication
+        let read = |sec| {
+            let req = Opcode::ReadReq.request(sec);
+            t.db.try_access(req, kin.value_handle()).map(|_| ())
+        };
+        assert!(read(hci::ConnSec::empty()).is_err());
+        read(hci::ConnSec::key_len(128)).unwrap();
+
+        // Output report
+        (t.write(kout.value_handle(), &[Led::CAPS_LOCK.bits()])).unwrap();
+        assert_eq!(
+            out.try_recv().unwrap(),
+            Report::output(1, &[Led::CAPS_LOCK.bits()])
+        );
+
+        // Input report
+        let (tx, mut rx) = mpsc::channel(1);
+        let req = NotifyReq {
+            hdl: kin.value_handle(),
+            uuid: kin.uuid(),
+            mtu: 255,
+            ind: false,
+            tx,
+            ct: CancellationToken::new(),
+        };
+        t.io.notify(req).unwrap();
+        let r = Report::input(1, &[0, Key::A as _, 0, 0, 0, 0, 0]);
+        let send = hid.send(r);
+        let ack = async {
+            let ntf = rx.recv().await.unwrap();
+            assert_eq!(ntf.as_ref(), &r.prefixed()[1..]);
+            ntf.result(Ok(()));
+        };
+        let (sent, ()) = tokio::join!(send, ack);
+        assert!(sent.unwrap());
+        assert!(!hid.send(Report::input(2, &[0; 3])).await.unwrap());
+    }
 }
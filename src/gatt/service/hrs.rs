@@ -0,0 +1,355 @@
+//! Heart Rate Service ([HRS]).
+//!
+//! This service exposes heart rate and other data from a heart rate sensor
+//! intended for fitness applications.
+//!
+//! [HRS]: https://www.bluetooth.com/specifications/specs/heart-rate-service-1-0/
+
+use std::sync::Arc;
+
+use structbuf::{Packer, Unpack};
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+/// Heart Rate service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct HeartRateService(Arc<SyncMutex<State>>);
+
+impl HeartRateService {
+    /// Creates a heart rate service without the optional features.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the Body Sensor Location characteristic.
+    #[inline]
+    #[must_use]
+    pub fn with_location(self, loc: BodySensorLocation) -> Self {
+        self.0.lock().loc = Some(loc);
+        self
+    }
+
+    /// Enables the Energy Expended feature, which adds the accumulated energy
+    /// to each measurement and the Heart Rate Control Point characteristic
+    /// that allows the client to reset it ([HRS] Section 3.1.1.3).
+    #[inline]
+    #[must_use]
+    pub fn with_energy_expended(self) -> Self {
+        self.0.lock().energy = Some(0);
+        self
+    }
+
+    /// Returns the accumulated energy expended in kilojoules or [`None`] if
+    /// the feature is not enabled.
+    #[inline]
+    #[must_use]
+    pub fn energy_expended(&self) -> Option<u16> {
+        self.0.lock().energy
+    }
+
+    /// Adds `kj` kilojoules to the accumulated energy expended. The value
+    /// saturates at `0xFFFF` until reset by the client.
+    #[inline]
+    pub fn add_energy_expended(&self, kj: u16) {
+        if let Some(e) = self.0.lock().energy.as_mut() {
+            *e = e.saturating_add(kj);
+        }
+    }
+
+    /// Notifies subscribed clients of a new measurement. The energy expended
+    /// field is set by the service when the feature is enabled. RR-Interval
+    /// values that do not fit within the client's MTU are discarded.
+    pub fn push_measurement(&self, mut m: Measurement) {
+        let mut s = self.0.lock();
+        m.energy_expended = s.energy;
+        s.ntf.retain(|n| {
+            n.notify_dropping_if_full(|p| m.pack(p))
+                .map_err(|e| debug!("Heart rate notify error: {e}"))
+                .is_ok()
+        });
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (loc, energy) = {
+            let s = self.0.lock();
+            (s.loc, s.energy.is_some())
+        };
+        let (hdl, ()) = db.primary_service(Service::HeartRate, [], |db| {
+            use Characteristic::*;
+            // Heart Rate Measurement ([HRS] Section 3.1)
+            db.characteristic(
+                HeartRateMeasurement,
+                Prop::NOTIFY,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().measurement_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+
+            // Body Sensor Location ([HRS] Section 3.2)
+            if let Some(loc) = loc {
+                db.ro_characteristic(BodySensorLocation, ac.read(), [loc as u8], |_| {});
+            }
+
+            // Heart Rate Control Point ([HRS] Section 3.3)
+            if energy {
+                db.characteristic(
+                    HeartRateControlPoint,
+                    Prop::WRITE,
+                    ac.write(),
+                    Io::with(&self.0, |this, req| this.lock().control_point_io(req)),
+                    |_| {},
+                );
+            }
+        });
+        hdl
+    }
+}
+
+/// Heart rate service state.
+#[derive(Debug, Default)]
+struct State {
+    loc: Option<BodySensorLocation>,
+    energy: Option<u16>,
+    ntf: Vec<NotifyReq>,
+}
+
+impl State {
+    /// Handles Heart Rate Measurement characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ntf.retain(|n| !n.is_closed());
+        self.ntf.push(n);
+        Ok(())
+    }
+
+    /// Handles Heart Rate Control Point characteristic I/O
+    /// ([HRS] Section 3.3.1).
+    #[allow(clippy::needless_pass_by_value)]
+    fn control_point_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Write(w) = req else { return Err(ErrorCode::RequestNotSupported) };
+        let mut v = [0];
+        w.update(&mut v)?;
+        match (v[0], self.energy.as_mut()) {
+            (0x01, Some(e)) => {
+                *e = 0;
+                Ok(())
+            }
+            _ => Err(ErrorCode::ControlPointNotSupported),
+        }
+    }
+}
+
+/// Body sensor location ([HRS] Section 3.2.1).
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum BodySensorLocation {
+    Other = 0,
+    Chest = 1,
+    Wrist = 2,
+    Finger = 3,
+    Hand = 4,
+    EarLobe = 5,
+    Foot = 6,
+}
+
+/// Heart Rate Measurement characteristic value ([HRS] Section 3.1.1).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Measurement {
+    /// Heart rate in beats per minute.
+    pub bpm: u16,
+    /// Sensor contact status or [`None`] if sensor contact detection is not
+    /// supported.
+    pub contact: Option<bool>,
+    /// Accumulated energy expended in kilojoules.
+    pub energy_expended: Option<u16>,
+    /// RR-Interval values in units of 1/1024 seconds, oldest first.
+    pub rr: Vec<u16>,
+}
+
+impl Measurement {
+    const U16: u8 = 1 << 0;
+    const CONTACT: u8 = 1 << 1;
+    const CONTACT_SUPPORTED: u8 = 1 << 2;
+    const ENERGY: u8 = 1 << 3;
+    const RR: u8 = 1 << 4;
+
+    /// Creates a measurement with the specified heart rate.
+    #[inline]
+    #[must_use]
+    pub const fn new(bpm: u16) -> Self {
+        Self {
+            bpm,
+            contact: None,
+            energy_expended: None,
+            rr: Vec::new(),
+        }
+    }
+
+    /// Returns the flags field for the measurement.
+    #[must_use]
+    pub fn flags(&self) -> u8 {
+        let mut f = 0;
+        if self.bpm > 0xFF {
+            f |= Self::U16;
+        }
+        match self.contact {
+            Some(true) => f |= Self::CONTACT_SUPPORTED | Self::CONTACT,
+            Some(false) => f |= Self::CONTACT_SUPPORTED,
+            None => {}
+        }
+        if self.energy_expended.is_some() {
+            f |= Self::ENERGY;
+        }
+        if !self.rr.is_empty() {
+            f |= Self::RR;
+        }
+        f
+    }
+
+    /// Packs the measurement. RR-Interval values that do not fit are
+    /// discarded.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn pack(&self, p: &mut Packer) {
+        let f = self.flags();
+        p.u8(f);
+        if f & Self::U16 == 0 {
+            p.u8(self.bpm as u8);
+        } else {
+            p.u16(self.bpm);
+        }
+        if let Some(e) = self.energy_expended {
+            p.u16(e);
+        }
+        for &rr in self.rr.iter().take(p.remaining() / 2) {
+            p.u16(rr);
+        }
+    }
+
+    /// Unpacks a measurement.
+    #[must_use]
+    pub fn unpack(v: &[u8]) -> Option<Self> {
+        let mut v = v.unpack();
+        let f = v.u8();
+        let bpm = if f & Self::U16 == 0 {
+            u16::from(v.u8())
+        } else {
+            v.u16()
+        };
+        let contact = (f & Self::CONTACT_SUPPORTED != 0).then_some(f & Self::CONTACT != 0);
+        let energy_expended = (f & Self::ENERGY != 0).then(|| v.u16());
+        let mut rr = Vec::with_capacity(v.len() / 2);
+        if f & Self::RR != 0 {
+            while v.len() >= 2 {
+                rr.push(v.u16());
+            }
+        }
+        v.is_ok().then_some(Self {
+            bpm,
+            contact,
+            energy_expended,
+            rr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn measurement() {
+        let pack = |m: &Measurement, n: usize| {
+            let mut b = StructBuf::new(n);
+            m.pack(&mut b.append());
+            b.as_ref().to_vec()
+        };
+        let mut m = Measurement::new(72);
+        assert_eq!(pack(&m, 20), [0x00, 72]);
+        m.contact = Some(false);
+        assert_eq!(pack(&m, 20), [0x04, 72]);
+        m.contact = Some(true);
+        assert_eq!(pack(&m, 20), [0x06, 72]);
+        m.bpm = 300;
+        assert_eq!(pack(&m, 20), [0x07, 0x2C, 0x01]);
+        m.energy_expended = Some(0x1234);
+        assert_eq!(pack(&m, 20), [0x0F, 0x2C, 0x01, 0x34, 0x12]);
+        m.rr = vec![0x0400, 0x0401];
+        let v = pack(&m, 20);
+        assert_eq!(v, [0x1F, 0x2C, 0x01, 0x34, 0x12, 0x00, 0x04, 0x01, 0x04]);
+        assert_eq!(Measurement::unpack(&v).unwrap(), m);
+
+        // RR-Interval values that do not fit are discarded
+        m.rr = (0..10).collect();
+        let v = pack(&m, 20);
+        assert_eq!(v.len(), 19);
+        assert_eq!(
+            Measurement::unpack(&v).unwrap().rr,
+            (0..7).collect::<Vec<_>>()
+        );
+
+        assert!(Measurement::unpack(&[]).is_none());
+        assert!(Measurement::unpack(&[0x01, 72]).is_none());
+    }
+
+    #[test]
+    fn service() {
+        let hrs = HeartRateService::new()
+            .with_location(BodySensorLocation::Wrist)
+            .with_energy_expended();
+        let t = TestDb::new(|db| hrs.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (hrm, loc, cp) = (
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+        );
+        assert_eq!(hrm.uuid(), Characteristic::HeartRateMeasurement);
+        assert_eq!(t.db.get(loc.value_handle()).unwrap().1, [2]);
+        assert_eq!(cp.uuid(), Characteristic::HeartRateControlPoint);
+
+        // Measurement notification
+        let (tx, mut rx) = mpsc::channel(4);
+        let req = NotifyReq {
+            hdl: hrm.value_handle(),
+            uuid: hrm.uuid(),
+            mtu: 23,
+            ind: false,
+            tx,
+            ct: CancellationToken::new(),
+        };
+        t.io.notify(req).unwrap();
+        hrs.add_energy_expended(10);
+        hrs.push_measurement(Measurement::new(60));
+        let v = rx.try_recv().unwrap();
+        assert_eq!(v.as_ref(), [0x08, 60, 10, 0]);
+
+        // Control point
+        let write = |v: u8| t.write(cp.value_handle(), &[v]);
+        assert_eq!(write(0x02), Err(ErrorCode::ControlPointNotSupported));
+        assert_eq!(hrs.energy_expended(), Some(10));
+        write(0x01).unwrap();
+        assert_eq!(hrs.energy_expended(), Some(0));
+    }
+}
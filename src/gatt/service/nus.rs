@@ -0,0 +1,290 @@
+//! Nordic UART Service (NUS).
+//!
+//! This is a vendor-specific service that emulates a serial port. The client
+//! writes data to the RX characteristic and receives data via TX
+//! characteristic notifications. The service is exposed to the application
+//! as a duplex byte stream.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll, Waker};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use burble_const::Uuid;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Db, Io, IoReq, IoResult, Notify, NotifyReq, Prop};
+use crate::SyncMutex;
+
+/// NUS service UUID.
+pub const SERVICE: Uuid = uuid(0x6E40_0001_B5A3_F393_E0A9_E50E_24DC_CA9E);
+
+/// RX characteristic UUID (client to server).
+pub const RX: Uuid = uuid(0x6E40_0002_B5A3_F393_E0A9_E50E_24DC_CA9E);
+
+/// TX characteristic UUID (server to client).
+pub const TX: Uuid = uuid(0x6E40_0003_B5A3_F393_E0A9_E50E_24DC_CA9E);
+
+/// Nordic UART service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct NusService(Arc<SyncMutex<State>>);
+
+impl NusService {
+    /// Maximum number of received bytes that are buffered until read by the
+    /// application. Client writes that exceed this limit are rejected.
+    const RX_CAP: usize = 4096;
+
+    /// Creates a Nordic UART service.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a duplex stream for exchanging data with the client. Only one
+    /// stream should be used at a time.
+    #[inline]
+    #[must_use]
+    pub fn stream(&self) -> NusStream {
+        NusStream {
+            s: Arc::clone(&self.0),
+            pending: None,
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (hdl, ()) = db.primary_service(SERVICE, [], |db| {
+            db.characteristic(
+                RX,
+                Prop::WRITE | Prop::WRITE_CMD,
+                ac.write(),
+                Io::with(&self.0, |this, req| this.lock().rx_io(req)),
+                |_| {},
+            );
+            db.characteristic(
+                TX,
+                Prop::NOTIFY,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().tx_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+        });
+        hdl
+    }
+}
+
+/// Nordic UART service state.
+#[derive(Debug, Default)]
+struct State {
+    rx: VecDeque<u8>,
+    ntf: Option<NotifyReq>,
+    rd: Option<Waker>,
+    wr: Option<Waker>,
+}
+
+impl State {
+    /// Handles RX characteristic I/O.
+    #[allow(clippy::needless_pass_by_value)]
+    fn rx_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Write(w) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if self.rx.len() + w.value().len() > NusService::RX_CAP {
+            return Err(ErrorCode::InsufficientResources);
+        }
+        self.rx.extend(w.value());
+        if let Some(rd) = self.rd.take() {
+            rd.wake();
+        }
+        Ok(())
+    }
+
+    /// Handles TX characteristic I/O.
+    fn tx_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ntf = Some(n);
+        if let Some(wr) = self.wr.take() {
+            wr.wake();
+        }
+        Ok(())
+    }
+}
+
+/// Duplex byte stream of a Nordic UART service.
+///
+/// Writes are split into notifications of up to `ATT_MTU - 3` bytes. Only one
+/// notification is in flight at a time, so writes wait until the previous
+/// notification is transferred to the controller. Writes also wait for a
+/// client to enable TX notifications.
+#[derive(Debug)]
+pub struct NusStream {
+    s: Arc<SyncMutex<State>>,
+    pending: Option<Pin<Box<Notify>>>,
+}
+
+impl NusStream {
+    /// Polls the in-flight notification, if any.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(ntf) = self.pending.as_mut() {
+            let r = ready!(ntf.as_mut().poll(cx));
+            self.pending = None;
+            r.map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for NusStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut s = self.s.lock();
+        if s.rx.is_empty() {
+            s.rd = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let (v, _) = s.rx.as_slices();
+        let n = v.len().min(buf.remaining());
+        buf.put_slice(&v[..n]);
+        s.rx.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for NusStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let mut s = this.s.lock();
+        let Some(n) = s.ntf.as_ref().filter(|n| !n.is_closed()) else {
+            s.ntf = None;
+            s.wr = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+        let v = &buf[..buf.len().min(n.max_len())];
+        let mut ntf = Box::pin(n.notify(|p| {
+            p.put(v);
+        }));
+        drop(s);
+        match ntf.as_mut().poll(cx) {
+            Poll::Ready(r) => r.map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?,
+            Poll::Pending => this.pending = Some(ntf),
+        }
+        Poll::Ready(Ok(v.len()))
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Creates a 128-bit UUID constant.
+const fn uuid(v: u128) -> Uuid {
+    match Uuid::new(v) {
+        Some(u) => u,
+        None => panic!("invalid UUID"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::att::Opcode;
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stream() {
+        let nus = NusService::new();
+        let t = TestDb::new(|db| nus.define(db, Access::NONE));
+        assert_eq!(t.service().uuid(), SERVICE);
+        let mut chars = t.characteristics();
+        let (rx, tx) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!((rx.uuid(), tx.uuid()), (RX, TX));
+        let mut s = nus.stream();
+
+        // Client to server
+        let write = |v: &[u8]| t.write_as(TestDb::PEER, Opcode::WriteCmd, rx.value_handle(), v);
+        write(b"hello ").unwrap();
+        write(b"world").unwrap();
+        let mut v = [0; 11];
+        s.read_exact(&mut v).await.unwrap();
+        assert_eq!(&v, b"hello world");
+        assert_eq!(
+            write(&[0; NusService::RX_CAP + 1]),
+            Err(ErrorCode::InsufficientResources)
+        );
+
+        // Writes wait for notifications to be enabled
+        let data: Vec<u8> = (0..50).collect();
+        let wait = Duration::from_millis(10);
+        timeout(wait, s.write_all(&data)).await.unwrap_err();
+
+        // Server to client
+        let (ntx, mut nrx) = mpsc::channel(1);
+        let ct = CancellationToken::new();
+        let req = NotifyReq {
+            hdl: tx.value_handle(),
+            uuid: tx.uuid(),
+            mtu: 23,
+            ind: false,
+            tx: ntx,
+            ct: ct.clone(),
+        };
+        t.io.notify(req).unwrap();
+        let recv = async {
+            let mut out = Vec::new();
+            while out.len() < data.len() {
+                let ntf = nrx.recv().await.unwrap();
+                assert!(ntf.as_ref().len() <= 20);
+                out.extend_from_slice(ntf.as_ref());
+                ntf.result(Ok(()));
+            }
+            out
+        };
+        let send = async {
+            s.write_all(&data).await.unwrap();
+            s.flush().await.unwrap();
+        };
+        let (out, ()) = timeout(Duration::from_secs(1), async { tokio::join!(recv, send) })
+            .await
+            .unwrap();
+        assert_eq!(out, data);
+
+        // Closed session
+        ct.cancel();
+        timeout(wait, s.write_all(&data)).await.unwrap_err();
+    }
+}
@@ -0,0 +1,639 @@
+//! Object Transfer Service ([OTS]).
+//!
+//! This service exposes a list of objects from an application-provided
+//! [`ObjectStore`]. The client selects the current object via the Object List
+//! Control Point, reads its metadata from the object characteristics, and
+//! requests actions on it via the Object Action Control Point.
+//!
+//! Object contents are transferred over an L2CAP connection-oriented channel
+//! (the Object Transfer Channel), which requires LE credit-based flow control
+//! mode. The L2CAP layer does not support dynamic channels yet, so the OACP
+//! Read procedure validates the request and responds with the "Channel
+//! Unavailable" result. The Calculate Checksum procedure can be used to verify
+//! object contents in the meantime.
+//!
+//! [OTS]: https://www.bluetooth.com/specifications/specs/object-transfer-service-1-0/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use structbuf::Unpack;
+
+use burble_const::Uuid;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, Prop, Service, ServiceDef};
+use crate::SyncMutex;
+
+use super::cp::ControlPoint;
+
+/// Object metadata characteristic value encoder.
+type MetadataFn = fn(&Metadata, ObjectId) -> Vec<u8>;
+
+/// 48-bit object identifier ([OTS] Section 3.2.6). Values `0x000000000000`
+/// to `0x0000000000FF` are reserved.
+pub type ObjectId = u64;
+
+/// Application-provided object storage.
+pub trait ObjectStore: Send + Sync {
+    /// Returns the IDs of all objects in list order.
+    #[must_use]
+    fn ids(&self) -> Vec<ObjectId>;
+
+    /// Returns the metadata of object `id`.
+    #[must_use]
+    fn metadata(&self, id: ObjectId) -> Option<Metadata>;
+
+    /// Returns `len` bytes of object `id` contents starting at offset `off`.
+    /// The range is always within the current object size.
+    #[must_use]
+    fn read(&self, id: ObjectId, off: u32, len: u32) -> Option<Vec<u8>>;
+
+    /// Deletes object `id`, returning whether the object was deleted. This is
+    /// only called if deletion is enabled by
+    /// [`ObjectTransferService::with_delete`] and the object has the
+    /// [`ObjectProperties::DELETE`] property.
+    fn delete(&self, _id: ObjectId) -> bool {
+        false
+    }
+}
+
+/// Object metadata.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Metadata {
+    /// Object name ([OTS] Section 3.2.2). Names longer than 120 bytes are
+    /// truncated.
+    pub name: String,
+    /// Object type ([OTS] Section 3.2.3).
+    pub typ: Uuid,
+    /// Current size of the object contents in bytes ([OTS] Section 3.2.4).
+    pub size: u32,
+    /// Number of bytes allocated for the object contents.
+    pub allocated: u32,
+    /// Object properties ([OTS] Section 3.2.8).
+    pub props: ObjectProperties,
+}
+
+/// Object Transfer service instance. Clones refer to the same instance.
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct ObjectTransferService(Arc<SyncMutex<State>>);
+
+impl ObjectTransferService {
+    /// Maximum length of the Object Name characteristic value.
+    const MAX_NAME_LEN: usize = 120;
+
+    /// Creates an object transfer service that provides objects from
+    /// `store`. If the store contains a single object, it is selected as the
+    /// current object.
+    #[inline]
+    #[must_use]
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self(Arc::new(SyncMutex::new(State {
+            store,
+            delete: false,
+            cur: None,
+            oacp: ControlPoint::new("OACP", OacpOp::RESPONSE),
+            olcp: ControlPoint::new("OLCP", OlcpOp::RESPONSE),
+        })))
+    }
+
+    /// Enables the OACP Delete procedure.
+    #[inline]
+    #[must_use]
+    pub fn with_delete(self) -> Self {
+        self.0.lock().delete = true;
+        self
+    }
+
+    /// Returns the OTS Feature characteristic value ([OTS] Section 3.1).
+    #[must_use]
+    pub fn features(&self) -> [u8; 8] {
+        self.0.lock().features()
+    }
+
+    /// Returns the ID of the current object.
+    #[inline]
+    #[must_use]
+    pub fn current(&self) -> Option<ObjectId> {
+        self.0.lock().current()
+    }
+
+    /// Selects object `id` as the current object. Returns `false` if the
+    /// object does not exist.
+    pub fn select(&self, id: ObjectId) -> bool {
+        let mut s = self.0.lock();
+        let ok = s.store.ids().contains(&id);
+        if ok {
+            s.cur = Some(id);
+        }
+        ok
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let feat = self.features();
+        let meta = |db: &mut Builder<ServiceDef>, uuid: Characteristic, f: MetadataFn| {
+            db.characteristic(
+                uuid,
+                Prop::READ,
+                ac.read(),
+                Io::with(&self.0, move |this, req| this.lock().metadata_io(req, f)),
+                |_| {},
+            );
+        };
+        let (hdl, ()) = db.primary_service(Service::ObjectTransfer, [], |db| {
+            use Characteristic::*;
+            // OTS Feature ([OTS] Section 3.1)
+            db.ro_characteristic(OtsFeature, ac.read(), feat, |_| {});
+
+            // Object metadata ([OTS] Section 3.2)
+            meta(db, ObjectName, |m, _| {
+                let mut n = m.name.len().min(ObjectTransferService::MAX_NAME_LEN);
+                while !m.name.is_char_boundary(n) {
+                    n -= 1;
+                }
+                m.name.as_bytes()[..n].to_vec()
+            });
+            meta(db, ObjectType, |m, _| m.typ.to_vec().to_vec());
+            meta(db, ObjectSize, |m, _| {
+                let mut v = m.size.to_le_bytes().to_vec();
+                v.extend_from_slice(&m.allocated.to_le_bytes());
+                v
+            });
+            meta(db, ObjectId, |_, id| id.to_le_bytes()[..6].to_vec());
+            meta(db, ObjectProperties, |m, _| {
+                m.props.bits().to_le_bytes().to_vec()
+            });
+
+            // Object Action Control Point ([OTS] Section 3.3)
+            db.characteristic(
+                ObjectActionControlPoint,
+                Prop::WRITE | Prop::INDICATE,
+                ac.write(),
+                {
+                    let this = Arc::clone(&self.0);
+                    move |req: IoReq| {
+                        ControlPoint::io_with(&this, req, |s| &mut s.oacp, State::oacp_exec)
+                    }
+                },
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+
+            // Object List Control Point ([OTS] Section 3.4)
+            db.characteristic(
+                ObjectListControlPoint,
+                Prop::WRITE | Prop::INDICATE,
+                ac.write(),
+                {
+                    let this = Arc::clone(&self.0);
+                    move |req: IoReq| {
+                        ControlPoint::io_with(&this, req, |s| &mut s.olcp, State::olcp_exec)
+                    }
+                },
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+        });
+        hdl
+    }
+}
+
+/// Object transfer service state.
+struct State {
+    store: Arc<dyn ObjectStore>,
+    delete: bool,
+    cur: Option<ObjectId>,
+    oacp: ControlPoint,
+    olcp: ControlPoint,
+}
+
+impl State {
+    /// Returns the OTS Feature characteristic value.
+    fn features(&self) -> [u8; 8] {
+        let mut oacp = OacpFeatures::CALCULATE_CHECKSUM | OacpFeatures::READ;
+        oacp.set(OacpFeatures::DELETE, self.delete);
+        let olcp = OlcpFeatures::GO_TO | OlcpFeatures::REQUEST_NUMBER_OF_OBJECTS;
+        let mut v = [0; 8];
+        v[..4].copy_from_slice(&oacp.bits().to_le_bytes());
+        v[4..].copy_from_slice(&olcp.bits().to_le_bytes());
+        v
+    }
+
+    /// Returns the ID of the current object, if it still exists.
+    fn current(&self) -> Option<ObjectId> {
+        let ids = self.store.ids();
+        match self.cur {
+            Some(id) if ids.contains(&id) => Some(id),
+            None if ids.len() == 1 => Some(ids[0]),
+            _ => None,
+        }
+    }
+
+    /// Returns the ID and metadata of the current object.
+    fn current_metadata(&self) -> Option<(ObjectId, Metadata)> {
+        let id = self.current()?;
+        Some((id, self.store.metadata(id)?))
+    }
+
+    /// Handles object metadata characteristic I/O. Reads fail with the
+    /// "Object Not Selected" error if there is no current object
+    /// ([OTS] Section 3.2).
+    #[allow(clippy::needless_pass_by_value)]
+    fn metadata_io(&self, req: IoReq, f: MetadataFn) -> IoResult {
+        let IoReq::Read(r) = req else { return Err(ErrorCode::RequestNotSupported) };
+        let (id, m) = self.current_metadata().ok_or(ErrorCode::OperationFailed)?;
+        r.complete(f(&m, id))
+    }
+
+    /// Executes an OACP procedure ([OTS] Section 3.3.2).
+    fn oacp_exec(&mut self, op: u8, param: &[u8]) -> (OacpResult, Vec<u8>) {
+        let range = |param: &[u8]| {
+            let mut p = param.unpack();
+            let (off, len) = (p.u32(), p.u32());
+            (p.is_ok() && p.is_empty()).then_some((off, len))
+        };
+        let (need, range) = match op {
+            OacpOp::DELETE if self.delete => {
+                if !param.is_empty() {
+                    return (OacpResult::InvalidParameter, Vec::new());
+                }
+                (ObjectProperties::DELETE, None)
+            }
+            OacpOp::CALCULATE_CHECKSUM | OacpOp::READ => match range(param) {
+                Some(r) => (ObjectProperties::READ, Some(r)),
+                None => return (OacpResult::InvalidParameter, Vec::new()),
+            },
+            _ => return (OacpResult::OpCodeNotSupported, Vec::new()),
+        };
+        let Some((id, m)) = self.current_metadata() else {
+            return (OacpResult::InvalidObject, Vec::new());
+        };
+        if !m.props.contains(need) {
+            return (OacpResult::ProcedureNotPermitted, Vec::new());
+        }
+        let Some((off, len)) = range else {
+            return if self.store.delete(id) {
+                self.cur = None;
+                (OacpResult::Success, Vec::new())
+            } else {
+                (OacpResult::OperationFailed, Vec::new())
+            };
+        };
+        if off.checked_add(len).map_or(true, |end| end > m.size) {
+            return (OacpResult::InvalidParameter, Vec::new());
+        }
+        if op == OacpOp::READ {
+            return (OacpResult::ChannelUnavailable, Vec::new());
+        }
+        match self.store.read(id, off, len) {
+            Some(v) if u32::try_from(v.len()) == Ok(len) => {
+                (OacpResult::Success, crc32(0, &v).to_le_bytes().to_vec())
+            }
+            _ => (OacpResult::OperationFailed, Vec::new()),
+        }
+    }
+
+    /// Executes an OLCP procedure ([OTS] Section 3.4.2).
+    fn olcp_exec(&mut self, op: u8, param: &[u8]) -> (OlcpResult, Vec<u8>) {
+        let ids = self.store.ids();
+        if !matches!(op, OlcpOp::GO_TO) && !param.is_empty() {
+            let known = matches!(op, OlcpOp::FIRST..=OlcpOp::NEXT)
+                || op == OlcpOp::REQUEST_NUMBER_OF_OBJECTS;
+            return if known {
+                (OlcpResult::InvalidParameter, Vec::new())
+            } else {
+                (OlcpResult::OpCodeNotSupported, Vec::new())
+            };
+        }
+        let pos = (self.current()).and_then(|id| ids.iter().position(|&v| v == id));
+        let sel = match op {
+            OlcpOp::FIRST => ids.first().copied().ok_or(OlcpResult::NoObject),
+            OlcpOp::LAST => ids.last().copied().ok_or(OlcpResult::NoObject),
+            OlcpOp::PREVIOUS | OlcpOp::NEXT => match pos {
+                _ if ids.is_empty() => Err(OlcpResult::NoObject),
+                None => Err(OlcpResult::OperationFailed),
+                Some(i) => {
+                    let i = if op == OlcpOp::PREVIOUS {
+                        i.checked_sub(1)
+                    } else {
+                        Some(i + 1)
+                    };
+                    (i.and_then(|i| ids.get(i)).copied()).ok_or(OlcpResult::OutOfBounds)
+                }
+            },
+            OlcpOp::GO_TO => {
+                let mut p = param.unpack();
+                let (lo, hi) = (p.u32(), p.u16());
+                if !p.is_ok() || !p.is_empty() {
+                    return (OlcpResult::InvalidParameter, Vec::new());
+                }
+                let id = (u64::from(hi) << 32) | u64::from(lo);
+                (ids.iter().copied().find(|&v| v == id)).ok_or(OlcpResult::ObjectIdNotFound)
+            }
+            OlcpOp::REQUEST_NUMBER_OF_OBJECTS => {
+                let n = u32::try_from(ids.len()).unwrap_or(u32::MAX);
+                return (OlcpResult::Success, n.to_le_bytes().to_vec());
+            }
+            _ => return (OlcpResult::OpCodeNotSupported, Vec::new()),
+        };
+        match sel {
+            Ok(id) => {
+                self.cur = Some(id);
+                (OlcpResult::Success, Vec::new())
+            }
+            Err(r) => (r, Vec::new()),
+        }
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("delete", &self.delete)
+            .field("cur", &self.cur)
+            .field("oacp", &self.oacp)
+            .field("olcp", &self.olcp)
+            .finish_non_exhaustive()
+    }
+}
+
+bitflags::bitflags! {
+    /// Object properties ([OTS] Section 3.2.8).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct ObjectProperties: u32 {
+        /// Deletion of the object is permitted.
+        const DELETE = 1 << 0;
+        /// Execution of the object is permitted.
+        const EXECUTE = 1 << 1;
+        /// Reading the object contents is permitted.
+        const READ = 1 << 2;
+        /// Writing the object contents is permitted.
+        const WRITE = 1 << 3;
+        /// Appending data to the object contents is permitted.
+        const APPEND = 1 << 4;
+        /// Truncation of the object is permitted.
+        const TRUNCATE = 1 << 5;
+        /// Patching of the object contents is permitted.
+        const PATCH = 1 << 6;
+        /// The object is marked.
+        const MARK = 1 << 7;
+    }
+}
+
+bitflags::bitflags! {
+    /// OACP features ([OTS] Section 3.1.1).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    struct OacpFeatures: u32 {
+        const CREATE = 1 << 0;
+        const DELETE = 1 << 1;
+        const CALCULATE_CHECKSUM = 1 << 2;
+        const EXECUTE = 1 << 3;
+        const READ = 1 << 4;
+        const WRITE = 1 << 5;
+        const APPEND = 1 << 6;
+        const TRUNCATE = 1 << 7;
+        const PATCH = 1 << 8;
+        const ABORT = 1 << 9;
+    }
+}
+
+bitflags::bitflags! {
+    /// OLCP features ([OTS] Section 3.1.2).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    struct OlcpFeatures: u32 {
+        const GO_TO = 1 << 0;
+        const ORDER = 1 << 1;
+        const REQUEST_NUMBER_OF_OBJECTS = 1 << 2;
+        const CLEAR_MARKING = 1 << 3;
+    }
+}
+
+/// OACP op codes ([OTS] Section 3.3.2).
+struct OacpOp;
+
+impl OacpOp {
+    const DELETE: u8 = 0x02;
+    const CALCULATE_CHECKSUM: u8 = 0x03;
+    const READ: u8 = 0x05;
+    const RESPONSE: u8 = 0x60;
+}
+
+/// OACP result codes ([OTS] Section 3.3.2.10).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+enum OacpResult {
+    Success = 0x01,
+    OpCodeNotSupported = 0x02,
+    InvalidParameter = 0x03,
+    InvalidObject = 0x05,
+    ChannelUnavailable = 0x06,
+    ProcedureNotPermitted = 0x08,
+    OperationFailed = 0x0A,
+}
+
+impl From<OacpResult> for u8 {
+    #[inline(always)]
+    fn from(r: OacpResult) -> Self {
+        r as Self
+    }
+}
+
+/// OLCP op codes ([OTS] Section 3.4.2).
+struct OlcpOp;
+
+impl OlcpOp {
+    const FIRST: u8 = 0x01;
+    const LAST: u8 = 0x02;
+    const PREVIOUS: u8 = 0x03;
+    const NEXT: u8 = 0x04;
+    const GO_TO: u8 = 0x05;
+    const REQUEST_NUMBER_OF_OBJECTS: u8 = 0x07;
+    const RESPONSE: u8 = 0x70;
+}
+
+/// OLCP result codes ([OTS] Section 3.4.2.9).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+enum OlcpResult {
+    Success = 0x01,
+    OpCodeNotSupported = 0x02,
+    InvalidParameter = 0x03,
+    OperationFailed = 0x04,
+    OutOfBounds = 0x05,
+    NoObject = 0x07,
+    ObjectIdNotFound = 0x08,
+}
+
+impl From<OlcpResult> for u8 {
+    #[inline(always)]
+    fn from(r: OlcpResult) -> Self {
+        r as Self
+    }
+}
+
+/// Updates CRC-32 checksum `crc` (initially 0) with `data`. The algorithm is
+/// defined in ISO/IEC 3309 ([OTS] Section 3.3.2.4).
+pub(super) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c ^= u32::from(b);
+        for _ in 0..8 {
+            c = (c >> 1) ^ (0xEDB8_8320 & (c & 1).wrapping_neg());
+        }
+    }
+    !c
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+    use crate::gatt::{CharacteristicDef, DbEntry, NotifyReq, NotifyVal};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Store(SyncMutex<BTreeMap<ObjectId, (String, Vec<u8>)>>);
+
+    impl ObjectStore for Store {
+        fn ids(&self) -> Vec<ObjectId> {
+            self.0.lock().keys().copied().collect()
+        }
+
+        fn metadata(&self, id: ObjectId) -> Option<Metadata> {
+            let s = self.0.lock();
+            let (name, v) = s.get(&id)?;
+            Some(Metadata {
+                name: name.clone(),
+                typ: Uuid::new(0x2AC3).unwrap(),
+                size: u32::try_from(v.len()).unwrap(),
+                allocated: u32::try_from(v.len()).unwrap(),
+                props: ObjectProperties::READ | ObjectProperties::DELETE,
+            })
+        }
+
+        fn read(&self, id: ObjectId, off: u32, len: u32) -> Option<Vec<u8>> {
+            let s = self.0.lock();
+            let v = &s.get(&id)?.1;
+            Some(v[off as usize..(off + len) as usize].to_vec())
+        }
+
+        fn delete(&self, id: ObjectId) -> bool {
+            self.0.lock().remove(&id).is_some()
+        }
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[tokio::test]
+    async fn ots() {
+        let store = Arc::new(Store::default());
+        store.0.lock().extend([
+            (0x100, ("log.txt".into(), b"123456789".to_vec())),
+            (0x101, ("other".into(), vec![0; 4])),
+        ]);
+        let ots = ObjectTransferService::new(Arc::clone(&store) as _).with_delete();
+        assert_eq!(ots.features(), [0x16, 0, 0, 0, 0x05, 0, 0, 0]);
+        let t = TestDb::new(|db| ots.define(db, Access::NONE));
+        let chars: Vec<_> = t.characteristics().collect();
+        assert_eq!(chars.len(), 8);
+        let (name, size, id, oacp, olcp) = (chars[1], chars[3], chars[4], chars[6], chars[7]);
+        assert_eq!(name.uuid(), Characteristic::ObjectName);
+        assert_eq!(olcp.uuid(), Characteristic::ObjectListControlPoint);
+
+        let read = |c: DbEntry<CharacteristicDef>| t.read(c.value_handle());
+        let write = |c: DbEntry<CharacteristicDef>, v: &[u8]| t.write(c.value_handle(), v);
+        let (tx, mut rx) = mpsc::channel(1);
+        for c in [oacp, olcp] {
+            t.io.notify(NotifyReq {
+                hdl: c.value_handle(),
+                uuid: c.uuid(),
+                mtu: 23,
+                ind: true,
+                tx: tx.clone(),
+                ct: CancellationToken::new(),
+            })
+            .unwrap();
+        }
+
+        // No current object
+        assert_eq!(read(name), Err(ErrorCode::OperationFailed));
+        write(oacp, &[0x03, 0, 0, 0, 0, 1, 0, 0, 0]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x60, 0x03, 0x05]);
+
+        // Object list navigation
+        write(olcp, &[0x07]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x07, 0x01, 2, 0, 0, 0]);
+        write(olcp, &[0x04]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x04, 0x04]);
+        write(olcp, &[0x02]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x02, 0x01]);
+        assert_eq!(read(name).unwrap(), b"other");
+        write(olcp, &[0x04]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x04, 0x05]);
+        write(olcp, &[0x05, 0x00, 0x01, 0, 0, 0, 0]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x05, 0x01]);
+        assert_eq!(ots.current(), Some(0x100));
+        write(olcp, &[0x05, 0x02, 0x01, 0, 0, 0, 0]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x05, 0x08]);
+        write(olcp, &[0x06, 0x01]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x70, 0x06, 0x02]);
+
+        // Metadata
+        assert_eq!(read(name).unwrap(), b"log.txt");
+        assert_eq!(read(size).unwrap(), [9, 0, 0, 0, 9, 0, 0, 0]);
+        assert_eq!(read(id).unwrap(), [0x00, 0x01, 0, 0, 0, 0]);
+
+        // Object actions
+        write(oacp, &[0x03, 0, 0, 0, 0, 9, 0, 0, 0]).unwrap();
+        assert_eq!(
+            confirm(&mut rx).await,
+            [0x60, 0x03, 0x01, 0x26, 0x39, 0xF4, 0xCB]
+        );
+        write(oacp, &[0x03, 1, 0, 0, 0, 9, 0, 0, 0]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x60, 0x03, 0x03]);
+        write(oacp, &[0x05, 0, 0, 0, 0, 9, 0, 0, 0]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x60, 0x05, 0x06]);
+        write(oacp, &[0x01]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x60, 0x01, 0x02]);
+        write(oacp, &[0x02]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x60, 0x02, 0x01]);
+        assert_eq!(store.ids(), [0x101]);
+
+        // Single remaining object is selected automatically
+        assert_eq!(ots.current(), Some(0x101));
+        assert_eq!(read(name).unwrap(), b"other");
+    }
+
+    /// Receives and confirms the next control point response, and waits for
+    /// the procedure to complete.
+    async fn confirm(rx: &mut mpsc::Receiver<NotifyVal>) -> Vec<u8> {
+        let v = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let r = v.as_ref().to_vec();
+        v.result(Ok(()));
+        tokio::task::yield_now().await;
+        r
+    }
+}
@@ -0,0 +1,619 @@
+//! Pulse Oximeter Service ([PLXS]).
+//!
+//! This service exposes oxygen saturation (`SpO2`) and pulse rate
+//! measurements from a pulse oximeter. Spot-check measurements may be stored
+//! by the server and retrieved by the client via the Record Access Control
+//! Point.
+//!
+//! [PLXS]: https://www.bluetooth.com/specifications/specs/pulse-oximeter-service-1-0-1/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use structbuf::Packer;
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{
+    Builder, Characteristic, Db, Io, IoReq, IoResult, Notify, NotifyReq, Prop, Service,
+};
+use crate::SyncMutex;
+
+use super::cts::ExactTime256;
+use super::racp::{Racp, RacpState, RecordStore};
+use super::sfloat::Sfloat;
+
+/// Spot-check measurement storage. Sequence numbers are only used to identify
+/// records; clients can only access all stored records.
+pub type PlxStore = dyn RecordStore<Record = SpotCheck>;
+
+/// Pulse oximeter service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct PulseOximeterService(Arc<SyncMutex<State>>);
+
+impl PulseOximeterService {
+    /// Creates a pulse oximeter service without any measurement
+    /// characteristics.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the PLX Spot-Check Measurement characteristic.
+    #[inline]
+    #[must_use]
+    pub fn with_spot_check(self) -> Self {
+        self.0.lock().spot_check = true;
+        self
+    }
+
+    /// Adds the PLX Continuous Measurement characteristic.
+    #[inline]
+    #[must_use]
+    pub fn with_continuous(self) -> Self {
+        self.0.lock().continuous = true;
+        self
+    }
+
+    /// Adds the PLX Spot-Check Measurement and Record Access Control Point
+    /// characteristics for retrieving spot-check measurements from `store`.
+    #[inline]
+    #[must_use]
+    pub fn with_store(self, store: Arc<PlxStore>) -> Self {
+        let mut s = self.0.lock();
+        s.spot_check = true;
+        s.store = Some(store);
+        drop(s);
+        self
+    }
+
+    /// Sets the supported features. Features that are implied by other
+    /// builder methods are set automatically.
+    #[inline]
+    #[must_use]
+    pub fn with_features(self, f: Features) -> Self {
+        self.0.lock().features = f;
+        self
+    }
+
+    /// Sets the supported Measurement Status bits.
+    #[inline]
+    #[must_use]
+    pub fn with_measurement_status(self, st: MeasurementStatus) -> Self {
+        self.0.lock().meas_status = Some(st);
+        self
+    }
+
+    /// Sets the supported Device and Sensor Status bits.
+    #[inline]
+    #[must_use]
+    pub fn with_sensor_status(self, st: SensorStatus) -> Self {
+        self.0.lock().sensor_status = Some(st);
+        self
+    }
+
+    /// Returns the supported features.
+    #[must_use]
+    pub fn features(&self) -> Features {
+        self.0.lock().features()
+    }
+
+    /// Indicates a new spot-check measurement to the client. The measurement
+    /// should also be added to the record store, if any.
+    pub fn push_spot_check(&self, m: &SpotCheck) {
+        let s = self.0.lock();
+        let Some(n) = s.spot_ind.as_ref() else { return };
+        if let Err(e) = n.notify_dropping_if_full(|p| m.pack(p)) {
+            debug!("PLX spot-check indicate error: {e}");
+        }
+    }
+
+    /// Notifies the client of a continuous measurement.
+    pub fn push_continuous(&self, m: &Continuous) {
+        let s = self.0.lock();
+        let Some(n) = s.cont_ntf.as_ref() else { return };
+        if let Err(e) = n.notify_dropping_if_full(|p| m.pack(p)) {
+            debug!("PLX continuous notify error: {e}");
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (spot_check, continuous, racp, feat) = {
+            let s = self.0.lock();
+            (
+                s.spot_check,
+                s.continuous,
+                s.store.is_some(),
+                s.features_value(),
+            )
+        };
+        let (hdl, ()) = db.primary_service(Service::PulseOximeter, [], |db| {
+            use Characteristic::*;
+            // PLX Spot-Check Measurement ([PLXS] Section 3.1)
+            if spot_check {
+                db.characteristic(
+                    PlxSpotCheckMeasurement,
+                    Prop::INDICATE,
+                    ac.read(),
+                    Io::with(&self.0, |this, req| this.lock().measurement_io(req, true)),
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+
+            // PLX Continuous Measurement ([PLXS] Section 3.2)
+            if continuous {
+                db.characteristic(
+                    PlxContinuousMeasurement,
+                    Prop::NOTIFY,
+                    ac.read(),
+                    Io::with(&self.0, |this, req| this.lock().measurement_io(req, false)),
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+
+            // PLX Features ([PLXS] Section 3.3)
+            db.ro_characteristic(PlxFeatures, ac.read(), feat, |_| {});
+
+            // Record Access Control Point ([PLXS] Section 3.4)
+            if racp {
+                db.characteristic(
+                    RecordAccessControlPoint,
+                    Prop::WRITE | Prop::INDICATE,
+                    ac.write(),
+                    {
+                        let this = Arc::clone(&self.0);
+                        move |req: IoReq| Racp::io(&this, req)
+                    },
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+        });
+        hdl
+    }
+}
+
+/// Pulse oximeter service state.
+struct State {
+    store: Option<Arc<PlxStore>>,
+    features: Features,
+    meas_status: Option<MeasurementStatus>,
+    sensor_status: Option<SensorStatus>,
+    spot_check: bool,
+    continuous: bool,
+    spot_ind: Option<NotifyReq>,
+    cont_ntf: Option<NotifyReq>,
+    racp: Racp,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            store: None,
+            features: Features::empty(),
+            meas_status: None,
+            sensor_status: None,
+            spot_check: false,
+            continuous: false,
+            spot_ind: None,
+            cont_ntf: None,
+            racp: Racp::all_records_only(),
+        }
+    }
+}
+
+impl State {
+    /// Returns the supported features.
+    fn features(&self) -> Features {
+        let mut f = self.features;
+        f.set(Features::MEASUREMENT_STATUS, self.meas_status.is_some());
+        f.set(Features::SENSOR_STATUS, self.sensor_status.is_some());
+        f.set(Features::SPOT_CHECK_STORAGE, self.store.is_some());
+        f
+    }
+
+    /// Returns the PLX Features characteristic value ([PLXS] Section 3.3.1).
+    fn features_value(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(7);
+        v.extend_from_slice(&self.features().bits().to_le_bytes());
+        if let Some(st) = self.meas_status {
+            v.extend_from_slice(&st.bits().to_le_bytes());
+        }
+        if let Some(st) = self.sensor_status {
+            v.extend_from_slice(&st.bits().to_le_bytes()[..3]);
+        }
+        v
+    }
+
+    /// Handles PLX Spot-Check Measurement and PLX Continuous Measurement
+    /// characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq, spot_check: bool) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() != spot_check {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        *(if spot_check {
+            &mut self.spot_ind
+        } else {
+            &mut self.cont_ntf
+        }) = Some(n);
+        Ok(())
+    }
+}
+
+impl RacpState for State {
+    #[inline(always)]
+    fn racp(&mut self) -> &mut Racp {
+        &mut self.racp
+    }
+
+    #[inline]
+    fn seqs(&self) -> Vec<u16> {
+        self.store.as_ref().map_or_else(Vec::new, |s| s.seqs())
+    }
+
+    fn report(&mut self, seq: u16) -> Option<Vec<Notify>> {
+        let n = self.spot_ind.as_ref().filter(|n| !n.is_closed())?;
+        let r = self.store.as_ref().and_then(|s| s.get(seq));
+        Some(r.map_or_else(Vec::new, |r| vec![n.notify(|p| r.pack(p))]))
+    }
+
+    #[inline]
+    fn remove(&mut self, seq: u16) {
+        if let Some(s) = self.store.as_ref() {
+            s.remove(seq);
+        }
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("features", &self.features)
+            .field("meas_status", &self.meas_status)
+            .field("sensor_status", &self.sensor_status)
+            .field("spot_check", &self.spot_check)
+            .field("continuous", &self.continuous)
+            .field("racp", &self.racp)
+            .finish_non_exhaustive()
+    }
+}
+
+bitflags::bitflags! {
+    /// PLX Features characteristic Supported Features field
+    /// ([PLXS] Section 3.3.1.1).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct Features: u16 {
+        const MEASUREMENT_STATUS = 1 << 0;
+        const SENSOR_STATUS = 1 << 1;
+        const SPOT_CHECK_STORAGE = 1 << 2;
+        const SPOT_CHECK_TIMESTAMP = 1 << 3;
+        const SPO2PR_FAST = 1 << 4;
+        const SPO2PR_SLOW = 1 << 5;
+        const PULSE_AMPLITUDE_INDEX = 1 << 6;
+        const MULTIPLE_BOND = 1 << 7;
+    }
+
+    /// Measurement Status field ([PLXS] Section 3.1.1.4).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct MeasurementStatus: u16 {
+        const MEASUREMENT_ONGOING = 1 << 5;
+        const EARLY_ESTIMATED_DATA = 1 << 6;
+        const VALIDATED_DATA = 1 << 7;
+        const FULLY_QUALIFIED_DATA = 1 << 8;
+        const DATA_FROM_MEASUREMENT_STORAGE = 1 << 9;
+        const DATA_FOR_DEMONSTRATION = 1 << 10;
+        const DATA_FOR_TESTING = 1 << 11;
+        const CALIBRATION_ONGOING = 1 << 12;
+        const MEASUREMENT_UNAVAILABLE = 1 << 13;
+        const QUESTIONABLE_MEASUREMENT_DETECTED = 1 << 14;
+        const INVALID_MEASUREMENT_DETECTED = 1 << 15;
+    }
+
+    /// Device and Sensor Status field, which is transferred as a 24-bit value
+    /// ([PLXS] Section 3.1.1.5).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct SensorStatus: u32 {
+        const EXTENDED_DISPLAY_UPDATE_ONGOING = 1 << 0;
+        const EQUIPMENT_MALFUNCTION_DETECTED = 1 << 1;
+        const SIGNAL_PROCESSING_IRREGULARITY_DETECTED = 1 << 2;
+        const INADEQUATE_SIGNAL_DETECTED = 1 << 3;
+        const POOR_SIGNAL_DETECTED = 1 << 4;
+        const LOW_PERFUSION_DETECTED = 1 << 5;
+        const ERRATIC_SIGNAL_DETECTED = 1 << 6;
+        const NONPULSATILE_SIGNAL_DETECTED = 1 << 7;
+        const QUESTIONABLE_PULSE_DETECTED = 1 << 8;
+        const SIGNAL_ANALYSIS_ONGOING = 1 << 9;
+        const SENSOR_INTERFERENCE_DETECTED = 1 << 10;
+        const SENSOR_UNCONNECTED_TO_USER = 1 << 11;
+        const UNKNOWN_SENSOR_CONNECTED = 1 << 12;
+        const SENSOR_DISPLACED = 1 << 13;
+        const SENSOR_MALFUNCTIONING = 1 << 14;
+        const SENSOR_DISCONNECTED = 1 << 15;
+    }
+}
+
+/// `SpO2` in percent and pulse rate in beats per minute.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpO2Pr {
+    /// Oxygen saturation in percent.
+    pub spo2: f32,
+    /// Pulse rate in beats per minute.
+    pub pulse_rate: f32,
+}
+
+impl SpO2Pr {
+    /// Packs the value as two SFLOATs.
+    #[inline]
+    fn pack(self, p: &mut Packer) {
+        p.u16(Sfloat::new(self.spo2))
+            .u16(Sfloat::new(self.pulse_rate));
+    }
+}
+
+/// PLX Spot-Check Measurement characteristic value ([PLXS] Section 3.1.1).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpotCheck {
+    /// `SpO2` and pulse rate.
+    pub value: SpO2Pr,
+    /// Measurement time. The `weekday` and `fractions256` fields are not
+    /// transferred.
+    pub time: Option<ExactTime256>,
+    /// Measurement Status bits. Stored measurements should set
+    /// [`MeasurementStatus::DATA_FROM_MEASUREMENT_STORAGE`].
+    pub status: Option<MeasurementStatus>,
+    /// Device and Sensor Status bits.
+    pub sensor_status: Option<SensorStatus>,
+    /// Pulse amplitude index in percent.
+    pub pulse_amplitude: Option<f32>,
+    /// Device clock is not set.
+    pub clock_not_set: bool,
+}
+
+impl SpotCheck {
+    const TIMESTAMP: u8 = 1 << 0;
+    const STATUS: u8 = 1 << 1;
+    const SENSOR_STATUS: u8 = 1 << 2;
+    const PULSE_AMPLITUDE: u8 = 1 << 3;
+    const CLOCK_NOT_SET: u8 = 1 << 4;
+
+    /// Packs the measurement.
+    pub fn pack(&self, p: &mut Packer) {
+        let flag = |present: bool, f: u8| if present { f } else { 0 };
+        let f = flag(self.time.is_some(), Self::TIMESTAMP)
+            | flag(self.status.is_some(), Self::STATUS)
+            | flag(self.sensor_status.is_some(), Self::SENSOR_STATUS)
+            | flag(self.pulse_amplitude.is_some(), Self::PULSE_AMPLITUDE)
+            | flag(self.clock_not_set, Self::CLOCK_NOT_SET);
+        p.u8(f);
+        self.value.pack(p);
+        if let Some(t) = self.time {
+            p.put(&t.to_bytes()[..7]);
+        }
+        pack_status(p, self.status, self.sensor_status, self.pulse_amplitude);
+    }
+}
+
+/// PLX Continuous Measurement characteristic value ([PLXS] Section 3.2.1).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Continuous {
+    /// `SpO2` and pulse rate using the normal averaging time.
+    pub normal: SpO2Pr,
+    /// `SpO2` and pulse rate using a fast response averaging time.
+    pub fast: Option<SpO2Pr>,
+    /// `SpO2` and pulse rate using a slow response averaging time.
+    pub slow: Option<SpO2Pr>,
+    /// Measurement Status bits.
+    pub status: Option<MeasurementStatus>,
+    /// Device and Sensor Status bits.
+    pub sensor_status: Option<SensorStatus>,
+    /// Pulse amplitude index in percent.
+    pub pulse_amplitude: Option<f32>,
+}
+
+impl Continuous {
+    const FAST: u8 = 1 << 0;
+    const SLOW: u8 = 1 << 1;
+    const STATUS: u8 = 1 << 2;
+    const SENSOR_STATUS: u8 = 1 << 3;
+    const PULSE_AMPLITUDE: u8 = 1 << 4;
+
+    /// Packs the measurement.
+    pub fn pack(&self, p: &mut Packer) {
+        let flag = |present: bool, f: u8| if present { f } else { 0 };
+        let f = flag(self.fast.is_some(), Self::FAST)
+            | flag(self.slow.is_some(), Self::SLOW)
+            | flag(self.status.is_some(), Self::STATUS)
+            | flag(self.sensor_status.is_some(), Self::SENSOR_STATUS)
+            | flag(self.pulse_amplitude.is_some(), Self::PULSE_AMPLITUDE);
+        p.u8(f);
+        self.normal.pack(p);
+        for v in [self.fast, self.slow].into_iter().flatten() {
+            v.pack(p);
+        }
+        pack_status(p, self.status, self.sensor_status, self.pulse_amplitude);
+    }
+}
+
+/// Packs the optional status and pulse amplitude index fields that are common
+/// to all measurements.
+fn pack_status(
+    p: &mut Packer,
+    status: Option<MeasurementStatus>,
+    sensor_status: Option<SensorStatus>,
+    pulse_amplitude: Option<f32>,
+) {
+    if let Some(st) = status {
+        p.u16(st.bits());
+    }
+    if let Some(st) = sensor_status {
+        p.u24(st.bits());
+    }
+    if let Some(pai) = pulse_amplitude {
+        p.u16(Sfloat::new(pai));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::{Records, TestDb};
+    use crate::gatt::{CharacteristicDef, DbEntry, NotifyVal};
+
+    use super::*;
+
+    fn spot_check(spo2: f32) -> SpotCheck {
+        SpotCheck {
+            value: SpO2Pr {
+                spo2,
+                pulse_rate: 72.0,
+            },
+            status: Some(MeasurementStatus::DATA_FROM_MEASUREMENT_STORAGE),
+            ..SpotCheck::default()
+        }
+    }
+
+    #[test]
+    fn pack() {
+        let m = SpotCheck {
+            value: SpO2Pr {
+                spo2: 98.5,
+                pulse_rate: 60.0,
+            },
+            time: Some(ExactTime256::from_unix(1_680_000_000, 0)),
+            status: Some(MeasurementStatus::VALIDATED_DATA),
+            sensor_status: Some(SensorStatus::SENSOR_DISPLACED),
+            pulse_amplitude: Some(f32::NAN),
+            clock_not_set: false,
+        };
+        let mut b = StructBuf::new(32);
+        m.pack(&mut b.append());
+        assert_eq!(
+            b.as_ref(),
+            [
+                0x0F, 0xD9, 0xF3, 0x58, 0xF2, 0xE7, 0x07, 0x03, 0x1C, 0x0A, 0x28, 0x00, 0x80, 0x00,
+                0x00, 0x20, 0x00, 0xFF, 0x07
+            ]
+        );
+
+        let m = Continuous {
+            normal: SpO2Pr {
+                spo2: 97.0,
+                pulse_rate: 80.0,
+            },
+            slow: Some(SpO2Pr {
+                spo2: f32::NAN,
+                pulse_rate: 81.0,
+            }),
+            ..Continuous::default()
+        };
+        let mut b = StructBuf::new(32);
+        m.pack(&mut b.append());
+        assert_eq!(
+            b.as_ref(),
+            [0x02, 0xCA, 0xF3, 0x20, 0xF3, 0xFF, 0x07, 0x2A, 0xF3]
+        );
+    }
+
+    #[tokio::test]
+    async fn service() {
+        let recs = [(1, 95.0), (2, 96.0)].map(|(seq, spo2)| (seq, spot_check(spo2)));
+        let store: Arc<Records<_>> = Arc::new(recs.into_iter().collect());
+        let plx = PulseOximeterService::new()
+            .with_continuous()
+            .with_store(Arc::clone(&store) as _)
+            .with_features(Features::SPOT_CHECK_TIMESTAMP)
+            .with_measurement_status(MeasurementStatus::DATA_FROM_MEASUREMENT_STORAGE)
+            .with_sensor_status(SensorStatus::SENSOR_DISCONNECTED);
+        assert_eq!(
+            plx.features(),
+            Features::MEASUREMENT_STATUS
+                | Features::SENSOR_STATUS
+                | Features::SPOT_CHECK_STORAGE
+                | Features::SPOT_CHECK_TIMESTAMP
+        );
+        let t = TestDb::new(|db| plx.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (spot, cont, feat, racp) = (
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+            chars.next().unwrap(),
+        );
+        assert_eq!(spot.properties(), Prop::INDICATE);
+        assert_eq!(cont.properties(), Prop::NOTIFY);
+        assert_eq!(
+            t.db.get(feat.value_handle()).unwrap().1,
+            [0x0F, 0x00, 0x00, 0x02, 0x00, 0x80, 0x00]
+        );
+
+        let sub = |c: &DbEntry<CharacteristicDef>, ind| {
+            let (tx, rx) = mpsc::channel(8);
+            let req = NotifyReq {
+                hdl: c.value_handle(),
+                uuid: c.uuid(),
+                mtu: 23,
+                ind,
+                tx,
+                ct: CancellationToken::new(),
+            };
+            t.io.notify(req).map(|()| rx)
+        };
+        let improper = Some(ErrorCode::CccdImproperlyConfigured);
+        assert_eq!(sub(&spot, false).err(), improper);
+        assert_eq!(sub(&cont, true).err(), improper);
+        let mut spot = sub(&spot, true).unwrap();
+        let mut cont = sub(&cont, false).unwrap();
+        let mut ind = sub(&racp, true).unwrap();
+
+        plx.push_continuous(&Continuous::default());
+        assert_eq!(recv(&mut cont).await[0], 0x00);
+
+        let write = |v: &[u8]| t.write(racp.value_handle(), v);
+
+        // Only the "All records" operator is supported
+        write(&[0x01, 0x05]).unwrap();
+        assert_eq!(recv(&mut ind).await, [0x06, 0x00, 0x01, 0x04]);
+
+        write(&[0x04, 0x01]).unwrap();
+        assert_eq!(recv(&mut ind).await, [0x05, 0x00, 2, 0]);
+
+        write(&[0x01, 0x01]).unwrap();
+        assert_eq!(recv(&mut spot).await[..3], [0x02, 0xB6, 0xF3]);
+        assert_eq!(recv(&mut spot).await[..3], [0x02, 0xC0, 0xF3]);
+        assert_eq!(recv(&mut ind).await, [0x06, 0x00, 0x01, 0x01]);
+
+        write(&[0x02, 0x01]).unwrap();
+        assert_eq!(recv(&mut ind).await, [0x06, 0x00, 0x02, 0x01]);
+        assert!(store.seqs().is_empty());
+    }
+
+    /// Receives the next notification or indication and completes it.
+    async fn recv(rx: &mut mpsc::Receiver<NotifyVal>) -> Vec<u8> {
+        let v = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let r = v.as_ref().to_vec();
+        v.result(Ok(()));
+        r
+    }
+}
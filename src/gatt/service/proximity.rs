@@ -0,0 +1,321 @@
+//! Proximity profile services ([PXP]).
+//!
+//! The Proximity Reporter role consists of the Link Loss ([LLS]), Immediate
+//! Alert ([IAS]), and Tx Power ([TPS]) services. The client uses them to
+//! configure an alert that is raised when the connection is lost, to raise an
+//! alert immediately, and to estimate path loss from the server's transmit
+//! power.
+//!
+//! [PXP]: https://www.bluetooth.com/specifications/specs/proximity-profile-1-0-1/
+//! [LLS]: https://www.bluetooth.com/specifications/specs/link-loss-service-1-0-1/
+//! [IAS]: https://www.bluetooth.com/specifications/specs/immediate-alert-service-1-0/
+//! [TPS]: https://www.bluetooth.com/specifications/specs/tx-power-service-1-0/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use tracing::{debug, info};
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gap::{ConnEvent, ConnEvents, DisconnectReason};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, Prop, Service};
+use crate::{hci, SyncMutex};
+
+/// Callback for alerts.
+type AlertFn = Box<dyn FnMut(AlertLevel) + Send>;
+
+/// Alert level ([LLS] Section 3.1, [IAS] Section 3.1).
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum AlertLevel {
+    None = 0,
+    Mild = 1,
+    High = 2,
+}
+
+impl AlertLevel {
+    /// Parses a written Alert Level characteristic value.
+    fn from_write(v: &[u8]) -> Result<Self, ErrorCode> {
+        match *v {
+            [v] => Self::try_from(v).map_err(|_| ErrorCode::ValueNotAllowed),
+            _ => Err(ErrorCode::InvalidAttributeValueLength),
+        }
+    }
+}
+
+/// Link Loss service instance. Clones refer to the same instance.
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct LinkLossService(Arc<SyncMutex<LinkLoss>>);
+
+impl LinkLossService {
+    /// Creates a link loss service that calls `f` with the client-configured
+    /// alert level when the connection is lost.
+    #[inline]
+    #[must_use]
+    pub fn new(f: impl FnMut(AlertLevel) + Send + 'static) -> Self {
+        Self(Arc::new(SyncMutex::new(LinkLoss {
+            level: AlertLevel::None,
+            alert: Box::new(f),
+        })))
+    }
+
+    /// Returns the alert level configured by the client.
+    #[inline]
+    #[must_use]
+    pub fn alert_level(&self) -> AlertLevel {
+        self.0.lock().level
+    }
+
+    /// Handles connection termination with the specified `reason`. Link loss
+    /// is any disconnection that was not initiated by the local host, in which
+    /// case the alert callback is called if the alert level is not
+    /// [`AlertLevel::None`] ([LLS] Section 4.2). Returns whether an alert was
+    /// raised.
+    pub fn disconnected(&self, reason: DisconnectReason) -> bool {
+        let mut s = self.0.lock();
+        if reason == DisconnectReason::LocalHost || s.level == AlertLevel::None {
+            return false;
+        }
+        info!("Link loss ({reason}), raising {:?} alert", s.level);
+        let level = s.level;
+        (s.alert)(level);
+        true
+    }
+
+    /// Monitors connection events and calls [`Self::disconnected`] when the
+    /// connection is closed.
+    pub async fn watch(&self, mut evts: ConnEvents) {
+        while let Some(e) = evts.next().await {
+            if let ConnEvent::Disconnected { reason } = e {
+                self.disconnected(reason);
+            }
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (hdl, ()) = db.primary_service(Service::LinkLoss, [], |db| {
+            // Alert Level ([LLS] Section 3.1)
+            db.characteristic(
+                Characteristic::AlertLevel,
+                Prop::READ | Prop::WRITE,
+                ac.read_write(),
+                Io::with(&self.0, |this, req| this.lock().alert_level_io(req)),
+                |_| {},
+            );
+        });
+        hdl
+    }
+}
+
+/// Link loss service state.
+struct LinkLoss {
+    level: AlertLevel,
+    alert: AlertFn,
+}
+
+impl LinkLoss {
+    /// Handles Alert Level characteristic I/O.
+    fn alert_level_io(&mut self, req: IoReq) -> IoResult {
+        match req {
+            IoReq::Read(r) => r.complete([u8::from(self.level)]),
+            IoReq::Write(w) => {
+                if w.offset() != 0 {
+                    return Err(ErrorCode::InvalidOffset);
+                }
+                self.level = AlertLevel::from_write(w.value())?;
+                debug!("Link loss alert level: {:?}", self.level);
+                Ok(())
+            }
+            IoReq::Notify(_) => Err(ErrorCode::RequestNotSupported),
+        }
+    }
+}
+
+impl Debug for LinkLoss {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LinkLoss")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Immediate Alert service instance. Clones refer to the same instance.
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct ImmediateAlertService(Arc<SyncMutex<ImmediateAlert>>);
+
+impl ImmediateAlertService {
+    /// Creates an immediate alert service that calls `f` with each alert level
+    /// written by the client.
+    #[inline]
+    #[must_use]
+    pub fn new(f: impl FnMut(AlertLevel) + Send + 'static) -> Self {
+        Self(Arc::new(SyncMutex::new(ImmediateAlert(Box::new(f)))))
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (hdl, ()) = db.primary_service(Service::ImmediateAlert, [], |db| {
+            // Alert Level ([IAS] Section 3.1)
+            db.characteristic(
+                Characteristic::AlertLevel,
+                Prop::WRITE_CMD,
+                ac.write(),
+                Io::with(&self.0, |this, req| this.lock().alert_level_io(req)),
+                |_| {},
+            );
+        });
+        hdl
+    }
+}
+
+/// Immediate alert service state.
+struct ImmediateAlert(AlertFn);
+
+impl ImmediateAlert {
+    /// Handles Alert Level characteristic I/O. Errors are not reported to the
+    /// client because this characteristic only supports Write Without
+    /// Response.
+    #[allow(clippy::needless_pass_by_value)]
+    fn alert_level_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Write(w) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if w.offset() != 0 {
+            return Err(ErrorCode::InvalidOffset);
+        }
+        (self.0)(AlertLevel::from_write(w.value())?);
+        Ok(())
+    }
+}
+
+impl Debug for ImmediateAlert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImmediateAlert").finish_non_exhaustive()
+    }
+}
+
+/// Tx Power service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct TxPowerService(Arc<SyncMutex<i8>>);
+
+impl TxPowerService {
+    /// Creates a tx power service with the specified transmit power level in
+    /// dBm.
+    #[inline]
+    #[must_use]
+    pub fn new(level: i8) -> Self {
+        Self(Arc::new(SyncMutex::new(level)))
+    }
+
+    /// Returns the current transmit power level in dBm.
+    #[inline]
+    #[must_use]
+    pub fn level(&self) -> i8 {
+        *self.0.lock()
+    }
+
+    /// Sets the transmit power level in dBm.
+    #[inline]
+    pub fn set_level(&self, level: i8) {
+        *self.0.lock() = level;
+    }
+
+    /// Updates the transmit power level with the current value for
+    /// connection `hdl`, as reported by the controller.
+    pub async fn update(&self, host: &hci::Host, hdl: hci::ConnHandle) -> hci::Result<i8> {
+        let level = host.read_transmit_power_level(hdl, false).await?;
+        self.set_level(level);
+        Ok(level)
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (hdl, ()) = db.primary_service(Service::TxPower, [], |db| {
+            // Tx Power Level ([TPS] Section 3.1)
+            db.characteristic(
+                Characteristic::TxPowerLevel,
+                Prop::READ,
+                ac.read(),
+                Io::with(&self.0, |this, req| match req {
+                    #[allow(clippy::cast_sign_loss)]
+                    IoReq::Read(r) => r.complete([*this.lock() as u8]),
+                    _ => Err(ErrorCode::RequestNotSupported),
+                }),
+                |_| {},
+            );
+        });
+        hdl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::att::Opcode;
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn link_loss() {
+        let alerts = Arc::new(SyncMutex::new(Vec::new()));
+        let lls = LinkLossService::new({
+            let alerts = Arc::clone(&alerts);
+            move |level| alerts.lock().push(level)
+        });
+        let t = TestDb::new(|db| lls.define(db, Access::NONE));
+        let c = t.characteristics().next().unwrap();
+        assert_eq!(c.properties(), Prop::READ | Prop::WRITE);
+
+        let read = || t.read(c.value_handle()).unwrap();
+        let write = |v: &[u8]| t.write(c.value_handle(), v);
+        assert_eq!(read(), [0]);
+        assert!(!lls.disconnected(hci::Status::ConnectionTimeout.into()));
+        assert_eq!(write(&[3]), Err(ErrorCode::ValueNotAllowed));
+        assert_eq!(write(&[1, 0]), Err(ErrorCode::InvalidAttributeValueLength));
+        write(&[2]).unwrap();
+        assert_eq!(read(), [2]);
+        assert_eq!(lls.alert_level(), AlertLevel::High);
+
+        assert!(!lls.disconnected(hci::Status::ConnectionTerminatedByLocalHost.into()));
+        assert!(alerts.lock().is_empty());
+        assert!(lls.disconnected(hci::Status::ConnectionTimeout.into()));
+        assert_eq!(*alerts.lock(), [AlertLevel::High]);
+    }
+
+    #[test]
+    fn immediate_alert() {
+        let alerts = Arc::new(SyncMutex::new(Vec::new()));
+        let ias = ImmediateAlertService::new({
+            let alerts = Arc::clone(&alerts);
+            move |level| alerts.lock().push(level)
+        });
+        let t = TestDb::new(|db| ias.define(db, Access::NONE));
+        let c = t.characteristics().next().unwrap();
+        assert_eq!(c.properties(), Prop::WRITE_CMD);
+
+        let write = |v: &[u8]| t.write_as(TestDb::PEER, Opcode::WriteCmd, c.value_handle(), v);
+        write(&[1]).unwrap();
+        write(&[0]).unwrap();
+        assert_eq!(write(&[0xFF]), Err(ErrorCode::ValueNotAllowed));
+        assert_eq!(*alerts.lock(), [AlertLevel::Mild, AlertLevel::None]);
+    }
+
+    #[test]
+    fn tx_power() {
+        let tps = TxPowerService::new(4);
+        let t = TestDb::new(|db| tps.define(db, Access::NONE));
+        let c = t.characteristics().next().unwrap();
+        let read = || t.read(c.value_handle()).unwrap();
+        assert_eq!(read(), [4]);
+        tps.set_level(-20);
+        assert_eq!(read(), [0xEC]);
+    }
+}
@@ -0,0 +1,442 @@
+//! Record Access Control Point ([RACP]).
+//!
+//! The RACP is used by services that store measurement records, such as the
+//! Glucose and Pulse Oximeter services, to let the client retrieve and delete
+//! records. Records are identified by their sequence numbers. This module
+//! implements request parsing, record filtering, and procedure sequencing;
+//! services provide record access via [`RacpState`].
+//!
+//! [RACP]: https://www.bluetooth.com/specifications/specs/glucose-service-1-0-1/
+
+use std::sync::Arc;
+
+use structbuf::{Packer, Unpack};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::att::ErrorCode;
+use crate::gatt::{IoReq, IoResult, Notify, NotifyReq};
+use crate::SyncMutex;
+
+/// Application-provided record storage.
+pub trait RecordStore: Send + Sync {
+    /// Type of stored records.
+    type Record;
+
+    /// Returns the sequence numbers of all stored records in ascending order.
+    #[must_use]
+    fn seqs(&self) -> Vec<u16>;
+
+    /// Returns the record with sequence number `seq`.
+    #[must_use]
+    fn get(&self, seq: u16) -> Option<Self::Record>;
+
+    /// Removes the record with sequence number `seq`.
+    fn remove(&self, seq: u16);
+}
+
+/// Service state that provides access to stored records.
+pub(super) trait RacpState: Send + 'static {
+    /// Returns the RACP state.
+    fn racp(&mut self) -> &mut Racp;
+
+    /// Returns the sequence numbers of all stored records in ascending order.
+    fn seqs(&self) -> Vec<u16>;
+
+    /// Returns the notifications that transfer record `seq` to the client in
+    /// the order they must be sent. Returns [`None`] if the client is not
+    /// subscribed to record notifications.
+    fn report(&mut self, seq: u16) -> Option<Vec<Notify>>;
+
+    /// Removes record `seq`.
+    fn remove(&mut self, seq: u16);
+}
+
+/// RACP state.
+#[derive(Debug, Default)]
+pub(super) struct Racp {
+    ind: Option<NotifyReq>,
+    busy: Option<(u64, CancellationToken)>,
+    next_id: u64,
+    all_only: bool,
+}
+
+impl Racp {
+    /// Creates RACP state that only supports the "All records" operator, as
+    /// required by services without record sequence numbers.
+    #[inline]
+    #[must_use]
+    pub(super) const fn all_records_only() -> Self {
+        Self {
+            ind: None,
+            busy: None,
+            next_id: 0,
+            all_only: true,
+        }
+    }
+
+    /// Handles RACP characteristic I/O. Valid write requests start a
+    /// procedure that indicates the result to the client.
+    pub(super) fn io<T: RacpState>(this: &Arc<SyncMutex<T>>, req: IoReq) -> IoResult {
+        let mut s = this.lock();
+        let r = s.racp();
+        match req {
+            IoReq::Read(_) => Err(ErrorCode::ReadNotPermitted),
+            IoReq::Write(w) => {
+                if w.offset() != 0 {
+                    return Err(ErrorCode::InvalidOffset);
+                }
+                if !(r.ind.as_ref()).map_or(false, |n| !n.is_closed()) {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                let req = Request::parse(w.value())?.and_then(|req| r.check(req));
+                let abort = matches!(req, Ok(Request::Abort));
+                if r.busy.is_some() && !abort {
+                    return Err(ErrorCode::ProcedureAlreadyInProgress);
+                }
+                let (id, ct) = (r.next_id, CancellationToken::new());
+                r.next_id += 1;
+                if let Some((_, prev)) = r.busy.replace((id, ct.clone())) {
+                    prev.cancel();
+                }
+                tokio::spawn(Self::run(Arc::clone(this), id, req, ct));
+                Ok(())
+            }
+            IoReq::Notify(n) => {
+                if !n.is_indicate() {
+                    return Err(ErrorCode::CccdImproperlyConfigured);
+                }
+                r.ind = Some(n);
+                Ok(())
+            }
+        }
+    }
+
+    /// Rejects requests with unsupported operators.
+    fn check(&self, req: Request) -> Result<Request, Response> {
+        match req {
+            Request::Report(f) | Request::Delete(f) | Request::Count(f)
+                if self.all_only && f != Filter::All =>
+            {
+                Err(Response::Code(req.op(), ResponseCode::OperatorNotSupported))
+            }
+            _ => Ok(req),
+        }
+    }
+
+    /// Executes a procedure and indicates the response to the client. The
+    /// response is not sent if the procedure is aborted.
+    async fn run<T: RacpState>(
+        this: Arc<SyncMutex<T>>,
+        id: u64,
+        req: Result<Request, Response>,
+        ct: CancellationToken,
+    ) {
+        let rsp = match req {
+            Ok(req) => {
+                debug!("RACP request: {req:?}");
+                tokio::select! {
+                    rsp = Self::exec(&this, req) => rsp,
+                    () = ct.cancelled() => {
+                        debug!("RACP procedure aborted");
+                        return;
+                    }
+                }
+            }
+            Err(rsp) => rsp,
+        };
+        debug!("RACP response: {rsp:?}");
+        let ind = {
+            let mut s = this.lock();
+            let r = s.racp();
+            if r.busy.as_ref().map_or(true, |&(busy, _)| busy != id) {
+                return;
+            }
+            r.busy = None;
+            (r.ind.as_ref()).map(|n| n.notify(|p| rsp.pack(p)))
+        };
+        let Some(ind) = ind else { return };
+        if let Err(e) = ind.await {
+            warn!("RACP response indication error: {e}");
+        }
+    }
+
+    /// Executes a request.
+    async fn exec<T: RacpState>(this: &Arc<SyncMutex<T>>, req: Request) -> Response {
+        let (op, filter) = match req {
+            Request::Report(f) => (Request::REPORT, f),
+            Request::Delete(f) => (Request::DELETE, f),
+            Request::Count(f) => {
+                let n = f.select(&this.lock().seqs()).len();
+                return Response::Count(u16::try_from(n).unwrap_or(u16::MAX));
+            }
+            Request::Abort => return Response::Code(Request::ABORT, ResponseCode::Success),
+        };
+        let seqs = filter.select(&this.lock().seqs());
+        if seqs.is_empty() {
+            return Response::Code(op, ResponseCode::NoRecordsFound);
+        }
+        for seq in seqs {
+            if op == Request::DELETE {
+                this.lock().remove(seq);
+                continue;
+            }
+            let Some(ntfs) = this.lock().report(seq) else {
+                return Response::Code(op, ResponseCode::ProcedureNotCompleted);
+            };
+            for ntf in ntfs {
+                if let Err(e) = ntf.await {
+                    warn!("RACP record {seq} transfer error: {e}");
+                    return Response::Code(op, ResponseCode::ProcedureNotCompleted);
+                }
+            }
+        }
+        Response::Code(op, ResponseCode::Success)
+    }
+}
+
+/// RACP request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Request {
+    Report(Filter),
+    Delete(Filter),
+    Abort,
+    Count(Filter),
+}
+
+impl Request {
+    const REPORT: u8 = 0x01;
+    const DELETE: u8 = 0x02;
+    const ABORT: u8 = 0x03;
+    const COUNT: u8 = 0x04;
+
+    /// Returns the request op code.
+    #[inline]
+    const fn op(self) -> u8 {
+        match self {
+            Self::Report(_) => Self::REPORT,
+            Self::Delete(_) => Self::DELETE,
+            Self::Abort => Self::ABORT,
+            Self::Count(_) => Self::COUNT,
+        }
+    }
+
+    /// Parses a request. Returns the response to an invalid request as
+    /// `Ok(Err(_))`. An empty request is rejected with an ATT error.
+    fn parse(v: &[u8]) -> Result<Result<Self, Response>, ErrorCode> {
+        let Some((&op, v)) = v.split_first() else {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        };
+        let req = match (op, v.split_first()) {
+            (Self::REPORT | Self::DELETE | Self::ABORT | Self::COUNT, None) => {
+                Err(ResponseCode::InvalidOperator)
+            }
+            (Self::REPORT, Some((&opr, v))) => Filter::parse(opr, v).map(Self::Report),
+            (Self::DELETE, Some((&opr, v))) => Filter::parse(opr, v).map(Self::Delete),
+            (Self::COUNT, Some((&opr, v))) => Filter::parse(opr, v).map(Self::Count),
+            (Self::ABORT, Some((&Filter::NULL, &[]))) => Ok(Self::Abort),
+            (Self::ABORT, Some((&Filter::NULL, _))) => Err(ResponseCode::InvalidOperand),
+            (Self::ABORT, Some(_)) => Err(ResponseCode::InvalidOperator),
+            _ => Err(ResponseCode::OpCodeNotSupported),
+        };
+        Ok(req.map_err(|c| Response::Code(op, c)))
+    }
+}
+
+/// Record selection filter.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Filter {
+    All,
+    AtMost(u16),
+    AtLeast(u16),
+    Range(u16, u16),
+    First,
+    Last,
+}
+
+impl Filter {
+    const NULL: u8 = 0x00;
+    const SEQ: u8 = 0x01;
+
+    /// Parses the operator and operand of a request.
+    fn parse(opr: u8, v: &[u8]) -> Result<Self, ResponseCode> {
+        let simple = match opr {
+            Self::NULL => return Err(ResponseCode::InvalidOperator),
+            0x01 => Some(Self::All),
+            0x05 => Some(Self::First),
+            0x06 => Some(Self::Last),
+            0x02..=0x04 => None,
+            _ => return Err(ResponseCode::OperatorNotSupported),
+        };
+        if let Some(f) = simple {
+            return v
+                .is_empty()
+                .then_some(f)
+                .ok_or(ResponseCode::InvalidOperand);
+        }
+        let Some((&typ, v)) = v.split_first() else { return Err(ResponseCode::InvalidOperand) };
+        if typ != Self::SEQ {
+            return Err(ResponseCode::OperandNotSupported);
+        }
+        let mut v = v.unpack();
+        let f = match opr {
+            0x02 => Self::AtMost(v.u16()),
+            0x03 => Self::AtLeast(v.u16()),
+            _ => match (v.u16(), v.u16()) {
+                (min, max) if min <= max => Self::Range(min, max),
+                _ => return Err(ResponseCode::InvalidOperand),
+            },
+        };
+        (v.is_ok() && v.is_empty())
+            .then_some(f)
+            .ok_or(ResponseCode::InvalidOperand)
+    }
+
+    /// Returns the selected sequence numbers from `seqs`, which must be
+    /// sorted in ascending order.
+    fn select(self, seqs: &[u16]) -> Vec<u16> {
+        let sel = |f: &dyn Fn(u16) -> bool| seqs.iter().copied().filter(|&s| f(s)).collect();
+        match self {
+            Self::All => seqs.to_vec(),
+            Self::AtMost(max) => sel(&|s| s <= max),
+            Self::AtLeast(min) => sel(&|s| s >= min),
+            Self::Range(min, max) => sel(&|s| (min..=max).contains(&s)),
+            Self::First => seqs.first().copied().into_iter().collect(),
+            Self::Last => seqs.last().copied().into_iter().collect(),
+        }
+    }
+}
+
+/// RACP response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Response {
+    /// Response code for the specified request op code.
+    Code(u8, ResponseCode),
+    /// Number of stored records.
+    Count(u16),
+}
+
+impl Response {
+    /// Packs the response.
+    fn pack(self, p: &mut Packer) {
+        match self {
+            Self::Code(op, c) => p.u8(0x06).u8(Filter::NULL).u8(op).u8(c as u8),
+            Self::Count(n) => p.u8(0x05).u8(Filter::NULL).u16(n),
+        };
+    }
+}
+
+/// RACP response code value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+enum ResponseCode {
+    Success = 0x01,
+    OpCodeNotSupported = 0x02,
+    InvalidOperator = 0x03,
+    OperatorNotSupported = 0x04,
+    InvalidOperand = 0x05,
+    NoRecordsFound = 0x06,
+    ProcedureNotCompleted = 0x08,
+    OperandNotSupported = 0x09,
+}
+
+#[cfg(test)]
+mod tests {
+    use structbuf::{Pack, StructBuf};
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        use ResponseCode::*;
+        let ok = |v: &[u8]| Request::parse(v).unwrap().unwrap();
+        let err = |v: &[u8]| match Request::parse(v).unwrap().unwrap_err() {
+            Response::Code(_, c) => c,
+            Response::Count(_) => unreachable!(),
+        };
+        assert_eq!(
+            Request::parse(&[]),
+            Err(ErrorCode::InvalidAttributeValueLength)
+        );
+        assert_eq!(ok(&[0x01, 0x01]), Request::Report(Filter::All));
+        assert_eq!(ok(&[0x02, 0x05]), Request::Delete(Filter::First));
+        assert_eq!(ok(&[0x04, 0x06]), Request::Count(Filter::Last));
+        assert_eq!(ok(&[0x03, 0x00]), Request::Abort);
+        assert_eq!(
+            ok(&[0x01, 0x02, 0x01, 0x34, 0x12]),
+            Request::Report(Filter::AtMost(0x1234))
+        );
+        assert_eq!(
+            ok(&[0x01, 0x03, 0x01, 0x34, 0x12]),
+            Request::Report(Filter::AtLeast(0x1234))
+        );
+        assert_eq!(
+            ok(&[0x01, 0x04, 0x01, 0x01, 0x00, 0x02, 0x00]),
+            Request::Report(Filter::Range(1, 2))
+        );
+
+        assert_eq!(err(&[0x00, 0x01]), OpCodeNotSupported);
+        assert_eq!(err(&[0x06, 0x00]), OpCodeNotSupported);
+        assert_eq!(err(&[0x01]), InvalidOperator);
+        assert_eq!(err(&[0x01, 0x00]), InvalidOperator);
+        assert_eq!(err(&[0x01, 0x07]), OperatorNotSupported);
+        assert_eq!(err(&[0x01, 0x01, 0x00]), InvalidOperand);
+        assert_eq!(err(&[0x01, 0x02]), InvalidOperand);
+        assert_eq!(err(&[0x01, 0x02, 0x01, 0x00]), InvalidOperand);
+        assert_eq!(err(&[0x01, 0x02, 0x01, 0x00, 0x00, 0x00]), InvalidOperand);
+        assert_eq!(err(&[0x01, 0x02, 0x02, 0x00, 0x00]), OperandNotSupported);
+        assert_eq!(
+            err(&[0x01, 0x04, 0x01, 0x02, 0x00, 0x01, 0x00]),
+            InvalidOperand
+        );
+        assert_eq!(err(&[0x03, 0x01]), InvalidOperator);
+        assert_eq!(err(&[0x03, 0x00, 0x00]), InvalidOperand);
+    }
+
+    #[test]
+    fn all_records_only() {
+        let all = Racp::all_records_only();
+        let check = |r: &Racp, v: &[u8]| Request::parse(v).unwrap().and_then(|req| r.check(req));
+        assert_eq!(check(&all, &[0x01, 0x01]), Ok(Request::Report(Filter::All)));
+        assert_eq!(check(&all, &[0x03, 0x00]), Ok(Request::Abort));
+        assert_eq!(
+            check(&all, &[0x02, 0x06]),
+            Err(Response::Code(0x02, ResponseCode::OperatorNotSupported))
+        );
+        assert_eq!(
+            check(&all, &[0x04, 0x02, 0x01, 0x00, 0x00]),
+            Err(Response::Code(0x04, ResponseCode::OperatorNotSupported))
+        );
+        assert_eq!(
+            check(&Racp::default(), &[0x02, 0x06]),
+            Ok(Request::Delete(Filter::Last))
+        );
+    }
+
+    #[test]
+    fn select() {
+        let seqs = [1, 2, 5, 7];
+        assert_eq!(Filter::All.select(&seqs), seqs);
+        assert_eq!(Filter::AtMost(5).select(&seqs), [1, 2, 5]);
+        assert_eq!(Filter::AtLeast(3).select(&seqs), [5, 7]);
+        assert_eq!(Filter::Range(2, 6).select(&seqs), [2, 5]);
+        assert_eq!(Filter::First.select(&seqs), [1]);
+        assert_eq!(Filter::Last.select(&seqs), [7]);
+        assert!(Filter::Last.select(&[]).is_empty());
+        assert!(Filter::AtLeast(8).select(&seqs).is_empty());
+    }
+
+    #[test]
+    fn response() {
+        let pack = |r: Response| {
+            let mut b = StructBuf::new(20);
+            r.pack(&mut b.append());
+            b.as_ref().to_vec()
+        };
+        assert_eq!(
+            pack(Response::Code(0x01, ResponseCode::Success)),
+            [0x06, 0x00, 0x01, 0x01]
+        );
+        assert_eq!(pack(Response::Count(0x0102)), [0x05, 0x00, 0x02, 0x01]);
+    }
+}
@@ -0,0 +1,531 @@
+//! Running Speed and Cadence Service ([RSCS]).
+//!
+//! This service exposes speed, cadence, and other data from a running speed
+//! and cadence sensor intended for fitness applications.
+//!
+//! [RSCS]: https://www.bluetooth.com/specifications/specs/running-speed-and-cadence-service-1-0/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use structbuf::{Packer, Unpack};
+use tracing::debug;
+
+pub use super::cscs::SensorLocation;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+use super::cp::{ControlPoint, ControlPointState, ScLocation, ScOp, ScResponse};
+
+/// Callback for the Start Sensor Calibration procedure.
+type CalibrateFn = Box<dyn FnMut() -> bool + Send>;
+
+/// Running speed and cadence service instance. Clones refer to the same
+/// instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct RunningSpeedCadenceService(Arc<SyncMutex<State>>);
+
+impl RunningSpeedCadenceService {
+    /// Creates a running speed and cadence service that reports only the
+    /// instantaneous speed and cadence.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables instantaneous stride length reporting.
+    #[inline]
+    #[must_use]
+    pub fn with_stride_length(self) -> Self {
+        self.0.lock().stride = Some(0);
+        self
+    }
+
+    /// Enables total distance reporting.
+    #[inline]
+    #[must_use]
+    pub fn with_total_distance(self) -> Self {
+        self.0.lock().distance = Some(0);
+        self
+    }
+
+    /// Enables walking or running status reporting.
+    #[inline]
+    #[must_use]
+    pub fn with_running_status(self) -> Self {
+        self.0.lock().status = true;
+        self
+    }
+
+    /// Enables the Start Sensor Calibration procedure. `f` is called when the
+    /// client requests calibration and returns whether calibration was
+    /// started.
+    #[inline]
+    #[must_use]
+    pub fn with_calibration(self, f: impl FnMut() -> bool + Send + 'static) -> Self {
+        self.0.lock().calibrate = Some(Box::new(f));
+        self
+    }
+
+    /// Adds the Sensor Location characteristic with a fixed location.
+    #[inline]
+    #[must_use]
+    pub fn with_location(self, loc: SensorLocation) -> Self {
+        self.0.lock().loc.loc = Some(loc);
+        self
+    }
+
+    /// Adds the Sensor Location characteristic with the current location
+    /// `loc`, which the client may change to any of the `supported`
+    /// locations.
+    #[must_use]
+    pub fn with_locations(
+        self,
+        loc: SensorLocation,
+        supported: impl IntoIterator<Item = SensorLocation>,
+    ) -> Self {
+        self.0.lock().loc.set(loc, supported);
+        self
+    }
+
+    /// Returns the RSC Feature characteristic value ([RSCS] Section 3.2.1).
+    #[must_use]
+    pub fn features(&self) -> u16 {
+        self.0.lock().features()
+    }
+
+    /// Returns the current sensor location.
+    #[inline]
+    #[must_use]
+    pub fn location(&self) -> Option<SensorLocation> {
+        self.0.lock().loc.loc
+    }
+
+    /// Sets the instantaneous speed in units of 1/256 m/s and cadence in
+    /// steps per minute.
+    #[inline]
+    pub fn set_speed_cadence(&self, speed: u16, cadence: u8) {
+        let mut s = self.0.lock();
+        s.speed = speed;
+        s.cadence = cadence;
+    }
+
+    /// Sets the instantaneous stride length in centimeters. Does nothing if
+    /// stride length reporting is not enabled.
+    #[inline]
+    pub fn set_stride_length(&self, cm: u16) {
+        if let Some(v) = self.0.lock().stride.as_mut() {
+            *v = cm;
+        }
+    }
+
+    /// Sets whether the user is running (`true`) or walking (`false`).
+    #[inline]
+    pub fn set_running(&self, running: bool) {
+        self.0.lock().running = running;
+    }
+
+    /// Adds `dm` decimeters to the total distance. The value wraps around at
+    /// 2^32. Does nothing if total distance reporting is not enabled.
+    #[inline]
+    pub fn add_distance(&self, dm: u32) {
+        if let Some(v) = self.0.lock().distance.as_mut() {
+            *v = v.wrapping_add(dm);
+        }
+    }
+
+    /// Returns the current measurement.
+    #[inline]
+    #[must_use]
+    pub fn measurement(&self) -> RscMeasurement {
+        self.0.lock().measurement()
+    }
+
+    /// Notifies the client of the current measurement. This should be called
+    /// periodically, typically once per second.
+    pub fn notify_measurement(&self) {
+        let s = self.0.lock();
+        let Some(n) = s.ntf.as_ref() else { return };
+        let m = s.measurement();
+        if let Err(e) = n.notify_dropping_if_full(|p| m.pack(p)) {
+            debug!("RSC measurement notify error: {e}");
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let (feat, loc, cp) = {
+            let s = self.0.lock();
+            (
+                s.features(),
+                s.loc.loc.is_some(),
+                s.distance.is_some() || s.calibrate.is_some() || s.loc.is_multiple(),
+            )
+        };
+        let (hdl, ()) = db.primary_service(Service::RunningSpeedAndCadence, [], |db| {
+            use Characteristic::*;
+            // RSC Measurement ([RSCS] Section 3.1)
+            db.characteristic(
+                RscMeasurement,
+                Prop::NOTIFY,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().measurement_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+
+            // RSC Feature ([RSCS] Section 3.2)
+            db.ro_characteristic(RscFeature, ac.read(), feat.to_le_bytes(), |_| {});
+
+            // Sensor Location ([RSCS] Section 3.3)
+            if loc {
+                db.characteristic(
+                    SensorLocation,
+                    Prop::READ,
+                    ac.read(),
+                    Io::with(&self.0, |this, req| this.lock().location_io(req)),
+                    |_| {},
+                );
+            }
+
+            // SC Control Point ([RSCS] Section 3.4)
+            if cp {
+                db.characteristic(
+                    ScControlPoint,
+                    Prop::WRITE | Prop::INDICATE,
+                    ac.write(),
+                    {
+                        let this = Arc::clone(&self.0);
+                        move |req: IoReq| ControlPoint::io(&this, req)
+                    },
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+        });
+        hdl
+    }
+}
+
+/// Running speed and cadence service state.
+#[derive(Default)]
+struct State {
+    status: bool,
+    calibrate: Option<CalibrateFn>,
+    loc: ScLocation,
+    speed: u16,
+    cadence: u8,
+    stride: Option<u16>,
+    distance: Option<u32>,
+    running: bool,
+    ntf: Option<NotifyReq>,
+    cp: ControlPoint,
+}
+
+impl State {
+    const STRIDE_LENGTH: u16 = 1 << 0;
+    const TOTAL_DISTANCE: u16 = 1 << 1;
+    const RUNNING_STATUS: u16 = 1 << 2;
+    const CALIBRATION: u16 = 1 << 3;
+    const MULTIPLE_LOCATIONS: u16 = 1 << 4;
+
+    /// Returns the RSC Feature characteristic value.
+    fn features(&self) -> u16 {
+        let flag = |present: bool, f: u16| if present { f } else { 0 };
+        flag(self.stride.is_some(), Self::STRIDE_LENGTH)
+            | flag(self.distance.is_some(), Self::TOTAL_DISTANCE)
+            | flag(self.status, Self::RUNNING_STATUS)
+            | flag(self.calibrate.is_some(), Self::CALIBRATION)
+            | flag(self.loc.is_multiple(), Self::MULTIPLE_LOCATIONS)
+    }
+
+    /// Returns the current measurement.
+    const fn measurement(&self) -> RscMeasurement {
+        RscMeasurement {
+            speed: self.speed,
+            cadence: self.cadence,
+            stride: self.stride,
+            distance: self.distance,
+            running: self.status && self.running,
+        }
+    }
+
+    /// Handles RSC Measurement characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ntf = Some(n);
+        Ok(())
+    }
+
+    /// Handles Sensor Location characteristic I/O.
+    #[allow(clippy::needless_pass_by_value)]
+    fn location_io(&self, req: IoReq) -> IoResult {
+        let IoReq::Read(r) = req else { return Err(ErrorCode::RequestNotSupported) };
+        r.complete([self.loc.value()])
+    }
+}
+
+impl ControlPointState for State {
+    type Code = ScResponse;
+
+    #[inline(always)]
+    fn control_point(&mut self) -> &mut ControlPoint {
+        &mut self.cp
+    }
+
+    fn exec(&mut self, op: u8, param: &[u8]) -> (ScResponse, Vec<u8>) {
+        if let Some(r) = self.loc.exec(op, param) {
+            return r;
+        }
+        let code = match op {
+            ScOp::SET_CUMULATIVE_VALUE if self.distance.is_some() => {
+                match <[u8; 4]>::try_from(param) {
+                    Ok(v) => {
+                        self.distance = Some(u32::from_le_bytes(v));
+                        ScResponse::Success
+                    }
+                    Err(_) => ScResponse::InvalidParameter,
+                }
+            }
+            ScOp::START_SENSOR_CALIBRATION => match self.calibrate.as_mut() {
+                None => ScResponse::OpCodeNotSupported,
+                Some(_) if !param.is_empty() => ScResponse::InvalidParameter,
+                Some(f) => {
+                    if f() {
+                        ScResponse::Success
+                    } else {
+                        ScResponse::OperationFailed
+                    }
+                }
+            },
+            _ => ScResponse::OpCodeNotSupported,
+        };
+        (code, Vec::new())
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("status", &self.status)
+            .field("loc", &self.loc)
+            .field("speed", &self.speed)
+            .field("cadence", &self.cadence)
+            .field("stride", &self.stride)
+            .field("distance", &self.distance)
+            .field("running", &self.running)
+            .field("ntf", &self.ntf)
+            .field("cp", &self.cp)
+            .finish_non_exhaustive()
+    }
+}
+
+/// RSC Measurement characteristic value ([RSCS] Section 3.1.1).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RscMeasurement {
+    /// Instantaneous speed in units of 1/256 m/s.
+    pub speed: u16,
+    /// Instantaneous cadence in steps per minute.
+    pub cadence: u8,
+    /// Instantaneous stride length in centimeters.
+    pub stride: Option<u16>,
+    /// Total distance in decimeters.
+    pub distance: Option<u32>,
+    /// Running (`true`) or walking (`false`) status.
+    pub running: bool,
+}
+
+impl RscMeasurement {
+    const STRIDE_LENGTH: u8 = 1 << 0;
+    const TOTAL_DISTANCE: u8 = 1 << 1;
+    const RUNNING: u8 = 1 << 2;
+
+    /// Packs the measurement.
+    pub fn pack(&self, p: &mut Packer) {
+        let flag = |present: bool, f: u8| if present { f } else { 0 };
+        p.u8(flag(self.stride.is_some(), Self::STRIDE_LENGTH)
+            | flag(self.distance.is_some(), Self::TOTAL_DISTANCE)
+            | flag(self.running, Self::RUNNING));
+        p.u16(self.speed).u8(self.cadence);
+        if let Some(v) = self.stride {
+            p.u16(v);
+        }
+        if let Some(v) = self.distance {
+            p.u32(v);
+        }
+    }
+
+    /// Unpacks a measurement received from a sensor.
+    #[must_use]
+    pub fn unpack(v: &[u8]) -> Option<Self> {
+        let mut p = v.unpack();
+        let f = p.u8();
+        let (speed, cadence) = (p.u16(), p.u8());
+        let stride = (f & Self::STRIDE_LENGTH != 0).then(|| p.u16());
+        let distance = (f & Self::TOTAL_DISTANCE != 0).then(|| p.u32());
+        (p.is_ok() && p.is_empty()).then_some(Self {
+            speed,
+            cadence,
+            stride,
+            distance,
+            running: f & Self::RUNNING != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+    use crate::gatt::NotifyVal;
+
+    use super::*;
+
+    #[test]
+    fn measurement() {
+        let m = RscMeasurement {
+            speed: 0x0102,
+            cadence: 0x03,
+            stride: Some(0x0405),
+            distance: Some(0x0607_0809),
+            running: true,
+        };
+        let mut b = StructBuf::new(10);
+        m.pack(&mut b.append());
+        assert_eq!(
+            b.as_ref(),
+            [0x07, 0x02, 0x01, 0x03, 0x05, 0x04, 0x09, 0x08, 0x07, 0x06]
+        );
+        assert_eq!(RscMeasurement::unpack(b.as_ref()), Some(m));
+        assert_eq!(
+            RscMeasurement::unpack(&[0x02, 0x02, 0x01, 0x03, 0x09, 0x08, 0x07, 0x06]),
+            Some(RscMeasurement {
+                stride: None,
+                running: false,
+                ..m
+            })
+        );
+        assert_eq!(RscMeasurement::unpack(&[0x01, 0x02, 0x01, 0x03]), None);
+        assert_eq!(
+            RscMeasurement::unpack(&[0x00, 0x02, 0x01, 0x03, 0x00]),
+            None
+        );
+    }
+
+    #[test]
+    fn values() {
+        let rsc = RunningSpeedCadenceService::new()
+            .with_total_distance()
+            .with_running_status();
+        assert_eq!(rsc.features(), 0x06);
+        rsc.set_speed_cadence(768, 170);
+        rsc.set_stride_length(120);
+        rsc.set_running(true);
+        rsc.add_distance(u32::MAX);
+        rsc.add_distance(6);
+        assert_eq!(
+            rsc.measurement(),
+            RscMeasurement {
+                speed: 768,
+                cadence: 170,
+                stride: None,
+                distance: Some(5),
+                running: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn control_point() {
+        use SensorLocation::*;
+        let calibrated = Arc::new(AtomicBool::new(false));
+        let rsc = RunningSpeedCadenceService::new()
+            .with_total_distance()
+            .with_calibration({
+                let calibrated = Arc::clone(&calibrated);
+                move || !calibrated.swap(true, Ordering::Relaxed)
+            })
+            .with_locations(InShoe, [TopOfShoe, Hip]);
+        assert_eq!(rsc.features(), 0x1A);
+        let t = TestDb::new(|db| rsc.define(db, Access::NONE));
+        let chars: Vec<_> = t.characteristics().collect();
+        assert_eq!(chars.len(), 4);
+        let cp = &chars[3];
+        assert_eq!(cp.uuid(), Characteristic::ScControlPoint);
+
+        let write = |v: &[u8]| t.write(cp.value_handle(), v);
+        let (tx, mut rx) = mpsc::channel(1);
+        t.io.notify(NotifyReq {
+            hdl: cp.value_handle(),
+            uuid: cp.uuid(),
+            mtu: 23,
+            ind: true,
+            tx,
+            ct: CancellationToken::new(),
+        })
+        .unwrap();
+
+        // Set cumulative value
+        write(&[0x01, 0x04, 0x03, 0x02, 0x01]).unwrap();
+        assert_eq!(write(&[0x02]), Err(ErrorCode::ProcedureAlreadyInProgress));
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x01, 0x01]);
+        assert_eq!(rsc.measurement().distance, Some(0x0102_0304));
+
+        // Start sensor calibration
+        write(&[0x02]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x02, 0x01]);
+        assert!(calibrated.load(Ordering::Relaxed));
+        write(&[0x02]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x02, 0x04]);
+        write(&[0x02, 0x00]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x02, 0x03]);
+
+        // Sensor locations
+        write(&[0x04]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x04, 0x01, 1, 2, 3]);
+        write(&[0x03, Hip as u8]).unwrap();
+        assert_eq!(confirm(&mut rx).await, [0x10, 0x03, 0x01]);
+        assert_eq!(rsc.location(), Some(Hip));
+    }
+
+    #[test]
+    fn optional_characteristics() {
+        let rsc = RunningSpeedCadenceService::new().with_stride_length();
+        let t = TestDb::new(|db| rsc.define(db, Access::NONE));
+        let uuids: Vec<_> = t.characteristics().map(|c| c.uuid()).collect();
+        assert_eq!(
+            uuids,
+            [Characteristic::RscMeasurement, Characteristic::RscFeature]
+        );
+    }
+
+    /// Receives and confirms the next control point response, and waits for
+    /// the procedure to complete.
+    async fn confirm(rx: &mut mpsc::Receiver<NotifyVal>) -> Vec<u8> {
+        let v = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let r = v.as_ref().to_vec();
+        v.result(Ok(()));
+        tokio::task::yield_now().await;
+        r
+    }
+}
@@ -0,0 +1,247 @@
+//! Scan Parameters Service ([SCPS]).
+//!
+//! This service allows a GATT client to store its LE scan parameters on the
+//! server, which the server can use to optimize its advertising and
+//! connection behavior.
+//!
+//! [SCPS]: https://www.bluetooth.com/specifications/specs/scan-parameters-service-1-0/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use structbuf::Unpack;
+use tracing::debug;
+
+use crate::att::{Access, ErrorCode, Handle};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+/// Callback for Scan Interval Window characteristic writes.
+type WriteFn = Box<dyn FnMut(ScanIntervalWindow) + Send>;
+
+/// Scan Parameters service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct ScanParamsService(Arc<SyncMutex<State>>);
+
+impl ScanParamsService {
+    /// Creates a scan parameters service that passes the client's scan
+    /// parameters to `f`.
+    #[inline]
+    #[must_use]
+    pub fn new(f: impl FnMut(ScanIntervalWindow) + Send + 'static) -> Self {
+        let s = Self::default();
+        s.0.lock().write = Some(Box::new(f));
+        s
+    }
+
+    /// Adds the Scan Refresh characteristic, which allows the server to
+    /// request updated scan parameters from the client.
+    #[inline]
+    #[must_use]
+    pub fn with_refresh(self) -> Self {
+        self.0.lock().refresh = true;
+        self
+    }
+
+    /// Requests the client to write its current scan parameters
+    /// ([SCPS] Section 3.2.1). This has no effect if the Scan Refresh
+    /// characteristic is not enabled or if the client has not enabled
+    /// notifications.
+    pub fn refresh(&self) {
+        self.0.lock().ntf.retain(|n| {
+            n.notify_dropping_if_full(|p| {
+                p.u8(ScanIntervalWindow::SERVER_REQUIRES_REFRESH);
+            })
+            .map_err(|e| debug!("Scan refresh notify error: {e}"))
+            .is_ok()
+        });
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let refresh = self.0.lock().refresh;
+        let (hdl, ()) = db.primary_service(Service::ScanParameters, [], |db| {
+            use Characteristic::*;
+            // Scan Interval Window ([SCPS] Section 3.1)
+            db.characteristic(
+                ScanIntervalWindow,
+                Prop::WRITE_CMD,
+                ac.write(),
+                Io::with(&self.0, |this, req| {
+                    this.lock().scan_interval_window_io(req)
+                }),
+                |_| {},
+            );
+
+            // Scan Refresh ([SCPS] Section 3.2)
+            if refresh {
+                db.characteristic(
+                    ScanRefresh,
+                    Prop::NOTIFY,
+                    ac.read(),
+                    Io::with(&self.0, |this, req| this.lock().scan_refresh_io(req)),
+                    |db| {
+                        db.cccd(ac.read_write());
+                    },
+                );
+            }
+        });
+        hdl
+    }
+}
+
+/// Scan parameters service state.
+#[derive(Default)]
+struct State {
+    refresh: bool,
+    write: Option<WriteFn>,
+    ntf: Vec<NotifyReq>,
+}
+
+impl State {
+    /// Handles Scan Interval Window characteristic I/O. Errors are not
+    /// reported to the client because this characteristic only supports
+    /// Write Without Response.
+    #[allow(clippy::needless_pass_by_value)]
+    fn scan_interval_window_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Write(w) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if w.offset() != 0 || w.value().len() != 4 {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        }
+        let p = ScanIntervalWindow::from_bytes(w.value()).ok_or(ErrorCode::ValueNotAllowed)?;
+        if let Some(f) = self.write.as_mut() {
+            f(p);
+        }
+        Ok(())
+    }
+
+    /// Handles Scan Refresh characteristic I/O.
+    fn scan_refresh_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ntf.retain(|n| !n.is_closed());
+        self.ntf.push(n);
+        Ok(())
+    }
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("refresh", &self.refresh)
+            .field("ntf", &self.ntf)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Client LE scan parameters ([SCPS] Section 3.1.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ScanIntervalWindow {
+    /// Scan interval.
+    pub interval: Duration,
+    /// Scan window.
+    pub window: Duration,
+}
+
+impl ScanIntervalWindow {
+    /// Scan Refresh value requesting the client to write the Scan Interval
+    /// Window characteristic.
+    const SERVER_REQUIRES_REFRESH: u8 = 0;
+
+    /// Parses the characteristic value. Returns [`None`] if the interval or
+    /// window is out of range, or if the window is greater than the interval
+    /// ([Vol 4] Part E, Section 7.8.10).
+    #[must_use]
+    pub fn from_bytes(v: &[u8]) -> Option<Self> {
+        const RANGE: std::ops::RangeInclusive<u16> = 0x0004..=0x4000;
+        if v.len() != 4 {
+            return None;
+        }
+        let mut v = v.unpack();
+        let (interval, window) = (v.u16(), v.u16());
+        if !RANGE.contains(&interval) || !RANGE.contains(&window) || window > interval {
+            return None;
+        }
+        let ticks = |n: u16| Duration::from_micros(u64::from(n) * 625);
+        Some(Self {
+            interval: ticks(interval),
+            window: ticks(window),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::att::Opcode;
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn scan_interval_window() {
+        assert_eq!(
+            ScanIntervalWindow::from_bytes(&[0x10, 0x00, 0x08, 0x00]),
+            Some(ScanIntervalWindow {
+                interval: Duration::from_millis(10),
+                window: Duration::from_millis(5),
+            })
+        );
+        assert!(ScanIntervalWindow::from_bytes(&[0x10, 0x00, 0x08]).is_none());
+        assert!(ScanIntervalWindow::from_bytes(&[0x03, 0x00, 0x03, 0x00]).is_none());
+        assert!(ScanIntervalWindow::from_bytes(&[0x01, 0x40, 0x01, 0x40]).is_none());
+        assert!(ScanIntervalWindow::from_bytes(&[0x08, 0x00, 0x10, 0x00]).is_none());
+    }
+
+    #[test]
+    fn service() {
+        let (tx, mut params) = mpsc::unbounded_channel();
+        let scps = ScanParamsService::new(move |p| tx.send(p).unwrap()).with_refresh();
+        let t = TestDb::new(|db| scps.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (siw, refresh) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(siw.properties(), Prop::WRITE_CMD);
+        assert_eq!(refresh.properties(), Prop::NOTIFY);
+
+        // Scan Interval Window
+        let write = |v: &[u8]| t.write_as(TestDb::PEER, Opcode::WriteCmd, siw.value_handle(), v);
+        write(&[0x10, 0x00, 0x10, 0x00]).unwrap();
+        let p = params.try_recv().unwrap();
+        assert_eq!(p.interval, Duration::from_millis(10));
+        assert_eq!(p.window, Duration::from_millis(10));
+        assert_eq!(
+            write(&[0x10, 0x00, 0x10]),
+            Err(ErrorCode::InvalidAttributeValueLength)
+        );
+        assert_eq!(
+            write(&[0x10, 0x00, 0x10, 0x00, 0x00]),
+            Err(ErrorCode::InvalidAttributeValueLength)
+        );
+        assert_eq!(
+            write(&[0x00, 0x00, 0x10, 0x00]),
+            Err(ErrorCode::ValueNotAllowed)
+        );
+        params.try_recv().unwrap_err();
+
+        // Scan Refresh
+        let (tx, mut rx) = mpsc::channel(1);
+        let req = NotifyReq {
+            hdl: refresh.value_handle(),
+            uuid: refresh.uuid(),
+            mtu: 23,
+            ind: false,
+            tx,
+            ct: CancellationToken::new(),
+        };
+        t.io.notify(req).unwrap();
+        scps.refresh();
+        assert_eq!(rx.try_recv().unwrap().as_ref(), [0]);
+    }
+}
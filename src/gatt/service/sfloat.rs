@@ -0,0 +1,237 @@
+//! IEEE 11073-20601 16-bit SFLOAT ([PHD]).
+//!
+//! SFLOAT is the medical device number format used by health services, such
+//! as the Glucose and Pulse Oximeter services. The value is a 12-bit signed
+//! mantissa and a 4-bit signed base-10 exponent. Special values are encoded
+//! with a zero exponent and mantissa values outside of `[-2045, 2045]`.
+//!
+//! [PHD]: https://www.bluetooth.com/specifications/specs/personal-health-devices-transcoding/
+
+/// IEEE 11073-20601 16-bit SFLOAT value ([PHD] Section 2.2.2).
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+#[repr(transparent)]
+pub struct Sfloat(u16);
+
+impl Sfloat {
+    /// Not a Number.
+    pub const NAN: Self = Self(0x07FF);
+    /// Not at this resolution.
+    pub const NRES: Self = Self(0x0800);
+    /// Positive infinity.
+    pub const POS_INF: Self = Self(0x07FE);
+    /// Negative infinity.
+    pub const NEG_INF: Self = Self(0x0802);
+    /// Reserved for future use.
+    pub const RESERVED: Self = Self(0x0801);
+
+    /// Maximum mantissa magnitude of a finite value.
+    const MAX_MANTISSA: i16 = 2045;
+
+    /// Encodes `v`, using the smallest exponent that can represent it without
+    /// mantissa overflow. Zero and values that round to zero are encoded as
+    /// `0x0000`. NaN is encoded as [`Self::NAN`] and values that
+    /// exceed the representable range as [`Self::POS_INF`] or
+    /// [`Self::NEG_INF`].
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    #[must_use]
+    pub fn new(v: f32) -> Self {
+        if v.is_nan() {
+            return Self::NAN;
+        }
+        for e in -8_i8..=7 {
+            let m = scale(f64::from(v), -e).round();
+            if m == 0.0 {
+                return Self(0);
+            }
+            if m.abs() <= f64::from(Self::MAX_MANTISSA) {
+                return Self((e as u16) << 12 | (m as i16 as u16) & 0x0FFF);
+            }
+        }
+        if v > 0.0 {
+            Self::POS_INF
+        } else {
+            Self::NEG_INF
+        }
+    }
+
+    /// Creates an SFLOAT from its raw encoding.
+    #[inline(always)]
+    #[must_use]
+    pub const fn from_raw(v: u16) -> Self {
+        Self(v)
+    }
+
+    /// Returns the raw encoding.
+    #[inline(always)]
+    #[must_use]
+    pub const fn raw(self) -> u16 {
+        self.0
+    }
+
+    /// Returns the mantissa and exponent of a finite value.
+    #[allow(clippy::cast_possible_wrap)]
+    #[must_use]
+    pub const fn parts(self) -> Option<(i16, i8)> {
+        // Sign-extend the 12-bit mantissa and 4-bit exponent
+        let m = (self.0 << 4) as i16 >> 4;
+        let e = (self.0 >> 8) as i8 >> 4;
+        if e == 0 && !(-Self::MAX_MANTISSA <= m && m <= Self::MAX_MANTISSA) {
+            return None;
+        }
+        Some((m, e))
+    }
+
+    /// Returns whether the value is finite.
+    #[inline]
+    #[must_use]
+    pub const fn is_finite(self) -> bool {
+        self.parts().is_some()
+    }
+
+    /// Decodes the value. [`Self::NAN`], [`Self::NRES`], and
+    /// [`Self::RESERVED`] are decoded as NaN.
+    #[allow(clippy::cast_possible_truncation)]
+    #[must_use]
+    pub fn to_f32(self) -> f32 {
+        match self.parts() {
+            Some((m, e)) => scale(f64::from(m), e) as f32,
+            None if self == Self::POS_INF => f32::INFINITY,
+            None if self == Self::NEG_INF => f32::NEG_INFINITY,
+            None => f32::NAN,
+        }
+    }
+}
+
+/// Returns `v * 10^e`. Powers of 10 are exact, so division is used for
+/// negative exponents to avoid rounding the multiplier.
+#[inline]
+fn scale(v: f64, e: i8) -> f64 {
+    let p = 10_f64.powi(i32::from(e.unsigned_abs()));
+    if e < 0 {
+        v / p
+    } else {
+        v * p
+    }
+}
+
+impl std::fmt::Debug for Sfloat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::NRES => f.write_str("Sfloat(NRes)"),
+            Self::RESERVED => f.write_str("Sfloat(Reserved)"),
+            _ => f.debug_tuple("Sfloat").field(&self.to_f32()).finish(),
+        }
+    }
+}
+
+impl From<f32> for Sfloat {
+    #[inline(always)]
+    fn from(v: f32) -> Self {
+        Self::new(v)
+    }
+}
+
+impl From<Sfloat> for f32 {
+    #[inline(always)]
+    fn from(v: Sfloat) -> Self {
+        v.to_f32()
+    }
+}
+
+impl From<Sfloat> for u16 {
+    #[inline(always)]
+    fn from(v: Sfloat) -> Self {
+        v.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special() {
+        for (s, v) in [
+            (Sfloat::NAN, f32::NAN),
+            (Sfloat::NRES, f32::NAN),
+            (Sfloat::RESERVED, f32::NAN),
+            (Sfloat::POS_INF, f32::INFINITY),
+            (Sfloat::NEG_INF, f32::NEG_INFINITY),
+        ] {
+            assert!(!s.is_finite());
+            assert_eq!(s.parts(), None);
+            assert_eq!(s.to_f32().to_bits(), v.to_bits());
+        }
+        assert_eq!(Sfloat::new(f32::NAN), Sfloat::NAN);
+        assert_eq!(Sfloat::new(f32::INFINITY), Sfloat::POS_INF);
+        assert_eq!(Sfloat::new(f32::NEG_INFINITY), Sfloat::NEG_INF);
+        assert_eq!(Sfloat::new(2.046e10), Sfloat::POS_INF);
+        assert_eq!(Sfloat::new(-2.046e10), Sfloat::NEG_INF);
+        assert_eq!(format!("{:?}", Sfloat::NRES), "Sfloat(NRes)");
+        assert_eq!(format!("{:?}", Sfloat::new(1.5)), "Sfloat(1.5)");
+    }
+
+    #[test]
+    fn encode() {
+        for (v, raw) in [
+            (0.0, 0x0000),
+            (-0.0, 0x0000),
+            (1.0, 0xD3E8),
+            (-1.0, 0xDC18),
+            (98.5, 0xF3D9),
+            (2045.0, 0x07FD),
+            (-2045.0, 0x0803),
+            (2046.0, 0x10CD),
+            (0.05, 0xC1F4),
+            (1e-9, 0x0000),
+            (2.045e10, 0x77FD),
+        ] {
+            assert_eq!(Sfloat::new(v).raw(), raw, "{v}");
+        }
+    }
+
+    #[allow(clippy::float_cmp)]
+    #[test]
+    fn decode() {
+        for (raw, v) in [
+            (0x0000, 0.0),
+            (0x0001, 1.0),
+            (0x0FFF, -1.0),
+            (0x07FD, 2045.0),
+            (0x0803, -2045.0),
+            (0xF3D9, 98.5),
+            (0xF001, 0.1),
+            (0x1FFF, -10.0),
+            (0x77FF, 2.047e10),
+            (0x8800, -2.048e-5),
+        ] {
+            assert_eq!(Sfloat::from_raw(raw).to_f32(), v, "{raw:#06X}");
+        }
+    }
+
+    /// Verifies the encoding of every decoded value.
+    #[allow(clippy::float_cmp)]
+    #[test]
+    fn exhaustive() {
+        for raw in 0..=u16::MAX {
+            let s = Sfloat::from_raw(raw);
+            let Some((m, e)) = s.parts() else {
+                assert!(matches!(raw, 0x07FE..=0x0802));
+                continue;
+            };
+            let v = s.to_f32();
+            let enc = Sfloat::new(v);
+            if m == 0 {
+                assert_eq!(enc.raw(), 0);
+            } else if m.abs() > Sfloat::MAX_MANTISSA {
+                // Mantissa is only representable at a higher exponent
+                assert!(enc.parts().map_or(e == 7, |(_, ee)| ee > e), "{raw:#06X}");
+            } else {
+                // Value is preserved using the same or a smaller exponent
+                assert_eq!(enc.to_f32(), v, "{raw:#06X}");
+                assert!(enc.parts().unwrap().1 <= e, "{raw:#06X}");
+                assert_eq!(Sfloat::new(enc.to_f32()), enc, "{raw:#06X}");
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Service implementation test helpers.
+
+use std::collections::BTreeMap;
+
+use crate::att::{ErrorCode, Handle, Opcode};
+use crate::gap::Uuid;
+use crate::gatt::{
+    Builder, CharacteristicDef, Db, DbEntry, IoMap, IoResult, ReadReq, ServiceDef, WriteReq,
+};
+use crate::le::{Addr, RawAddr};
+use crate::SyncMutex;
+
+use super::racp::RecordStore;
+
+/// Frozen database with the services under test.
+#[derive(Debug)]
+pub(super) struct TestDb {
+    pub db: Db,
+    pub io: IoMap,
+}
+
+impl TestDb {
+    /// Peer address used by [`Self::write`].
+    pub const PEER: Addr = Addr::Public(RawAddr::from_le_bytes([0; 6]));
+
+    /// Defines services with `f` and freezes the database.
+    pub fn new<T>(f: impl FnOnce(&mut Builder<Db>) -> T) -> Self {
+        let mut db = Db::build();
+        f(&mut db);
+        let (db, io) = db.freeze();
+        Self { db, io }
+    }
+
+    /// Returns the first primary service.
+    pub fn service(&self) -> DbEntry<ServiceDef> {
+        (self.db.primary_services(Handle::MIN, None).next()).expect("no primary service")
+    }
+
+    /// Returns the characteristics of the first primary service.
+    pub fn characteristics(&self) -> impl Iterator<Item = DbEntry<CharacteristicDef>> {
+        self.db.characteristics(self.service().handle_range())
+    }
+
+    /// Reads the value of attribute `hdl` with an `ATT_READ_REQ`.
+    pub fn read(&self, hdl: Handle) -> Result<Vec<u8>, ErrorCode> {
+        let mut req = ReadReq::new(Opcode::ReadReq, 23);
+        req.with(hdl, self.uuid(hdl), 0);
+        self.io.read(&mut req).map(|()| req.buf.as_ref().to_vec())
+    }
+
+    /// Writes `v` to attribute `hdl` with an `ATT_WRITE_REQ` from
+    /// [`Self::PEER`].
+    pub fn write(&self, hdl: Handle, v: &[u8]) -> IoResult {
+        self.write_as(Self::PEER, Opcode::WriteReq, hdl, v)
+    }
+
+    /// Writes `v` to attribute `hdl` with opcode `op` from `peer`.
+    pub fn write_as(&self, peer: Addr, op: Opcode, hdl: Handle, v: &[u8]) -> IoResult {
+        self.io.write(&WriteReq {
+            peer,
+            op,
+            hdl,
+            uuid: self.uuid(hdl),
+            off: 0,
+            val: v,
+        })
+    }
+
+    /// Returns the type of attribute `hdl`.
+    fn uuid(&self, hdl: Handle) -> Uuid {
+        self.db.get(hdl).expect("invalid handle").0
+    }
+}
+
+/// In-memory [`RecordStore`].
+#[derive(Debug)]
+pub(super) struct Records<T>(pub SyncMutex<BTreeMap<u16, T>>);
+
+impl<T> FromIterator<(u16, T)> for Records<T> {
+    fn from_iter<I: IntoIterator<Item = (u16, T)>>(it: I) -> Self {
+        Self(SyncMutex::new(it.into_iter().collect()))
+    }
+}
+
+impl<T: Clone + Send> RecordStore for Records<T> {
+    type Record = T;
+
+    fn seqs(&self) -> Vec<u16> {
+        self.0.lock().keys().copied().collect()
+    }
+
+    fn get(&self, seq: u16) -> Option<Self::Record> {
+        self.0.lock().get(&seq).cloned()
+    }
+
+    fn remove(&self, seq: u16) {
+        self.0.lock().remove(&seq);
+    }
+}
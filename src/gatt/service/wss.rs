@@ -0,0 +1,342 @@
+//! Weight Scale Service ([WSS]).
+//!
+//! This service exposes weight measurements from a weight scale. It also
+//! defines the unit, resolution, and user/time stamp field handling that is
+//! shared with the Body Composition Service.
+//!
+//! [WSS]: https://www.bluetooth.com/specifications/specs/weight-scale-service-1-0/
+
+use std::sync::Arc;
+
+use structbuf::Packer;
+
+use crate::att::{Access, ErrorCode, Handle, Result};
+use crate::gatt::{Builder, Characteristic, Db, Io, IoReq, IoResult, NotifyReq, Prop, Service};
+use crate::SyncMutex;
+
+use super::cts::ExactTime256;
+
+/// Weight scale service instance. Clones refer to the same instance.
+#[derive(Clone, Debug, Default)]
+#[repr(transparent)]
+pub struct WeightScaleService(Arc<SyncMutex<State>>);
+
+impl WeightScaleService {
+    /// Creates a weight scale service.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the supported features.
+    #[inline]
+    #[must_use]
+    pub fn with_features(self, f: Features) -> Self {
+        self.0.lock().features = f;
+        self
+    }
+
+    /// Sets the weight and height measurement resolutions.
+    #[inline]
+    #[must_use]
+    pub fn with_resolution(self, mass: MassResolution, height: HeightResolution) -> Self {
+        self.0.lock().res = (mass, height);
+        self
+    }
+
+    /// Returns the Weight Scale Feature characteristic value
+    /// ([WSS] Section 3.1.1).
+    #[must_use]
+    pub fn features(&self) -> u32 {
+        let s = self.0.lock();
+        let (mass, height) = s.res;
+        s.features.bits() | (mass as u32) << 3 | (height as u32) << 7
+    }
+
+    /// Indicates a weight measurement to the client. Returns `Ok(false)` if
+    /// the client has not enabled indications.
+    pub async fn indicate(&self, m: &WeightMeasurement) -> Result<bool> {
+        let ind = {
+            let s = self.0.lock();
+            (s.ind.as_ref())
+                .filter(|n| !n.is_closed())
+                .map(|n| n.notify(|p| m.pack(p)))
+        };
+        match ind {
+            Some(ind) => ind.await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Defines the service structure. `ac` specifies the security
+    /// requirements for all characteristics.
+    pub fn define(&self, db: &mut Builder<Db>, ac: Access) -> Handle {
+        let feat = self.features().to_le_bytes();
+        let (hdl, ()) = db.primary_service(Service::WeightScale, [], |db| {
+            use Characteristic::*;
+            // Weight Scale Feature ([WSS] Section 3.1)
+            db.ro_characteristic(WeightScaleFeature, ac.read(), feat, |_| {});
+
+            // Weight Measurement ([WSS] Section 3.2)
+            db.characteristic(
+                WeightMeasurement,
+                Prop::INDICATE,
+                ac.read(),
+                Io::with(&self.0, |this, req| this.lock().measurement_io(req)),
+                |db| {
+                    db.cccd(ac.read_write());
+                },
+            );
+        });
+        hdl
+    }
+}
+
+/// Weight scale service state.
+#[derive(Debug, Default)]
+struct State {
+    features: Features,
+    res: (MassResolution, HeightResolution),
+    ind: Option<NotifyReq>,
+}
+
+impl State {
+    /// Handles Weight Measurement characteristic I/O.
+    fn measurement_io(&mut self, req: IoReq) -> IoResult {
+        let IoReq::Notify(n) = req else { return Err(ErrorCode::RequestNotSupported) };
+        if !n.is_indicate() {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        self.ind = Some(n);
+        Ok(())
+    }
+}
+
+bitflags::bitflags! {
+    /// Weight Scale Feature characteristic flags ([WSS] Section 3.1.1). The
+    /// measurement resolutions are set separately.
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct Features: u32 {
+        const TIME_STAMP = 1 << 0;
+        const MULTIPLE_USERS = 1 << 1;
+        const BMI = 1 << 2;
+    }
+}
+
+/// Measurement units.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Units {
+    /// Kilograms and meters.
+    #[default]
+    Si,
+    /// Pounds and inches.
+    Imperial,
+}
+
+/// Weight or mass measurement resolution.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum MassResolution {
+    /// Resolution not specified.
+    #[default]
+    Unspecified = 0,
+    /// 0.5 kg or 1 lb.
+    R500g = 1,
+    /// 0.2 kg or 0.5 lb.
+    R200g = 2,
+    /// 0.1 kg or 0.2 lb.
+    R100g = 3,
+    /// 0.05 kg or 0.1 lb.
+    R50g = 4,
+    /// 0.02 kg or 0.05 lb.
+    R20g = 5,
+    /// 0.01 kg or 0.02 lb.
+    R10g = 6,
+    /// 0.005 kg or 0.01 lb.
+    R5g = 7,
+}
+
+/// Height measurement resolution.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum HeightResolution {
+    /// Resolution not specified.
+    #[default]
+    Unspecified = 0,
+    /// 0.01 m or 1 in.
+    R10mm = 1,
+    /// 0.005 m or 0.5 in.
+    R5mm = 2,
+    /// 0.001 m or 0.1 in.
+    R1mm = 3,
+}
+
+/// Weight Measurement characteristic value ([WSS] Section 3.2.1).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WeightMeasurement {
+    /// Measurement units.
+    pub units: Units,
+    /// Weight in kilograms or pounds. NaN indicates an unsuccessful
+    /// measurement.
+    pub weight: f32,
+    /// Measurement time. The `weekday` and `fractions256` fields are not
+    /// transferred.
+    pub time: Option<ExactTime256>,
+    /// User index. [`UNKNOWN_USER`] indicates an unknown user.
+    pub user: Option<u8>,
+    /// Body mass index in kg/m² and height in meters or inches.
+    pub bmi_height: Option<(f32, f32)>,
+}
+
+impl WeightMeasurement {
+    const IMPERIAL: u8 = 1 << 0;
+    const TIME_STAMP: u8 = 1 << 1;
+    const USER: u8 = 1 << 2;
+    const BMI_HEIGHT: u8 = 1 << 3;
+
+    /// Packs the measurement.
+    pub fn pack(&self, p: &mut Packer) {
+        let flag = |present: bool, f: u8| if present { f } else { 0 };
+        let f = flag(self.units == Units::Imperial, Self::IMPERIAL)
+            | flag(self.time.is_some(), Self::TIME_STAMP)
+            | flag(self.user.is_some(), Self::USER)
+            | flag(self.bmi_height.is_some(), Self::BMI_HEIGHT);
+        p.u8(f).u16(mass(self.units, self.weight));
+        pack_time_user(p, self.time, self.user);
+        if let Some((bmi, h)) = self.bmi_height {
+            p.u16(fixed(bmi, 0.1)).u16(height(self.units, h));
+        }
+    }
+}
+
+/// User index of an unknown user.
+pub const UNKNOWN_USER: u8 = 0xFF;
+
+/// Encodes a weight or mass in kilograms (0.005 kg resolution) or pounds
+/// (0.01 lb resolution).
+#[inline]
+pub(super) fn mass(u: Units, v: f32) -> u16 {
+    fixed(v, if u == Units::Si { 0.005 } else { 0.01 })
+}
+
+/// Encodes a height in meters (0.001 m resolution) or inches (0.1 in
+/// resolution).
+#[inline]
+pub(super) fn height(u: Units, v: f32) -> u16 {
+    fixed(v, if u == Units::Si { 0.001 } else { 0.1 })
+}
+
+/// Encodes a non-negative fixed-point value with resolution `res`. NaN is
+/// encoded as `0xFFFF` (value not known or measurement unsuccessful).
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(super) fn fixed(v: f32, res: f64) -> u16 {
+    if v.is_nan() {
+        return u16::MAX;
+    }
+    (f64::from(v) / res)
+        .round()
+        .clamp(0.0, f64::from(u16::MAX - 1)) as u16
+}
+
+/// Packs the optional time stamp and user ID fields.
+pub(super) fn pack_time_user(p: &mut Packer, time: Option<ExactTime256>, user: Option<u8>) {
+    if let Some(t) = time {
+        p.put(&t.to_bytes()[..7]);
+    }
+    if let Some(u) = user {
+        p.u8(u);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use structbuf::{Pack, StructBuf};
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::gatt::service::test::TestDb;
+
+    use super::*;
+
+    #[test]
+    fn pack() {
+        let pack = |m: WeightMeasurement| {
+            let mut b = StructBuf::new(32);
+            m.pack(&mut b.append());
+            b.as_ref().to_vec()
+        };
+        let m = WeightMeasurement {
+            weight: 70.0,
+            ..WeightMeasurement::default()
+        };
+        assert_eq!(pack(m), [0x00, 0xB0, 0x36]);
+        let m = WeightMeasurement {
+            units: Units::Imperial,
+            weight: 154.32,
+            time: Some(ExactTime256::from_unix(1_680_000_000, 0)),
+            user: Some(UNKNOWN_USER),
+            bmi_height: Some((22.4, 69.5)),
+        };
+        assert_eq!(
+            pack(m),
+            [
+                0x0F, 0x48, 0x3C, 0xE7, 0x07, 0x03, 0x1C, 0x0A, 0x28, 0x00, 0xFF, 0xE0, 0x00, 0xB7,
+                0x02
+            ]
+        );
+        let m = WeightMeasurement {
+            weight: f32::NAN,
+            ..WeightMeasurement::default()
+        };
+        assert_eq!(pack(m), [0x00, 0xFF, 0xFF]);
+        assert_eq!(fixed(-1.0, 0.1), 0);
+        assert_eq!(fixed(1e9, 0.1), 0xFFFE);
+    }
+
+    #[tokio::test]
+    async fn service() {
+        let wss = WeightScaleService::new()
+            .with_features(Features::TIME_STAMP | Features::BMI)
+            .with_resolution(MassResolution::R50g, HeightResolution::R1mm);
+        assert_eq!(wss.features(), 0x05 | 4 << 3 | 3 << 7);
+        let t = TestDb::new(|db| wss.define(db, Access::NONE));
+        let mut chars = t.characteristics();
+        let (feat, meas) = (chars.next().unwrap(), chars.next().unwrap());
+        assert_eq!(t.db.get(feat.value_handle()).unwrap().1, [0xA5, 0x01, 0, 0]);
+        assert_eq!(meas.properties(), Prop::INDICATE);
+
+        let m = WeightMeasurement::default();
+        assert!(!wss.indicate(&m).await.unwrap());
+        let (tx, mut rx) = mpsc::channel(1);
+        let sub = |ind| {
+            t.io.notify(NotifyReq {
+                hdl: meas.value_handle(),
+                uuid: meas.uuid(),
+                mtu: 23,
+                ind,
+                tx: tx.clone(),
+                ct: CancellationToken::new(),
+            })
+        };
+        assert_eq!(sub(false), Err(ErrorCode::CccdImproperlyConfigured));
+        sub(true).unwrap();
+        let confirm = async {
+            let v = timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(v.as_ref(), [0x00, 0x00, 0x00]);
+            v.result(Ok(()));
+        };
+        let (r, ()) = tokio::join!(wss.indicate(&m), confirm);
+        assert!(r.unwrap());
+    }
+}
@@ -1,28 +1,41 @@
 use std::collections::BTreeSet;
+use std::mem;
 
 use futures_core::FusedFuture;
 use pin_project::pin_project;
+use tracing::Instrument;
 
-use crate::le::TxPower;
+use crate::le::{RawAddr, TxPower};
 
 use super::*;
 
-/// Advertisement manager.
+/// Advertisement manager. Each advertising set is owned by the [`AdvSet`]
+/// returned from [`Advertiser::create`], which must be passed back to the
+/// advertiser that created it to be modified or removed.
 #[derive(Debug)]
 pub struct Advertiser {
     host: Host,
-    handles: BTreeSet<AdvHandle>,
+    id: u64,
     max_data_len: usize,
+    random_addr: BTreeMap<AdvHandle, RawAddr>,
 }
 
 impl Advertiser {
-    /// Creates a new advertisement manager.
+    /// Creates a new advertisement manager. Advertising sets left behind by
+    /// dropped advertisers are cleared if no other advertiser is active.
     pub async fn new(host: &Host) -> Result<Self> {
-        host.le_clear_advertising_sets().await?;
+        let (id, clear) = {
+            let mut a = host.adv.lock();
+            (a.new_owner(), a.used.is_empty())
+        };
+        if clear {
+            host.le_clear_advertising_sets().await?;
+        }
         Ok(Self {
             host: host.clone(),
-            handles: BTreeSet::new(),
+            id,
             max_data_len: host.le_read_maximum_advertising_data_length().await?,
+            random_addr: BTreeMap::new(),
         })
     }
 
@@ -33,58 +46,111 @@ impl Advertiser {
         self.max_data_len
     }
 
-    /// Creates a new advertising handle with the specified parameters.
-    pub async fn create(&mut self, p: AdvParams) -> Result<(AdvHandle, TxPower)> {
-        // TODO: Allow using a random address.
+    /// Creates a new advertising set with the specified parameters. If the
+    /// own address type is random, the set uses the static random address set
+    /// by [`Host::set_static_address`], if any.
+    pub async fn create(&mut self, p: AdvParams) -> Result<(AdvSet, TxPower)> {
         // TODO: Handle legacy advertisements?
-        let h = self.alloc_handle()?;
-        (self.host.le_set_extended_advertising_parameters(h, p).await).map(|p| {
-            self.handles.insert(h);
-            (h, p)
-        })
+        let h = self.alloc_handle().await?;
+        let r = (self.host.le_set_extended_advertising_parameters(h, p))
+            .instrument(adv_span(h))
+            .await;
+        let (s, tx_power) = match r {
+            Ok(tx_power) => {
+                if p.props.contains(AdvProp::CONNECTABLE) {
+                    self.host.adv.lock().connectable.insert(h);
+                }
+                (AdvSet { h, owner: self.id }, tx_power)
+            }
+            Err(e) => {
+                self.host.adv.lock().free(h, self.id);
+                return Err(e);
+            }
+        };
+        if let (OwnAddrType::Random, Some(a)) = (p.addr_type, self.host.static_address()) {
+            if let Err(e) = self.set_random_address(&s, a).await {
+                let _ = self.remove(s).await;
+                return Err(e);
+            }
+        }
+        Ok((s, tx_power))
+    }
+
+    /// Sets the random device address used by an advertising set with a
+    /// random own address type, such as a static random address from
+    /// [`RawAddr::load_or_gen_static_random`].
+    pub async fn set_random_address(&mut self, s: &AdvSet, a: RawAddr) -> Result<()> {
+        let h = self.handle(s);
+        (self.host.le_set_advertising_set_random_address(h, a))
+            .instrument(adv_span(h))
+            .await?;
+        self.random_addr.insert(h, a);
+        Ok(())
     }
 
-    /// Sets advertising data.
-    pub async fn set_data<V>(&mut self, h: AdvHandle, d: V) -> Result<()>
+    /// Sets advertising data. The data is split into fragments on AD
+    /// structure boundaries where possible.
+    #[inline]
+    pub async fn set_data<V>(&mut self, s: &AdvSet, d: V) -> Result<()>
     where
         V: AsRef<[u8]> + Send + Sync,
     {
+        self.set_data_structs(s, gap::ad_structs(d.as_ref())).await
+    }
+
+    /// Sets advertising data from an iterator of complete AD structures, such
+    /// as [`gap::ResponseDataMut::structs`]. Each command fragment is packed
+    /// directly from the structures, which are split only if they don't fit
+    /// into one fragment. If a fragment other than the first one is rejected,
+    /// the advertising data is cleared rather than left partially written.
+    pub async fn set_data_structs<'a>(
+        &mut self,
+        s: &AdvSet,
+        ad: impl IntoIterator<Item = &'a [u8]> + Send,
+    ) -> Result<()> {
         // [Vol 4] Part E, Section 7.8.54
-        for (op, chunk) in Self::op_chunks(d.as_ref(), 251) {
-            self.host
-                .le_set_extended_advertising_data(h, op, true, chunk)
-                .await?;
-        }
-        Ok(())
+        let (host, h) = (&self.host, self.handle(s));
+        let frags = fragments(ad, 251);
+        send_fragments(frags, |op, v| async move {
+            (host.le_set_extended_advertising_data(h, op, true, &v)).await
+        })
+        .instrument(adv_span(h))
+        .await
     }
 
     /// Sets scan response data.
-    pub async fn set_scan_response<V>(&mut self, h: AdvHandle, d: V) -> Result<()>
+    pub async fn set_scan_response<V>(&mut self, s: &AdvSet, d: V) -> Result<()>
     where
         V: AsRef<[u8]> + Send + Sync,
     {
         // [Vol 4] Part E, Section 7.8.55
-        for (op, chunk) in Self::op_chunks(d.as_ref(), 31) {
-            self.host
-                .le_set_extended_scan_response_data(h, op, true, chunk)
-                .await?;
-        }
-        Ok(())
+        let (host, h) = (&self.host, self.handle(s));
+        let frags = fragments(gap::ad_structs(d.as_ref()), 31);
+        send_fragments(frags, |op, v| async move {
+            (host.le_set_extended_scan_response_data(h, op, true, &v)).await
+        })
+        .instrument(adv_span(h))
+        .await
     }
 
     /// Enable advertising.
     pub async fn enable(&mut self, p: impl Into<AdvEnableParams> + Send) -> Result<AdvFuture> {
         let p = p.into();
         let ctl = self.host.events();
-        (self.host.le_set_extended_advertising_enable(true, &[p])).await?;
-        Ok(AdvFuture::new(p.handle, ctl, self.host.info.addr))
+        (self.host.le_set_extended_advertising_enable(true, &[p]))
+            .instrument(adv_span(p.handle))
+            .await?;
+        let local_addr = self.random_addr.get(&p.handle).copied();
+        let local_addr = local_addr.map_or(self.host.info.addr, Addr::Random);
+        Ok(AdvFuture::new(p.handle, ctl, local_addr))
     }
 
     // Disable advertising.
-    pub async fn disable(&mut self, h: AdvHandle) -> Result<()> {
-        self.host
-            .le_set_extended_advertising_enable(false, &[h.into()])
-            .await
+    pub async fn disable(&mut self, s: &AdvSet) -> Result<()> {
+        let h = self.handle(s);
+        let p = [h.into()];
+        let r = (self.host).le_set_extended_advertising_enable(false, &p);
+        r.instrument(adv_span(h)).await
     }
 
     // Disable advertising.
@@ -92,44 +158,219 @@ impl Advertiser {
         (self.host.le_set_extended_advertising_enable(false, &[])).await
     }
 
-    /// Removes an advertising handle.
-    pub async fn remove(&mut self, h: AdvHandle) -> Result<()> {
-        self.host.le_remove_advertising_set(h).await
+    /// Removes an advertising set. The handle is released even if the
+    /// command fails, in which case the set is reclaimed when its handle is
+    /// reused or all sets are cleared.
+    pub async fn remove(&mut self, s: AdvSet) -> Result<()> {
+        let h = self.handle(&s);
+        let r = (self.host.le_remove_advertising_set(h))
+            .instrument(adv_span(h))
+            .await;
+        self.host.adv.lock().free(h, self.id);
+        self.random_addr.remove(&h);
+        match r {
+            Err(e) if e.status() == Some(Status::UnknownAdvertisingIdentifier) => Ok(()),
+            r => r,
+        }
+    }
+
+    /// Removes all advertising sets created by this advertiser, invalidating
+    /// all of its [`AdvSet`]s.
+    pub async fn remove_all(self) -> Result<()> {
+        let (hs, all) = {
+            let a = self.host.adv.lock();
+            let hs = a.owned(self.id);
+            let all = hs.len() == a.used.len();
+            (hs, all)
+        };
+        if all {
+            return self.host.le_clear_advertising_sets().await;
+        }
+        for h in hs {
+            (self.host.le_remove_advertising_set(h))
+                .instrument(adv_span(h))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the handle of advertising set `s`.
+    #[inline]
+    fn handle(&self, s: &AdvSet) -> AdvHandle {
+        assert_eq!(s.owner, self.id, "{} used with another advertiser", s.h);
+        s.h
+    }
+
+    /// Allocates an unused advertising handle. The number of supported
+    /// advertising sets may change while the controller is in use, so it is
+    /// read again whenever the allocation fails.
+    async fn alloc_handle(&self) -> Result<AdvHandle> {
+        if let Some(h) = self.host.adv.lock().alloc(self.id) {
+            return Ok(h);
+        }
+        // [Vol 4] Part E, Section 7.8.58
+        let n = (self.host.le_read_number_of_supported_advertising_sets()).await?;
+        let mut a = self.host.adv.lock();
+        a.max_sets = Some(usize::from(n));
+        a.alloc(self.id).ok_or_else(|| Status::LimitReached.into())
+    }
+}
+
+impl Drop for Advertiser {
+    /// Releases all handles owned by the advertiser. Any advertising sets
+    /// that were not removed remain configured in the controller until their
+    /// handles are reused or all sets are cleared.
+    fn drop(&mut self) {
+        self.host.adv.lock().free_all(self.id);
+    }
+}
+
+/// Advertising set owned by an [`Advertiser`]. The set remains allocated
+/// until it is passed to [`Advertiser::remove`] or the advertiser is removed
+/// or dropped.
+#[derive(Debug, Eq, PartialEq)]
+pub struct AdvSet {
+    h: AdvHandle,
+    owner: u64,
+}
+
+impl AdvSet {
+    /// Returns the advertising handle.
+    #[inline(always)]
+    #[must_use]
+    pub const fn handle(&self) -> AdvHandle {
+        self.h
+    }
+}
+
+impl From<&AdvSet> for AdvEnableParams {
+    #[inline]
+    fn from(s: &AdvSet) -> Self {
+        Self::from(s.h)
+    }
+}
+
+/// Advertising handle allocator shared by all advertisers of a [`Host`].
+#[derive(Debug, Default)]
+pub(super) struct AdvHandleAlloc {
+    /// Allocated handles and the IDs of the advertisers that own them.
+    used: BTreeMap<AdvHandle, u64>,
+    /// Allocated handles of connectable advertising sets.
+    connectable: BTreeSet<AdvHandle>,
+    /// Number of advertising sets supported by the controller, if known.
+    max_sets: Option<usize>,
+    /// Last advertiser ID.
+    last_owner: u64,
+}
+
+impl AdvHandleAlloc {
+    /// Returns a new advertiser ID.
+    #[inline]
+    fn new_owner(&mut self) -> u64 {
+        self.last_owner += 1;
+        self.last_owner
+    }
+
+    /// Allocates the lowest unused handle for advertiser `owner`. Returns
+    /// [`None`] if the supported number of sets is unknown or exhausted.
+    fn alloc(&mut self, owner: u64) -> Option<AdvHandle> {
+        if self.used.len() >= self.max_sets? {
+            return None;
+        }
+        let h = (AdvHandle::MIN..=AdvHandle::MAX)
+            .filter_map(AdvHandle::new)
+            .find(|h| !self.used.contains_key(h))?;
+        self.used.insert(h, owner);
+        Some(h)
+    }
+
+    /// Releases handle `h` owned by advertiser `owner`.
+    #[inline]
+    fn free(&mut self, h: AdvHandle, owner: u64) {
+        let prev = self.used.remove(&h);
+        self.connectable.remove(&h);
+        debug_assert_eq!(prev, Some(owner), "{h} not owned by advertiser {owner}");
+    }
+
+    /// Releases all handles owned by advertiser `owner`.
+    #[inline]
+    fn free_all(&mut self, owner: u64) {
+        self.used.retain(|_, &mut o| o != owner);
+        let used = &self.used;
+        self.connectable.retain(|h| used.contains_key(h));
+    }
+
+    /// Returns all handles owned by advertiser `owner`.
+    fn owned(&self, owner: u64) -> Vec<AdvHandle> {
+        (self.used.iter())
+            .filter_map(|(&h, &o)| (o == owner).then_some(h))
+            .collect()
     }
 
-    /// Removes all advertising handles.
-    pub async fn remove_all(&mut self) -> Result<()> {
-        self.host.le_clear_advertising_sets().await
+    /// Returns the handles of all connectable advertising sets.
+    #[inline]
+    pub(super) fn connectable(&self) -> Vec<AdvHandle> {
+        self.connectable.iter().copied().collect()
     }
+}
 
-    /// Allocates an unused advertising handle.
-    fn alloc_handle(&self) -> Result<AdvHandle> {
-        for i in AdvHandle::MIN..=AdvHandle::MAX {
-            // SAFETY: `i` is always valid
-            let h = unsafe { AdvHandle::new(i).unwrap_unchecked() };
-            if !self.handles.contains(&h) {
-                return Ok(h);
+/// Splits AD structures into command fragments of at most `max` bytes without
+/// copying the data. A structure is split only if it doesn't fit into an empty
+/// fragment. Returns a single empty fragment if there is no data.
+fn fragments<'a>(ad: impl IntoIterator<Item = &'a [u8]>, max: usize) -> Vec<Vec<&'a [u8]>> {
+    let (mut frags, mut cur, mut n) = (Vec::new(), Vec::new(), 0);
+    for mut s in ad {
+        if n > 0 && n + s.len() > max && s.len() <= max {
+            frags.push(mem::take(&mut cur));
+            n = 0;
+        }
+        while !s.is_empty() {
+            if n == max {
+                frags.push(mem::take(&mut cur));
+                n = 0;
             }
+            let (v, tail) = s.split_at(s.len().min(max - n));
+            cur.push(v);
+            n += v.len();
+            s = tail;
         }
-        Err(Status::LimitReached.into())
     }
+    frags.push(cur);
+    frags
+}
 
-    /// Returns an iterator over chunks in `d`, specifying the appropriate
-    /// data setting operation for each chunk.
-    fn op_chunks(d: &[u8], chunk_size: usize) -> impl ExactSizeIterator<Item = (AdvDataOp, &[u8])> {
-        let mut chunks = d.chunks(chunk_size);
-        if chunks.len() == 0 {
-            // Ensure that there is always at least one chunk
-            chunks = [0].chunks(1);
+/// Sends data fragments using `f` with the appropriate data operation for
+/// each one. If a fragment after the first one fails, the data is cleared by
+/// sending an empty complete fragment.
+async fn send_fragments<'a, F, R>(frags: Vec<Vec<&'a [u8]>>, mut f: F) -> Result<()>
+where
+    F: FnMut(AdvDataOp, Vec<&'a [u8]>) -> R + Send,
+    R: Future<Output = Result<()>> + Send,
+{
+    let last = frags.len() - 1;
+    for (i, frag) in frags.into_iter().enumerate() {
+        let op = match i {
+            _ if last == 0 => AdvDataOp::Complete,
+            0 => AdvDataOp::First,
+            i if i == last => AdvDataOp::Last,
+            _ => AdvDataOp::Cont,
+        };
+        let Err(e) = f(op, frag).await else { continue };
+        if i > 0 {
+            warn!("Failed to set data fragment {i} of {}: {e}", last + 1);
+            if let Err(e) = f(AdvDataOp::Complete, Vec::new()).await {
+                warn!("Failed to clear partial data: {e}");
+            }
         }
-        let last = chunks.len() - 1;
-        chunks.enumerate().map(move |(i, c)| match i {
-            0 if last == 0 => (AdvDataOp::Complete, d),
-            0 => (AdvDataOp::First, c),
-            i if last == i => (AdvDataOp::Last, c),
-            _ => (AdvDataOp::Cont, c),
-        })
+        return Err(e);
     }
+    Ok(())
+}
+
+/// Returns a new span for operations on advertising set `h`.
+#[inline]
+fn adv_span(h: AdvHandle) -> tracing::Span {
+    tracing::debug_span!("adv", handle = u8::from(h))
 }
 
 /// Advertising set completion result.
@@ -232,6 +473,9 @@ impl Future for AdvFuture {
         let term = match evt.code() {
             LeConnectionComplete | LeEnhancedConnectionComplete => {
                 let conn: super::LeConnectionComplete = evt.get();
+                if !conn.status.is_ok() || conn.role == Role::Central {
+                    return Poll::Pending; // Not created by advertising
+                }
                 if let Some(term) = this.term.as_ref() {
                     if conn.handle == term.conn_handle.unwrap() {
                         return this.ready_conn(conn, term.clone());
@@ -274,3 +518,84 @@ impl FusedFuture for AdvFuture {
         self.hdl.is_none()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_alloc() {
+        let mut a = AdvHandleAlloc::default();
+        let (x, y) = (a.new_owner(), a.new_owner());
+        assert_eq!(a.alloc(x), None);
+        a.max_sets = Some(2);
+        let h0 = a.alloc(x).unwrap();
+        let h1 = a.alloc(y).unwrap();
+        assert_eq!((u8::from(h0), u8::from(h1)), (0, 1));
+        assert_eq!(a.alloc(x), None);
+        a.connectable.insert(h0);
+        a.free(h0, x);
+        assert!(a.connectable().is_empty());
+        assert_eq!(a.alloc(y), Some(h0));
+        assert_eq!(a.owned(y), [h0, h1]);
+        assert!(a.owned(x).is_empty());
+        a.connectable.insert(h1);
+        a.free_all(y);
+        assert!(a.connectable().is_empty());
+    }
+
+    /// Returns an AD structure of `n` bytes filled with `v`.
+    fn ad(n: u8, v: u8) -> Vec<u8> {
+        let mut s = vec![v; usize::from(n)];
+        s[0] = n - 1;
+        s
+    }
+
+    #[test]
+    fn data_fragments() {
+        let d: Vec<u8> = (1..=6).flat_map(|i| ad(100, i)).collect();
+        assert_eq!(d.len(), 600);
+        let frags = fragments(gap::ad_structs(&d), 251);
+        assert_eq!(frags.len(), 3);
+        for (f, v) in frags.iter().zip(d.chunks(200)) {
+            assert_eq!(f.concat(), v);
+        }
+
+        // Oversized structures are split mid-structure
+        let d = [ad(10, 1), vec![0xFF; 256]].concat();
+        let frags = fragments(gap::ad_structs(&d), 251);
+        let n: Vec<_> = frags.iter().map(|f| f.concat().len()).collect();
+        assert_eq!(n, [251, 15]);
+        assert_eq!(fragments(gap::ad_structs(&[]), 251), [Vec::<&[u8]>::new()]);
+    }
+
+    #[tokio::test]
+    async fn send_data() {
+        let d: Vec<u8> = (1..=6).flat_map(|i| ad(100, i)).collect();
+        let mut sent = Vec::new();
+        let r = send_fragments(fragments(gap::ad_structs(&d), 251), |op, v| {
+            sent.push((op, v.concat()));
+            std::future::ready(Ok(()))
+        });
+        r.await.unwrap();
+        let ops: Vec<_> = sent.iter().map(|&(op, _)| op).collect();
+        assert_eq!(ops, [AdvDataOp::First, AdvDataOp::Cont, AdvDataOp::Last]);
+        let data: Vec<u8> = sent.into_iter().flat_map(|(_, v)| v).collect();
+        assert_eq!(data, d);
+
+        // A failure clears partially written data
+        let mut sent = Vec::new();
+        let r = send_fragments(fragments(gap::ad_structs(&d), 251), |op, v| {
+            sent.push((op, v.concat()));
+            std::future::ready(if op == AdvDataOp::Cont {
+                Err(Status::MemoryCapacityExceeded.into())
+            } else {
+                Ok(())
+            })
+        });
+        assert!(r.await.is_err());
+        let last = sent.last().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!((last.0, last.1.len()), (AdvDataOp::Complete, 0));
+    }
+}
@@ -20,15 +20,31 @@ pub(super) struct Command {
 
 impl Command {
     /// Creates a new HCI command.
+    #[inline]
     #[must_use]
     pub fn new(host: &Host, opcode: Opcode) -> Self {
+        Self::with_raw_opcode(host, opcode, u16::from(opcode))
+    }
+
+    /// Creates a new vendor-specific HCI command
+    /// ([Vol 4] Part E, Section 5.4.1).
+    #[inline]
+    #[must_use]
+    pub fn vendor(host: &Host, ocf: u16) -> Self {
+        Self::with_raw_opcode(host, Opcode::Vendor, Opcode::vendor(ocf))
+    }
+
+    /// Creates a new HCI command with a `raw` opcode that is routed as
+    /// `opcode`.
+    #[must_use]
+    fn with_raw_opcode(host: &Host, opcode: Opcode, raw: u16) -> Self {
         let mut cmd = Self {
             router: Arc::clone(&host.router),
             opcode,
             xfer: host.new_cmd(),
             host_cmd: Arc::clone(&host.cmd),
         };
-        cmd.append().u16(opcode).u8(0); // Final length is set in exec()
+        cmd.append().u16(raw).u8(0); // Final length is set in exec()
         cmd
     }
 
@@ -36,10 +52,7 @@ impl Command {
     /// check the completion status to determine whether the command was
     /// successful.
     pub async fn exec(mut self) -> Result<Event> {
-        let xfer = self.xfer.as_mut();
-        let n = u8::try_from(xfer.as_ref().len() - CMD_HDR).expect("command too long");
-        xfer.at(CMD_HDR - 1).u8(n);
-        trace!("Command: {:02X?}", xfer.as_ref());
+        self.finish();
         let cmd_guard = self.router.reserve(self.opcode).await;
         *self.host_cmd.lock() = Some(self.xfer.exec().await?);
         let mut events = cmd_guard.submitted();
@@ -61,9 +74,28 @@ impl Command {
                 return Ok(evt);
             } else if let Err(e) = evt.cmd_ok() {
                 return Err(e); // Failed CommandStatus
+            } else if self.opcode.is_status_only() {
+                return Ok(evt);
             }
         }
     }
+
+    /// Submits the command without waiting for its completion. This bypasses
+    /// command flow control, so it should only be used when the event loop
+    /// may not be running.
+    pub async fn submit(mut self) -> Result<()> {
+        self.finish();
+        *self.host_cmd.lock() = Some(self.xfer.exec().await?);
+        Ok(())
+    }
+
+    /// Sets the final parameter length.
+    fn finish(&mut self) {
+        let xfer = self.xfer.as_mut();
+        let n = u8::try_from(xfer.as_ref().len() - CMD_HDR).expect("command too long");
+        xfer.at(CMD_HDR - 1).u8(n);
+        trace!("Command: {:02X?}", xfer.as_ref());
+    }
 }
 
 impl Pack for Command {
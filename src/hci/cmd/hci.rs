@@ -2,6 +2,37 @@ use structbuf::Unpacker;
 
 use crate::hci::*;
 
+/// Link Control commands ([Vol 4] Part E, Section 7.1).
+impl Host {
+    /// Terminates an existing connection ([Vol 4] Part E, Section 7.1.6).
+    /// Completion is indicated by a `DisconnectionComplete` event, which can be
+    /// awaited via [`Host::disconnected`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reason` is not one of the allowed disconnect reasons.
+    pub async fn disconnect(&self, h: ConnHandle, reason: Status) -> Result<()> {
+        use Status::*;
+        assert!(
+            matches!(
+                reason,
+                AuthenticationFailure
+                    | RemoteUserTerminatedConnection
+                    | RemoteDeviceTerminatedConnectionDueToLowResources
+                    | RemoteDeviceTerminatedConnectionDueToPowerOff
+                    | UnsupportedRemoteFeature
+                    | PairingWithUnitKeyNotSupported
+                    | UnacceptableConnectionParameters
+            ),
+            "invalid disconnect reason: {reason}"
+        );
+        let r = self.exec_params(Opcode::Disconnect, |cmd| {
+            cmd.u16(h).u8(reason as u8);
+        });
+        r.await?.cmd_ok()
+    }
+}
+
 /// HCI Control and Baseband commands ([Vol 4] Part E, Section 7.3).
 impl Host {
     /// Configures which events can be generated by the controller
@@ -19,6 +50,23 @@ impl Host {
         self.exec(Opcode::Reset).await?.ok()
     }
 
+    /// Returns the current or maximum transmit power level of the specified
+    /// connection in dBm ([Vol 4] Part E, Section 7.3.35).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn read_transmit_power_level(&self, h: ConnHandle, max: bool) -> Result<i8> {
+        let r = self.exec_params(Opcode::ReadTransmitPowerLevel, |cmd| {
+            cmd.u16(h).u8(u8::from(max));
+        });
+        r.await?.map_ok(|_, p| {
+            assert_eq!(ConnHandle::new(p.u16()), Some(h));
+            p.i8()
+        })
+    }
+
     /// Turns flow control on or off for data sent from the controller to the
     /// host ([Vol 4] Part E, Section 7.3.38).
     pub async fn set_controller_to_host_flow_control(&self, enable: bool) -> Result<()> {
@@ -48,6 +96,56 @@ impl Host {
         });
         r.await?.ok()
     }
+
+    /// Returns the maximum time between packets authenticated by a MIC on the
+    /// specified connection ([Vol 4] Part E, Section 7.3.93).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn read_authenticated_payload_timeout(&self, h: ConnHandle) -> Result<Duration> {
+        let r = self.exec_params(Opcode::ReadAuthenticatedPayloadTimeout, |cmd| {
+            cmd.u16(h);
+        });
+        r.await?.map_ok(|_, p| {
+            assert_eq!(ConnHandle::new(p.u16()), Some(h));
+            Duration::from_millis(u64::from(p.u16()) * 10)
+        })
+    }
+
+    /// Sets the maximum time between packets authenticated by a MIC on the
+    /// specified connection ([Vol 4] Part E, Section 7.3.94). The timeout must
+    /// not be less than the connection interval multiplied by the peripheral
+    /// latency plus one, otherwise [`Status::InvalidCommandParameters`] is
+    /// returned without sending the command. The controller uses the LE Ping
+    /// procedure to prevent the timeout from expiring and generates an
+    /// `HCI_Authenticated_Payload_Timeout_Expired` event if it does, which
+    /// [`Keepalive`] reports to the application.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is not in the range 10 ms to 655.35 s or if
+    /// there is a mismatch with the returned connection handle parameter.
+    pub async fn write_authenticated_payload_timeout(
+        &self,
+        h: ConnHandle,
+        timeout: Duration,
+    ) -> Result<()> {
+        let ticks = ticks_10ms(timeout).filter(|&t| t != 0);
+        let ticks = ticks.expect("invalid authenticated payload timeout");
+        if let Some(cn) = self.conn(h) {
+            let p = cn.borrow().params;
+            if timeout < p.interval * (u32::from(p.latency) + 1) {
+                return Err(Status::InvalidCommandParameters.into());
+            }
+        }
+        let r = self.exec_params(Opcode::WriteAuthenticatedPayloadTimeout, |cmd| {
+            cmd.u16(h).u16(ticks);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
 }
 
 /// Informational parameters commands ([Vol 4] Part E, Section 7.4).
@@ -86,6 +184,27 @@ impl Host {
     }
 }
 
+/// Status parameters commands ([Vol 4] Part E, Section 7.5).
+impl Host {
+    /// Returns the received signal strength of the specified connection in
+    /// dBm ([Vol 4] Part E, Section 7.5.4). Returns [`None`] if the RSSI is
+    /// not available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn read_rssi(&self, h: ConnHandle) -> Result<Option<i8>> {
+        let r = self.exec_params(Opcode::ReadRssi, |cmd| {
+            cmd.u16(h);
+        });
+        r.await?.map_ok(|_, p| {
+            assert_eq!(ConnHandle::new(p.u16()), Some(h));
+            Some(p.i8()).filter(|&rssi| rssi != 0x7F)
+        })
+    }
+}
+
 /// `HCI_Set_Event_Mask`, `HCI_Set_Event_Mask_Page_2`, and
 /// `HCI_LE_Set_Event_Mask` command parameters
 /// ([Vol 4] Part E, Section 7.3.1, 7.3.69, 7.8.1).
@@ -156,15 +275,15 @@ pub struct BufferSize {
     pub acl_num_pkts: u16,
 }
 
-impl FromEvent for BufferSize {
+impl TryFromEvent for BufferSize {
     #[inline]
-    fn unpack(_: &Event, p: &mut Unpacker) -> Self {
+    fn unpack(_: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
         let (acl_data_len, _sco_data_len) = (p.u16(), p.u8());
         let (acl_num_pkts, _sco_num_pkts) = (p.u16(), p.u16());
-        Self {
+        Ok(Self {
             acl_data_len,
             acl_num_pkts,
-        }
+        })
     }
 }
 
@@ -179,16 +298,16 @@ pub struct LocalVersion {
     pub lmp_subversion: u16,
 }
 
-impl FromEvent for LocalVersion {
+impl TryFromEvent for LocalVersion {
     #[inline]
-    fn unpack(_: &Event, p: &mut Unpacker) -> Self {
-        Self {
+    fn unpack(_: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
             hci_version: CoreVersion::from(p.u8()),
             hci_subversion: p.u16(),
             lmp_version: CoreVersion::from(p.u8()),
             company_id: CompanyId(p.u16()),
             lmp_subversion: p.u16(),
-        }
+        })
     }
 }
 
@@ -1,6 +1,8 @@
-use structbuf::Unpacker;
+use std::fmt::{Debug, Formatter};
 
-use burble_crypto::LTK;
+use structbuf::{Packer, Unpacker};
+
+use burble_crypto::{IRK, LTK};
 
 use crate::hci::*;
 use crate::le::{Addr, RawAddr, TxPower};
@@ -40,6 +42,146 @@ impl Host {
         r.await?.ok()
     }
 
+    /// Cancels an `HCI_LE_Extended_Create_Connection` command that is still in
+    /// progress ([Vol 4] Part E, Section 7.8.13). The controller generates an
+    /// `HCI_LE_Enhanced_Connection_Complete` event with
+    /// [`Status::UnknownConnectionIdentifier`] if the cancellation was
+    /// successful.
+    pub async fn le_create_connection_cancel(&self) -> Result<()> {
+        self.exec(Opcode::LeCreateConnectionCancel).await?.ok()
+    }
+
+    /// Returns the total number of Filter Accept List entries that can be
+    /// stored in the controller ([Vol 4] Part E, Section 7.8.14).
+    pub async fn le_read_filter_accept_list_size(&self) -> Result<usize> {
+        let r = self.exec(Opcode::LeReadFilterAcceptListSize);
+        r.await?.map_ok(|_, p| usize::from(p.u8()))
+    }
+
+    /// Removes all devices from the Filter Accept List
+    /// ([Vol 4] Part E, Section 7.8.15).
+    pub async fn le_clear_filter_accept_list(&self) -> Result<()> {
+        self.exec(Opcode::LeClearFilterAcceptList).await?.ok()
+    }
+
+    /// Adds a device to the Filter Accept List
+    /// ([Vol 4] Part E, Section 7.8.16).
+    pub async fn le_add_device_to_filter_accept_list(
+        &self,
+        e: impl Into<AcceptListEntry> + Send,
+    ) -> Result<()> {
+        let e = e.into();
+        let r = self.exec_params(Opcode::LeAddDeviceToFilterAcceptList, |cmd| {
+            e.pack(cmd);
+        });
+        r.await?.ok()
+    }
+
+    /// Removes a device from the Filter Accept List
+    /// ([Vol 4] Part E, Section 7.8.17).
+    pub async fn le_remove_device_from_filter_accept_list(
+        &self,
+        e: impl Into<AcceptListEntry> + Send,
+    ) -> Result<()> {
+        let e = e.into();
+        let r = self.exec_params(Opcode::LeRemoveDeviceFromFilterAcceptList, |cmd| {
+            e.pack(cmd);
+        });
+        r.await?.ok()
+    }
+
+    /// Changes the parameters of an existing connection
+    /// ([Vol 4] Part E, Section 7.8.18). Completion is indicated by an
+    /// `HCI_LE_Connection_Update_Complete` event.
+    pub async fn le_connection_update(&self, h: ConnHandle, p: ConnParams) -> Result<()> {
+        let r = self.exec_params(Opcode::LeConnectionUpdate, |cmd| {
+            cmd.u16(h)
+                .u16(ticks_1250us(p.interval.0).expect("invalid connection interval"))
+                .u16(ticks_1250us(p.interval.1).expect("invalid connection interval"))
+                .u16(p.latency)
+                .u16(ticks_10ms(p.timeout).expect("invalid supervision timeout"))
+                .u16(0_u16) // Min_CE_Length
+                .u16(0_u16); // Max_CE_Length
+        });
+        r.await?.cmd_ok()
+    }
+
+    /// Marks the channels that are known to be bad, e.g. because of
+    /// interference, as unused for all connections and periodic advertising
+    /// ([Vol 4] Part E, Section 7.8.19).
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer than 2 channels are marked as used.
+    pub async fn le_set_host_channel_classification(&self, m: ChannelMap) -> Result<()> {
+        assert!(m.len() >= 2, "at least 2 channels must be used");
+        let r = self.exec_params(Opcode::LeSetHostChannelClassification, |cmd| {
+            cmd.put(m.to_le_bytes());
+        });
+        r.await?.ok()
+    }
+
+    /// Returns the data channels used by the specified connection
+    /// ([Vol 4] Part E, Section 7.8.20).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_read_channel_map(&self, h: ConnHandle) -> Result<ChannelMap> {
+        let r = self.exec_params(Opcode::LeReadChannelMap, |cmd| {
+            cmd.u16(h);
+        });
+        r.await?.map_ok(|_, p| {
+            assert_eq!(ConnHandle::new(p.u16()), Some(h));
+            ChannelMap::from_le_bytes(p.bytes())
+        })
+    }
+
+    /// Requests the features used on the specified connection by the remote
+    /// device ([Vol 4] Part E, Section 7.8.21). Completion is indicated by an
+    /// `HCI_LE_Read_Remote_Features_Complete` event.
+    pub async fn le_read_remote_features(&self, h: ConnHandle) -> Result<()> {
+        let r = self.exec_params(Opcode::LeReadRemoteFeatures, |cmd| {
+            cmd.u16(h);
+        });
+        r.await?.cmd_ok()
+    }
+
+    /// Encrypts `plaintext` with AES-128 using key `k` in the controller
+    /// ([Vol 4] Part E, Section 7.8.22). This is the security function `e`
+    /// ([Vol 3] Part H, Section 2.2.1).
+    pub async fn le_encrypt(&self, k: impl Into<u128> + Send, plaintext: u128) -> Result<u128> {
+        let k = k.into();
+        let r = self.exec_params(Opcode::LeEncrypt, |cmd| {
+            cmd.u128(k).u128(plaintext);
+        });
+        r.await?.map_ok(|_, p| p.u128())
+    }
+
+    /// Returns 64 random bits generated by the controller
+    /// ([Vol 4] Part E, Section 7.8.23).
+    pub async fn le_rand(&self) -> Result<u64> {
+        self.exec(Opcode::LeRand).await?.map_ok(|_, p| p.u64())
+    }
+
+    /// Starts or restarts encryption of the specified connection in the Central
+    /// role using the given Long Term Key ([Vol 4] Part E, Section 7.8.24).
+    /// Completion is indicated by an `HCI_Encryption_Change` or
+    /// `HCI_Encryption_Key_Refresh_Complete` event.
+    pub async fn le_enable_encryption(
+        &self,
+        h: ConnHandle,
+        rand: u64,
+        ediv: u16,
+        k: &LTK,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeEnableEncryption, |cmd| {
+            cmd.u16(h).u64(rand).u16(ediv).u128(k);
+        });
+        r.await?.cmd_ok()
+    }
+
     /// Replies to an `HCI_LE_Long_Term_Key_Request` event from the controller,
     /// specifying the Long Term Key for the connection, if one is available
     /// ([Vol 4] Part E, Section 7.8.25 and 7.8.26).
@@ -75,6 +217,167 @@ impl Host {
         r.await?.map_ok(|_, p| LeStateCombinations(p.u64()))
     }
 
+    /// Accepts the connection parameters requested by the remote device in an
+    /// `HCI_LE_Remote_Connection_Parameter_Request` event
+    /// ([Vol 4] Part E, Section 7.8.31).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_remote_connection_parameter_request_reply(
+        &self,
+        h: ConnHandle,
+        p: ConnParams,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeRemoteConnectionParameterRequestReply, |cmd| {
+            cmd.u16(h)
+                .u16(ticks_1250us(p.interval.0).expect("invalid connection interval"))
+                .u16(ticks_1250us(p.interval.1).expect("invalid connection interval"))
+                .u16(p.latency)
+                .u16(ticks_10ms(p.timeout).expect("invalid supervision timeout"))
+                .u16(0_u16) // Min_CE_Length
+                .u16(0_u16); // Max_CE_Length
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Rejects the connection parameters requested by the remote device in an
+    /// `HCI_LE_Remote_Connection_Parameter_Request` event
+    /// ([Vol 4] Part E, Section 7.8.32).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_remote_connection_parameter_request_negative_reply(
+        &self,
+        h: ConnHandle,
+        reason: Status,
+    ) -> Result<()> {
+        let r = self.exec_params(
+            Opcode::LeRemoteConnectionParameterRequestNegativeReply,
+            |cmd| {
+                cmd.u16(h).u8(reason as u8);
+            },
+        );
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Suggests the maximum LL Data PDU payload size and transmission time for
+    /// the specified connection ([Vol 4] Part E, Section 7.8.33). The
+    /// controller generates an `HCI_LE_Data_Length_Change` event if the values
+    /// used on the connection change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_set_data_length(
+        &self,
+        h: ConnHandle,
+        tx_octets: u16,
+        tx_time: Duration,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetDataLength, |cmd| {
+            cmd.u16(h)
+                .u16(tx_octets)
+                .u16(u16::try_from(tx_time.as_micros()).expect("invalid tx time"));
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Returns the suggested maximum LL Data PDU payload size and transmission
+    /// time for new connections ([Vol 4] Part E, Section 7.8.34).
+    pub async fn le_read_suggested_default_data_length(&self) -> Result<(u16, Duration)> {
+        let r = self.exec(Opcode::LeReadSuggestedDefaultDataLength);
+        r.await?.map_ok(|_, p| {
+            let (octets, time) = (p.u16(), p.u16());
+            (octets, Duration::from_micros(u64::from(time)))
+        })
+    }
+
+    /// Sets the suggested maximum LL Data PDU payload size and transmission
+    /// time for new connections ([Vol 4] Part E, Section 7.8.35).
+    pub async fn le_write_suggested_default_data_length(
+        &self,
+        tx_octets: u16,
+        tx_time: Duration,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeWriteSuggestedDefaultDataLength, |cmd| {
+            cmd.u16(tx_octets)
+                .u16(u16::try_from(tx_time.as_micros()).expect("invalid tx time"));
+        });
+        r.await?.ok()
+    }
+
+    /// Adds a device to the resolving list used to generate and resolve
+    /// Resolvable Private Addresses in the controller
+    /// ([Vol 4] Part E, Section 7.8.38). `peer` must be an identity address.
+    pub async fn le_add_device_to_resolving_list(
+        &self,
+        peer: Addr,
+        peer_irk: &IRK,
+        local_irk: &IRK,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeAddDeviceToResolvingList, |cmd| {
+            cmd.u8(u8::from(peer.is_random()))
+                .put(peer.raw())
+                .u128(peer_irk)
+                .u128(local_irk);
+        });
+        r.await?.ok()
+    }
+
+    /// Removes a device from the resolving list
+    /// ([Vol 4] Part E, Section 7.8.39).
+    pub async fn le_remove_device_from_resolving_list(&self, peer: Addr) -> Result<()> {
+        let r = self.exec_params(Opcode::LeRemoveDeviceFromResolvingList, |cmd| {
+            cmd.u8(u8::from(peer.is_random())).put(peer.raw());
+        });
+        r.await?.ok()
+    }
+
+    /// Removes all devices from the resolving list
+    /// ([Vol 4] Part E, Section 7.8.40).
+    pub async fn le_clear_resolving_list(&self) -> Result<()> {
+        self.exec(Opcode::LeClearResolvingList).await?.ok()
+    }
+
+    /// Returns the total number of resolving list entries that can be stored
+    /// in the controller ([Vol 4] Part E, Section 7.8.41).
+    pub async fn le_read_resolving_list_size(&self) -> Result<usize> {
+        let r = self.exec(Opcode::LeReadResolvingListSize);
+        r.await?.map_ok(|_, p| usize::from(p.u8()))
+    }
+
+    /// Enables or disables resolution of Resolvable Private Addresses in the
+    /// controller ([Vol 4] Part E, Section 7.8.44).
+    pub async fn le_set_address_resolution_enable(&self, enable: bool) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetAddressResolutionEnable, |cmd| {
+            cmd.bool(enable);
+        });
+        r.await?.ok()
+    }
+
+    /// Sets the length of time the controller uses a Resolvable Private
+    /// Address before a new one is generated ([Vol 4] Part E, Section 7.8.45).
+    pub async fn le_set_resolvable_private_address_timeout(&self, t: Duration) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetResolvablePrivateAddressTimeout, |cmd| {
+            cmd.u16(u16::try_from(t.as_secs()).expect("invalid RPA timeout"));
+        });
+        r.await?.ok()
+    }
+
+    /// Returns the maximum LL Data PDU payload sizes and transmission times
+    /// supported by the controller ([Vol 4] Part E, Section 7.8.46).
+    pub async fn le_read_maximum_data_length(&self) -> Result<DataLen> {
+        self.exec(Opcode::LeReadMaximumDataLength).await?.ok()
+    }
+
     /// Reads the current transmitter and receiver PHY for the specified
     /// connection ([Vol 4] Part E, Section 7.8.47).
     ///
@@ -106,6 +409,27 @@ impl Host {
         r.await?.ok()
     }
 
+    /// Requests a change of the transmitter and receiver PHY for the specified
+    /// connection ([Vol 4] Part E, Section 7.8.49). A [`None`] mask indicates
+    /// no preference. Completion is indicated by an `HCI_LE_PHY_Update_Complete`
+    /// event. See [`Host::update_phy`] for waiting on the result.
+    pub async fn le_set_phy(
+        &self,
+        h: ConnHandle,
+        tx: Option<PhyMask>,
+        rx: Option<PhyMask>,
+        coded: CodedPhy,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetPhy, |cmd| {
+            cmd.u16(h)
+                .u8(u8::from(tx.is_none()) | (u8::from(rx.is_none()) << 1))
+                .u8(tx.unwrap_or_default().bits())
+                .u8(rx.unwrap_or_default().bits())
+                .u16(coded);
+        });
+        r.await?.cmd_ok()
+    }
+
     /// Sets the random device address for an advertising set
     /// ([Vol 4] Part E, Section 7.8.52).
     pub async fn le_set_advertising_set_random_address(
@@ -132,52 +456,57 @@ impl Host {
                 .u24(ticks_625us(p.pri_interval.1).unwrap_or(0))
                 .u8(p.pri_chan_map.bits())
                 .u8(p.addr_type)
-                .u8(match p.peer_addr {
-                    Addr::Public(_) => 0x00,
-                    Addr::Random(_) => 0x01,
-                })
+                .u8(u8::from(p.peer_addr.is_random()))
                 .put(p.peer_addr.raw())
                 .u8(p.filter_policy)
-                .i8(p.tx_power.map_or(TxPower::NONE, i8::from))
+                .i8(p.tx_power.unwrap_or(TxPower::UNSPECIFIED))
                 .u8(p.pri_phy)
                 .u8(p.sec_max_skip)
                 .u8(p.sec_phy)
                 .u8(p.sid)
                 .bool(p.scan_request_notify);
         });
-        r.await?.map_ok(|_, p| TxPower::new(p.i8()))
+        r.await?.map_ok(|_, p| TxPower::from_hci(p.i8()))
     }
 
     /// Sets the data used in advertising PDUs that have a data field
-    /// ([Vol 4] Part E, Section 7.8.54).
+    /// ([Vol 4] Part E, Section 7.8.54). The fragment is the concatenation of
+    /// all `data` slices.
     pub async fn le_set_extended_advertising_data(
         &self,
         h: AdvHandle,
         op: AdvDataOp,
         dont_frag: bool,
-        data: &[u8],
+        data: &[&[u8]],
     ) -> Result<()> {
+        let n = data.iter().map(|v| v.len()).sum::<usize>();
         let r = self.exec_params(Opcode::LeSetExtendedAdvertisingData, |cmd| {
             cmd.u8(h).u8(op).bool(dont_frag);
-            cmd.u8(u8::try_from(data.len()).expect("data too long"));
-            cmd.put(data);
+            cmd.u8(u8::try_from(n).expect("data too long"));
+            for &v in data {
+                cmd.put(v);
+            }
         });
         r.await?.ok()
     }
 
     /// Sets the data used in scan response PDUs
-    /// ([Vol 4] Part E, Section 7.8.55).
+    /// ([Vol 4] Part E, Section 7.8.55). The fragment is the concatenation of
+    /// all `data` slices.
     pub async fn le_set_extended_scan_response_data(
         &self,
         h: AdvHandle,
         op: AdvDataOp,
         dont_frag: bool,
-        data: &[u8],
+        data: &[&[u8]],
     ) -> Result<()> {
+        let n = data.iter().map(|v| v.len()).sum::<usize>();
         let r = self.exec_params(Opcode::LeSetExtendedScanResponseData, |cmd| {
             cmd.u8(h).u8(op).bool(dont_frag);
-            cmd.u8(u8::try_from(data.len()).expect("data too long"));
-            cmd.put(data);
+            cmd.u8(u8::try_from(n).expect("data too long"));
+            for &v in data {
+                cmd.put(v);
+            }
         });
         r.await?.ok()
     }
@@ -278,9 +607,277 @@ impl Host {
         });
         r.await?.ok()
     }
+
+    /// Sets the scan parameters for all PHYs in `p.phys`
+    /// ([Vol 4] Part E, Section 7.8.64).
+    pub async fn le_set_extended_scan_parameters(&self, p: ScanParams) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetExtendedScanParameters, |cmd| {
+            cmd.u8(p.addr_type).u8(p.filter_policy).u8(p.phys.bits());
+            for _ in 0..p.phys.bits().count_ones() {
+                cmd.bool(p.active)
+                    .u16(ticks_625us_u16(p.interval).expect("invalid scan interval"))
+                    .u16(ticks_625us_u16(p.window).expect("invalid scan window"));
+            }
+        });
+        r.await?.ok()
+    }
+
+    /// Enables or disables scanning ([Vol 4] Part E, Section 7.8.65). Scanning
+    /// continues until disabled if `duration` is zero. Otherwise, an
+    /// `HCI_LE_Scan_Timeout` event is generated when the duration expires.
+    pub async fn le_set_extended_scan_enable(
+        &self,
+        enable: bool,
+        filter_dup: bool,
+        duration: Duration,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetExtendedScanEnable, |cmd| {
+            cmd.bool(enable)
+                .bool(filter_dup)
+                .u16(ticks_10ms(duration).expect("invalid scan duration"))
+                .u16(0_u16); // Period
+        });
+        r.await?.ok()
+    }
+
+    /// Creates a connection to an advertising device using all PHYs in
+    /// `p.phys` ([Vol 4] Part E, Section 7.8.66). Completion is indicated by an
+    /// `HCI_LE_Enhanced_Connection_Complete` or `HCI_LE_Connection_Complete`
+    /// event.
+    pub async fn le_extended_create_connection(&self, p: CreateConnParams) -> Result<()> {
+        let r = self.exec_params(Opcode::LeExtendedCreateConnection, |cmd| {
+            cmd.bool(p.filter_accept_list)
+                .u8(p.addr_type)
+                .u8(u8::from(p.peer_addr.is_random()))
+                .put(p.peer_addr.raw())
+                .u8(p.phys.bits());
+            let c = p.conn;
+            for _ in 0..p.phys.bits().count_ones() {
+                cmd.u16(ticks_625us_u16(p.scan_interval).expect("invalid scan interval"))
+                    .u16(ticks_625us_u16(p.scan_window).expect("invalid scan window"))
+                    .u16(ticks_1250us(c.interval.0).expect("invalid connection interval"))
+                    .u16(ticks_1250us(c.interval.1).expect("invalid connection interval"))
+                    .u16(c.latency)
+                    .u16(ticks_10ms(c.timeout).expect("invalid supervision timeout"))
+                    .u16(0_u16) // Min_CE_Length
+                    .u16(0_u16); // Max_CE_Length
+            }
+        });
+        r.await?.cmd_ok()
+    }
+
+    /// Returns the minimum and maximum transmit power levels supported by the
+    /// controller ([Vol 4] Part E, Section 7.8.74).
+    pub async fn le_read_transmit_power(&self) -> Result<(TxPower, TxPower)> {
+        let r = self.exec(Opcode::LeReadTransmitPower);
+        r.await?
+            .map_ok(|_, p| (TxPower::from_hci(p.i8()), TxPower::from_hci(p.i8())))
+    }
+
+    /// Enables or disables IQ sampling of Constant Tone Extensions received on
+    /// the specified connection and sets the antenna switching pattern
+    /// ([Vol 4] Part E, Section 7.8.83).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_set_connection_cte_receive_parameters(
+        &self,
+        h: ConnHandle,
+        enable: bool,
+        slot: CteSlot,
+        antenna_ids: &[u8],
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetConnectionCteReceiveParameters, |cmd| {
+            cmd.u16(h).bool(enable).u8(slot);
+            cmd.u8(u8::try_from(antenna_ids.len()).expect("too many antenna IDs"));
+            cmd.put(antenna_ids);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Sets the allowed Constant Tone Extension types and the antenna switching
+    /// pattern for CTEs transmitted on the specified connection
+    /// ([Vol 4] Part E, Section 7.8.84).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_set_connection_cte_transmit_parameters(
+        &self,
+        h: ConnHandle,
+        types: CteTypes,
+        antenna_ids: &[u8],
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetConnectionCteTransmitParameters, |cmd| {
+            cmd.u16(h).u8(types.bits());
+            cmd.u8(u8::try_from(antenna_ids.len()).expect("too many antenna IDs"));
+            cmd.put(antenna_ids);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Starts or stops requesting Constant Tone Extensions from the peer on the
+    /// specified connection. An `interval` of 0 requests a CTE only once,
+    /// otherwise a CTE is requested every `interval` connection events. The
+    /// CTE length is specified in 8 µs units ([Vol 4] Part E, Section 7.8.85).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_connection_cte_request_enable(
+        &self,
+        h: ConnHandle,
+        enable: bool,
+        interval: u16,
+        len: u8,
+        typ: CteType,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeConnectionCteRequestEnable, |cmd| {
+            cmd.u16(h).bool(enable).u16(interval).u8(len).u8(typ);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Enables or disables responding to CTE requests from the peer on the
+    /// specified connection ([Vol 4] Part E, Section 7.8.86).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_connection_cte_response_enable(
+        &self,
+        h: ConnHandle,
+        enable: bool,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeConnectionCteResponseEnable, |cmd| {
+            cmd.u16(h).bool(enable);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Returns the current and maximum transmit power levels of the specified
+    /// connection for the given PHY ([Vol 4] Part E, Section 7.8.117).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle or
+    /// PHY parameters.
+    pub async fn le_enhanced_read_transmit_power_level(
+        &self,
+        h: ConnHandle,
+        phy: PowerPhy,
+    ) -> Result<(TxPower, TxPower)> {
+        let r = self.exec_params(Opcode::LeEnhancedReadTransmitPowerLevel, |cmd| {
+            cmd.u16(h).u8(phy);
+        });
+        r.await?.map_ok(|_, p| {
+            assert_eq!(ConnHandle::new(p.u16()), Some(h));
+            assert_eq!(p.u8(), u8::from(phy));
+            (TxPower::from_hci(p.i8()), TxPower::from_hci(p.i8()))
+        })
+    }
+
+    /// Requests the transmit power level used by the remote device on the
+    /// specified connection for the given PHY
+    /// ([Vol 4] Part E, Section 7.8.118). Completion is indicated by an
+    /// `HCI_LE_Transmit_Power_Reporting` event with
+    /// [`LeTransmitPowerReporting::reason`] set to 0x02.
+    pub async fn le_read_remote_transmit_power_level(
+        &self,
+        h: ConnHandle,
+        phy: PowerPhy,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeReadRemoteTransmitPowerLevel, |cmd| {
+            cmd.u16(h).u8(phy);
+        });
+        r.await?.cmd_ok()
+    }
+
+    /// Sets the path loss thresholds for the specified connection
+    /// ([Vol 4] Part E, Section 7.8.119).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a threshold is 0xFF or there is a mismatch with the returned
+    /// connection handle parameter.
+    pub async fn le_set_path_loss_reporting_parameters(
+        &self,
+        h: ConnHandle,
+        p: PathLossParams,
+    ) -> Result<()> {
+        assert!(
+            p.high.map_or(true, |v| v.0 != 0xFF) && p.low.map_or(true, |v| v.0 != 0xFF),
+            "invalid path loss threshold"
+        );
+        let (hi, lo) = (p.high.unwrap_or((0xFF, 0)), p.low.unwrap_or((0xFF, 0)));
+        let r = self.exec_params(Opcode::LeSetPathLossReportingParameters, |cmd| {
+            cmd.u16(h)
+                .u8(hi.0)
+                .u8(hi.1)
+                .u8(lo.0)
+                .u8(lo.1)
+                .u16(p.min_time_spent);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Enables or disables path loss reporting for the specified connection
+    /// via `HCI_LE_Path_Loss_Threshold` events
+    /// ([Vol 4] Part E, Section 7.8.120). See [`PathLossZones`] for receiving
+    /// the events.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_set_path_loss_reporting_enable(
+        &self,
+        h: ConnHandle,
+        enable: bool,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetPathLossReportingEnable, |cmd| {
+            cmd.u16(h).bool(enable);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
+
+    /// Enables or disables reporting of local and remote transmit power level
+    /// changes on the specified connection via `HCI_LE_Transmit_Power_Reporting`
+    /// events ([Vol 4] Part E, Section 7.8.121). See [`TxPowerReports`] for
+    /// receiving the events.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is a mismatch with the returned connection handle
+    /// parameter.
+    pub async fn le_set_transmit_power_reporting_enable(
+        &self,
+        h: ConnHandle,
+        local: bool,
+        remote: bool,
+    ) -> Result<()> {
+        let r = self.exec_params(Opcode::LeSetTransmitPowerReportingEnable, |cmd| {
+            cmd.u16(h).bool(local).bool(remote);
+        });
+        assert_eq!(r.await?.map_ok(|_, p| ConnHandle::new(p.u16()))?, Some(h));
+        Ok(())
+    }
 }
 
 /// `HCI_LE_Read_Buffer_Size` return parameters ([Vol 4] Part E, Section 7.8.2).
+/// ACL data length and packet count of zero indicate that the controller uses
+/// shared BR/EDR buffers, which are reported by `HCI_Read_Buffer_Size`.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct LeBufferSize {
     pub acl_data_len: u16,
@@ -289,15 +886,38 @@ pub struct LeBufferSize {
     pub iso_num_pkts: u8,
 }
 
-impl FromEvent for LeBufferSize {
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self {
+impl TryFromEvent for LeBufferSize {
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
         let v2 = e.opcode() == Opcode::LeReadBufferSizeV2;
-        Self {
+        Ok(Self {
             acl_data_len: p.u16(),
             acl_num_pkts: p.u8(),
             iso_data_len: v2.then(|| p.u16()).unwrap_or_default(),
             iso_num_pkts: v2.then(|| p.u8()).unwrap_or_default(),
-        }
+        })
+    }
+}
+
+/// LL Data PDU payload sizes and transmission times
+/// ([Vol 6] Part B, Section 4.5.10).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DataLen {
+    pub tx_octets: u16,
+    pub tx_time: Duration,
+    pub rx_octets: u16,
+    pub rx_time: Duration,
+}
+
+impl TryFromEvent for DataLen {
+    fn unpack(_: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        let (tx_octets, tx_time) = (p.u16(), p.u16());
+        let (rx_octets, rx_time) = (p.u16(), p.u16());
+        Ok(Self {
+            tx_octets,
+            tx_time: Duration::from_micros(u64::from(tx_time)),
+            rx_octets,
+            rx_time: Duration::from_micros(u64::from(rx_time)),
+        })
     }
 }
 
@@ -319,6 +939,253 @@ pub struct AdvParams {
     pub scan_request_notify: bool,
 }
 
+/// `HCI_LE_Set_Extended_Scan_Parameters` command parameters
+/// ([Vol 4] Part E, Section 7.8.64). The same scan type, interval, and window
+/// are used for all PHYs. Only [`PhyMask::LE_1M`] and [`PhyMask::LE_CODED`]
+/// are valid scanning PHYs.
+#[derive(Clone, Copy, Debug)]
+pub struct ScanParams {
+    pub addr_type: OwnAddrType,
+    pub filter_policy: ScanFilterPolicy,
+    pub phys: PhyMask,
+    pub active: bool,
+    pub interval: Duration,
+    pub window: Duration,
+}
+
+impl Default for ScanParams {
+    /// Returns parameters for active scanning on the LE 1M PHY using
+    /// `TGAP(scan_fast_interval)` and `TGAP(scan_fast_window)`
+    /// ([Vol 3] Part C, Section 12).
+    #[inline]
+    fn default() -> Self {
+        Self {
+            addr_type: OwnAddrType::default(),
+            filter_policy: ScanFilterPolicy::default(),
+            phys: PhyMask::LE_1M,
+            active: true,
+            interval: Duration::from_millis(60),
+            window: Duration::from_millis(30),
+        }
+    }
+}
+
+/// Filter Accept List entry ([Vol 4] Part E, Section 7.8.16).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum AcceptListEntry {
+    /// Device with a public or random address.
+    Addr(Addr),
+    /// All devices sending anonymous advertisements.
+    Anonymous,
+}
+
+impl AcceptListEntry {
+    /// Packs `Address_Type` and `Address` command parameters.
+    fn pack(self, cmd: &mut Packer) {
+        match self {
+            Self::Addr(a) => cmd.u8(u8::from(a.is_random())).put(a.raw()),
+            Self::Anonymous => cmd.u8(0xFF_u8).put(RawAddr::default()),
+        };
+    }
+}
+
+impl From<Addr> for AcceptListEntry {
+    #[inline(always)]
+    fn from(a: Addr) -> Self {
+        Self::Addr(a)
+    }
+}
+
+/// LE data channel map with one bit for each channel index 0-36
+/// ([Vol 4] Part E, Section 7.8.19 and 7.8.20).
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+#[repr(transparent)]
+pub struct ChannelMap(u64);
+
+impl ChannelMap {
+    /// Number of data channels.
+    pub const CHANNELS: u8 = 37;
+    /// Map containing all data channels.
+    pub const ALL: Self = Self((1 << Self::CHANNELS) - 1);
+
+    /// Creates a channel map from the little-endian HCI parameter encoding.
+    /// Reserved bits are ignored.
+    #[inline]
+    #[must_use]
+    pub const fn from_le_bytes(b: [u8; 5]) -> Self {
+        let v = u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], 0, 0, 0]);
+        Self(v & Self::ALL.0)
+    }
+
+    /// Returns the little-endian HCI parameter encoding.
+    #[inline]
+    #[must_use]
+    pub const fn to_le_bytes(self) -> [u8; 5] {
+        let b = self.0.to_le_bytes();
+        [b[0], b[1], b[2], b[3], b[4]]
+    }
+
+    /// Returns whether channel `ch` is used.
+    #[inline]
+    #[must_use]
+    pub const fn contains(self, ch: u8) -> bool {
+        ch < Self::CHANNELS && self.0 & (1 << ch) != 0
+    }
+
+    /// Marks channel `ch` as used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ch` is not a data channel index.
+    #[inline]
+    pub fn insert(&mut self, ch: u8) {
+        assert!(ch < Self::CHANNELS, "invalid data channel {ch}");
+        self.0 |= 1 << ch;
+    }
+
+    /// Marks channel `ch` as unused.
+    #[inline]
+    pub fn remove(&mut self, ch: u8) {
+        if ch < Self::CHANNELS {
+            self.0 &= !(1 << ch);
+        }
+    }
+
+    /// Returns the number of used channels.
+    #[inline]
+    #[must_use]
+    pub const fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns whether all channels are unused.
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns an iterator over the used channel indices in ascending order.
+    #[inline]
+    pub fn iter(self) -> impl Iterator<Item = u8> {
+        (0..Self::CHANNELS).filter(move |&ch| self.contains(ch))
+    }
+}
+
+impl FromIterator<u8> for ChannelMap {
+    /// Creates a channel map from used channel indices.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any index is not a data channel index.
+    fn from_iter<T: IntoIterator<Item = u8>>(it: T) -> Self {
+        let mut m = Self::default();
+        for ch in it {
+            m.insert(ch);
+        }
+        m
+    }
+}
+
+impl Debug for ChannelMap {
+    /// Formats the used channels as a list of ranges, e.g.
+    /// `ChannelMap[0-10, 12, 14-36]`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChannelMap[")?;
+        let mut it = self.iter().peekable();
+        let mut first = true;
+        while let Some(start) = it.next() {
+            let mut end = start;
+            while it.next_if_eq(&(end + 1)).is_some() {
+                end += 1;
+            }
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+            if start == end {
+                write!(f, "{start}")?;
+            } else {
+                write!(f, "{start}-{end}")?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+/// Connection parameters ([Vol 4] Part E, Section 7.8.66).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnParams {
+    /// Minimum and maximum connection interval.
+    pub interval: (Duration, Duration),
+    /// Maximum peripheral latency in connection events.
+    pub latency: u16,
+    /// Supervision timeout.
+    pub timeout: Duration,
+}
+
+impl Default for ConnParams {
+    /// Returns parameters using `TGAP(initial_conn_interval)`
+    /// ([Vol 3] Part C, Section 12).
+    #[inline]
+    fn default() -> Self {
+        Self {
+            interval: (Duration::from_millis(30), Duration::from_millis(50)),
+            latency: 0,
+            timeout: Duration::from_secs(4),
+        }
+    }
+}
+
+impl ConnParams {
+    /// Returns whether the parameters are within the ranges allowed by the
+    /// Link Layer ([Vol 6] Part B, Section 4.5.1 and 4.5.2).
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let interval = Duration::from_micros(7500)..=Duration::from_secs(4);
+        let timeout = Duration::from_millis(100)..=Duration::from_secs(32);
+        let (min, max) = self.interval;
+        (interval.contains(&min) && interval.contains(&max) && min <= max)
+            && self.latency <= 499
+            && timeout.contains(&self.timeout)
+            && self.timeout > max * (u32::from(self.latency) + 1) * 2
+    }
+}
+
+/// `HCI_LE_Extended_Create_Connection` command parameters
+/// ([Vol 4] Part E, Section 7.8.66). The same scan and connection parameters
+/// are used for all initiating PHYs.
+#[derive(Clone, Copy, Debug)]
+pub struct CreateConnParams {
+    /// Ignore `peer_addr` and connect to any device in the Filter Accept List.
+    pub filter_accept_list: bool,
+    pub addr_type: OwnAddrType,
+    pub peer_addr: Addr,
+    pub phys: PhyMask,
+    pub scan_interval: Duration,
+    pub scan_window: Duration,
+    pub conn: ConnParams,
+}
+
+impl CreateConnParams {
+    /// Returns default parameters for connecting to `peer` on the LE 1M PHY.
+    #[inline]
+    #[must_use]
+    pub fn new(peer: Addr) -> Self {
+        let scan = ScanParams::default();
+        Self {
+            filter_accept_list: false,
+            addr_type: OwnAddrType::default(),
+            peer_addr: peer,
+            phys: PhyMask::LE_1M,
+            scan_interval: scan.interval,
+            scan_window: scan.window,
+            conn: ConnParams::default(),
+        }
+    }
+}
+
 /// `HCI_LE_Set_Extended_Advertising_Enable` command parameters
 /// ([Vol 4] Part E, Section 7.8.56).
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -338,3 +1205,41 @@ impl From<AdvHandle> for AdvEnableParams {
         }
     }
 }
+
+/// `HCI_LE_Set_Path_Loss_Reporting_Parameters` command parameters
+/// ([Vol 4] Part E, Section 7.8.119). Path loss is the difference between the
+/// transmit power of the peer and the RSSI in dB.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PathLossParams {
+    /// High threshold and hysteresis in dB or [`None`] if unused.
+    pub high: Option<(u8, u8)>,
+    /// Low threshold and hysteresis in dB or [`None`] if unused.
+    pub low: Option<(u8, u8)>,
+    /// Minimum number of connection events that the path loss must remain
+    /// past a threshold before a zone change is reported.
+    pub min_time_spent: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_map() {
+        let m = ChannelMap::ALL;
+        assert_eq!(m.len(), 37);
+        assert_eq!(m.to_le_bytes(), [0xFF, 0xFF, 0xFF, 0xFF, 0x1F]);
+        assert_eq!(ChannelMap::from_le_bytes([0xFF; 5]), m);
+        assert_eq!(format!("{m:?}"), "ChannelMap[0-36]");
+
+        let mut m: ChannelMap = (0..=10).chain([12]).chain(14..=36).collect();
+        assert_eq!(format!("{m:?}"), "ChannelMap[0-10, 12, 14-36]");
+        m.remove(0);
+        m.remove(36);
+        m.remove(40);
+        assert!(!m.contains(0) && m.contains(12) && !m.contains(13));
+        assert_eq!(m.iter().next(), Some(1));
+        assert_eq!(ChannelMap::from_le_bytes(m.to_le_bytes()), m);
+        assert_eq!(format!("{:?}", ChannelMap::default()), "ChannelMap[]");
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::warn;
+
+use super::*;
+
+/// Controller initialization step reported by [`Error::InitFailed`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum InitStep {
+    PreInitHook,
+    Reset,
+    ReadLocalSupportedCommands,
+    ReadLocalVersion,
+    ReadLocalSupportedFeatures,
+    LeReadLocalSupportedFeatures,
+    LeReadSupportedStates,
+    LeReadBufferSize,
+    ReadBufferSize,
+    ReadBdAddr,
+    HostBufferSize,
+    SetEventMask,
+    LeWriteSuggestedDefaultDataLength,
+    LeSetDefaultPhy,
+    LeSetAddressResolutionEnable,
+    PostInitHook,
+}
+
+impl InitStep {
+    /// Returns a function that converts an error returned by this step into
+    /// [`Error::InitFailed`] if the controller rejected the command. Other
+    /// errors are returned unchanged.
+    pub(super) fn err(self) -> impl FnOnce(Error) -> Error {
+        move |e| {
+            warn!("Controller initialization failed at {self:?} step: {e}");
+            match e {
+                Error::InitFailed { .. } => e,
+                _ => match e.status() {
+                    Some(status) => Error::InitFailed { step: self, status },
+                    None => e,
+                },
+            }
+        }
+    }
+}
+
+/// Future returned by an initialization hook.
+pub type InitHookFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Initialization hook.
+type InitHook = Arc<dyn for<'a> Fn(&'a Host) -> InitHookFuture<'a> + Send + Sync>;
+
+/// Controller initialization configuration used by [`Host::init_with`]. The
+/// default configuration resets the controller, enables all events required
+/// by the library, advertises a single-packet host ACL buffer, and leaves all
+/// optional features at their controller defaults.
+#[derive(Clone)]
+#[must_use]
+pub struct HostConfig {
+    pub(super) reset: bool,
+    pub(super) event_mask: EventMask,
+    pub(super) host_buf_pkts: Option<u16>,
+    pub(super) data_len: Option<(u16, Duration)>,
+    pub(super) default_phy: Option<(Option<PhyMask>, Option<PhyMask>)>,
+    pub(super) addr_resolution: Option<bool>,
+    pub(super) pre_init: Vec<InitHook>,
+    pub(super) post_init: Vec<InitHook>,
+}
+
+impl HostConfig {
+    /// Creates a default configuration.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            reset: true,
+            event_mask: EventMask::default(),
+            host_buf_pkts: Some(1),
+            data_len: None,
+            default_phy: None,
+            addr_resolution: None,
+            pre_init: Vec::new(),
+            post_init: Vec::new(),
+        }
+    }
+
+    /// Sets whether the controller is reset at the start of initialization
+    /// ([Vol 4] Part E, Section 7.3.2).
+    #[inline]
+    pub fn with_reset(mut self, reset: bool) -> Self {
+        self.reset = reset;
+        self
+    }
+
+    /// Sets the events that can be generated by the controller.
+    #[inline]
+    pub fn with_event_mask(mut self, m: EventMask) -> Self {
+        self.event_mask = m;
+        self
+    }
+
+    /// Sets the number of ACL data packets that the host advertises to the
+    /// controller via `HCI_Host_Buffer_Size`. The packet length is always the
+    /// controller's LE ACL data packet length. [`None`] skips the command
+    /// ([Vol 4] Part E, Section 7.3.39).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_pkts` is 0.
+    #[inline]
+    pub fn with_host_buffer_size(mut self, num_pkts: Option<u16>) -> Self {
+        assert_ne!(num_pkts, Some(0), "invalid host buffer size");
+        self.host_buf_pkts = num_pkts;
+        self
+    }
+
+    /// Sets the suggested maximum LL Data PDU payload size and transmission
+    /// time for new connections ([Vol 4] Part E, Section 7.8.35).
+    ///
+    /// # Panics
+    ///
+    /// Panics if either value is outside of the range allowed by
+    /// [Vol 6] Part B, Section 4.5.10.
+    #[inline]
+    pub fn with_default_data_len(mut self, octets: u16, time: Duration) -> Self {
+        assert!((LL_MIN_OCTETS..=LL_MAX_OCTETS).contains(&octets));
+        assert!((Duration::from_micros(328)..=Duration::from_micros(17040)).contains(&time));
+        self.data_len = Some((octets, time));
+        self
+    }
+
+    /// Sets the preferred transmitter and receiver PHY for all connections. A
+    /// [`None`] mask indicates no preference
+    /// ([Vol 4] Part E, Section 7.8.48).
+    #[inline]
+    pub fn with_default_phy(mut self, tx: Option<PhyMask>, rx: Option<PhyMask>) -> Self {
+        self.default_phy = Some((tx, rx));
+        self
+    }
+
+    /// Enables or disables address resolution in the controller
+    /// ([Vol 4] Part E, Section 7.8.44).
+    #[inline]
+    pub fn with_addr_resolution(mut self, enable: bool) -> Self {
+        self.addr_resolution = Some(enable);
+        self
+    }
+
+    /// Adds a hook that is called before the standard initialization
+    /// sequence, including the reset. Hooks are called in the order they were
+    /// added. Controller information is not available to the hooks, and the
+    /// hooks must not retain clones of the host.
+    #[inline]
+    pub fn with_pre_init<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a Host) -> InitHookFuture<'a> + Send + Sync + 'static,
+    {
+        self.pre_init.push(Arc::new(f));
+        self
+    }
+
+    /// Adds a hook that is called after the standard initialization sequence.
+    /// Hooks are called in the order they were added.
+    #[inline]
+    pub fn with_post_init<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a Host) -> InitHookFuture<'a> + Send + Sync + 'static,
+    {
+        self.post_init.push(Arc::new(f));
+        self
+    }
+}
+
+impl Default for HostConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for HostConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostConfig")
+            .field("reset", &self.reset)
+            .field("event_mask", &self.event_mask)
+            .field("host_buf_pkts", &self.host_buf_pkts)
+            .field("data_len", &self.data_len)
+            .field("default_phy", &self.default_phy)
+            .field("addr_resolution", &self.addr_resolution)
+            .field("pre_init", &self.pre_init.len())
+            .field("post_init", &self.post_init.len())
+            .finish()
+    }
+}
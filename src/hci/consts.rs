@@ -11,6 +11,11 @@ pub(crate) const CMD_BUF: usize = CMD_HDR + u8::MAX as usize;
 pub(crate) const ACL_HDR: usize = 4;
 pub(crate) const ACL_LE_MIN_DATA_LEN: u16 = 27;
 
+/// Minimum and maximum LL Data PDU payload sizes
+/// ([Vol 6] Part B, Section 4.5.10).
+pub const LL_MIN_OCTETS: u16 = 27;
+pub const LL_MAX_OCTETS: u16 = 251;
+
 /// HCI event header and buffer sizes ([Vol 4] Part E, Section 5.4.4).
 pub(super) const EVT_HDR: usize = 2;
 pub(crate) const EVT_BUF: usize = EVT_HDR + u8::MAX as usize;
@@ -36,12 +41,18 @@ pub enum Opcode {
     #[default]
     None = 0x0000,
 
+    // Link Control commands ([Vol 4] Part E, Section 7.1)
+    Disconnect = LinkControl.ocf(0x0006),
+
     // HCI Control and Baseband commands ([Vol 4] Part E, Section 7.3)
     SetEventMask = HciControl.ocf(0x0001),
     Reset = HciControl.ocf(0x0003),
+    ReadTransmitPowerLevel = HciControl.ocf(0x002D),
     SetControllerToHostFlowControl = HciControl.ocf(0x0031),
     HostBufferSize = HciControl.ocf(0x0033),
     SetEventMaskPage2 = HciControl.ocf(0x0063),
+    ReadAuthenticatedPayloadTimeout = HciControl.ocf(0x007B),
+    WriteAuthenticatedPayloadTimeout = HciControl.ocf(0x007C),
 
     // Informational parameters commands ([Vol 4] Part E, Section 7.4)
     ReadLocalVersionInformation = InfoParams.ocf(0x0001),
@@ -50,15 +61,42 @@ pub enum Opcode {
     ReadBufferSize = InfoParams.ocf(0x0005),
     ReadBdAddr = InfoParams.ocf(0x0009),
 
+    // Status parameters commands ([Vol 4] Part E, Section 7.5)
+    ReadRssi = StatusParams.ocf(0x0005),
+
     // LE Controller commands ([Vol 4] Part E, Section 7.8)
     LeSetEventMask = Le.ocf(0x0001),
     LeReadBufferSize = Le.ocf(0x0002),
     LeReadLocalSupportedFeatures = Le.ocf(0x0003),
     LeSetRandomAddress = Le.ocf(0x0005),
+    LeClearFilterAcceptList = Le.ocf(0x0010),
+    LeCreateConnectionCancel = Le.ocf(0x000E),
+    LeReadFilterAcceptListSize = Le.ocf(0x000F),
+    LeAddDeviceToFilterAcceptList = Le.ocf(0x0011),
+    LeRemoveDeviceFromFilterAcceptList = Le.ocf(0x0012),
+    LeConnectionUpdate = Le.ocf(0x0013),
+    LeSetHostChannelClassification = Le.ocf(0x0014),
+    LeReadChannelMap = Le.ocf(0x0015),
+    LeReadRemoteFeatures = Le.ocf(0x0016),
+    LeEncrypt = Le.ocf(0x0017),
+    LeRand = Le.ocf(0x0018),
+    LeEnableEncryption = Le.ocf(0x0019),
     LeReadBufferSizeV2 = Le.ocf(0x0060),
     LeLongTermKeyRequestReply = Le.ocf(0x001A),
     LeLongTermKeyRequestNegativeReply = Le.ocf(0x001B),
     LeReadSupportedStates = Le.ocf(0x001C),
+    LeRemoteConnectionParameterRequestReply = Le.ocf(0x0020),
+    LeRemoteConnectionParameterRequestNegativeReply = Le.ocf(0x0021),
+    LeSetDataLength = Le.ocf(0x0022),
+    LeReadSuggestedDefaultDataLength = Le.ocf(0x0023),
+    LeWriteSuggestedDefaultDataLength = Le.ocf(0x0024),
+    LeAddDeviceToResolvingList = Le.ocf(0x0027),
+    LeRemoveDeviceFromResolvingList = Le.ocf(0x0028),
+    LeClearResolvingList = Le.ocf(0x0029),
+    LeReadResolvingListSize = Le.ocf(0x002A),
+    LeSetAddressResolutionEnable = Le.ocf(0x002D),
+    LeSetResolvablePrivateAddressTimeout = Le.ocf(0x002E),
+    LeReadMaximumDataLength = Le.ocf(0x002F),
     LeReadPhy = Le.ocf(0x0030),
     LeSetDefaultPhy = Le.ocf(0x0031),
     LeSetPhy = Le.ocf(0x0032),
@@ -74,6 +112,24 @@ pub enum Opcode {
     LeSetPeriodicAdvertisingParameters = Le.ocf(0x003E),
     LeSetPeriodicAdvertisingData = Le.ocf(0x003F),
     LeSetPeriodicAdvertisingEnable = Le.ocf(0x0040),
+    LeSetExtendedScanParameters = Le.ocf(0x0041),
+    LeSetExtendedScanEnable = Le.ocf(0x0042),
+    LeExtendedCreateConnection = Le.ocf(0x0043),
+    LeReadTransmitPower = Le.ocf(0x004B),
+    LeSetConnectionCteReceiveParameters = Le.ocf(0x0054),
+    LeSetConnectionCteTransmitParameters = Le.ocf(0x0055),
+    LeConnectionCteRequestEnable = Le.ocf(0x0056),
+    LeConnectionCteResponseEnable = Le.ocf(0x0057),
+    LeEnhancedReadTransmitPowerLevel = Le.ocf(0x0076),
+    LeReadRemoteTransmitPowerLevel = Le.ocf(0x0077),
+    LeSetPathLossReportingParameters = Le.ocf(0x0078),
+    LeSetPathLossReportingEnable = Le.ocf(0x0079),
+    LeSetTransmitPowerReportingEnable = Le.ocf(0x007A),
+
+    /// Any vendor-specific command ([Vol 4] Part E, Section 5.4.1). Commands in
+    /// the vendor group are routed by this opcode, so only one can execute at a
+    /// time.
+    Vendor = OpcodeGroup::Vendor.ocf(0x0000),
 }
 
 impl Opcode {
@@ -91,6 +147,49 @@ impl Opcode {
         !self.is_none()
     }
 
+    /// Converts a raw opcode from a command event, mapping all vendor-specific
+    /// opcodes to [`Self::Vendor`].
+    #[inline]
+    #[must_use]
+    pub(super) fn from_raw(v: u16) -> Self {
+        if v >> 10 == OpcodeGroup::Vendor as u16 {
+            Self::Vendor
+        } else {
+            Self::from(v)
+        }
+    }
+
+    /// Returns the raw opcode of the vendor-specific command `ocf`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ocf` is not a 10-bit value.
+    #[inline]
+    #[must_use]
+    pub(super) const fn vendor(ocf: u16) -> u16 {
+        assert!(ocf >> 10 == 0, "invalid vendor command OCF");
+        OpcodeGroup::Vendor.ocf(ocf)
+    }
+
+    /// Returns whether the controller acknowledges the command with a
+    /// `CommandStatus` event instead of `CommandComplete`. Completion of these
+    /// commands is indicated by a separate event.
+    #[inline]
+    #[must_use]
+    pub(super) const fn is_status_only(self) -> bool {
+        use Opcode::*;
+        matches!(
+            self,
+            Disconnect
+                | LeConnectionUpdate
+                | LeReadRemoteFeatures
+                | LeEnableEncryption
+                | LeSetPhy
+                | LeExtendedCreateConnection
+                | LeReadRemoteTransmitPowerLevel
+        )
+    }
+
     /// Returns the associated octet and mask of the command in the
     /// `Supported_Commands` parameter of `HCI_Read_Local_Supported_Commands`
     /// ([Vol 4] Part E, Section 6.27). Returns `(0, 0)` for unconditionally
@@ -99,24 +198,53 @@ impl Opcode {
     pub(super) const fn mask(self) -> (usize, u8) {
         use Opcode::*;
         let (octet, bit) = match self {
-            None | ReadLocalSupportedCommands => (0, u32::MAX),
+            None | ReadLocalSupportedCommands | Vendor => (0, u32::MAX),
+            Disconnect => (0, 5),
             SetEventMask => (5, 6),
             Reset => (5, 7),
+            ReadTransmitPowerLevel => (10, 2),
             SetControllerToHostFlowControl => (10, 5),
             HostBufferSize => (10, 6),
             SetEventMaskPage2 => (22, 2),
+            ReadAuthenticatedPayloadTimeout => (32, 4),
+            WriteAuthenticatedPayloadTimeout => (32, 5),
             ReadLocalVersionInformation => (14, 3),
             ReadLocalSupportedFeatures => (14, 5),
             ReadBufferSize => (14, 7),
             ReadBdAddr => (15, 1),
+            ReadRssi => (15, 5),
             LeSetEventMask => (25, 0),
             LeReadBufferSize => (25, 1),
             LeReadLocalSupportedFeatures => (25, 2),
             LeSetRandomAddress => (25, 4),
+            LeCreateConnectionCancel => (26, 5),
+            LeReadFilterAcceptListSize => (26, 6),
+            LeClearFilterAcceptList => (26, 7),
+            LeAddDeviceToFilterAcceptList => (27, 0),
+            LeRemoveDeviceFromFilterAcceptList => (27, 1),
+            LeConnectionUpdate => (27, 2),
+            LeSetHostChannelClassification => (27, 3),
+            LeReadChannelMap => (27, 4),
+            LeReadRemoteFeatures => (27, 5),
+            LeEncrypt => (27, 6),
+            LeRand => (27, 7),
             LeReadBufferSizeV2 => (41, 5),
+            LeEnableEncryption => (28, 0),
             LeLongTermKeyRequestReply => (28, 1),
             LeLongTermKeyRequestNegativeReply => (28, 2),
             LeReadSupportedStates => (28, 3),
+            LeRemoteConnectionParameterRequestReply => (33, 4),
+            LeRemoteConnectionParameterRequestNegativeReply => (33, 5),
+            LeSetDataLength => (33, 6),
+            LeReadSuggestedDefaultDataLength => (33, 7),
+            LeWriteSuggestedDefaultDataLength => (34, 0),
+            LeAddDeviceToResolvingList => (34, 3),
+            LeRemoveDeviceFromResolvingList => (34, 4),
+            LeClearResolvingList => (34, 5),
+            LeReadResolvingListSize => (34, 6),
+            LeSetAddressResolutionEnable => (35, 1),
+            LeSetResolvablePrivateAddressTimeout => (35, 2),
+            LeReadMaximumDataLength => (35, 3),
             LeReadPhy => (35, 4),
             LeSetDefaultPhy => (35, 5),
             LeSetPhy => (35, 6),
@@ -132,6 +260,19 @@ impl Opcode {
             LeSetPeriodicAdvertisingParameters => (37, 2),
             LeSetPeriodicAdvertisingData => (37, 3),
             LeSetPeriodicAdvertisingEnable => (37, 4),
+            LeSetExtendedScanParameters => (37, 5),
+            LeSetExtendedScanEnable => (37, 6),
+            LeExtendedCreateConnection => (37, 7),
+            LeReadTransmitPower => (38, 7),
+            LeSetConnectionCteReceiveParameters => (40, 2),
+            LeSetConnectionCteTransmitParameters => (40, 3),
+            LeConnectionCteRequestEnable => (40, 4),
+            LeConnectionCteResponseEnable => (40, 5),
+            LeEnhancedReadTransmitPowerLevel => (44, 1),
+            LeReadRemoteTransmitPowerLevel => (44, 2),
+            LeSetPathLossReportingParameters => (44, 3),
+            LeSetPathLossReportingEnable => (44, 4),
+            LeSetTransmitPowerReportingEnable => (44, 5),
         };
         (octet, ((bit >> 3 == 0) as u8).wrapping_shl(bit))
     }
@@ -141,14 +282,14 @@ impl Opcode {
 #[derive(Clone, Copy)]
 #[repr(u16)]
 enum OpcodeGroup {
-    _LinkControl = 0x01,
+    LinkControl = 0x01,
     _LinkPolicy = 0x02,
     HciControl = 0x03,
     InfoParams = 0x04,
-    _StatusParams = 0x05,
+    StatusParams = 0x05,
     _Testing = 0x06,
     Le = 0x08,
-    _Vendor = 0x3F, // [Vol 4] Part E, Section 5.4.1
+    Vendor = 0x3F, // [Vol 4] Part E, Section 5.4.1
 }
 
 impl OpcodeGroup {
@@ -465,21 +606,21 @@ impl EventCode {
             LeMetaEvent => true,                                        // Required
             LeConnectionComplete => true,                               // Required
             LeAdvertisingReport => false,                               // Central support
-            LeConnectionUpdateComplete => false,                        // Unused
+            LeConnectionUpdateComplete => true,                         // Optional
             LeReadRemoteFeaturesComplete => true,                       // Required
             LeLongTermKeyRequest => true,                               // Required
             LeRemoteConnectionParameterRequest => true,                 // Optional
-            LeDataLengthChange => false,                                // Unused
+            LeDataLengthChange => true,                                 // Optional
             LeReadLocalP256PublicKeyComplete => false,                  // Unused
             LeGenerateDhKeyComplete => false,                           // Unused
             LeEnhancedConnectionComplete => true,                       // Optional
             LeDirectedAdvertisingReport => false,                       // Central support
-            LePhyUpdateComplete => false,                               // Unused
-            LeExtendedAdvertisingReport => false,                       // Central support
+            LePhyUpdateComplete => true,                                // Optional
+            LeExtendedAdvertisingReport => true,                        // Central support
             LePeriodicAdvertisingSyncEstablished => false,              // Periodic adv support
             LePeriodicAdvertisingReport => false,                       // Periodic adv support
             LePeriodicAdvertisingSyncLost => false,                     // Periodic adv support
-            LeScanTimeout => false,                                     // Central support
+            LeScanTimeout => true,                                      // Central support
             LeAdvertisingSetTerminated => true,                         // Required
             LeScanRequestReceived => false,                             // Unused
             LeChannelSelectionAlgorithm => false,                       // Unused
@@ -494,10 +635,10 @@ impl EventCode {
             LeBigSyncEstablished => false,                              // BIG support
             LeBigSyncLost => false,                                     // BIG support
             LeRequestPeerScaComplete => false,                          // SCA support
-            LePathLossThreshold => false,                               // Unused
-            LeTransmitPowerReporting => false,                          // Unused
+            LePathLossThreshold => true,                                // Optional
+            LeTransmitPowerReporting => true,                           // Optional
             LeBigInfoAdvertisingReport => false,                        // BIG support
-            LeSubrateChange => false,                                   // Unused
+            LeSubrateChange => true,                                    // Optional
             TriggeredClockCapture => false,                             // BR/EDR only
             SynchronizationTrainComplete => false,                      // BR/EDR only
             SynchronizationTrainReceived => false,                      // BR/EDR only
@@ -507,7 +648,7 @@ impl EventCode {
             PeripheralPageResponseTimeout => false,                     // BR/EDR only
             ConnectionlessPeripheralBroadcastChannelMapChange => false, // BR/EDR only
             InquiryResponseNotification => false,                       // BR/EDR only
-            AuthenticatedPayloadTimeoutExpired => false,                // Unused
+            AuthenticatedPayloadTimeoutExpired => true,                 // Optional
             SamStatusChange => false,                                   // BR/EDR only
             Vendor => true,                                             // Unmaskable
         }
@@ -868,6 +1009,42 @@ bitflags::bitflags! {
     }
 }
 
+/// Preferred coding when transmitting on the LE Coded PHY
+/// ([Vol 4] Part E, Section 7.8.49).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, num_enum::IntoPrimitive)]
+#[non_exhaustive]
+#[repr(u16)]
+pub enum CodedPhy {
+    #[default]
+    NoPreference = 0x0000,
+    S2 = 0x0001,
+    S8 = 0x0002,
+}
+
+/// PHY and coding for which a transmit power level is reported
+/// ([Vol 4] Part E, Section 7.8.117).
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum PowerPhy {
+    Le1M = 0x01,
+    Le2M = 0x02,
+    LeCodedS8 = 0x03,
+    LeCodedS2 = 0x04,
+}
+
+/// Path loss zone entered by a connection ([Vol 4] Part E, Section 7.7.65.32).
+#[derive(Clone, Copy, Debug, Eq, PartialEq, num_enum::TryFromPrimitive)]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum ZoneEntered {
+    Low = 0x00,
+    Mid = 0x01,
+    High = 0x02,
+}
+
 bitflags::bitflags! {
     /// Basic properties of an advertising event
     /// ([Vol 4] Part E, Section 7.8.53).
@@ -945,6 +1122,50 @@ pub enum AdvFilterPolicy {
     FilterAll = 0x03,
 }
 
+/// Scanning filter policy ([Vol 4] Part E, Section 7.8.64).
+#[allow(clippy::exhaustive_enums)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, num_enum::IntoPrimitive)]
+#[repr(u8)]
+pub enum ScanFilterPolicy {
+    /// Accept all advertising packets except directed packets not addressed to
+    /// this device.
+    #[default]
+    None = 0x00,
+    /// Accept only advertising packets from devices in the Filter Accept List.
+    FilterAcceptList = 0x01,
+}
+
+bitflags::bitflags! {
+    /// Advertising report event type
+    /// ([Vol 4] Part E, Section 7.7.65.13).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct AdvReportType: u16 {
+        const CONNECTABLE = 1 << 0;
+        const SCANNABLE = 1 << 1;
+        const DIRECTED = 1 << 2;
+        const SCAN_RESPONSE = 1 << 3;
+        const LEGACY = 1 << 4;
+        /// More data to come in subsequent reports.
+        const INCOMPLETE = 0b01 << 5;
+        /// Data truncated with no more to come.
+        const TRUNCATED = 0b10 << 5;
+    }
+}
+
+/// Data status of an extended advertising report
+/// ([Vol 4] Part E, Section 7.7.65.13).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AdvDataStatus {
+    /// Data is complete.
+    Complete,
+    /// Data is incomplete with more to come in subsequent reports.
+    Incomplete,
+    /// Data is incomplete and truncated with no more to come.
+    Truncated,
+}
+
 /// Defines the interpretation of advertising data
 /// ([Vol 4] Part E, Section 7.8.54).
 #[allow(clippy::exhaustive_enums)]
@@ -963,6 +1184,48 @@ pub enum AdvDataOp {
     Unchanged = 0x04,
 }
 
+/// Constant Tone Extension type ([Vol 4] Part E, Section 7.8.85).
+#[allow(clippy::exhaustive_enums)]
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum CteType {
+    /// Angle of Arrival CTE.
+    AoA = 0x00,
+    /// Angle of Departure CTE with 1 µs slots.
+    AoD1us = 0x01,
+    /// Angle of Departure CTE with 2 µs slots.
+    AoD2us = 0x02,
+}
+
+bitflags::bitflags! {
+    /// Allowed Constant Tone Extension types
+    /// ([Vol 4] Part E, Section 7.8.84).
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(transparent)]
+    pub struct CteTypes: u8 {
+        const AOA = 1 << 0;
+        const AOD_1US = 1 << 1;
+        const AOD_2US = 1 << 2;
+    }
+}
+
+/// Switching and sampling slot duration for receiving Constant Tone Extensions
+/// ([Vol 4] Part E, Section 7.8.83).
+#[allow(clippy::exhaustive_enums)]
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum CteSlot {
+    /// 1 µs slots.
+    #[default]
+    Us1 = 0x01,
+    /// 2 µs slots.
+    Us2 = 0x02,
+}
+
 bitflags::bitflags! {
     /// LMP feature support bitmask ([Vol 2] Part C, Section 3.3 and 3.5.2).
     #[derive(Clone, Copy, Debug, Default)]
@@ -1131,6 +1394,20 @@ mod tests {
         assert_eq!(SetEventMask.mask(), (5, 1 << 6));
         assert_eq!(Reset.mask(), (5, 1 << 7));
         assert_eq!(LeSetEventMask.mask(), (25, 1 << 0));
+        assert_eq!(LeRemoteConnectionParameterRequestReply.mask(), (33, 1 << 4));
+        assert_eq!(LeExtendedCreateConnection.mask(), (37, 1 << 7));
+        assert_eq!(LeConnectionCteResponseEnable.mask(), (40, 1 << 5));
+        assert_eq!(Vendor.mask(), (0, 0));
+    }
+
+    #[test]
+    fn vendor_opcode() {
+        assert_eq!(Opcode::vendor(0x0001), 0xFC01);
+        assert_eq!(Opcode::vendor(0x03FF), 0xFFFF);
+        assert_eq!(Opcode::from_raw(0xFC01), Opcode::Vendor);
+        assert_eq!(Opcode::from_raw(0xFFFF), Opcode::Vendor);
+        assert_eq!(Opcode::from_raw(0x0C03), Opcode::Reset);
+        assert_eq!(Opcode::from_raw(0x0000), Opcode::None);
     }
 
     #[test]
@@ -0,0 +1,41 @@
+use super::*;
+
+/// Connection Constant Tone Extension procedures used for direction finding
+/// ([Vol 6] Part B, Section 2.5 and [Vol 4] Part E, Section 7.8.83 - 7.8.86).
+impl Host {
+    /// Configures and enables CTE responses on the specified connection,
+    /// allowing the peer to request CTEs for angle of arrival or departure
+    /// estimation. Antenna switching is only used for AoD CTEs.
+    pub async fn enable_cte_response(
+        &self,
+        h: ConnHandle,
+        types: CteTypes,
+        antenna_ids: &[u8],
+    ) -> Result<()> {
+        (self.le_set_connection_cte_transmit_parameters(h, types, antenna_ids)).await?;
+        self.le_connection_cte_response_enable(h, true).await
+    }
+
+    /// Disables CTE responses on the specified connection.
+    #[inline]
+    pub async fn disable_cte_response(&self, h: ConnHandle) -> Result<()> {
+        self.le_connection_cte_response_enable(h, false).await
+    }
+
+    /// Calls `f` for each `HCI_LE_Connection_IQ_Report` event until an error
+    /// is encountered. The event must be enabled in the event mask passed to
+    /// [`Self::init`], and CTE requests must be enabled with
+    /// [`Self::le_connection_cte_request_enable`].
+    pub async fn on_iq_report(
+        &self,
+        mut f: impl FnMut(&LeConnectionIqReport) + Send,
+    ) -> Result<()> {
+        let mut ctl = self.events();
+        loop {
+            let evt = ctl.next().await?;
+            if evt.code() == EventCode::LeConnectionIqReport {
+                f(&evt.get());
+            }
+        }
+    }
+}
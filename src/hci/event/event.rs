@@ -1,4 +1,5 @@
-use std::collections::VecDeque;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeSet;
 use std::fmt::Formatter;
 use std::future::Future;
 use std::marker::PhantomData;
@@ -9,7 +10,7 @@ use std::time::Duration;
 
 use structbuf::Unpacker;
 use tokio::time::timeout;
-use tracing::{trace, warn};
+use tracing::{debug, trace, warn};
 
 pub use {hci::*, le::*};
 
@@ -46,7 +47,7 @@ impl EventHeader {
             0
         };
         if usize::from(len) != rem || !p.is_ok() {
-            return Err(Error::InvalidEvent(Vec::from(raw)));
+            return Err(Error::InvalidEvent(EventBytes::new(raw)));
         }
         let code = match EventCode::try_from(u16::from(subcode) << 8 | u16::from(code)) {
             Ok(code) if !matches!(code, EventCode::LeMetaEvent) => code,
@@ -54,7 +55,7 @@ impl EventHeader {
                 return Err(Error::UnknownEvent {
                     code,
                     subcode,
-                    params: Vec::from(p.as_ref()),
+                    params: EventBytes::new(p.as_ref()),
                 })
             }
         };
@@ -65,7 +66,7 @@ impl EventHeader {
         match code {
             EventCode::CommandComplete => {
                 hdr.cmd_quota = p.u8();
-                hdr.opcode = Opcode::from(p.u16());
+                hdr.opcode = Opcode::from_raw(p.u16());
                 if !p.is_empty() {
                     hdr.status = Status::from(p.u8());
                 }
@@ -73,7 +74,7 @@ impl EventHeader {
             EventCode::CommandStatus => {
                 hdr.status = Status::from(p.u8());
                 hdr.cmd_quota = p.u8();
-                hdr.opcode = Opcode::from(p.u16());
+                hdr.opcode = Opcode::from_raw(p.u16());
             }
             _ => {
                 let pf = code.param_fmt();
@@ -92,7 +93,7 @@ impl EventHeader {
         if p.is_ok() {
             Ok((hdr, p.into_inner()))
         } else {
-            Err(Error::InvalidEvent(Vec::from(raw)))
+            Err(Error::InvalidEvent(EventBytes::new(raw)))
         }
     }
 }
@@ -183,14 +184,25 @@ impl Event {
         }
     }
 
-    /// Unpacks event parameters.
+    /// Unpacks event parameters of a non-command event. Events received from
+    /// the router are validated before delivery, so this only fails if the
+    /// event was not validated.
     ///
     /// # Panics
     ///
-    /// Panics if `T` is not a representation of the event.
+    /// Panics if `T` is not a representation of the event or the parameters
+    /// cannot be decoded.
     #[inline]
     #[must_use]
-    pub(crate) fn get<T: FromEvent>(&self) -> T {
+    pub(crate) fn get<T: TryFromEvent>(&self) -> T {
+        self.try_get()
+            .unwrap_or_else(|e| panic!("unvalidated event: {e}"))
+    }
+
+    /// Unpacks event parameters or returns an error if the parameters cannot
+    /// be decoded.
+    #[inline]
+    pub(crate) fn try_get<T: TryFromEvent>(&self) -> DecodeResult<T> {
         debug_assert!(
             T::matches(self.code()),
             "event type mismatch for {}",
@@ -200,26 +212,28 @@ impl Event {
     }
 
     /// Unpacks successful command completion or status parameters. Returns an
-    /// error if the command failed.
+    /// error if the command failed or the parameters cannot be decoded.
     ///
     /// # Panics
     ///
-    /// Panics for non-command events or if `T` is not a representation of the
-    /// event.
+    /// Panics for non-command events.
     #[inline]
-    pub(crate) fn ok<T: FromEvent>(&self) -> Result<T> {
-        self.cmd_ok().map(|_| self.get())
+    pub(crate) fn ok<T: TryFromEvent>(&self) -> Result<T> {
+        self.cmd_ok()?;
+        Ok(self.try_get()?)
     }
 
     /// Calls `f` to unpack successful command completion or status parameters.
-    /// Returns an error if the command failed.
+    /// Returns an error if the command failed or the parameters cannot be
+    /// decoded.
     ///
     /// # Panics
     ///
     /// Panics for non-command events.
     #[inline]
     pub(crate) fn map_ok<T>(&self, f: impl FnOnce(&Self, &mut Unpacker) -> T) -> Result<T> {
-        self.cmd_ok().map(|_| self.unpack(f))
+        self.cmd_ok()?;
+        Ok(self.unpack(|e, p| Ok(f(e, p)))?)
     }
 
     /// Ensures that the event represent successful command status or
@@ -240,15 +254,77 @@ impl Event {
         }
     }
 
-    /// Calls `f` to unpack event parameters.
+    /// Ensures that the parameters of a non-command event can be decoded.
+    /// Events without a decoder are always valid.
+    pub(super) fn validate(&self) -> DecodeResult<()> {
+        fn check<T: TryFromEvent>(e: &Event) -> DecodeResult<()> {
+            e.unpack(T::check)
+        }
+        match self.code() {
+            EventCode::DisconnectionComplete => check::<DisconnectionComplete>(self),
+            EventCode::EncryptionChange | EventCode::EncryptionChangeV2 => {
+                check::<EncryptionChange>(self)
+            }
+            EventCode::NumberOfCompletedPackets => check::<NumberOfCompletedPackets>(self),
+            EventCode::LeConnectionComplete | EventCode::LeEnhancedConnectionComplete => {
+                check::<LeConnectionComplete>(self)
+            }
+            EventCode::LeConnectionUpdateComplete => check::<LeConnectionUpdateComplete>(self),
+            EventCode::LeReadRemoteFeaturesComplete => check::<LeReadRemoteFeaturesComplete>(self),
+            EventCode::LeLongTermKeyRequest => check::<LeLongTermKeyRequest>(self),
+            EventCode::LeRemoteConnectionParameterRequest => {
+                check::<LeRemoteConnectionParameterRequest>(self)
+            }
+            EventCode::LeDataLengthChange => check::<LeDataLengthChange>(self),
+            EventCode::LePhyUpdateComplete => check::<LePhyUpdateComplete>(self),
+            EventCode::LeExtendedAdvertisingReport => check::<LeExtendedAdvertisingReport>(self),
+            EventCode::LeAdvertisingSetTerminated => check::<LeAdvertisingSetTerminated>(self),
+            EventCode::LePathLossThreshold => check::<LePathLossThreshold>(self),
+            EventCode::LeTransmitPowerReporting => check::<LeTransmitPowerReporting>(self),
+            EventCode::LeSubrateChange => check::<LeSubrateChange>(self),
+            EventCode::LeConnectionIqReport => check::<LeConnectionIqReport>(self),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the connection handle of a connection event or an error if the
+    /// handle is invalid.
+    #[inline]
+    pub(crate) fn valid_conn_handle(&self, p: &Unpacker) -> DecodeResult<ConnHandle> {
+        (self.conn_handle()).ok_or_else(|| self.invalid("connection handle", p))
+    }
+
+    /// Returns an error for an invalid `field`. The remaining parameters are
+    /// read from `p`.
     #[inline]
     #[must_use]
-    fn unpack<T>(&self, f: impl FnOnce(&Self, &mut Unpacker) -> T) -> T {
+    pub(crate) fn invalid(&self, field: &'static str, p: &Unpacker) -> DecodeError {
+        DecodeError::InvalidField {
+            code: self.code(),
+            field,
+            rem: EventBytes::new(p.as_ref()),
+        }
+    }
+
+    /// Calls `f` to unpack event parameters, ensuring that all parameters are
+    /// consumed without reading past the end.
+    fn unpack<T>(
+        &self,
+        f: impl FnOnce(&Self, &mut Unpacker) -> DecodeResult<T>,
+    ) -> DecodeResult<T> {
         let mut p = self.0.params();
-        let v = f(self, &mut p);
-        debug_assert!(p.is_ok(), "unexpected {} event format", self.code());
-        debug_assert!(p.is_empty(), "unconsumed {} event", self.code());
-        v
+        let r = f(self, &mut p);
+        if !p.is_ok() {
+            return Err(DecodeError::Truncated { code: self.code() });
+        }
+        let v = r?;
+        if !p.is_empty() {
+            return Err(DecodeError::Unconsumed {
+                code: self.code(),
+                rem: EventBytes::new(p.as_ref()),
+            });
+        }
+        Ok(v)
     }
 }
 
@@ -259,8 +335,85 @@ impl Debug for Event {
     }
 }
 
+/// Error returned when event parameters cannot be decoded.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum DecodeError {
+    #[error("truncated {code} event")]
+    Truncated { code: EventCode },
+    #[error("{code} event with {} unconsumed bytes: {rem:?}", .rem.total_len())]
+    Unconsumed { code: EventCode, rem: EventBytes },
+    #[error("invalid {code} event {field} with {} remaining bytes: {rem:?}", .rem.total_len())]
+    InvalidField {
+        code: EventCode,
+        field: &'static str,
+        rem: EventBytes,
+    },
+}
+
+/// Copy of raw event bytes carried by errors. At most [`EventBytes::CAP`]
+/// bytes are retained, which avoids heap allocation when malformed or unknown
+/// events are received.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct EventBytes {
+    buf: [u8; Self::CAP],
+    total: usize,
+}
+
+impl EventBytes {
+    /// Maximum number of retained bytes.
+    pub const CAP: usize = 32;
+
+    /// Creates a copy of `b`, truncated to [`Self::CAP`] bytes.
+    #[must_use]
+    pub fn new(b: &[u8]) -> Self {
+        let mut buf = [0; Self::CAP];
+        let n = b.len().min(Self::CAP);
+        buf[..n].copy_from_slice(&b[..n]);
+        Self {
+            buf,
+            total: b.len(),
+        }
+    }
+
+    /// Returns the length of the original byte slice.
+    #[inline(always)]
+    #[must_use]
+    pub const fn total_len(&self) -> usize {
+        self.total
+    }
+
+    /// Returns whether the copy is truncated.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_truncated(&self) -> bool {
+        self.total > Self::CAP
+    }
+}
+
+impl AsRef<[u8]> for EventBytes {
+    /// Returns the retained bytes.
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[..self.total.min(Self::CAP)]
+    }
+}
+
+impl Debug for EventBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02X?}", self.as_ref())?;
+        if self.is_truncated() {
+            write!(f, "..")?;
+        }
+        Ok(())
+    }
+}
+
+/// Event decoding result type.
+pub(crate) type DecodeResult<T> = std::result::Result<T, DecodeError>;
+
 /// Trait for unpacking event parameters.
-pub(crate) trait FromEvent {
+pub(crate) trait TryFromEvent: Sized {
     /// Returns whether `unpack` supports event code `c`.
     #[inline(always)]
     #[must_use]
@@ -269,15 +422,24 @@ pub(crate) trait FromEvent {
     }
 
     /// Unpacks event parameters. Implementations must consume `p` fully without
-    /// reading past the end.
-    #[must_use]
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self;
+    /// reading past the end, and return an error for any parameter with an
+    /// invalid value.
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self>;
+
+    /// Checks whether event parameters can be unpacked. Implementations that
+    /// allocate in `unpack` should override this method to avoid allocation.
+    #[inline]
+    fn check(e: &Event, p: &mut Unpacker) -> DecodeResult<()> {
+        Self::unpack(e, p).map(|_| ())
+    }
 }
 
 /// Unpacker for command completion events without parameters.
-impl FromEvent for () {
+impl TryFromEvent for () {
     #[inline(always)]
-    fn unpack(_: &Event, _: &mut Unpacker) -> Self {}
+    fn unpack(_: &Event, _: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(())
+    }
 }
 
 /// Extension trait providing [`Event`]-specific [`Unpacker`] methods.
@@ -298,6 +460,13 @@ impl EventUnpacker for Unpacker<'_> {
 /// receivers are notified in a broadcast fashion, and must process and drop the
 /// event before the next one can be received. Command status and completion
 /// events are delivered to exactly one receiver with the matching [`Opcode`].
+///
+/// Non-command event streams never conflict with each other, so multiple
+/// subsystems (e.g. `ChanManager` and `SecDb`) can each observe the same
+/// connection events. Command receivers conflict only if they have the same
+/// opcode, in which case the later command waits until the earlier receiver is
+/// dropped. Commands with different opcodes can execute concurrently, subject
+/// to the controller's command quota.
 #[derive(Debug)]
 pub(super) struct EventRouter {
     monitor: SyncMutex<Monitor>,
@@ -331,7 +500,15 @@ impl EventRouter {
     /// Returns a non-command event stream.
     #[inline(always)]
     pub fn events(self: &Arc<Self>) -> EventStream {
-        self.events_locked(self.monitor.lock(), Opcode::None)
+        self.events_locked(self.monitor.lock(), Opcode::None, None)
+    }
+
+    /// Returns a non-command event stream that only receives events for
+    /// connection `hdl`. The stream does not receive any events after
+    /// `DisconnectionComplete`.
+    #[inline(always)]
+    pub fn conn_events(self: &Arc<Self>, hdl: ConnHandle) -> EventStream {
+        self.events_locked(self.monitor.lock(), Opcode::None, Some(hdl))
     }
 
     /// Registers a new event stream.
@@ -340,12 +517,14 @@ impl EventRouter {
         self: &Arc<Self>,
         mut m: SyncMutexGuard<Monitor>,
         opcode: Opcode,
+        conn: Option<ConnHandle>,
     ) -> EventStream {
         let id = m.next_id;
         m.next_id = m.next_id.checked_add(1).expect("overflow");
-        m.queue.push_back(Receiver {
+        m.register(Receiver {
             id,
             opcode,
+            conn,
             ..Receiver::default()
         });
         EventStream {
@@ -362,6 +541,12 @@ impl EventRouter {
         (m.conns.get(&hdl)).map(tokio::sync::watch::Sender::subscribe)
     }
 
+    /// Returns the handles of all established connections.
+    #[inline]
+    pub fn conn_handles(&self) -> Vec<ConnHandle> {
+        self.monitor.lock().conns.keys().copied().collect()
+    }
+
     /// Calls `f` to update connection parameters. This is a no-op if the handle
     /// is invalid.
     #[inline]
@@ -384,41 +569,68 @@ impl EventRouter {
         // receiver is blocked on RwLock::write_owned().
         let rwlock = self.xfer.try_lock().expect("multiple event receivers");
 
-        // Wait for all event handles to be dropped and receive the next event.
-        // Clippy checks that Event handles are not held across await points, so
-        // the caller can't deadlock, but it's possible that another Receiver
-        // with an event is not being polled. The timeout will detect this.
-        let mut xfer = timeout(Duration::from_secs(3), Arc::clone(&rwlock).write_owned())
-            .await
-            .expect("EventRouter stalled (Event not handled)");
-        xfer.next(t).await.map_err(|e| {
-            // Fatal transport error
-            let mut m = self.monitor.lock();
-            m.err = Some(e);
-            for r in &mut m.queue {
-                r.ready = None;
-                if let Some(w) = r.waker.take() {
-                    w.wake();
+        loop {
+            // Wait for all event handles to be dropped and receive the next
+            // event. Clippy checks that Event handles are not held across await
+            // points, so the caller can't deadlock, but it's possible that
+            // another Receiver with an event is not being polled. The timeout
+            // will detect this.
+            let mut xfer = timeout(Duration::from_secs(3), Arc::clone(&rwlock).write_owned())
+                .await
+                .expect("EventRouter stalled (Event not handled)");
+            xfer.next(t).await.map_err(|e| {
+                // Fatal transport error
+                let mut m = self.monitor.lock();
+                m.err = Some(e);
+                for r in m.queue.values_mut() {
+                    r.ready = None;
+                    if let Some(w) = r.waker.take() {
+                        w.wake();
+                    }
                 }
+                e
+            })?;
+
+            let evt = Event::new(xfer)?;
+            // A malformed event from a buggy controller must not stop the
+            // router, so it is discarded without notifying any receivers.
+            if let Err(e) = evt.validate() {
+                warn!("Discarding undecodable event: {e}");
+                self.monitor.lock().invalid_events += 1;
+                continue;
             }
-            e
-        })?;
+            self.monitor.lock().notify(&rwlock, &evt);
+            return Ok(evt);
+        }
+    }
 
-        let evt = Event::new(xfer)?;
-        self.monitor.lock().notify(&rwlock, &evt);
-        Ok(evt)
+    /// Returns the number of events that were discarded because their
+    /// parameters could not be decoded.
+    #[inline]
+    #[must_use]
+    pub fn invalid_events(&self) -> u64 {
+        self.monitor.lock().invalid_events
     }
 }
 
-/// Receiver queue and router state monitor.
+/// Receiver queue and router state monitor. Receivers are indexed by type and
+/// connection handle, so the cost of routing an event does not depend on the
+/// number of connections.
 #[derive(Debug)]
 struct Monitor {
     conns: BTreeMap<ConnHandle, tokio::sync::watch::Sender<Conn>>,
-    queue: VecDeque<Receiver>,
+    queue: BTreeMap<u64, Receiver>,
+    /// Command receivers.
+    cmds: BTreeSet<u64>,
+    /// Receivers of all non-command events.
+    all: BTreeSet<u64>,
+    /// Receivers of non-command events for one connection.
+    by_conn: BTreeMap<ConnHandle, BTreeSet<u64>>,
     err: Option<host::Error>,
     next_id: u64,
     cmd_quota: u8,
     cmd_wakers: Vec<Waker>,
+    invalid_events: u64,
 }
 
 impl Monitor {
@@ -459,18 +671,23 @@ impl Monitor {
             }
             self.set_cmd_quota(hdr.cmd_quota);
             if hdr.opcode.is_some() {
-                (self.queue.iter_mut().find(|r| r.opcode == hdr.opcode)).map_or_else(
-                    || warn!("Ignored {} command completion", hdr.opcode),
-                    |r| r.ready(xfer),
-                );
+                let id = (self.cmds.iter()).find(|&id| self.queue[id].opcode == hdr.opcode);
+                match id.copied() {
+                    Some(id) => self.get(id).ready(xfer),
+                    None => warn!("Ignored {} command completion", hdr.opcode),
+                }
             }
             return;
         }
+        let span = (evt.conn_handle().and_then(|h| self.conns.get(&h)))
+            .map_or_else(tracing::Span::none, |s| s.borrow().span.clone());
+        let _span = span.enter();
         match hdr.code {
             DisconnectionComplete => {
                 if hdr.status.is_ok() {
                     let e: super::DisconnectionComplete = evt.get();
                     if let Some(s) = self.conns.remove(&e.handle) {
+                        debug!("Disconnected: {}", e.reason);
                         s.send_modify(|cn| cn.disconnect_reason = Some(e.reason));
                     }
                 }
@@ -482,26 +699,114 @@ impl Monitor {
                 if hdr.status.is_ok() {
                     let e: super::LeConnectionComplete = evt.get();
                     let (cn, _) = tokio::sync::watch::channel(Conn::new(&e));
+                    cn.borrow().span.in_scope(|| debug!("Connected"));
                     let old = self.conns.insert(e.handle, cn);
                     assert!(old.is_none(), "duplicate connection handle");
                 }
             }
+            LeConnectionUpdateComplete => {
+                if hdr.status.is_ok() {
+                    let e: super::LeConnectionUpdateComplete = evt.get();
+                    if let Some(s) = self.conns.get(&e.handle) {
+                        s.send_modify(|cn| cn.params = e.into());
+                    }
+                }
+            }
+            LeDataLengthChange => {
+                let e: super::LeDataLengthChange = evt.get();
+                if let Some(s) = self.conns.get(&e.handle) {
+                    s.send_modify(|cn| cn.max_octets = (e.max.tx_octets, e.max.rx_octets));
+                }
+            }
+            LeReadRemoteFeaturesComplete => {
+                if hdr.status.is_ok() {
+                    let e: super::LeReadRemoteFeaturesComplete = evt.get();
+                    if let Some(s) = self.conns.get(&e.handle) {
+                        s.send_modify(|cn| cn.remote_features = Some(e.features));
+                    }
+                }
+            }
+            LeSubrateChange => {
+                if hdr.status.is_ok() {
+                    let e: super::LeSubrateChange = evt.get();
+                    if let Some(s) = self.conns.get(&e.handle) {
+                        s.send_modify(|cn| cn.subrate_factor = e.subrate_factor);
+                    }
+                }
+            }
+            LePhyUpdateComplete => {
+                if hdr.status.is_ok() {
+                    let e: super::LePhyUpdateComplete = evt.get();
+                    if let Some(s) = self.conns.get(&e.handle) {
+                        s.send_modify(|cn| cn.phy = (e.tx_phy, e.rx_phy));
+                    }
+                }
+            }
             _ => {}
         }
+        let Self {
+            queue,
+            all,
+            by_conn,
+            ..
+        } = self;
+        let hdl = evt.conn_handle();
+        let conn = hdl.and_then(|h| by_conn.get(&h)).into_iter().flatten();
         let mut received = false;
-        for r in self.queue.iter_mut().filter(|r| r.opcode.is_none()) {
-            r.ready(xfer);
+        for id in all.iter().chain(conn) {
+            queue.get_mut(id).expect("invalid receiver").ready(xfer);
             received = true;
         }
         if !received {
             trace!("Ignored event: {evt:?}");
         }
+        if let (DisconnectionComplete, true, Some(h)) = (hdr.code, hdr.status.is_ok(), hdl) {
+            // Connection handle may be reused after this event
+            self.by_conn.remove(&h);
+        }
+    }
+
+    /// Registers a new receiver.
+    fn register(&mut self, r: Receiver) {
+        if r.opcode.is_some() {
+            self.cmds.insert(r.id);
+        } else if let Some(h) = r.conn {
+            self.by_conn.entry(h).or_default().insert(r.id);
+        } else {
+            self.all.insert(r.id);
+        }
+        assert!(self.queue.insert(r.id, r).is_none(), "duplicate receiver");
+    }
+
+    /// Unregisters a receiver, returning it to the caller.
+    fn unregister(&mut self, id: u64) -> Option<Receiver> {
+        let r = self.queue.remove(&id)?;
+        if r.opcode.is_some() {
+            self.cmds.remove(&id);
+        } else if let Some(h) = r.conn {
+            if let Entry::Occupied(mut e) = self.by_conn.entry(h) {
+                e.get_mut().remove(&id);
+                if e.get().is_empty() {
+                    e.remove();
+                }
+            }
+        } else {
+            self.all.remove(&id);
+        }
+        Some(r)
+    }
+
+    /// Returns whether a command with the specified opcode conflicts with any
+    /// registered receiver.
+    #[inline]
+    fn conflicts(&self, opcode: Opcode) -> bool {
+        (self.cmds.iter()).any(|id| self.queue[id].conflicts_with(opcode))
     }
 
     /// Returns the receiver with the specified `id`.
     #[inline(always)]
     fn get(&mut self, id: u64) -> &mut Receiver {
-        (self.queue.iter_mut().find(|r| r.id == id)).expect("unregistered event receiver")
+        (self.queue.get_mut(&id)).expect("unregistered event receiver")
     }
 }
 
@@ -510,11 +815,15 @@ impl Default for Monitor {
     fn default() -> Self {
         Self {
             conns: BTreeMap::new(),
-            queue: VecDeque::with_capacity(8),
+            queue: BTreeMap::new(),
+            cmds: BTreeSet::new(),
+            all: BTreeSet::new(),
+            by_conn: BTreeMap::new(),
             err: None,
             next_id: 0,
             cmd_quota: 1, // [Vol 4] Part E, Section 4.4
             cmd_wakers: Vec::new(),
+            invalid_events: 0,
         }
     }
 }
@@ -524,11 +833,22 @@ impl Default for Monitor {
 struct Receiver {
     id: u64,
     opcode: Opcode,
+    conn: Option<ConnHandle>,
     ready: Option<EventRef>,
     waker: Option<Waker>,
 }
 
 impl Receiver {
+    /// Returns whether a command receiver with the specified opcode can't be
+    /// registered concurrently with this receiver. Command completion events
+    /// are routed by opcode, so only receivers for the same command conflict.
+    /// Non-command receivers ([`Opcode::None`]) never conflict because they
+    /// all receive every non-command event.
+    #[inline(always)]
+    fn conflicts_with(&self, opcode: Opcode) -> bool {
+        opcode.is_some() && self.opcode == opcode
+    }
+
     /// Provides a new event to and notifies the receiver.
     ///
     /// # Panics
@@ -563,7 +883,7 @@ impl Future for Reserve {
         let mut m = router.monitor.lock();
         // The spec doesn't say whether commands with the same opcode can be
         // executed concurrently, but it's safer to avoid.
-        if m.queue.iter().any(|r| r.opcode == self.opcode) || m.cmd_quota == 0 {
+        if m.conflicts(self.opcode) || m.cmd_quota == 0 {
             m.cmd_wakers.push(cx.waker().clone());
             return Poll::Pending;
         }
@@ -572,7 +892,7 @@ impl Future for Reserve {
         } else {
             m.cmd_quota -= 1;
         }
-        let events = router.events_locked(m, self.opcode);
+        let events = router.events_locked(m, self.opcode, None);
         Poll::Ready(CmdGuard {
             router: self.router.take(),
             events: Some(events),
@@ -685,10 +1005,7 @@ impl EventStream {
 impl Drop for EventStream {
     fn drop(&mut self) {
         let mut m = self.router.monitor.lock();
-        if let Some(i) = m.queue.iter().position(|r| r.id == self.id) {
-            // Order doesn't matter since we don't control the order in which
-            // async tasks are executed.
-            let Some(r) = m.queue.swap_remove_back(i) else { unreachable!() };
+        if let Some(r) = m.unregister(self.id) {
             if r.opcode.is_some() {
                 m.wake_cmds();
             }
@@ -725,7 +1042,7 @@ impl Future for NextEvent<'_, ()> {
 impl<T> Drop for NextEvent<'_, T> {
     fn drop(&mut self) {
         let mut m = self.0.router.monitor.lock();
-        if let Some(r) = m.queue.iter_mut().find(|r| r.id == self.0.id) {
+        if let Some(r) = m.queue.get_mut(&self.0.id) {
             r.waker = None;
         }
     }
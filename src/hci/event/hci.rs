@@ -11,18 +11,18 @@ pub struct DisconnectionComplete {
     pub reason: Status,
 }
 
-impl FromEvent for DisconnectionComplete {
+impl TryFromEvent for DisconnectionComplete {
     #[inline(always)]
     fn matches(c: EventCode) -> bool {
         matches!(c, EventCode::DisconnectionComplete)
     }
 
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self {
-        Self {
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
             status: e.status(),
-            handle: e.conn_handle().unwrap(),
+            handle: e.valid_conn_handle(p)?,
             reason: Status::from(p.u8()),
-        }
+        })
     }
 }
 
@@ -34,7 +34,7 @@ pub struct EncryptionChange {
     pub enabled: bool,
 }
 
-impl FromEvent for EncryptionChange {
+impl TryFromEvent for EncryptionChange {
     #[inline(always)]
     fn matches(c: EventCode) -> bool {
         matches!(
@@ -43,18 +43,18 @@ impl FromEvent for EncryptionChange {
         )
     }
 
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self {
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
         let enabled = p.bool();
         if matches!(e.code(), EventCode::EncryptionChangeV2) {
             // Encryption_Key_Size parameter must be consumed, but is ignored
             // for LE connections.
             let _ = p.u8();
         }
-        Self {
+        Ok(Self {
             status: e.status(),
-            handle: e.conn_handle().unwrap(),
+            handle: e.valid_conn_handle(p)?,
             enabled,
-        }
+        })
     }
 }
 
@@ -64,13 +64,13 @@ impl FromEvent for EncryptionChange {
 #[repr(transparent)]
 pub struct NumberOfCompletedPackets(SmallVec<[(ConnHandle, u16); 4]>);
 
-impl FromEvent for NumberOfCompletedPackets {
+impl TryFromEvent for NumberOfCompletedPackets {
     #[inline(always)]
     fn matches(c: EventCode) -> bool {
         matches!(c, EventCode::NumberOfCompletedPackets)
     }
 
-    fn unpack(_: &Event, p: &mut Unpacker) -> Self {
+    fn unpack(_: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
         let n = usize::from(p.u8());
         let mut v = SmallVec::with_capacity(n);
         for _ in 0..n {
@@ -78,7 +78,13 @@ impl FromEvent for NumberOfCompletedPackets {
                 v.push((cn, n));
             }
         }
-        Self(v)
+        Ok(Self(v))
+    }
+
+    fn check(_: &Event, p: &mut Unpacker) -> DecodeResult<()> {
+        let n = usize::from(p.u8());
+        p.skip(4 * n);
+        Ok(())
     }
 }
 
@@ -88,3 +94,28 @@ impl AsRef<[(ConnHandle, u16)]> for NumberOfCompletedPackets {
         self.0.as_ref()
     }
 }
+
+/// Vendor-specific event parameters ([Vol 4] Part E, Section 5.4.4).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct VendorEvent(Vec<u8>);
+
+impl TryFromEvent for VendorEvent {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::Vendor)
+    }
+
+    fn unpack(_: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        let n = p.len();
+        let v = p.skip(n).map_or_else(Vec::new, |d| d.into_inner().to_vec());
+        Ok(Self(v))
+    }
+}
+
+impl AsRef<[u8]> for VendorEvent {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
@@ -1,4 +1,7 @@
-use crate::le::{Addr, RawAddr};
+use smallvec::SmallVec;
+
+use crate::gap::ResponseDataIter;
+use crate::le::{Addr, RawAddr, TxPower};
 
 use super::*;
 
@@ -18,7 +21,7 @@ pub struct LeConnectionComplete {
     pub central_clock_accuracy: u16,
 }
 
-impl FromEvent for LeConnectionComplete {
+impl TryFromEvent for LeConnectionComplete {
     #[inline(always)]
     fn matches(c: EventCode) -> bool {
         matches!(
@@ -27,17 +30,33 @@ impl FromEvent for LeConnectionComplete {
         )
     }
 
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self {
-        let role = Role::try_from(p.u8()).expect("invalid role");
-        let peer_addr = Addr::peer(p.u8(), p.addr());
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        // Parameters other than the status are invalid when the connection
+        // was not created, such as after HCI_LE_Create_Connection_Cancel
+        // ([Vol 4] Part E, Section 7.7.65.10).
+        let ok = e.status().is_ok();
+        let role = match Role::try_from(p.u8()) {
+            Ok(role) => role,
+            Err(_) if !ok => Role::Central,
+            Err(_) => return Err(e.invalid("role", p)),
+        };
+        let peer_addr = match (p.u8(), p.addr()) {
+            (typ, raw) if typ <= 0x03 => Addr::peer(typ, raw),
+            _ if !ok => Addr::default(),
+            _ => return Err(e.invalid("peer address type", p)),
+        };
         let (local_rpa, peer_rpa) = match e.code() {
             EventCode::LeConnectionComplete => Default::default(),
             EventCode::LeEnhancedConnectionComplete => (p.addr(), p.addr()),
             code => unreachable!("unexpected {code} event"),
         };
-        Self {
+        Ok(Self {
             status: e.status(),
-            handle: e.conn_handle().unwrap(),
+            handle: match e.conn_handle() {
+                Some(hdl) => hdl,
+                None if !ok => ConnHandle::new(0).unwrap(),
+                None => return Err(e.invalid("connection handle", p)),
+            },
             role,
             peer_addr,
             local_rpa,
@@ -56,7 +75,59 @@ impl FromEvent for LeConnectionComplete {
                 0x07 => 20,
                 _ => 0,
             },
-        }
+        })
+    }
+}
+
+/// `HCI_LE_Connection_Update_Complete` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.3).
+#[derive(Clone, Copy, Debug)]
+pub struct LeConnectionUpdateComplete {
+    pub status: Status,
+    pub handle: ConnHandle,
+    pub conn_interval: Duration,
+    pub peripheral_latency: u16,
+    pub supervision_timeout: Duration,
+}
+
+impl TryFromEvent for LeConnectionUpdateComplete {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeConnectionUpdateComplete)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            status: e.status(),
+            handle: e.valid_conn_handle(p)?,
+            conn_interval: duration_1250us(p.u16()),
+            peripheral_latency: p.u16(),
+            supervision_timeout: duration_10ms(p.u16()),
+        })
+    }
+}
+
+/// `HCI_LE_Read_Remote_Features_Complete` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.4).
+#[derive(Clone, Copy, Debug)]
+pub struct LeReadRemoteFeaturesComplete {
+    pub status: Status,
+    pub handle: ConnHandle,
+    pub features: LeFeature,
+}
+
+impl TryFromEvent for LeReadRemoteFeaturesComplete {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeReadRemoteFeaturesComplete)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            status: e.status(),
+            handle: e.valid_conn_handle(p)?,
+            features: LeFeature::from_bits_retain(p.u64()),
+        })
     }
 }
 
@@ -69,18 +140,221 @@ pub struct LeLongTermKeyRequest {
     pub ediv: u16,
 }
 
-impl FromEvent for LeLongTermKeyRequest {
+impl TryFromEvent for LeLongTermKeyRequest {
     #[inline(always)]
     fn matches(c: EventCode) -> bool {
         matches!(c, EventCode::LeLongTermKeyRequest)
     }
 
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self {
-        Self {
-            handle: e.conn_handle().unwrap(),
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            handle: e.valid_conn_handle(p)?,
             rand: p.u64(),
             ediv: p.u16(),
+        })
+    }
+}
+
+/// `HCI_LE_Remote_Connection_Parameter_Request` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.6).
+#[derive(Clone, Copy, Debug)]
+pub struct LeRemoteConnectionParameterRequest {
+    pub handle: ConnHandle,
+    pub params: ConnParams,
+}
+
+impl TryFromEvent for LeRemoteConnectionParameterRequest {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeRemoteConnectionParameterRequest)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            handle: e.valid_conn_handle(p)?,
+            params: ConnParams {
+                interval: (duration_1250us(p.u16()), duration_1250us(p.u16())),
+                latency: p.u16(),
+                timeout: duration_10ms(p.u16()),
+            },
+        })
+    }
+}
+
+/// `HCI_LE_Data_Length_Change` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.7).
+#[derive(Clone, Copy, Debug)]
+pub struct LeDataLengthChange {
+    pub handle: ConnHandle,
+    pub max: DataLen,
+}
+
+impl TryFromEvent for LeDataLengthChange {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeDataLengthChange)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            handle: e.valid_conn_handle(p)?,
+            max: DataLen::unpack(e, p)?,
+        })
+    }
+}
+
+/// `HCI_LE_PHY_Update_Complete` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.12).
+#[derive(Clone, Copy, Debug)]
+pub struct LePhyUpdateComplete {
+    pub status: Status,
+    pub handle: ConnHandle,
+    pub tx_phy: Phy,
+    pub rx_phy: Phy,
+}
+
+impl TryFromEvent for LePhyUpdateComplete {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LePhyUpdateComplete)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            status: e.status(),
+            handle: e.valid_conn_handle(p)?,
+            tx_phy: Phy::try_from(p.u8()).unwrap_or_default(),
+            rx_phy: Phy::try_from(p.u8()).unwrap_or_default(),
+        })
+    }
+}
+
+/// `HCI_LE_Extended_Advertising_Report` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.13).
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct LeExtendedAdvertisingReport(SmallVec<[AdvReport; 1]>);
+
+impl TryFromEvent for LeExtendedAdvertisingReport {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeExtendedAdvertisingReport)
+    }
+
+    fn unpack(_: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        let n = usize::from(p.u8());
+        let mut v = SmallVec::with_capacity(n);
+        for _ in 0..n {
+            let typ = AdvReportType::from_bits_retain(p.u16());
+            let (addr_type, addr) = (p.u8(), p.addr());
+            v.push(AdvReport {
+                typ,
+                addr_type,
+                addr: AdvReport::addr(addr_type, addr),
+                pri_phy: Phy::try_from(p.u8()).unwrap_or_default(),
+                sec_phy: Phy::try_from(p.u8()).ok(),
+                sid: Some(p.u8()).filter(|&sid| sid <= 0x0F),
+                tx_power: TxPower::from_hci(p.i8()),
+                rssi: Some(p.i8()).filter(|&rssi| rssi != 0x7F),
+                periodic_interval: duration_1250us(p.u16()),
+                direct_addr: AdvReport::addr(p.u8(), p.addr()),
+                data: {
+                    let n = usize::from(p.u8());
+                    p.skip(n).map_or_else(Vec::new, |d| d.into_inner().to_vec())
+                },
+            });
+        }
+        Ok(Self(v))
+    }
+
+    fn check(_: &Event, p: &mut Unpacker) -> DecodeResult<()> {
+        for _ in 0..p.u8() {
+            // Event_Type through Direct_Address
+            p.skip(2 + 1 + 6 + 1 + 1 + 1 + 1 + 1 + 2 + 1 + 6);
+            let n = usize::from(p.u8());
+            p.skip(n);
         }
+        Ok(())
+    }
+}
+
+impl AsRef<[AdvReport]> for LeExtendedAdvertisingReport {
+    #[inline]
+    fn as_ref(&self) -> &[AdvReport] {
+        self.0.as_ref()
+    }
+}
+
+impl IntoIterator for LeExtendedAdvertisingReport {
+    type Item = AdvReport;
+    type IntoIter = smallvec::IntoIter<[AdvReport; 1]>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Single report from an `HCI_LE_Extended_Advertising_Report` event.
+#[derive(Clone, Debug)]
+pub struct AdvReport {
+    pub typ: AdvReportType,
+    /// Raw advertiser address type. Values `0x02` and `0x03` indicate an
+    /// identity address resolved by the controller, `0xFE` an unresolved RPA,
+    /// and `0xFF` an anonymous advertisement.
+    pub addr_type: u8,
+    /// Advertiser address or [`None`] for anonymous advertisements.
+    pub addr: Option<Addr>,
+    pub pri_phy: Phy,
+    pub sec_phy: Option<Phy>,
+    pub sid: Option<u8>,
+    pub tx_power: TxPower,
+    pub rssi: Option<i8>,
+    pub periodic_interval: Duration,
+    /// Target address of directed advertisements.
+    pub direct_addr: Option<Addr>,
+    pub data: Vec<u8>,
+}
+
+impl AdvReport {
+    /// Returns whether the advertisement is connectable.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_connectable(&self) -> bool {
+        self.typ.contains(AdvReportType::CONNECTABLE)
+    }
+
+    /// Returns whether the advertisement was sent without an address.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_anonymous(&self) -> bool {
+        self.addr_type == 0xFF
+    }
+
+    /// Returns the completeness of the advertising data in this report.
+    #[inline]
+    #[must_use]
+    pub const fn data_status(&self) -> AdvDataStatus {
+        if self.typ.contains(AdvReportType::TRUNCATED) {
+            AdvDataStatus::Truncated
+        } else if self.typ.contains(AdvReportType::INCOMPLETE) {
+            AdvDataStatus::Incomplete
+        } else {
+            AdvDataStatus::Complete
+        }
+    }
+
+    /// Returns a parser for the advertising or scan response data.
+    #[inline(always)]
+    pub fn data(&self) -> ResponseDataIter<'_> {
+        ResponseDataIter::new(&self.data)
+    }
+
+    /// Converts report address type and address into [`Addr`]. Returns
+    /// [`None`] for anonymous or unresolved addresses.
+    #[inline]
+    fn addr(typ: u8, raw: RawAddr) -> Option<Addr> {
+        (typ <= 0x03).then(|| Addr::peer(typ, raw))
     }
 }
 
@@ -94,16 +368,16 @@ pub struct LeAdvertisingSetTerminated {
     pub num_events: u8,
 }
 
-impl FromEvent for LeAdvertisingSetTerminated {
+impl TryFromEvent for LeAdvertisingSetTerminated {
     #[inline(always)]
     fn matches(c: EventCode) -> bool {
         matches!(c, EventCode::LeAdvertisingSetTerminated)
     }
 
-    fn unpack(e: &Event, p: &mut Unpacker) -> Self {
-        Self {
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
             status: e.status(),
-            adv_handle: e.adv_handle().unwrap(),
+            adv_handle: (e.adv_handle()).ok_or_else(|| e.invalid("advertising handle", p))?,
             conn_handle: {
                 let cn = p.u16();
                 if e.status().is_ok() {
@@ -113,6 +387,158 @@ impl FromEvent for LeAdvertisingSetTerminated {
                 }
             },
             num_events: p.u8(),
-        }
+        })
+    }
+}
+
+/// `HCI_LE_Path_Loss_Threshold` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.32).
+#[derive(Clone, Copy, Debug)]
+pub struct LePathLossThreshold {
+    pub handle: ConnHandle,
+    /// Current path loss in dB or [`None`] if not available.
+    pub path_loss: Option<u8>,
+    pub zone: ZoneEntered,
+}
+
+impl TryFromEvent for LePathLossThreshold {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LePathLossThreshold)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            handle: e.valid_conn_handle(p)?,
+            path_loss: Some(p.u8()).filter(|&v| v != 0xFF),
+            zone: ZoneEntered::try_from(p.u8()).map_err(|_| e.invalid("zone entered", p))?,
+        })
+    }
+}
+
+/// `HCI_LE_Transmit_Power_Reporting` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.33).
+#[derive(Clone, Debug)]
+pub struct LeTransmitPowerReporting {
+    pub status: Status,
+    pub handle: ConnHandle,
+    /// Local or remote power level change (0x00 or 0x01), or completion of
+    /// `HCI_LE_Read_Remote_Transmit_Power_Level` (0x02).
+    pub reason: u8,
+    pub phy: u8,
+    /// Power level, which may be [`TxPower::UNSPECIFIED`] or
+    /// [`TxPower::NOT_MANAGED`].
+    pub tx_power: TxPower,
+    pub at_min: bool,
+    pub at_max: bool,
+    /// Power level change in dB or [`None`] if not available or out of
+    /// range.
+    pub delta: Option<i8>,
+}
+
+impl TryFromEvent for LeTransmitPowerReporting {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeTransmitPowerReporting)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        let (reason, phy, tx_power, flags) = (p.u8(), p.u8(), p.i8(), p.u8());
+        Ok(Self {
+            status: e.status(),
+            handle: e.valid_conn_handle(p)?,
+            reason,
+            phy,
+            tx_power: TxPower::from_hci(tx_power),
+            at_min: flags & 1 != 0,
+            at_max: flags & (1 << 1) != 0,
+            delta: Some(p.i8()).filter(|&d| d != 0x7F),
+        })
+    }
+}
+
+/// `HCI_LE_Subrate_Change` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.35).
+#[derive(Clone, Copy, Debug)]
+pub struct LeSubrateChange {
+    pub status: Status,
+    pub handle: ConnHandle,
+    pub subrate_factor: u16,
+    pub peripheral_latency: u16,
+    pub continuation_number: u16,
+    pub supervision_timeout: Duration,
+}
+
+impl TryFromEvent for LeSubrateChange {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeSubrateChange)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        Ok(Self {
+            status: e.status(),
+            handle: e.valid_conn_handle(p)?,
+            subrate_factor: p.u16(),
+            peripheral_latency: p.u16(),
+            continuation_number: p.u16(),
+            supervision_timeout: duration_10ms(p.u16()),
+        })
+    }
+}
+
+/// `HCI_LE_Connection_IQ_Report` event parameters
+/// ([Vol 4] Part E, Section 7.7.65.22).
+#[derive(Clone, Debug)]
+pub struct LeConnectionIqReport {
+    pub handle: ConnHandle,
+    pub rx_phy: Phy,
+    pub chan: u8,
+    /// RSSI in units of 0.1 dBm.
+    pub rssi: i16,
+    pub rssi_antenna_id: u8,
+    pub cte_type: CteType,
+    pub slot: CteSlot,
+    pub packet_status: u8,
+    pub conn_event_counter: u16,
+    /// I and Q samples. A sample count of zero indicates that the controller
+    /// had insufficient resources to sample the CTE.
+    pub samples: Vec<(i8, i8)>,
+}
+
+impl TryFromEvent for LeConnectionIqReport {
+    #[inline(always)]
+    fn matches(c: EventCode) -> bool {
+        matches!(c, EventCode::LeConnectionIqReport)
+    }
+
+    fn unpack(e: &Event, p: &mut Unpacker) -> DecodeResult<Self> {
+        let mut r = Self {
+            handle: e.valid_conn_handle(p)?,
+            rx_phy: Phy::try_from(p.u8()).map_err(|_| e.invalid("PHY", p))?,
+            chan: p.u8(),
+            rssi: p.i16(),
+            rssi_antenna_id: p.u8(),
+            cte_type: CteType::try_from(p.u8()).map_err(|_| e.invalid("CTE type", p))?,
+            slot: CteSlot::try_from(p.u8()).map_err(|_| e.invalid("CTE slot duration", p))?,
+            packet_status: p.u8(),
+            conn_event_counter: p.u16(),
+            samples: Vec::new(),
+        };
+        let n = usize::from(p.u8());
+        r.samples = (0..n).map(|_| (p.i8(), p.i8())).collect();
+        Ok(r)
+    }
+
+    fn check(e: &Event, p: &mut Unpacker) -> DecodeResult<()> {
+        e.valid_conn_handle(p)?;
+        Phy::try_from(p.u8()).map_err(|_| e.invalid("PHY", p))?;
+        p.skip(1 + 2 + 1);
+        CteType::try_from(p.u8()).map_err(|_| e.invalid("CTE type", p))?;
+        CteSlot::try_from(p.u8()).map_err(|_| e.invalid("CTE slot duration", p))?;
+        p.skip(1 + 2);
+        let n = usize::from(p.u8());
+        p.skip(2 * n);
+        Ok(())
     }
 }
@@ -1,9 +1,15 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::Duration;
+
 use matches::assert_matches;
-use structbuf::Unpack;
+use structbuf::{Pack, Packer, StructBuf, Unpack};
 
-use crate::hci::event::EventHeader;
+use crate::hci::event::{EventHeader, EventTransfer};
 use crate::hci::*;
-use crate::le::RawAddr;
+use crate::host;
+use crate::le::{Addr, RawAddr};
 
 #[test]
 fn hci() {
@@ -122,3 +128,307 @@ fn addr() {
         RawAddr::from_le_bytes([0, 1, 2, 3, 4, 5])
     );
 }
+
+#[test]
+fn cmd_conflicts() {
+    use super::{Monitor, Receiver};
+    let rx = |id, opcode| Receiver {
+        id,
+        opcode,
+        ..Receiver::default()
+    };
+    let mut m = Monitor::default();
+    // Non-command streams (e.g. ChanManager and SecDb) share all events
+    m.register(rx(0, Opcode::None));
+    m.register(rx(1, Opcode::None));
+    assert!(!m.conflicts(Opcode::None));
+    assert!(!m.conflicts(Opcode::Reset));
+
+    m.register(rx(2, Opcode::Reset));
+    assert!(m.conflicts(Opcode::Reset));
+    assert!(!m.conflicts(Opcode::ReadBdAddr));
+
+    // Different commands can be pending at the same time
+    m.register(rx(3, Opcode::ReadBdAddr));
+    assert!(m.conflicts(Opcode::ReadBdAddr));
+    assert!(!m.conflicts(Opcode::LeSetEventMask));
+}
+
+#[test]
+fn conn_receivers() {
+    use super::{Monitor, Receiver};
+    let rx = |id, conn| Receiver {
+        id,
+        conn: Some(ConnHandle::new(conn).unwrap()),
+        ..Receiver::default()
+    };
+    let mut m = Monitor::default();
+    m.register(rx(0, 1));
+    m.register(rx(1, 1));
+    m.register(rx(2, 2));
+    assert_eq!(m.by_conn.len(), 2);
+    assert!(m.all.is_empty() && m.cmds.is_empty());
+
+    assert!(m.unregister(0).is_some());
+    assert!(m.unregister(0).is_none());
+    assert_eq!(m.by_conn.len(), 2);
+    assert!(m.unregister(1).is_some());
+    assert_eq!(m.by_conn.len(), 1);
+    assert!(m.unregister(2).is_some());
+    assert!(m.by_conn.is_empty() && m.queue.is_empty());
+}
+
+#[test]
+fn decode() {
+    const DISCONNECT: u8 = EventCode::DisconnectionComplete as u8;
+    let e = event(&[DISCONNECT, 4, 0, 0x40, 0x00, 0x13]);
+    assert_eq!(e.validate(), Ok(()));
+    let v: DisconnectionComplete = e.get();
+    assert_eq!(v.reason, Status::RemoteUserTerminatedConnection);
+
+    let e = event(&[DISCONNECT, 3, 0, 0x40, 0x00]);
+    assert_matches!(e.validate(), Err(DecodeError::Truncated { .. }));
+    let e = event(&[DISCONNECT, 5, 0, 0x40, 0x00, 0x13, 0xFF]);
+    assert_matches!(e.validate(), Err(DecodeError::Unconsumed { rem, .. }) if rem.as_ref() == [0xFF]);
+    let e = event(&[DISCONNECT, 4, 0, 0x00, 0x0F, 0x13]);
+    assert_matches!(
+        e.validate(),
+        Err(DecodeError::InvalidField {
+            field: "connection handle",
+            ..
+        })
+    );
+
+    let mut pkt = vec![EventCode::LeMetaEvent as u8, 19, 0x01, 0, 0x40, 0x00, 0xFF];
+    pkt.extend_from_slice(&[0; 14]);
+    let e = event(&pkt);
+    assert_matches!(
+        e.validate(),
+        Err(DecodeError::InvalidField { field: "role", rem, .. }) if rem.total_len() == 14
+    );
+
+    // Events without a decoder are not validated
+    let e = event(&[EventCode::Vendor as u8, 1, 0]);
+    assert_eq!(e.validate(), Ok(()));
+}
+
+#[test]
+fn vendor() {
+    let e = event(&[EventCode::Vendor as u8, 3, 0x3E, 1, 2]);
+    assert_eq!(e.validate(), Ok(()));
+    let v: VendorEvent = e.get();
+    assert_eq!(v.as_ref(), [0x3E, 1, 2]);
+
+    let v: VendorEvent = event(&[EventCode::Vendor as u8, 0]).get();
+    assert!(v.as_ref().is_empty());
+}
+
+#[test]
+fn remote_conn_param_req() {
+    let mut pkt = vec![EventCode::LeMetaEvent as u8, 11, 0x06, 0x40, 0x00];
+    pkt.extend_from_slice(&[0x18, 0x00, 0x28, 0x00, 0x04, 0x00, 0x90, 0x01]);
+    let e = event(&pkt);
+    assert_eq!(e.validate(), Ok(()));
+    let v: LeRemoteConnectionParameterRequest = e.get();
+    assert_eq!(u16::from(v.handle), 0x40);
+    let want = ConnParams {
+        interval: (Duration::from_millis(30), Duration::from_millis(50)),
+        latency: 4,
+        timeout: Duration::from_secs(4),
+    };
+    assert_eq!(v.params, want);
+    assert!(v.params.is_valid());
+}
+
+#[test]
+fn conn_complete_failed() {
+    // Enhanced Connection Complete after HCI_LE_Create_Connection_Cancel
+    // with invalid parameters
+    const STATUS: u8 = Status::UnknownConnectionIdentifier as u8;
+    let mut pkt = vec![EventCode::LeMetaEvent as u8, 31, 0x0A, STATUS];
+    pkt.extend_from_slice(&[0xFF; 29]);
+    let e = event(&pkt);
+    assert_eq!(e.validate(), Ok(()));
+    let v: LeConnectionComplete = e.get();
+    assert_eq!(v.status, Status::UnknownConnectionIdentifier);
+
+    // The same parameters are invalid for a successful connection
+    pkt[3] = Status::Success as u8;
+    assert_matches!(
+        event(&pkt).validate(),
+        Err(DecodeError::InvalidField { field: "role", .. })
+    );
+}
+
+#[test]
+fn path_loss_threshold() {
+    let pkt = [EventCode::LeMetaEvent as u8, 5, 0x20, 0x40, 0x00, 62, 2];
+    let e = event(&pkt);
+    assert_eq!(e.validate(), Ok(()));
+    let v: LePathLossThreshold = e.get();
+    assert_eq!(u16::from(v.handle), 0x40);
+    assert_eq!(v.path_loss, Some(62));
+    assert_eq!(v.zone, ZoneEntered::High);
+
+    let e = event(&[EventCode::LeMetaEvent as u8, 5, 0x20, 0x40, 0x00, 0xFF, 3]);
+    assert_matches!(
+        e.validate(),
+        Err(DecodeError::InvalidField {
+            field: "zone entered",
+            ..
+        })
+    );
+}
+
+#[test]
+fn tx_power_reporting() {
+    // Remote power level change on LE 2M to -4 dBm, at minimum, down by 6 dB
+    let mut pkt = vec![EventCode::LeMetaEvent as u8, 9, 0x21, 0, 0x40, 0x00];
+    pkt.extend_from_slice(&[1, 2, 0xFC, 1, 0xFA]);
+    let e = event(&pkt);
+    assert_eq!(e.validate(), Ok(()));
+    let v: LeTransmitPowerReporting = e.get();
+    assert_eq!(u16::from(v.handle), 0x40);
+    assert_eq!((v.reason, v.phy), (1, 2));
+    assert_eq!(v.tx_power.dbm(), Some(-4));
+    assert!(v.at_min && !v.at_max);
+    assert_eq!(v.delta, Some(-6));
+
+    // Remote device is not managing power levels and the change is unknown
+    pkt.truncate(6);
+    pkt.extend_from_slice(&[2, 1, 0x7E, 0, 0x7F]);
+    let e = event(&pkt);
+    assert_eq!(e.validate(), Ok(()));
+    let v: LeTransmitPowerReporting = e.get();
+    assert!(v.tx_power.is_not_managed());
+    assert_eq!(v.delta, None);
+}
+
+#[test]
+fn ext_adv_report() {
+    let mut pkt = vec![EventCode::LeMetaEvent as u8, 0, 0x0D, 2];
+    // Connectable legacy advertisement from a public address
+    pkt.extend_from_slice(&[0x13, 0x00, 0x00, 1, 2, 3, 4, 5, 6, 0x01, 0x00, 0xFF]);
+    pkt.extend_from_slice(&[0x7F, 0xC4, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0]);
+    pkt.extend_from_slice(&[3, 0x02, 0x01, 0x06]);
+    // Truncated anonymous extended advertisement with a periodic interval
+    pkt.extend_from_slice(&[0x40, 0x00, 0xFF, 0, 0, 0, 0, 0, 0, 0x03, 0x02, 0x05]);
+    pkt.extend_from_slice(&[0xFC, 0x7F, 0x50, 0x00, 0xFF, 0, 0, 0, 0, 0, 0]);
+    pkt.extend_from_slice(&[5, 0x04, 0xFF, 0x01, 0x02, 0x03]);
+    pkt[1] = u8::try_from(pkt.len() - 2).unwrap();
+
+    let e = event(&pkt);
+    assert_eq!(e.validate(), Ok(()));
+    let v: LeExtendedAdvertisingReport = e.get();
+    let [a, b] = v.as_ref() else { panic!("expected 2 reports") };
+
+    assert!(a.is_connectable() && !a.is_anonymous());
+    assert_eq!(a.data_status(), AdvDataStatus::Complete);
+    assert_eq!(a.addr_type, 0x00);
+    let addr = RawAddr::from_le_bytes([1, 2, 3, 4, 5, 6]);
+    assert_eq!(a.addr, Some(Addr::Public(addr)));
+    assert_eq!((a.pri_phy, a.sec_phy, a.sid), (Phy::Le1M, None, None));
+    assert_eq!(a.rssi, Some(-60));
+    assert_eq!(a.periodic_interval, Duration::ZERO);
+    assert_eq!(a.data, [0x02, 0x01, 0x06]);
+
+    assert!(!b.is_connectable() && b.is_anonymous());
+    assert_eq!(b.data_status(), AdvDataStatus::Truncated);
+    assert_eq!(b.addr, None);
+    assert_eq!((b.pri_phy, b.sec_phy), (Phy::LeCoded, Some(Phy::Le2M)));
+    assert_eq!(b.sid, Some(5));
+    assert_eq!(b.tx_power.dbm(), Some(-4));
+    assert_eq!(b.rssi, None);
+    assert_eq!(b.periodic_interval, Duration::from_millis(100));
+    assert_eq!(b.direct_addr, None);
+    assert_eq!(b.data, [0x04, 0xFF, 0x01, 0x02, 0x03]);
+
+    // Data length exceeding the event
+    let n = pkt.len();
+    pkt[n - 6] = 6;
+    assert_matches!(event(&pkt).validate(), Err(DecodeError::Truncated { .. }));
+}
+
+#[test]
+fn error_allocs() {
+    let unknown = [0, 40, 0xAA, 0xBB].repeat(11);
+    let trunc = event(&[EventCode::DisconnectionComplete as u8, 3, 0, 0x40, 0x00]);
+    let n = allocs();
+    for _ in 0..10_000 {
+        let e = EventHeader::unpack(&unknown[..42]).unwrap_err();
+        assert_matches!(e, Error::UnknownEvent { params, .. } if params.is_truncated());
+        assert_matches!(trunc.validate(), Err(DecodeError::Truncated { .. }));
+    }
+    assert_eq!(allocs(), n);
+}
+
+/// Returns an event handle for the raw event packet.
+fn event(pkt: &[u8]) -> Event {
+    let mut buf = StructBuf::new(pkt.len());
+    buf.append().put(pkt);
+    let t = EventTransfer {
+        xfer: Some(Box::new(TestTransfer(buf))),
+        ..EventTransfer::default()
+    };
+    let t = Arc::new(tokio::sync::RwLock::new(t)).try_write_owned();
+    Event::new(t.unwrap()).unwrap()
+}
+
+/// Completed in-memory event transfer.
+#[derive(Debug)]
+struct TestTransfer(StructBuf);
+
+impl AsRef<[u8]> for TestTransfer {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl Pack for TestTransfer {
+    fn append(&mut self) -> Packer {
+        self.0.append()
+    }
+
+    fn at(&mut self, i: usize) -> Packer {
+        self.0.at(i)
+    }
+}
+
+impl host::Transfer for TestTransfer {
+    fn typ(&self) -> TransferType {
+        TransferType::Event
+    }
+
+    fn exec(self: Box<Self>) -> host::Exec {
+        host::Exec::ready(Ok(self))
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Allocator that counts allocations made by each thread.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = Cell::new(0);
+}
+
+// SAFETY: All requests are forwarded to the system allocator
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Returns the number of allocations made by the current thread.
+fn allocs() -> usize {
+    ALLOCS.with(Cell::get)
+}
@@ -12,18 +12,30 @@ use once_cell::sync::Lazy;
 use structbuf::{Pack, Packer};
 use tracing::{debug, error, warn};
 
-pub use {adv::*, cmd::*, consts::*, event::*, handle::*};
+pub use {
+    adv::*, cmd::*, config::*, consts::*, event::*, handle::*, keepalive::*, link::*, rng::*,
+    rssi::*, shutdown::*, txpower::*, vendor::*,
+};
 
-use crate::le::Addr;
-use crate::{host, smp, SyncMutex};
+use crate::le::{Addr, RawAddr};
+use crate::{gap, host, smp, PeerStore, SyncMutex};
 
 mod adv;
 #[path = "cmd/cmd.rs"]
 mod cmd;
+mod config;
 mod consts;
+mod cte;
 #[path = "event/event.rs"]
 mod event;
 mod handle;
+mod keepalive;
+mod link;
+mod rng;
+mod rssi;
+mod shutdown;
+mod txpower;
+mod vendor;
 
 /// Error type returned by the HCI layer.
 #[derive(Clone, Debug, thiserror::Error)]
@@ -39,13 +51,17 @@ pub enum Error {
     },
     #[error("controller initialization error: {0}")]
     Init(&'static str),
-    #[error("invalid event: {0:02X?}")]
-    InvalidEvent(Vec<u8>),
-    #[error("unknown event [code={code:#04X}, subcode={subcode:#04X}]: {params:02X?}")]
+    #[error("controller initialization failed at {step:?} step: {status}")]
+    InitFailed { step: InitStep, status: Status },
+    #[error("invalid event: {0:?}")]
+    InvalidEvent(EventBytes),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error("unknown event [code={code:#04X}, subcode={subcode:#04X}]: {params:?}")]
     UnknownEvent {
         code: u8,
         subcode: u8,
-        params: Vec<u8>,
+        params: EventBytes,
     },
     #[error("{opcode} command failed: {status}")]
     CommandFailed { opcode: Opcode, status: Status },
@@ -53,6 +69,8 @@ pub enum Error {
     CommandAborted { opcode: Opcode, status: Status },
     #[error("{opcode} command timeout")]
     CommandTimeout { opcode: Opcode },
+    #[error("invalid static random address: {0}")]
+    InvalidStaticAddress(RawAddr),
 }
 
 impl Error {
@@ -61,12 +79,17 @@ impl Error {
     pub const fn status(&self) -> Option<Status> {
         use Error::*;
         match *self {
-            Hci { status } | CommandFailed { status, .. } | CommandAborted { status, .. } => {
-                Some(status)
-            }
-            Host(_) | Init(_) | InvalidEvent(_) | UnknownEvent { .. } | CommandTimeout { .. } => {
-                None
-            }
+            Hci { status }
+            | InitFailed { status, .. }
+            | CommandFailed { status, .. }
+            | CommandAborted { status, .. } => Some(status),
+            Host(_)
+            | Init(_)
+            | InvalidEvent(_)
+            | Decode(_)
+            | UnknownEvent { .. }
+            | CommandTimeout { .. }
+            | InvalidStaticAddress(_) => None,
         }
     }
 
@@ -79,10 +102,13 @@ impl Error {
             Host(_)
             | Hci { .. }
             | Init(_)
+            | InitFailed { .. }
             | InvalidEvent(_)
+            | Decode(_)
             | UnknownEvent { .. }
             | CommandFailed { .. }
-            | CommandAborted { .. } => false,
+            | CommandAborted { .. }
+            | InvalidStaticAddress(_) => false,
         }
     }
 }
@@ -125,13 +151,18 @@ impl TransferType {
     }
 }
 
-/// Host-side of a Host Controller Interface.
+/// Host-side of a Host Controller Interface. Dropping the last clone of the
+/// host without calling [`Host::shutdown`] terminates all connections without
+/// waiting for them to close and stops the event loop.
 #[derive(Clone, Debug)]
 pub struct Host {
     transport: Arc<dyn host::Transport>,
     info: Arc<ControllerInfo>,
     router: Arc<EventRouter>,
     cmd: Arc<CommandTransfer>,
+    adv: Arc<SyncMutex<AdvHandleAlloc>>,
+    static_addr: Arc<SyncMutex<Option<RawAddr>>>,
+    guard: Option<Arc<ShutdownGuard>>,
 }
 
 impl Host {
@@ -139,12 +170,17 @@ impl Host {
     #[inline]
     #[must_use]
     pub fn new(t: Arc<dyn host::Transport>) -> Self {
-        Self {
+        let mut h = Self {
             transport: t,
             info: Arc::default(),
             router: EventRouter::new(),
             cmd: Arc::new(CommandTransfer::default()),
-        }
+            adv: Arc::default(),
+            static_addr: Arc::default(),
+            guard: None,
+        };
+        h.guard = Some(Arc::new(ShutdownGuard::new(&h)));
+        h
     }
 
     /// Returns the underlying transport.
@@ -154,18 +190,98 @@ impl Host {
         &self.transport
     }
 
+    /// Returns transport latency, throughput, and error statistics, if the
+    /// transport collects them.
+    #[inline]
+    #[must_use]
+    pub fn transport_stats(&self) -> Option<host::TransportStats> {
+        self.transport.stats()
+    }
+
     /// Returns controller information.
     #[inline(always)]
     pub(crate) fn info(&self) -> &ControllerInfo {
         &self.info
     }
 
+    /// Sets the static random device address used as the local identity
+    /// address ([Vol 6] Part B, Section 1.3.2.1). The address is used as the
+    /// random address for scanning and initiating connections, and for any
+    /// advertising set created with a random own address type. It should be set
+    /// before scanning or advertising and must not change while any bonds
+    /// exist, because peers identify the device by this address. Returns
+    /// [`Error::InvalidStaticAddress`] without sending any commands if `a` is
+    /// not a static random address.
+    pub async fn set_static_address(&self, a: RawAddr) -> Result<()> {
+        if !a.is_static_random() {
+            return Err(Error::InvalidStaticAddress(a));
+        }
+        self.le_set_random_address(a).await?;
+        debug!("Static random address: {a}");
+        *self.static_addr.lock() = Some(a);
+        Ok(())
+    }
+
+    /// Sets the static random device address saved in `store` as the local
+    /// identity address, generating and saving a new one if needed. See
+    /// [`RawAddr::load_or_gen_static_random`] for store requirements.
+    pub async fn load_or_gen_static_address(
+        &self,
+        store: &dyn PeerStore<Value = RawAddr>,
+    ) -> Result<RawAddr> {
+        let a = RawAddr::load_or_gen_static_random(store);
+        self.set_static_address(a).await?;
+        Ok(a)
+    }
+
+    /// Returns the static random device address set by
+    /// [`Self::set_static_address`].
+    #[inline]
+    #[must_use]
+    pub fn static_address(&self) -> Option<RawAddr> {
+        *self.static_addr.lock()
+    }
+
+    /// Returns the local identity address, which is the static random device
+    /// address, if set, or the public device address. The address is
+    /// distributed to peers during bonding via
+    /// [`crate::smp::Device::with_identity`].
+    #[inline]
+    #[must_use]
+    pub fn identity_addr(&self) -> Addr {
+        self.static_address().map_or(self.info.addr, Addr::Random)
+    }
+
+    /// Returns the handles of all connectable advertising sets created by any
+    /// [`Advertiser`].
+    #[inline]
+    pub(crate) fn connectable_adv_sets(&self) -> Vec<AdvHandle> {
+        self.adv.lock().connectable()
+    }
+
     /// Returns an event stream that will yield non-command events.
     #[inline(always)]
     pub(crate) fn events(&self) -> EventStream {
         self.router.events()
     }
 
+    /// Returns an event stream that will yield non-command events for
+    /// connection `hdl`. This should be preferred over [`Self::events`] for
+    /// per-connection tasks, because it does not receive events for other
+    /// connections.
+    #[inline(always)]
+    pub(crate) fn conn_events(&self, hdl: ConnHandle) -> EventStream {
+        self.router.conn_events(hdl)
+    }
+
+    /// Returns the number of received events that were discarded because their
+    /// parameters could not be decoded.
+    #[inline]
+    #[must_use]
+    pub fn invalid_events(&self) -> u64 {
+        self.router.invalid_events()
+    }
+
     /// Returns connection information for the specified handle or [`None`] if
     /// the handle is invalid.
     #[inline(always)]
@@ -173,6 +289,32 @@ impl Host {
         self.router.conn(hdl)
     }
 
+    /// Returns a future that resolves to the reason parameter of the
+    /// `DisconnectionComplete` event for connection `hdl`, or
+    /// [`Status::UnknownConnectionIdentifier`] if the handle is invalid. The
+    /// future must be created before the connection is closed.
+    pub fn disconnected(&self, hdl: ConnHandle) -> impl Future<Output = Status> + Send + 'static {
+        let cn = self.conn(hdl);
+        async move {
+            let Some(mut cn) = cn else {
+                return Status::UnknownConnectionIdentifier;
+            };
+            // The sender is dropped after the reason is set
+            while cn.changed().await.is_ok() {}
+            let r = cn.borrow().disconnect_reason;
+            r.unwrap_or(Status::UnspecifiedError)
+        }
+    }
+
+    /// Returns the tracing span of the specified connection or a disabled span
+    /// if the handle is invalid. The span has `handle`, `peer`, and `role`
+    /// fields, allowing log output to be filtered by connection.
+    #[must_use]
+    pub fn conn_span(&self, hdl: ConnHandle) -> tracing::Span {
+        self.conn(hdl)
+            .map_or_else(tracing::Span::none, |cn| cn.borrow().span.clone())
+    }
+
     /// Calls `f` to update connection parameters. This is a no-op if the handle
     /// is invalid.
     #[inline(always)]
@@ -180,62 +322,115 @@ impl Host {
         self.router.update_conn(hdl, f);
     }
 
-    /// Resets and initializes the controller ([Vol 6] Part D, Section 2.1). The
-    /// event loop must be running prior to calling this method.
+    /// Resets and initializes the controller using the default configuration
+    /// with the specified event mask. See [`Self::init_with`] for details.
+    #[inline]
     pub async fn init(&mut self, event_mask: &EventMask) -> Result<()> {
+        (self.init_with(&HostConfig::new().with_event_mask(*event_mask))).await
+    }
+
+    /// Initializes the controller using the specified configuration
+    /// ([Vol 6] Part D, Section 2.1). The event loop must be running prior to
+    /// calling this method.
+    ///
+    /// If the controller reports zero LE ACL data packet length or count, then
+    /// LE and BR/EDR share the same data buffers, and the ACL parameters
+    /// returned by [`Self::read_buffer_size`] are used for LE flow control
+    /// instead. The shared packet count is capped at 255.
+    ///
+    /// Initialization stops at the first command rejected by the controller,
+    /// returning [`Error::InitFailed`] with the failed step and status.
+    pub async fn init_with(&mut self, cfg: &HostConfig) -> Result<()> {
+        use InitStep::*;
         fn info_mut(this: &mut Host) -> &mut ControllerInfo {
             Arc::get_mut(&mut this.info).expect("host is shared")
         }
 
-        // Reset after allowing the event loop to discard any unexpected events
+        // Allow the event loop to discard any unexpected events
         tokio::time::sleep(Duration::from_millis(100)).await;
-        debug!("HCI reset...");
-        self.reset().await?;
+        for f in &cfg.pre_init {
+            f(self).await.map_err(PreInitHook.err())?;
+        }
+        if cfg.reset {
+            debug!("HCI reset...");
+            self.reset().await.map_err(Reset.err())?;
+        }
 
         // Get controller information
         info_mut(self).cmd = match self.read_local_supported_commands().await {
             // The first command after a reset may time out, so we retry it once
             Err(e) if e.is_timeout() => self.read_local_supported_commands().await,
             r => r,
-        }?;
-        info_mut(self).ver = self.read_local_version().await?;
+        }
+        .map_err(ReadLocalSupportedCommands.err())?;
+        info_mut(self).ver = (self.read_local_version().await).map_err(ReadLocalVersion.err())?;
         debug!("Controller version: {:?}", self.info.ver);
         if self.info.ver.hci_version < CoreVersion::V5_0 {
             return Err(Error::Init("pre-v5 controller"));
         }
-        info_mut(self).lmp_features = self.read_local_supported_features().await?;
+        info_mut(self).lmp_features = (self.read_local_supported_features().await)
+            .map_err(ReadLocalSupportedFeatures.err())?;
         debug!("Controller LMP features: {:?}", self.info.lmp_features);
         if !self.info.lmp_features.contains(LmpFeature::LE_SUPPORTED) {
             return Err(Error::Init("non-LE controller"));
         }
-        info_mut(self).le_features = self.le_read_local_supported_features().await?;
+        info_mut(self).le_features = (self.le_read_local_supported_features().await)
+            .map_err(LeReadLocalSupportedFeatures.err())?;
         debug!("Controller LE features: {:?}", self.info.le_features);
-        info_mut(self).states = self.le_read_supported_states().await?;
+        info_mut(self).states =
+            (self.le_read_supported_states().await).map_err(LeReadSupportedStates.err())?;
         debug!("Controller LE states: {:#044b}", self.info.states.0);
 
         // [Vol 4] Part E, Section 4.1 and [Vol 4] Part E, Section 7.8.2
         if self.info.states.supports_connection_state() {
-            let mut buf = self.le_read_buffer_size().await?;
+            let mut buf = (self.le_read_buffer_size().await).map_err(LeReadBufferSize.err())?;
             debug!("Controller LE buffers: {:?}", buf);
             #[allow(clippy::cast_possible_truncation)]
             if buf.acl_data_len == 0 || buf.acl_num_pkts == 0 {
-                let shared = self.read_buffer_size().await?;
+                // Shared buffers ([Vol 4] Part E, Section 7.8.2)
+                let shared = (self.read_buffer_size().await).map_err(ReadBufferSize.err())?;
                 debug!("Controller BR/EDR/LE buffers: {:?}", shared);
                 buf.acl_data_len = shared.acl_data_len;
-                buf.acl_num_pkts = shared.acl_num_pkts.max(u16::from(u8::MAX)) as _;
+                buf.acl_num_pkts = shared.acl_num_pkts.min(u16::from(u8::MAX)) as _;
                 if buf.acl_data_len == 0 || buf.acl_num_pkts == 0 {
                     return Err(Error::Init("invalid buffer parameters"));
                 }
             }
             info_mut(self).buf = buf;
+
+            // [Vol 4] Part E, Section 4.2 and [Vol 4] Part E, Section 7.3.39
+            // TODO: Enable controller to host flow control?
+            if let Some(acl_num_pkts) = cfg.host_buf_pkts {
+                let hbuf = BufferSize {
+                    acl_data_len: buf.acl_data_len,
+                    acl_num_pkts,
+                };
+                (self.host_buffer_size(hbuf).await).map_err(HostBufferSize.err())?;
+                info_mut(self).host_buf = Some(hbuf);
+            }
         } else {
             warn!("Controller does not support Connection State");
         }
-        info_mut(self).addr = self.read_bd_addr().await?;
+        info_mut(self).addr = (self.read_bd_addr().await).map_err(ReadBdAddr.err())?;
         debug!("Controller address: {:?}", self.info.addr);
 
-        // Enable requested events
-        event_mask.apply(self).await
+        // Enable requested events and optional features
+        (cfg.event_mask.apply(self).await).map_err(SetEventMask.err())?;
+        if let Some((octets, time)) = cfg.data_len {
+            let r = self.le_write_suggested_default_data_length(octets, time);
+            r.await.map_err(LeWriteSuggestedDefaultDataLength.err())?;
+        }
+        if let Some((tx, rx)) = cfg.default_phy {
+            (self.le_set_default_phy(tx, rx).await).map_err(LeSetDefaultPhy.err())?;
+        }
+        if let Some(enable) = cfg.addr_resolution {
+            let r = self.le_set_address_resolution_enable(enable);
+            r.await.map_err(LeSetAddressResolutionEnable.err())?;
+        }
+        for f in &cfg.post_init {
+            f(self).await.map_err(PostInitHook.err())?;
+        }
+        Ok(())
     }
 
     /// Receives the next HCI event, routes it to registered waiters, and
@@ -259,14 +454,19 @@ impl Host {
 
     /// Spawns a task that continuously receives HCI events until a fatal error
     /// is encountered. The task is canceled when the returned future is
-    /// dropped.
+    /// dropped or the host is shut down.
     #[inline]
     #[must_use]
     pub fn event_loop(&self) -> EventLoop {
         let ct = tokio_util::sync::CancellationToken::new();
+        if let Some(g) = self.guard.as_ref() {
+            g.set_event_loop(ct.clone());
+        }
         let mut host = self.clone();
         // Drop ControllerInfo reference to allow exclusive access in init()
         host.info = Arc::clone(&NO_CONTROLLER_INFO);
+        // The event loop must not prevent the host from being shut down
+        host.guard = None;
         EventLoop {
             join: tokio::spawn(EventLoop::run(host, ct.clone())),
             cancel: ct.clone(),
@@ -328,6 +528,7 @@ pub struct ControllerInfo {
     le_features: LeFeature,
     states: LeStateCombinations,
     buf: LeBufferSize,
+    host_buf: Option<BufferSize>,
     addr: Addr,
 }
 
@@ -362,6 +563,20 @@ impl ControllerInfo {
     pub const fn buffer_size(&self) -> LeBufferSize {
         self.buf
     }
+
+    /// Returns the host buffer size advertised to the controller, if any.
+    #[inline(always)]
+    #[must_use]
+    pub const fn host_buffer_size(&self) -> Option<BufferSize> {
+        self.host_buf
+    }
+
+    /// Returns the public device address.
+    #[inline(always)]
+    #[must_use]
+    pub const fn addr(&self) -> Addr {
+        self.addr
+    }
 }
 
 /// Future that continuously receives HCI events.
@@ -401,9 +616,7 @@ impl EventLoop {
         // stops all communication. Doing this at the transport layer would be
         // better, but WinUSB does not support actual device resets.
         // TODO: Is there a better place to do this?
-        let mut reset = h.new_cmd();
-        reset.append().u16(Opcode::Reset).u8(0);
-        match reset.exec().await {
+        match Command::new(&h, Opcode::Reset).submit().await {
             Ok(_) => debug!("Submitted controller reset command"),
             Err(e) => warn!("Failed to reset controller: {e}"),
         }
@@ -423,7 +636,7 @@ impl Future for EventLoop {
 pub(crate) type ConnWatch = tokio::sync::watch::Receiver<Conn>;
 
 /// Information about an established connection.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct Conn {
     /// Local role.
     pub role: Role,
@@ -440,21 +653,53 @@ pub(crate) struct Conn {
     /// indicate the existence of a trusted relationship with the peer. A change
     /// in the ID invalidates any cached data.
     pub bond_id: Option<smp::BondId>,
+    /// Current connection interval, latency, and supervision timeout.
+    pub params: gap::ActiveConnParams,
     /// Reason parameter from the [`DisconnectionComplete`] event.
     pub disconnect_reason: Option<Status>,
+    /// Current transmitter and receiver PHY. This is assumed to be LE 1M until
+    /// an [`LePhyUpdateComplete`] event is received.
+    pub phy: (Phy, Phy),
+    /// Maximum LL Data PDU payload sizes in the transmit and receive
+    /// directions ([Vol 6] Part B, Section 4.5.10).
+    pub max_octets: (u16, u16),
+    /// Features used on the connection by the peer once read by
+    /// `HCI_LE_Read_Remote_Features`.
+    pub remote_features: Option<LeFeature>,
+    /// Connection subrate factor ([Vol 6] Part B, Section 4.5.1). A factor
+    /// greater than one indicates that most connection events are skipped.
+    pub subrate_factor: u16,
+    /// Tracing span for all events related to the connection.
+    pub span: tracing::Span,
 }
 
 impl Conn {
     /// Creates new connection info.
     #[inline(always)]
-    fn new(e: &LeConnectionComplete) -> Self {
+    pub(crate) fn new(e: &LeConnectionComplete) -> Self {
         Self {
             role: e.role,
             local_addr: Addr::default(),
             peer_addr: e.peer_addr,
             sec: ConnSec::empty(),
             bond_id: None,
+            params: gap::ActiveConnParams {
+                interval: e.conn_interval,
+                latency: e.peripheral_latency,
+                timeout: e.supervision_timeout,
+            },
             disconnect_reason: None,
+            phy: (Phy::Le1M, Phy::Le1M),
+            max_octets: (LL_MIN_OCTETS, LL_MIN_OCTETS),
+            remote_features: None,
+            subrate_factor: 1,
+            span: tracing::info_span!(
+                parent: None,
+                "conn",
+                handle = u16::from(e.handle),
+                peer = %e.peer_addr,
+                role = ?e.role,
+            ),
         }
     }
 }
@@ -539,6 +784,13 @@ pub(crate) fn ticks_625us(d: Duration) -> Option<u32> {
     ticks_us(d, 625)
 }
 
+/// Returns the number of 0.625ms ticks in `d` (rounding down) or `None` if the
+/// value overflows `u16`.
+#[inline]
+pub(crate) fn ticks_625us_u16(d: Duration) -> Option<u16> {
+    ticks_us(d, 625)
+}
+
 /// Converts duration `d` into the count of `tick` milliseconds, rounding down.
 /// Returns `None` if the count overflows `T`.
 #[inline]
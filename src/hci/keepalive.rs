@@ -0,0 +1,199 @@
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, warn, Instrument};
+
+use super::*;
+
+/// Notification that a connection did not pass the liveness check for the
+/// specified number of consecutive periods.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkStalled {
+    pub handle: ConnHandle,
+    pub count: u32,
+}
+
+/// Connection liveness monitor started by [`Keepalive::new`].
+///
+/// On encrypted connections, the controller is configured with the
+/// authenticated payload timeout ([Vol 6] Part B, Section 5.4), causing it to
+/// use the LE Ping procedure whenever no other packets authenticated by a MIC
+/// are received. Each expiration of the timeout is reported as a
+/// [`LinkStalled`] notification.
+///
+/// On unencrypted connections, the RSSI is read once per timeout period
+/// instead, and each failed read is reported as a stall. This only verifies
+/// that the controller still considers the link to be active, so it is a much
+/// weaker check than LE Ping.
+///
+/// The monitor stops when it is dropped or the connection is closed.
+#[derive(Debug)]
+pub struct Keepalive {
+    stalled: mpsc::Receiver<LinkStalled>,
+    disconnect_after: Arc<AtomicU32>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Keepalive {
+    /// Starts monitoring connection `hdl`, which is considered stalled if no
+    /// authenticated packets are received for `timeout`.
+    pub async fn new(host: &Host, hdl: ConnHandle, timeout: Duration) -> Result<Self> {
+        let Some(cn) = host.conn(hdl) else {
+            return Err(Status::UnknownConnectionIdentifier.into());
+        };
+        let encrypted = !cn.borrow().sec.intersection(ConnSec::KEY_LEN).is_empty();
+        let ctl = host.conn_events(hdl);
+        if encrypted {
+            (host.write_authenticated_payload_timeout(hdl, timeout)).await?;
+        }
+        let (tx, stalled) = mpsc::channel(4);
+        let disconnect_after = Arc::new(AtomicU32::new(0));
+        let t = Task {
+            host: host.clone(),
+            hdl,
+            timeout,
+            stalled: tx,
+            disconnect_after: Arc::clone(&disconnect_after),
+        };
+        let span = host.conn_span(hdl);
+        let task = if encrypted {
+            debug!("Monitoring {hdl} with authenticated payload timeout {timeout:?}");
+            tokio::task::spawn(t.ping(ctl).instrument(span))
+        } else {
+            debug!("Monitoring {hdl} with RSSI polling every {timeout:?}");
+            drop(ctl);
+            tokio::task::spawn(t.poll(cn).instrument(span))
+        };
+        Ok(Self {
+            stalled,
+            disconnect_after,
+            task,
+        })
+    }
+
+    /// Enables the connection to be terminated after `n` consecutive
+    /// stalled periods, or disables termination if `n` is [`None`].
+    #[inline]
+    pub fn disconnect_after(&self, n: Option<NonZeroU32>) {
+        (self.disconnect_after).store(n.map_or(0, NonZeroU32::get), Ordering::Relaxed);
+    }
+
+    /// Returns the next stall notification or [`None`] if the connection was
+    /// closed. Notifications are discarded if they are not received in time.
+    /// This method is cancel safe.
+    #[inline]
+    pub async fn stalled(&mut self) -> Option<LinkStalled> {
+        self.stalled.recv().await
+    }
+}
+
+impl Drop for Keepalive {
+    #[inline]
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Liveness monitor task.
+#[derive(Debug)]
+struct Task {
+    host: Host,
+    hdl: ConnHandle,
+    timeout: Duration,
+    stalled: mpsc::Sender<LinkStalled>,
+    disconnect_after: Arc<AtomicU32>,
+}
+
+impl Task {
+    /// Handles authenticated payload timeout expiration events until the
+    /// connection is closed.
+    async fn ping(self, mut ctl: EventStream) {
+        use EventCode::*;
+        let mut count = 0;
+        let mut last = Instant::now();
+        loop {
+            let code = match ctl.next().await {
+                Ok(evt) if evt.conn_handle() == Some(self.hdl) => evt.code(),
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Keepalive for {} stopped: {e}", self.hdl);
+                    return;
+                }
+            };
+            match code {
+                AuthenticatedPayloadTimeoutExpired => {
+                    // The timer restarts after each expiration, so a longer
+                    // interval means that authenticated packets were received
+                    if last.elapsed() > self.timeout * 2 {
+                        count = 0;
+                    }
+                    last = Instant::now();
+                    count += 1;
+                    if !self.stall(count).await {
+                        return;
+                    }
+                }
+                DisconnectionComplete => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the RSSI once per timeout period until the connection is closed.
+    async fn poll(self, mut cn: ConnWatch) {
+        let mut count = 0;
+        let mut t = tokio::time::interval_at(Instant::now() + self.timeout, self.timeout);
+        t.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                r = cn.changed() => {
+                    if r.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                _ = t.tick() => {}
+            }
+            match self.host.read_rssi(self.hdl).await {
+                Ok(_) => count = 0,
+                Err(Error::CommandFailed {
+                    status: Status::UnknownConnectionIdentifier,
+                    ..
+                }) => return,
+                Err(e) => {
+                    debug!("RSSI read for {} failed: {e}", self.hdl);
+                    count += 1;
+                    if !self.stall(count).await {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reports a stalled period and terminates the connection if the limit
+    /// was reached. Returns whether monitoring should continue.
+    async fn stall(&self, count: u32) -> bool {
+        warn!("Link {} stalled ({count})", self.hdl);
+        let n = LinkStalled {
+            handle: self.hdl,
+            count,
+        };
+        if self.stalled.try_send(n).is_err() {
+            debug!("Discarded stall notification for {}", self.hdl);
+        }
+        let limit = self.disconnect_after.load(Ordering::Relaxed);
+        if limit == 0 || count < limit {
+            return true;
+        }
+        warn!("Disconnecting stalled link {}", self.hdl);
+        let r = Status::RemoteUserTerminatedConnection;
+        if let Err(e) = self.host.disconnect(self.hdl, r).await {
+            warn!("Failed to disconnect {}: {e}", self.hdl);
+        }
+        false
+    }
+}
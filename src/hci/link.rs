@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use tracing::{debug, info, warn, Instrument};
+
+use super::*;
+
+/// Link Layer procedure response timeout ([Vol 6] Part B, Section 5.2).
+const LL_TIMEOUT: Duration = Duration::from_secs(40);
+
+/// Policy that switches new connections to a faster PHY when supported by
+/// both devices ([Vol 6] Part B, Section 5.1.10). By default, only the LE 2M
+/// PHY is requested. Failures are logged and the connection remains on its
+/// current PHY.
+#[derive(Clone, Debug)]
+pub struct PhyPolicy {
+    host: Host,
+    phys: PhyMask,
+    coded: CodedPhy,
+}
+
+impl PhyPolicy {
+    /// Delay before retrying a failed PHY update procedure.
+    pub const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+    /// Creates a policy that requests the LE 2M PHY.
+    #[inline]
+    #[must_use]
+    pub fn new(host: &Host) -> Self {
+        Self {
+            host: host.clone(),
+            phys: PhyMask::LE_2M,
+            coded: CodedPhy::NoPreference,
+        }
+    }
+
+    /// Sets the preferred PHYs. If multiple PHYs are specified, the
+    /// controller selects among them.
+    #[inline]
+    #[must_use]
+    pub const fn with_phys(mut self, phys: PhyMask) -> Self {
+        self.phys = phys;
+        self
+    }
+
+    /// Sets the preferred coding when the LE Coded PHY is used.
+    #[inline]
+    #[must_use]
+    pub const fn with_coded(mut self, coded: CodedPhy) -> Self {
+        self.coded = coded;
+        self
+    }
+
+    /// Starts the PHY update procedure for each new connection until an error
+    /// is encountered. This method is not cancel safe.
+    pub async fn event_loop(&self) -> Result<()> {
+        let mut ctl = self.host.events();
+        loop {
+            let hdl = next_conn(&mut ctl).await?;
+            let this = self.clone();
+            let f = async move {
+                if let Err(e) = this.negotiate(hdl).await {
+                    warn!("PHY update for {hdl} failed: {e}");
+                }
+            };
+            tokio::task::spawn(f.instrument(self.host.conn_span(hdl)));
+        }
+    }
+
+    /// Requests the preferred PHYs for connection `hdl`, retrying once on
+    /// failure.
+    async fn negotiate(&self, hdl: ConnHandle) -> Result<()> {
+        let phys = self.phys & phy_mask(self.host.info().features());
+        if phys.is_empty() {
+            debug!("Controller does not support {:?}", self.phys);
+            return Ok(());
+        }
+        // Assume that the peer supports the PHYs if its features are unknown
+        let remote = remote_features(&self.host, hdl).await?;
+        let phys = phys & remote.map_or(PhyMask::all(), phy_mask);
+        if phys.is_empty() {
+            debug!("Peer {hdl} does not support {:?}", self.phys);
+            return Ok(());
+        }
+        match self.set_phy(hdl, phys).await {
+            Ok(()) => Ok(()),
+            Err(Error::Hci { status: st } | Error::CommandFailed { status: st, .. })
+                if st != Status::UnsupportedRemoteFeature =>
+            {
+                debug!("Retrying PHY update for {hdl} after {st}");
+                tokio::time::sleep(Self::RETRY_DELAY).await;
+                self.set_phy(hdl, phys).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Performs the PHY update procedure and waits for its completion.
+    async fn set_phy(&self, hdl: ConnHandle, phys: PhyMask) -> Result<()> {
+        let (tx, rx) = (Some(phys), Some(phys));
+        let (tx, rx) = self.host.update_phy(hdl, tx, rx, self.coded).await?;
+        info!("PHY for {hdl}: TX {tx:?}, RX {rx:?}");
+        Ok(())
+    }
+}
+
+impl Host {
+    /// Requests a change of the transmitter and receiver PHY for connection
+    /// `hdl` and waits for the PHY update procedure to complete
+    /// ([Vol 4] Part E, Section 7.8.49). A [`None`] mask indicates no
+    /// preference. Returns the transmitter and receiver PHY from the
+    /// `HCI_LE_PHY_Update_Complete` event, which may differ from the preferred
+    /// ones.
+    pub async fn update_phy(
+        &self,
+        hdl: ConnHandle,
+        tx: Option<PhyMask>,
+        rx: Option<PhyMask>,
+        coded: CodedPhy,
+    ) -> Result<(Phy, Phy)> {
+        let mut ctl = self.conn_events(hdl);
+        self.le_set_phy(hdl, tx, rx, coded).await?;
+        let Some(evt) = wait(&mut ctl, hdl, EventCode::LePhyUpdateComplete).await? else {
+            return Err(Status::LmpLlResponseTimeout.into());
+        };
+        let e: LePhyUpdateComplete = evt.get();
+        if !e.status.is_ok() {
+            return Err(e.status.into());
+        }
+        Ok((e.tx_phy, e.rx_phy))
+    }
+}
+
+/// Policy that enables LL Data Length Extension for new connections when
+/// supported by both devices ([Vol 6] Part B, Section 5.1.9). By default, the
+/// controller maximum payload size and transmission time are requested. The
+/// procedure is skipped if the peer features are unknown, because some older
+/// controllers fail to respond to it. Failures are logged and the connection
+/// continues to use the current data length.
+#[derive(Clone, Debug)]
+pub struct DataLenPolicy {
+    host: Host,
+    target: Option<(u16, Duration)>,
+}
+
+impl DataLenPolicy {
+    /// Creates a policy that requests the controller maximum data length.
+    #[inline]
+    #[must_use]
+    pub fn new(host: &Host) -> Self {
+        Self {
+            host: host.clone(),
+            target: None,
+        }
+    }
+
+    /// Sets the requested maximum LL Data PDU payload size and transmission
+    /// time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either value is outside of the range allowed by
+    /// [Vol 6] Part B, Section 4.5.10.
+    #[must_use]
+    pub fn with_target(mut self, octets: u16, time: Duration) -> Self {
+        assert!((LL_MIN_OCTETS..=LL_MAX_OCTETS).contains(&octets));
+        assert!((Duration::from_micros(328)..=Duration::from_micros(17040)).contains(&time));
+        self.target = Some((octets, time));
+        self
+    }
+
+    /// Starts the data length update procedure for each new connection until
+    /// an error is encountered. This method is not cancel safe.
+    pub async fn event_loop(&self) -> Result<()> {
+        let mut ctl = self.host.events();
+        loop {
+            let hdl = next_conn(&mut ctl).await?;
+            let this = self.clone();
+            let f = async move {
+                if let Err(e) = this.negotiate(hdl).await {
+                    warn!("Data length update for {hdl} failed: {e}");
+                }
+            };
+            tokio::task::spawn(f.instrument(self.host.conn_span(hdl)));
+        }
+    }
+
+    /// Requests the target data length for connection `hdl`.
+    async fn negotiate(&self, hdl: ConnHandle) -> Result<()> {
+        let dle = LeFeature::DATA_PACKET_LENGTH_EXTENSION;
+        if !self.host.info().features().contains(dle) {
+            debug!("Controller does not support data length extension");
+            return Ok(());
+        }
+        match remote_features(&self.host, hdl).await? {
+            Some(f) if f.contains(dle) => {}
+            _ => {
+                debug!("Peer {hdl} does not support data length extension");
+                return Ok(());
+            }
+        }
+        let (octets, time) = match self.target {
+            Some(t) => t,
+            None => {
+                let max = self.host.le_read_maximum_data_length().await?;
+                (max.tx_octets, max.tx_time)
+            }
+        };
+        let mut ctl = self.host.conn_events(hdl);
+        self.host.le_set_data_length(hdl, octets, time).await?;
+        // The event is only generated if the data length changes
+        match wait(&mut ctl, hdl, EventCode::LeDataLengthChange).await? {
+            Some(evt) => {
+                let e: LeDataLengthChange = evt.get();
+                info!("Data length for {hdl}: {:?}", e.max);
+            }
+            None => debug!("Data length for {hdl} unchanged"),
+        }
+        Ok(())
+    }
+}
+
+/// Returns the handle of the next successfully established connection.
+async fn next_conn(ctl: &mut EventStream) -> Result<ConnHandle> {
+    use EventCode::*;
+    loop {
+        let evt = ctl.next().await?;
+        if matches!(
+            evt.code(),
+            LeConnectionComplete | LeEnhancedConnectionComplete
+        ) && evt.status().is_ok()
+        {
+            return Ok(evt.conn_handle().expect("invalid event"));
+        }
+    }
+}
+
+/// Returns the features used on connection `hdl` by the peer, reading them
+/// first if necessary. Returns [`None`] if the features could not be read.
+async fn remote_features(host: &Host, hdl: ConnHandle) -> Result<Option<LeFeature>> {
+    let mut ctl = host.conn_events(hdl);
+    let Some(cn) = host.conn(hdl) else {
+        return Err(Status::UnknownConnectionIdentifier.into());
+    };
+    if let Some(f) = cn.borrow().remote_features {
+        return Ok(Some(f));
+    }
+    match host.le_read_remote_features(hdl).await {
+        Ok(()) => {}
+        // Another procedure is already reading the features
+        Err(Error::CommandFailed {
+            status: Status::CommandDisallowed,
+            ..
+        }) => {}
+        Err(e) => {
+            debug!("Failed to read features of {hdl}: {e}");
+            return Ok(None);
+        }
+    }
+    let Some(evt) = wait(&mut ctl, hdl, EventCode::LeReadRemoteFeaturesComplete).await? else {
+        return Ok(None);
+    };
+    let e: LeReadRemoteFeaturesComplete = evt.get();
+    if !e.status.is_ok() {
+        debug!("Failed to read features of {hdl}: {}", e.status);
+        return Ok(None);
+    }
+    Ok(Some(e.features))
+}
+
+/// Waits for an event with the specified code for connection `hdl`. Returns
+/// [`None`] on timeout.
+async fn wait(ctl: &mut EventStream, hdl: ConnHandle, code: EventCode) -> Result<Option<Event>> {
+    let r = tokio::time::timeout(LL_TIMEOUT, async {
+        loop {
+            let evt = ctl.next().await?;
+            if evt.conn_handle() != Some(hdl) {
+                continue;
+            }
+            if evt.code() == code {
+                return Ok(evt);
+            }
+            if evt.code() == EventCode::DisconnectionComplete {
+                return Err(Error::from(Status::UnknownConnectionIdentifier));
+            }
+        }
+    });
+    match r.await {
+        Ok(r) => r.map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Returns the PHYs supported by a device with the specified features.
+fn phy_mask(f: LeFeature) -> PhyMask {
+    let mut m = PhyMask::LE_1M;
+    m.set(PhyMask::LE_2M, f.contains(LeFeature::LE_2M_PHY));
+    m.set(PhyMask::LE_CODED, f.contains(LeFeature::LE_CODED_PHY));
+    m
+}
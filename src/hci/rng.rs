@@ -0,0 +1,69 @@
+use burble_crypto::Nonce;
+
+use super::*;
+
+/// Random number generator that returns bytes generated by the controller
+/// using the `HCI_LE_Rand` command ([Vol 4] Part E, Section 7.8.23). This can
+/// replace the OS CSPRNG when the platform source is untrusted. The bytes are
+/// fetched in advance because [`rand_core::RngCore`] is synchronous.
+///
+/// # Panics
+///
+/// [`rand_core::RngCore`] methods panic if more bytes are requested than were
+/// fetched.
+#[derive(Debug)]
+pub struct ControllerRng(Vec<u8>);
+
+impl ControllerRng {
+    /// Fetches at least `n` random bytes from the controller.
+    pub async fn new(host: &Host, n: usize) -> Result<Self> {
+        let mut v = Vec::with_capacity(n + 7);
+        while v.len() < n {
+            v.extend_from_slice(&host.le_rand().await?.to_le_bytes());
+        }
+        Ok(Self(v))
+    }
+
+    /// Generates a new non-zero random nonce using the controller.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the controller returns all zeros.
+    pub async fn nonce(host: &Host) -> Result<Nonce> {
+        let mut rng = Self::new(host, std::mem::size_of::<u128>()).await?;
+        Ok(Nonce::new_with(&mut rng))
+    }
+
+    /// Returns the number of remaining random bytes.
+    #[inline(always)]
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl rand_core::RngCore for ControllerRng {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        let n = (self.0.len().checked_sub(dst.len())).expect("controller random bytes exhausted");
+        dst.copy_from_slice(&self.0[n..]);
+        self.0.truncate(n);
+    }
+
+    #[inline]
+    fn try_fill_bytes(&mut self, dst: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
+        self.fill_bytes(dst);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for ControllerRng {}
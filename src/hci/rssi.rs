@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tracing::{debug, trace};
+
+use super::*;
+
+/// Received signal strength sample.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RssiSample {
+    /// RSSI in dBm.
+    pub rssi: i8,
+    pub time: Instant,
+}
+
+/// RSSI statistics over a window of samples.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RssiStats {
+    pub min: i8,
+    /// Average RSSI rounded to the nearest dBm.
+    pub avg: i8,
+    pub max: i8,
+    pub count: usize,
+}
+
+impl RssiStats {
+    /// Returns statistics for the specified samples or [`None`] if there are
+    /// no samples.
+    #[must_use]
+    fn new(it: impl IntoIterator<Item = i8>) -> Option<Self> {
+        let (mut min, mut max, mut sum, mut n) = (i8::MAX, i8::MIN, 0_i64, 0_i64);
+        for v in it {
+            (min, max) = (min.min(v), max.max(v));
+            sum += i64::from(v);
+            n += 1;
+        }
+        (n > 0).then(|| Self {
+            min,
+            // Round half away from zero
+            avg: i8::try_from((2 * sum + sum.signum() * n) / (2 * n)).unwrap(),
+            max,
+            count: usize::try_from(n).unwrap(),
+        })
+    }
+}
+
+/// Periodic RSSI monitor for a connection ([Vol 4] Part E, Section 7.5.4).
+/// Polls are skipped while the connection is subrated, and failed polls are
+/// coalesced into the next successful one. The stream ends when the
+/// connection is closed.
+#[derive(Debug)]
+pub struct RssiStream {
+    host: Host,
+    hdl: ConnHandle,
+    cn: ConnWatch,
+    t: Interval,
+    window: Duration,
+    samples: VecDeque<RssiSample>,
+}
+
+impl RssiStream {
+    /// Default window used by [`Self::stats`].
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+    /// Creates a stream that reads the RSSI of connection `hdl` every
+    /// `interval`.
+    pub fn new(host: &Host, hdl: ConnHandle, interval: Duration) -> Result<Self> {
+        let Some(cn) = host.conn(hdl) else {
+            return Err(Status::UnknownConnectionIdentifier.into());
+        };
+        let mut t = tokio::time::interval(interval);
+        t.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self {
+            host: host.clone(),
+            hdl,
+            cn,
+            t,
+            window: Self::DEFAULT_WINDOW,
+            samples: VecDeque::new(),
+        })
+    }
+
+    /// Sets the time window used by [`Self::stats`].
+    #[inline]
+    #[must_use]
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Returns the next sample or [`None`] if the connection was closed. This
+    /// method is cancel safe.
+    pub async fn next(&mut self) -> Option<RssiSample> {
+        loop {
+            tokio::select! {
+                r = self.cn.changed() => {
+                    if r.is_err() {
+                        return None;
+                    }
+                    continue;
+                }
+                _ = self.t.tick() => {}
+            }
+            if self.cn.borrow().subrate_factor > 1 {
+                trace!("Skipping RSSI poll for subrated {}", self.hdl);
+                continue;
+            }
+            match self.host.read_rssi(self.hdl).await {
+                Ok(Some(rssi)) => {
+                    let s = RssiSample {
+                        rssi,
+                        time: Instant::now(),
+                    };
+                    self.samples.push_back(s);
+                    self.prune();
+                    return Some(s);
+                }
+                Ok(None) => {}
+                Err(Error::CommandFailed {
+                    status: Status::UnknownConnectionIdentifier,
+                    ..
+                }) => return None,
+                Err(e) => debug!("RSSI read for {} failed: {e}", self.hdl),
+            }
+        }
+    }
+
+    /// Returns statistics for the samples received within the window or
+    /// [`None`] if there are no such samples.
+    #[must_use]
+    pub fn stats(&self) -> Option<RssiStats> {
+        let start = Instant::now().checked_sub(self.window);
+        let recent = (self.samples.iter()).filter(|s| start.map_or(true, |t| t <= s.time));
+        RssiStats::new(recent.map(|s| s.rssi))
+    }
+
+    /// Removes samples that are outside of the window.
+    fn prune(&mut self) {
+        let Some(start) = Instant::now().checked_sub(self.window) else {
+            return;
+        };
+        while self.samples.front().map_or(false, |s| s.time < start) {
+            self.samples.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        assert_eq!(RssiStats::new([]), None);
+        assert_eq!(
+            RssiStats::new([-60, -70, -65]),
+            Some(RssiStats {
+                min: -70,
+                avg: -65,
+                max: -60,
+                count: 3,
+            })
+        );
+        assert_eq!(RssiStats::new([-60, -61]).map(|s| s.avg), Some(-61));
+        assert_eq!(
+            RssiStats::new([i8::MIN, i8::MIN]).map(|s| s.avg),
+            Some(i8::MIN)
+        );
+        assert_eq!(RssiStats::new([1, 2]).map(|s| s.avg), Some(2));
+    }
+}
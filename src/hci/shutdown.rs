@@ -0,0 +1,184 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::*;
+
+/// Reason sent to the peers of connections closed during shutdown. This tells
+/// the peer that the device is going away, so it does not wait for the
+/// supervision timeout.
+const REASON: Status = Status::RemoteDeviceTerminatedConnectionDueToPowerOff;
+
+/// Result of [`Host::shutdown`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ShutdownReport {
+    /// Whether all advertising sets were disabled.
+    pub adv_disabled: bool,
+    /// Connections that were closed.
+    pub disconnected: Vec<ConnHandle>,
+    /// Connections that were not closed before the timeout.
+    pub timed_out: Vec<ConnHandle>,
+    /// Whether the controller was reset.
+    pub reset: bool,
+    /// Whether the event loop was stopped.
+    pub event_loop_stopped: bool,
+}
+
+impl ShutdownReport {
+    /// Returns whether all shutdown steps were completed.
+    #[inline]
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.adv_disabled && self.timed_out.is_empty() && self.reset && self.event_loop_stopped
+    }
+}
+
+impl Host {
+    /// Gracefully shuts down the host. All advertising sets are disabled, all
+    /// connections are terminated, and the controller is reset after waiting
+    /// up to `timeout` for the connections to close. The event loop is
+    /// stopped at the end.
+    ///
+    /// Peer data, such as bonding keys and client characteristic
+    /// configurations, is saved to its store as soon as it changes, so no
+    /// data is lost once the connections are closed.
+    ///
+    /// Failed steps are logged and reported instead of stopping the shutdown.
+    /// The host should not be used after calling this method.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let ct = (self.guard.as_ref()).and_then(|g| g.take_event_loop());
+        let mut rpt = ShutdownReport::default();
+        info!("Host shutdown...");
+
+        // [Vol 4] Part E, Section 7.8.56
+        match self.le_set_extended_advertising_enable(false, &[]).await {
+            Ok(()) => rpt.adv_disabled = true,
+            Err(e) => warn!("Failed to disable advertising: {e}"),
+        }
+
+        // The stream must be created before the connection handles are read to
+        // guarantee that all DisconnectionComplete events are received.
+        let mut ctl = self.events();
+        let mut pending: BTreeSet<ConnHandle> = self.router.conn_handles().into_iter().collect();
+        for &hdl in &pending {
+            debug!("Disconnecting {hdl}");
+            if let Err(e) = self.disconnect(hdl, REASON).await {
+                warn!("Failed to disconnect {hdl}: {e}");
+            }
+        }
+        while !pending.is_empty() {
+            let Ok(r) = tokio::time::timeout_at(deadline, ctl.next()).await else {
+                warn!("Timeout waiting for connections to close: {pending:?}");
+                break;
+            };
+            let evt = match r {
+                Ok(evt) => evt,
+                Err(e) => {
+                    warn!("Event stream error: {e}");
+                    break;
+                }
+            };
+            if evt.code() != EventCode::DisconnectionComplete || !evt.status().is_ok() {
+                continue;
+            }
+            if let Some(hdl) = evt.conn_handle().filter(|h| pending.remove(h)) {
+                rpt.disconnected.push(hdl);
+            }
+        }
+        drop(ctl);
+        rpt.timed_out.extend(pending);
+
+        match self.reset().await {
+            Ok(()) => rpt.reset = true,
+            Err(e) => warn!("Failed to reset controller: {e}"),
+        }
+        if let Some(ct) = ct {
+            ct.cancel();
+            rpt.event_loop_stopped = true;
+        }
+        info!("Host shutdown complete: {rpt:?}");
+        rpt
+    }
+
+    /// Submits the commands to disable advertising and terminate all
+    /// connections without waiting for their completion.
+    async fn shutdown_now(&self) {
+        let mut cmd = Command::new(self, Opcode::LeSetExtendedAdvertisingEnable);
+        cmd.append().bool(false).u8(0);
+        if let Err(e) = cmd.submit().await {
+            warn!("Failed to disable advertising: {e}");
+        }
+        for hdl in self.router.conn_handles() {
+            debug!("Disconnecting {hdl}");
+            let mut cmd = Command::new(self, Opcode::Disconnect);
+            cmd.append().u16(hdl).u8(REASON as u8);
+            if let Err(e) = cmd.submit().await {
+                warn!("Failed to disconnect {hdl}: {e}");
+            }
+        }
+    }
+}
+
+/// Guard shared by all application-owned clones of a [`Host`]. When the last
+/// clone is dropped without calling [`Host::shutdown`], it submits the
+/// shutdown commands without waiting for their completion and stops the event
+/// loop, which resets the controller.
+#[derive(Debug)]
+pub(super) struct ShutdownGuard {
+    host: Host,
+    event_loop: SyncMutex<Option<CancellationToken>>,
+}
+
+impl ShutdownGuard {
+    /// Creates a guard for the specified host.
+    #[must_use]
+    pub fn new(host: &Host) -> Self {
+        let mut host = host.clone();
+        host.info = Arc::clone(&NO_CONTROLLER_INFO);
+        host.guard = None;
+        Self {
+            host,
+            event_loop: SyncMutex::default(),
+        }
+    }
+
+    /// Sets the cancellation token of the current event loop.
+    #[inline]
+    pub fn set_event_loop(&self, ct: CancellationToken) {
+        *self.event_loop.lock() = Some(ct);
+    }
+
+    /// Takes the cancellation token of the current event loop, disabling the
+    /// shutdown on drop.
+    #[inline]
+    fn take_event_loop(&self) -> Option<CancellationToken> {
+        self.event_loop.lock().take()
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        // The controller is reset when the event loop stops, so there is
+        // nothing to do if it is no longer running.
+        let Some(ct) = self.event_loop.get_mut().take() else {
+            return;
+        };
+        if ct.is_cancelled() {
+            return;
+        }
+        let Ok(rt) = tokio::runtime::Handle::try_current() else {
+            ct.cancel();
+            return;
+        };
+        debug!("Host dropped without shutdown");
+        let host = self.host.clone();
+        rt.spawn(async move {
+            host.shutdown_now().await;
+            ct.cancel();
+        });
+    }
+}
@@ -0,0 +1,114 @@
+use tokio::sync::mpsc;
+use tracing::{debug, trace, warn, Instrument};
+
+use super::*;
+
+/// Transmit power level change reports for a connection started by
+/// [`TxPowerReports::new`] ([Vol 4] Part E, Section 7.7.65.33).
+///
+/// Reports are received when the local or remote transmit power level changes,
+/// as enabled by [`Host::le_set_transmit_power_reporting_enable`], and when
+/// [`Host::le_read_remote_transmit_power_level`] completes. This is the input
+/// for a power control loop that adjusts the link based on the peer's
+/// transmit power and the path loss.
+///
+/// The reports stop when this is dropped or the connection is closed.
+#[derive(Debug)]
+pub struct TxPowerReports {
+    reports: mpsc::Receiver<LeTransmitPowerReporting>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl TxPowerReports {
+    /// Enables local and/or remote transmit power reporting for connection
+    /// `hdl` and starts receiving reports.
+    pub async fn new(host: &Host, hdl: ConnHandle, local: bool, remote: bool) -> Result<Self> {
+        if host.conn(hdl).is_none() {
+            return Err(Status::UnknownConnectionIdentifier.into());
+        }
+        // Register the stream first to avoid missing any reports
+        let ctl = host.conn_events(hdl);
+        (host.le_set_transmit_power_reporting_enable(hdl, local, remote)).await?;
+        debug!("Receiving TX power reports for {hdl} (local={local}, remote={remote})");
+        let (tx, reports) = mpsc::channel(16);
+        let task = tokio::task::spawn(forward(hdl, ctl, tx).instrument(host.conn_span(hdl)));
+        Ok(Self { reports, task })
+    }
+
+    /// Returns the next report or [`None`] if the connection was closed.
+    /// Reports are discarded if they are not received in time. This method is
+    /// cancel safe.
+    #[inline]
+    pub async fn next(&mut self) -> Option<LeTransmitPowerReporting> {
+        self.reports.recv().await
+    }
+}
+
+impl Drop for TxPowerReports {
+    #[inline]
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Path loss zone transitions for a connection started by
+/// [`PathLossZones::new`] ([Vol 4] Part E, Section 7.7.65.32).
+///
+/// A transition is reported when the path loss crosses one of the thresholds
+/// in [`PathLossParams`] and remains in the new zone for the minimum time. The
+/// transitions stop when this is dropped or the connection is closed.
+#[derive(Debug)]
+pub struct PathLossZones {
+    zones: mpsc::Receiver<LePathLossThreshold>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl PathLossZones {
+    /// Configures and enables path loss reporting for connection `hdl` and
+    /// starts receiving zone transitions.
+    pub async fn new(host: &Host, hdl: ConnHandle, p: PathLossParams) -> Result<Self> {
+        if host.conn(hdl).is_none() {
+            return Err(Status::UnknownConnectionIdentifier.into());
+        }
+        (host.le_set_path_loss_reporting_parameters(hdl, p)).await?;
+        let ctl = host.conn_events(hdl);
+        (host.le_set_path_loss_reporting_enable(hdl, true)).await?;
+        debug!("Receiving path loss zone transitions for {hdl} ({p:?})");
+        let (tx, zones) = mpsc::channel(16);
+        let task = tokio::task::spawn(forward(hdl, ctl, tx).instrument(host.conn_span(hdl)));
+        Ok(Self { zones, task })
+    }
+
+    /// Returns the next zone transition or [`None`] if the connection was
+    /// closed. Transitions are discarded if they are not received in time. This
+    /// method is cancel safe.
+    #[inline]
+    pub async fn next(&mut self) -> Option<LePathLossThreshold> {
+        self.zones.recv().await
+    }
+}
+
+impl Drop for PathLossZones {
+    #[inline]
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Forwards events of type `T` until the connection is closed.
+async fn forward<T: TryFromEvent>(hdl: ConnHandle, mut ctl: EventStream, tx: mpsc::Sender<T>) {
+    loop {
+        let v = match ctl.next().await {
+            Ok(evt) if T::matches(evt.code()) => evt.get(),
+            Ok(evt) if evt.code() == EventCode::DisconnectionComplete => return,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Event forwarding for {hdl} stopped: {e}");
+                return;
+            }
+        };
+        if tx.try_send(v).is_err() {
+            trace!("Discarded event for {hdl}");
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use tokio::sync::mpsc;
+use tracing::{debug, error, trace};
+
+use super::*;
+
+impl Host {
+    /// Executes vendor-specific command `ocf`, calling `f` to provide
+    /// parameters, and returns the return parameters following the status
+    /// ([Vol 4] Part E, Section 5.4.1). This is used for controller-specific
+    /// setup, such as loading firmware patches. The controller must complete
+    /// the command with an `HCI_Command_Complete` event.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ocf` is not a 10-bit value.
+    pub async fn vendor_cmd(
+        &self,
+        ocf: u16,
+        f: impl FnOnce(&mut Packer) + Send,
+    ) -> Result<Vec<u8>> {
+        let mut cmd = Command::vendor(self, ocf);
+        f(&mut cmd.append());
+        let evt = cmd.exec().await.map_err(|e| {
+            error!("Vendor command {ocf:#06X} error: {e}");
+            e
+        })?;
+        evt.map_ok(|_, p| {
+            let n = p.len();
+            p.skip(n).map_or_else(Vec::new, |d| d.into_inner().to_vec())
+        })
+    }
+
+    /// Returns a stream of vendor-specific events
+    /// ([Vol 4] Part E, Section 5.4.4). Only events received after this call
+    /// are returned.
+    #[must_use]
+    pub fn vendor_events(&self) -> VendorEvents {
+        let (tx, events) = mpsc::channel(16);
+        VendorEvents {
+            events,
+            task: tokio::task::spawn(forward(self.events(), tx)),
+        }
+    }
+}
+
+/// Vendor-specific event stream returned by [`Host::vendor_events`]. The stream
+/// stops when it is dropped.
+#[derive(Debug)]
+pub struct VendorEvents {
+    events: mpsc::Receiver<VendorEvent>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl VendorEvents {
+    /// Returns the next event or [`None`] if the event stream was closed due
+    /// to a host error. Events are discarded if they are not received in
+    /// time. This method is cancel safe.
+    #[inline]
+    pub async fn next(&mut self) -> Option<VendorEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for VendorEvents {
+    #[inline]
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Forwards vendor-specific events until the event stream is closed.
+async fn forward(mut ctl: EventStream, tx: mpsc::Sender<VendorEvent>) {
+    loop {
+        let v = match ctl.next().await {
+            Ok(evt) if matches!(evt.code(), EventCode::Vendor) => evt.get(),
+            Ok(_) => continue,
+            Err(e) => {
+                debug!("Vendor event stream stopped: {e}");
+                return;
+            }
+        };
+        if tx.try_send(v).is_err() {
+            trace!("Discarded vendor event");
+        }
+    }
+}
@@ -9,16 +9,18 @@ use std::task::{ready, Context, Poll};
 
 use futures_core::FusedFuture;
 
+pub use stats::*;
 #[cfg(feature = "usb")]
 pub use usb::*;
 
 use crate::hci;
 
+mod stats;
 #[cfg(feature = "usb")]
 mod usb;
 
 /// Local host errors.
-#[derive(Clone, Copy, Debug, thiserror::Error)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
 #[non_exhaustive]
 pub enum Error {
     /// Controller cannot be accessed due to a permission problem.
@@ -55,6 +57,14 @@ pub trait Transport: Debug + Send + Sync {
     /// Returns an Asynchronous Connection-Oriented transfer.
     #[must_use]
     fn acl(&self, dir: hci::Direction, max_data_len: u16) -> Box<dyn Transfer>;
+
+    /// Returns transfer latency, throughput, and error statistics, if the
+    /// transport collects them.
+    #[inline]
+    #[must_use]
+    fn stats(&self) -> Option<TransportStats> {
+        None
+    }
 }
 
 /// Asynchronous I/O transfer.
@@ -0,0 +1,315 @@
+use std::time::{Duration, Instant};
+
+use crate::hci::{Direction, TransferType};
+use crate::SyncMutex;
+
+use super::Error;
+
+/// Transport statistics recorder. Transports record the submit-to-complete
+/// latency, size, and result of each transfer, and return a snapshot from
+/// [`super::Transport::stats`].
+#[derive(Debug)]
+pub struct StatsRecorder(SyncMutex<Stats>);
+
+impl StatsRecorder {
+    /// Creates a new recorder.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self(SyncMutex::new(Stats::new(Instant::now())))
+    }
+
+    /// Records a successful transfer of `n` bytes submitted at `start`.
+    pub fn ok(&self, typ: TransferType, start: Instant, n: usize) {
+        let now = Instant::now();
+        let mut s = self.0.lock();
+        let t = s.typ(typ);
+        t.latency.add(now.saturating_duration_since(start));
+        t.bytes += n as u64;
+        let rate = match typ.dir() {
+            Direction::ToHost => &mut s.rx,
+            Direction::FromHost => &mut s.tx,
+        };
+        rate.add(now, n as u64);
+    }
+
+    /// Records a failed transfer.
+    pub fn err(&self, typ: TransferType, e: Error) {
+        let mut s = self.0.lock();
+        let errors = &mut s.typ(typ).errors;
+        match errors.iter_mut().find(|(k, _)| *k == e) {
+            Some((_, n)) => *n += 1,
+            None => errors.push((e, 1)),
+        }
+    }
+
+    /// Returns a snapshot of the current statistics.
+    #[must_use]
+    pub fn snapshot(&self) -> TransportStats {
+        self.0.lock().snapshot(Instant::now())
+    }
+}
+
+impl Default for StatsRecorder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Snapshot of transport statistics.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TransportStats {
+    pub command: TransferStats,
+    /// Event transfers. Their latency includes the time spent waiting for the
+    /// controller to generate an event.
+    pub event: TransferStats,
+    pub acl_in: TransferStats,
+    pub acl_out: TransferStats,
+    /// Bytes per second received from the controller over the last
+    /// [`RATE_WINDOW`].
+    pub rx_rate: u64,
+    /// Bytes per second sent to the controller over the last [`RATE_WINDOW`].
+    pub tx_rate: u64,
+}
+
+/// Window over which [`TransportStats`] transfer rates are computed.
+pub const RATE_WINDOW: Duration = Duration::from_secs(Rate::SLOTS as u64);
+
+/// Statistics for one transfer type.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TransferStats {
+    /// Latency of successful transfers.
+    pub latency: Histogram,
+    /// Total number of bytes transferred.
+    pub bytes: u64,
+    /// Number of failed transfers by error.
+    pub errors: Vec<(Error, u64)>,
+}
+
+impl TransferStats {
+    /// Returns the total number of failed transfers.
+    #[inline]
+    #[must_use]
+    pub fn error_count(&self) -> u64 {
+        self.errors.iter().map(|&(_, n)| n).sum()
+    }
+}
+
+/// Latency histogram with power-of-two microsecond buckets. Bucket `i`
+/// contains latencies in the range `[2^i, 2^(i+1))` µs, except that the first
+/// bucket also contains latencies below 1 µs and the last bucket contains all
+/// latencies above its lower bound.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Histogram {
+    pub buckets: [u64; Histogram::BUCKETS],
+    pub count: u64,
+    pub sum: Duration,
+    pub max: Duration,
+}
+
+impl Histogram {
+    /// Number of histogram buckets. The last bucket starts at ~16.8 seconds.
+    pub const BUCKETS: usize = 25;
+
+    /// Adds a latency sample.
+    fn add(&mut self, d: Duration) {
+        let us = u64::try_from(d.as_micros()).unwrap_or(u64::MAX);
+        let i = (u64::BITS - 1).saturating_sub(us.leading_zeros()) as usize;
+        self.buckets[i.min(Self::BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(d);
+        self.max = self.max.max(d);
+    }
+
+    /// Returns the mean latency or [`None`] if the histogram is empty.
+    #[must_use]
+    pub fn mean(&self) -> Option<Duration> {
+        let n = u32::try_from(self.count).unwrap_or(u32::MAX);
+        (n > 0).then(|| self.sum / n)
+    }
+
+    /// Returns the upper bound of the bucket containing the `p`-th percentile
+    /// sample (`0.0 < p <= 100.0`), limited by the maximum latency. Returns
+    /// [`None`] if the histogram is empty.
+    #[must_use]
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        assert!(p > 0.0 && p <= 100.0, "invalid percentile");
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let rank = ((self.count as f64) * p / 100.0).ceil() as u64;
+        let mut n = 0;
+        for (i, &v) in self.buckets.iter().enumerate() {
+            n += v;
+            if n >= rank.max(1) {
+                let upper = if i + 1 < Self::BUCKETS {
+                    Duration::from_micros(1 << (i + 1))
+                } else {
+                    Duration::MAX
+                };
+                return Some(upper.min(self.max));
+            }
+        }
+        None
+    }
+}
+
+/// Recorded statistics.
+#[derive(Debug)]
+struct Stats {
+    typ: [TransferStats; 4],
+    rx: Rate,
+    tx: Rate,
+}
+
+impl Stats {
+    /// Creates empty statistics with the rate windows starting at `epoch`.
+    fn new(epoch: Instant) -> Self {
+        Self {
+            typ: Default::default(),
+            rx: Rate::new(epoch),
+            tx: Rate::new(epoch),
+        }
+    }
+
+    /// Returns the statistics for transfer type `t`.
+    fn typ(&mut self, t: TransferType) -> &mut TransferStats {
+        &mut self.typ[match t {
+            TransferType::Command => 0,
+            TransferType::Event => 1,
+            TransferType::Acl(Direction::ToHost) => 2,
+            TransferType::Acl(Direction::FromHost) => 3,
+        }]
+    }
+
+    /// Returns a snapshot of the statistics at time `now`.
+    fn snapshot(&self, now: Instant) -> TransportStats {
+        let [command, event, acl_in, acl_out] = self.typ.clone();
+        TransportStats {
+            command,
+            event,
+            acl_in,
+            acl_out,
+            rx_rate: self.rx.per_sec(now),
+            tx_rate: self.tx.per_sec(now),
+        }
+    }
+}
+
+/// Sliding window transfer rate with one-second resolution.
+#[derive(Debug)]
+struct Rate {
+    epoch: Instant,
+    slots: [(u64, u64); Self::SLOTS],
+}
+
+impl Rate {
+    /// Number of one-second slots in the window.
+    const SLOTS: usize = 10;
+
+    /// Creates an empty window starting at `epoch`.
+    const fn new(epoch: Instant) -> Self {
+        Self {
+            epoch,
+            slots: [(0, 0); Self::SLOTS],
+        }
+    }
+
+    /// Adds `n` bytes transferred at time `now`.
+    fn add(&mut self, now: Instant, n: u64) {
+        let sec = self.sec(now);
+        let slot = &mut self.slots[usize::try_from(sec % Self::SLOTS as u64).unwrap()];
+        if slot.0 != sec {
+            *slot = (sec, 0);
+        }
+        slot.1 += n;
+    }
+
+    /// Returns the average number of bytes per second over the window ending
+    /// at `now`, or since the epoch if the window is not yet full.
+    fn per_sec(&self, now: Instant) -> u64 {
+        let sec = self.sec(now);
+        let (first, span) = match sec.checked_sub(Self::SLOTS as u64 - 1) {
+            Some(first) => (first, Self::SLOTS as u64),
+            None => (0, sec + 1),
+        };
+        let sum: u64 = (self.slots.iter())
+            .filter(|&&(s, _)| first <= s && s <= sec)
+            .map(|&(_, n)| n)
+            .sum();
+        sum / span
+    }
+
+    /// Returns the number of whole seconds between the epoch and `t`.
+    #[inline]
+    fn sec(&self, t: Instant) -> u64 {
+        t.saturating_duration_since(self.epoch).as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut h = Histogram::default();
+        assert_eq!(h.mean(), None);
+        assert_eq!(h.percentile(50.0), None);
+        for us in [0, 1, 3, 900, 1000, 1100] {
+            h.add(Duration::from_micros(us));
+        }
+        h.add(Duration::from_secs(60));
+        assert_eq!(h.buckets[0], 2);
+        assert_eq!(h.buckets[1], 1);
+        assert_eq!(h.buckets[9], 2);
+        assert_eq!(h.buckets[10], 1);
+        assert_eq!(h.buckets[Histogram::BUCKETS - 1], 1);
+        assert_eq!(h.count, 7);
+        assert_eq!(h.max, Duration::from_secs(60));
+        assert_eq!(h.percentile(25.0), Some(Duration::from_micros(2)));
+        assert_eq!(h.percentile(50.0), Some(Duration::from_micros(1024)));
+        assert_eq!(h.percentile(85.0), Some(Duration::from_micros(2048)));
+        assert_eq!(h.percentile(100.0), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn rate() {
+        let t0 = Instant::now();
+        let sec = |s| t0 + Duration::from_secs(s);
+        let mut r = Rate::new(t0);
+        assert_eq!(r.per_sec(t0), 0);
+        r.add(t0, 100);
+        r.add(sec(1), 300);
+        assert_eq!(r.per_sec(sec(1)), 200);
+        assert_eq!(r.per_sec(sec(9)), 40);
+
+        // Old slots leave the window and are reused
+        r.add(sec(10), 600);
+        assert_eq!(r.per_sec(sec(10)), 90);
+        assert_eq!(r.per_sec(sec(11)), 60);
+        assert_eq!(r.per_sec(sec(30)), 0);
+    }
+
+    #[test]
+    fn recorder() {
+        let r = StatsRecorder::new();
+        let start = Instant::now();
+        r.ok(TransferType::Command, start, 4);
+        r.ok(TransferType::Acl(Direction::ToHost), start, 27);
+        r.err(TransferType::Event, Error::Timeout);
+        r.err(TransferType::Event, Error::Timeout);
+        r.err(TransferType::Event, Error::Broken);
+        let s = r.snapshot();
+        assert_eq!((s.command.latency.count, s.command.bytes), (1, 4));
+        assert_eq!(s.acl_in.bytes, 27);
+        assert_eq!(s.tx_rate + s.rx_rate, 31);
+        assert_eq!(s.event.errors, [(Error::Timeout, 2), (Error::Broken, 1)]);
+        assert_eq!(s.event.error_count(), 3);
+    }
+}
@@ -5,7 +5,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use rusb::UsbContext;
 use structbuf::{Pack, Packer};
@@ -119,12 +119,14 @@ impl Display for UsbControllerInfo {
 pub struct UsbController {
     hdl: Arc<DeviceHandle>,
     ep: Endpoints,
+    stats: Arc<StatsRecorder>,
 }
 
 impl UsbController {
     fn new(hdl: DeviceHandle, ep: Endpoints) -> Self {
         let hdl = Arc::new(hdl);
-        Self { hdl, ep }
+        let stats = Arc::default();
+        Self { hdl, ep, stats }
     }
 
     /// Configures the controller for HCI access.
@@ -166,15 +168,15 @@ impl Transport for UsbController {
         // A one-second timer is recommended for command completion, so we use
         // the same for submission ([Vol 4] Part E, Section 4.4)
         t.set_timeout(Duration::from_secs(1));
-        Box::new(UsbTransfer::Idle(t))
+        self.transfer(t)
     }
 
     fn event(&self) -> Box<dyn Transfer> {
-        Box::new(UsbTransfer::Idle(libusb::Transfer::new_interrupt(
+        self.transfer(libusb::Transfer::new_interrupt(
             &self.hdl,
             self.ep.event,
             hci::EVT_BUF,
-        )))
+        ))
     }
 
     fn acl(&self, dir: Direction, max_data_len: u16) -> Box<dyn Transfer> {
@@ -182,17 +184,42 @@ impl Transport for UsbController {
             Direction::ToHost => self.ep.acl_in,
             Direction::FromHost => self.ep.acl_out,
         };
-        Box::new(UsbTransfer::Idle(libusb::Transfer::new_bulk(
+        self.transfer(libusb::Transfer::new_bulk(
             &self.hdl,
             endpoint,
             hci::ACL_HDR + max_data_len as usize,
-        )))
+        ))
+    }
+
+    #[inline]
+    fn stats(&self) -> Option<TransportStats> {
+        Some(self.stats.snapshot())
+    }
+}
+
+impl UsbController {
+    /// Returns a new transfer that records its statistics.
+    #[inline]
+    fn transfer(&self, t: Box<libusb::Transfer<rusb::Context>>) -> Box<dyn Transfer> {
+        Box::new(UsbTransfer {
+            state: UsbState::Idle(t),
+            stats: Arc::clone(&self.stats),
+            start: None,
+        })
     }
 }
 
 /// Asynchronous USB transfer.
 #[derive(Debug)]
-enum UsbTransfer {
+struct UsbTransfer {
+    state: UsbState,
+    stats: Arc<StatsRecorder>,
+    start: Option<(TransferType, Instant)>,
+}
+
+/// USB transfer state.
+#[derive(Debug)]
+enum UsbState {
     Idle(Box<libusb::Transfer<rusb::Context>>),
     Future(libusb::TransferFuture<rusb::Context>),
 }
@@ -200,8 +227,8 @@ enum UsbTransfer {
 impl Transfer for UsbTransfer {
     fn typ(&self) -> TransferType {
         use rusb::{Direction::*, TransferType::*};
-        match *self {
-            Self::Idle(ref t) => match t.typ() {
+        match self.state {
+            UsbState::Idle(ref t) => match t.typ() {
                 Control => TransferType::Command,
                 Isochronous => unreachable!(),
                 Bulk => TransferType::Acl(match t.dir() {
@@ -210,26 +237,31 @@ impl Transfer for UsbTransfer {
                 }),
                 Interrupt => TransferType::Event,
             },
-            Self::Future(_) => unreachable!(),
+            UsbState::Future(_) => unreachable!(),
         }
     }
 
     fn exec(mut self: Box<Self>) -> Exec {
-        *self = match *self {
-            Self::Idle(t) => match t.submit() {
-                Ok(t) => Self::Future(t),
-                Err(e) => return Exec::ready(Err(e.into())),
+        let typ = self.typ();
+        self.state = match self.state {
+            UsbState::Idle(t) => match t.submit() {
+                Ok(t) => UsbState::Future(t),
+                Err(e) => {
+                    self.stats.err(typ, e.into());
+                    return Exec::ready(Err(e.into()));
+                }
             },
-            Self::Future(_) => unreachable!(),
+            UsbState::Future(_) => unreachable!(),
         };
+        self.start = Some((typ, Instant::now()));
         let fut: Pin<Box<Self>> = Pin::new(self);
         Exec::pending(fut)
     }
 
     fn reset(&mut self) {
-        match *self {
-            Self::Idle(ref mut t) => t.reset(),
-            Self::Future(_) => unreachable!(),
+        match self.state {
+            UsbState::Idle(ref mut t) => t.reset(),
+            UsbState::Future(_) => unreachable!(),
         }
     }
 }
@@ -239,11 +271,20 @@ impl Future for UsbTransfer {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-        *this = Self::Idle(match *this {
-            Self::Idle(_) => unreachable!(),
-            Self::Future(ref mut f) => ready!(Pin::new(f).poll(cx))?,
-        });
-        Poll::Ready(Ok(()))
+        let UsbState::Future(ref mut f) = this.state else { unreachable!() };
+        let r = ready!(Pin::new(f).poll(cx));
+        let (typ, start) = this.start.take().unwrap();
+        match r {
+            Ok(t) => {
+                this.stats.ok(typ, start, (*t).as_ref().len());
+                this.state = UsbState::Idle(t);
+                Poll::Ready(Ok(()))
+            }
+            Err(e) => {
+                this.stats.err(typ, e.into());
+                Poll::Ready(Err(e.into()))
+            }
+        }
     }
 }
 
@@ -258,9 +299,9 @@ impl PendingTransfer for UsbTransfer {
 impl AsRef<[u8]> for UsbTransfer {
     #[inline]
     fn as_ref(&self) -> &[u8] {
-        match *self {
-            Self::Idle(ref t) => (**t).as_ref(),
-            Self::Future(_) => unreachable!(),
+        match self.state {
+            UsbState::Idle(ref t) => (**t).as_ref(),
+            UsbState::Future(_) => unreachable!(),
         }
     }
 }
@@ -268,17 +309,17 @@ impl AsRef<[u8]> for UsbTransfer {
 impl Pack for UsbTransfer {
     #[inline]
     fn append(&mut self) -> Packer {
-        match *self {
-            Self::Idle(ref mut t) => (**t).append(),
-            Self::Future(_) => unreachable!(),
+        match self.state {
+            UsbState::Idle(ref mut t) => (**t).append(),
+            UsbState::Future(_) => unreachable!(),
         }
     }
 
     #[inline]
     fn at(&mut self, i: usize) -> Packer {
-        match *self {
-            Self::Idle(ref mut t) => (**t).at(i),
-            Self::Future(_) => unreachable!(),
+        match self.state {
+            UsbState::Idle(ref mut t) => (**t).at(i),
+            UsbState::Future(_) => unreachable!(),
         }
     }
 }
@@ -3,11 +3,12 @@ use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
 
 use structbuf::{Unpack, Unpacker};
-use tracing::{error, info, trace};
+use tracing::{debug, error, info, trace};
 
 use crate::hci::ACL_LE_MIN_DATA_LEN;
 
@@ -27,7 +28,7 @@ impl Chan {
     pub(super) fn new(cid: LeCid, cn: &hci::ConnWatch, tx: &Arc<Sender>, mtu: u16) -> Self {
         assert!(mtu >= L2CAP_LE_MIN_MTU);
         Self {
-            raw: RawChan::new(cid, cn, L2CAP_HDR + mtu as usize),
+            raw: RawChan::new(cid, cn, mtu),
             tx: Arc::clone(tx),
             mtu,
         }
@@ -51,12 +52,13 @@ impl Chan {
         self.mtu
     }
 
-    /// Returns the maximum MTU that avoids fragmentation.
+    /// Returns the maximum MTU that avoids fragmentation. If the data length
+    /// was increased, this is also limited by the maximum LL Data PDU payload
+    /// size, so that each PDU is sent in a single LL packet.
     #[allow(clippy::cast_possible_truncation)]
     #[inline]
     pub(crate) fn preferred_mtu(&self) -> u16 {
-        // TODO: Use HCI_LE_Data_Length_Change information?
-        self.tx.alloc().acl_data_len - L2CAP_HDR as u16
+        self.raw.max_frag_len(self.tx.alloc().acl_data_len) - L2CAP_HDR as u16
     }
 
     /// Sets new channel MTU.
@@ -73,6 +75,7 @@ impl Chan {
                 info!("{} MTU change: {} -> {mtu}", self.raw.cid, self.mtu);
                 self.mtu = mtu;
                 self.raw.state.lock().max_frame_len = L2CAP_HDR + mtu as usize;
+                self.raw.mtu.send_replace(mtu);
             }
         }
     }
@@ -83,6 +86,20 @@ impl Chan {
         self.raw.set_error();
     }
 
+    /// Sets the channel transmit priority.
+    #[inline]
+    pub fn set_priority(&self, p: Priority) {
+        self.raw.state.lock().priority = p;
+    }
+
+    /// Limits the number of received commands that may be queued.
+    #[inline]
+    pub fn set_cmd_limit(&self, lim: CmdLimit) {
+        let mut cs = self.raw.state.lock();
+        cs.cmds = cs.rx_pdu.iter().filter(|f| lim.is_cmd(f)).count();
+        cs.cmd_limit = Some(lim);
+    }
+
     /// Allocates a new outbound SDU.
     #[inline]
     pub fn alloc(&self) -> Payload {
@@ -137,7 +154,7 @@ impl Future for Recv {
         if let Err(e) = cs.err(self.raw.cid) {
             return Poll::Ready(Err(e));
         }
-        if let Some(pdu) = cs.rx_pdu.pop_front() {
+        if let Some(pdu) = cs.pop(0) {
             return Poll::Ready(Ok(Payload::new(pdu, L2CAP_HDR)));
         }
         cs.rx_await(cx, self.have_lock);
@@ -176,7 +193,7 @@ impl<F: Fn(Unpacker) -> bool + Send + Unpin> Future for RecvFilter<F> {
         let mut it = cs.rx_pdu.iter();
         if let Some(i) = it.position(|pdu| (self.f)(pdu.unpack().split_at(L2CAP_HDR).1)) {
             // SAFETY: `i` is within bounds
-            let pdu = unsafe { cs.rx_pdu.remove(i).unwrap_unchecked() };
+            let pdu = unsafe { cs.pop(i).unwrap_unchecked() };
             return Poll::Ready(Ok(Payload::new(pdu, L2CAP_HDR)));
         }
         cs.rx_await(cx, self.r.have_lock);
@@ -223,6 +240,75 @@ impl Drop for MaySend<'_> {
     }
 }
 
+/// Action taken when a channel receives a command while its command queue is
+/// full.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CmdOverflow {
+    /// Drop the oldest queued command to make room for the new one.
+    DropOldest,
+    /// Drop the new command.
+    #[default]
+    DropNewest,
+    /// Treat the overflow as a fatal channel error, which terminates the
+    /// channel owner and should be followed by a disconnection.
+    Disconnect,
+}
+
+/// Transmit priority of a channel. When several channels on one logical link
+/// are waiting to send a PDU, the channel with the highest priority sends
+/// next, and channels with equal priority take turns. A PDU is never
+/// interrupted once its first fragment is sent, so a channel waits for at most
+/// one PDU from a lower priority channel.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Priority {
+    /// Bulk data, such as connection-oriented channels.
+    Low,
+    /// Fixed protocol channels, such as ATT and SMP.
+    #[default]
+    Normal,
+    /// LE signaling channel.
+    High,
+}
+
+impl Priority {
+    /// Returns the default priority of channel `cid`.
+    #[inline]
+    #[must_use]
+    fn of(cid: Cid) -> Self {
+        if cid == Cid::SIG {
+            Self::High
+        } else if cid.is_dynamic() {
+            Self::Low
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+/// Limit on the number of received commands (PDUs that are not flow
+/// controlled by a response) that may be queued for a channel.
+#[derive(Clone, Debug)]
+pub(crate) struct CmdLimit {
+    /// Maximum number of queued commands.
+    pub max: usize,
+    /// Overflow policy.
+    pub policy: CmdOverflow,
+    /// Returns whether an SDU is a command.
+    pub is_cmd: fn(&[u8]) -> bool,
+    /// Number of dropped commands.
+    pub dropped: Arc<AtomicU64>,
+}
+
+impl CmdLimit {
+    /// Returns whether received PDU `f` is a command.
+    #[inline]
+    fn is_cmd(&self, f: &Frame) -> bool {
+        (self.is_cmd)(f.as_ref().get(L2CAP_HDR..).unwrap_or_default())
+    }
+}
+
 /// Channel state shared between the Channel Manager, Resource Manager, and the
 /// channel owner.
 #[derive(Debug)]
@@ -230,19 +316,36 @@ pub(super) struct RawChan {
     pub cid: LeCid,
     pub cn: hci::ConnWatch,
     pub state: SyncMutex<State>,
+    /// Current MTU, which is broadcast to connection event streams.
+    pub mtu: tokio::sync::watch::Sender<u16>,
 }
 
 impl RawChan {
     /// Creates new channel state.
     #[inline]
-    fn new(cid: LeCid, cn: &hci::ConnWatch, max_frame_len: usize) -> Arc<Self> {
+    pub(super) fn new(cid: LeCid, cn: &hci::ConnWatch, mtu: u16) -> Arc<Self> {
         Arc::new(Self {
             cid,
             cn: cn.clone(),
-            state: SyncMutex::new(State::new(max_frame_len)),
+            state: SyncMutex::new(State::new(L2CAP_HDR + mtu as usize, Priority::of(cid.chan))),
+            mtu: tokio::sync::watch::channel(mtu).0,
         })
     }
 
+    /// Returns the maximum ACL data packet payload size for the channel given
+    /// the controller's `acl_data_len`. If the data length was increased, this
+    /// is also limited by the maximum LL Data PDU payload size, so that each
+    /// fragment is sent in a single LL packet.
+    #[inline]
+    pub fn max_frag_len(&self, acl_data_len: u16) -> u16 {
+        let (ll_tx, _) = self.cn.borrow().max_octets;
+        if ll_tx > hci::LL_MIN_OCTETS {
+            acl_data_len.min(ll_tx)
+        } else {
+            acl_data_len
+        }
+    }
+
     /// Allows the channel to send PDU fragments.
     #[inline]
     pub fn allow_send(&self) {
@@ -315,6 +418,12 @@ pub(super) struct State {
     max_frame_len: usize,
     /// Received PDU queue.
     rx_pdu: VecDeque<Frame>,
+    /// Received command limit.
+    cmd_limit: Option<CmdLimit>,
+    /// Number of commands in `rx_pdu`.
+    cmds: usize,
+    /// Transmit priority.
+    priority: Priority,
     /// Receive task waker.
     rx_waker: Option<Waker>,
     /// Transmit task waker.
@@ -328,12 +437,15 @@ impl State {
 
     /// Creates new channel state.
     #[inline]
-    fn new(max_frame_len: usize) -> Self {
+    fn new(max_frame_len: usize, priority: Priority) -> Self {
         assert!(max_frame_len >= ACL_LE_MIN_DATA_LEN as usize);
         Self {
             status: Status::empty(),
             max_frame_len,
             rx_pdu: VecDeque::new(),
+            cmd_limit: None,
+            cmds: 0,
+            priority,
             rx_waker: None,
             tx_waker: None,
         }
@@ -345,6 +457,12 @@ impl State {
         !self.status.intersects(Status::CLOSED.union(Status::ERROR))
     }
 
+    /// Returns the channel transmit priority.
+    #[inline(always)]
+    pub const fn priority(&self) -> Priority {
+        self.priority
+    }
+
     /// Returns whether the channel is registered with the Scheduler.
     #[inline]
     pub const fn is_scheduled(&self) -> bool {
@@ -414,18 +532,64 @@ impl State {
         if !self.is_ok() {
             return;
         }
+        let is_cmd = (self.cmd_limit.as_ref()).map_or(false, |lim| lim.is_cmd(&pdu));
+        if is_cmd && !self.make_room_for_cmd(cid) {
+            return;
+        }
         if self.rx_pdu.len() == Self::MAX_PDUS {
             error!("PDU queue overflow for {}", cid);
             self.set_fatal(Status::ERROR);
             return;
         }
         trace!("New PDU for {}", cid);
+        self.cmds += usize::from(is_cmd);
         self.rx_pdu.push_back(pdu);
         if let Some(rx) = self.rx_waker.take() {
             rx.wake();
         }
     }
 
+    /// Applies the command overflow policy before a new command is queued.
+    /// Returns whether the new command should be queued.
+    fn make_room_for_cmd(&mut self, cid: LeCid) -> bool {
+        let Some(lim) = self.cmd_limit.as_ref() else { return true };
+        if self.cmds < lim.max {
+            return true;
+        }
+        let policy = lim.policy;
+        if policy != CmdOverflow::Disconnect {
+            lim.dropped.fetch_add(1, Relaxed);
+        }
+        match policy {
+            CmdOverflow::DropOldest => {
+                if let Some(i) = self.rx_pdu.iter().position(|f| lim.is_cmd(f)) {
+                    debug!("Command queue full for {cid}, dropping the oldest command");
+                    self.pop(i);
+                }
+                true
+            }
+            CmdOverflow::DropNewest => {
+                debug!("Command queue full for {cid}, dropping the new command");
+                false
+            }
+            CmdOverflow::Disconnect => {
+                error!("Command queue overflow for {cid}");
+                self.set_fatal(Status::ERROR);
+                false
+            }
+        }
+    }
+
+    /// Removes the PDU at index `i` from the queue.
+    #[inline]
+    fn pop(&mut self, i: usize) -> Option<Frame> {
+        let pdu = self.rx_pdu.remove(i)?;
+        if (self.cmd_limit.as_ref()).map_or(false, |lim| lim.is_cmd(&pdu)) {
+            self.cmds -= 1;
+        }
+        Some(pdu)
+    }
+
     /// Returns [`Poll::Pending`] after configuring the rx waker.
     #[inline(always)]
     fn rx_await(&mut self, cx: &Context<'_>, have_lock: bool) {
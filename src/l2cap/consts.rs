@@ -63,13 +63,18 @@ impl SigCode {
 }
 
 /// `L2CAP_COMMAND_REJECT_RSP` reason ([Vol 3] Part A, Section 4.1).
-#[derive(Clone, Copy, Debug, num_enum::IntoPrimitive)]
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
 #[non_exhaustive]
 #[repr(u16)]
-pub(super) enum Reason {
+pub enum RejectReason {
+    /// Command code was not recognized or not supported.
     CommandNotUnderstood = 0x0000,
-    _SignalingMtuExceeded = 0x0001,
-    _InvalidCidInRequest = 0x0002,
+    /// Command exceeded the signaling MTU of the receiver.
+    SignalingMtuExceeded = 0x0001,
+    /// Request referred to a channel that does not exist.
+    InvalidCidInRequest = 0x0002,
 }
 
-crate::impl_display_via_debug! { SigCode, Reason }
+crate::impl_display_via_debug! { SigCode, RejectReason }
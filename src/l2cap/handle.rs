@@ -89,6 +89,14 @@ impl Cid {
         )
     }
 
+    /// Returns whether the CID is from the dynamically allocated range
+    /// ([Vol 3] Part A, Section 2.1, Table 2.3).
+    #[inline(always)]
+    #[must_use]
+    pub(super) const fn is_dynamic(self) -> bool {
+        self.0.get() >= 0x0040
+    }
+
     /// Writes CID name to `f`.
     #[inline(always)]
     fn write_fmt(self, f: &mut Formatter<'_>) -> std::fmt::Result {
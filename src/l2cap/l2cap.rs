@@ -1,22 +1,28 @@
 //! Logical Link Control and Adaptation Protocol ([Vol 3] Part A).
 
 use std::collections::hash_map::Entry;
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt::{Debug, Formatter};
 use std::mem;
 use std::ops::Deref;
 use std::sync::Arc;
+use std::time::Duration;
 
 use structbuf::{Pack, Packer, StructBuf};
-use tracing::error;
+use tracing::{info, warn, Instrument};
 
 pub(crate) use chan::*;
-pub use handle::*;
+pub use {
+    chan::{CmdOverflow, Priority},
+    consts::RejectReason,
+    handle::*,
+    sig::SigHandle,
+};
 use {consts::*, rx::Receiver, tx::Sender};
 
 use crate::hci::ACL_HDR;
 use crate::l2cap::sig::SigChan;
-use crate::{att, hci, host, smp, SyncMutex};
+use crate::{att, gap, hci, host, smp, SyncMutex};
 
 mod chan;
 mod consts;
@@ -44,6 +50,10 @@ pub enum Error {
     ChanClosed(LeCid),
     #[error("channel is broken ({0})")]
     ChanBroken(LeCid),
+    #[error("request timeout ({0})")]
+    Timeout(LeCid),
+    #[error("request rejected ({0}): {1}")]
+    Rejected(LeCid, RejectReason),
 }
 
 impl From<host::Error> for Error {
@@ -62,20 +72,78 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub struct ChanManager {
     rx: tokio::sync::mpsc::Receiver<Conn>,
     join: Option<tokio::task::JoinHandle<Result<()>>>,
+    count: tokio::sync::watch::Receiver<usize>,
+    max_conns: usize,
 }
 
 impl ChanManager {
-    /// Creates a new Channel Manager.
+    /// Maximum number of new connections that may be queued for [`Self::next`].
+    /// A connection that doesn't fit into the queue is terminated.
+    const MAX_QUEUED: usize = 16;
+
+    /// Creates a new Channel Manager without a connection limit.
     #[inline]
     pub async fn new(host: &hci::Host) -> Result<Self> {
-        let (tx, rx) = tokio::sync::mpsc::channel(4);
-        let task = ChanManagerTask::new(host.clone(), tx).await?;
+        Self::with_limit(host, usize::MAX).await
+    }
+
+    /// Creates a new Channel Manager that accepts up to `max_conns`
+    /// simultaneous connections in the Peripheral role. Connections initiated
+    /// by the local device as a Central are not limited. When the limit is
+    /// reached, all connectable advertising sets are disabled to stop new
+    /// connection requests, and any Peripheral connection established beyond
+    /// the limit is terminated. The disabled sets are not re-enabled
+    /// automatically, so the application should enable them again after
+    /// [`Self::wait_for_capacity`] returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_conns` is 0.
+    #[inline]
+    pub async fn with_max_conns(host: &hci::Host, max_conns: usize) -> Result<Self> {
+        assert!(max_conns > 0, "invalid connection limit");
+        Self::with_limit(host, max_conns).await
+    }
+
+    /// Creates a new Channel Manager that accepts up to `max_conns`
+    /// simultaneous Peripheral connections.
+    async fn with_limit(host: &hci::Host, max_conns: usize) -> Result<Self> {
+        let (tx, rx) = tokio::sync::mpsc::channel(max_conns.min(Self::MAX_QUEUED));
+        let (count_tx, count) = tokio::sync::watch::channel(0);
+        let task = ChanManagerTask::new(host.clone(), tx, count_tx, max_conns).await?;
         Ok(Self {
             rx,
             join: Some(tokio::spawn(task.run())),
+            count,
+            max_conns,
         })
     }
 
+    /// Returns the number of established connections in the Peripheral role,
+    /// which count towards the connection limit.
+    #[inline]
+    #[must_use]
+    pub fn conn_count(&self) -> usize {
+        *self.count.borrow()
+    }
+
+    /// Returns whether another connection can be accepted.
+    #[inline]
+    #[must_use]
+    pub fn has_capacity(&self) -> bool {
+        self.conn_count() < self.max_conns
+    }
+
+    /// Waits until another connection can be accepted. This method is cancel
+    /// safe.
+    pub async fn wait_for_capacity(&mut self) {
+        while *self.count.borrow_and_update() >= self.max_conns {
+            if self.count.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
     /// Returns the next LE-U connection. This method is cancel safe.
     #[inline]
     pub async fn next(&mut self) -> Result<Conn> {
@@ -100,7 +168,9 @@ impl ChanManager {
 
 /// Established connection over an LE-U logical link.
 pub struct Conn {
+    host: hci::Host,
     raw: Arc<RawConn>,
+    sig: SigHandle,
     att: Option<Chan>,
     smp: Option<Chan>,
 }
@@ -116,12 +186,16 @@ impl Conn {
         let att = Chan::new(link.chan(Cid::ATT), &cn, &rm.tx, 23);
         // [Vol 3] Part H, Section 3.2
         let smp = Chan::new(link.chan(Cid::SMP), &cn, &rm.tx, 65);
+        let raw = Arc::new(RawConn {
+            sig: Arc::clone(&sig.raw),
+            att: Arc::clone(&att.raw),
+            smp: Arc::clone(&smp.raw),
+        });
+        let (sig, sig_handle) = SigChan::new(sig);
         let cn = Self {
-            raw: Arc::new(RawConn {
-                sig: Arc::clone(&sig.raw),
-                att: Arc::clone(&att.raw),
-                smp: Arc::clone(&smp.raw),
-            }),
+            host: host.clone(),
+            raw,
+            sig: sig_handle,
             att: Some(att),
             smp: Some(smp),
         };
@@ -130,7 +204,7 @@ impl Conn {
         rm.rx.register_chan(&cn.raw.sig);
         rm.rx.register_chan(&cn.raw.att);
         rm.rx.register_chan(&cn.raw.smp);
-        (cn, SigChan::new(sig))
+        (cn, sig)
     }
 
     /// Returns the LE-U logical link handle.
@@ -140,6 +214,82 @@ impl Conn {
         self.raw.sig.cid.link
     }
 
+    /// Returns the connection tracing span.
+    #[inline]
+    #[must_use]
+    pub fn span(&self) -> tracing::Span {
+        self.raw.sig.cn.borrow().span.clone()
+    }
+
+    /// Returns the current transmitter and receiver PHY.
+    #[inline]
+    #[must_use]
+    pub fn phy(&self) -> (hci::Phy, hci::Phy) {
+        self.raw.sig.cn.borrow().phy
+    }
+
+    /// Returns the reason why the connection was closed or [`None`] if the
+    /// connection is still open.
+    #[inline]
+    #[must_use]
+    pub fn disconnect_reason(&self) -> Option<gap::DisconnectReason> {
+        (self.raw.sig.cn.borrow().disconnect_reason).map(gap::DisconnectReason::from)
+    }
+
+    /// Terminates the connection and waits for the disconnection to complete
+    /// ([Vol 4] Part E, Section 7.1.6). See [`hci::Host::disconnect`] for the
+    /// allowed reasons.
+    pub async fn disconnect(&self, reason: hci::Status) -> hci::Result<gap::DisconnectReason> {
+        let hdl = hci::ConnHandle::from(self.link());
+        let done = self.host.disconnected(hdl);
+        self.host.disconnect(hdl, reason).await?;
+        Ok(done.await.into())
+    }
+
+    /// Requests new connection parameters and waits for the update to
+    /// complete. As the Peripheral, the Link Layer connection parameters
+    /// request procedure is used if supported by both controllers, and the
+    /// L2CAP connection parameter update procedure otherwise. Returns the new
+    /// parameters or [`None`] if the request was rejected or the new parameters
+    /// are outside of the requested ranges. See [`gap::ConnParamsManager`] for
+    /// automatic profile-based management.
+    pub async fn request_conn_params(
+        &self,
+        p: hci::ConnParams,
+    ) -> gap::Result<Option<gap::ActiveConnParams>> {
+        gap::request_conn_params(&self.host, self, p).await
+    }
+
+    /// Returns a stream of connection lifecycle events, starting with
+    /// [`gap::ConnEvent::Connected`] and ending with
+    /// [`gap::ConnEvent::Disconnected`].
+    #[must_use]
+    pub fn events(&self) -> gap::ConnEvents {
+        let mtu = self.raw.att.mtu.subscribe();
+        gap::ConnEvents::new(&self.host, self.link().into(), mtu)
+    }
+
+    /// Starts monitoring the connection for stalls, which are detected if no
+    /// authenticated packets are received for `timeout`. See
+    /// [`hci::Keepalive`] for details.
+    pub async fn keepalive(&self, timeout: Duration) -> hci::Result<hci::Keepalive> {
+        hci::Keepalive::new(&self.host, self.link().into(), timeout).await
+    }
+
+    /// Returns a stream of RSSI samples read every `interval`. See
+    /// [`hci::RssiStream`] for details.
+    pub fn rssi_stream(&self, interval: Duration) -> hci::Result<hci::RssiStream> {
+        hci::RssiStream::new(&self.host, self.link().into(), interval)
+    }
+
+    /// Returns a handle for sending requests over the LE Signaling fixed
+    /// channel.
+    #[inline(always)]
+    #[must_use]
+    pub fn sig(&self) -> SigHandle {
+        self.sig.clone()
+    }
+
     /// Returns the Attribute Protocol (ATT) fixed channel bearer or [`None`] if
     /// the channel was already consumed.
     #[inline]
@@ -153,6 +303,13 @@ impl Conn {
     pub fn smp_peripheral(&mut self) -> Option<smp::Peripheral> {
         self.smp.take().map(smp::Peripheral::new)
     }
+
+    /// Returns the Security Manager Protocol (SMP) fixed channel for the
+    /// Central role or [`None`] if the channel was already consumed.
+    #[inline]
+    pub fn smp_central(&mut self) -> Option<smp::Central> {
+        self.smp.take().map(smp::Central::new)
+    }
 }
 
 impl Debug for Conn {
@@ -204,12 +361,20 @@ pub struct ChanManagerTask {
     tx: tokio::sync::mpsc::Sender<Conn>,
     rm: ResManager,
     conns: BTreeMap<LeU, ConnGuard>,
+    periph: BTreeSet<LeU>,
+    count: tokio::sync::watch::Sender<usize>,
+    max_conns: usize,
 }
 
 impl ChanManagerTask {
     /// Creates a new channel manager task state.
     #[inline]
-    async fn new(host: hci::Host, tx: tokio::sync::mpsc::Sender<Conn>) -> Result<Self> {
+    async fn new(
+        host: hci::Host,
+        tx: tokio::sync::mpsc::Sender<Conn>,
+        count: tokio::sync::watch::Sender<usize>,
+        max_conns: usize,
+    ) -> Result<Self> {
         let ctl = host.events();
         let rm = ResManager::new(&host).await?;
         Ok(Self {
@@ -218,6 +383,9 @@ impl ChanManagerTask {
             tx,
             rm,
             conns: BTreeMap::new(),
+            periph: BTreeSet::new(),
+            count,
+            max_conns,
         })
     }
 
@@ -238,21 +406,94 @@ impl ChanManagerTask {
             DisconnectionComplete => self.handle_disconnect(evt.get()),
             NumberOfCompletedPackets => self.rm.tx.handle_num_completed(&evt.get()),
             LeConnectionComplete | LeEnhancedConnectionComplete => self.handle_connect(&evt.get()),
+            LeRemoteConnectionParameterRequest => self.handle_conn_param_req(evt.get()),
             _ => {}
         }
     }
 
+    /// Replies to a connection parameters request from the peer, accepting
+    /// all valid parameters ([Vol 6] Part B, Section 5.1.7).
+    fn handle_conn_param_req(&self, evt: hci::LeRemoteConnectionParameterRequest) {
+        let host = self.host.clone();
+        let f = async move {
+            let (hdl, p) = (evt.handle, evt.params);
+            let r = if p.is_valid() {
+                (host.le_remote_connection_parameter_request_reply(hdl, p)).await
+            } else {
+                warn!("Rejecting invalid connection parameters: {p:?}");
+                let st = hci::Status::UnacceptableConnectionParameters;
+                (host.le_remote_connection_parameter_request_negative_reply(hdl, st)).await
+            };
+            if let Err(e) = r {
+                warn!("Failed to reply to connection parameters request: {e}");
+            }
+        };
+        tokio::task::spawn(f.instrument(self.host.conn_span(evt.handle)));
+    }
+
     /// Handles the creation of a new LE-U logical link.
     fn handle_connect(&mut self, evt: &hci::LeConnectionComplete) {
         if !evt.status.is_ok() {
             return;
         }
+        let is_periph = evt.role == hci::Role::Peripheral;
+        if is_periph && self.periph.len() >= self.max_conns {
+            warn!("Rejecting {} (connection limit reached)", evt.handle);
+            self.spawn_disconnect(evt.handle);
+            return;
+        }
+        let Ok(permit) = self.tx.try_reserve() else {
+            warn!("Rejecting {} (connection queue full or closed)", evt.handle);
+            self.spawn_disconnect(evt.handle);
+            return;
+        };
         let (cn, sig) = Conn::new(&self.host, LeU::new(evt.handle), &mut self.rm);
         let link = cn.link();
         let guard = ConnGuard(Arc::clone(&cn.raw));
-        self.tx.try_send(cn).expect("connection channel is full");
-        tokio::task::spawn(sig.serve()); // TODO: Store handle?
+        permit.send(cn);
+        let span = self.host.conn_span(evt.handle);
+        tokio::task::spawn(sig.serve().instrument(span)); // TODO: Store handle?
         assert!(self.conns.insert(link, guard).is_none());
+        if !is_periph {
+            return;
+        }
+        self.periph.insert(link);
+        self.count.send_replace(self.periph.len());
+        if self.periph.len() == self.max_conns {
+            self.spawn_disable_adv();
+        }
+    }
+
+    /// Disables all connectable advertising sets when the connection limit is
+    /// reached. Non-connectable sets, such as beacons, are not affected.
+    fn spawn_disable_adv(&self) {
+        let sets: Vec<_> = (self.host.connectable_adv_sets().into_iter())
+            .map(hci::AdvEnableParams::from)
+            .collect();
+        if sets.is_empty() {
+            return;
+        }
+        info!("Connection limit reached, disabling connectable advertising");
+        let host = self.host.clone();
+        tokio::task::spawn(async move {
+            // [Vol 4] Part E, Section 7.8.56
+            if let Err(e) = host.le_set_extended_advertising_enable(false, &sets).await {
+                warn!("Failed to disable advertising: {e}");
+            }
+        });
+    }
+
+    /// Terminates a connection that exceeds the connection limit or can't be
+    /// queued.
+    fn spawn_disconnect(&self, hdl: hci::ConnHandle) {
+        const REASON: hci::Status = hci::Status::RemoteDeviceTerminatedConnectionDueToLowResources;
+        let host = self.host.clone();
+        let f = async move {
+            if let Err(e) = host.disconnect(hdl, REASON).await {
+                warn!("Failed to disconnect {hdl}: {e}");
+            }
+        };
+        tokio::task::spawn(f.instrument(self.host.conn_span(hdl)));
     }
 
     /// Handles LE-U logical link disconnection.
@@ -265,6 +506,9 @@ impl ChanManagerTask {
         self.rm.rx.remove_chan(cn.att.cid);
         self.rm.rx.remove_chan(cn.smp.cid);
         self.rm.tx.handle_disconnect(evt);
+        if self.periph.remove(&LeU::new(evt.handle)) {
+            self.count.send_replace(self.periph.len());
+        }
     }
 }
 
@@ -281,15 +525,10 @@ impl ResManager {
     /// parameters ([Vol 3] Part A, Section 1.1).
     async fn new(host: &hci::Host) -> Result<Self> {
         let cbuf = host.info().buffer_size();
-        // [Vol 4] Part E, Section 4.2 and [Vol 4] Part E, Section 7.3.39
-        // TODO: Enable controller to host flow control?
-        let hbuf = hci::BufferSize {
-            acl_data_len: cbuf.acl_data_len,
-            acl_num_pkts: 1,
-        };
-        host.host_buffer_size(hbuf).await?;
+        // [Vol 4] Part E, Section 4.2
+        let hbuf = (host.info().host_buffer_size()).map_or(cbuf.acl_data_len, |b| b.acl_data_len);
         Ok(Self {
-            rx: Receiver::new(host.transport(), hbuf.acl_data_len),
+            rx: Receiver::new(host.transport(), hbuf),
             tx: Sender::new(host.transport(), cbuf.acl_num_pkts, cbuf.acl_data_len),
         })
     }
@@ -311,6 +550,17 @@ impl Payload {
         debug_assert!(f.as_ref().len() >= i);
         Self { f, i }
     }
+
+    /// Allocates an outbound SDU of at most `mtu` bytes that is not tied to a
+    /// channel. This allows the SDU to be encoded before the channel is
+    /// available, after which it is sent with a single copy into ACL data
+    /// packets.
+    #[inline]
+    pub(crate) fn detached(mtu: u16) -> Self {
+        let mut buf = StructBuf::new(L2CAP_HDR + usize::from(mtu));
+        buf.at(L2CAP_HDR).put([]);
+        Self::new(Frame::Buf(buf), L2CAP_HDR)
+    }
 }
 
 impl AsRef<[u8]> for Payload {
@@ -424,9 +674,14 @@ struct Alloc {
     dir: hci::Direction,
     /// Maximum size of a PDU fragment in an ACL data packet.
     acl_data_len: u16,
+    /// Transfers that can be reused for PDU fragments.
+    pool: SyncMutex<Vec<Box<dyn host::Transfer>>>,
 }
 
 impl Alloc {
+    /// Maximum number of transfers kept for reuse.
+    const POOL_SIZE: usize = 8;
+
     /// Creates a new transfer allocator.
     #[inline]
     #[must_use]
@@ -436,13 +691,24 @@ impl Alloc {
             transport: Arc::clone(t),
             dir,
             acl_data_len,
+            pool: SyncMutex::new(Vec::new()),
         }
     }
 
-    /// Allocates a new ACL data transfer.
+    /// Allocates a new ACL data transfer or reuses one returned by
+    /// [`Self::recycle`].
     #[must_use]
     fn xfer(&self) -> Box<dyn host::Transfer> {
-        self.transport.acl(self.dir, self.acl_data_len)
+        (self.pool.lock().pop()).unwrap_or_else(|| self.transport.acl(self.dir, self.acl_data_len))
+    }
+
+    /// Returns a transfer allocated by [`Self::xfer`] to the pool.
+    fn recycle(&self, mut xfer: Box<dyn host::Transfer>) {
+        let mut pool = self.pool.lock();
+        if pool.len() < Self::POOL_SIZE {
+            xfer.reset();
+            pool.push(xfer);
+        }
     }
 
     /// Allocates an outbound frame with a zero-filled basic L2CAP header.
@@ -459,3 +725,298 @@ impl Alloc {
         }
     }
 }
+
+/// Loopback channels for testing higher layer protocols without a controller.
+#[cfg(test)]
+pub(crate) mod loopback {
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use std::sync::Weak;
+    use std::time::Duration;
+
+    use crate::le::{Addr, RawAddr};
+
+    use super::*;
+
+    /// Returns a pair of connected ATT channels with the default MTU. Each PDU
+    /// sent on one channel is received by the other. The first channel is the
+    /// Peripheral and the second is the Central.
+    #[must_use]
+    pub(crate) fn att() -> (Chan, Chan) {
+        pair(Cid::ATT)
+    }
+
+    /// Returns a pair of connected LE signaling channels, as in [`att`].
+    #[must_use]
+    pub(crate) fn sig() -> (Chan, Chan) {
+        pair(Cid::SIG)
+    }
+
+    /// Returns `n` pairs of connected ATT channels, as in [`att`], where each
+    /// pair uses a separate connection. The Peripheral channels share one
+    /// transport with a controller buffer of `max_pkts` ACL data packets.
+    #[must_use]
+    pub(crate) fn att_conns(n: u16, max_pkts: u8) -> Vec<(Chan, Chan)> {
+        conns(Cid::ATT, n, max_pkts)
+    }
+
+    /// Channels of one loopback connection created by [`chans`].
+    #[derive(Debug)]
+    pub(crate) struct Chans {
+        /// Peripheral and Central channels in the requested order.
+        pub pairs: Vec<(Chan, Chan)>,
+        /// Peripheral connection state.
+        pub conn: tokio::sync::watch::Sender<hci::Conn>,
+        /// Number of ACL data packets sent by the Peripheral.
+        pub pkts: Arc<AtomicUsize>,
+    }
+
+    /// Returns a connection with a pair of connected channels for each CID and
+    /// MTU in `cids`. The Peripheral transport has a controller buffer of
+    /// `max_pkts` ACL data packets of up to `acl_data_len` bytes.
+    #[must_use]
+    pub(crate) fn chans(cids: &[(Cid, u16)], max_pkts: u8, acl_data_len: u16) -> Chans {
+        let (pt, ct) = (Arc::new(Loopback::default()), Arc::new(Loopback::default()));
+        let ptx = sender(&pt, max_pkts, acl_data_len);
+        let ctx = sender(&ct, u8::MAX, hci::ACL_LE_MIN_DATA_LEN);
+        let addr = Addr::Public(RawAddr::default());
+        let (pw, pcn) = conn(1, hci::Role::Peripheral, addr, addr);
+        let (_, ccn) = conn(1, hci::Role::Central, addr, addr);
+        let pairs = (cids.iter())
+            .map(|&(cid, mtu)| {
+                let p = chan(&ptx, &pcn, 1, cid, mtu);
+                let c = chan(&ctx, &ccn, 1, cid, mtu);
+                join(&pt, &p, &ct, &c);
+                (p, c)
+            })
+            .collect();
+        Chans {
+            pairs,
+            conn: pw,
+            pkts: Arc::clone(&pt.pkts),
+        }
+    }
+
+    /// One end of a loopback connection created by [`connect`].
+    #[derive(Debug)]
+    pub(crate) struct End {
+        /// ATT channel with the default MTU.
+        pub att: Chan,
+        /// SMP channel.
+        pub smp: Chan,
+        /// Connection state shared by all channels.
+        pub conn: tokio::sync::watch::Sender<hci::Conn>,
+    }
+
+    /// Returns the Peripheral and Central ends of a connection with connected
+    /// ATT and SMP channels. `p` and `c` are the Peripheral and Central
+    /// addresses, which are set as the local and peer addresses of each end.
+    #[must_use]
+    pub(crate) fn connect(p: Addr, c: Addr) -> (End, End) {
+        let (pt, ct) = (Arc::new(Loopback::default()), Arc::new(Loopback::default()));
+        let ptx = sender(&pt, u8::MAX, hci::ACL_LE_MIN_DATA_LEN);
+        let ctx = sender(&ct, u8::MAX, hci::ACL_LE_MIN_DATA_LEN);
+        let (pw, pcn) = conn(1, hci::Role::Peripheral, p, c);
+        let (cw, ccn) = conn(1, hci::Role::Central, c, p);
+        let chans = [(Cid::ATT, L2CAP_LE_MIN_MTU), (Cid::SMP, 65)].map(|(cid, mtu)| {
+            let p = chan(&ptx, &pcn, 1, cid, mtu);
+            let c = chan(&ctx, &ccn, 1, cid, mtu);
+            join(&pt, &p, &ct, &c);
+            (p, c)
+        });
+        let [(patt, catt), (psmp, csmp)] = chans;
+        let p = End {
+            att: patt,
+            smp: psmp,
+            conn: pw,
+        };
+        let c = End {
+            att: catt,
+            smp: csmp,
+            conn: cw,
+        };
+        (p, c)
+    }
+
+    /// Returns a pair of connected channels with the specified CID.
+    fn pair(cid: Cid) -> (Chan, Chan) {
+        conns(cid, 1, u8::MAX).pop().unwrap()
+    }
+
+    /// Returns `n` pairs of connected channels with the specified CID.
+    fn conns(cid: Cid, n: u16, max_pkts: u8) -> Vec<(Chan, Chan)> {
+        let pt = Arc::new(Loopback::default());
+        let ptx = sender(&pt, max_pkts, hci::ACL_LE_MIN_DATA_LEN);
+        let addr = Addr::Public(RawAddr::default());
+        (1..=n)
+            .map(|hdl| {
+                let ct = Arc::new(Loopback::default());
+                let ctx = sender(&ct, u8::MAX, hci::ACL_LE_MIN_DATA_LEN);
+                let (_, pcn) = conn(hdl, hci::Role::Peripheral, addr, addr);
+                let (_, ccn) = conn(hdl, hci::Role::Central, addr, addr);
+                let p = chan(&ptx, &pcn, hdl, cid, L2CAP_LE_MIN_MTU);
+                let c = chan(&ctx, &ccn, hdl, cid, L2CAP_LE_MIN_MTU);
+                join(&pt, &p, &ct, &c);
+                (p, c)
+            })
+            .collect()
+    }
+
+    /// Creates a sender that submits ACL data packets to transport `t`.
+    fn sender(t: &Arc<Loopback>, max_pkts: u8, acl_data_len: u16) -> Arc<Sender> {
+        let tx = Sender::new(&(Arc::clone(t) as _), max_pkts, acl_data_len);
+        *t.tx.lock() = Arc::downgrade(&tx);
+        tx
+    }
+
+    /// Creates the state of connection `hdl` with the specified local and peer
+    /// addresses.
+    fn conn(
+        hdl: u16,
+        role: hci::Role,
+        local: Addr,
+        peer: Addr,
+    ) -> (tokio::sync::watch::Sender<hci::Conn>, hci::ConnWatch) {
+        let e = hci::LeConnectionComplete {
+            status: hci::Status::Success,
+            handle: hci::ConnHandle::new(hdl).unwrap(),
+            role,
+            peer_addr: peer,
+            local_rpa: RawAddr::default(),
+            peer_rpa: RawAddr::default(),
+            conn_interval: Duration::from_millis(30),
+            peripheral_latency: 0,
+            supervision_timeout: Duration::from_secs(4),
+            central_clock_accuracy: 0,
+        };
+        let mut cn = hci::Conn::new(&e);
+        cn.local_addr = local;
+        tokio::sync::watch::channel(cn)
+    }
+
+    /// Creates a channel for connection `hdl` that sends PDUs via `tx`.
+    fn chan(tx: &Arc<Sender>, cn: &hci::ConnWatch, hdl: u16, cid: Cid, mtu: u16) -> Chan {
+        let link = LeU::new(hci::ConnHandle::new(hdl).unwrap());
+        tx.register_link(link);
+        Chan::new(link.chan(cid), cn, tx, mtu)
+    }
+
+    /// Connects channel `p`, which sends PDUs via transport `pt`, to channel
+    /// `c`, which sends PDUs via transport `ct`.
+    fn join(pt: &Loopback, p: &Chan, ct: &Loopback, c: &Chan) {
+        for (t, a, b) in [(pt, p, c), (ct, c, p)] {
+            let mut links = t.links.lock();
+            let ln = links.entry(a.raw.cid.link).or_default();
+            ln.peers.insert(a.raw.cid.chan, Arc::downgrade(&b.raw));
+        }
+    }
+
+    /// Transport that delivers outbound PDUs to the peer channel of each
+    /// logical link.
+    #[derive(Debug, Default)]
+    struct Loopback {
+        tx: SyncMutex<Weak<Sender>>,
+        links: Arc<SyncMutex<BTreeMap<LeU, Link>>>,
+        pkts: Arc<AtomicUsize>,
+    }
+
+    /// Peer channels and PDU reassembly buffer of one logical link.
+    #[derive(Debug, Default)]
+    struct Link {
+        peers: BTreeMap<Cid, Weak<RawChan>>,
+        frag: Option<StructBuf>,
+    }
+
+    impl host::Transport for Loopback {
+        fn command(&self) -> Box<dyn host::Transfer> {
+            panic!("loopback transport does not support HCI commands")
+        }
+
+        fn event(&self) -> Box<dyn host::Transfer> {
+            panic!("loopback transport does not support HCI events")
+        }
+
+        fn acl(&self, dir: hci::Direction, max_data_len: u16) -> Box<dyn host::Transfer> {
+            Box::new(Xfer {
+                dir,
+                buf: StructBuf::new(ACL_HDR + usize::from(max_data_len)),
+                tx: self.tx.lock().clone(),
+                links: Arc::clone(&self.links),
+                pkts: Arc::clone(&self.pkts),
+            })
+        }
+    }
+
+    /// ACL data transfer that contains a PDU fragment. Fragments are
+    /// recombined before the PDU is delivered to the peer.
+    #[derive(Debug)]
+    struct Xfer {
+        dir: hci::Direction,
+        buf: StructBuf,
+        tx: Weak<Sender>,
+        links: Arc<SyncMutex<BTreeMap<LeU, Link>>>,
+        pkts: Arc<AtomicUsize>,
+    }
+
+    impl host::Transfer for Xfer {
+        fn typ(&self) -> hci::TransferType {
+            hci::TransferType::Acl(self.dir)
+        }
+
+        fn exec(self: Box<Self>) -> host::Exec {
+            self.pkts.fetch_add(1, Relaxed);
+            let (hdr, data) = self.buf.as_ref().split_at(ACL_HDR);
+            let hdr = u16::from_le_bytes([hdr[0], hdr[1]]);
+            let link = LeU::new(hci::ConnHandle::new(hdr).expect("invalid connection handle"));
+            let is_cont = (hdr >> hci::ConnHandle::BITS) & 0b11 == 0b01;
+            let mut links = self.links.lock();
+            let ln = links.get_mut(&link).expect("unknown logical link");
+            if !is_cont {
+                let pdu_len = usize::from(u16::from_le_bytes([data[0], data[1]]));
+                ln.frag = Some(StructBuf::with_capacity(L2CAP_HDR + pdu_len));
+            }
+            let f = ln.frag.as_mut().expect("unexpected continuation fragment");
+            f.append().put(data);
+            if f.is_full() {
+                let pdu = ln.frag.take().unwrap();
+                let hdr: &[u8] = pdu.as_ref();
+                let cid = Cid::new(u16::from_le_bytes([hdr[2], hdr[3]]));
+                let peer = cid.and_then(|cid| ln.peers.get(&cid)?.upgrade());
+                if let Some(peer) = peer {
+                    let mut cs = peer.state.lock();
+                    if cs.can_recv(peer.cid, pdu.len()) {
+                        cs.push(peer.cid, Frame::Buf(pdu));
+                    }
+                }
+            }
+            drop(links);
+            if let Some(tx) = self.tx.upgrade() {
+                // Complete the packet after the sender accounts for it
+                tokio::spawn(async move { tx.ack(link, 1) });
+            }
+            host::Exec::ready(Ok(self as Box<dyn host::Transfer>))
+        }
+
+        fn reset(&mut self) {
+            self.buf.clear();
+        }
+    }
+
+    impl AsRef<[u8]> for Xfer {
+        #[inline]
+        fn as_ref(&self) -> &[u8] {
+            self.buf.as_ref()
+        }
+    }
+
+    impl Pack for Xfer {
+        #[inline]
+        fn append(&mut self) -> Packer {
+            self.buf.append()
+        }
+
+        #[inline]
+        fn at(&mut self, i: usize) -> Packer {
+            self.buf.at(i)
+        }
+    }
+}
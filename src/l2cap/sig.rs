@@ -1,50 +1,328 @@
 //! Signaling channel manager ([Vol 3] Part A, Section 4).
 
-use structbuf::Unpack;
-use tracing::warn;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+use structbuf::{Unpack, Unpacker};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
 
 use super::*;
 
+/// Result of a signaling request that was answered by the peer.
+type SigResult<T> = std::result::Result<T, RejectReason>;
+
 /// Signaling channel manager ([Vol 3] Part A, Section 4).
 #[derive(Debug)]
 pub(super) struct SigChan {
     ch: Chan,
+    req: mpsc::Receiver<SigReq>,
+    ident: u8,
+    sent: VecDeque<(u8, Instant)>,
+    pending: Option<(u8, oneshot::Sender<SigResult<bool>>)>,
+    ignored: Arc<AtomicU64>,
 }
 
 impl SigChan {
-    /// Creates a new signaling channel manager.
-    #[inline(always)]
+    /// Creates a new signaling channel manager and a handle for sending
+    /// requests over it.
+    #[inline]
     #[must_use]
-    pub const fn new(ch: Chan) -> Self {
-        Self { ch }
+    pub fn new(ch: Chan) -> (Self, SigHandle) {
+        let (tx, req) = mpsc::channel(1);
+        let ignored = Arc::default();
+        let h = SigHandle {
+            cid: ch.cid(),
+            tx,
+            ignored: Arc::clone(&ignored),
+        };
+        let sig = Self {
+            ch,
+            req,
+            ident: 0,
+            sent: VecDeque::new(),
+            pending: None,
+            ignored,
+        };
+        (sig, h)
     }
 
     /// Handles signaling channel communications.
     pub async fn serve(mut self) -> Result<()> {
         loop {
-            self.recv().await?;
+            let req = tokio::select! {
+                pdu = self.ch.recv() => {
+                    self.recv(pdu?).await?;
+                    continue;
+                }
+                Some(req) = self.req.recv() => req,
+            };
+            self.send_req(req).await?;
         }
     }
 
-    /// Receives and handles the next request.
-    async fn recv(&mut self) -> Result<()> {
-        let pdu = self.ch.recv().await?;
+    /// Handles a received command.
+    async fn recv(&mut self, pdu: Payload) -> Result<()> {
         let mut p = pdu.unpack();
         let (code, ident) = (p.u8(), p.u8());
-        if let Ok(code) = SigCode::try_from(code) {
-            warn!("Rejecting {code} on {}", self.ch.cid());
-            if !code.is_req() {
+        if ident == 0 {
+            // [Vol 3] Part A, Section 4
+            warn!("Ignoring command {code:#04X} with invalid identifier");
+            self.ignored.fetch_add(1, Relaxed);
+            return Ok(());
+        }
+        match SigCode::try_from(code) {
+            Ok(code) if !code.is_req() => {
+                self.recv_rsp(code, ident, p);
                 return Ok(());
             }
-        } else {
-            warn!("Rejecting unknown code {code:#04X} on {}", self.ch.cid());
+            Ok(code) => warn!("Rejecting {code} on {}", self.ch.cid()),
+            Err(_) => warn!("Rejecting unknown code {code:#04X} on {}", self.ch.cid()),
         }
         let mut rsp = self.ch.alloc();
         rsp.append()
             .u8(SigCode::CommandRejectRsp)
             .u8(ident)
             .u16(2_u16)
-            .u16(Reason::CommandNotUnderstood);
+            .u16(RejectReason::CommandNotUnderstood);
         self.ch.send(rsp).await
     }
+
+    /// Completes the pending request with a received response. Responses that
+    /// don't match the identifier of the pending request are ignored
+    /// ([Vol 3] Part A, Section 4).
+    fn recv_rsp(&mut self, code: SigCode, ident: u8, mut p: Unpacker) {
+        let is_pending = self.pending.as_ref().map_or(false, |&(id, _)| id == ident);
+        let r = match code {
+            SigCode::ConnectionParameterUpdateRsp if is_pending => {
+                // [Vol 3] Part A, Section 4.21
+                let (_len, result) = (p.u16(), p.u16());
+                let accepted = p.is_ok() && result == 0x0000;
+                debug!("Connection parameter update accepted: {accepted}");
+                Ok(accepted)
+            }
+            SigCode::CommandRejectRsp if is_pending => {
+                // [Vol 3] Part A, Section 4.1
+                let (_len, reason) = (p.u16(), p.u16());
+                let reason = RejectReason::try_from(reason).unwrap_or_else(|_| {
+                    warn!("Unknown Command Reject reason {reason:#06X}");
+                    RejectReason::CommandNotUnderstood
+                });
+                debug!("Request rejected: {reason}");
+                Err(reason)
+            }
+            _ => {
+                debug!("Ignoring {code} with stale identifier {ident:#04X}");
+                self.ignored.fetch_add(1, Relaxed);
+                return;
+            }
+        };
+        let (_, tx) = self.pending.take().unwrap();
+        let _ = tx.send(r);
+    }
+
+    /// Sends a request, replacing any request that is still pending.
+    async fn send_req(&mut self, req: SigReq) -> Result<()> {
+        let ident = self.next_ident(Instant::now());
+        let mut pdu = self.ch.alloc();
+        match req {
+            SigReq::ConnParamUpdate(p, tx) => {
+                // [Vol 3] Part A, Section 4.20
+                pdu.append()
+                    .u8(SigCode::ConnectionParameterUpdateReq)
+                    .u8(ident)
+                    .u16(8_u16)
+                    .u16(hci::ticks_1250us(p.interval.0).expect("invalid connection interval"))
+                    .u16(hci::ticks_1250us(p.interval.1).expect("invalid connection interval"))
+                    .u16(p.latency)
+                    .u16(hci::ticks_10ms(p.timeout).expect("invalid supervision timeout"));
+                self.pending = Some((ident, tx));
+            }
+        }
+        self.ch.send(pdu).await
+    }
+
+    /// Returns the identifier for a new request. Identifiers used within the
+    /// last `RTX` interval are skipped, so a late response to an abandoned
+    /// request cannot complete a new one ([Vol 3] Part A, Section 4).
+    fn next_ident(&mut self, now: Instant) -> u8 {
+        let expired = |&(_, t): &(u8, Instant)| now.saturating_duration_since(t) >= SigHandle::RTX;
+        while self.sent.front().map_or(false, expired) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= usize::from(u8::MAX) {
+            self.sent.pop_front(); // All identifiers are in use
+        }
+        loop {
+            self.ident = self.ident.wrapping_add(1).max(1);
+            if !self.sent.iter().any(|&(id, _)| id == self.ident) {
+                break;
+            }
+        }
+        self.sent.push_back((self.ident, now));
+        self.ident
+    }
+}
+
+/// Outbound signaling request.
+#[derive(Debug)]
+enum SigReq {
+    ConnParamUpdate(hci::ConnParams, oneshot::Sender<SigResult<bool>>),
+}
+
+/// Handle for sending requests over the LE signaling channel.
+#[derive(Clone, Debug)]
+pub struct SigHandle {
+    cid: LeCid,
+    tx: mpsc::Sender<SigReq>,
+    ignored: Arc<AtomicU64>,
+}
+
+impl SigHandle {
+    /// Response timeout (`RTX`) ([Vol 3] Part A, Section 6.2.1).
+    const RTX: Duration = Duration::from_secs(30);
+
+    /// Requests new connection parameters from the Central using the
+    /// connection parameter update procedure
+    /// ([Vol 3] Part A, Section 4.20 and [Vol 3] Part C, Section 9.3.9.2).
+    /// Returns whether the Central accepted the request or
+    /// [`Error::Rejected`] if the Central does not support the procedure. The
+    /// new parameters take effect when the Central completes the Link Layer
+    /// connection update procedure. This must only be used in the Peripheral
+    /// role.
+    pub async fn conn_param_update(&self, p: hci::ConnParams) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+        let req = SigReq::ConnParamUpdate(p, tx);
+        if self.tx.send(req).await.is_err() {
+            return Err(Error::ChanClosed(self.cid));
+        }
+        match tokio::time::timeout(Self::RTX, rx).await {
+            Ok(Ok(Ok(accepted))) => Ok(accepted),
+            Ok(Ok(Err(reason))) => Err(Error::Rejected(self.cid, reason)),
+            Ok(Err(_)) => Err(Error::ChanClosed(self.cid)),
+            Err(_) => Err(Error::Timeout(self.cid)),
+        }
+    }
+
+    /// Returns the number of received commands that were ignored because they
+    /// had an invalid identifier or were responses that did not match the
+    /// pending request.
+    #[inline]
+    #[must_use]
+    pub fn ignored_cmds(&self) -> u64 {
+        self.ignored.load(Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> hci::ConnParams {
+        hci::ConnParams {
+            interval: (Duration::from_millis(15), Duration::from_millis(30)),
+            latency: 0,
+            timeout: Duration::from_secs(4),
+        }
+    }
+
+    /// Starts a signaling channel manager on the Peripheral and returns its
+    /// handle and the Central channel.
+    fn sig() -> (SigHandle, Chan) {
+        let (p, c) = loopback::sig();
+        let (sig, h) = SigChan::new(p);
+        tokio::spawn(sig.serve());
+        (h, c)
+    }
+
+    /// Sends a command with the specified parameters.
+    async fn send(ch: &mut Chan, code: SigCode, ident: u8, params: &[u16]) {
+        let mut pdu = ch.alloc();
+        let mut p = pdu.append();
+        let len = u16::try_from(params.len() * 2).unwrap();
+        p.u8(code).u8(ident).u16(len);
+        for &v in params {
+            p.u16(v);
+        }
+        ch.send(pdu).await.unwrap();
+    }
+
+    /// Receives a command, returning its code, identifier, and PDU.
+    async fn recv(ch: &mut Chan) -> (u8, u8, Payload) {
+        let pdu = ch.recv().await.unwrap();
+        let mut p = pdu.unpack();
+        let (code, ident) = (p.u8(), p.u8());
+        (code, ident, pdu)
+    }
+
+    #[tokio::test]
+    async fn command_reject() {
+        use RejectReason::*;
+        let (h, mut c) = sig();
+        for reason in [
+            CommandNotUnderstood,
+            SignalingMtuExceeded,
+            InvalidCidInRequest,
+        ] {
+            let req = tokio::spawn({
+                let h = h.clone();
+                async move { h.conn_param_update(params()).await }
+            });
+            let (code, ident, _) = recv(&mut c).await;
+            assert_eq!(code, u8::from(SigCode::ConnectionParameterUpdateReq));
+            send(&mut c, SigCode::CommandRejectRsp, ident, &[reason.into()]).await;
+            let e = req.await.unwrap().unwrap_err();
+            assert!(matches!(e, Error::Rejected(_, r) if r == reason));
+        }
+        assert_eq!(h.ignored_cmds(), 0);
+    }
+
+    #[tokio::test]
+    async fn stale_ident() {
+        let (h, mut c) = sig();
+        let req = tokio::spawn({
+            let h = h.clone();
+            async move { h.conn_param_update(params()).await }
+        });
+        let (_, ident, _) = recv(&mut c).await;
+        let stale = ident.wrapping_add(1);
+
+        // Responses with a different identifier and commands with an invalid
+        // identifier are ignored.
+        send(&mut c, SigCode::CommandRejectRsp, stale, &[0]).await;
+        send(&mut c, SigCode::ConnectionParameterUpdateRsp, stale, &[0]).await;
+        send(&mut c, SigCode::ConnectionParameterUpdateRsp, 0, &[0]).await;
+        send(&mut c, SigCode::ConnectionParameterUpdateRsp, ident, &[0]).await;
+        assert!(req.await.unwrap().unwrap());
+        assert_eq!(h.ignored_cmds(), 3);
+
+        // A duplicate response is stale once the request is complete
+        send(&mut c, SigCode::ConnectionParameterUpdateRsp, ident, &[1]).await;
+
+        // Unknown requests are rejected
+        send(&mut c, SigCode::EchoReq, 7, &[]).await;
+        let (code, rsp_ident, pdu) = recv(&mut c).await;
+        assert_eq!(code, u8::from(SigCode::CommandRejectRsp));
+        assert_eq!(rsp_ident, 7);
+        assert_eq!(pdu.as_ref()[4..], [0, 0]);
+        assert_eq!(h.ignored_cmds(), 4);
+    }
+
+    #[tokio::test]
+    async fn ident_reuse() {
+        let (p, _c) = loopback::sig();
+        let (mut sig, _h) = SigChan::new(p);
+        let t0 = Instant::now();
+        let ids: Vec<u8> = (0..u8::MAX).map(|_| sig.next_ident(t0)).collect();
+        assert!(ids.iter().copied().eq(1..=u8::MAX));
+
+        // An identifier is not reused before RTX expires
+        let t1 = t0 + SigHandle::RTX / 2;
+        sig.sent.retain(|&(id, _)| id != 2);
+        assert_eq!(sig.next_ident(t1), 2);
+        assert_eq!(sig.next_ident(t1), 1); // All in use, reuse the oldest
+        assert_eq!(sig.next_ident(t0 + SigHandle::RTX), 3);
+        assert_eq!(sig.next_ident(t0 + SigHandle::RTX), 4);
+    }
 }
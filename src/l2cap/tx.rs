@@ -1,5 +1,7 @@
 //! Send side of the Resource Manager.
 
+use std::cmp::Reverse;
+
 use structbuf::Pack;
 use tracing::{trace, warn};
 
@@ -34,8 +36,11 @@ impl Sender {
     /// the controller.
     #[inline]
     pub async fn send(self: &Arc<Self>, ch: &Arc<RawChan>, pdu: Frame) -> Result<()> {
-        let (tx, ch) = (Arc::clone(self), Arc::clone(ch));
-        let guard = self.sched.lock().schedule(tx, ch)?;
+        self.sched.lock().schedule(ch)?;
+        let guard = SchedulerGuard {
+            tx: Arc::clone(self),
+            ch: Arc::clone(ch),
+        };
         guard.send(pdu).await
     }
 
@@ -55,12 +60,22 @@ impl Sender {
         self.sched.lock().ack(pkts);
     }
 
+    /// Acknowledges `n` packets sent over `link` without an
+    /// `HCI_Number_Of_Completed_Packets` event.
+    #[cfg(test)]
+    pub(super) fn ack(&self, link: LeU, n: u16) {
+        self.sched.lock().ack(std::iter::once((link, n)));
+    }
+
     // TODO: Handle Data Buffer Overflow event?
 }
 
 /// Outbound PDU scheduler. Ensures that the controller's transmit buffer is
-/// shared fairly between all logical links. Within each logical link, PDUs are
-/// transmitted in FIFO order.
+/// shared fairly between all logical links. Within each logical link, the
+/// waiting channel with the highest [`Priority`] sends the next PDU, and
+/// channels with equal priority send in FIFO order. Each link is limited to a
+/// share of the buffer, so that a link that stops acknowledging packets cannot
+/// prevent other links from sending.
 #[derive(Debug)]
 struct Scheduler {
     /// Channels that are blocked from sending because another channel on the
@@ -76,6 +91,8 @@ struct Scheduler {
     sent: HashMap<LeU, u16>,
     /// Number of new PDU fragments that the controller can accept immediately.
     quota: u16,
+    /// Total number of PDU fragments that the controller can buffer.
+    max_pkts: u16,
 }
 
 impl Scheduler {
@@ -90,9 +107,26 @@ impl Scheduler {
             active: None,
             sent: HashMap::new(),
             quota: max_pkts,
+            max_pkts,
         }
     }
 
+    /// Returns the maximum number of unacknowledged PDU fragments for one
+    /// logical link. Up to half of the controller's buffer is reserved for
+    /// other links, allowing them to send even if this link is stalled.
+    #[inline]
+    fn link_quota(&self) -> u16 {
+        let others = u16::try_from(self.blocked.len().saturating_sub(1)).unwrap_or(u16::MAX);
+        (self.max_pkts - others.min(self.max_pkts / 2)).max(1)
+    }
+
+    /// Returns whether logical link `link` can send another PDU fragment
+    /// without exceeding its share of the controller's buffer.
+    #[inline]
+    fn within_link_quota(&self, link: LeU) -> bool {
+        (self.sent.get(&link)).map_or(true, |&n| n < self.link_quota())
+    }
+
     /// Registers a new LE-U logical link, allowing it to send data.
     #[inline]
     fn register_link(&mut self, link: LeU) {
@@ -178,22 +212,23 @@ impl Scheduler {
         if self.quota == 0 || self.active.is_some() {
             return;
         }
-        self.active = match self.ready.len() {
-            0 => return,
-            1 => self.ready.pop_front(),
-            // Pick the logical link with the fewest unacknowledged packets to
-            // maximize physical link utilization and prevent a stalled link
-            // from filling up the controller's buffer. Channels are removed
-            // from the front and pushed to the back after sending, resulting in
-            // round-robin scheduling when the number of unacknowledged packets
-            // is equal.
-            _ => self.ready.remove(
-                (self.ready.iter().map(|ch| self.sent[&ch.cid.link]))
-                    .enumerate()
-                    .reduce(|min, cur| if min.1 <= cur.1 { min } else { cur })
-                    .map_or(0, |min| min.0),
-            ),
+        // Pick the logical link with the fewest unacknowledged packets to
+        // maximize physical link utilization and prevent a stalled link from
+        // filling up the controller's buffer. Ties are broken by channel
+        // priority. Channels are removed from the front and pushed to the back
+        // after sending, resulting in round-robin scheduling when the number of
+        // unacknowledged packets and priorities are equal.
+        let Some((i, (sent, _))) = (self.ready.iter())
+            .map(|ch| (self.sent[&ch.cid.link], Reverse(ch.state.lock().priority())))
+            .enumerate()
+            .reduce(|min, cur| if min.1 <= cur.1 { min } else { cur })
+        else {
+            return;
         };
+        if sent >= self.link_quota() {
+            return; // All ready links must wait for acknowledgments
+        }
+        self.active = self.ready.remove(i);
         self.active.as_ref().unwrap().allow_send();
     }
 
@@ -205,8 +240,11 @@ impl Scheduler {
         (self.active.as_ref()).map_or(false, |act| Arc::ptr_eq(act, ch))
     }
 
-    /// Schedules channel `ch` for sending a PDU.
-    fn schedule(&mut self, tx: Arc<Sender>, ch: Arc<RawChan>) -> Result<SchedulerGuard> {
+    /// Schedules channel `ch` for sending a PDU. The caller must remove the
+    /// channel from the scheduler when the send operation is done.
+    fn schedule(&mut self, ch: &Arc<RawChan>) -> Result<()> {
+        let may_send =
+            self.quota > 0 && self.active.is_none() && self.within_link_quota(ch.cid.link);
         let Some(blocked) = self.blocked.get_mut(&ch.cid.link) else {
             return Err(Error::InvalidConn(ch.cid.link.into()));
         };
@@ -216,11 +254,11 @@ impl Scheduler {
             "A channel may not send two PDUs concurrently"
         );
         cs.err(ch.cid)?;
-        if self.quota > 0 && self.active.is_none() {
+        if may_send {
             // Fast path for the only sender, no notification needed
             cs.set_scheduled_active();
             drop(cs);
-            self.active = Some(Arc::clone(&ch));
+            self.active = Some(Arc::clone(ch));
         } else {
             cs.set_scheduled(true);
             drop(cs);
@@ -229,13 +267,13 @@ impl Scheduler {
                     .all(|other| other.cid.link != ch.cid.link)
             {
                 // First sender for this logical link
-                self.ready.push_back(Arc::clone(&ch));
+                self.ready.push_back(Arc::clone(ch));
             } else {
                 // Multiple senders for this logical link
-                blocked.push_back(Arc::clone(&ch));
+                blocked.push_back(Arc::clone(ch));
             }
         }
-        Ok(SchedulerGuard { tx, ch })
+        Ok(())
     }
 
     /// Updates scheduler state after a PDU fragment is sent to the controller.
@@ -254,7 +292,8 @@ impl Scheduler {
         if !more {
             // Keep the channel active so that remove() takes the fast path
             ch.deny_send();
-        } else if self.quota == 0 || !self.ready.is_empty() {
+        } else if self.quota == 0 || !self.ready.is_empty() || !self.within_link_quota(ch.cid.link)
+        {
             ch.deny_send();
             self.ready.push_back(self.active.take().unwrap());
             self.reschedule();
@@ -273,7 +312,7 @@ impl Scheduler {
             // Fast path for the active channel
             self.active = None;
             ch.state.lock().set_scheduled(false);
-            if let Some(next) = (self.blocked.get_mut(&ch.cid.link)).and_then(VecDeque::pop_front) {
+            if let Some(next) = (self.blocked.get_mut(&ch.cid.link)).and_then(next_blocked) {
                 self.ready.push_back(next);
             }
             self.reschedule();
@@ -287,11 +326,12 @@ impl Scheduler {
             return;
         }
         cs.set_scheduled(false);
+        drop(cs);
         let Some(i) = position(&self.ready, ch) else {
             blocked.remove(position(blocked, ch).unwrap());
             return;
         };
-        match blocked.pop_front() {
+        match next_blocked(blocked) {
             Some(next) => self.ready[i] = next,
             None => {
                 self.ready.remove(i);
@@ -300,6 +340,15 @@ impl Scheduler {
     }
 }
 
+/// Removes the next channel to send from the queue of channels blocked on one
+/// logical link, which is the first channel with the highest priority.
+fn next_blocked(blocked: &mut VecDeque<Arc<RawChan>>) -> Option<Arc<RawChan>> {
+    let (i, _) = (blocked.iter().map(|ch| ch.state.lock().priority()))
+        .enumerate()
+        .reduce(|max, cur| if max.1 >= cur.1 { max } else { cur })?;
+    blocked.remove(i)
+}
+
 /// Scheduled PDU send task. Ensures that the channel is removed from the
 /// Scheduler when the send operation is done (or dropped).
 #[derive(Debug)]
@@ -317,14 +366,19 @@ impl SchedulerGuard {
         let pdu_len = u16::try_from(hdr.as_ref().len() - L2CAP_HDR).unwrap();
         hdr.u16(pdu_len).u16(self.ch.cid.chan);
 
-        if let Some(xfer) = pdu.take_xfer() {
-            // Fast path for a single-fragment PDU
-            debug_assert_eq!(xfer.typ(), hci::TransferType::Acl(hci::Direction::FromHost));
-            return self.send_frag(xfer, false, false).await.map(|_xfer| ());
+        let frag_len = usize::from(self.ch.max_frag_len(self.tx.alloc.acl_data_len));
+        if pdu.as_ref().len() <= frag_len {
+            if let Some(xfer) = pdu.take_xfer() {
+                // Fast path for a single-fragment PDU
+                debug_assert_eq!(xfer.typ(), hci::TransferType::Acl(hci::Direction::FromHost));
+                return self.send_frag(xfer, false, false).await.map(|_xfer| ());
+            }
         }
 
+        // Each fragment is copied once into a reusable transfer after the ACL
+        // data packet header
         let mut xfer = self.tx.alloc.xfer();
-        let frags = pdu.as_ref().chunks(self.tx.alloc.acl_data_len as _);
+        let frags = pdu.as_ref().chunks(frag_len);
         let last = frags.len() - 1;
         for (i, frag) in frags.enumerate() {
             xfer.at(ACL_HDR).put(frag);
@@ -333,6 +387,7 @@ impl SchedulerGuard {
                 xfer.reset();
             }
         }
+        self.tx.alloc.recycle(xfer);
         Ok(())
     }
 
@@ -374,3 +429,214 @@ impl Drop for SchedulerGuard {
         self.tx.sched.lock().remove(&self.ch);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use std::time::Duration;
+
+    use crate::le::{Addr, RawAddr};
+
+    use super::*;
+
+    /// Creates an ATT channel for connection `hdl`.
+    fn chan(hdl: u16) -> Arc<RawChan> {
+        let (link, cn) = conn(hdl);
+        RawChan::new(link.chan(Cid::ATT), &cn, 23)
+    }
+
+    /// Creates a logical link and its connection watch channel for connection
+    /// `hdl`.
+    fn conn(hdl: u16) -> (LeU, hci::ConnWatch) {
+        let e = hci::LeConnectionComplete {
+            status: hci::Status::Success,
+            handle: hci::ConnHandle::new(hdl).unwrap(),
+            role: hci::Role::Peripheral,
+            peer_addr: Addr::Public(RawAddr::default()),
+            local_rpa: RawAddr::default(),
+            peer_rpa: RawAddr::default(),
+            conn_interval: Duration::from_millis(30),
+            peripheral_latency: 0,
+            supervision_timeout: Duration::from_secs(4),
+            central_clock_accuracy: 0,
+        };
+        let (_, cn) = tokio::sync::watch::channel(hci::Conn::new(&e));
+        (LeU::new(e.handle), cn)
+    }
+
+    /// Sends single-fragment PDUs from active channels until the scheduler
+    /// blocks, updating the number of PDUs sent by each link. Returns the
+    /// channels in the order that they were removed from the scheduler.
+    fn send_all(s: &mut Scheduler, count: &mut HashMap<LeU, usize>) -> Vec<Arc<RawChan>> {
+        let mut done = Vec::new();
+        while let Some(ch) = s.active.clone() {
+            s.sent(&ch, false);
+            s.remove(&ch);
+            *count.entry(ch.cid.link).or_default() += 1;
+            done.push(ch);
+        }
+        done
+    }
+
+    #[test]
+    fn fairness() {
+        const LINKS: u16 = 16;
+        const MAX_PKTS: u16 = 8;
+        let mut s = Scheduler::new(MAX_PKTS);
+        let chans: Vec<_> = (1..=LINKS).map(chan).collect();
+        for ch in &chans {
+            s.register_link(ch.cid.link);
+        }
+        let link_quota = s.link_quota();
+        assert_eq!(link_quota, MAX_PKTS / 2);
+
+        // A stalled link that never acknowledges packets can only use its
+        // share of the controller's buffer
+        let (stalled, fast) = (&chans[0], &chans[1..]);
+        let mut count = HashMap::new();
+        for _ in 0..MAX_PKTS {
+            if !stalled.state.lock().is_scheduled() {
+                s.schedule(stalled).unwrap();
+            }
+            send_all(&mut s, &mut count);
+        }
+        assert_eq!(count[&stalled.cid.link], usize::from(link_quota));
+        assert_eq!(s.quota, MAX_PKTS - link_quota);
+
+        // Other links share the rest of the buffer equally while the stalled
+        // link remains ready to send. Each link sends another PDU as soon as
+        // the previous one is done.
+        count.clear();
+        let mut idle = fast.to_vec();
+        for _ in 0..100 {
+            for ch in idle.drain(..) {
+                s.schedule(&ch).unwrap();
+            }
+            idle = send_all(&mut s, &mut count);
+            let acks: Vec<_> = (s.sent.iter())
+                .filter(|&(&link, &n)| link != stalled.cid.link && n > 0)
+                .map(|(&link, &n)| (link, n))
+                .collect();
+            s.ack(acks.into_iter());
+        }
+        assert!(!count.contains_key(&stalled.cid.link));
+        let n: Vec<_> = fast.iter().map(|ch| count[&ch.cid.link]).collect();
+        let (min, max) = (n.iter().min().unwrap(), n.iter().max().unwrap());
+        assert!(max - min <= 1, "unfair distribution: {n:?}");
+        let total: usize = n.iter().sum();
+        assert_eq!(total, 100 * usize::from(MAX_PKTS - link_quota));
+    }
+
+    /// Notifications from 16 simulated connections that share the controller's
+    /// buffer over the loopback transport make progress at the same rate and
+    /// are delivered intact.
+    #[tokio::test]
+    async fn loopback_conns() {
+        const CONNS: u16 = 16;
+        const PDUS: usize = 64;
+        let (periph, mut central): (Vec<_>, Vec<_>) =
+            (crate::l2cap::loopback::att_conns(CONNS, 8).into_iter()).unzip();
+        let sent: Arc<Vec<_>> = Arc::new(periph.iter().map(|_| AtomicUsize::new(0)).collect());
+        let first_done = Arc::new(SyncMutex::new(None));
+        let tasks: Vec<_> = (periph.into_iter().enumerate())
+            .map(|(i, mut ch)| {
+                let (sent, first_done) = (Arc::clone(&sent), Arc::clone(&first_done));
+                tokio::spawn(async move {
+                    let id = u8::try_from(i).unwrap();
+                    for _ in 0..PDUS {
+                        let mut sdu = ch.alloc();
+                        sdu.append().u8(0x1B).u8(id).put([0; 21]);
+                        ch.send(sdu).await.unwrap();
+                        sent[i].fetch_add(1, Relaxed);
+                    }
+                    let n: Vec<_> = sent.iter().map(|n| n.load(Relaxed)).collect();
+                    first_done.lock().get_or_insert(n);
+                })
+            })
+            .collect();
+        for t in tasks {
+            t.await.unwrap();
+        }
+        let n = first_done.lock().take().unwrap();
+        assert!(n.iter().all(|&n| n >= PDUS / 2), "unfair progress: {n:?}");
+        for (i, ch) in central.iter_mut().enumerate() {
+            for _ in 0..PDUS {
+                let sdu = ch.recv().await.unwrap();
+                assert_eq!(sdu.as_ref()[..2], [0x1B, u8::try_from(i).unwrap()]);
+                assert_eq!(sdu.as_ref().len(), usize::from(L2CAP_LE_MIN_MTU));
+            }
+        }
+    }
+
+    /// An ATT PDU waits for at most one PDU from bulk channels on the same
+    /// logical link while they transfer 1 MB, unless the ATT bearer priority
+    /// is lowered to match the bulk channels.
+    #[tokio::test]
+    async fn priority() {
+        let (max_wait, frags) = bulk_wait(None).await;
+        assert!(max_wait <= frags + 1, "waited for {max_wait} packets");
+        let (max_wait, frags) = bulk_wait(Some(Priority::Low)).await;
+        assert!(max_wait > frags + 1, "waited for {max_wait} packets");
+    }
+
+    /// Sends ATT PDUs while two CoC channels on the same logical link transfer
+    /// 1 MB. Returns the maximum number of ACL data packets that any ATT PDU
+    /// waited for and the number of packets in each CoC PDU.
+    async fn bulk_wait(att_priority: Option<Priority>) -> (usize, usize) {
+        const BULK: usize = 1 << 20;
+        const SDU: usize = 512;
+        let mtu = u16::try_from(SDU).unwrap();
+        let cids = [
+            (Cid::ATT, L2CAP_LE_MIN_MTU),
+            (Cid::new(0x40).unwrap(), mtu),
+            (Cid::new(0x41).unwrap(), mtu),
+        ];
+        let l = crate::l2cap::loopback::chans(&cids, 8, hci::ACL_LE_MIN_DATA_LEN);
+        let mut pairs = l.pairs.into_iter().map(|(p, _)| p);
+        let mut att = crate::att::Bearer::new(pairs.next().unwrap());
+        if let Some(p) = att_priority {
+            att.set_priority(p);
+        }
+        let bulk: Vec<_> = pairs
+            .map(|mut ch| {
+                tokio::spawn(async move {
+                    for _ in 0..BULK / 2 / SDU {
+                        let mut sdu = ch.alloc();
+                        sdu.append().put([0; SDU]);
+                        ch.send(sdu).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        let acl_len = usize::from(hci::ACL_LE_MIN_DATA_LEN);
+        let frags = (L2CAP_HDR + SDU + acl_len - 1) / acl_len;
+        let (mut n, mut max_wait) = (0, 0);
+        while !bulk.iter().all(tokio::task::JoinHandle::is_finished) {
+            let before = l.pkts.load(Relaxed);
+            att.write_cmd(crate::att::Handle::MIN, &[]).await.unwrap();
+            max_wait = max_wait.max(l.pkts.load(Relaxed) - before);
+            n += 1;
+            tokio::task::yield_now().await;
+        }
+        assert!(n > 100);
+        assert_eq!(l.pkts.load(Relaxed), BULK / SDU * frags + n);
+        (max_wait, frags)
+    }
+
+    /// PDUs are fragmented to the maximum LL Data PDU payload size once the
+    /// data length is increased.
+    #[tokio::test]
+    async fn data_len() {
+        const ACL_LEN: u16 = 251;
+        let l = crate::l2cap::loopback::chans(&[(Cid::ATT, 512)], 8, ACL_LEN);
+        let (mut ch, _peer) = l.pairs.into_iter().next().unwrap();
+        for (ll_tx, pkts) in [(hci::LL_MIN_OCTETS, 1), (100, 3), (ACL_LEN, 1)] {
+            l.conn.send_modify(|cn| cn.max_octets = (ll_tx, ll_tx));
+            let before = l.pkts.load(Relaxed);
+            let mut sdu = ch.alloc();
+            sdu.append().put([0; 200]);
+            ch.send(sdu).await.unwrap();
+            assert_eq!(l.pkts.load(Relaxed) - before, pkts, "LL TX octets: {ll_tx}");
+        }
+    }
+}
@@ -1,22 +1,37 @@
 //! LE-specific types.
 
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 
-use structbuf::{Packer, Unpacker};
+use structbuf::{Pack, Packer, StructBuf, Unpacker};
+use tracing::warn;
+
+use burble_crypto::{Codec, Nonce};
+
+use crate::PeerStore;
+
+pub mod rpa;
 
 /// Bluetooth device address ([Vol 6] Part B, Section 1.3).
 #[allow(clippy::exhaustive_enums)]
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(
+    Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, serde::Deserialize, serde::Serialize,
+)]
 pub enum Addr {
     Public(RawAddr),
     Random(RawAddr),
 }
 
 impl Addr {
-    /// Constructs a peer address from type and raw components.
+    /// Constructs a peer address from the HCI address type and raw
+    /// components.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the address type is invalid.
     #[inline]
     #[must_use]
-    pub(super) fn peer(typ: u8, raw: RawAddr) -> Self {
+    pub fn peer(typ: u8, raw: RawAddr) -> Self {
         // [Vol 4] Part E, Sections 7.7.65.1 and 7.7.65.10
         match typ {
             // Public Device Address or Public Identity Address
@@ -36,6 +51,30 @@ impl Addr {
         }
     }
 
+    /// Returns whether this is a random device address.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_random(self) -> bool {
+        matches!(self, Self::Random(_))
+    }
+
+    /// Returns the address kind, which distinguishes the random address
+    /// subtypes by the two most significant bits ([Vol 6] Part B, Section
+    /// 1.3.2).
+    #[inline]
+    #[must_use]
+    pub const fn kind(self) -> AddrKind {
+        match self {
+            Self::Public(_) => AddrKind::Public,
+            Self::Random(raw) => match raw.0[5] >> 6 {
+                0b11 => AddrKind::Static,
+                0b01 => AddrKind::Resolvable,
+                0b00 => AddrKind::NonResolvable,
+                _ => AddrKind::Reserved,
+            },
+        }
+    }
+
     /// Returns whether the address is all-zero.
     #[inline(always)]
     #[must_use]
@@ -44,6 +83,32 @@ impl Addr {
     }
 }
 
+impl Display for Addr {
+    /// Formats the address as `public/` or `random/` followed by the raw
+    /// address.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Public(raw) => write!(f, "public/{raw}"),
+            Self::Random(raw) => write!(f, "random/{raw}"),
+        }
+    }
+}
+
+impl FromStr for Addr {
+    type Err = AddrParseError;
+
+    /// Parses an address in the format produced by [`Display`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(raw) = s.strip_prefix("public/") {
+            Ok(Self::Public(raw.parse()?))
+        } else if let Some(raw) = s.strip_prefix("random/") {
+            Ok(Self::Random(raw.parse()?))
+        } else {
+            Err(AddrParseError::Type)
+        }
+    }
+}
+
 impl Default for Addr {
     #[inline(always)]
     fn default() -> Self {
@@ -74,17 +139,16 @@ impl burble_crypto::Codec for Addr {
 impl From<Addr> for burble_crypto::Addr {
     #[inline]
     fn from(a: Addr) -> Self {
-        let (is_random, raw) = match a {
-            Addr::Public(addr) => (false, addr.0),
-            Addr::Random(addr) => (true, addr.0),
-        };
-        Self::from_le_bytes(is_random, raw)
+        Self::from_le_bytes(a.is_random(), a.raw().0)
     }
 }
 
 // 48-bit untyped device address stored in little-endian byte order.
-#[derive(Clone, Copy, Default, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(
+    Clone, Copy, Default, Eq, Ord, PartialEq, PartialOrd, serde::Deserialize, serde::Serialize,
+)]
 #[repr(transparent)]
+#[serde(transparent)]
 pub struct RawAddr([u8; 6]);
 
 impl RawAddr {
@@ -101,6 +165,53 @@ impl RawAddr {
     pub const fn as_le_bytes(self) -> [u8; 6] {
         self.0
     }
+
+    /// Generates a new static random device address using the OS CSPRNG
+    /// ([Vol 6] Part B, Section 1.3.2.1).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the OS CSPRNG is broken.
+    #[must_use]
+    pub fn gen_static_random() -> Self {
+        loop {
+            let mut v = random();
+            v[5] |= 0b11 << 6;
+            let a = Self(v);
+            if a.is_static_random() {
+                return a;
+            }
+        }
+    }
+
+    /// Returns the static random device address saved in `store` or
+    /// generates and saves a new one. The address is saved for the all-zero
+    /// public peer address, so the store should not be used for any other
+    /// data. Bonded peers may identify the device by this address, so the
+    /// store should be persistent.
+    #[must_use]
+    pub fn load_or_gen_static_random(store: &dyn PeerStore<Value = Self>) -> Self {
+        let slot = Addr::default();
+        if let Some(a) = store.load(slot).filter(|a| a.is_static_random()) {
+            return a;
+        }
+        let a = Self::gen_static_random();
+        if !store.save(slot, &a) {
+            warn!("Failed to save static random address: {a}");
+        }
+        a
+    }
+
+    /// Returns whether the address is a valid static random device address
+    /// with the two most significant bits set and at least one `0` and one
+    /// `1` bit in the random part ([Vol 6] Part B, Section 1.3.2.1).
+    #[must_use]
+    pub const fn is_static_random(self) -> bool {
+        const RAND: u64 = (1 << 46) - 1;
+        let v = self.0;
+        let v = u64::from_le_bytes([v[0], v[1], v[2], v[3], v[4], v[5], 0, 0]);
+        v >> 46 == 0b11 && !matches!(v & RAND, 0 | RAND)
+    }
 }
 
 impl AsRef<[u8]> for RawAddr {
@@ -121,24 +232,136 @@ impl Debug for RawAddr {
     }
 }
 
-/// Transmission power level in dBm.
+impl FromStr for RawAddr {
+    type Err = AddrParseError;
+
+    /// Parses an address in the colon-separated hex format (e.g.
+    /// `E4:5F:01:23:45:67`), with the most significant octet first.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut v = [0; 6];
+        let mut octets = s.split(':');
+        for (i, b) in v.iter_mut().rev().enumerate() {
+            let o = octets.next().ok_or(AddrParseError::Length)?;
+            if o.len() != 2 || !o.bytes().all(|c| c.is_ascii_hexdigit()) {
+                return Err(AddrParseError::Octet(i));
+            }
+            *b = u8::from_str_radix(o, 16).map_err(|_| AddrParseError::Octet(i))?;
+        }
+        match octets.next() {
+            None => Ok(Self(v)),
+            Some(_) => Err(AddrParseError::Length),
+        }
+    }
+}
+
+/// Returns random address bytes from the OS CSPRNG.
+fn random() -> [u8; 6] {
+    let mut b = StructBuf::new(16);
+    Nonce::new().pack(&mut b.append());
+    let mut v = [0; 6];
+    v.copy_from_slice(&b[..6]);
+    v
+}
+
+/// Device address kind ([Vol 6] Part B, Section 1.3).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AddrKind {
+    /// Public device address.
+    Public,
+    /// Static random device address.
+    Static,
+    /// Resolvable private address.
+    Resolvable,
+    /// Non-resolvable private address.
+    NonResolvable,
+    /// Random address with the reserved `0b10` subtype.
+    Reserved,
+}
+
+/// Error returned when parsing a device address from a string.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum AddrParseError {
+    #[error("invalid address type (expected \"public/\" or \"random/\" prefix)")]
+    Type,
+    #[error("invalid address octet {0} (expected two hex digits)")]
+    Octet(usize),
+    #[error("invalid address length (expected six octets)")]
+    Length,
+}
+
+/// Transmission power level in dBm. Power levels are ordered by their dBm
+/// value, with [`Self::NOT_MANAGED`] and [`Self::UNSPECIFIED`] ordered after
+/// all valid levels.
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 #[repr(transparent)]
 pub struct TxPower(i8);
 
 impl TxPower {
+    /// Minimum power level ([Vol 4] Part E, Section 7.8.53).
+    pub const MIN: i8 = -127;
+
     /// Maximum power level ([Vol 6] Part A, Section 3).
-    pub(crate) const MAX: i8 = 20;
+    pub const MAX: i8 = 20;
+
+    /// Power level reported for a remote device that is not managing power
+    /// levels ([Vol 4] Part E, Section 7.7.65.33).
+    pub const NOT_MANAGED: Self = Self(0x7E);
 
     /// Unknown or no preference power level
     /// ([Vol 4] Part E, Sections 7.5.4 and 7.8.53).
-    pub(crate) const NONE: i8 = 0x7F;
+    pub const UNSPECIFIED: Self = Self(0x7F);
 
-    /// Creates a power level of `v` dBm.
+    /// Creates a power level of `dbm` dBm, clamped to the valid range.
+    #[inline]
+    #[must_use]
+    pub const fn new(dbm: i8) -> Self {
+        Self(if dbm < Self::MIN {
+            Self::MIN
+        } else if dbm > Self::MAX {
+            Self::MAX
+        } else {
+            dbm
+        })
+    }
+
+    /// Converts an HCI power level parameter, which may be
+    /// [`Self::NOT_MANAGED`] or [`Self::UNSPECIFIED`]. Other out-of-range
+    /// values are clamped.
+    #[inline]
+    #[must_use]
+    pub(crate) const fn from_hci(v: i8) -> Self {
+        match v {
+            0x7E | 0x7F => Self(v),
+            _ => Self::new(v),
+        }
+    }
+
+    /// Returns the power level in dBm or [`None`] if the level is unspecified
+    /// or not managed.
+    #[inline]
+    #[must_use]
+    pub const fn dbm(self) -> Option<i8> {
+        if self.is_unspecified() || self.is_not_managed() {
+            None
+        } else {
+            Some(self.0)
+        }
+    }
+
+    /// Returns whether the power level is unknown or has no preference.
     #[inline(always)]
     #[must_use]
-    pub const fn new(v: i8) -> Self {
-        Self(v)
+    pub const fn is_unspecified(self) -> bool {
+        self.0 == Self::UNSPECIFIED.0
+    }
+
+    /// Returns whether the remote device is not managing power levels.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_not_managed(self) -> bool {
+        self.0 == Self::NOT_MANAGED.0
     }
 }
 
@@ -149,11 +372,109 @@ impl Default for TxPower {
     }
 }
 
+impl Display for TxPower {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.dbm() {
+            Some(v) => write!(f, "{v:+} dBm"),
+            None if self.is_not_managed() => f.write_str("not managed"),
+            None => f.write_str("unspecified"),
+        }
+    }
+}
+
 impl From<TxPower> for i8 {
+    /// Returns the HCI power level parameter.
     #[inline(always)]
     fn from(p: TxPower) -> Self {
         p.0
     }
 }
 
-crate::impl_display_via_debug! { Addr, RawAddr }
+crate::impl_display_via_debug! { RawAddr }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let raw = RawAddr::from_le_bytes([0x67, 0x45, 0x23, 0x01, 0x5F, 0xE4]);
+        assert_eq!("E4:5F:01:23:45:67".parse(), Ok(raw));
+        assert_eq!("e4:5f:01:23:45:67".parse(), Ok(raw));
+        assert_eq!(raw.to_string(), "E4:5F:01:23:45:67");
+        assert_eq!("public/E4:5F:01:23:45:67".parse(), Ok(Addr::Public(raw)));
+        let a = Addr::Random(raw);
+        assert_eq!(a.to_string(), "random/E4:5F:01:23:45:67");
+        assert_eq!(a.to_string().parse(), Ok(a));
+    }
+
+    #[test]
+    fn parse_error() {
+        use AddrParseError::*;
+        let p = |s: &str| s.parse::<Addr>().unwrap_err();
+        assert_eq!(p("E4:5F:01:23:45:67"), Type);
+        assert_eq!(p("Public/E4:5F:01:23:45:67"), Type);
+        assert_eq!(p("public/X4:5F:01:23:45:67"), Octet(0));
+        assert_eq!(p("public/E4:5F:01:2:45:67"), Octet(3));
+        assert_eq!(p("public/E4:5F:01:23:45:+6"), Octet(5));
+        assert_eq!(p("public/E4:5F:01:23:45:"), Octet(5));
+        assert_eq!(p("public/E4:5F:01:23:45"), Length);
+        assert_eq!(p("public/E4:5F:01:23:45:67:89"), Length);
+        assert_eq!(p("public/"), Octet(0));
+    }
+
+    #[test]
+    fn static_random() {
+        for _ in 0..1000 {
+            let a = RawAddr::gen_static_random();
+            assert!(a.is_static_random());
+            assert_eq!(a.0[5] >> 6, 0b11);
+            assert_eq!(Addr::Random(a).kind(), AddrKind::Static);
+        }
+        assert!(!RawAddr([0, 0, 0, 0, 0, 0xC0]).is_static_random());
+        assert!(!RawAddr([0xFF; 6]).is_static_random());
+        assert!(!RawAddr([1, 0, 0, 0, 0, 0x80]).is_static_random());
+        assert!(RawAddr([1, 0, 0, 0, 0, 0xC0]).is_static_random());
+        assert!(RawAddr([0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_static_random());
+    }
+
+    #[test]
+    fn load_or_gen_static_random() {
+        let s = crate::MemoryStore::new();
+        let a = RawAddr::load_or_gen_static_random(&s);
+        assert_eq!(RawAddr::load_or_gen_static_random(&s), a);
+        assert!(s.save(Addr::default(), &RawAddr([0xFF; 6])));
+        let b = RawAddr::load_or_gen_static_random(&s);
+        assert!(b.is_static_random());
+        assert_eq!(s.load(Addr::default()), Some(b));
+    }
+
+    #[test]
+    fn tx_power() {
+        assert_eq!(TxPower::new(4).dbm(), Some(4));
+        assert_eq!(TxPower::new(21).dbm(), Some(20));
+        assert_eq!(TxPower::new(i8::MIN).dbm(), Some(-127));
+        assert_eq!(TxPower::new(0x7F), TxPower::new(TxPower::MAX));
+        assert!(TxPower::from_hci(0x7F).is_unspecified());
+        assert!(TxPower::from_hci(0x7E).is_not_managed());
+        assert_eq!(TxPower::from_hci(0x7D).dbm(), Some(20));
+        assert_eq!(TxPower::UNSPECIFIED.dbm(), None);
+        assert!(TxPower::new(-5) < TxPower::new(4));
+        assert!(TxPower::new(20) < TxPower::UNSPECIFIED);
+        assert_eq!(TxPower::new(4).to_string(), "+4 dBm");
+        assert_eq!(TxPower::new(-10).to_string(), "-10 dBm");
+        assert_eq!(TxPower::new(0).to_string(), "+0 dBm");
+        assert_eq!(TxPower::UNSPECIFIED.to_string(), "unspecified");
+        assert_eq!(TxPower::NOT_MANAGED.to_string(), "not managed");
+    }
+
+    #[test]
+    fn kind() {
+        let a = |typ: u8, msb: u8| Addr::peer(typ, RawAddr::from_le_bytes([1, 2, 3, 4, 5, msb]));
+        assert_eq!(a(0, 0xC0).kind(), AddrKind::Public);
+        assert_eq!(a(1, 0xC0).kind(), AddrKind::Static);
+        assert_eq!(a(1, 0x7F).kind(), AddrKind::Resolvable);
+        assert_eq!(a(1, 0x3F).kind(), AddrKind::NonResolvable);
+        assert_eq!(a(1, 0x80).kind(), AddrKind::Reserved);
+    }
+}
@@ -0,0 +1,156 @@
+//! Resolvable private address utilities for host-based privacy
+//! ([Vol 6] Part B, Section 1.3.2.2).
+
+use std::future::Future;
+use std::time::Duration;
+
+use burble_crypto::IRK;
+
+use super::*;
+
+/// Generates a new resolvable private address using the specified IRK.
+///
+/// # Panics
+///
+/// Panics if the OS CSPRNG is broken.
+#[must_use]
+pub fn generate(irk: &IRK) -> RawAddr {
+    const RAND: u32 = (1 << 22) - 1;
+    loop {
+        let r = random();
+        let prand = u32::from_le_bytes([r[0], r[1], r[2], 0]) & RAND;
+        if matches!(prand, 0 | RAND) {
+            continue;
+        }
+        let prand = prand | (0b01 << 22);
+        let v = ((u64::from(prand) << 24) | u64::from(irk.ah(prand))).to_le_bytes();
+        return RawAddr::from_le_bytes([v[0], v[1], v[2], v[3], v[4], v[5]]);
+    }
+}
+
+/// Returns whether `addr` is a resolvable private address generated using
+/// the specified IRK ([Vol 6] Part B, Section 1.3.2.3).
+#[inline]
+#[must_use]
+pub fn matches(irk: &IRK, addr: Addr) -> bool {
+    addr.is_random() && addr.raw().is_resolvable_by(irk)
+}
+
+impl RawAddr {
+    /// Returns whether this random device address is a resolvable private
+    /// address generated using the specified IRK. The 24-bit `hash` part of
+    /// the address is compared with `ah(irk, prand)`
+    /// ([Vol 6] Part B, Section 1.3.2.3).
+    #[must_use]
+    pub fn is_resolvable_by(self, irk: &IRK) -> bool {
+        let v = self.as_le_bytes();
+        if v[5] >> 6 != 0b01 {
+            return false;
+        }
+        let hash = u32::from_le_bytes([v[0], v[1], v[2], 0]);
+        let prand = u32::from_le_bytes([v[3], v[4], v[5], 0]);
+        irk.ah(prand) == hash
+    }
+}
+
+/// Resolvable private address that is periodically replaced with a new one
+/// generated from the local IRK. This provides privacy for controllers that
+/// do not support link layer privacy.
+#[derive(Debug)]
+pub struct RotatingRpa {
+    irk: IRK,
+    interval: Duration,
+    addr: RawAddr,
+}
+
+impl RotatingRpa {
+    /// Recommended address rotation interval
+    /// (`TGAP(private_addr_int)`, [Vol 3] Part C, Appendix A).
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+    /// Creates a new rotating address with an initial address generated from
+    /// `irk`.
+    #[inline]
+    #[must_use]
+    pub fn new(irk: IRK, interval: Duration) -> Self {
+        let addr = generate(&irk);
+        Self {
+            irk,
+            interval,
+            addr,
+        }
+    }
+
+    /// Returns the current address.
+    #[inline(always)]
+    #[must_use]
+    pub const fn addr(&self) -> RawAddr {
+        self.addr
+    }
+
+    /// Calls `f` with the current address and then with a new address after
+    /// every rotation interval until `f` returns an error. The callback
+    /// should update the controller, e.g. by disabling advertising, calling
+    /// [`crate::hci::Advertiser::set_random_address`], and enabling
+    /// advertising again. Existing connections are not affected.
+    pub async fn run<E, F, Fut>(&mut self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(RawAddr) -> Fut + Send,
+        Fut: Future<Output = Result<(), E>> + Send,
+    {
+        loop {
+            f(self.addr).await?;
+            tokio::time::sleep(self.interval).await;
+            self.addr = generate(&self.irk);
+        }
+    }
+}
+
+#[allow(clippy::unreadable_literal)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: u128 = 0xec0234a3_57c8ad05_341010a6_0a397d9b;
+
+    #[test]
+    fn resolve() {
+        // [Vol 3] Part H, Section D.7
+        let rpa = Addr::Random(RawAddr::from_le_bytes([0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x70]));
+        assert!(matches(&IRK::new(KEY), rpa));
+        assert!(!matches(&IRK::new(KEY + 1), rpa));
+        assert!(!matches(&IRK::new(KEY), Addr::Public(rpa.raw())));
+        assert!(rpa.raw().is_resolvable_by(&IRK::new(KEY)));
+
+        // Non-resolvable address with the same hash and prand bits
+        let nrpa = RawAddr::from_le_bytes([0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x30]);
+        assert!(!nrpa.is_resolvable_by(&IRK::new(KEY)));
+    }
+
+    #[test]
+    fn generate_rpa() {
+        let irk = IRK::new(KEY);
+        for _ in 0..100 {
+            let a = Addr::Random(generate(&irk));
+            assert_eq!(a.kind(), AddrKind::Resolvable);
+            assert!(matches(&irk, a));
+        }
+    }
+
+    #[tokio::test]
+    async fn rotate() {
+        let mut rpa = RotatingRpa::new(IRK::new(KEY), Duration::from_millis(1));
+        let mut v = Vec::new();
+        let r = rpa
+            .run(|a| {
+                v.push(a);
+                std::future::ready(if v.len() < 3 { Ok(()) } else { Err(()) })
+            })
+            .await;
+        assert_eq!(r, Err(()));
+        assert_eq!(v.len(), 3);
+        assert_ne!(v[0], v[1]);
+        assert_eq!(v[2], rpa.addr());
+        assert!((v.iter()).all(|&a| matches(&IRK::new(KEY), Addr::Random(a))));
+    }
+}
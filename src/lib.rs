@@ -18,12 +18,15 @@ pub mod l2cap;
 pub mod le;
 #[path = "smp/smp.rs"]
 pub mod smp;
+mod store;
 
 /// Service Discovery Protocol constants ([Vol 3] Part B).
 pub mod sdp {
     pub use burble_const::ServiceClass;
 }
 
+pub use store::*;
+
 type SyncMutex<T> = parking_lot::Mutex<T>;
 type SyncMutexGuard<'a, T> = parking_lot::MutexGuard<'a, T>;
 type AsyncMutex<T> = tokio::sync::Mutex<T>;
@@ -46,6 +49,45 @@ pub trait PeerStore: std::fmt::Debug + Send + Sync {
 
     /// Removes all peer data.
     fn clear(&self);
+
+    /// Returns the addresses of all peers with stored data. The default
+    /// implementation returns an empty list for stores that cannot enumerate
+    /// their contents.
+    #[must_use]
+    fn peers(&self) -> Vec<le::Addr> {
+        Vec::new()
+    }
+}
+
+/// Interface to persistent peer data storage that keeps multiple independent
+/// kinds of data for each peer. Each kind is identified by a name and stored
+/// as opaque bytes, allowing different layers to share one store without
+/// knowing about each other. `fs::StoreFor` adapts one kind to the
+/// [`PeerStore`] interface.
+///
+/// Kind names must be non-empty and consist of ASCII alphanumeric characters,
+/// `-`, or `_`.
+pub trait KindStore: std::fmt::Debug + Send + Sync {
+    /// Saves one kind of peer data and returns `true` if the operation was
+    /// successful.
+    fn save_kind(&self, peer: le::Addr, kind: &str, v: &[u8]) -> bool;
+
+    /// Loads one kind of peer data.
+    #[must_use]
+    fn load_kind(&self, peer: le::Addr, kind: &str) -> Option<Vec<u8>>;
+
+    /// Removes one kind of peer data.
+    fn remove_kind(&self, peer: le::Addr, kind: &str);
+
+    /// Removes all kinds of peer data atomically.
+    fn remove(&self, peer: le::Addr);
+
+    /// Removes all peer data.
+    fn clear(&self);
+
+    /// Returns the addresses of all peers with stored data.
+    #[must_use]
+    fn peers(&self) -> Vec<le::Addr>;
 }
 
 /// Forwards [`core::fmt::Display`] implementation to [`core::fmt::Debug`].
@@ -0,0 +1,341 @@
+use tracing::{debug, error, Instrument};
+
+use burble_crypto::{PublicKeyX, LTK, TK};
+
+use crate::hci::Role;
+use crate::l2cap::Chan;
+use crate::{hci, le};
+
+use super::*;
+
+/// Central role security manager implementing the initiator side of LE Secure
+/// Connections pairing ([Vol 3] Part H, Section 2.3). Like [`Peripheral`], it
+/// supports the Just Works and Numeric Comparison association models. When
+/// bonding, it exchanges identity and signing information in phase 3.
+#[derive(Debug)]
+pub struct Central {
+    ch: SmpChan,
+    dist: Option<Dist>,
+}
+
+impl Central {
+    /// Creates a new central security manager.
+    #[inline(always)]
+    pub(crate) fn new(ch: Chan) -> Self {
+        assert_eq!(ch.conn().borrow().role, Role::Central);
+        Self {
+            ch: SmpChan::new(ch),
+            dist: None,
+        }
+    }
+
+    /// Handles initiator pairing role, requesting a bond if `bond` is `true`.
+    /// The keys are saved in `store` and returned to the caller, which must
+    /// use the Long Term Key to start encryption and then call
+    /// [`Self::distribute_keys`]. This method is not cancel safe.
+    pub async fn initiate(
+        &mut self,
+        dev: &mut Device,
+        store: &KeyStore,
+        bond: bool,
+    ) -> Result<Keys> {
+        let span = self.ch.pairing_span();
+        self.pair(dev, store, bond).instrument(span).await
+    }
+
+    /// Performs pairing phases 1 and 2 as the initiator.
+    async fn pair(&mut self, dev: &mut Device, store: &KeyStore, bond: bool) -> Result<Keys> {
+        let Phase1 {
+            a,
+            b,
+            method,
+            sec,
+            legacy,
+        } = self.phase1(dev, bond).await?;
+        let (peer, ltk) = if legacy {
+            let preq = a.to_cmd_bytes(Code::PairingRequest);
+            (self.legacy_phase2(dev, &preq, &b.to_cmd_bytes(Code::PairingResponse))).await?
+        } else {
+            self.phase2(dev, method, a.into(), b.into()).await?
+        };
+        let keys = Keys::new(sec, ltk);
+        save(store, peer, &keys)?;
+        self.dist = Some(Dist {
+            peer,
+            a: b.initiator_keys,
+            b: b.responder_keys,
+        });
+        Ok(keys)
+    }
+
+    /// Performs Transport Specific Key Distribution phase after the caller
+    /// encrypts the link with the keys returned by [`Self::initiate`]
+    /// ([Vol 3] Part H, Section 3.6.1 and C.3). The keys distributed by the
+    /// peer are added to `keys`, which are saved in `store` under the peer's
+    /// identity address, if distributed. Returns the address under which the
+    /// keys were saved. This method is not cancel safe.
+    ///
+    /// # Panics
+    ///
+    /// Panics if pairing was not initiated.
+    pub async fn distribute_keys(
+        &mut self,
+        dev: &Device,
+        store: &KeyStore,
+        mut keys: Keys,
+    ) -> Result<le::Addr> {
+        let d = self.dist.take().expect("pairing not initiated");
+        if d.a.union(d.b).is_empty() {
+            return Ok(d.peer);
+        }
+        let span = self.ch.pairing_span();
+        let id = self.phase3(dev, d.a, d.b, &mut keys);
+        let id = id.instrument(span).await?;
+        save_identity(store, d.peer, id, &keys)
+    }
+
+    /// Performs Pairing Feature Exchange phase
+    /// ([Vol 3] Part H, Section 2.3.5.1 and C.1).
+    async fn phase1(&mut self, dev: &Device, bond: bool) -> Result<Phase1> {
+        let mut a = PairingParams {
+            io_cap: dev.io_cap(),
+            ..PairingParams::default()
+        };
+        (a.auth_req).set(AuthReq::BONDING, bond);
+        (a.auth_req).set(AuthReq::MITM, !matches!(a.io_cap, IoCap::NoInputNoOutput));
+        if bond {
+            a.initiator_keys = dev.key_dist();
+            a.responder_keys = KeyDist::ID | KeyDist::SIGN;
+        }
+        self.ch.send(Command::PairingRequest(a)).await?;
+        let b = loop {
+            match self.ch.recv().await? {
+                Command::PairingResponse(b) => break b,
+                // The peripheral may have requested security before receiving
+                // our request ([Vol 3] Part H, Section 2.4.6).
+                Command::SecurityRequest(_) => debug!("Ignoring SecurityRequest"),
+                _ => return self.ch.expecting(Code::PairingResponse).await,
+            }
+        };
+        let legacy = !b.auth_req.contains(AuthReq::SC);
+        if legacy && !dev.legacy {
+            // [Vol 3] Part H, Section 2.3 and C.5.1
+            error!("Peer does not support LE Secure Connections");
+            return self.ch.fail(Reason::PairingNotSupported).await;
+        }
+        if b.max_key_len < PairingParams::MIN_KEY_LEN {
+            // [Vol 3] Part H, Section C.5.3
+            error!(
+                "Peer does not support {}-bit encryption (maximum is {})",
+                PairingParams::MIN_KEY_LEN * 8,
+                b.max_key_len * 8
+            );
+            return self.ch.fail(Reason::EncryptionKeySize).await;
+        }
+        if !(a.initiator_keys.contains(b.initiator_keys)
+            && a.responder_keys.contains(b.responder_keys))
+        {
+            // [Vol 3] Part H, Section 3.6.1
+            error!("Peer requested key distribution that was not offered");
+            return self.ch.fail(Reason::InvalidParameters).await;
+        }
+        // [Vol 3] Part H, Section 2.3.5.1, Table 2.7
+        if b.oob_data {
+            error!("OOB pairing method not implemented"); // TODO: Implement
+            return self.ch.fail(Reason::OobNotAvailable).await;
+        }
+        // [Vol 3] Part H, Section 2.3.5.1, Table 2.8
+        let method = if !a.auth_req.union(b.auth_req).contains(AuthReq::MITM) {
+            KeyGenMethod::JustWorks
+        } else if legacy {
+            KeyGenMethod::resolve_legacy(a.io_cap, b.io_cap)
+        } else {
+            KeyGenMethod::resolve(a.io_cap, b.io_cap)
+        };
+        if legacy && !matches!(method, KeyGenMethod::JustWorks) {
+            error!("LE legacy pairing only supports the Just Works association model");
+            return self.ch.fail(Reason::AuthenticationRequirements).await;
+        }
+        if matches!(method, KeyGenMethod::PasskeyEntry) {
+            error!("Passkey Entry association model not implemented"); // TODO: Implement
+            return self.ch.fail(Reason::AuthenticationRequirements).await;
+        }
+        let authn = !matches!(method, KeyGenMethod::JustWorks);
+        if a.auth_req.contains(AuthReq::MITM) && !authn {
+            error!("Just Works association model selected, but MITM protection is required");
+            return self.ch.fail(Reason::AuthenticationRequirements).await;
+        }
+        // [Vol 3] Part H, Section 2.3.4
+        let mut sec = hci::ConnSec::key_len(a.max_key_len.min(b.max_key_len) * 8);
+        if authn {
+            sec.insert(hci::ConnSec::AUTHN);
+        }
+        // Legacy pairing would require LTK distribution to create a bond
+        if !legacy && (a.auth_req & b.auth_req).contains(AuthReq::BONDING) {
+            sec.insert(hci::ConnSec::BOND);
+        }
+        Ok(Phase1 {
+            a,
+            b,
+            method,
+            sec,
+            legacy,
+        })
+    }
+
+    /// Performs LE Secure Connections Long Term Key (LTK) Generation phase
+    /// ([Vol 3] Part H, Section 2.3.5.6).
+    async fn phase2(
+        &mut self,
+        dev: &mut Device,
+        method: KeyGenMethod,
+        ioa: burble_crypto::IoCap,
+        iob: burble_crypto::IoCap,
+    ) -> Result<(le::Addr, LTK)> {
+        // Public key exchange ([Vol 3] Part H, Section 2.3.5.6.1 and C.2.2.1)
+        let kp = dev.key_pair();
+        let (ska, pka) = (kp.secret_key(), kp.public_key());
+        self.ch.send(Command::PairingPublicKey(pka)).await?;
+        let Command::PairingPublicKey(pkb) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingPublicKey).await;
+        };
+        if !pkb.validate() || pkb == pka {
+            error!("Invalid or reflected peer public key");
+            return self.ch.fail(Reason::DhKeyCheckFailed).await;
+        }
+        let Some(dh_key) = ska.dh_key(pkb) else {
+            return self.ch.fail(Reason::InvalidParameters).await; // Debug key
+        };
+
+        // Authentication stage 1 ([Vol 3] Part H, Section C.2.2.2)
+        let Authn1 { na, nb, ra, rb } = match method {
+            KeyGenMethod::JustWorks | KeyGenMethod::NumCompare => {
+                self.authn1_num_compare(dev, method, pka.x(), pkb.x())
+                    .await?
+            }
+            KeyGenMethod::PasskeyEntry => unreachable!("Passkey Entry protocol"),
+        };
+
+        // Authentication stage 2 and long term key calculation
+        // ([Vol 3] Part H, Section 2.3.5.6.5 and C.2.2.4).
+        let (peer, a, b) = {
+            let (peer_addr, local_addr) = {
+                let cn = self.ch.conn().borrow();
+                (cn.peer_addr, cn.local_addr)
+            };
+            if local_addr.is_zero() {
+                error!("Pairing failed because local address is unknown");
+                return self.ch.fail(Reason::UnspecifiedReason).await;
+            }
+            (peer_addr, local_addr.into(), peer_addr.into())
+        };
+        let (mac_key, ltk) = dh_key.f5(na, nb, a, b);
+        let ea = mac_key.f6(na, nb, rb, ioa, a, b);
+        self.ch.send(Command::PairingDhKeyCheck(ea)).await?;
+        let Command::PairingDhKeyCheck(eb) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingDhKeyCheck).await;
+        };
+        if eb != mac_key.f6(nb, na, ra, iob, b, a) {
+            return self.ch.fail(Reason::DhKeyCheckFailed).await;
+        }
+        Ok((peer, ltk))
+    }
+
+    /// Performs LE legacy pairing Short Term Key (STK) Generation phase using
+    /// the Just Works association model ([Vol 3] Part H, Section 2.3.5.5 and
+    /// C.2.1). `preq` and `pres` are the exchanged pairing commands.
+    async fn legacy_phase2(
+        &mut self,
+        dev: &Device,
+        preq: &[u8; 7],
+        pres: &[u8; 7],
+    ) -> Result<(le::Addr, LTK)> {
+        let (peer, ia, ra) = {
+            let (peer_addr, local_addr) = {
+                let cn = self.ch.conn().borrow();
+                (cn.peer_addr, cn.local_addr)
+            };
+            if local_addr.is_zero() {
+                error!("Pairing failed because local address is unknown");
+                return self.ch.fail(Reason::UnspecifiedReason).await;
+            }
+            (peer_addr, local_addr.into(), peer_addr.into())
+        };
+        let tk = TK::new(0);
+        let mrand = dev.nonce().await?;
+        let mconfirm = tk.c1(&mrand, preq, pres, ia, ra);
+        self.ch.send(Command::PairingConfirm(mconfirm)).await?;
+        let Command::PairingConfirm(sconfirm) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingConfirm).await;
+        };
+        self.ch.send(Command::PairingRandom(mrand)).await?;
+        let Command::PairingRandom(srand) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingRandom).await;
+        };
+        if sconfirm != tk.c1(&srand, preq, pres, ia, ra) {
+            return self.ch.fail(Reason::ConfirmValueFailed).await;
+        }
+        Ok((peer, tk.s1(&srand, &mrand)))
+    }
+
+    /// Implements Authentication stage 1 – Just Works or Numeric Comparison
+    /// ([Vol 3] Part H, Section 2.3.5.6.2 and C.2.2.2.1).
+    async fn authn1_num_compare(
+        &mut self,
+        dev: &mut Device,
+        method: KeyGenMethod,
+        pka: &PublicKeyX,
+        pkb: &PublicKeyX,
+    ) -> Result<Authn1> {
+        let na = dev.nonce().await?;
+        let (ra, rb) = (0, 0);
+        let Command::PairingConfirm(cb) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingConfirm).await;
+        };
+        self.ch.send(Command::PairingRandom(na)).await?;
+        let Command::PairingRandom(nb) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingRandom).await;
+        };
+        if cb != nb.f4(pkb, pka, 0) {
+            return self.ch.fail(Reason::ConfirmValueFailed).await;
+        }
+        if matches!(method, KeyGenMethod::JustWorks) {
+            return Ok(Authn1 { na, nb, ra, rb });
+        }
+        let va = na.g2(pka, pkb, &nb);
+        let peer = self.ch.conn().borrow().peer_addr;
+        // TODO: Abort if PairingFailed is received while waiting for the user
+        if !dev.num_compare(peer, va).await {
+            // [Vol 3] Part H, Section C.2.2.2.4
+            return self.ch.fail(Reason::NumericComparisonFailed).await;
+        }
+        Ok(Authn1 { na, nb, ra, rb })
+    }
+
+    /// Performs Transport Specific Key Distribution phase
+    /// ([Vol 3] Part H, Section 3.6.1 and C.3). The Peripheral distributes the
+    /// keys in `b` before receiving the keys in `a`. Returns the Peripheral's
+    /// identity address, if distributed.
+    async fn phase3(
+        &mut self,
+        dev: &Device,
+        a: KeyDist,
+        b: KeyDist,
+        k: &mut Keys,
+    ) -> Result<Option<le::Addr>> {
+        let id = self.ch.recv_keys(b, k).await?;
+        self.ch.send_keys(dev, a).await?;
+        Ok(id)
+    }
+}
+
+/// Keys negotiated for distribution in phase 3.
+#[derive(Clone, Copy, Debug)]
+struct Dist {
+    /// Peer connection address.
+    peer: le::Addr,
+    /// Keys distributed by the initiator.
+    a: KeyDist,
+    /// Keys distributed by the responder.
+    b: KeyDist,
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use tracing::error;
+
+use burble_crypto::{CSRK, IRK};
+
+use crate::l2cap::Chan;
+use crate::{hci, le};
+
+use super::*;
+
+/// Security Manager fixed channel that exchanges SMP commands.
+#[derive(Debug)]
+#[repr(transparent)]
+pub(super) struct SmpChan(Chan);
+
+impl SmpChan {
+    /// Pairing procedure timeout ([Vol 3] Part H, Section 3.4).
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Wraps an SMP fixed channel.
+    #[inline(always)]
+    pub const fn new(ch: Chan) -> Self {
+        Self(ch)
+    }
+
+    /// Returns the connection watch channel.
+    #[inline(always)]
+    pub fn conn(&self) -> &hci::ConnWatch {
+        self.0.conn()
+    }
+
+    /// Returns a new pairing procedure span within the connection span.
+    #[inline]
+    pub fn pairing_span(&self) -> tracing::Span {
+        tracing::info_span!(parent: &self.conn().borrow().span, "pairing")
+    }
+
+    /// Returns the next command, waiting indefinitely. This is used to wait
+    /// for the start of the pairing procedure.
+    pub async fn next(&mut self) -> Result<Command> {
+        let pdu = self.0.recv().await?;
+        self.parse(pdu).await
+    }
+
+    /// Returns the next command of an ongoing pairing procedure.
+    pub async fn recv(&mut self) -> Result<Command> {
+        let pdu = match tokio::time::timeout(Self::TIMEOUT, self.0.recv()).await {
+            Ok(Ok(pdu)) => pdu,
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => return Err(Error::Timeout), // TODO: Mark channel as unusable
+        };
+        self.parse(pdu).await
+    }
+
+    /// Waits for the link to be encrypted with the key generated in phase 2,
+    /// which is required before key distribution
+    /// ([Vol 3] Part H, Section 3.6.1).
+    pub async fn encrypted(&mut self) -> Result<()> {
+        let cid = self.0.cid();
+        let mut cn = self.conn().clone();
+        let wait = async move {
+            loop {
+                let sec = cn.borrow_and_update().sec;
+                if sec.intersects(hci::ConnSec::KEY_LEN) {
+                    return Ok(());
+                }
+                if cn.changed().await.is_err() {
+                    return Err(Error::L2cap(l2cap::Error::ChanClosed(cid)));
+                }
+            }
+        };
+        (tokio::time::timeout(Self::TIMEOUT, wait).await).unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Sends the local keys in `dist` ([Vol 3] Part H, Section 3.6.1).
+    pub async fn send_keys(&mut self, dev: &Device, dist: KeyDist) -> Result<()> {
+        let id = dev.identity.as_ref().filter(|_| dist.contains(KeyDist::ID));
+        if let Some(&(addr, ref irk)) = id {
+            let irk = IRK::new(u128::from(irk));
+            self.send(Command::IdentityInformation(irk)).await?;
+            self.send(Command::IdentityAddressInformation(addr)).await?;
+        }
+        let csrk = dev.csrk.as_ref().filter(|_| dist.contains(KeyDist::SIGN));
+        if let Some(csrk) = csrk {
+            let csrk = CSRK::new(u128::from(csrk));
+            self.send(Command::SigningInformation(csrk)).await?;
+        }
+        Ok(())
+    }
+
+    /// Receives the peer keys in `dist` and adds them to `k`
+    /// ([Vol 3] Part H, Section 3.6.1). Returns the peer's identity address,
+    /// if distributed.
+    pub async fn recv_keys(&mut self, dist: KeyDist, k: &mut Keys) -> Result<Option<le::Addr>> {
+        let mut id = None;
+        if dist.contains(KeyDist::ID) {
+            let Command::IdentityInformation(irk) = self.recv().await? else {
+                return self.expecting(Code::IdentityInformation).await;
+            };
+            let Command::IdentityAddressInformation(addr) = self.recv().await? else {
+                return self.expecting(Code::IdentityAddressInformation).await;
+            };
+            // [Vol 3] Part H, Section 3.6.5
+            if !matches!(addr.kind(), le::AddrKind::Public | le::AddrKind::Static) {
+                error!("Invalid peer identity address: {addr}");
+                return self.fail(Reason::InvalidParameters).await;
+            }
+            // An all-zero IRK indicates that the peer does not use resolvable
+            // private addresses ([Vol 3] Part H, Section 3.6.4).
+            k.irk = (u128::from(&irk) != 0).then_some(irk);
+            id = Some(addr);
+        }
+        if dist.contains(KeyDist::SIGN) {
+            let Command::SigningInformation(csrk) = self.recv().await? else {
+                return self.expecting(Code::SigningInformation).await;
+            };
+            k.csrk = Some(csrk);
+        }
+        Ok(id)
+    }
+
+    /// Sends a `PairingFailed(InvalidParameters)` response when the received
+    /// command didn't match code `c`.
+    pub async fn expecting<R>(&mut self, c: Code) -> Result<R> {
+        error!("Command mismatch while waiting for {c}");
+        self.fail(Reason::InvalidParameters).await
+    }
+
+    /// Sends a `PairingFailed` command over the channel to indicate a local
+    /// failure.
+    pub async fn fail<R>(&mut self, r: Reason) -> Result<R> {
+        if let Err(e) = self.send(Command::PairingFailed(r)).await {
+            error!("Error sending PairingFailed response: {e}");
+        }
+        Err(Error::Local(r))
+    }
+
+    /// Sends a command over the channel.
+    pub async fn send(&mut self, cmd: Command) -> Result<()> {
+        let mut pdu = self.0.alloc();
+        cmd.pack(&mut pdu);
+        Ok(self.0.send(pdu).await?)
+    }
+
+    /// Decodes a received command, converting `PairingFailed` into an error.
+    async fn parse(&mut self, pdu: l2cap::Payload) -> Result<Command> {
+        match Command::try_from(pdu) {
+            Ok(Command::PairingFailed(r)) => {
+                error!("Remote error: {r}");
+                Err(Error::Remote(r))
+            }
+            Ok(cmd) => Ok(cmd),
+            Err(r) => self.fail(r).await,
+        }
+    }
+}
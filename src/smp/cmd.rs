@@ -1,7 +1,7 @@
 use structbuf::{Pack, Packer, Unpack, Unpacker};
 use tracing::{error, trace};
 
-use burble_crypto::{Check, Codec, Confirm, Nonce, PublicKey};
+use burble_crypto::{Check, Codec, Confirm, Nonce, PublicKey, CSRK, IRK};
 
 use crate::l2cap::Payload;
 use crate::le;
@@ -9,7 +9,7 @@ use crate::le;
 use super::*;
 
 /// SMP command ([Vol 3] Part H, Section 3.3).
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug)]
 pub(super) enum Command {
     PairingRequest(PairingParams),
     PairingResponse(PairingParams),
@@ -18,9 +18,9 @@ pub(super) enum Command {
     PairingFailed(Reason),
     EncryptionInformation(), // LE legacy pairing only
     CentralIdentification(), // LE legacy pairing only
-    IdentityInformation(),   // TODO
+    IdentityInformation(IRK),
     IdentityAddressInformation(le::Addr),
-    SigningInformation(), // TODO
+    SigningInformation(CSRK),
     SecurityRequest(AuthReq),
     PairingPublicKey(PublicKey),
     PairingDhKeyCheck(Check),
@@ -40,9 +40,9 @@ impl Command {
             EncryptionInformation() | CentralIdentification() => {
                 unimplemented!("legacy pairing command")
             }
-            IdentityInformation() => unimplemented!(),
+            IdentityInformation(ref v) => v.pack(p.u8(Code::IdentityInformation)),
             IdentityAddressInformation(ref v) => v.pack(p.u8(Code::IdentityAddressInformation)),
-            SigningInformation() => unimplemented!(),
+            SigningInformation(ref v) => v.pack(p.u8(Code::SigningInformation)),
             SecurityRequest(v) => p.u8(Code::SecurityRequest).u8(v.bits()).into(),
             PairingPublicKey(ref v) => v.pack(p.u8(Code::PairingPublicKey)),
             PairingDhKeyCheck(ref v) => v.pack(p.u8(Code::PairingDhKeyCheck)),
@@ -74,11 +74,11 @@ impl TryFrom<Payload> for Command {
             Code::PairingFailed => Reason::try_from(p.u8()).ok().map(Self::PairingFailed),
             Code::EncryptionInformation => Some(Self::EncryptionInformation()),
             Code::CentralIdentification => Some(Self::CentralIdentification()),
-            Code::IdentityInformation => Some(Self::IdentityInformation()),
+            Code::IdentityInformation => IRK::unpack(p).map(Self::IdentityInformation),
             Code::IdentityAddressInformation => {
                 le::Addr::unpack(p).map(Self::IdentityAddressInformation)
             }
-            Code::SigningInformation => Some(Self::SigningInformation()),
+            Code::SigningInformation => CSRK::unpack(p).map(Self::SigningInformation),
             Code::SecurityRequest => {
                 Some(Self::SecurityRequest(AuthReq::from_bits_truncate(p.u8())))
             }
@@ -124,6 +124,21 @@ pub(super) struct PairingParams {
 impl PairingParams {
     /// Minimum allowed key length (128-bit).
     pub const MIN_KEY_LEN: u8 = 16;
+
+    /// Returns the Pairing Request or Response command with code `c` in the
+    /// over-the-air byte order, as used by the LE legacy pairing confirm value
+    /// generation function ([Vol 3] Part H, Section 2.2.3).
+    pub fn to_cmd_bytes(&self, c: Code) -> [u8; 7] {
+        [
+            c.into(),
+            self.io_cap.into(),
+            u8::from(self.oob_data),
+            self.auth_req.bits(),
+            self.max_key_len,
+            self.initiator_keys.bits(),
+            self.responder_keys.bits(),
+        ]
+    }
 }
 
 impl Default for PairingParams {
@@ -58,6 +58,19 @@ impl KeyGenMethod {
     pub const fn resolve(a: IoCap, b: IoCap) -> Self {
         Self::MAP[a as usize][b as usize]
     }
+
+    /// Returns key generation method for LE legacy pairing, which does not
+    /// support Numeric Comparison ([Vol 3] Part H, Section 2.3.5.1, Table 2.8).
+    #[inline]
+    pub const fn resolve_legacy(a: IoCap, b: IoCap) -> Self {
+        match Self::resolve(a, b) {
+            Self::NumCompare if matches!((a, b), (IoCap::DisplayYesNo, IoCap::DisplayYesNo)) => {
+                Self::JustWorks
+            }
+            Self::NumCompare => Self::PasskeyEntry,
+            m => m,
+        }
+    }
 }
 
 /// Command code ([Vol 3] Part H, Section 3.3).
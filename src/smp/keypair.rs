@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use burble_crypto::{PublicKey, SecretKey};
+
+use crate::SyncMutex;
+
+/// P-256 key pair used for LE Secure Connections public key exchange
+/// ([Vol 3] Part H, Section 2.3.5.6.1).
+#[derive(Debug)]
+#[must_use]
+pub struct KeyPair {
+    sk: SecretKey,
+    pk: PublicKey,
+}
+
+impl KeyPair {
+    /// Generates a new random key pair.
+    #[allow(clippy::new_without_default)]
+    #[inline]
+    pub fn new() -> Self {
+        let sk = SecretKey::new();
+        let pk = sk.public_key();
+        Self { sk, pk }
+    }
+
+    /// Returns the public key.
+    #[inline(always)]
+    pub const fn public_key(&self) -> PublicKey {
+        self.pk
+    }
+
+    /// Returns the secret key.
+    #[inline(always)]
+    pub(super) const fn secret_key(&self) -> &SecretKey {
+        &self.sk
+    }
+}
+
+/// Shared key pair cache that limits how long a key pair is used for pairing.
+/// The key pair is regenerated after it was used for `max_uses` pairing
+/// procedures or after `max_age` has elapsed since it was generated, whichever
+/// happens first.
+///
+/// Each pairing procedure obtains its key pair once via [`Self::get`] and keeps
+/// it until the procedure is finished. Regeneration only affects subsequent
+/// calls, so concurrent procedures on different connections always use a
+/// consistent key pair.
+#[derive(Debug)]
+pub struct KeyPairCache {
+    max_uses: u32,
+    max_age: Duration,
+    state: SyncMutex<State>,
+}
+
+impl KeyPairCache {
+    /// Creates a new key pair cache. A `max_uses` value of 0 or 1 generates a
+    /// new key pair for every pairing procedure.
+    #[must_use]
+    pub fn new(max_uses: u32, max_age: Duration) -> Self {
+        Self {
+            max_uses: max_uses.max(1),
+            max_age,
+            state: SyncMutex::new(State::new()),
+        }
+    }
+
+    /// Returns the key pair for a new pairing procedure, regenerating it first
+    /// if the current key pair has expired.
+    pub fn get(&self) -> Arc<KeyPair> {
+        let mut st = self.state.lock();
+        if st.uses >= self.max_uses || st.created.elapsed() >= self.max_age {
+            debug!("Regenerating pairing key pair after {} use(s)", st.uses);
+            let regens = st.regens + 1;
+            *st = State::new();
+            st.regens = regens;
+        }
+        st.uses += 1;
+        Arc::clone(&st.kp)
+    }
+
+    /// Returns the number of times the key pair was regenerated.
+    #[inline]
+    #[must_use]
+    pub fn regenerations(&self) -> u64 {
+        self.state.lock().regens
+    }
+}
+
+impl Default for KeyPairCache {
+    /// Creates a cache that generates a new key pair for every pairing.
+    #[inline]
+    fn default() -> Self {
+        Self::new(1, Duration::MAX)
+    }
+}
+
+/// Current key pair and usage statistics.
+#[derive(Debug)]
+struct State {
+    kp: Arc<KeyPair>,
+    created: Instant,
+    uses: u32,
+    regens: u64,
+}
+
+impl State {
+    /// Creates a new state with a new key pair.
+    #[inline]
+    fn new() -> Self {
+        Self {
+            kp: Arc::new(KeyPair::new()),
+            created: Instant::now(),
+            uses: 0,
+            regens: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_uses() {
+        let c = KeyPairCache::new(2, Duration::MAX);
+        let (a, b) = (c.get(), c.get());
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(c.regenerations(), 0);
+
+        // Procedures in progress keep their key pair
+        let pk = a.public_key();
+        let d = c.get();
+        assert!(!Arc::ptr_eq(&a, &d));
+        assert_ne!(d.public_key(), pk);
+        assert_eq!(a.public_key(), pk);
+        assert_eq!(c.regenerations(), 1);
+
+        let c = KeyPairCache::default();
+        assert!(!Arc::ptr_eq(&c.get(), &c.get()));
+        assert_eq!(c.regenerations(), 1);
+    }
+
+    #[test]
+    fn max_age() {
+        let c = KeyPairCache::new(u32::MAX, Duration::ZERO);
+        let a = c.get();
+        assert!(!Arc::ptr_eq(&a, &c.get()));
+        assert_eq!(c.regenerations(), 2);
+    }
+}
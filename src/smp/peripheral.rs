@@ -1,8 +1,6 @@
-use std::time::Duration;
+use tracing::{error, Instrument};
 
-use tracing::error;
-
-use burble_crypto::{Nonce, PublicKeyX, SecretKey, LTK};
+use burble_crypto::{PublicKeyX, LTK, TK};
 
 use crate::hci::Role;
 use crate::l2cap::Chan;
@@ -15,7 +13,7 @@ use super::*;
 /// mode ([Vol 3] Part C, Section 10.2.1 and 10.2.4).
 #[derive(Debug)]
 pub struct Peripheral {
-    ch: Chan,
+    ch: SmpChan,
 }
 
 impl Peripheral {
@@ -23,45 +21,60 @@ impl Peripheral {
     #[inline(always)]
     pub(crate) fn new(ch: Chan) -> Self {
         assert_eq!(ch.conn().borrow().role, Role::Peripheral);
-        Self { ch }
+        Self {
+            ch: SmpChan::new(ch),
+        }
     }
 
     /// Handles responder pairing role. This method is not cancel safe.
     pub async fn respond(&mut self, dev: &mut Device, store: &KeyStore) -> Result<()> {
+        let span = self.ch.pairing_span();
+        self.pair(dev, store).instrument(span).await
+    }
+
+    /// Performs all pairing phases as the responder.
+    async fn pair(&mut self, dev: &mut Device, store: &KeyStore) -> Result<()> {
         // TODO: Return a cancellable task?
-        let init = match Command::try_from(self.ch.recv().await?) {
-            Ok(Command::PairingRequest(init)) => init,
-            Ok(Command::PairingFailed(r)) => {
-                error!("Remote error instead of PairingRequest: {r}");
-                return Err(Error::Remote(r));
-            }
-            Ok(cmd) => {
+        let init = match self.ch.next().await? {
+            Command::PairingRequest(init) => init,
+            cmd => {
                 error!("Unexpected command instead of PairingRequest: {cmd:?}");
-                return self.fail(Reason::InvalidParameters).await;
+                return self.ch.fail(Reason::InvalidParameters).await;
             }
-            Err(reason) => return self.fail(reason).await,
         };
-        let Phase1 { a, b, method, sec } = self.phase1(dev, init).await?;
-        let (peer, ltk) = self.phase2(dev, method, a.into(), b.into()).await?;
+        let Phase1 {
+            a,
+            b,
+            method,
+            sec,
+            legacy,
+        } = self.phase1(dev, init).await?;
+        let (peer, ltk) = if legacy {
+            let preq = a.to_cmd_bytes(Code::PairingRequest);
+            (self.legacy_phase2(dev, &preq, &b.to_cmd_bytes(Code::PairingResponse))).await?
+        } else {
+            self.phase2(dev, method, a.into(), b.into()).await?
+        };
         let mut keys = Keys::new(sec, ltk);
-        // TODO: Save LTK before phase 3 to ensure that SecDb finds it
-        (self.phase3(b.initiator_keys, b.responder_keys, &mut keys)).await?;
-        if !store.save(peer, &keys) {
-            return Err(Error::Io(io::Error::new(
-                io::ErrorKind::Other,
-                "failed to save peer keys",
-            )));
+        // Save the LTK before phase 3 to ensure that SecDb finds it when the
+        // Central starts encryption
+        save(store, peer, &keys)?;
+        if b.initiator_keys.union(b.responder_keys).is_empty() {
+            return Ok(());
         }
+        let id = (self.phase3(dev, b.initiator_keys, b.responder_keys, &mut keys)).await?;
+        save_identity(store, peer, id, &keys)?;
         Ok(())
     }
 
     /// Performs Pairing Feature Exchange phase
     /// ([Vol 3] Part H, Section 2.3.5.1 and C.1).
     async fn phase1(&mut self, dev: &Device, a: PairingParams) -> Result<Phase1> {
-        if !a.auth_req.contains(AuthReq::SC) {
+        let legacy = !a.auth_req.contains(AuthReq::SC);
+        if legacy && !dev.legacy {
             // [Vol 3] Part H, Section 2.3 and C.5.1
             error!("Peer does not support LE Secure Connections");
-            return self.fail(Reason::PairingNotSupported).await;
+            return self.ch.fail(Reason::PairingNotSupported).await;
         }
         if a.max_key_len < PairingParams::MIN_KEY_LEN {
             // [Vol 3] Part H, Section C.5.3
@@ -70,34 +83,49 @@ impl Peripheral {
                 PairingParams::MIN_KEY_LEN * 8,
                 a.max_key_len * 8
             );
-            return self.fail(Reason::EncryptionKeySize).await;
+            return self.ch.fail(Reason::EncryptionKeySize).await;
         }
         let mut b = PairingParams {
             io_cap: dev.io_cap(),
             ..PairingParams::default()
         };
-        (b.auth_req).set(AuthReq::BONDING, a.auth_req.contains(AuthReq::BONDING));
+        // Legacy pairing would require LTK distribution to create a bond
+        (b.auth_req).set(
+            AuthReq::BONDING,
+            !legacy && a.auth_req.contains(AuthReq::BONDING),
+        );
         (b.auth_req).set(AuthReq::MITM, !matches!(b.io_cap, IoCap::NoInputNoOutput));
+        if b.auth_req.contains(AuthReq::BONDING) {
+            // [Vol 3] Part H, Section 3.6.1
+            b.initiator_keys = a.initiator_keys & (KeyDist::ID | KeyDist::SIGN);
+            b.responder_keys = a.responder_keys & dev.key_dist();
+        }
         // [Vol 3] Part H, Section 2.3.5.1, Table 2.7
         if a.oob_data | b.oob_data {
             error!("OOB pairing method not implemented"); // TODO: Implement
-            return self.fail(Reason::OobNotAvailable).await;
+            return self.ch.fail(Reason::OobNotAvailable).await;
         }
         // [Vol 3] Part H, Section 2.3.5.1, Table 2.8
-        let method = if a.auth_req.union(b.auth_req).contains(AuthReq::MITM) {
-            KeyGenMethod::resolve(a.io_cap, b.io_cap)
-        } else {
+        let method = if !a.auth_req.union(b.auth_req).contains(AuthReq::MITM) {
             KeyGenMethod::JustWorks
+        } else if legacy {
+            KeyGenMethod::resolve_legacy(a.io_cap, b.io_cap)
+        } else {
+            KeyGenMethod::resolve(a.io_cap, b.io_cap)
         };
+        if legacy && !matches!(method, KeyGenMethod::JustWorks) {
+            error!("LE legacy pairing only supports the Just Works association model");
+            return self.ch.fail(Reason::AuthenticationRequirements).await;
+        }
         // TODO: Out-of-band may be unauthenticated
         // ([Vol 3] Part H, Section 2.3.5.1)
         let authn = !matches!(method, KeyGenMethod::JustWorks);
         // TODO: Compile-time option to allow Just Works?
         if b.auth_req.contains(AuthReq::MITM) && !authn {
             error!("Just Works association model selected, but MITM protection is required");
-            return self.fail(Reason::AuthenticationRequirements).await;
+            return self.ch.fail(Reason::AuthenticationRequirements).await;
         }
-        self.send(Command::PairingResponse(b)).await?;
+        self.ch.send(Command::PairingResponse(b)).await?;
         // [Vol 3] Part H, Section 2.3.4
         let mut sec = hci::ConnSec::key_len(a.max_key_len.min(b.max_key_len) * 8);
         if authn {
@@ -106,7 +134,13 @@ impl Peripheral {
         if b.auth_req.contains(AuthReq::BONDING) {
             sec.insert(hci::ConnSec::BOND);
         }
-        Ok(Phase1 { a, b, method, sec })
+        Ok(Phase1 {
+            a,
+            b,
+            method,
+            sec,
+            legacy,
+        })
     }
 
     /// Performs LE Secure Connections Long Term Key (LTK) Generation phase
@@ -119,17 +153,21 @@ impl Peripheral {
         iob: burble_crypto::IoCap,
     ) -> Result<(le::Addr, LTK)> {
         // Public key exchange ([Vol 3] Part H, Section 2.3.5.6.1 and C.2.2.1)
-        let skb = SecretKey::new();
-        let pkb = skb.public_key();
-        let Command::PairingPublicKey(pka) = self.recv().await? else {
-            return self.expecting(Code::PairingPublicKey).await;
+        let kp = dev.key_pair();
+        let (skb, pkb) = (kp.secret_key(), kp.public_key());
+        let Command::PairingPublicKey(pka) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingPublicKey).await;
         };
 
         // Send the local public key before validating the remote key to allow
         // parallel computation of DHKey. No security risk in doing so.
-        self.send(Command::PairingPublicKey(pkb)).await?;
+        self.ch.send(Command::PairingPublicKey(pkb)).await?;
+        if !pka.validate() || pka == pkb {
+            error!("Invalid or reflected peer public key");
+            return self.ch.fail(Reason::DhKeyCheckFailed).await;
+        }
         let Some(dh_key) = skb.dh_key(pka) else {
-            return self.fail(Reason::InvalidParameters).await; // Invalid or debug key
+            return self.ch.fail(Reason::InvalidParameters).await; // Debug key
         };
 
         // Authentication stage 1 ([Vol 3] Part H, Section C.2.2.2)
@@ -150,22 +188,59 @@ impl Peripheral {
             };
             if local_addr.is_zero() {
                 error!("Pairing failed because local address is unknown");
-                return self.fail(Reason::UnspecifiedReason).await;
+                return self.ch.fail(Reason::UnspecifiedReason).await;
             }
             (peer_addr, peer_addr.into(), local_addr.into())
         };
         let (mac_key, ltk) = dh_key.f5(na, nb, a, b);
         let eb = mac_key.f6(nb, na, ra, iob, b, a);
-        let Command::PairingDhKeyCheck(ea) = self.recv().await? else {
-            return self.expecting(Code::PairingDhKeyCheck).await;
+        let Command::PairingDhKeyCheck(ea) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingDhKeyCheck).await;
         };
         if ea != mac_key.f6(na, nb, rb, ioa, a, b) {
-            return self.fail(Reason::DhKeyCheckFailed).await;
+            return self.ch.fail(Reason::DhKeyCheckFailed).await;
         }
-        self.send(Command::PairingDhKeyCheck(eb)).await?;
+        self.ch.send(Command::PairingDhKeyCheck(eb)).await?;
         Ok((peer, ltk))
     }
 
+    /// Performs LE legacy pairing Short Term Key (STK) Generation phase using
+    /// the Just Works association model ([Vol 3] Part H, Section 2.3.5.5 and
+    /// C.2.1). `preq` and `pres` are the exchanged pairing commands.
+    async fn legacy_phase2(
+        &mut self,
+        dev: &Device,
+        preq: &[u8; 7],
+        pres: &[u8; 7],
+    ) -> Result<(le::Addr, LTK)> {
+        let (peer, ia, ra) = {
+            let (peer_addr, local_addr) = {
+                let cn = self.ch.conn().borrow();
+                (cn.peer_addr, cn.local_addr)
+            };
+            if local_addr.is_zero() {
+                error!("Pairing failed because local address is unknown");
+                return self.ch.fail(Reason::UnspecifiedReason).await;
+            }
+            (peer_addr, peer_addr.into(), local_addr.into())
+        };
+        let tk = TK::new(0);
+        let Command::PairingConfirm(mconfirm) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingConfirm).await;
+        };
+        let srand = dev.nonce().await?;
+        let sconfirm = tk.c1(&srand, preq, pres, ia, ra);
+        self.ch.send(Command::PairingConfirm(sconfirm)).await?;
+        let Command::PairingRandom(mrand) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingRandom).await;
+        };
+        if mconfirm != tk.c1(&mrand, preq, pres, ia, ra) {
+            return self.ch.fail(Reason::ConfirmValueFailed).await;
+        }
+        self.ch.send(Command::PairingRandom(srand)).await?;
+        Ok((peer, tk.s1(&srand, &mrand)))
+    }
+
     /// Implements Authentication stage 1 – Just Works or Numeric Comparison
     /// ([Vol 3] Part H, Section 2.3.5.6.2 and C.2.2.2.1).
     async fn authn1_num_compare(
@@ -175,105 +250,42 @@ impl Peripheral {
         pka: &PublicKeyX,
         pkb: &PublicKeyX,
     ) -> Result<Authn1> {
-        let nb = Nonce::new();
+        let nb = dev.nonce().await?;
         let (rb, ra) = (0, 0);
         let cb = nb.f4(pkb, pka, 0);
         // SUBTLE: The order of these send/recv ops is important. See last
         // paragraph of Section 2.3.5.6.2.
-        self.send(Command::PairingConfirm(cb)).await?;
-        let Command::PairingRandom(na) = self.recv().await? else {
-            return self.expecting(Code::PairingRandom).await;
+        self.ch.send(Command::PairingConfirm(cb)).await?;
+        let Command::PairingRandom(na) = self.ch.recv().await? else {
+            return self.ch.expecting(Code::PairingRandom).await;
         };
-        self.send(Command::PairingRandom(nb)).await?;
+        self.ch.send(Command::PairingRandom(nb)).await?;
         if matches!(method, KeyGenMethod::JustWorks) {
             return Ok(Authn1 { na, nb, ra, rb });
         }
         let vb = na.g2(pka, pkb, &nb);
-        let display = dev.display.as_mut().expect("display not available");
-        let yes_no = dev.confirm.as_mut().expect("input not available");
+        let peer = self.ch.conn().borrow().peer_addr;
         // TODO: Abort if PairingFailed is received while waiting for the user
-        if !display.show(vb).await || !yes_no.confirm().await {
+        if !dev.num_compare(peer, vb).await {
             // [Vol 3] Part H, Section C.2.2.2.4
-            return self.fail(Reason::NumericComparisonFailed).await;
+            return self.ch.fail(Reason::NumericComparisonFailed).await;
         }
         Ok(Authn1 { na, nb, ra, rb })
     }
 
-    /// Performs Transport Specific Key Distribution phase
-    /// ([Vol 3] Part H, Section 3.6.1 and C.3).
-    async fn phase3(&mut self, a: KeyDist, b: KeyDist, _k: &mut Keys) -> Result<()> {
-        if !b.is_empty() {
-            // TODO: IRK
-            // TODO: BD_ADDR
-            // TODO: CSRK
-            return self.fail(Reason::UnspecifiedReason).await;
-        }
-        if !a.is_empty() {
-            // TODO: IRK
-            // TODO: BD_ADDR
-            // TODO: CSRK
-            return self.fail(Reason::KeyRejected).await;
-        }
-        Ok(())
-    }
-
-    /// Returns the next command.
-    async fn recv(&mut self) -> Result<Command> {
-        // [Vol 3] Part H, Section 3.4
-        let pdu = match tokio::time::timeout(Duration::from_secs(30), self.ch.recv()).await {
-            Ok(Ok(pdu)) => pdu,
-            Ok(Err(e)) => return Err(e.into()),
-            Err(_) => return Err(Error::Timeout), // TODO: Mark channel as unusable
-        };
-        match Command::try_from(pdu) {
-            Ok(Command::PairingFailed(r)) => {
-                error!("Remote error: {r}");
-                Err(Error::Remote(r))
-            }
-            Ok(cmd) => Ok(cmd),
-            Err(r) => self.fail(r).await,
-        }
-    }
-
-    /// Sends a `PairingFailed(InvalidParameters)` response when the received
-    /// command didn't match code `c`.
-    async fn expecting<R>(&mut self, c: Code) -> Result<R> {
-        error!("Command mismatch while waiting for {c}");
-        self.fail(Reason::InvalidParameters).await
-    }
-
-    /// Sends a `PairingFailed` command over the channel to indicate a local
-    /// failure.
-    async fn fail<R>(&mut self, r: Reason) -> Result<R> {
-        if let Err(e) = self.send(Command::PairingFailed(r)).await {
-            error!("Error sending PairingFailed response: {e}");
-        }
-        Err(Error::Local(r))
-    }
-
-    /// Sends a command over the channel.
-    async fn send(&mut self, cmd: Command) -> Result<()> {
-        let mut pdu = self.ch.alloc();
-        cmd.pack(&mut pdu);
-        Ok(self.ch.send(pdu).await?)
+    /// Performs Transport Specific Key Distribution phase after the Central
+    /// encrypts the link ([Vol 3] Part H, Section 3.6.1 and C.3). The
+    /// Central distributes the keys in `a` after receiving the keys in `b`.
+    /// Returns the Central's identity address, if distributed.
+    async fn phase3(
+        &mut self,
+        dev: &Device,
+        a: KeyDist,
+        b: KeyDist,
+        k: &mut Keys,
+    ) -> Result<Option<le::Addr>> {
+        self.ch.encrypted().await?;
+        self.ch.send_keys(dev, b).await?;
+        self.ch.recv_keys(a, k).await
     }
 }
-
-/// Output of phase 1.
-#[derive(Clone, Copy)]
-#[must_use]
-struct Phase1 {
-    a: PairingParams,
-    b: PairingParams,
-    method: KeyGenMethod,
-    sec: hci::ConnSec,
-}
-
-/// Output of phase 2, authentication stage 1.
-#[must_use]
-struct Authn1 {
-    na: Nonce,
-    nb: Nonce,
-    ra: u128,
-    rb: u128,
-}
@@ -4,32 +4,51 @@ use std::mem;
 use std::num::NonZeroU128;
 use std::sync::Arc;
 
+use structbuf::Unpacker;
 use tracing::{debug, error, info, warn};
 
-use burble_crypto::LTK;
+use burble_crypto::{Codec, CSRK, IRK, LTK};
 
-use crate::hci;
+use crate::{hci, le};
 
 /// Interface to persistent security database storage.
 pub type KeyStore = dyn crate::PeerStore<Value = Keys>;
 
-/// Security keys for a peer device.
+/// Security keys for a peer device, which are the bond record containing
+/// everything required to restore a trusted relationship
+/// ([Vol 3] Part C, Section 9.4.4). Records are versioned so that keys saved
+/// by older versions are migrated on load instead of being discarded. Records
+/// from newer versions are loaded as unsupported keys, which can't be used,
+/// but are not removed from the store.
 #[derive(Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 #[must_use]
-#[serde(deny_unknown_fields)]
+#[serde(try_from = "Record")]
 pub struct Keys {
+    pub(super) version: u8,
     pub(super) sec: hci::ConnSec,
     pub(super) id: Option<BondId>,
     pub(super) ltk: LTK,
+    pub(super) csrk: Option<CSRK>,
+    pub(super) irk: Option<IRK>,
 }
 
 impl Keys {
+    /// Current record format version.
+    pub const VERSION: u8 = 2;
+
     /// Creates a new key set.
     #[doc(hidden)]
     #[inline(always)]
     pub(super) fn new(sec: hci::ConnSec, ltk: LTK) -> Self {
         let id = (sec.contains(hci::ConnSec::BOND)).then(|| BondId::new(sec, &ltk));
-        Self { sec, id, ltk }
+        Self {
+            version: Self::VERSION,
+            sec,
+            id,
+            ltk,
+            csrk: None,
+            irk: None,
+        }
     }
 
     /// Returns unit test keys.
@@ -41,12 +60,206 @@ impl Keys {
         )
     }
 
+    /// Returns the record format version.
+    #[inline(always)]
+    #[must_use]
+    pub const fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns whether the record format is supported. Unsupported keys were
+    /// saved by a newer version and can't be used.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_supported(&self) -> bool {
+        self.version <= Self::VERSION
+    }
+
+    /// Returns the Long Term Key.
+    #[inline(always)]
+    #[must_use]
+    pub(crate) const fn ltk(&self) -> &LTK {
+        &self.ltk
+    }
+
+    /// Returns the peer's Connection Signature Resolving Key, if distributed.
+    #[inline(always)]
+    #[must_use]
+    pub const fn csrk(&self) -> Option<&CSRK> {
+        self.csrk.as_ref()
+    }
+
+    /// Returns the peer's Identity Resolving Key, if distributed. The keys are
+    /// stored under the peer's identity address when an IRK is present.
+    #[inline(always)]
+    #[must_use]
+    pub const fn irk(&self) -> Option<&IRK> {
+        self.irk.as_ref()
+    }
+
+    /// Returns the encryption key length in bits.
+    #[inline(always)]
+    #[must_use]
+    pub const fn key_len(&self) -> u8 {
+        self.sec.intersection(hci::ConnSec::KEY_LEN).bits()
+    }
+
+    /// Returns whether the keys were generated using an authenticated pairing
+    /// method.
+    #[inline(always)]
+    #[must_use]
+    pub const fn is_authenticated(&self) -> bool {
+        self.sec.contains(hci::ConnSec::AUTHN)
+    }
+
+    /// Encodes the keys as a byte vector for stores that don't use serde. The
+    /// encoding is stable for a given [`Self::VERSION`].
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut f = Fields::empty();
+        f.set(Fields::IRK, self.irk.is_some());
+        f.set(Fields::CSRK, self.csrk.is_some());
+        let mut b = Vec::with_capacity(3 + 3 * 16);
+        b.extend([self.version, self.sec.bits(), f.bits()]);
+        b.extend(self.ltk.to_le_bytes());
+        if let Some(ref k) = self.irk {
+            b.extend(k.to_le_bytes());
+        }
+        if let Some(ref k) = self.csrk {
+            b.extend(k.to_le_bytes());
+        }
+        b
+    }
+
+    /// Decodes keys encoded by [`Self::to_bytes`]. Returns [`None`] if the
+    /// encoding is invalid. Keys encoded by a newer version are decoded as
+    /// unsupported keys.
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        if let Some(&version) = b.first().filter(|&&v| v > Self::VERSION) {
+            return Some(Self::unsupported(version));
+        }
+        let v = Unpacker::new(b).map(|p| {
+            let (version, sec, f) = (p.u8(), p.u8(), p.u8());
+            if version != Self::VERSION {
+                return None;
+            }
+            let sec = hci::ConnSec::from_bits(sec)?;
+            let f = Fields::from_bits(f)?;
+            let mut k = Self::new(sec, LTK::from_le_bytes(p.bytes()));
+            k.irk = (f.contains(Fields::IRK)).then(|| IRK::from_le_bytes(p.bytes()));
+            k.csrk = (f.contains(Fields::CSRK)).then(|| CSRK::from_le_bytes(p.bytes()));
+            Some(k)
+        });
+        v.flatten()
+    }
+
+    /// Returns whether the keys belong to a usable bond.
+    #[inline]
+    #[must_use]
+    pub(crate) fn is_bond(&self) -> bool {
+        self.is_supported() && self.id.is_some() && self.is_valid()
+    }
+
     /// Returns whether the keys are valid by comparing bond ID.
     #[inline(always)]
     #[must_use]
     fn is_valid(&self) -> bool {
         (self.id).map_or(true, |id| id == BondId::new(self.sec, &self.ltk))
     }
+
+    /// Returns a placeholder for a record saved by a newer version.
+    fn unsupported(version: u8) -> Self {
+        Self {
+            version,
+            sec: hci::ConnSec::empty(),
+            id: None,
+            ltk: LTK::new(0),
+            csrk: None,
+            irk: None,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Optional keys present in the [`Keys`] byte encoding.
+    #[derive(Clone, Copy, Debug)]
+    #[repr(transparent)]
+    struct Fields: u8 {
+        const IRK = 1 << 0;
+        const CSRK = 1 << 1;
+    }
+}
+
+/// Saved [`Keys`] record in any known format.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Record {
+    V2(KeysV2),
+    V0(KeysV0),
+    Future { version: u8 },
+}
+
+/// Version 2 record, which added the IRK. Version 1 records, which added the
+/// version and CSRK, use the same format without the IRK.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysV2 {
+    version: u8,
+    sec: hci::ConnSec,
+    id: Option<BondId>,
+    ltk: LTK,
+    csrk: Option<CSRK>,
+    irk: Option<IRK>,
+}
+
+/// Unversioned record.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysV0 {
+    sec: hci::ConnSec,
+    id: Option<BondId>,
+    ltk: LTK,
+}
+
+impl KeysV0 {
+    /// Migrates the record to version 2.
+    fn migrate(self) -> KeysV2 {
+        KeysV2 {
+            version: 2,
+            sec: self.sec,
+            id: self.id,
+            ltk: self.ltk,
+            csrk: None,
+            irk: None,
+        }
+    }
+}
+
+impl TryFrom<Record> for Keys {
+    type Error = String;
+
+    fn try_from(r: Record) -> Result<Self, Self::Error> {
+        let k = match r {
+            Record::V2(k) if k.version == 2 || (k.version == 1 && k.irk.is_none()) => k,
+            Record::V0(k) => k.migrate(),
+            Record::V2(KeysV2 { version, .. }) | Record::Future { version }
+                if version > Self::VERSION =>
+            {
+                return Ok(Self::unsupported(version));
+            }
+            Record::V2(KeysV2 { version, .. }) | Record::Future { version } => {
+                return Err(format!("invalid keys record version {version}"));
+            }
+        };
+        Ok(Self {
+            version: Self::VERSION,
+            sec: k.sec,
+            id: k.id,
+            ltk: k.ltk,
+            csrk: k.csrk,
+            irk: k.irk,
+        })
+    }
 }
 
 /// Bond ID derived from the connection security properties and the LTK.
@@ -75,6 +288,46 @@ impl BondId {
     }
 }
 
+/// Read-only view of the bonded peer Connection Signature Resolving Keys in a
+/// [`KeyStore`], which provides keys for verifying signed writes via
+/// [`crate::gatt::ServerBuilder::with_signing_key_store`]. Keys are only saved by the
+/// pairing procedure, so modifications through this interface are ignored.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct SigningKeyStore(Arc<KeyStore>);
+
+impl SigningKeyStore {
+    /// Creates a view of the signing keys in `store`.
+    #[inline(always)]
+    #[must_use]
+    pub fn new(store: Arc<KeyStore>) -> Self {
+        Self(store)
+    }
+}
+
+impl crate::PeerStore for SigningKeyStore {
+    type Value = CSRK;
+
+    fn save(&self, _: le::Addr, _: &Self::Value) -> bool {
+        false
+    }
+
+    fn load(&self, peer: le::Addr) -> Option<Self::Value> {
+        let k = (self.0.load(peer)).filter(Keys::is_bond);
+        k.and_then(|k| k.csrk)
+    }
+
+    fn remove(&self, _: le::Addr) {}
+
+    fn clear(&self) {}
+
+    fn peers(&self) -> Vec<le::Addr> {
+        let mut v = self.0.peers();
+        v.retain(|&peer| self.load(peer).is_some());
+        v
+    }
+}
+
 /// Security database that stores encryption (LTK), identity (IRK), and signing
 /// (CSRK) keys.
 #[derive(Debug)]
@@ -82,6 +335,7 @@ pub struct SecDb {
     host: hci::Host,
     store: Arc<KeyStore>,
     sec: BTreeMap<hci::ConnHandle, hci::ConnSec>,
+    local_irk: Option<IRK>,
 }
 
 impl SecDb {
@@ -92,14 +346,27 @@ impl SecDb {
             host,
             store,
             sec: BTreeMap::new(),
+            local_irk: None,
         }
     }
 
+    /// Loads the controller's resolving list when the event loop starts. See
+    /// [`Self::load_resolving_list`].
+    #[inline(always)]
+    #[must_use]
+    pub fn with_resolving_list(mut self, local: IRK) -> Self {
+        self.local_irk = Some(local);
+        self
+    }
+
     /// Handles security database events until an error is encountered. This
     /// method is not cancel safe.
     pub async fn event_loop(&mut self) -> hci::Result<()> {
         use hci::EventCode::*;
         let mut ctl = self.host.events();
+        if let Some(ref irk) = self.local_irk {
+            self.load_resolving_list(irk).await?;
+        }
         loop {
             let req = loop {
                 let evt = ctl.next().await?;
@@ -126,6 +393,24 @@ impl SecDb {
         }
     }
 
+    /// Replaces the contents of the controller's resolving list with all bonded
+    /// peers that distributed an IRK, allowing the controller to resolve their
+    /// Resolvable Private Addresses to identity addresses
+    /// ([Vol 6] Part B, Section 4.7). `local` is the IRK used to generate local
+    /// Resolvable Private Addresses. Address resolution must be enabled
+    /// separately with [`hci::Host::le_set_address_resolution_enable`].
+    pub async fn load_resolving_list(&self, local: &IRK) -> hci::Result<()> {
+        self.host.le_clear_resolving_list().await?;
+        for peer in self.store.peers() {
+            let k = (self.store.load(peer)).filter(Keys::is_bond);
+            if let Some(irk) = k.and_then(|k| k.irk) {
+                debug!("Adding {peer} to the resolving list");
+                (self.host.le_add_device_to_resolving_list(peer, &irk, local)).await?;
+            }
+        }
+        Ok(())
+    }
+
     /// Loads the keys for the specified connection handle and updates the
     /// connection bond ID.
     fn load_keys(&mut self, hdl: hci::ConnHandle) -> Option<Keys> {
@@ -134,6 +419,12 @@ impl SecDb {
             return None;
         };
         match self.store.load(peer) {
+            Some(k) if !k.is_supported() => {
+                warn!(
+                    "Ignoring keys for {peer} {hdl} with unsupported version {}",
+                    k.version
+                );
+            }
             Some(k) if k.is_valid() => {
                 if k.id.is_some() {
                     debug!("Found keys for {peer} {hdl}");
@@ -173,13 +464,24 @@ impl SecDb {
 
     /// Handles [`hci::EncryptionChange`] event.
     fn handle_encryption_change(&mut self, e: hci::EncryptionChange) {
-        let Some(peer) = self.host.conn(e.handle).map(|cn| cn.borrow().peer_addr) else { return };
+        let Some((peer, role)) = (self.host.conn(e.handle)).map(|cn| {
+            let cn = cn.borrow();
+            (cn.peer_addr, cn.role)
+        }) else {
+            return;
+        };
         if !e.status.is_ok() {
             warn!("Encryption change for {peer} failed: {}", e.status);
             return;
         }
+        let mut sec = self.sec.remove(&e.handle);
+        if sec.is_none() && e.enabled && role == hci::Role::Central {
+            // As the Central, encryption was started using keys from the store
+            // rather than in response to an LTK request.
+            sec = self.load_keys(e.handle).map(|k| k.sec);
+        }
         self.host.update_conn(e.handle, |cn| {
-            cn.sec = if let (true, Some(mut sec)) = (e.enabled, self.sec.remove(&e.handle)) {
+            cn.sec = if let (true, Some(mut sec)) = (e.enabled, sec) {
                 // TODO: Should the AUTHZ bit ever be kept?
                 sec.remove(hci::ConnSec::AUTHZ);
                 info!("Encryption enabled for {peer}: {sec}");
@@ -191,3 +493,112 @@ impl SecDb {
         });
     }
 }
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+
+    const SEC: &str = r#""sec": "AUTHN | BOND | 0x80""#;
+    const ID: &str = r#""id": "EFFBB63179C1C844B2B88CF1723E1A27""#;
+    const KEY: &str = r#""ltk": "0123456789ABCDEFFEDCBA9876543210""#;
+
+    fn keys() -> Keys {
+        let sec = hci::ConnSec::key_len(128) | hci::ConnSec::AUTHN | hci::ConnSec::BOND;
+        Keys::new(sec, LTK::new(0x0123456789ABCDEF_FEDCBA9876543210))
+    }
+
+    fn load(s: &str) -> serde_json::Result<Keys> {
+        serde_json::from_str(s)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut k = keys();
+        k.csrk = Some(CSRK::new(3));
+        k.irk = Some(IRK::new(4));
+        let v = load(&serde_json::to_string(&k).unwrap()).unwrap();
+        assert_eq!(v, k);
+    }
+
+    #[test]
+    fn unversioned() {
+        let k = load(&format!("{{{SEC}, {ID}, {KEY}}}")).unwrap();
+        assert_eq!(k, keys());
+        assert_eq!(k.version(), Keys::VERSION);
+        assert!(k.is_valid() && k.csrk().is_none());
+        assert!(load(&format!("{{{SEC}, {ID}, {KEY}, \"x\": 1}}")).is_err());
+    }
+
+    #[test]
+    fn v1() {
+        let k = load(&format!(
+            r#"{{"version": 1, {SEC}, {ID}, {KEY}, "csrk": "00000000000000000000000000000003"}}"#
+        ))
+        .unwrap();
+        assert_eq!(k.csrk(), Some(&CSRK::new(3)));
+        assert!(k.is_supported() && k.is_valid());
+        let k = load(&format!(r#"{{"version": 1, {SEC}, {ID}, {KEY}}}"#)).unwrap();
+        assert_eq!(k, keys());
+        assert_eq!(k.version(), Keys::VERSION);
+        let irk = r#""irk": "00000000000000000000000000000004""#;
+        assert!(load(&format!(r#"{{"version": 1, {SEC}, {ID}, {KEY}, {irk}}}"#)).is_err());
+        let k = load(&format!(r#"{{"version": 2, {SEC}, {ID}, {KEY}, {irk}}}"#)).unwrap();
+        assert_eq!(k.irk(), Some(&IRK::new(4)));
+    }
+
+    #[test]
+    fn bytes() {
+        let mut k = keys();
+        assert_eq!(k.key_len(), 128);
+        assert!(k.is_authenticated());
+        let v = k.to_bytes();
+        assert_eq!(v.len(), 3 + 16);
+        assert_eq!(Keys::from_bytes(&v), Some(keys()));
+
+        // Absent CSRK
+        k.irk = Some(IRK::new(4));
+        let v = k.to_bytes();
+        assert_eq!(v.len(), 3 + 2 * 16);
+        assert_eq!(v[..3], [Keys::VERSION, k.sec.bits(), 0b01]);
+        let v = Keys::from_bytes(&v).unwrap();
+        assert!(v.is_bond() && v.csrk().is_none());
+        assert_eq!(v, k);
+
+        k.csrk = Some(CSRK::new(3));
+        let v = k.to_bytes();
+        assert_eq!(v.len(), 3 + 3 * 16);
+        assert_eq!(Keys::from_bytes(&v), Some(k));
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let mut k = keys();
+        k.csrk = Some(CSRK::new(3));
+        let v = k.to_bytes();
+        assert!(Keys::from_bytes(&v[..v.len() - 1]).is_none());
+        let mut w = v.clone();
+        w.push(0);
+        assert!(Keys::from_bytes(&w).is_none());
+        let mut w = v.clone();
+        w[0] = Keys::VERSION - 1;
+        assert!(Keys::from_bytes(&w).is_none());
+        let mut w = v.clone();
+        w[2] |= 1 << 7;
+        assert!(Keys::from_bytes(&w).is_none());
+        let mut w = v;
+        w[0] = Keys::VERSION + 1;
+        let k = Keys::from_bytes(&w).unwrap();
+        assert!(!k.is_supported() && !k.is_bond());
+    }
+
+    #[test]
+    fn future_version() {
+        let k = load(&format!(r#"{{"version": 3, {SEC}, {KEY}, "x": "01"}}"#)).unwrap();
+        assert_eq!(k.version(), 3);
+        assert!(!k.is_supported());
+        let k = load(&format!(r#"{{"version": 3, {SEC}, {ID}, {KEY}}}"#)).unwrap();
+        assert!(!k.is_supported());
+        assert!(load(&format!(r#"{{"version": 0, {SEC}, {ID}, {KEY}}}"#)).is_err());
+        assert!(load(r#"{"sec": 1}"#).is_err());
+    }
+}
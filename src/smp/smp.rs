@@ -1,25 +1,35 @@
 //! Security Manager Protocol ([Vol 3] Part H).
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter};
 use std::io;
+use std::sync::Arc;
 
 use futures_core::future::BoxFuture;
+use tracing::debug;
 
 pub use burble_crypto::NumCompare;
-pub(self) use cmd::*;
-pub use {consts::*, peripheral::*, secdb::*};
+use burble_crypto::{Nonce, CSRK, IRK};
+pub use {central::*, consts::*, keypair::*, peripheral::*, secdb::*};
+pub(self) use {chan::*, cmd::*};
 
-use crate::l2cap;
+use crate::{hci, l2cap, le, name_of};
 
+mod central;
+mod chan;
 mod cmd;
 mod consts;
+mod keypair;
 mod peripheral;
 mod secdb;
+#[cfg(test)]
+pub(crate) mod test;
 
 /// Error type returned by the SMP layer.
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum Error {
+    #[error(transparent)]
+    Hci(#[from] hci::Error),
     #[error(transparent)]
     L2cap(#[from] l2cap::Error),
     #[error("local failure: {0}")]
@@ -32,15 +42,84 @@ pub enum Error {
     Timeout,
 }
 
+impl Error {
+    /// Returns the pairing outcome that the error represents.
+    #[must_use]
+    pub const fn outcome(&self) -> PairingOutcome {
+        match *self {
+            Self::Local(r) | Self::Remote(r) => PairingOutcome::from_reason(r),
+            Self::Hci(_) | Self::L2cap(_) | Self::Io(_) | Self::Timeout => PairingOutcome::Aborted,
+        }
+    }
+}
+
 /// Common SMP result type.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Outcome of a pairing procedure, classifying `PairingFailed` reasons by the
+/// action that the application should take.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum PairingOutcome {
+    /// Pairing completed successfully.
+    Paired,
+    /// Pairing was cancelled by the user or is not possible with the I/O
+    /// capabilities, OOB data, or security requirements of the devices.
+    /// Retrying without a configuration change will fail again.
+    Rejected(Reason),
+    /// Confirm, DHKey check, or numeric comparison values did not match,
+    /// indicating a user input error or an active attacker.
+    AuthenticationFailed(Reason),
+    /// Pairing was attempted too soon after a previous failure.
+    RepeatedAttempts,
+    /// Pairing failed because of a protocol error or an unspecified reason.
+    ProtocolError(Reason),
+    /// Pairing timed out or the channel was closed.
+    Aborted,
+}
+
+impl PairingOutcome {
+    /// Returns the outcome for a `PairingFailed` reason.
+    #[must_use]
+    pub const fn from_reason(r: Reason) -> Self {
+        use Reason::*;
+        match r {
+            PasskeyEntryFailed
+            | OobNotAvailable
+            | AuthenticationRequirements
+            | PairingNotSupported
+            | EncryptionKeySize
+            | BrEdrPairingInProgress
+            | CrossTransportKeyDerivationNotAllowed
+            | KeyRejected => Self::Rejected(r),
+            ConfirmValueFailed | DhKeyCheckFailed | NumericComparisonFailed => {
+                Self::AuthenticationFailed(r)
+            }
+            RepeatedAttempts => Self::RepeatedAttempts,
+            CommandNotSupported | UnspecifiedReason | InvalidParameters => Self::ProtocolError(r),
+        }
+    }
+}
+
+impl<T> From<&Result<T>> for PairingOutcome {
+    #[inline]
+    fn from(r: &Result<T>) -> Self {
+        r.as_ref().map_or_else(Error::outcome, |_| Self::Paired)
+    }
+}
+
 /// SMP interface to the local device.
 #[derive(Debug)]
 #[must_use]
 pub struct Device {
     display: Option<Box<dyn Display>>,
     confirm: Option<Box<dyn Confirm>>,
+    num_compare: Option<NumCompareHandler>,
+    key_pairs: Option<Arc<KeyPairCache>>,
+    legacy: bool,
+    rng: Option<hci::Host>,
+    identity: Option<(le::Addr, IRK)>,
+    csrk: Option<CSRK>,
 }
 
 impl Device {
@@ -50,6 +129,12 @@ impl Device {
         Self {
             display: None,
             confirm: None,
+            num_compare: None,
+            key_pairs: None,
+            legacy: false,
+            rng: None,
+            identity: None,
+            csrk: None,
         }
     }
 
@@ -67,8 +152,114 @@ impl Device {
         self
     }
 
+    /// Sets the handler that displays the LE Secure Connections Numeric
+    /// Comparison value and obtains user confirmation
+    /// ([Vol 3] Part H, Section 2.3.5.6.2). The returned future resolves to
+    /// `true` if the user confirmed that the values match or `false` to abort
+    /// pairing. The handler takes precedence over any display and yes/no input
+    /// devices.
+    ///
+    /// If neither a handler nor both display and yes/no input devices are
+    /// provided, the device advertises no I/O capabilities, so Numeric
+    /// Comparison is never selected and Secure Connections pairing uses the
+    /// unauthenticated Just Works model instead
+    /// ([Vol 3] Part H, Section 2.3.5.1). There is no default handler that
+    /// rejects every comparison, because advertising yes/no input without a
+    /// way to confirm would fail pairing with every peer that has a display
+    /// and yes/no input.
+    #[inline]
+    pub fn with_numeric_comparison_handler(
+        mut self,
+        cb: impl Fn(le::Addr, NumCompare) -> BoxFuture<'static, bool> + Send + Sync + 'static,
+    ) -> Self {
+        self.num_compare = Some(NumCompareHandler(Arc::new(cb)));
+        self
+    }
+
+    /// Provides a key pair cache that may be shared by multiple devices. By
+    /// default, a new key pair is generated for every pairing procedure.
+    #[inline(always)]
+    pub fn with_key_pair_cache(mut self, c: Arc<KeyPairCache>) -> Self {
+        self.key_pairs = Some(c);
+        self
+    }
+
+    /// Allows LE legacy pairing with peers that do not support LE Secure
+    /// Connections ([Vol 3] Part H, Section 2.3.5.5). Legacy pairing does not
+    /// protect against passive eavesdropping. Only the Just Works association
+    /// model is supported, so the resulting Short Term Key is unauthenticated
+    /// and is used for the current connection without creating a bond.
+    #[inline(always)]
+    pub const fn with_legacy_pairing(mut self) -> Self {
+        self.legacy = true;
+        self
+    }
+
+    /// Generates pairing nonces using the controller's random number generator
+    /// instead of the OS CSPRNG, which may be used when the platform source is
+    /// untrusted. See [`hci::ControllerRng`].
+    #[inline(always)]
+    pub fn with_controller_rng(mut self, host: hci::Host) -> Self {
+        self.rng = Some(host);
+        self
+    }
+
+    /// Distributes the local identity address `addr` and Identity Resolving
+    /// Key `irk` when the peer requests identity information during bonding
+    /// ([Vol 3] Part H, Section 3.6.4). The address should be
+    /// [`hci::Host::identity_addr`]. `irk` is [`None`] if the device does not
+    /// use resolvable private addresses, in which case an all-zero IRK is
+    /// distributed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is not a public or static random address.
+    #[inline]
+    pub fn with_identity(mut self, addr: le::Addr, irk: Option<IRK>) -> Self {
+        assert!(
+            matches!(addr.kind(), le::AddrKind::Public | le::AddrKind::Static),
+            "invalid identity address: {addr}"
+        );
+        self.identity = Some((addr, irk.unwrap_or(IRK::new(0))));
+        self
+    }
+
+    /// Distributes the local Connection Signature Resolving Key `csrk` when
+    /// the peer requests signing information during bonding
+    /// ([Vol 3] Part H, Section 3.6.6). The peer uses it to verify signed
+    /// writes sent with [`crate::gatt::Client::signed_write_command`].
+    #[inline(always)]
+    pub fn with_signing_key(mut self, csrk: CSRK) -> Self {
+        self.csrk = Some(csrk);
+        self
+    }
+
+    /// Returns the keys that the device can distribute.
+    fn key_dist(&self) -> KeyDist {
+        let mut d = KeyDist::empty();
+        d.set(KeyDist::ID, self.identity.is_some());
+        d.set(KeyDist::SIGN, self.csrk.is_some());
+        d
+    }
+
+    /// Returns a new random nonce for the pairing procedure.
+    async fn nonce(&self) -> Result<Nonce> {
+        match self.rng {
+            Some(ref host) => Ok(hci::ControllerRng::nonce(host).await?),
+            None => Ok(Nonce::new()),
+        }
+    }
+
+    /// Returns the key pair for a new pairing procedure.
+    fn key_pair(&self) -> Arc<KeyPair> {
+        (self.key_pairs.as_ref()).map_or_else(|| Arc::new(KeyPair::new()), |c| c.get())
+    }
+
     /// Returns IO capabilities based on the device configuration.
     const fn io_cap(&self) -> IoCap {
+        if self.num_compare.is_some() {
+            return IoCap::new(InputCap::YesNo, OutputCap::Numeric);
+        }
         let inp = match self.confirm {
             Some(_) => InputCap::YesNo,
             _ => InputCap::None,
@@ -79,6 +270,19 @@ impl Device {
         };
         IoCap::new(inp, out)
     }
+
+    /// Shows the Numeric Comparison value `n` to the user and returns whether
+    /// the user confirmed it. Returns `false` if the device is not capable of
+    /// performing the comparison.
+    async fn num_compare(&mut self, peer: le::Addr, n: NumCompare) -> bool {
+        if let Some(ref h) = self.num_compare {
+            return (h.0)(peer, n).await;
+        }
+        match (self.display.as_mut(), self.confirm.as_mut()) {
+            (Some(d), Some(c)) => d.show(n).await && c.confirm().await,
+            _ => false,
+        }
+    }
 }
 
 impl Default for Device {
@@ -88,6 +292,21 @@ impl Default for Device {
     }
 }
 
+/// Numeric Comparison callback.
+#[derive(Clone)]
+#[repr(transparent)]
+struct NumCompareHandler(
+    Arc<dyn Fn(le::Addr, NumCompare) -> BoxFuture<'static, bool> + Send + Sync>,
+);
+
+impl Debug for NumCompareHandler {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let p = Arc::as_ptr(&self.0);
+        (f.debug_tuple(name_of!(NumCompareHandler)).field(&p)).finish()
+    }
+}
+
 /// Device display capable of showing a 6-digit number to the user.
 pub trait Display: Debug + Send + Sync {
     /// Show a 6-digit number to the user, returning `true` when the number is
@@ -102,3 +321,188 @@ pub trait Confirm: Debug + Send + Sync {
 }
 
 // TODO: Keyboard
+
+/// Output of phase 1.
+#[derive(Clone, Copy)]
+#[must_use]
+struct Phase1 {
+    a: PairingParams,
+    b: PairingParams,
+    method: KeyGenMethod,
+    sec: hci::ConnSec,
+    legacy: bool,
+}
+
+/// Saves `keys` for `peer` in `store`.
+fn save(store: &KeyStore, peer: le::Addr, keys: &Keys) -> Result<()> {
+    if store.save(peer, keys) {
+        return Ok(());
+    }
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::Other,
+        "failed to save peer keys",
+    )))
+}
+
+/// Saves `keys` after key distribution under the peer's identity address `id`,
+/// if it was distributed, replacing the keys saved under the connection
+/// address `peer`. Returns the address under which the keys were saved.
+fn save_identity(
+    store: &KeyStore,
+    peer: le::Addr,
+    id: Option<le::Addr>,
+    keys: &Keys,
+) -> Result<le::Addr> {
+    let addr = id.unwrap_or(peer);
+    save(store, addr, keys)?;
+    if addr != peer {
+        debug!("Keys for {peer} saved under identity address {addr}");
+        store.remove(peer);
+    }
+    Ok(addr)
+}
+
+/// Output of phase 2, authentication stage 1.
+#[must_use]
+struct Authn1 {
+    na: Nonce,
+    nb: Nonce,
+    ra: u128,
+    rb: u128,
+}
+
+#[cfg(test)]
+mod tests {
+    use burble_crypto::Codec;
+
+    use crate::PeerStore;
+
+    use super::test::Store;
+    use super::*;
+
+    #[test]
+    fn pairing_outcome() {
+        let f = |e: Error| PairingOutcome::from(&Result::<()>::Err(e));
+        assert_eq!(PairingOutcome::from(&Ok(())), PairingOutcome::Paired);
+        assert_eq!(
+            f(Error::Local(Reason::PasskeyEntryFailed)),
+            PairingOutcome::Rejected(Reason::PasskeyEntryFailed)
+        );
+        assert_eq!(
+            f(Error::Remote(Reason::DhKeyCheckFailed)),
+            PairingOutcome::AuthenticationFailed(Reason::DhKeyCheckFailed)
+        );
+        assert_eq!(
+            f(Error::Remote(Reason::RepeatedAttempts)),
+            PairingOutcome::RepeatedAttempts
+        );
+        assert_eq!(
+            f(Error::Local(Reason::InvalidParameters)),
+            PairingOutcome::ProtocolError(Reason::InvalidParameters)
+        );
+        assert_eq!(f(Error::Timeout), PairingOutcome::Aborted);
+    }
+
+    /// A Numeric Comparison handler enables authenticated pairing and takes
+    /// precedence over display and yes/no input devices.
+    #[tokio::test]
+    async fn num_compare_handler() {
+        let n = num(1);
+        let mut dev = Device::new()
+            .with_display(Box::new(Reply(false)))
+            .with_numeric_comparison_handler(move |_, v| Box::pin(async move { v == n }));
+        assert_eq!(dev.io_cap(), IoCap::DisplayYesNo);
+        assert!(dev.num_compare(peer(), n).await);
+        assert!(!dev.num_compare(peer(), num(2)).await);
+    }
+
+    /// Numeric Comparison fails without a handler unless both display and
+    /// yes/no input devices are provided.
+    #[tokio::test]
+    async fn num_compare_unsupported() {
+        let mut dev = Device::new();
+        assert_eq!(dev.io_cap(), IoCap::NoInputNoOutput);
+        assert!(!dev.num_compare(peer(), num(1)).await);
+
+        let mut dev = Device::new().with_display(Box::new(Reply(true)));
+        assert_eq!(dev.io_cap(), IoCap::DisplayOnly);
+        assert!(!dev.num_compare(peer(), num(1)).await);
+
+        let mut dev = Device::new().with_confirm(Box::new(Reply(true)));
+        assert_eq!(dev.io_cap(), IoCap::NoInputNoOutput);
+        assert!(!dev.num_compare(peer(), num(1)).await);
+
+        let mut dev = Device::new()
+            .with_display(Box::new(Reply(true)))
+            .with_confirm(Box::new(Reply(true)));
+        assert_eq!(dev.io_cap(), IoCap::DisplayYesNo);
+        assert!(dev.num_compare(peer(), num(1)).await);
+    }
+
+    /// Bonding exchanges identity information over the encrypted link, and
+    /// the keys are saved under the identity addresses.
+    #[tokio::test]
+    async fn bond_identity() {
+        let (pid, cid) = (addr(0xC0, 1), addr(0xC0, 2));
+        let (p, c) = l2cap::loopback::connect(addr(0x40, 1), addr(0x40, 2));
+        let (ps, cs) = (Store::default(), Store::default());
+        let mut pdev = Device::new().with_identity(pid, Some(IRK::new(1)));
+        let mut cdev = Device::new().with_identity(cid, None);
+        let (mut psm, mut csm) = (Peripheral::new(p.smp), Central::new(c.smp));
+        let (pr, cr) = tokio::join!(psm.respond(&mut pdev, &ps), async {
+            let keys = csm.initiate(&mut cdev, &cs, true).await?;
+            let sec = keys.sec;
+            (p.conn).send_modify(|cn| cn.sec = sec);
+            (c.conn).send_modify(|cn| cn.sec = sec);
+            csm.distribute_keys(&cdev, &cs, keys).await
+        });
+        pr.unwrap();
+        assert_eq!(cr.unwrap(), pid);
+
+        assert_eq!(ps.peers(), [cid]);
+        let pk = ps.load(cid).unwrap();
+        assert!(pk.is_bond());
+        assert!(pk.irk().is_none());
+
+        assert_eq!(cs.peers(), [pid]);
+        let ck = cs.load(pid).unwrap();
+        assert!(ck.is_bond());
+        assert_eq!(ck.irk(), Some(&IRK::new(1)));
+        assert_eq!(ck.ltk(), pk.ltk());
+    }
+
+    /// Display and yes/no input device that always returns the same reply.
+    #[derive(Debug)]
+    struct Reply(bool);
+
+    impl Display for Reply {
+        fn show(&mut self, _: NumCompare) -> BoxFuture<bool> {
+            let v = self.0;
+            Box::pin(async move { v })
+        }
+    }
+
+    impl Confirm for Reply {
+        fn confirm(&mut self) -> BoxFuture<bool> {
+            let v = self.0;
+            Box::pin(async move { v })
+        }
+    }
+
+    /// Returns a fixed Numeric Comparison value. Different `i` values produce
+    /// different results.
+    fn num(i: u8) -> NumCompare {
+        let x = |b| burble_crypto::PublicKeyX::from_be_bytes([b; 32]);
+        let n = |b| Nonce::unpack(&mut structbuf::Unpacker::new(&[b; 16])).unwrap();
+        n(i).g2(&x(1), &x(2), &n(i + 1))
+    }
+
+    fn peer() -> le::Addr {
+        le::Addr::Public(le::RawAddr::default())
+    }
+
+    /// Returns a random address with the two most significant bits in `msb`.
+    fn addr(msb: u8, i: u8) -> le::Addr {
+        le::Addr::Random(le::RawAddr::from_le_bytes([i, 0, 0, 0, 0, msb]))
+    }
+}
@@ -0,0 +1,33 @@
+//! Security Manager test helpers.
+
+use crate::{le, MemoryStore, PeerStore};
+
+use super::Keys;
+
+/// Key store that keeps encoded keys in memory.
+#[derive(Debug, Default)]
+pub(crate) struct Store(MemoryStore<Vec<u8>>);
+
+impl PeerStore for Store {
+    type Value = Keys;
+
+    fn save(&self, peer: le::Addr, v: &Self::Value) -> bool {
+        self.0.save(peer, &v.to_bytes())
+    }
+
+    fn load(&self, peer: le::Addr) -> Option<Self::Value> {
+        Keys::from_bytes(&self.0.load(peer)?)
+    }
+
+    fn remove(&self, peer: le::Addr) {
+        self.0.remove(peer);
+    }
+
+    fn clear(&self) {
+        self.0.clear();
+    }
+
+    fn peers(&self) -> Vec<le::Addr> {
+        self.0.peers()
+    }
+}
@@ -0,0 +1,331 @@
+//! In-memory peer data storage.
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use crate::{le::Addr, KindStore, PeerStore, SyncMutex};
+
+/// Peer data store that keeps all data in memory. Clones share the same
+/// storage, so one store can be used by multiple layers (e.g. SMP keys and GATT
+/// cache persistence in tests or devices without persistent storage).
+///
+/// A store with limited capacity evicts the least recently used peer when a
+/// new peer is saved and the store is full. Both saving and loading count as a
+/// use.
+pub struct MemoryStore<T>(Arc<SyncMutex<Inner<T>>>);
+
+impl<T> MemoryStore<T> {
+    /// Creates a new store with unlimited capacity.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_limit(None)
+    }
+
+    /// Creates a new store that holds data for at most `cap` peers.
+    #[inline]
+    #[must_use]
+    pub fn with_capacity(cap: NonZeroUsize) -> Self {
+        Self::with_limit(Some(cap))
+    }
+
+    /// Returns the number of stored peers.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.lock().map.len()
+    }
+
+    /// Returns whether the store is empty.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().map.is_empty()
+    }
+
+    /// Creates a new store with an optional capacity limit.
+    #[inline]
+    fn with_limit(cap: Option<NonZeroUsize>) -> Self {
+        Self(Arc::new(SyncMutex::new(Inner {
+            map: BTreeMap::new(),
+            cap,
+            seq: 0,
+        })))
+    }
+}
+
+impl<T> Clone for MemoryStore<T> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Default for MemoryStore<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Debug for MemoryStore<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let inner = self.0.lock();
+        (f.debug_struct("MemoryStore"))
+            .field("len", &inner.map.len())
+            .field("cap", &inner.cap)
+            .finish()
+    }
+}
+
+impl<T: Clone + Send> PeerStore for MemoryStore<T> {
+    type Value = T;
+
+    fn save(&self, peer: Addr, v: &Self::Value) -> bool {
+        let mut inner = self.0.lock();
+        let seq = inner.next_seq();
+        inner.reserve(peer);
+        inner.map.insert(peer, (seq, v.clone()));
+        true
+    }
+
+    fn load(&self, peer: Addr) -> Option<Self::Value> {
+        let mut inner = self.0.lock();
+        let seq = inner.next_seq();
+        let &mut (ref mut used, ref v) = inner.map.get_mut(&peer)?;
+        *used = seq;
+        Some(v.clone())
+    }
+
+    #[inline]
+    fn remove(&self, peer: Addr) {
+        self.0.lock().map.remove(&peer);
+    }
+
+    #[inline]
+    fn clear(&self) {
+        self.0.lock().map.clear();
+    }
+
+    #[inline]
+    fn peers(&self) -> Vec<Addr> {
+        self.0.lock().map.keys().copied().collect()
+    }
+}
+
+/// In-memory [`KindStore`] that keeps all kinds of data for a peer in one
+/// map, so removing a peer removes all of its data at once. Clones share the
+/// same storage. A store with limited capacity evicts whole peers.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryKindStore(MemoryStore<BTreeMap<String, Vec<u8>>>);
+
+impl MemoryKindStore {
+    /// Creates a new store with unlimited capacity.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self(MemoryStore::new())
+    }
+
+    /// Creates a new store that holds data for at most `cap` peers.
+    #[inline]
+    #[must_use]
+    pub fn with_capacity(cap: NonZeroUsize) -> Self {
+        Self(MemoryStore::with_capacity(cap))
+    }
+
+    /// Returns the number of stored peers.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the store is empty.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl KindStore for MemoryKindStore {
+    fn save_kind(&self, peer: Addr, kind: &str, v: &[u8]) -> bool {
+        assert!(is_valid_kind(kind), "invalid kind name: {kind:?}");
+        let mut inner = self.0 .0.lock();
+        let seq = inner.next_seq();
+        inner.reserve(peer);
+        let (used, kinds) = inner.map.entry(peer).or_default();
+        *used = seq;
+        kinds.insert(kind.to_owned(), v.to_vec());
+        true
+    }
+
+    fn load_kind(&self, peer: Addr, kind: &str) -> Option<Vec<u8>> {
+        let mut inner = self.0 .0.lock();
+        let seq = inner.next_seq();
+        let &mut (ref mut used, ref kinds) = inner.map.get_mut(&peer)?;
+        *used = seq;
+        kinds.get(kind).cloned()
+    }
+
+    fn remove_kind(&self, peer: Addr, kind: &str) {
+        let mut inner = self.0 .0.lock();
+        let Some((_, kinds)) = inner.map.get_mut(&peer) else {
+            return;
+        };
+        kinds.remove(kind);
+        if kinds.is_empty() {
+            inner.map.remove(&peer);
+        }
+    }
+
+    #[inline(always)]
+    fn remove(&self, peer: Addr) {
+        self.0.remove(peer);
+    }
+
+    #[inline(always)]
+    fn clear(&self) {
+        self.0.clear();
+    }
+
+    #[inline(always)]
+    fn peers(&self) -> Vec<Addr> {
+        self.0.peers()
+    }
+}
+
+/// Returns whether `kind` is a valid [`KindStore`] kind name.
+#[must_use]
+pub(crate) fn is_valid_kind(kind: &str) -> bool {
+    !kind.is_empty()
+        && (kind.bytes()).all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_'))
+}
+
+/// Shared store state.
+struct Inner<T> {
+    map: BTreeMap<Addr, (u64, T)>,
+    cap: Option<NonZeroUsize>,
+    seq: u64,
+}
+
+impl<T> Inner<T> {
+    /// Returns the next use sequence number.
+    #[inline]
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    /// Evicts the least recently used peer if the store is full and `peer` is
+    /// not already in it.
+    fn reserve(&mut self, peer: Addr) {
+        if let Some(cap) = self.cap {
+            if self.map.len() >= cap.get() && !self.map.contains_key(&peer) {
+                self.evict();
+            }
+        }
+    }
+
+    /// Removes the least recently used peer.
+    fn evict(&mut self) {
+        let lru = (self.map.iter()).min_by_key(|&(_, &(used, _))| used);
+        if let Some(peer) = lru.map(|(&peer, _)| peer) {
+            self.map.remove(&peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::le::RawAddr;
+
+    use super::*;
+
+    #[test]
+    fn shared() {
+        let a = MemoryStore::new();
+        let b = a.clone();
+        assert!(a.save(peer(1), &1_u32));
+        assert_eq!(b.load(peer(1)), Some(1));
+        assert_eq!(b.load(peer(2)), None);
+        b.remove(peer(1));
+        assert!(a.is_empty());
+        assert!(a.save(peer(1), &1));
+        assert!(a.save(peer(2), &2));
+        assert_eq!(b.len(), 2);
+        assert_eq!(b.peers(), [peer(1), peer(2)]);
+        b.clear();
+        assert!(a.is_empty());
+        assert!(a.peers().is_empty());
+    }
+
+    #[test]
+    fn lru() {
+        let s = MemoryStore::with_capacity(NonZeroUsize::new(2).unwrap());
+        assert!(s.save(peer(1), &1_u32));
+        assert!(s.save(peer(2), &2));
+        assert_eq!(s.load(peer(1)), Some(1));
+        assert!(s.save(peer(3), &3));
+        assert_eq!(s.len(), 2);
+        assert_eq!(s.load(peer(2)), None);
+        assert!(s.save(peer(1), &4));
+        assert!(s.save(peer(5), &5));
+        assert_eq!(s.load(peer(3)), None);
+        assert_eq!(s.load(peer(1)), Some(4));
+        assert_eq!(s.load(peer(5)), Some(5));
+    }
+
+    #[test]
+    fn kinds() {
+        let s = MemoryKindStore::new();
+        assert!(s.save_kind(peer(1), "keys", &[1]));
+        assert!(s.save_kind(peer(1), "ccc", &[2]));
+        assert!(s.save_kind(peer(2), "ccc", &[3]));
+        assert_eq!(s.load_kind(peer(1), "keys"), Some(vec![1]));
+        assert_eq!(s.load_kind(peer(1), "ccc"), Some(vec![2]));
+        assert_eq!(s.load_kind(peer(2), "keys"), None);
+        s.remove_kind(peer(1), "keys");
+        assert_eq!(s.load_kind(peer(1), "keys"), None);
+        assert_eq!(s.load_kind(peer(1), "ccc"), Some(vec![2]));
+        s.remove_kind(peer(2), "ccc");
+        assert_eq!(s.peers(), [peer(1)]);
+        assert!(s.save_kind(peer(2), "keys", &[4]));
+        s.remove(peer(1));
+        assert_eq!(s.load_kind(peer(1), "ccc"), None);
+        assert_eq!(s.peers(), [peer(2)]);
+        s.clear();
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn concurrent_kinds() {
+        let s = MemoryKindStore::new();
+        std::thread::scope(|sc| {
+            for i in 0..8_u8 {
+                let s = s.clone();
+                sc.spawn(move || {
+                    for j in 0..100_u8 {
+                        assert!(s.save_kind(peer(1), &format!("k{i}"), &[j]));
+                    }
+                });
+            }
+        });
+        for i in 0..8 {
+            assert_eq!(s.load_kind(peer(1), &format!("k{i}")), Some(vec![99]));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid kind name")]
+    fn invalid_kind() {
+        let _ = MemoryKindStore::new().save_kind(peer(1), "../keys", &[]);
+    }
+
+    fn peer(i: u8) -> Addr {
+        Addr::Public(RawAddr::from_le_bytes([i, 0, 0, 0, 0, 0]))
+    }
+}